@@ -0,0 +1,163 @@
+// Benchmarks of the hot library paths, for before/after numbers of performance work on the
+// octree, the bricks and minivector.
+//
+//   cargo bench --bench library
+//   cargo bench --bench library --features simd   (Mat4x4 on SSE/NEON)
+//
+// Criterion keeps the previous run in target/criterion and reports the change against it.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::svosdf::*;
+
+const SIZE: u32 = 64;
+
+// Sphere of radius 0.6 in the [-1, 1] box, like examples/sphere_svo.rs
+// Voxel of the octree, uncovered voxels are at the surface level like in the viewer
+fn sample(svo_sdf: &SvoSdf, voxel: UVec3) -> u16 {
+    match svo_sdf.brick_at(voxel) {
+        Some(brick) => brick.data[(voxel - brick.position).linear_index(UVec3::splat(brick.size))],
+        None => LEVEL_ZERO,
+    }
+}
+
+// Every 3rd voxel of the volume along each axis
+fn sample_voxels() -> Vec<UVec3> {
+    let mut voxels = Vec::new();
+    for z in (0..SIZE).step_by(3) {
+        for y in (0..SIZE).step_by(3) {
+            for x in (0..SIZE).step_by(3) {
+                voxels.push(UVec3::new(x, y, z));
+            }
+        }
+    }
+    voxels
+}
+
+fn octree(c: &mut Criterion) {
+    let sdf = generate_sphere(SIZE, 0.6, 0.1);
+    let svo_sdf = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    let voxels = sample_voxels();
+
+    c.bench_function("octree/sample", |b| {
+        b.iter(|| {
+            voxels
+                .iter()
+                .map(|&voxel| sample(&svo_sdf, voxel) as u64)
+                .sum::<u64>()
+        })
+    });
+    c.bench_function("octree/build", |b| {
+        b.iter(|| SvoSdf::from_sdf(black_box(&sdf), 8, 8, 0.004))
+    });
+}
+
+fn bricks(c: &mut Criterion) {
+    let sdf = generate_sphere(SIZE, 0.6, 0.1);
+    // Surface crossing brick on the +x axis and a uniform one in a corner
+    let surface = UVec3::new(48, 28, 28);
+    let corner = UVec3::ZERO;
+
+    c.bench_function("brick/extract", |b| {
+        b.iter(|| Brick::extract_from_sdf(black_box(&sdf), black_box(surface), 8))
+    });
+    let surface_brick = Brick::extract_from_sdf(&sdf, surface, 8);
+    let uniform_brick = Brick::extract_from_sdf(&sdf, corner, 8);
+    c.bench_function("brick/has_surface", |b| {
+        b.iter(|| {
+            black_box(&surface_brick).has_surface(0.004)
+                | black_box(&uniform_brick).has_surface(0.004)
+        })
+    });
+    c.bench_function("brick/is_uniform", |b| {
+        b.iter(|| {
+            black_box(&surface_brick).is_uniform(0.004)
+                | black_box(&uniform_brick).is_uniform(0.004)
+        })
+    });
+}
+
+fn serialization(c: &mut Criterion) {
+    let svo_sdf = SvoSdf::from_sdf(&generate_sphere(SIZE, 0.6, 0.1), 8, 8, 0.004);
+    let path = std::env::temp_dir().join(format!("bench_{}.svosdf", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+
+    c.bench_function("serialization/save", |b| {
+        b.iter(|| svo_sdf.save(&path).unwrap())
+    });
+    let bytes = std::fs::read(&path).unwrap();
+    c.bench_function("serialization/from_bytes", |b| {
+        b.iter(|| SvoSdf::from_bytes(black_box(&bytes)).unwrap())
+    });
+    c.bench_function("serialization/round_trip", |b| {
+        b.iter(|| {
+            svo_sdf.save(&path).unwrap();
+            SvoSdf::load(&path).unwrap()
+        })
+    });
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn matrices(c: &mut Criterion) {
+    let camera = view(
+        Vec3 {
+            x: 3.0,
+            y: -2.0,
+            z: 5.0,
+        },
+        Vec3 {
+            x: -0.3,
+            y: 0.4,
+            z: -1.0,
+        },
+        Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        },
+    );
+    let proj = projection(1.2, 16.0 / 9.0, 0.1, 1000.0);
+    let point = Vec4 {
+        x: 1.0,
+        y: 2.0,
+        z: 3.0,
+        w: 1.0,
+    };
+
+    c.bench_function("mat4/mul", |b| {
+        b.iter(|| black_box(camera) * black_box(proj))
+    });
+    c.bench_function("mat4/inverse", |b| {
+        b.iter(|| inverse(black_box(camera) * proj))
+    });
+    c.bench_function("mat4/transform_vec4", |b| {
+        b.iter(|| black_box(point) * black_box(proj))
+    });
+    // The per-instance pattern, one matrix applied to many points
+    let points: Vec<Vec4> = (0..1024)
+        .map(|i| Vec4 {
+            x: i as f32,
+            y: (i * 7 % 13) as f32,
+            z: -(i as f32),
+            w: 1.0,
+        })
+        .collect();
+    let world_to_screen = camera * proj;
+    c.bench_function("mat4/transform_1024", |b| {
+        b.iter_batched(
+            || points.clone(),
+            |mut points| {
+                for p in points.iter_mut() {
+                    *p = *p * world_to_screen;
+                }
+                points
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, octree, bricks, serialization, matrices);
+criterion_main!(benches);
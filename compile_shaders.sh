@@ -10,13 +10,16 @@ glslc shader/simple.frag -o shader/simple_frag.spv
 glslc shader/vbuffer.vert -o shader/vbuffer_vert.spv
 glslc shader/vbuffer_nonindexed.vert -o shader/vbuffer_nonindexed_vert.spv
 glslc shader/vbuffer_leadingvertex.vert -o shader/vbuffer_leadingvertex_vert.spv
+glslc shader/vbuffer_leadingvertex_push.vert -o shader/vbuffer_leadingvertex_push_vert.spv
 glslc shader/vbuffer_getattributeatvertex.vert -o shader/vbuffer_getattributeatvertex_vert.spv
+glslc --target-env=vulkan1.2 shader/vbuffer_meshshader.mesh -o shader/vbuffer_meshshader_mesh.spv
 
 glslc shader/vbuffer_color.frag -o shader/vbuffer_color_frag.spv
 glslc shader/vbuffer_primid.frag -o shader/vbuffer_primid_frag.spv
 glslc shader/vbuffer_nonindexed.frag -o shader/vbuffer_nonindexed_frag.spv
 glslc shader/vbuffer_leadingvertex.frag -o shader/vbuffer_leadingvertex_frag.spv
 glslc shader/vbuffer_getattributeatvertex.frag -o shader/vbuffer_getattributeatvertex_frag.spv
+glslc --target-env=vulkan1.2 shader/vbuffer_meshshader.frag -o shader/vbuffer_meshshader_frag.spv
 
 glslc shader/depth_pyramid_first_mip.comp -o shader/depth_pyramid_first_mip.spv
 glslc shader/depth_pyramid_downsample.comp -o shader/depth_pyramid_downsample.spv
@@ -25,3 +28,15 @@ glslc shader/depth_pyramid_downsample_all.comp -o shader/depth_pyramid_downsampl
 glslc shader/culling.comp -o shader/culling.spv
 glslc shader/culling_debug.frag -o shader/culling_debug_frag.spv
 
+glslc shader/volume_projection.comp -o shader/volume_projection.spv
+
+glslc shader/post_dof.comp -o shader/post_dof.spv
+glslc shader/post_vignette.comp -o shader/post_vignette.spv
+glslc shader/post_ao.comp -o shader/post_ao.spv
+glslc shader/post_ao_upsample.comp -o shader/post_ao_upsample.spv
+glslc shader/post_tonemap.frag -o shader/post_tonemap_frag.spv
+
+
+# Shader printf permutations (run with --shader-printf), see shader/debug_printf.glsl:
+# glslc -DDEBUG_PRINTF -DDEBUG_PIXEL_X=640 -DDEBUG_PIXEL_Y=360 shader/svo_main.frag -o shader/svo_main_frag.spv
+# Rebuild without -DDEBUG_PRINTF before committing the .spv
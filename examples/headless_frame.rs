@@ -0,0 +1,159 @@
+// Renders one frame without showing a window: clears an offscreen image on the GPU, copies it
+// to a host visible buffer and writes it to a PNG. Needs a Vulkan device, VulkanBase still
+// creates a surface for a hidden window.
+//
+//   cargo run --example headless_frame [OUTPUT.png]
+
+use rust_test::packing::pack_unorm8;
+use rust_test::vulkan_base::*;
+use rust_test::vulkan_helpers::*;
+
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use winit::{event_loop::EventLoop, window::WindowBuilder};
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 256,
+    height: 256,
+};
+const CLEAR_COLOR: [f32; 4] = [0.2, 0.4, 1.0, 1.0]; // Exact in 8 bit UNORM
+
+fn main() {
+    let output = env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| env::temp_dir().join("headless_frame.png"));
+
+    let event_loop = EventLoop::new().unwrap();
+    let window = WindowBuilder::new()
+        .with_title("Headless frame")
+        .with_visible(false)
+        .build(&event_loop)
+        .unwrap();
+    let size = window.inner_size();
+    let mut base =
+        VulkanBase::new(&window, size.width.max(1), size.height.max(1)).unwrap_or_else(|err| {
+            println!("No Vulkan device: {}", err);
+            process::exit(1);
+        });
+
+    let image_info = vk::ImageCreateInfo {
+        image_type: vk::ImageType::TYPE_2D,
+        format: vk::Format::R8G8B8A8_UNORM,
+        extent: EXTENT.into(),
+        mip_levels: 1,
+        array_layers: 1,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+    let mut image = VkImage::new(
+        &base.device,
+        &mut base.allocator,
+        &image_info,
+        MemoryLocation::GpuOnly,
+    );
+    let readback_info = vk::BufferCreateInfo {
+        size: (EXTENT.width * EXTENT.height * 4) as u64,
+        usage: vk::BufferUsageFlags::TRANSFER_DST,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+    let mut readback = VkBuffer::new(
+        &base.device,
+        &mut base.allocator,
+        &readback_info,
+        MemoryLocation::GpuToCpu,
+    );
+
+    let copy = vk::BufferImageCopy {
+        image_subresource: vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            layer_count: 1,
+            ..Default::default()
+        },
+        image_extent: EXTENT.into(),
+        ..Default::default()
+    };
+    let host_read_barrier = vk::MemoryBarrier2 {
+        src_stage_mask: vk::PipelineStageFlags2::COPY,
+        src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags2::HOST,
+        dst_access_mask: vk::AccessFlags2::HOST_READ,
+        ..Default::default()
+    };
+    let barrier_api = &base.barrier_api;
+    base.record_submit_commandbuffer(
+        0,
+        base.present_queue,
+        &[],
+        &[],
+        &[],
+        |device, command_buffer| unsafe {
+            image.transition_discarding(
+                device,
+                barrier_api,
+                command_buffer,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags2::CLEAR,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            );
+            device.cmd_clear_color_image(
+                command_buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &vk::ClearColorValue {
+                    float32: CLEAR_COLOR,
+                },
+                &[image.subresource_range],
+            );
+            image.transition_to(
+                device,
+                barrier_api,
+                command_buffer,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_READ,
+            );
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback.buffer,
+                &[copy],
+            );
+            barrier2(
+                device,
+                barrier_api,
+                command_buffer,
+                &[host_read_barrier],
+                &[],
+                &[],
+            );
+        },
+    )
+    .expect("Submit failed");
+    unsafe { base.device.device_wait_idle() }.expect("Wait failed");
+
+    let pixels = readback.mapped_bytes();
+    let expected: Vec<u8> = CLEAR_COLOR.iter().map(|&c| pack_unorm8(c)).collect();
+    assert_eq!(&pixels[..4], &expected[..], "Unexpected clear color");
+    image::save_buffer(
+        &output,
+        pixels,
+        EXTENT.width,
+        EXTENT.height,
+        image::ColorType::Rgba8,
+    )
+    .expect("Writing the PNG failed");
+    println!("Wrote {}", output.display());
+
+    readback.destroy(&base.device, &mut base.allocator);
+    image.destroy(&base.device, &mut base.allocator);
+}
@@ -0,0 +1,63 @@
+// Builds a sparse voxel octree from an analytic sphere, samples it, saves it and loads it
+// back using only the public library API.
+//
+//   cargo run --example sphere_svo
+
+use rust_test::brick_cache::*;
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::svosdf::*;
+
+use std::env;
+use std::fs;
+
+const SIZE: u32 = 64;
+const RADIUS: f32 = 0.6; // Fraction of the half extent of the [-1, 1] box
+
+// Distances are stored in u16 around LEVEL_ZERO, one step is SdfHeader::distance_per_step.
+// Like generated SDF files only a band around the surface has exact distances, bricks that
+// are uniformly inside or outside are left out of the octree.
+fn main() {
+    let sdf = generate_sphere(SIZE, RADIUS, 0.1);
+    let svo_sdf = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    println!(
+        "{} bricks, {} octree nodes, {} bytes",
+        svo_sdf.bricks.len(),
+        svo_sdf.memory_usage().node_count,
+        svo_sdf.calculate_memory_usage()
+    );
+
+    // Compare against the source, then find the brick at a surface voxel
+    let lod = SdfLod::new(&sdf, 0, LodFilter::Point);
+    let compare = svo_sdf.compare(&lod, 0.01);
+    println!(
+        "RMS error {:.6}, {} voxels near the surface without a brick",
+        compare.error.rms, compare.missing_surface
+    );
+    let surface_voxel = UVec3::new(
+        (SIZE as f32 * (0.5 + RADIUS * 0.5)) as u32,
+        SIZE / 2,
+        SIZE / 2,
+    );
+    let brick = svo_sdf
+        .brick_at(surface_voxel)
+        .expect("No brick at the sphere surface");
+    println!("Surface brick at {:?}, size {}", brick.position, brick.size);
+
+    // Save, then sample the file through the brick cache
+    let path = env::temp_dir().join("sphere_svo_example.svosdf");
+    let filename = path.to_string_lossy();
+    svo_sdf.save(&filename).expect("Save failed");
+    let loaded = SvoSdf::load(&filename).expect("Load failed");
+    assert_eq!(loaded.bricks.len(), svo_sdf.bricks.len());
+
+    let mut cache = BrickCache::open(&path, 64).expect("Open failed");
+    let on_surface = cache
+        .sample_position((RADIUS, 0.0, 0.0))
+        .expect("Read failed")
+        .expect("No brick at the sphere surface");
+    let encoded_distance = on_surface as i32 - LEVEL_ZERO as i32;
+    println!("Distance code at the surface: {}", encoded_distance);
+
+    fs::remove_file(&path).expect("Removing the saved file failed");
+}
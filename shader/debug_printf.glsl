@@ -0,0 +1,33 @@
+// Shader printf helpers (GL_EXT_debug_printf). Output is printed by the validation layer when
+// the application runs with --shader-printf.
+//
+// Usage: #include "debug_printf.glsl" after the #version/#extension lines and wrap prints in
+// #ifdef DEBUG_PRINTF. Build a printf permutation with -DDEBUG_PRINTF (see compile_shaders.sh),
+// the default permutation compiles the prints out. Printing every pixel floods the output,
+// restrict prints to one pixel/invocation:
+//
+//     #ifdef DEBUG_PRINTF
+//     if (DEBUG_PIXEL) debugPrintfEXT("steps %d dist %f", steps, dist);
+//     #endif
+
+#ifdef DEBUG_PRINTF
+#extension GL_EXT_debug_printf : enable
+
+// Pixel to print, override with -DDEBUG_PIXEL_X=... -DDEBUG_PIXEL_Y=...
+#ifndef DEBUG_PIXEL_X
+#define DEBUG_PIXEL_X 640
+#endif
+#ifndef DEBUG_PIXEL_Y
+#define DEBUG_PIXEL_Y 360
+#endif
+
+// Invocation to print in compute shaders
+#ifndef DEBUG_INVOCATION
+#define DEBUG_INVOCATION 0
+#endif
+
+// Macros so only the stage using them needs gl_FragCoord / gl_GlobalInvocationID
+#define DEBUG_PIXEL (ivec2(gl_FragCoord.xy) == ivec2(DEBUG_PIXEL_X, DEBUG_PIXEL_Y))
+#define DEBUG_THREAD (gl_GlobalInvocationID.x == DEBUG_INVOCATION)
+
+#endif
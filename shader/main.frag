@@ -53,7 +53,9 @@ vec3 normal(vec3 uvw) {
     float yp = textureLod(samplerSDF, uvw + vec3( 0,   e.y,  0), lod).x;
     float zm = textureLod(samplerSDF, uvw + vec3( 0,   0, -e.z), lod).x;
     float zp = textureLod(samplerSDF, uvw + vec3( 0,   0,  e.z), lod).x;
-    return normalize(vec3(xp - xm, yp - ym, zp - zm));
+    // Differences over one voxel, divided by the voxel spacing of each axis
+    vec3 spacing = ubo.center_to_edge.xyz * ubo.texel_scale.xyz;
+    return normalize(vec3(xp - xm, yp - ym, zp - zm) / spacing);
 }
 
 void main() {
@@ -0,0 +1,44 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_ARB_shading_language_420pack : enable
+
+// Output pass of the post-processing chain, maps the HDR result to the swapchain
+
+#define TONEMAP_ACES 0
+#define TONEMAP_REINHARD 1
+
+layout (binding = 0, rgba16f) uniform readonly image2D hdr_image;
+
+layout (push_constant) uniform PostPushConstants {
+    vec4 params;        // Exposure, tonemap operator
+    vec4 depth_params;
+} push;
+
+layout (location = 0) in vec2 i_uv;
+
+layout (location = 0) out vec4 o_color;
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 tonemap_aces(vec3 color)
+{
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 tonemap_reinhard(vec3 color)
+{
+    return color / (1.0 + color);
+}
+
+void main()
+{
+    vec3 color = imageLoad(hdr_image, ivec2(gl_FragCoord.xy)).rgb * push.params.x;
+    if (uint(push.params.y) == TONEMAP_REINHARD)
+    {
+        color = tonemap_reinhard(color);
+    }
+    else
+    {
+        color = tonemap_aces(color);
+    }
+    o_color = vec4(color, 1.0);
+}
@@ -3,21 +3,24 @@
 #extension GL_ARB_shading_language_420pack : enable
 
 layout (binding = 0) uniform UBO {
-    mat4 world_to_screen;
     vec4 color;
-    vec4 camera_position;
     vec4 volume_scale;
     vec4 center_to_edge;
     vec4 texel_scale;
     uint brick_size;
+    float brick_curve; // Exponent of the brick code curve, 1 = linear
+    uint bricks_per_scene_instance;
+    uint scene_instance_visibility; // Visibility lists scene instances instead of bricks
+    mat3 normal_matrix; // Brick uvw to volume space normals
 } ubo;
 
-struct InstanceData
+struct BrickData
 {
     vec4 position;
     uint brick_index;
     uint brick_size;
-    uint padding[2];
+    uint atlas_slot;
+    uint padding;
 };
 
 struct VisibilityData
@@ -36,9 +39,9 @@ struct OctreeNode
     uint padding[2];
 };
 
-layout(std430, binding = 1) buffer Instances
+layout(std430, binding = 1) buffer Bricks
 {
-    InstanceData instances[];
+    BrickData bricks[];
 };
 
 layout(std430, binding = 2) buffer Visibility
@@ -53,10 +56,37 @@ layout(std430, binding = 4) buffer OctreeBuffer
     OctreeNode octree_nodes[];
 };
 
+layout (binding = 5) uniform sampler3D samplerDetail;
+
+layout (binding = 6) uniform DetailUBO {
+    vec4 materials[8]; // amplitude, frequency, fade start, fade end
+    uint enabled;
+} detail;
+
+struct LightData
+{
+    vec4 position_range;      // xyz position, w range
+    vec4 direction_cos_outer; // xyz direction the light travels, w cosine of the outer cone
+    vec4 color_cos_inner;     // rgb color times intensity, w cosine of the inner cone
+    uint type;                // 0 directional, 1 point, 2 spot
+    uint padding[3];
+};
+
+// Scene lights culled for this frame, see lights.rs
+layout(std430, binding = 7) readonly buffer Lights
+{
+    uint light_count;
+    float ambient;
+    uint light_padding[2];
+    LightData lights[];
+};
+
 layout (location = 0) in vec3 o_uvw;
 layout (location = 1) in vec4 o_local_camera_pos_lod;
 layout (location = 2) in vec3 o_local_pos;
 layout (location = 3) in flat uint o_brick_index;
+layout (location = 4) in flat vec4 o_instance_pos_size;
+layout (location = 5) in flat uint o_material;
 
 layout (location = 0) out vec4 uFragColor;
 
@@ -64,16 +94,73 @@ bool outside(vec3 uwv) {
     return any(greaterThan(abs(uwv - vec3(0.5, 0.5, 0.5)), vec3(0.5, 0.5, 0.5)));
 }
 
+// Tiling noise in world space, faded out with camera distance. 0 when detail is off.
+float detail_distance(vec3 uvw) {
+    if (detail.enabled == 0) return 0.0;
+    vec4 material = detail.materials[min(o_material, 7u)];
+    float camera_distance = length(o_local_camera_pos_lod.xyz);
+    float fade = 1.0 - smoothstep(material.z, material.w, camera_distance);
+    if (fade <= 0.0) return 0.0;
+    vec3 world = o_instance_pos_size.xyz + (uvw - 0.5) * o_instance_pos_size.w;
+    float noise = textureLod(samplerDetail, world * material.y, 0.0).x - 0.5;
+    return noise * material.x * fade;
+}
+
+// Brick codes are sign(s) * |s|^(1 / brick_curve) of the distance s in [-1, 1], see BrickCurve
+float decode_brick(float code) {
+    float c = code * 2.0 - 1.0;
+    return sign(c) * pow(abs(c), ubo.brick_curve) * 0.5 + 0.5;
+}
+
+float sample_distance(vec3 uvw, float lod) {
+    return decode_brick(textureLod(samplerBricks, uvw, lod).x) + detail_distance(uvw) * 0.5;
+}
+
 vec3 normal(vec3 uvw) {
     float lod = o_local_camera_pos_lod.w;
     vec3 e = ubo.texel_scale.xyz * 0.5;
-    float xm = textureLod(samplerBricks, uvw + vec3(-e.x, 0,    0), lod).x;
-    float xp = textureLod(samplerBricks, uvw + vec3( e.x, 0,    0), lod).x;
-    float ym = textureLod(samplerBricks, uvw + vec3( 0,   -e.y, 0), lod).x;
-    float yp = textureLod(samplerBricks, uvw + vec3( 0,   e.y,  0), lod).x;
-    float zm = textureLod(samplerBricks, uvw + vec3( 0,   0, -e.z), lod).x;
-    float zp = textureLod(samplerBricks, uvw + vec3( 0,   0,  e.z), lod).x;
-    return normalize(vec3(xp - xm, yp - ym, zp - zm));
+    float xm = sample_distance(uvw + vec3(-e.x, 0,    0), lod);
+    float xp = sample_distance(uvw + vec3( e.x, 0,    0), lod);
+    float ym = sample_distance(uvw + vec3( 0,   -e.y, 0), lod);
+    float yp = sample_distance(uvw + vec3( 0,   e.y,  0), lod);
+    float zm = sample_distance(uvw + vec3( 0,   0, -e.z), lod);
+    float zp = sample_distance(uvw + vec3( 0,   0,  e.z), lod);
+    // Gradient in uvw space, anisotropic volumes need the normal matrix to light correctly
+    vec3 gradient = vec3(xp - xm, yp - ym, zp - zm) / ubo.texel_scale.xyz;
+    return normalize(ubo.normal_matrix * gradient);
+}
+
+// World position of a point of the brick, the box of svo_main.vert
+vec3 world_position(vec3 uvw) {
+    vec3 spacing = ubo.center_to_edge.xyz * ubo.texel_scale.xyz;
+    vec3 aspect = spacing / max(spacing.x, max(spacing.y, spacing.z));
+    return o_instance_pos_size.xyz + (uvw - 0.5) * o_instance_pos_size.w * aspect;
+}
+
+// Windowed inverse square falloff and spot cone of the scene lights, matches Light in lights.rs
+vec3 light_surface(vec3 position, vec3 n) {
+    vec3 result = vec3(ambient);
+    for (uint i = 0; i < light_count; ++i) {
+        LightData light = lights[i];
+        vec3 to_light = -light.direction_cos_outer.xyz;
+        float attenuation = 1.0;
+        if (light.type != 0) {
+            vec3 offset = light.position_range.xyz - position;
+            float distance = length(offset);
+            to_light = offset / max(distance, 1e-4);
+            float ratio = distance / light.position_range.w;
+            float window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+            attenuation = window * window / max(distance * distance, 1e-4);
+        }
+        if (light.type == 2) {
+            float cos_outer = light.direction_cos_outer.w;
+            float cos_inner = max(light.color_cos_inner.w, cos_outer + 1e-4);
+            float cos_angle = dot(-to_light, light.direction_cos_outer.xyz);
+            attenuation *= smoothstep(cos_outer, cos_inner, cos_angle);
+        }
+        result += light.color_cos_inner.rgb * attenuation * max(dot(n, to_light), 0.0);
+    }
+    return result;
 }
 
 void main() {
@@ -85,7 +172,7 @@ void main() {
     // Sample from the brick texture using the brick index
     // For now, we'll use a simple approach - in a full implementation,
     // you'd calculate the proper texture coordinates based on brick layout
-    float s = textureLod(samplerBricks, ray_pos, o_local_camera_pos_lod.w).x;
+    float s = sample_distance(ray_pos, o_local_camera_pos_lod.w);
     s = s * 2.0 - 1.0;
 
     float d = s;
@@ -97,14 +184,15 @@ void main() {
                 discard;
                 break;
             }
-            float s = textureLod(samplerBricks, uvw, o_local_camera_pos_lod.w).x;
+            float s = sample_distance(uvw, o_local_camera_pos_lod.w);
             s = s * 2.0 - 1.0;
             d += s;
             if (s < 0.00025) break;
         }
     }
     
-    vec3 final_normal = normal(ray_pos + ray_dir * d);
+    vec3 hit_uvw = ray_pos + ray_dir * d;
+    vec3 final_normal = normal(hit_uvw);
     
     // Color based on brick index for debugging
     vec3 brick_color = vec3(
@@ -113,5 +201,11 @@ void main() {
         float((o_brick_index * 211) % 255) / 255.0
     );
     
-    uFragColor = vec4(final_normal * 0.7 + brick_color * 0.3, 1.0);
+    // Normal debug colors until the scene has lights
+    if (light_count == 0) {
+        uFragColor = vec4(final_normal * 0.7 + brick_color * 0.3, 1.0);
+        return;
+    }
+    vec3 albedo = mix(vec3(0.8), brick_color, 0.3);
+    uFragColor = vec4(albedo * light_surface(world_position(hit_uvw), final_normal), 1.0);
 }
\ No newline at end of file
@@ -4,22 +4,39 @@
 
 #define USE_VISIBILITY_DATA
 
-layout (binding = 0) uniform UBO {
+layout(push_constant) uniform PushConstants {
     mat4 world_to_screen;
-    vec4 color;
     vec4 camera_position;
+} push;
+
+layout (binding = 0) uniform UBO {
+    vec4 color;
     vec4 volume_scale;
     vec4 center_to_edge;
     vec4 texel_scale;
     uint brick_size;
+    float brick_curve; // Exponent of the brick code curve, 1 = linear
+    uint bricks_per_scene_instance;
+    uint scene_instance_visibility; // Visibility lists scene instances instead of bricks
+    mat3 normal_matrix; // Brick uvw to volume space normals
 } ubo;
 
-struct InstanceData
+// Stored once, xyz is the brick offset in the volume and w its size
+struct BrickData
 {
     vec4 position;
     uint brick_index;
     uint brick_size;
-    uint padding[2];
+    uint atlas_slot;
+    uint padding;
+};
+
+// xyz is the translation and w the scale of the volume copy
+struct SceneInstanceData
+{
+    vec4 translation_scale;
+    uint material;
+    uint padding[3];
 };
 
 struct VisibilityData
@@ -38,9 +55,9 @@ struct OctreeNode
     uint padding[2];
 };
 
-layout(std430, binding = 1) buffer Instances
+layout(std430, binding = 1) buffer Bricks
 {
-    InstanceData instances[];
+    BrickData bricks[];
 };
 
 layout(std430, binding = 2) buffer Visibility
@@ -55,29 +72,54 @@ layout(std430, binding = 4) buffer OctreeBuffer
     OctreeNode octree_nodes[];
 };
 
+layout(std430, binding = 8) buffer SceneInstances
+{
+    SceneInstanceData scene_instances[];
+};
+
 layout (location = 0) out vec3 o_uvw;
 layout (location = 1) out vec4 o_local_camera_pos_lod;
 layout (location = 2) out vec3 o_local_pos;
 layout (location = 3) out flat uint o_brick_index;
+layout (location = 4) out flat vec4 o_instance_pos_size;
+layout (location = 5) out flat uint o_material;
 
 void main() {
     uint vx = gl_VertexIndex;
-    uint instance = vx >> 3;
+    uint instance = gl_InstanceIndex;
 
 #ifdef USE_VISIBILITY_DATA
-    instance = visibility[instance].index;
+    // Culling lists the visible bricks of all scene instances. Without culling it lists the
+    // visible scene instances and every one of them draws all its bricks.
+    if (ubo.scene_instance_visibility != 0)
+    {
+        uint scene_instance = visibility[instance / ubo.bricks_per_scene_instance].index;
+        instance = scene_instance * ubo.bricks_per_scene_instance
+            + instance % ubo.bricks_per_scene_instance;
+    }
+    else
+    {
+        instance = visibility[instance].index;
+    }
 #endif
 
     uvec3 xyz = uvec3(vx & 0x1, (vx & 0x4) >> 2, (vx & 0x2) >> 1);
     vec3 uvw = vec3(xyz);
     vec3 pos = uvw * 2.0 - 1.0;
 
-    InstanceData inst = instances[instance];
-    vec3 instance_pos = inst.position.xyz;
-    float instance_radius = inst.position.w;
+    // Brick placed in the volume, the volume placed by the scene instance
+    BrickData brick = bricks[instance % ubo.bricks_per_scene_instance];
+    SceneInstanceData scene_instance = scene_instances[instance / ubo.bricks_per_scene_instance];
+    float instance_scale = scene_instance.translation_scale.w;
+    vec3 instance_pos = scene_instance.translation_scale.xyz + brick.position.xyz * instance_scale;
+    float instance_radius = brick.position.w * instance_scale;
 
-    vec3 local_pos = pos.xyz * instance_radius * 0.5;
-    vec3 local_camera_pos = ubo.camera_position.xyz - instance_pos;
+    // Bricks of anisotropic volumes are boxes, instance_radius is the size along the axis
+    // with the largest voxel spacing. center_to_edge * texel_scale = spacing / 2.
+    vec3 spacing = ubo.center_to_edge.xyz * ubo.texel_scale.xyz;
+    vec3 aspect = spacing / max(spacing.x, max(spacing.y, spacing.z));
+    vec3 local_pos = pos.xyz * instance_radius * 0.5 * aspect;
+    vec3 local_camera_pos = push.camera_position.xyz - instance_pos;
 
     float lod = 0.5 * log2(dot(local_camera_pos, local_camera_pos)) - 6.0;
 
@@ -86,7 +128,9 @@ void main() {
     o_uvw = uvw * (vec3(1.0) - texel_scale_lod) + texel_scale_lod * 0.5;
     o_local_pos = local_pos;
     o_local_camera_pos_lod = vec4(local_camera_pos, lod);
-    o_brick_index = inst.brick_index;
+    o_brick_index = brick.brick_index;
+    o_instance_pos_size = vec4(instance_pos, instance_radius);
+    o_material = scene_instance.material;
     
-    gl_Position = ubo.world_to_screen * vec4(local_pos + instance_pos, 1.0);
+    gl_Position = push.world_to_screen * vec4(local_pos + instance_pos, 1.0);
 }
\ No newline at end of file
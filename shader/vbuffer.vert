@@ -22,6 +22,23 @@ layout(std430, binding = 1) buffer Instances
     InstanceData instances[];
 };
 
+layout(constant_id = 0) const bool HALF_INSTANCES = false;
+
+layout(std430, binding = 1) buffer PackedInstances
+{
+    uvec2 packed_instances[];
+};
+
+vec3 instance_position(uint instance)
+{
+    if (HALF_INSTANCES)
+    {
+        uvec2 p = packed_instances[instance];
+        return vec3(unpackHalf2x16(p.x), unpackHalf2x16(p.y).x);
+    }
+    return instances[instance].position.xyz;
+}
+
 layout (location = 0) out vec3 o_uvw;
 
 void main() {
@@ -36,7 +53,7 @@ void main() {
     vec3 uvw = vec3(xyz) * (1.0 / (GRID_DIM_VX - 1));
     vec3 pos = uvw * 2.0 - 1.0;
 
-    vec3 instance_pos = instances[instance].position.xyz;
+    vec3 instance_pos = instance_position(instance);
 
     vec3 local_pos = pos.xyz * ubo.center_to_edge.xyz;
 
@@ -1,7 +1,7 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 #extension GL_ARB_shading_language_420pack : enable
-#extension GL_NV_mesh_shader : require
+#extension GL_EXT_mesh_shader : require
 
 layout (binding = 0) uniform UBO {
     mat4 world_to_screen;
@@ -24,7 +24,7 @@ layout(location = 0) in PerVertexData
   vec3 uvw;
 } fragVertIn;  
 
-perprimitiveNV layout(location = 1) in PerPrimitiveData
+layout(location = 1) perprimitiveEXT flat in PerPrimitiveData
 {
   uint primitiveID;
 } fragPrimIn;  
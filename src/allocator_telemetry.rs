@@ -0,0 +1,374 @@
+// Wraps gpu_allocator to record allocation lifetimes and block occupancy over time. The
+// fragmentation report and the timeline CSV are used for tuning brick texture and buffer sizes.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ash::vk;
+use ash::Instance;
+use gpu_allocator::vulkan::*;
+use gpu_allocator::AllocationSizes;
+
+// Block sizes passed to gpu_allocator, non-dedicated allocations of the same memory
+// type share blocks of this size
+pub const DEVICE_MEMBLOCK_SIZE: u64 = 256 * 1024 * 1024;
+pub const HOST_MEMBLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+pub fn allocation_sizes() -> AllocationSizes {
+    AllocationSizes::new(DEVICE_MEMBLOCK_SIZE, HOST_MEMBLOCK_SIZE)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationEvent {
+    Allocate,
+    Free,
+}
+
+impl AllocationEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AllocationEvent::Allocate => "allocate",
+            AllocationEvent::Free => "free",
+        }
+    }
+}
+
+// Allocator state after an allocate or free
+#[derive(Clone, Debug)]
+pub struct TimelineSample {
+    pub time: Duration, // Since allocator creation
+    pub event: AllocationEvent,
+    pub name: String,
+    pub size: u64,
+    pub heap: u32,
+    pub allocated_bytes: u64,
+    pub reserved_bytes: u64, // Blocks with live allocations
+    pub num_blocks: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct AllocationLifetime {
+    pub name: String,
+    pub size: u64,
+    pub heap: u32,
+    pub allocated_at: Duration,
+    pub freed_at: Duration,
+}
+
+struct LiveAllocation {
+    name: String,
+    size: u64,
+    heap: u32,
+    block_size: u64,
+    allocated_at: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HeapFragmentation {
+    pub heap: u32,
+    pub num_blocks: usize,
+    pub reserved_bytes: u64,
+    pub allocated_bytes: u64,
+    pub largest_free_bytes: u64, // Largest contiguous free range in any block
+}
+
+impl HeapFragmentation {
+    // Reserved by blocks but not used by allocations
+    pub fn wasted_bytes(&self) -> u64 {
+        self.reserved_bytes - self.allocated_bytes
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FragmentationReport {
+    pub heaps: Vec<HeapFragmentation>,
+    // gpu_allocator keeps one empty block per memory type around for reuse
+    pub empty_block_bytes: u64,
+    pub num_lifetimes: usize,
+    pub average_lifetime: Duration,
+}
+
+struct TrackedBlock {
+    heap: u32,
+    size: u64,
+    ranges: Vec<(u64, u64)>, // (offset, size) of live allocations
+}
+
+// Live allocations are keyed by (memory block, offset)
+pub struct AllocatorTelemetry {
+    start: Instant,
+    memory_types: Vec<(vk::MemoryPropertyFlags, u32)>,
+    live: HashMap<(vk::DeviceMemory, u64), LiveAllocation>,
+    pub lifetimes: Vec<AllocationLifetime>,
+    pub timeline: Vec<TimelineSample>,
+}
+
+impl AllocatorTelemetry {
+    pub fn new(instance: &Instance, pdevice: vk::PhysicalDevice) -> AllocatorTelemetry {
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(pdevice) };
+        let memory_types = memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .map(|memory_type| (memory_type.property_flags, memory_type.heap_index))
+            .collect();
+
+        AllocatorTelemetry {
+            start: Instant::now(),
+            memory_types,
+            live: HashMap::new(),
+            lifetimes: Vec::new(),
+            timeline: Vec::new(),
+        }
+    }
+
+    // gpu_allocator picks the first memory type allowed by the requirements with matching
+    // properties, so the heap follows from the allocation's memory properties
+    fn heap_index(&self, memory_type_bits: u32, properties: vk::MemoryPropertyFlags) -> u32 {
+        self.memory_types
+            .iter()
+            .enumerate()
+            .find(|(index, (flags, _))| {
+                memory_type_bits & (1 << index) != 0 && *flags == properties
+            })
+            .map(|(_, (_, heap))| *heap)
+            .unwrap_or(0)
+    }
+
+    fn record_allocate(&mut self, desc: &AllocationCreateDesc, allocation: &Allocation) {
+        let memory_properties = allocation.memory_properties();
+        let memblock_size = if memory_properties.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            HOST_MEMBLOCK_SIZE
+        } else {
+            DEVICE_MEMBLOCK_SIZE
+        };
+        let block_size = if allocation.is_dedicated() || allocation.size() > memblock_size {
+            allocation.size()
+        } else {
+            memblock_size
+        };
+
+        let live = LiveAllocation {
+            name: desc.name.to_string(),
+            size: allocation.size(),
+            heap: self.heap_index(desc.requirements.memory_type_bits, memory_properties),
+            block_size,
+            allocated_at: self.start.elapsed(),
+        };
+        let key = (unsafe { allocation.memory() }, allocation.offset());
+        self.live.insert(key, live);
+        let sample = self.sample(AllocationEvent::Allocate, &self.live[&key]);
+        self.timeline.push(sample);
+    }
+
+    fn record_free(&mut self, allocation: &Allocation) {
+        let key = (unsafe { allocation.memory() }, allocation.offset());
+        if let Some(live) = self.live.remove(&key) {
+            let sample = self.sample(AllocationEvent::Free, &live);
+            self.timeline.push(sample);
+            self.lifetimes.push(AllocationLifetime {
+                name: live.name,
+                size: live.size,
+                heap: live.heap,
+                allocated_at: live.allocated_at,
+                freed_at: self.start.elapsed(),
+            });
+        }
+    }
+
+    // Samples are taken after the allocation was inserted or removed
+    fn sample(&self, event: AllocationEvent, allocation: &LiveAllocation) -> TimelineSample {
+        let heaps = self.heap_fragmentation();
+        TimelineSample {
+            time: self.start.elapsed(),
+            event,
+            name: allocation.name.clone(),
+            size: allocation.size,
+            heap: allocation.heap,
+            allocated_bytes: heaps.iter().map(|heap| heap.allocated_bytes).sum(),
+            reserved_bytes: heaps.iter().map(|heap| heap.reserved_bytes).sum(),
+            num_blocks: heaps.iter().map(|heap| heap.num_blocks).sum(),
+        }
+    }
+
+    // Blocks are only known through their live allocations, empty blocks are not counted
+    fn heap_fragmentation(&self) -> Vec<HeapFragmentation> {
+        let mut blocks: HashMap<vk::DeviceMemory, TrackedBlock> = HashMap::new();
+        let mut heaps: Vec<HeapFragmentation> = Vec::new();
+        for (&(memory, offset), live) in self.live.iter() {
+            blocks
+                .entry(memory)
+                .or_insert_with(|| TrackedBlock {
+                    heap: live.heap,
+                    size: live.block_size,
+                    ranges: Vec::new(),
+                })
+                .ranges
+                .push((offset, live.size));
+            heap_entry(&mut heaps, live.heap).allocated_bytes += live.size;
+        }
+
+        // Free ranges are the gaps between the allocations of each block
+        for mut block in blocks.into_values() {
+            block.ranges.sort_unstable();
+            let mut largest = 0;
+            let mut end = 0;
+            for (offset, size) in block.ranges {
+                largest = largest.max(offset.saturating_sub(end));
+                end = end.max(offset + size);
+            }
+            largest = largest.max(block.size.saturating_sub(end));
+
+            let heap = heap_entry(&mut heaps, block.heap);
+            heap.num_blocks += 1;
+            heap.reserved_bytes += block.size;
+            heap.largest_free_bytes = heap.largest_free_bytes.max(largest);
+        }
+
+        heaps.sort_by_key(|heap| heap.heap);
+        heaps
+    }
+
+    pub fn fragmentation_report(&self, allocator: &Allocator) -> FragmentationReport {
+        let heaps = self.heap_fragmentation();
+        let tracked_bytes: u64 = heaps.iter().map(|heap| heap.reserved_bytes).sum();
+        let reserved_bytes = allocator.generate_report().total_reserved_bytes;
+
+        let total_lifetime: Duration = self
+            .lifetimes
+            .iter()
+            .map(|lifetime| lifetime.freed_at - lifetime.allocated_at)
+            .sum();
+        let average_lifetime = if self.lifetimes.is_empty() {
+            Duration::ZERO
+        } else {
+            total_lifetime / self.lifetimes.len() as u32
+        };
+
+        FragmentationReport {
+            heaps,
+            empty_block_bytes: reserved_bytes.saturating_sub(tracked_bytes),
+            num_lifetimes: self.lifetimes.len(),
+            average_lifetime,
+        }
+    }
+
+    pub fn write_timeline_csv(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "time_ms,event,name,size,heap,allocated_bytes,reserved_bytes,num_blocks"
+        )?;
+        for sample in self.timeline.iter() {
+            writeln!(
+                writer,
+                "{:.3},{},{},{},{},{},{},{}",
+                sample.time.as_secs_f64() * 1000.0,
+                sample.event.as_str(),
+                sample.name,
+                sample.size,
+                sample.heap,
+                sample.allocated_bytes,
+                sample.reserved_bytes,
+                sample.num_blocks
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+fn heap_entry(heaps: &mut Vec<HeapFragmentation>, heap: u32) -> &mut HeapFragmentation {
+    let index = match heaps.iter().position(|entry| entry.heap == heap) {
+        Some(index) => index,
+        None => {
+            heaps.push(HeapFragmentation {
+                heap,
+                ..Default::default()
+            });
+            heaps.len() - 1
+        }
+    };
+    &mut heaps[index]
+}
+
+// Forwards to gpu_allocator, allocate and free also record telemetry
+pub struct TrackingAllocator {
+    allocator: Allocator,
+    pub telemetry: AllocatorTelemetry,
+}
+
+impl TrackingAllocator {
+    pub fn new(allocator: Allocator, telemetry: AllocatorTelemetry) -> TrackingAllocator {
+        TrackingAllocator {
+            allocator,
+            telemetry,
+        }
+    }
+
+    pub fn allocate(&mut self, desc: &AllocationCreateDesc) -> gpu_allocator::Result<Allocation> {
+        let allocation = self.allocator.allocate(desc)?;
+        self.telemetry.record_allocate(desc, &allocation);
+        Ok(allocation)
+    }
+
+    pub fn free(&mut self, allocation: Allocation) -> gpu_allocator::Result<()> {
+        self.telemetry.record_free(&allocation);
+        self.allocator.free(allocation)
+    }
+
+    pub fn fragmentation_report(&self) -> FragmentationReport {
+        self.telemetry.fragmentation_report(&self.allocator)
+    }
+}
+
+impl Deref for TrackingAllocator {
+    type Target = Allocator;
+
+    fn deref(&self) -> &Allocator {
+        &self.allocator
+    }
+}
+
+impl DerefMut for TrackingAllocator {
+    fn deref_mut(&mut self) -> &mut Allocator {
+        &mut self.allocator
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+impl fmt::Display for FragmentationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "GPU memory fragmentation:")?;
+        for heap in self.heaps.iter() {
+            writeln!(
+                f,
+                "  Heap {}: {} blocks, {:.1} of {:.1} MiB allocated, {:.1} MiB wasted, \
+                 largest free block {:.1} MiB",
+                heap.heap,
+                heap.num_blocks,
+                mib(heap.allocated_bytes),
+                mib(heap.reserved_bytes),
+                mib(heap.wasted_bytes()),
+                mib(heap.largest_free_bytes)
+            )?;
+        }
+        writeln!(
+            f,
+            "  Empty blocks kept for reuse: {:.1} MiB",
+            mib(self.empty_block_bytes)
+        )?;
+        write!(
+            f,
+            "  Freed allocations: {}, average lifetime {:.1} s",
+            self.num_lifetimes,
+            self.average_lifetime.as_secs_f32()
+        )
+    }
+}
@@ -0,0 +1,77 @@
+// Asset path resolution. Relative asset paths ("data/x.svosdf", "lang/en.toml") are searched in
+// the RUST_TEST_ASSETS directory, the working directory, then the executable directory and its
+// parents (target/release/<binary> finds the repository root). Critical assets are embedded so
+// the binaries run from any directory (shaders are include_bytes! in the renderer modules and
+// English strings are built into localization).
+
+use std::borrow::Cow;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const ASSET_DIR_ENV: &str = "RUST_TEST_ASSETS";
+
+// Small scene used when the main data file can't be found
+pub const FALLBACK_SVO_SDF: &str = "sphere.svosdf";
+
+const EXECUTABLE_PARENT_LEVELS: usize = 3;
+
+static EMBEDDED: &[(&str, &[u8])] = &[(FALLBACK_SVO_SDF, include_bytes!("../sphere.svosdf"))];
+
+pub fn search_roots() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+    if let Some(dir) = env::var_os(ASSET_DIR_ENV) {
+        roots.push(PathBuf::from(dir));
+    }
+    if let Ok(dir) = env::current_dir() {
+        roots.push(dir);
+    }
+    if let Some(dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        roots.extend(
+            dir.ancestors()
+                .take(EXECUTABLE_PARENT_LEVELS + 1)
+                .map(Path::to_path_buf),
+        );
+    }
+    roots
+}
+
+// First existing match, absolute paths are returned as is
+pub fn resolve(relative: &str) -> Option<PathBuf> {
+    let path = Path::new(relative);
+    if path.is_absolute() {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    search_roots()
+        .into_iter()
+        .map(|root| root.join(path))
+        .find(|candidate| candidate.exists())
+}
+
+// Resolved path, or the relative path unchanged so errors report the name that was asked for
+pub fn asset_path(relative: &str) -> PathBuf {
+    resolve(relative).unwrap_or_else(|| PathBuf::from(relative))
+}
+
+pub fn embedded(relative: &str) -> Option<&'static [u8]> {
+    EMBEDDED
+        .iter()
+        .find(|(name, _)| *name == relative)
+        .map(|(_, bytes)| *bytes)
+}
+
+// Files on disk take priority over the embedded copy
+pub fn read(relative: &str) -> io::Result<Cow<'static, [u8]>> {
+    match resolve(relative) {
+        Some(path) => std::fs::read(path).map(Cow::Owned),
+        None => embedded(relative).map(Cow::Borrowed).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Asset {} not found", relative),
+            )
+        }),
+    }
+}
@@ -6,10 +6,13 @@ use std::mem;
 use ash::util::*;
 use ash::{vk, Device};
 
+use crate::allocator_telemetry::*;
 use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
+use rust_test::device_capabilities::DeviceCapabilities;
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
 #[derive(Clone, Copy)]
@@ -18,26 +21,21 @@ pub struct VisibilityData {
 }
 
 #[derive(Clone, Copy)]
-pub struct DrawIndexedIndirectArguments {
-    pub index_count: u32,
-    pub instance_count: u32,
-    pub first_index: u32,
-    pub vertex_offset: i32,
-    pub first_instance: u32,
-}
-
-#[derive(Clone, Copy)]
+#[repr(C)]
 pub struct CullingUniforms {
     pub world_to_screen: Mat4x4,
     pub depth_pyramid_dimension: u32, // pow2 y dimension of mip 0 (texture x is 1.5x wider)
 }
 
+unsafe impl Zeroable for CullingUniforms {}
+unsafe impl Pod for CullingUniforms {}
+
 pub struct Culling {
     pub pipeline_layout: vk::PipelineLayout,
     pub uniform_buffer: VkBuffer,
     pub uniform_buffer_gpu: VkBuffer,
     pub visibility_buffer: VkBuffer,
-    pub visibility_arguments: VkBuffer,
+    pub visibility_arguments: IndirectArgsBuffer,
     pub visibility_buffer_descriptor: vk::DescriptorBufferInfo,
     pub desc_set_layout: vk::DescriptorSetLayout,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
@@ -46,14 +44,17 @@ pub struct Culling {
 }
 
 impl Culling {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        capabilities: &DeviceCapabilities,
+        descriptor_allocator: &mut DescriptorAllocator,
         depth_pyramid_descriptor: &vk::DescriptorImageInfo,
         depth_pyramid_debug_descriptor: &vk::DescriptorImageInfo,
         instances_buffer_descriptor: &vk::DescriptorBufferInfo,
         num_instances: usize,
+        depth: DepthConvention,
     ) -> Culling {
         let visibility_buffer_info = vk::BufferCreateInfo {
             size: (std::mem::size_of::<VisibilityData>() * num_instances) as u64,
@@ -75,27 +76,9 @@ impl Culling {
             range: (std::mem::size_of::<VisibilityData>() * num_instances) as u64,
         };
 
-        let visibility_arguments_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
-            usage: vk::BufferUsageFlags::STORAGE_BUFFER
-                | vk::BufferUsageFlags::TRANSFER_DST
-                | vk::BufferUsageFlags::INDIRECT_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let visibility_arguments = VkBuffer::new(
-            device,
-            allocator,
-            &visibility_arguments_info,
-            MemoryLocation::GpuOnly,
-        );
-
-        let visibility_arguments_descriptor = vk::DescriptorBufferInfo {
-            buffer: visibility_arguments.buffer,
-            offset: 0,
-            range: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
-        };
+        let visibility_arguments =
+            IndirectArgsBuffer::new(device, allocator, capabilities, 1, MemoryLocation::GpuOnly);
+        let visibility_arguments_descriptor = visibility_arguments.descriptor();
 
         let uniform_buffer_info = vk::BufferCreateInfo {
             size: std::mem::size_of::<CullingUniforms>() as u64,
@@ -180,15 +163,9 @@ impl Culling {
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let descriptor_sets = {
-            let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-                descriptor_pool: *descriptor_pool,
-                descriptor_set_count: desc_set_layouts.len() as u32,
-                p_set_layouts: desc_set_layouts.as_ptr(),
-                ..Default::default()
-            };
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
         let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: uniform_buffer_gpu.buffer,
@@ -270,6 +247,11 @@ impl Culling {
                 .expect("Fragment shader module error")
         };
 
+        // Farthest depth and occlusion test of the depth convention
+        let specialization = SpecializationConstants::new()
+            .with(REVERSED_DEPTH_CONSTANT_ID, depth.specialization_value());
+        let specialization_info = specialization.info();
+
         let shader_entry_name = CString::new("main").unwrap();
 
         let compute_pipeline_info = {
@@ -277,6 +259,7 @@ impl Culling {
                 module: compute_shader_module,
                 p_name: shader_entry_name.as_ptr(),
                 stage: vk::ShaderStageFlags::COMPUTE,
+                p_specialization_info: &specialization_info,
                 ..Default::default()
             };
             vk::ComputePipelineCreateInfo {
@@ -312,23 +295,17 @@ impl Culling {
     }
 
     pub fn gpu_setup(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        unsafe {
-            // Lazy way to init the argument buffer :)
-            let arguments = [0, 1, 0, 0, 0];
-            for (a, i) in arguments.iter().enumerate() {
-                device.cmd_fill_buffer(
-                    *command_buffer,
-                    self.visibility_arguments.buffer,
-                    (i * std::mem::size_of::<u32>()) as u64,
-                    std::mem::size_of::<u32>() as u64,
-                    a as u32,
-                );
-            }
-        }
+        // One instance, the culling shader appends the visible cubes to the index count
+        let arguments = DrawIndexedIndirectCommand {
+            instance_count: 1,
+            ..Default::default()
+        };
+        self.visibility_arguments
+            .cmd_write(device, *command_buffer, 0, &[arguments]);
     }
 
     pub fn update(&self, uniforms: &CullingUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+        self.uniform_buffer.copy_struct(uniforms, 0).unwrap();
     }
 
     pub fn gpu_draw(
@@ -365,27 +342,27 @@ impl Culling {
         let arguments_barrier_clear = vk::BufferMemoryBarrier {
             //src_access_mask: vk::AccessFlags::INDIRECT_COMMAND_READ,
             dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            buffer: self.visibility_arguments.buffer,
+            buffer: self.visibility_arguments.buffer.buffer.buffer,
             offset: 0,
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
+            size: self.visibility_arguments.size_bytes(),
             ..Default::default()
         };
 
         let arguments_barrier_compute = vk::BufferMemoryBarrier {
             src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
             dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
-            buffer: self.visibility_arguments.buffer,
+            buffer: self.visibility_arguments.buffer.buffer.buffer,
             offset: 0,
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
+            size: self.visibility_arguments.size_bytes(),
             ..Default::default()
         };
 
         let arguments_barrier_indirect = vk::BufferMemoryBarrier {
             src_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
             dst_access_mask: vk::AccessFlags::INDIRECT_COMMAND_READ,
-            buffer: self.visibility_arguments.buffer,
+            buffer: self.visibility_arguments.buffer.buffer.buffer,
             offset: 0,
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
+            size: self.visibility_arguments.size_bytes(),
             ..Default::default()
         };
 
@@ -480,13 +457,8 @@ impl Culling {
             );
 
             // Clear the visible index count (remaining of the buffer stays)
-            device.cmd_fill_buffer(
-                *command_buffer,
-                self.visibility_arguments.buffer,
-                0,
-                std::mem::size_of::<u32>() as u64,
-                0,
-            );
+            self.visibility_arguments
+                .cmd_reset_index_counts(device, *command_buffer);
 
             // Pyramid to GPU read
             device.cmd_pipeline_barrier(
@@ -554,7 +526,7 @@ impl Culling {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             self.visibility_arguments.destroy(device, allocator);
             self.visibility_buffer.destroy(device, allocator);
@@ -9,16 +9,22 @@ use std::mem;
 use ash::util::*;
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::projection_settings::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct CullingDebugUniforms {
     pub depth_pyramid_dimension: u32, // pow2 y dimension of mip 0 (texture x is 1.5x wider)
 }
 
+unsafe impl Zeroable for CullingDebugUniforms {}
+unsafe impl Pod for CullingDebugUniforms {}
+
 pub struct CullingDebug {
     pub pipeline_layout: vk::PipelineLayout,
     pub uniform_buffer: VkBuffer,
@@ -34,11 +40,12 @@ impl CullingDebug {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
         render_pass: &vk::RenderPass,
         view_scissor: &VkViewScissor,
         depth_pyramid_debug_descriptor: &vk::DescriptorImageInfo,
+        depth: DepthConvention,
     ) -> CullingDebug {
         let uniform_buffer_info = vk::BufferCreateInfo {
             size: std::mem::size_of::<CullingDebugUniforms>() as u64,
@@ -95,15 +102,9 @@ impl CullingDebug {
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let descriptor_sets = {
-            let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-                descriptor_pool: *descriptor_pool,
-                descriptor_set_count: desc_set_layouts.len() as u32,
-                p_set_layouts: desc_set_layouts.as_ptr(),
-                ..Default::default()
-            };
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
         let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: uniform_buffer_gpu.buffer,
@@ -227,7 +228,7 @@ impl CullingDebug {
         let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
             depth_test_enable: 1,
             depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            depth_compare_op: depth.compare_op(),
             front: noop_stencil_state,
             back: noop_stencil_state,
             max_depth_bounds: 1.0,
@@ -298,7 +299,7 @@ impl CullingDebug {
     }
 
     pub fn update(&self, uniforms: &CullingDebugUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+        self.uniform_buffer.copy_struct(uniforms, 0).unwrap();
     }
 
     pub fn gpu_draw(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
@@ -376,7 +377,7 @@ impl CullingDebug {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             device.destroy_pipeline(self.graphic_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
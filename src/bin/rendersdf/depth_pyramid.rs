@@ -6,9 +6,11 @@ use std::mem;
 use ash::util::*;
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::projection_settings::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
@@ -17,11 +19,15 @@ pub struct DepthPyramidPushConstants {
 }
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct DepthPyramidUniforms {
-    pub depth_buffer_dimensions: (u32, u32),
+    pub depth_buffer_dimensions: [u32; 2],
     pub depth_pyramid_dimension: u32, // pow2 y dimension of mip 0 (texture x is 1.5x wider)
 }
 
+unsafe impl Zeroable for DepthPyramidUniforms {}
+unsafe impl Pod for DepthPyramidUniforms {}
+
 pub struct DepthPyramid {
     pub pipeline_layout: vk::PipelineLayout,
     pub uniform_buffer: VkBuffer,
@@ -48,10 +54,12 @@ pub struct DepthPyramid {
 impl DepthPyramid {
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
         depth_view: &vk::ImageView,
         image_dimensions: (u32, u32),
+        sampler_cache: &mut SamplerCache,
+        depth: DepthConvention,
     ) -> DepthPyramid {
         let uniform_buffer_info = vk::BufferCreateInfo {
             size: std::mem::size_of::<DepthPyramidUniforms>() as u64,
@@ -157,20 +165,13 @@ impl DepthPyramid {
             MemoryLocation::GpuOnly,
         );
 
-        let sampler_info = vk::SamplerCreateInfo {
-            mag_filter: vk::Filter::LINEAR,
-            min_filter: vk::Filter::LINEAR,
-            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
-            address_mode_u: vk::SamplerAddressMode::REPEAT,
-            address_mode_v: vk::SamplerAddressMode::REPEAT,
-            address_mode_w: vk::SamplerAddressMode::REPEAT,
-            max_anisotropy: 1.0,
-            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
-            compare_op: vk::CompareOp::NEVER,
-            ..Default::default()
-        };
-
-        let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+        let sampler = sampler_cache.get(
+            device,
+            SamplerDesc {
+                anisotropic: false,
+                ..Default::default()
+            },
+        );
 
         let view_info = vk::ImageViewCreateInfo {
             view_type: vk::ImageViewType::TYPE_2D,
@@ -190,7 +191,7 @@ impl DepthPyramid {
             image: image.image,
             ..Default::default()
         };
-        let view = unsafe { device.create_image_view(&view_info, None) }.unwrap();
+        let view = create_image_view(device, &view_info).unwrap();
 
         let view_debug_info = vk::ImageViewCreateInfo {
             view_type: vk::ImageViewType::TYPE_2D,
@@ -210,7 +211,7 @@ impl DepthPyramid {
             image: image_debug.image,
             ..Default::default()
         };
-        let view_debug = unsafe { device.create_image_view(&view_debug_info, None) }.unwrap();
+        let view_debug = create_image_view(device, &view_debug_info).unwrap();
 
         let view_counters_info = vk::ImageViewCreateInfo {
             view_type: vk::ImageViewType::TYPE_2D,
@@ -230,7 +231,7 @@ impl DepthPyramid {
             image: image_counters.image,
             ..Default::default()
         };
-        let view_counters = unsafe { device.create_image_view(&view_counters_info, None) }.unwrap();
+        let view_counters = create_image_view(device, &view_counters_info).unwrap();
 
         let descriptor_rw = vk::DescriptorImageInfo {
             image_layout: vk::ImageLayout::GENERAL,
@@ -303,16 +304,9 @@ impl DepthPyramid {
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-            descriptor_pool: *descriptor_pool,
-            descriptor_set_count: 1,
-            p_set_layouts: desc_set_layouts.as_ptr(),
-            ..Default::default()
-        };
-
-        let descriptor_sets = {
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
         let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: uniform_buffer_gpu.buffer,
@@ -362,14 +356,11 @@ impl DepthPyramid {
         ];
         unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
 
-        let layout_create_info = vk::PipelineLayoutCreateInfo {
-            set_layout_count: desc_set_layouts.len() as u32,
-            p_set_layouts: desc_set_layouts.as_ptr(),
-            ..Default::default()
-        };
-
-        let pipeline_layout =
-            unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
+        let pipeline_layout = PipelineLayoutBuilder::new()
+            .set_layouts(desc_set_layouts)
+            .push_constants::<DepthPyramidPushConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build(device)
+            .unwrap();
 
         let compute_shader_module_pass_1 = {
             let mut comp_spv_file = Cursor::new(&include_bytes!(
@@ -387,6 +378,11 @@ impl DepthPyramid {
                 .expect("Fragment shader module error")
         };
 
+        // Farthest depth and occlusion test of the depth convention
+        let specialization = SpecializationConstants::new()
+            .with(REVERSED_DEPTH_CONSTANT_ID, depth.specialization_value());
+        let specialization_info = specialization.info();
+
         let shader_entry_name = CString::new("main").unwrap();
 
         let compute_pipeline_info_pass_1 = vk::ComputePipelineCreateInfo {
@@ -394,6 +390,7 @@ impl DepthPyramid {
                 module: compute_shader_module_pass_1,
                 p_name: shader_entry_name.as_ptr(),
                 stage: vk::ShaderStageFlags::COMPUTE,
+                p_specialization_info: &specialization_info,
                 ..Default::default()
             },
             layout: pipeline_layout,
@@ -421,6 +418,7 @@ impl DepthPyramid {
                 module: compute_shader_module_downsample,
                 p_name: shader_entry_name.as_ptr(),
                 stage: vk::ShaderStageFlags::COMPUTE,
+                p_specialization_info: &specialization_info,
                 ..Default::default()
             },
             layout: pipeline_layout,
@@ -508,7 +506,7 @@ impl DepthPyramid {
     }
 
     pub fn update(&self, uniforms: &DepthPyramidUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+        self.uniform_buffer.copy_struct(uniforms, 0).unwrap();
     }
 
     pub fn gpu_draw(
@@ -516,6 +514,7 @@ impl DepthPyramid {
         device: &Device,
         command_buffer: &vk::CommandBuffer,
         depth_image: &vk::Image,
+        depth_aspect_mask: vk::ImageAspectFlags,
         pyramid_mip0_dimension: u32,
         num_mips: u32,
     ) {
@@ -550,7 +549,7 @@ impl DepthPyramid {
             new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
             image: *depth_image,
             subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                aspect_mask: depth_aspect_mask,
                 level_count: 1,
                 layer_count: 1,
                 ..Default::default()
@@ -566,7 +565,7 @@ impl DepthPyramid {
             new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
             image: *depth_image,
             subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::DEPTH,
+                aspect_mask: depth_aspect_mask,
                 level_count: 1,
                 layer_count: 1,
                 ..Default::default()
@@ -694,12 +693,13 @@ impl DepthPyramid {
                 let mip2 = 1 << mip;
                 let push_constants = DepthPyramidPushConstants { mip: mip - 1 };
 
-                device.cmd_push_constants(
+                cmd_push(
+                    device,
                     *command_buffer,
                     self.pipeline_layout,
                     vk::ShaderStageFlags::COMPUTE,
                     0,
-                    raw_bytes(&[push_constants]),
+                    &push_constants,
                 );
 
                 let dim = (
@@ -722,17 +722,16 @@ impl DepthPyramid {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
-            device.destroy_image_view(self.view, None);
-            device.destroy_image_view(self.view_debug, None);
-            device.destroy_image_view(self.view_counters, None);
+            destroy_image_view(device, self.view);
+            destroy_image_view(device, self.view_debug);
+            destroy_image_view(device, self.view_counters);
             self.image.destroy(device, allocator);
             self.image_debug.destroy(device, allocator);
             self.image_counters.destroy(device, allocator);
             self.uniform_buffer.destroy(device, allocator);
             self.uniform_buffer_gpu.destroy(device, allocator);
-            device.destroy_sampler(self.sampler, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
             device.destroy_descriptor_set_layout(self.desc_set_layout, None);
             device.destroy_pipeline(self.compute_pipeline_pass_1, None);
@@ -1,30 +1,40 @@
 pub const NUM_INSTANCES: usize = 1024 * 1024;
 pub const CLOUD_RADIUS: f32 = 8000.0;
+// Clumps of roughly a quarter of the cloud radius
+pub const CLUSTER_FREQUENCY: f32 = 1.0 / 2000.0;
 
-use rand::Rng;
 use rand::SeedableRng;
 use std::default::Default;
 
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
 use crate::minivector::*;
+use crate::procedural::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct InstanceData {
     pub position: Vec4,
 }
 
+unsafe impl Zeroable for InstanceData {}
+unsafe impl Pod for InstanceData {}
+
 pub struct Instances {
     pub instances_buffer: VkBuffer,
     pub instances_buffer_descriptor: vk::DescriptorBufferInfo,
 }
 
 impl Instances {
-    pub fn new(device: &Device, allocator: &mut Allocator, instance_radius: f32) -> Instances {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        instance_radius: f32,
+    ) -> Instances {
         let instances_buffer_info = vk::BufferCreateInfo {
             size: (std::mem::size_of::<InstanceData>() * NUM_INSTANCES) as u64,
             usage: vk::BufferUsageFlags::STORAGE_BUFFER,
@@ -45,24 +55,27 @@ impl Instances {
             range: (std::mem::size_of::<InstanceData>() * NUM_INSTANCES) as u64,
         };
 
-        // Random cloud of SDF box instances
+        // Random cloud of SDF box instances, clustered by noise into clumps and voids
         //let mut rng = rand::thread_rng();
         let mut rng = rand::rngs::StdRng::from_seed([
             0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
             11, 12, 13, 14, 15,
         ]);
-        let instances_buffer_data: Vec<InstanceData> = (0..NUM_INSTANCES)
-            .map(|_i| InstanceData {
-                position: Vec4 {
-                    x: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    y: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    z: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    w: instance_radius,
-                },
-            })
-            .collect();
+        let density = Fbm::new(NoiseKind::Simplex, 3, CLUSTER_FREQUENCY);
+        let instances_buffer_data: Vec<InstanceData> =
+            clustered_cloud(NUM_INSTANCES, CLOUD_RADIUS, &density, &mut rng)
+                .into_iter()
+                .map(|position| InstanceData {
+                    position: Vec4 {
+                        w: instance_radius,
+                        ..position.to_4d()
+                    },
+                })
+                .collect();
 
-        instances_buffer.copy_from_slice(&instances_buffer_data[..], 0);
+        instances_buffer
+            .copy_from_slice(&instances_buffer_data[..], 0)
+            .unwrap();
 
         Instances {
             instances_buffer,
@@ -70,7 +83,7 @@ impl Instances {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         self.instances_buffer.destroy(device, allocator);
     }
 }
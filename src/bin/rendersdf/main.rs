@@ -1,8 +1,7 @@
 #![allow(dead_code)]
 
 const SDF_LEVELS: u32 = 6;
-const NUM_DESCRIPTORS_PER_TYPE: u32 = 1024;
-const NUM_DESCRIPTOR_SETS: u32 = 1024;
+const DESCRIPTOR_SETS_PER_POOL: u32 = 16;
 const ENABLE_CULLING_DEBUG: bool = false;
 
 extern crate winit;
@@ -14,22 +13,41 @@ mod instances;
 mod render_cubes;
 mod sdf_texture;
 
+use rust_test::allocator_telemetry;
+use rust_test::assets;
+use rust_test::color;
+use rust_test::display_settings;
+use rust_test::input;
+use rust_test::localization;
+use rust_test::memory_profile;
 use rust_test::minivector;
+use rust_test::procedural;
+use rust_test::projection_settings;
+use rust_test::quality_settings;
 use rust_test::sdf;
 use rust_test::vulkan_base;
 use rust_test::vulkan_helpers;
 
+use std::env;
 use std::time::Instant;
 
 use ash::vk;
 
 use winit::{
-    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
+    event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::EventLoop,
     window::WindowBuilder,
 };
 
+use assets::*;
+use color::*;
+use display_settings::*;
+use input::*;
+use localization::*;
+use memory_profile::*;
 use minivector::*;
+use projection_settings::*;
+use quality_settings::*;
 use sdf::*;
 
 use vulkan_base::*;
@@ -53,9 +71,32 @@ pub struct SdfLevel {
     pub offset: u32,
 }
 
+fn print_projection(settings: &ProjectionSettings) {
+    let near = format!("{}", settings.near);
+    let depth = settings.depth.name();
+    let message = match settings.projection_type {
+        ProjectionType::Perspective => {
+            let fov = format!("{}", settings.fov_degrees);
+            tr_args(
+                "viewer.projection_perspective",
+                &[("fov", &fov), ("near", &near), ("depth", &depth)],
+            )
+        }
+        ProjectionType::Orthographic => {
+            let height = format!("{}", settings.ortho_height);
+            tr_args(
+                "viewer.projection_orthographic",
+                &[("height", &height), ("near", &near), ("depth", &depth)],
+            )
+        }
+    };
+    println!("{}", message);
+}
+
 fn main() {
     // Distance field
-    let sdf = load_sdf_zlib("data/ganymede-and-jupiter.sdf").expect("SDF loading failed");
+    let sdf = load_sdf_zlib(&asset_path("data/ganymede-and-jupiter.sdf").to_string_lossy())
+        .expect("SDF loading failed");
 
     let mut sdf_levels = Vec::new();
     let mut sdf_total_voxels = sdf.header.dim.0 * sdf.header.dim.1 * sdf.header.dim.2;
@@ -67,13 +108,13 @@ fn main() {
         sdf_levels.push(SdfLevel { sdf, offset });
     }
 
-    let dx = sdf_levels[0].sdf.header.dx;
+    let world_size = sdf_levels[0].sdf.header.world_size();
     let dim = sdf_levels[0].sdf.header.dim;
 
     let diagonal = Vec3 {
-        x: dx * dim.0 as f32,
-        y: dx * dim.1 as f32,
-        z: dx * dim.2 as f32,
+        x: world_size.0,
+        y: world_size.1,
+        z: world_size.2,
     };
 
     let center_to_edge = diagonal * 0.5;
@@ -102,7 +143,22 @@ fn main() {
         .unwrap();
 
     // Vulkan base initialization
-    let mut base = VulkanBase::new(&window, window_width, window_height);
+    let args: Vec<String> = env::args().collect();
+    if let Err(err) = init_from_args(&args) {
+        println!(
+            "{}",
+            tr_args("cli.error.language_load_failed", &[("error", &err)])
+        );
+    }
+    let mut base = VulkanBase::new_with_settings(
+        &window,
+        window_width,
+        window_height,
+        MemoryProfile::default(),
+        DebugSettings::from_args(&args),
+        DisplaySettings::default(),
+    )
+    .expect("Vulkan initialization failed");
 
     // Render passes
     let render_pass_attachments = [
@@ -115,7 +171,7 @@ fn main() {
             ..Default::default()
         },
         vk::AttachmentDescription {
-            format: vk::Format::D32_SFLOAT,
+            format: base.depth_format,
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::CLEAR,
             initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
@@ -202,37 +258,19 @@ fn main() {
         VkViewScissor { viewport, scissor }
     };
 
-    // Descriptor pool
-    let descriptor_sizes = [
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_BUFFER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_IMAGE,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-    ];
-    let descriptor_pool_info = vk::DescriptorPoolCreateInfo {
-        pool_size_count: descriptor_sizes.len() as u32,
-        p_pool_sizes: descriptor_sizes.as_ptr(),
-        max_sets: NUM_DESCRIPTOR_SETS,
-        ..Default::default()
-    };
+    // Descriptor sets of all renderer modules, pools are added as needed
+    let mut descriptor_allocator = DescriptorAllocator::new(DESCRIPTOR_SETS_PER_POOL);
 
-    let descriptor_pool = unsafe {
-        base.device
-            .create_descriptor_pool(&descriptor_pool_info, None)
-    }
-    .unwrap();
+    // Samplers shared by all renderer modules
+    let quality_settings = QualitySettings::from_args(&args);
+    base.sampler_cache
+        .set_max_anisotropy(quality_settings.anisotropy);
+
+    // --fov, --near and --ortho, changed with hotkeys and console commands while running.
+    // --depth only on the command line, the pipelines are built for it.
+    let mut projection_settings = ProjectionSettings::from_args(&args);
+    let depth = projection_settings.depth;
+    let console_commands = spawn_console_commands();
 
     // SDF volume texture
     let mut sdf_texture = SdfTexture::new(
@@ -240,6 +278,7 @@ fn main() {
         &mut base.allocator,
         &sdf_levels,
         sdf_total_voxels as usize,
+        &mut base.sampler_cache,
     );
 
     // Cube instances
@@ -252,41 +291,47 @@ fn main() {
     let mut depth_pyramid = DepthPyramid::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
+        &mut descriptor_allocator,
         &base.depth_image_view,
         pyramid_texture_dimensions,
+        &mut base.sampler_cache,
+        depth,
     );
 
     let mut culling = Culling::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
+        &base.capabilities,
+        &mut descriptor_allocator,
         &depth_pyramid.descriptor_sample,
         &depth_pyramid.descriptor_debug_sample,
         &instances.instances_buffer_descriptor,
         NUM_INSTANCES,
+        depth,
     );
 
     let mut culling_debug = CullingDebug::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
+        &mut descriptor_allocator,
         &render_pass,
         &view_scissor,
         &depth_pyramid.descriptor_debug_sample,
+        depth,
     );
 
     // Cube renderer
     let mut render_cubes = RenderCubes::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
+        &mut descriptor_allocator,
         &render_pass,
         &view_scissor,
         &sdf_texture.descriptor,
         &instances.instances_buffer_descriptor,
         &culling.visibility_buffer_descriptor,
         NUM_INSTANCES,
+        depth,
     );
     // Submit initialization command buffer before rendering starts
     base.record_submit_commandbuffer(
@@ -302,12 +347,27 @@ fn main() {
             depth_pyramid.gpu_setup(device, &command_buffer);
             culling.gpu_setup(device, &command_buffer);
         },
-    );
+    )
+    .expect("Setup command buffer submit failed");
+
+    if args.iter().any(|arg| arg == "--mem-stats") {
+        println!("{}", base.memory_report());
+    }
 
-    // Camera
+    // Camera, the orientation rotates +Z to the view direction
     struct Camera {
         position: Vec3,
-        direction: Vec3,
+        orientation: Quat,
+    }
+
+    impl Camera {
+        fn direction(&self) -> Vec3 {
+            self.orientation.rotate_vec3(Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            })
+        }
     }
 
     let mut camera = Camera {
@@ -316,19 +376,35 @@ fn main() {
             y: 25.0,
             z: 50.0,
         },
-        direction: Vec3 {
-            x: 0.0,
-            y: -0.5,
-            z: -1.0,
-        },
+        orientation: Quat::look_rotation(
+            Vec3 {
+                x: 0.0,
+                y: -0.5,
+                z: -1.0,
+            },
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ),
     };
 
+    let input_settings = InputSettings::from_args(&args).unwrap_or_else(|err| {
+        println!(
+            "{}",
+            tr_args("viewer.input_settings_failed", &[("error", &err)])
+        );
+        InputSettings::default()
+    });
+
     // Inputs
     #[derive(Clone, Copy)]
     struct Inputs {
         is_left_clicked: bool,
         cursor_position: (i32, i32),
         wheel_delta: f32,
+        raw_mouse_delta: (f32, f32),
         keyboard_forward: i32,
         keyboard_side: i32,
     }
@@ -339,6 +415,7 @@ fn main() {
                 is_left_clicked: false,
                 cursor_position: (0, 0),
                 wheel_delta: 0.0,
+                raw_mouse_delta: (0.0, 0.0),
                 keyboard_forward: 0,
                 keyboard_side: 0,
             }
@@ -346,10 +423,12 @@ fn main() {
     }
 
     // Window event loop
-    println!("Start window event loop");
+    println!("{}", tr("viewer.start_event_loop"));
 
     let mut inputs_prev: Inputs = Default::default();
     let mut inputs: Inputs = Default::default();
+    let mut mouse_look = MouseLook::new();
+    let mut time_prev_frame = Instant::now();
 
     let mut time_start = Instant::now();
     let mut frame = 0u32;
@@ -360,6 +439,7 @@ fn main() {
         match event {
             Event::NewEvents(_) => {
                 inputs.wheel_delta = 0.0;
+                inputs.raw_mouse_delta = (0.0, 0.0);
             }
 
             Event::AboutToWait => {
@@ -371,51 +451,38 @@ fn main() {
                 inputs_prev = inputs;
 
                 // Update camera based in inputs
-                let view_rot = view(
-                    Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 0.0,
-                    },
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                );
-
                 let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
-                camera.position = camera.position + camera.direction * forward_speed;
+                camera.position = camera.position + camera.direction() * forward_speed;
 
                 let side_speed = inputs.keyboard_side as f32 * 1.5;
-                let side_vec = Vec3 {
-                    x: view_rot.r0.x,
-                    y: view_rot.r1.x,
-                    z: view_rot.r2.x,
-                };
+                let side_vec = camera.orientation.rotate_vec3(Vec3 {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                });
                 camera.position = camera.position + side_vec * side_speed;
 
-                if inputs.is_left_clicked {
-                    let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
-                        * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
-
-                    let rot = rot * inverse(view_rot);
-
-                    camera.direction = Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 1.0,
-                    } * rot;
-
-                    camera.direction = camera.direction.normalize();
+                // Raw mouse deltas bypass OS pointer acceleration
+                let now = Instant::now();
+                let frame_seconds = (now - time_prev_frame).as_secs_f32();
+                time_prev_frame = now;
+                let mouse_delta = if !inputs.is_left_clicked {
+                    (0.0, 0.0)
+                } else if input_settings.raw_mouse {
+                    inputs.raw_mouse_delta
+                } else {
+                    (cursor_delta.0 as f32, cursor_delta.1 as f32)
+                };
+                let (yaw, pitch) = mouse_look.update(&input_settings, mouse_delta, frame_seconds);
+                if yaw != 0.0 || pitch != 0.0 {
+                    camera.orientation = camera.orientation.add_yaw_pitch(yaw, pitch);
                 }
 
                 // Render
                 let (present_index, _) = unsafe {
                     base.swapchain_loader.acquire_next_image(
                         base.swapchain,
-                        std::u64::MAX,
+                        u64::MAX,
                         base.present_complete_semaphore,
                         vk::Fence::null(),
                     )
@@ -423,39 +490,42 @@ fn main() {
                 .unwrap();
 
                 // Update uniform buffer
-                let color = Vec4 {
-                    x: 1.0,
-                    y: 0.1,
-                    z: 0.0,
-                    w: 0.0,
-                };
+                let color = ColorRgba::new(1.0, 0.1, 0.0, 0.0);
+
+                // Console commands: fov DEGREES, near DISTANCE, ortho [HEIGHT], perspective
+                while let Ok(line) = console_commands.try_recv() {
+                    match projection_settings.apply_command(&line) {
+                        Ok(()) => print_projection(&projection_settings),
+                        Err(err) => {
+                            println!("{}", tr_args("viewer.console_error", &[("error", &err)]))
+                        }
+                    }
+                }
 
                 let world_to_screen = view(
                     camera.position,
-                    camera.direction,
+                    camera.direction(),
                     Vec3 {
                         x: 0.0,
                         y: 1.0,
                         z: 0.0,
                     },
-                ) * projection(
-                    std::f32::consts::PI / 2.0,
-                    window_width as f32 / window_height as f32,
-                    1.0,
-                    10000000.0,
-                );
+                ) * projection_settings
+                    .matrix(window_width as f32 / window_height as f32);
 
+                let ray_origin =
+                    projection_settings.ray_origin(camera.position, camera.direction());
                 let cube_uniforms = CubeUniforms {
                     world_to_screen,
                     color,
-                    camera_position: camera.position.to_4d(),
+                    camera_position: ray_origin.to_4d(),
                     volume_scale: volume_scale.to_4d(),
                     center_to_edge: center_to_edge.to_4d(),
                     texel_scale: texel_scale.to_4d(),
                 };
 
                 let pyramid_uniforms = DepthPyramidUniforms {
-                    depth_buffer_dimensions: (window_width, window_height),
+                    depth_buffer_dimensions: [window_width, window_height],
                     depth_pyramid_dimension: pyramid_dimension,
                 };
 
@@ -484,7 +554,7 @@ fn main() {
                     },
                     vk::ClearValue {
                         depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 0.0,
+                            depth: depth.clear_depth(),
                             stencil: 0,
                         },
                     },
@@ -503,60 +573,67 @@ fn main() {
                 };
 
                 // Submit main command buffer
-                active_command_buffer = base.record_submit_commandbuffer(
-                    active_command_buffer,
-                    base.present_queue,
-                    &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
-                    &[base.present_complete_semaphore],
-                    &[base.rendering_complete_semaphore],
-                    |device, command_buffer| {
-                        // Draw/setup (before main render pass)
-                        render_cubes.gpu_draw(device, &command_buffer);
-                        culling_debug.gpu_draw(device, &command_buffer);
-
-                        // Render pass
-                        unsafe {
-                            device.cmd_begin_render_pass(
-                                command_buffer,
-                                &render_pass_begin_info,
-                                vk::SubpassContents::INLINE,
+                active_command_buffer = base
+                    .record_submit_commandbuffer(
+                        active_command_buffer,
+                        base.present_queue,
+                        &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
+                        &[base.present_complete_semaphore],
+                        &[base.rendering_complete_semaphore],
+                        |device, command_buffer| {
+                            // Draw/setup (before main render pass)
+                            render_cubes.gpu_draw(device, &command_buffer);
+                            culling_debug.gpu_draw(device, &command_buffer);
+
+                            // Render pass
+                            unsafe {
+                                device.cmd_begin_render_pass(
+                                    command_buffer,
+                                    &render_pass_begin_info,
+                                    vk::SubpassContents::INLINE,
+                                );
+                                device.cmd_set_viewport(
+                                    command_buffer,
+                                    0,
+                                    &[view_scissor.viewport],
+                                );
+                                device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
+                            }
+
+                            // Draw (main render pass)
+                            //render_cubes.gpu_draw_main_render_pass(device, &command_buffer, None);
+                            render_cubes.gpu_draw_main_render_pass(
+                                device,
+                                &command_buffer,
+                                Some(culling.visibility_arguments.draws()),
                             );
-                            device.cmd_set_viewport(command_buffer, 0, &[view_scissor.viewport]);
-                            device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
-                        }
-
-                        // Draw (main render pass)
-                        //render_cubes.gpu_draw_main_render_pass(device, &command_buffer, None);
-                        render_cubes.gpu_draw_main_render_pass(
-                            device,
-                            &command_buffer,
-                            Some(&culling.visibility_arguments.buffer),
-                        );
-                        if ENABLE_CULLING_DEBUG {
-                            culling_debug.gpu_draw_main_render_pass(device, &command_buffer);
-                        }
-
-                        unsafe {
-                            device.cmd_end_render_pass(command_buffer);
-                        }
-
-                        // Draw/setup (after main render pass)
-                        depth_pyramid.gpu_draw(
-                            device,
-                            &command_buffer,
-                            &base.depth_image.image,
-                            pyramid_dimension,
-                            pyramid_mips,
-                        );
-                        culling.gpu_draw(
-                            device,
-                            &command_buffer,
-                            &depth_pyramid.image.image,
-                            &depth_pyramid.image_debug.image,
-                            NUM_INSTANCES as u32,
-                        );
-                    },
-                );
+                            if ENABLE_CULLING_DEBUG {
+                                culling_debug.gpu_draw_main_render_pass(device, &command_buffer);
+                            }
+
+                            unsafe {
+                                device.cmd_end_render_pass(command_buffer);
+                            }
+
+                            // Draw/setup (after main render pass)
+                            depth_pyramid.gpu_draw(
+                                device,
+                                &command_buffer,
+                                &base.depth_image.image,
+                                depth_aspect_mask(base.depth_format),
+                                pyramid_dimension,
+                                pyramid_mips,
+                            );
+                            culling.gpu_draw(
+                                device,
+                                &command_buffer,
+                                &depth_pyramid.image.image,
+                                &depth_pyramid.image_debug.image,
+                                NUM_INSTANCES as u32,
+                            );
+                        },
+                    )
+                    .expect("Frame submit failed");
 
                 // Present frame
                 let present_info = vk::PresentInfoKHR {
@@ -576,10 +653,14 @@ fn main() {
 
                 // Output performance info every 60 frames
                 frame += 1;
-                if (frame % 60) == 0 {
+                if frame.is_multiple_of(60) {
                     let time_now = Instant::now();
                     let interval = (time_now - time_start).as_millis();
-                    println!("Average frame time: {} ms", interval as f32 / 60.0f32);
+                    let frame_time = interval as f32 / 60.0f32;
+                    println!(
+                        "{}",
+                        tr_args("viewer.average_frame_time", &[("ms", &frame_time)])
+                    );
 
                     time_start = time_now;
                 }
@@ -608,40 +689,53 @@ fn main() {
                         Key::Character(c) if c.as_str() == "a" || c.as_str() == "A" => {
                             inputs.keyboard_side = if pressed { -1 } else { 0 };
                         }
+                        // +/- = zoom, P = projection type, [ and ] = near plane
+                        Key::Character(c) if pressed && PROJECTION_KEYS.contains(&c.as_str()) => {
+                            projection_settings.apply_key(c.as_str());
+                            print_projection(&projection_settings);
+                        }
                         _ => {}
                     }
                 }
 
                 // Mouse
-                WindowEvent::MouseInput { button, state, .. } => {
-                    if button == MouseButton::Left {
-                        inputs.is_left_clicked = state == ElementState::Pressed;
-                    }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Left,
+                    state,
+                    ..
+                } => {
+                    inputs.is_left_clicked = state == ElementState::Pressed;
                 }
                 WindowEvent::CursorMoved { position, .. } => {
                     let x = position.x.round() as i32;
                     let y = position.y.round() as i32;
                     inputs.cursor_position = (x, y);
                 }
-                WindowEvent::MouseWheel { delta, .. } => {
-                    match delta {
-                        MouseScrollDelta::LineDelta(_, v_lines) => {
-                            inputs.wheel_delta += v_lines;
-                        }
-                        MouseScrollDelta::PixelDelta(pos) => {
-                            inputs.wheel_delta += pos.y as f32 / 10.0;
-                        }
+                WindowEvent::MouseWheel { delta, .. } => match delta {
+                    MouseScrollDelta::LineDelta(_, v_lines) => {
+                        inputs.wheel_delta += v_lines;
                     }
-                }
+                    MouseScrollDelta::PixelDelta(pos) => {
+                        inputs.wheel_delta += pos.y as f32 / 10.0;
+                    }
+                },
                 _ => (),
             },
 
             Event::LoopExiting => unsafe { base.device.device_wait_idle() }.unwrap(),
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                inputs.raw_mouse_delta.0 += delta.0 as f32;
+                inputs.raw_mouse_delta.1 += delta.1 as f32;
+            }
+
             _ => (),
         }
     });
 
-    println!("End window event loop");
+    println!("{}", tr("viewer.end_event_loop"));
 
     unsafe { base.device.device_wait_idle() }.unwrap();
 
@@ -652,8 +746,8 @@ fn main() {
     render_cubes.destroy(&base.device, &mut base.allocator);
     sdf_texture.destroy(&base.device, &mut base.allocator);
     depth_pyramid.destroy(&base.device, &mut base.allocator);
+    descriptor_allocator.destroy(&base.device);
     unsafe {
-        base.device.destroy_descriptor_pool(descriptor_pool, None);
         for framebuffer in framebuffers {
             base.device.destroy_framebuffer(framebuffer, None);
         }
@@ -9,22 +9,29 @@ use std::mem;
 use ash::util::*;
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::color::*;
 use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct CubeUniforms {
     pub world_to_screen: Mat4x4,
-    pub color: Vec4,
+    pub color: ColorRgba,
     pub camera_position: Vec4,
     pub volume_scale: Vec4,
     pub center_to_edge: Vec4,
     pub texel_scale: Vec4,
 }
 
+unsafe impl Zeroable for CubeUniforms {}
+unsafe impl Pod for CubeUniforms {}
+
 pub struct RenderCubes {
     pub pipeline_layout: vk::PipelineLayout,
     pub index_buffer: VkBuffer,
@@ -42,14 +49,15 @@ impl RenderCubes {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
         render_pass: &vk::RenderPass,
         view_scissor: &VkViewScissor,
         sdf_texture_descriptor: &vk::DescriptorImageInfo,
         instances_buffer_descriptor: &vk::DescriptorBufferInfo,
         visibility_buffer_descriptor: &vk::DescriptorBufferInfo,
         num_instances: usize,
+        depth: DepthConvention,
     ) -> RenderCubes {
         const NUM_CUBE_INDICES: usize = if CUBE_BACKFACE_OPTIMIZATION {
             3 * 3 * 2
@@ -91,7 +99,9 @@ impl RenderCubes {
             &index_buffer_info,
             MemoryLocation::CpuToGpu,
         );
-        index_buffer.copy_from_slice(&index_buffer_data[..], 0);
+        index_buffer
+            .copy_from_slice(&index_buffer_data[..], 0)
+            .unwrap();
 
         let index_buffer_gpu_info = vk::BufferCreateInfo {
             size: std::mem::size_of_val(&index_buffer_data[..]) as u64,
@@ -176,16 +186,9 @@ impl RenderCubes {
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let descriptor_sets = {
-            let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-                descriptor_pool: *descriptor_pool,
-                descriptor_set_count: 1,
-                p_set_layouts: desc_set_layouts.as_ptr(),
-                ..Default::default()
-            };
-
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
         let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: uniform_buffer_gpu.buffer,
@@ -330,7 +333,7 @@ impl RenderCubes {
         let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
             depth_test_enable: 1,
             depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            depth_compare_op: depth.compare_op(),
             front: noop_stencil_state,
             back: noop_stencil_state,
             max_depth_bounds: 1.0,
@@ -404,7 +407,7 @@ impl RenderCubes {
     }
 
     pub fn update(&self, uniforms: &CubeUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+        self.uniform_buffer.copy_struct(uniforms, 0).unwrap();
     }
 
     pub fn gpu_setup(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
@@ -519,7 +522,7 @@ impl RenderCubes {
         &self,
         device: &Device,
         command_buffer: &vk::CommandBuffer,
-        argument_buffer: Option<&vk::Buffer>,
+        indirect_draws: Option<IndirectDraws>,
     ) {
         unsafe {
             device.cmd_bind_descriptor_sets(
@@ -544,10 +547,8 @@ impl RenderCubes {
                 vk::IndexType::UINT32,
             );
 
-            match argument_buffer {
-                Some(buffer) => {
-                    device.cmd_draw_indexed_indirect(*command_buffer, *buffer, 0, 1, 0);
-                }
+            match indirect_draws {
+                Some(indirect_draws) => indirect_draws.record(device, *command_buffer),
                 None => {
                     device.cmd_draw_indexed(
                         *command_buffer,
@@ -562,7 +563,7 @@ impl RenderCubes {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             device.destroy_pipeline(self.graphic_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
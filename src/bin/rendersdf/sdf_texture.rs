@@ -1,9 +1,9 @@
 use ash::{vk, Device};
 use std::default::Default;
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
 use crate::vulkan_helpers::*;
 use crate::SdfLevel;
 
@@ -18,9 +18,10 @@ pub struct SdfTexture {
 impl SdfTexture {
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
+        allocator: &mut TrackingAllocator,
         sdf_levels: &[SdfLevel],
         sdf_total_voxels: usize,
+        sampler_cache: &mut SamplerCache,
     ) -> SdfTexture {
         let image_buffer_info = vk::BufferCreateInfo {
             size: (std::mem::size_of::<u16>() * sdf_total_voxels) as u64,
@@ -37,10 +38,12 @@ impl SdfTexture {
         );
 
         for level in sdf_levels {
-            upload_buffer.copy_from_slice(
-                &level.sdf.voxels[..],
-                level.offset as usize * std::mem::size_of::<u16>(),
-            );
+            upload_buffer
+                .copy_from_slice(
+                    &level.sdf.voxels[..],
+                    level.offset as usize * std::mem::size_of::<u16>(),
+                )
+                .unwrap();
         }
 
         let image_dimensions = sdf_levels[0].sdf.header.dim;
@@ -69,23 +72,13 @@ impl SdfTexture {
             MemoryLocation::GpuOnly,
         );
 
-        let sampler_info = vk::SamplerCreateInfo {
-            mag_filter: vk::Filter::LINEAR,
-            min_filter: vk::Filter::LINEAR,
-            //mipmap_mode: vk::SamplerMipmapMode::LINEAR,    // LINEAR mipmap sampling is 1/2 rate
-            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
-            address_mode_u: vk::SamplerAddressMode::REPEAT,
-            address_mode_v: vk::SamplerAddressMode::REPEAT,
-            address_mode_w: vk::SamplerAddressMode::REPEAT,
-            max_anisotropy: 1.0,
-            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
-            compare_op: vk::CompareOp::NEVER,
-            min_lod: 0.0,
-            max_lod: sdf_levels.len() as f32,
-            ..Default::default()
-        };
-
-        let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+        let sampler = sampler_cache.get(
+            device,
+            SamplerDesc {
+                max_lod: sdf_levels.len() as u32,
+                ..Default::default()
+            },
+        );
 
         let view_info = vk::ImageViewCreateInfo {
             view_type: vk::ImageViewType::TYPE_3D,
@@ -105,7 +98,7 @@ impl SdfTexture {
             image: image.image,
             ..Default::default()
         };
-        let view = unsafe { device.create_image_view(&view_info, None) }.unwrap();
+        let view = create_image_view(device, &view_info).unwrap();
 
         let descriptor = vk::DescriptorImageInfo {
             image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
@@ -143,23 +136,20 @@ impl SdfTexture {
         };
 
         let image_copys: Vec<vk::BufferImageCopy> = (0..sdf_levels.len())
-            .map(|i| {
-                let buffer_image_copy_regions = vk::BufferImageCopy {
-                    buffer_offset: std::mem::size_of::<u16>() as u64 * sdf_levels[i].offset as u64,
-                    image_subresource: vk::ImageSubresourceLayers {
-                        aspect_mask: vk::ImageAspectFlags::COLOR,
-                        mip_level: i as u32,
-                        base_array_layer: 0,
-                        layer_count: 1,
-                    },
-                    image_extent: vk::Extent3D {
-                        width: sdf_levels[i].sdf.header.dim.0,
-                        height: sdf_levels[i].sdf.header.dim.1,
-                        depth: sdf_levels[i].sdf.header.dim.2,
-                    },
-                    ..Default::default()
-                };
-                buffer_image_copy_regions
+            .map(|i| vk::BufferImageCopy {
+                buffer_offset: std::mem::size_of::<u16>() as u64 * sdf_levels[i].offset as u64,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: i as u32,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_extent: vk::Extent3D {
+                    width: sdf_levels[i].sdf.header.dim.0,
+                    height: sdf_levels[i].sdf.header.dim.1,
+                    depth: sdf_levels[i].sdf.header.dim.2,
+                },
+                ..Default::default()
             })
             .collect();
 
@@ -209,12 +199,9 @@ impl SdfTexture {
         };
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
-        unsafe {
-            device.destroy_image_view(self.view, None);
-            self.image.destroy(device, allocator);
-            self.upload_buffer.destroy(device, allocator);
-            device.destroy_sampler(self.sampler, None);
-        }
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        destroy_image_view(device, self.view);
+        self.image.destroy(device, allocator);
+        self.upload_buffer.destroy(device, allocator);
     }
 }
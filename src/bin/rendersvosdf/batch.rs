@@ -0,0 +1,814 @@
+// rendersvosdf batch JOBS.json renders a list of viewpoints to PNG files without showing the
+// window. All jobs share one VulkanBase. A loader thread reads the volume and scene of the
+// next job while the GPU renders the current one. Jobs file:
+//
+//   [
+//     {
+//       "volume": "data/ganymede-and-jupiter.svosdf",
+//       "scene": "scene.toml",
+//       "camera": { "position": [0, 25, 50], "direction": [0, -0.5, -1] },
+//       "resolution": [1920, 1080],
+//       "output": "renders/0000.png"
+//     }
+//   ]
+//
+// Only output is required. Consecutive jobs with the same volume and scene reuse its GPU
+// resources.
+
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+use serde_json::Value;
+use winit::{event_loop::EventLoop, window::WindowBuilder};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Instant;
+
+use crate::assets::*;
+use crate::culling::*;
+use crate::depth_pyramid::*;
+use crate::detail_noise::*;
+use crate::detail_texture::*;
+use crate::display_settings::*;
+use crate::instances::*;
+use crate::localization::*;
+use crate::memory_profile::*;
+use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::quality_settings::*;
+use crate::render_svo_cubes::*;
+use crate::scene_file::*;
+use crate::svo_texture::*;
+use crate::svosdf::*;
+use crate::visibility_mask::*;
+use crate::vulkan_base::*;
+use crate::vulkan_helpers::*;
+use crate::{DESCRIPTOR_SETS_PER_POOL, SVO_SDF_FILE};
+
+const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+const DEFAULT_RESOLUTION: (u32, u32) = (1920, 1080);
+
+#[derive(Debug)]
+pub enum BatchError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    InvalidValue(usize, String), // Job index, key
+    Scene(SceneError),
+    Image(image::ImageError),
+    Vulkan(VulkanError),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatchError::Io(err) => write!(f, "{}", err),
+            BatchError::Json(err) => write!(f, "{}", err),
+            BatchError::InvalidValue(job, key) => {
+                write!(f, "Invalid value for {} in job {}", key, job)
+            }
+            BatchError::Scene(err) => write!(f, "{}", err),
+            BatchError::Image(err) => write!(f, "{}", err),
+            BatchError::Vulkan(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<VulkanError> for BatchError {
+    fn from(err: VulkanError) -> Self {
+        BatchError::Vulkan(err)
+    }
+}
+
+impl From<vk::Result> for BatchError {
+    fn from(result: vk::Result) -> Self {
+        BatchError::Vulkan(result.into())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BatchJob {
+    pub volume: Option<PathBuf>, // None = the viewer's default volume
+    pub scene: Option<PathBuf>,  // None = one instance at the origin
+    pub camera_position: Vec3,
+    pub camera_direction: Vec3,
+    pub resolution: (u32, u32),
+    pub output: PathBuf,
+}
+
+impl BatchJob {
+    // Jobs with the same key render the same GPU resources
+    fn scene_key(&self) -> (Option<PathBuf>, Option<PathBuf>) {
+        (self.volume.clone(), self.scene.clone())
+    }
+}
+
+// [x, y, z], integers are accepted
+fn vec3_value(value: &Value) -> Option<Vec3> {
+    let array = value.as_array().filter(|array| array.len() == 3)?;
+    let xyz: Vec<f32> = array
+        .iter()
+        .map(|value| value.as_f64().map(|value| value as f32))
+        .collect::<Option<_>>()?;
+    Some(Vec3 {
+        x: xyz[0],
+        y: xyz[1],
+        z: xyz[2],
+    })
+}
+
+fn resolution_value(value: &Value) -> Option<(u32, u32)> {
+    let array = value.as_array().filter(|array| array.len() == 2)?;
+    let width = array[0].as_u64().filter(|&width| width > 0)?;
+    let height = array[1].as_u64().filter(|&height| height > 0)?;
+    Some((width as u32, height as u32))
+}
+
+fn parse_job(index: usize, value: &Value) -> Result<BatchJob, BatchError> {
+    let invalid = |key: &str| BatchError::InvalidValue(index, key.to_string());
+    let object = value.as_object().ok_or_else(|| invalid("job"))?;
+    let path = |key: &str| match object.get(key) {
+        Some(value) => value
+            .as_str()
+            .map(|path| Some(PathBuf::from(path)))
+            .ok_or_else(|| invalid(key)),
+        None => Ok(None),
+    };
+
+    let mut job = BatchJob {
+        volume: path("volume")?,
+        scene: path("scene")?,
+        camera_position: Vec3 {
+            x: 0.0,
+            y: 25.0,
+            z: 50.0,
+        },
+        camera_direction: Vec3 {
+            x: 0.0,
+            y: -0.5,
+            z: -1.0,
+        },
+        resolution: DEFAULT_RESOLUTION,
+        output: path("output")?.ok_or_else(|| invalid("output"))?,
+    };
+    if let Some(camera) = object.get("camera") {
+        if let Some(position) = camera.get("position") {
+            job.camera_position = vec3_value(position).ok_or_else(|| invalid("position"))?;
+        }
+        if let Some(direction) = camera.get("direction") {
+            job.camera_direction = vec3_value(direction).ok_or_else(|| invalid("direction"))?;
+        }
+    }
+    if let Some(resolution) = object.get("resolution") {
+        job.resolution = resolution_value(resolution).ok_or_else(|| invalid("resolution"))?;
+    }
+    Ok(job)
+}
+
+pub fn parse_jobs(text: &str) -> Result<Vec<BatchJob>, BatchError> {
+    let value: Value = serde_json::from_str(text).map_err(BatchError::Json)?;
+    let jobs = value
+        .as_array()
+        .ok_or_else(|| BatchError::InvalidValue(0, "jobs".to_string()))?;
+    jobs.iter()
+        .enumerate()
+        .map(|(index, job)| parse_job(index, job))
+        .collect()
+}
+
+// CPU side data of a job, produced by the loader thread
+struct LoadedScene {
+    svo_sdf: SvoSdf,
+    scene_instances: Vec<SceneInstance>,
+}
+
+fn load_scene(job: &BatchJob) -> Result<LoadedScene, BatchError> {
+    let svo_sdf = match &job.volume {
+        Some(path) => SvoSdf::load(&path.to_string_lossy()),
+        None => match resolve(SVO_SDF_FILE) {
+            Some(path) => SvoSdf::load(&path.to_string_lossy()),
+            None => read(FALLBACK_SVO_SDF).and_then(|bytes| SvoSdf::from_bytes(&bytes)),
+        },
+    }
+    .map_err(BatchError::Io)?;
+
+    let scene_instances = match &job.scene {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(BatchError::Io)?;
+            parse_scene(&text).map_err(BatchError::Scene)?
+        }
+        None => Vec::new(),
+    };
+    let scene_instances = if scene_instances.is_empty() {
+        vec![SceneInstance::default()]
+    } else {
+        scene_instances
+    };
+
+    Ok(LoadedScene {
+        svo_sdf,
+        scene_instances,
+    })
+}
+
+// Loads one scene ahead, in job order, skipping jobs that reuse the previous scene
+fn spawn_loader(jobs: Vec<BatchJob>) -> Receiver<Result<LoadedScene, BatchError>> {
+    let (sender, receiver) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        let mut previous_key = None;
+        for job in jobs.iter() {
+            let key = Some(job.scene_key());
+            if key == previous_key {
+                continue;
+            }
+            previous_key = key;
+            if sender.send(load_scene(job)).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+fn create_render_pass(base: &VulkanBase) -> Result<vk::RenderPass, BatchError> {
+    let render_pass_attachments = [
+        vk::AttachmentDescription {
+            format: COLOR_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ..Default::default()
+        },
+        vk::AttachmentDescription {
+            format: base.depth_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        },
+    ];
+    let color_attachment_refs = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    // The color attachment is copied to the readback buffer after the pass
+    let dependencies = [vk::SubpassDependency {
+        src_subpass: 0,
+        dst_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::TRANSFER,
+        dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+        ..Default::default()
+    }];
+
+    let subpasses = [vk::SubpassDescription {
+        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+        color_attachment_count: color_attachment_refs.len() as u32,
+        p_color_attachments: color_attachment_refs.as_ptr(),
+        p_depth_stencil_attachment: &depth_attachment_ref,
+        ..Default::default()
+    }];
+
+    let render_pass_create_info = vk::RenderPassCreateInfo {
+        attachment_count: render_pass_attachments.len() as u32,
+        p_attachments: render_pass_attachments.as_ptr(),
+        subpass_count: subpasses.len() as u32,
+        p_subpasses: subpasses.as_ptr(),
+        dependency_count: dependencies.len() as u32,
+        p_dependencies: dependencies.as_ptr(),
+        ..Default::default()
+    };
+    Ok(unsafe {
+        base.device
+            .create_render_pass(&render_pass_create_info, None)
+    }?)
+}
+
+// Offscreen color and depth targets of one resolution and the buffer the image is read into
+struct RenderTarget {
+    extent: vk::Extent2D,
+    color_image: VkImage,
+    color_view: vk::ImageView,
+    depth_image: VkImage,
+    depth_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+    readback_buffer: VkBuffer,
+}
+
+impl RenderTarget {
+    fn new(
+        base: &mut VulkanBase,
+        render_pass: vk::RenderPass,
+        resolution: (u32, u32),
+    ) -> Result<RenderTarget, BatchError> {
+        let extent = vk::Extent2D {
+            width: resolution.0,
+            height: resolution.1,
+        };
+        let image_info = |format, usage| vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: extent.into(),
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let view_info = |image, format, aspect_mask| vk::ImageViewCreateInfo {
+            view_type: vk::ImageViewType::TYPE_2D,
+            format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask,
+                level_count: 1,
+                layer_count: 1,
+                ..Default::default()
+            },
+            image,
+            ..Default::default()
+        };
+
+        let color_image = VkImage::new(
+            &base.device,
+            &mut base.allocator,
+            &image_info(
+                COLOR_FORMAT,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            ),
+            MemoryLocation::GpuOnly,
+        );
+        let depth_image = VkImage::new(
+            &base.device,
+            &mut base.allocator,
+            &image_info(
+                base.depth_format,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            ),
+            MemoryLocation::GpuOnly,
+        );
+
+        let color_view_info =
+            view_info(color_image.image, COLOR_FORMAT, vk::ImageAspectFlags::COLOR);
+        let depth_view_info = view_info(
+            depth_image.image,
+            base.depth_format,
+            depth_aspect_mask(base.depth_format),
+        );
+        let color_view = create_image_view(&base.device, &color_view_info)?;
+        let depth_view = create_image_view(&base.device, &depth_view_info)?;
+
+        let attachments = [color_view, depth_view];
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass,
+            attachment_count: attachments.len() as u32,
+            p_attachments: attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = unsafe { base.device.create_framebuffer(&framebuffer_info, None) }?;
+
+        let readback_buffer_info = vk::BufferCreateInfo {
+            size: (extent.width * extent.height * 4) as u64,
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let readback_buffer = VkBuffer::new(
+            &base.device,
+            &mut base.allocator,
+            &readback_buffer_info,
+            MemoryLocation::GpuToCpu,
+        );
+
+        Ok(RenderTarget {
+            extent,
+            color_image,
+            color_view,
+            depth_image,
+            depth_view,
+            framebuffer,
+            readback_buffer,
+        })
+    }
+
+    fn view_scissor(&self) -> VkViewScissor {
+        VkViewScissor::from_rect(vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        })
+    }
+
+    fn destroy(&mut self, base: &mut VulkanBase) {
+        unsafe { base.device.destroy_framebuffer(self.framebuffer, None) };
+        destroy_image_view(&base.device, self.color_view);
+        destroy_image_view(&base.device, self.depth_view);
+        self.color_image.destroy(&base.device, &mut base.allocator);
+        self.depth_image.destroy(&base.device, &mut base.allocator);
+        self.readback_buffer
+            .destroy(&base.device, &mut base.allocator);
+    }
+}
+
+// GPU resources of one volume and scene
+struct SceneResources {
+    loaded: LoadedScene,
+    svo_texture: SvoTexture,
+    instances: Instances,
+    culling: Culling,
+    render_svo_cubes: RenderSvoCubes,
+    uploaded: bool, // gpu_setup recorded
+}
+
+impl SceneResources {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        base: &mut VulkanBase,
+        descriptor_allocator: &mut DescriptorAllocator,
+        render_pass: vk::RenderPass,
+        view_scissor: &VkViewScissor,
+        depth_pyramid: &DepthPyramid,
+        detail_texture: &DetailTexture,
+        scratch: &ScratchAllocator,
+        atlas_order: AtlasOrder,
+        loaded: LoadedScene,
+        depth: DepthConvention,
+    ) -> SceneResources {
+        let svo_texture = SvoTexture::new(
+            &base.device,
+            &mut base.allocator,
+            &loaded.svo_sdf,
+            &base.memory_profile,
+            &base.gpu_budget,
+            &mut base.sampler_cache,
+            atlas_order,
+            (&base.instance, base.pdevice, &base.capabilities),
+            false,
+        );
+        let instances = Instances::new(
+            &base.device,
+            &mut base.allocator,
+            &loaded.svo_sdf,
+            &loaded.scene_instances,
+            &svo_texture.atlas_slots,
+        );
+        let num_instances = instances.num_instances;
+
+        // A single frame has no previous depth to cull against
+        let culling = Culling::new(
+            &base.device,
+            &mut base.allocator,
+            &base.capabilities,
+            descriptor_allocator,
+            &depth_pyramid.descriptor_sample,
+            &depth_pyramid.descriptor_debug_sample,
+            &instances.bricks_buffer_descriptor,
+            &instances.scene_instances_buffer_descriptor,
+            num_instances,
+            loaded.scene_instances.len(),
+            depth,
+        );
+
+        let mut render_svo_cubes = RenderSvoCubes::new(
+            &base.device,
+            &mut base.allocator,
+            descriptor_allocator,
+            &render_pass,
+            view_scissor,
+            &svo_texture.brick_texture_descriptor,
+            &svo_texture.octree_buffer_descriptor,
+            &instances.bricks_buffer_descriptor,
+            &instances.scene_instances_buffer_descriptor,
+            &culling.visibility_buffer_descriptor,
+            &detail_texture.noise_descriptor,
+            &detail_texture.materials_descriptor,
+            scratch,
+            &SvoCubeUniforms::new(
+                &loaded.svo_sdf,
+                svo_texture.brick_curve,
+                instances.bricks_per_scene_instance,
+                true,
+            ),
+            depth,
+        );
+        let mask = VisibilityMask::from_instances(&loaded.scene_instances);
+        render_svo_cubes.visible_instances =
+            Some(culling.set_visible_scene_instances(&mask, instances.bricks_per_scene_instance));
+
+        SceneResources {
+            loaded,
+            svo_texture,
+            instances,
+            culling,
+            render_svo_cubes,
+            uploaded: false,
+        }
+    }
+
+    fn destroy(&mut self, base: &mut VulkanBase) {
+        self.culling.destroy(&base.device, &mut base.allocator);
+        self.instances.destroy(&base.device, &mut base.allocator);
+        self.render_svo_cubes
+            .destroy(&base.device, &mut base.allocator);
+        self.svo_texture.destroy(&base.device, &mut base.allocator);
+    }
+}
+
+fn save_png(path: &Path, target: &RenderTarget) -> Result<(), BatchError> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).map_err(BatchError::Io)?;
+    }
+    image::save_buffer(
+        path,
+        target.readback_buffer.mapped_bytes(),
+        target.extent.width,
+        target.extent.height,
+        image::ColorType::Rgba8,
+    )
+    .map_err(BatchError::Image)
+}
+
+// Returns the number of rendered images
+pub fn run_batch(args: &[String], jobs_path: &Path) -> Result<usize, BatchError> {
+    let text = fs::read_to_string(jobs_path).map_err(BatchError::Io)?;
+    let jobs = parse_jobs(&text)?;
+    if jobs.is_empty() {
+        return Ok(0);
+    }
+    let loader = spawn_loader(jobs.clone());
+
+    // The surface and swapchain of VulkanBase need a window, it is never shown
+    let event_loop = EventLoop::new().unwrap();
+    let window = WindowBuilder::new()
+        .with_title("SVO SDF batch render")
+        .with_visible(false)
+        .build(&event_loop)
+        .unwrap();
+    let size = window.inner_size();
+    let mut base = VulkanBase::new_with_settings(
+        &window,
+        size.width.max(1),
+        size.height.max(1),
+        MemoryProfile::from_args(args),
+        DebugSettings::from_args(args),
+        DisplaySettings::from_args(args),
+    )?;
+
+    let render_pass = create_render_pass(&base)?;
+    let mut target = RenderTarget::new(&mut base, render_pass, jobs[0].resolution)?;
+
+    // Sets of shared resources live for the whole batch, scene sets are reset per scene
+    let mut descriptor_allocator = DescriptorAllocator::new(DESCRIPTOR_SETS_PER_POOL);
+    let mut scene_descriptor_allocator = DescriptorAllocator::new(DESCRIPTOR_SETS_PER_POOL);
+    let quality_settings = QualitySettings::from_args(args);
+    base.sampler_cache
+        .set_max_anisotropy(quality_settings.anisotropy);
+    let atlas_order = AtlasOrder::from_args(args);
+    let depth = ProjectionSettings::from_args(args).depth;
+
+    let mut detail_texture = DetailTexture::new(
+        &base.device,
+        &mut base.allocator,
+        &mut base.sampler_cache,
+        &DetailSettings::from_args(args),
+    );
+    let mut detail_uploaded = false;
+
+    // Culling descriptors need a depth pyramid, it is never built in batch mode
+    let pyramid_dimension = 512;
+    let mut depth_pyramid = DepthPyramid::new(
+        &base.device,
+        &mut base.allocator,
+        &mut descriptor_allocator,
+        &target.depth_view,
+        (pyramid_dimension * 3 / 2, pyramid_dimension),
+        &mut base.sampler_cache,
+        depth,
+    );
+
+    // Svo cube uniforms, bound with dynamic offsets
+    let mut scratch = ScratchAllocator::new(
+        &base.device,
+        &mut base.allocator,
+        &base.gpu_budget,
+        4 * 1024,
+        base.command_buffer_pool.command_buffers.len(),
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        MemoryLocation::CpuToGpu,
+    )
+    .unwrap_or_else(|err| panic!("Scratch buffer allocation failed: {}", err));
+
+    let mut scene: Option<SceneResources> = None;
+    let mut scene_key = None;
+    let mut active_command_buffer = 0;
+
+    let result = (|| -> Result<usize, BatchError> {
+        for (index, job) in jobs.iter().enumerate() {
+            let time_job = Instant::now();
+
+            if target.extent.width != job.resolution.0 || target.extent.height != job.resolution.1 {
+                target.destroy(&mut base);
+                target = RenderTarget::new(&mut base, render_pass, job.resolution)?;
+                depth_pyramid.set_depth_view(&base.device, &target.depth_view);
+            }
+
+            let key = Some(job.scene_key());
+            if key != scene_key {
+                // The previous job was waited for, its resources are idle
+                if let Some(mut previous) = scene.take() {
+                    previous.destroy(&mut base);
+                }
+                scene_descriptor_allocator.reset(&base.device)?;
+                let loaded = loader.recv().expect("Batch loader thread stopped early")?;
+                scene = Some(SceneResources::new(
+                    &mut base,
+                    &mut scene_descriptor_allocator,
+                    render_pass,
+                    &target.view_scissor(),
+                    &depth_pyramid,
+                    &detail_texture,
+                    &scratch,
+                    atlas_order,
+                    loaded,
+                    depth,
+                ));
+                scene_key = key;
+            }
+            let resources = scene.as_mut().unwrap();
+
+            let world_to_screen = view(
+                job.camera_position,
+                job.camera_direction,
+                Vec3 {
+                    x: 0.0,
+                    y: 1.0,
+                    z: 0.0,
+                },
+            ) * depth.projection(
+                std::f32::consts::PI / 2.0,
+                job.resolution.0 as f32 / job.resolution.1 as f32,
+                1.0,
+                10000000.0,
+            );
+            resources.render_svo_cubes.update(&SvoCubePushConstants {
+                world_to_screen,
+                camera_position: job.camera_position.to_4d(),
+            });
+
+            let clear_values = [
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, 0.0],
+                    },
+                },
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: depth.clear_depth(),
+                        stencil: 0,
+                    },
+                },
+            ];
+            let render_pass_begin_info = vk::RenderPassBeginInfo {
+                render_pass,
+                framebuffer: target.framebuffer,
+                render_area: vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: target.extent,
+                },
+                clear_value_count: clear_values.len() as u32,
+                p_clear_values: clear_values.as_ptr(),
+                ..Default::default()
+            };
+            let image_copy = vk::BufferImageCopy {
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    layer_count: 1,
+                    ..Default::default()
+                },
+                image_extent: target.extent.into(),
+                ..Default::default()
+            };
+            let host_read_barrier = vk::MemoryBarrier2 {
+                src_stage_mask: vk::PipelineStageFlags2::COPY,
+                src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags2::HOST,
+                dst_access_mask: vk::AccessFlags2::HOST_READ,
+                ..Default::default()
+            };
+            let view_scissor = target.view_scissor();
+            let upload_detail = !detail_uploaded;
+            let upload_scene = !resources.uploaded;
+            let frame_command_buffer = active_command_buffer;
+
+            active_command_buffer = base.record_submit_commandbuffer(
+                active_command_buffer,
+                base.present_queue,
+                &[],
+                &[],
+                &[],
+                |device, command_buffer| {
+                    scratch.begin_frame(frame_command_buffer);
+                    if upload_detail {
+                        detail_texture.gpu_setup(device, &base.barrier_api, &command_buffer);
+                    }
+                    if upload_scene {
+                        resources.render_svo_cubes.gpu_setup(
+                            device,
+                            &base.barrier_api,
+                            &command_buffer,
+                        );
+                        resources.svo_texture.gpu_setup(
+                            device,
+                            &base.barrier_api,
+                            &command_buffer,
+                            &resources.loaded.svo_sdf,
+                        );
+                    }
+
+                    unsafe {
+                        device.cmd_begin_render_pass(
+                            command_buffer,
+                            &render_pass_begin_info,
+                            vk::SubpassContents::INLINE,
+                        );
+                    }
+                    view_scissor.set(device, command_buffer);
+                    resources.render_svo_cubes.gpu_draw_main_render_pass(
+                        device,
+                        &command_buffer,
+                        &mut scratch,
+                        None,
+                    );
+                    unsafe {
+                        device.cmd_end_render_pass(command_buffer);
+                        device.cmd_copy_image_to_buffer(
+                            command_buffer,
+                            target.color_image.image,
+                            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                            target.readback_buffer.buffer,
+                            &[image_copy],
+                        );
+                    }
+                    barrier2(
+                        device,
+                        &base.barrier_api,
+                        command_buffer,
+                        &[host_read_barrier],
+                        &[],
+                        &[],
+                    );
+                },
+            )?;
+            detail_uploaded = true;
+            resources.uploaded = true;
+
+            // The loader thread keeps reading the next scene meanwhile
+            unsafe { base.device.device_wait_idle() }?;
+            save_png(&job.output, &target)?;
+
+            println!(
+                "{}",
+                tr_args(
+                    "batch.job_done",
+                    &[
+                        ("job", &(index + 1)),
+                        ("count", &jobs.len()),
+                        ("file", &job.output.display()),
+                        ("ms", &time_job.elapsed().as_millis())
+                    ]
+                )
+            );
+        }
+        Ok(jobs.len())
+    })();
+
+    // A lost device still has to be cleaned up, so the error is ignored
+    let _ = unsafe { base.device.device_wait_idle() };
+    if let Some(mut resources) = scene.take() {
+        resources.destroy(&mut base);
+    }
+    depth_pyramid.destroy(&base.device, &mut base.allocator);
+    scratch.destroy(&base.device, &mut base.allocator);
+    detail_texture.destroy(&base.device, &mut base.allocator);
+    target.destroy(&mut base);
+    scene_descriptor_allocator.destroy(&base.device);
+    descriptor_allocator.destroy(&base.device);
+    unsafe { base.device.destroy_render_pass(render_pass, None) };
+
+    result
+}
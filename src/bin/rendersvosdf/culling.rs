@@ -6,39 +6,51 @@ use std::mem;
 use ash::util::*;
 use ash::{vk, Device};
 
+use crate::allocator_telemetry::*;
 use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::render_svo_cubes::NUM_CUBE_INDICES;
+use crate::visibility_mask::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
+use rust_test::device_capabilities::DeviceCapabilities;
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+// Longest visibility list, 8 MiB. Culling skips visible bricks past it for the frame, the
+// list doesn't grow with the bricks of all scene instances.
+pub const MAX_VISIBLE_BRICKS: usize = 2 * 1024 * 1024;
+
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct VisibilityData {
     pub index: u32,
 }
 
-#[derive(Clone, Copy)]
-pub struct DrawIndexedIndirectArguments {
-    pub index_count: u32,
-    pub instance_count: u32,
-    pub first_index: u32,
-    pub vertex_offset: i32,
-    pub first_instance: u32,
-}
+unsafe impl Zeroable for VisibilityData {}
+unsafe impl Pod for VisibilityData {}
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct CullingUniforms {
     pub world_to_screen: Mat4x4,
     pub depth_pyramid_dimension: u32, // pow2 y dimension of mip 0 (texture x is 1.5x wider)
+    pub bricks_per_scene_instance: u32, // Maps instances to their bit of the visibility mask
+    pub max_visible_bricks: u32,
+    pub _padding: u32,
 }
 
+unsafe impl Zeroable for CullingUniforms {}
+unsafe impl Pod for CullingUniforms {}
+
 pub struct Culling {
     pub pipeline_layout: vk::PipelineLayout,
-    pub uniform_buffer: VkBuffer,
     pub uniform_buffer_gpu: VkBuffer,
     pub visibility_buffer: VkBuffer,
-    pub visibility_arguments: VkBuffer,
+    pub visibility_arguments: IndirectArgsBuffer,
     pub visibility_buffer_descriptor: vk::DescriptorBufferInfo,
+    pub visibility_mask_buffer: VkBuffer,
+    pub max_visible_bricks: usize, // Entries of the visibility buffer
     pub desc_set_layout: vk::DescriptorSetLayout,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
     pub compute_pipeline: vk::Pipeline,
@@ -46,17 +58,27 @@ pub struct Culling {
 }
 
 impl Culling {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        capabilities: &DeviceCapabilities,
+        descriptor_allocator: &mut DescriptorAllocator,
         depth_pyramid_descriptor: &vk::DescriptorImageInfo,
         depth_pyramid_debug_descriptor: &vk::DescriptorImageInfo,
-        instances_buffer_descriptor: &vk::DescriptorBufferInfo,
+        bricks_buffer_descriptor: &vk::DescriptorBufferInfo,
+        scene_instances_buffer_descriptor: &vk::DescriptorBufferInfo,
         num_instances: usize,
+        num_scene_instances: usize,
+        depth: DepthConvention,
     ) -> Culling {
+        // Culled bricks, or the visible scene instances when HiZ is off
+        let max_visible_bricks = num_instances
+            .min(MAX_VISIBLE_BRICKS)
+            .max(num_scene_instances)
+            .max(1);
         let visibility_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<VisibilityData>() * num_instances) as u64,
+            size: (std::mem::size_of::<VisibilityData>() * max_visible_bricks) as u64,
             usage: vk::BufferUsageFlags::STORAGE_BUFFER,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
@@ -72,44 +94,34 @@ impl Culling {
         let visibility_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: visibility_buffer.buffer,
             offset: 0,
-            range: (std::mem::size_of::<VisibilityData>() * num_instances) as u64,
+            range: (std::mem::size_of::<VisibilityData>() * max_visible_bricks) as u64,
         };
 
-        let visibility_arguments_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
-            usage: vk::BufferUsageFlags::STORAGE_BUFFER
-                | vk::BufferUsageFlags::TRANSFER_DST
-                | vk::BufferUsageFlags::INDIRECT_BUFFER,
+        let visibility_mask_size =
+            (std::mem::size_of::<u32>() * VisibilityMask::word_count(num_scene_instances)) as u64;
+        let visibility_mask_buffer_info = vk::BufferCreateInfo {
+            size: visibility_mask_size,
+            usage: vk::BufferUsageFlags::STORAGE_BUFFER,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
         };
 
-        let visibility_arguments = VkBuffer::new(
+        let visibility_mask_buffer = VkBuffer::new(
             device,
             allocator,
-            &visibility_arguments_info,
-            MemoryLocation::GpuOnly,
+            &visibility_mask_buffer_info,
+            MemoryLocation::CpuToGpu,
         );
 
-        let visibility_arguments_descriptor = vk::DescriptorBufferInfo {
-            buffer: visibility_arguments.buffer,
+        let visibility_mask_descriptor = vk::DescriptorBufferInfo {
+            buffer: visibility_mask_buffer.buffer,
             offset: 0,
-            range: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
-        };
-
-        let uniform_buffer_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<CullingUniforms>() as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
+            range: visibility_mask_size,
         };
 
-        let uniform_buffer = VkBuffer::new(
-            device,
-            allocator,
-            &uniform_buffer_info,
-            MemoryLocation::CpuToGpu,
-        );
+        let visibility_arguments =
+            IndirectArgsBuffer::new(device, allocator, capabilities, 1, MemoryLocation::GpuOnly);
+        let visibility_arguments_descriptor = visibility_arguments.descriptor();
 
         let uniform_buffer_gpu_info = vk::BufferCreateInfo {
             size: std::mem::size_of::<CullingUniforms>() as u64,
@@ -168,31 +180,36 @@ impl Culling {
                 stage_flags: vk::ShaderStageFlags::COMPUTE,
                 ..Default::default()
             },
+            vk::DescriptorSetLayoutBinding {
+                binding: 6,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 7,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
         ];
-        
+
         let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
             binding_count: desc_layout_bindings.len() as u32,
             p_bindings: desc_layout_bindings.as_ptr(),
             ..Default::default()
         };
-        
+
         let desc_set_layout =
             unsafe { device.create_descriptor_set_layout(&descriptor_info, None) }.unwrap();
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let descriptor_sets = {
-            let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-                s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
-                p_next: std::ptr::null(),
-                descriptor_pool: *descriptor_pool,
-                descriptor_set_count: desc_set_layouts.len() as u32,
-                p_set_layouts: desc_set_layouts.as_ptr(),
-                ..Default::default()
-            };
-
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
         let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: uniform_buffer_gpu.buffer,
@@ -222,7 +239,7 @@ impl Culling {
                 dst_binding: 2,
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
-                p_buffer_info: instances_buffer_descriptor,
+                p_buffer_info: bricks_buffer_descriptor,
                 ..Default::default()
             },
             vk::WriteDescriptorSet {
@@ -249,14 +266,30 @@ impl Culling {
                 p_image_info: depth_pyramid_debug_descriptor,
                 ..Default::default()
             },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 6,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: &visibility_mask_descriptor,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 7,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: scene_instances_buffer_descriptor,
+                ..Default::default()
+            },
         ];
         unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
-        
+
         let layout_create_info = vk::PipelineLayoutCreateInfo {
-                    set_layout_count: desc_set_layouts.len() as u32,
-                    p_set_layouts: desc_set_layouts.as_ptr(),
-                    ..Default::default()
-                };
+            set_layout_count: desc_set_layouts.len() as u32,
+            p_set_layouts: desc_set_layouts.as_ptr(),
+            ..Default::default()
+        };
 
         let pipeline_layout =
             unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
@@ -276,6 +309,11 @@ impl Culling {
                 .expect("Fragment shader module error")
         };
 
+        // Farthest depth and occlusion test of the depth convention
+        let specialization = SpecializationConstants::new()
+            .with(REVERSED_DEPTH_CONSTANT_ID, depth.specialization_value());
+        let specialization_info = specialization.info();
+
         let shader_entry_name = CString::new("main").unwrap();
 
         let compute_pipeline_info = {
@@ -283,6 +321,7 @@ impl Culling {
                 module: compute_shader_module,
                 p_name: shader_entry_name.as_ptr(),
                 stage: vk::ShaderStageFlags::COMPUTE,
+                p_specialization_info: &specialization_info,
                 ..Default::default()
             };
             vk::ComputePipelineCreateInfo {
@@ -305,11 +344,12 @@ impl Culling {
 
         Culling {
             pipeline_layout,
-            uniform_buffer,
             uniform_buffer_gpu,
             visibility_buffer,
             visibility_arguments,
             visibility_buffer_descriptor,
+            visibility_mask_buffer,
+            max_visible_bricks,
             desc_set_layout,
             descriptor_sets,
             compute_pipeline,
@@ -318,50 +358,78 @@ impl Culling {
     }
 
     pub fn gpu_setup(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        unsafe {
-            // Lazy way to init the argument buffer :)
-            let arguments = [0, 1, 0, 0, 0];
-            for (a, i) in arguments.iter().enumerate() {
-                device.cmd_fill_buffer(
-                    *command_buffer,
-                    self.visibility_arguments.buffer,
-                    (i * std::mem::size_of::<u32>()) as u64,
-                    std::mem::size_of::<u32>() as u64,
-                    a as u32,
-                );
-            }
-        }
+        // One cube, the culling shader appends an instance per visible brick
+        let arguments = DrawIndexedIndirectCommand {
+            index_count: NUM_CUBE_INDICES as u32,
+            ..Default::default()
+        };
+        self.visibility_arguments
+            .cmd_write(device, *command_buffer, 0, &[arguments]);
+    }
+
+    // Visibility list used when occlusion culling is disabled: the visible scene instances in
+    // order, svo_main.vert draws all bricks of each. Returns the number of cubes to draw.
+    pub fn set_visible_scene_instances(
+        &self,
+        mask: &VisibilityMask,
+        bricks_per_scene_instance: usize,
+    ) -> usize {
+        let visibility_data: Vec<VisibilityData> = (0..mask.words().len() * 32)
+            .filter(|&i| mask.is_visible(i))
+            .map(|i| VisibilityData { index: i as u32 })
+            .collect();
+        self.visibility_buffer
+            .copy_from_slice(&visibility_data[..], 0)
+            .unwrap();
+        visibility_data.len() * bricks_per_scene_instance
     }
 
-    pub fn update(&self, uniforms: &CullingUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+    // Read by the culling shader, which skips the instances of hidden scene instances
+    pub fn set_visibility_mask(&self, mask: &VisibilityMask) {
+        self.visibility_mask_buffer
+            .copy_from_slice(mask.words(), 0)
+            .unwrap();
     }
 
+    // The uniforms are staged in this frame's scratch memory, so frames in flight don't
+    // overwrite each other's copy source
+    #[allow(clippy::too_many_arguments)]
     pub fn gpu_draw(
         &self,
         device: &Device,
+        barrier_api: &BarrierApi,
         command_buffer: &vk::CommandBuffer,
+        scratch: &mut ScratchAllocator,
+        uniforms: &CullingUniforms,
         pyramid_image: &vk::Image,
         pyramid_debug_image: &vk::Image,
         num_instances: u32,
     ) {
+        let uniform_range = scratch
+            .push(std::slice::from_ref(uniforms), 16)
+            .expect("Culling uniforms don't fit the scratch buffer");
         let buffer_copy_regions = vk::BufferCopy {
-            src_offset: 0,
+            src_offset: uniform_range.offset,
             dst_offset: 0,
-            size: self.uniform_buffer.size,
+            size: uniform_range.size,
         };
 
-        let buffer_barrier = vk::BufferMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        let buffer_barrier = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::COPY,
+            dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
             buffer: self.uniform_buffer_gpu.buffer,
             offset: 0,
             size: buffer_copy_regions.size,
             ..Default::default()
         };
 
-        let buffer_barrier_end = vk::BufferMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::INDEX_READ,
+        let buffer_barrier_end = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COPY,
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            dst_access_mask: vk::AccessFlags2::UNIFORM_READ,
             buffer: self.uniform_buffer_gpu.buffer,
             offset: 0,
             size: buffer_copy_regions.size,
@@ -370,27 +438,27 @@ impl Culling {
 
         let arguments_barrier_clear = vk::BufferMemoryBarrier {
             dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            buffer: self.visibility_arguments.buffer,
+            buffer: self.visibility_arguments.buffer.buffer.buffer,
             offset: 0,
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
+            size: self.visibility_arguments.size_bytes(),
             ..Default::default()
         };
 
         let arguments_barrier_compute = vk::BufferMemoryBarrier {
             src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
             dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
-            buffer: self.visibility_arguments.buffer,
+            buffer: self.visibility_arguments.buffer.buffer.buffer,
             offset: 0,
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
+            size: self.visibility_arguments.size_bytes(),
             ..Default::default()
         };
 
         let arguments_barrier_indirect = vk::BufferMemoryBarrier {
             src_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
             dst_access_mask: vk::AccessFlags::INDIRECT_COMMAND_READ,
-            buffer: self.visibility_arguments.buffer,
+            buffer: self.visibility_arguments.buffer.buffer.buffer,
             offset: 0,
-            size: std::mem::size_of::<DrawIndexedIndirectArguments>() as u64,
+            size: self.visibility_arguments.size_bytes(),
             ..Default::default()
         };
 
@@ -438,11 +506,10 @@ impl Culling {
 
         unsafe {
             // Update uniform buffer
-            device.cmd_pipeline_barrier(
+            barrier2(
+                device,
+                barrier_api,
                 *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
                 &[],
                 &[buffer_barrier],
                 &[],
@@ -450,16 +517,15 @@ impl Culling {
 
             device.cmd_copy_buffer(
                 *command_buffer,
-                self.uniform_buffer.buffer,
+                uniform_range.buffer,
                 self.uniform_buffer_gpu.buffer,
                 &[buffer_copy_regions],
             );
 
-            device.cmd_pipeline_barrier(
+            barrier2(
+                device,
+                barrier_api,
                 *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                vk::DependencyFlags::empty(),
                 &[],
                 &[buffer_barrier_end],
                 &[],
@@ -484,14 +550,9 @@ impl Culling {
                 &[],
             );
 
-            // Clear the visible index count (remaining of the buffer stays)
-            device.cmd_fill_buffer(
-                *command_buffer,
-                self.visibility_arguments.buffer,
-                0,
-                std::mem::size_of::<u32>() as u64,
-                0,
-            );
+            // Clear the visible instance count (remaining of the buffer stays)
+            self.visibility_arguments
+                .cmd_reset_instance_counts(device, *command_buffer);
 
             // Pyramid to GPU read
             device.cmd_pipeline_barrier(
@@ -559,11 +620,11 @@ impl Culling {
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             self.visibility_arguments.destroy(device, allocator);
             self.visibility_buffer.destroy(device, allocator);
-            self.uniform_buffer.destroy(device, allocator);
+            self.visibility_mask_buffer.destroy(device, allocator);
             self.uniform_buffer_gpu.destroy(device, allocator);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
             device.destroy_descriptor_set_layout(self.desc_set_layout, None);
@@ -571,4 +632,4 @@ impl Culling {
             device.destroy_shader_module(self.compute_shader_module, None);
         }
     }
-}
\ No newline at end of file
+}
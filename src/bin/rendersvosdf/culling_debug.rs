@@ -6,16 +6,52 @@ use std::mem;
 use ash::util::*;
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::projection_settings::*;
+use crate::render_module::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct CullingDebugUniforms {
     pub depth_pyramid_dimension: u32, // pow2 y dimension of mip 0 (texture x is 1.5x wider)
 }
 
+unsafe impl Zeroable for CullingDebugUniforms {}
+unsafe impl Pod for CullingDebugUniforms {}
+
+pub struct CullingDebugDraw {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub graphic_pipeline: vk::Pipeline,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl CullingDebugDraw {
+    pub fn record(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptor_sets[..],
+                &[],
+            );
+
+            device.cmd_bind_pipeline(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.graphic_pipeline,
+            );
+
+            device.cmd_draw(*command_buffer, 3, 1, 0, 0);
+        }
+    }
+}
+
 pub struct CullingDebug {
     pub pipeline_layout: vk::PipelineLayout,
     pub uniform_buffer: VkBuffer,
@@ -25,17 +61,19 @@ pub struct CullingDebug {
     pub descriptor_sets: Vec<vk::DescriptorSet>,
     pub vertex_shader_module: vk::ShaderModule,
     pub fragment_shader_module: vk::ShaderModule,
+    pub enabled: bool, // Draws the overlay in the main render pass
 }
 
 impl CullingDebug {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
         render_pass: &vk::RenderPass,
         view_scissor: &VkViewScissor,
         depth_pyramid_debug_descriptor: &vk::DescriptorImageInfo,
+        depth: DepthConvention,
     ) -> CullingDebug {
         let uniform_buffer_info = vk::BufferCreateInfo {
             size: std::mem::size_of::<CullingDebugUniforms>() as u64,
@@ -81,7 +119,7 @@ impl CullingDebug {
                 ..Default::default()
             },
         ];
-            let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
+        let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
             binding_count: desc_layout_bindings.len() as u32,
             p_bindings: desc_layout_bindings.as_ptr(),
             ..Default::default()
@@ -91,15 +129,9 @@ impl CullingDebug {
 
         let desc_set_layouts = &[desc_set_layout];
 
-          let descriptor_sets = {
-            let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-                descriptor_pool: *descriptor_pool,
-                descriptor_set_count: desc_set_layouts.len() as u32,
-                p_set_layouts: desc_set_layouts.as_ptr(),
-                ..Default::default()
-            };
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
         let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: uniform_buffer_gpu.buffer,
@@ -127,7 +159,7 @@ impl CullingDebug {
         ];
         unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
 
-          let layout_create_info = vk::PipelineLayoutCreateInfo {
+        let layout_create_info = vk::PipelineLayoutCreateInfo {
             set_layout_count: desc_set_layouts.len() as u32,
             p_set_layouts: desc_set_layouts.as_ptr(),
             ..Default::default()
@@ -142,7 +174,7 @@ impl CullingDebug {
 
         let vertex_code =
             read_spv(&mut vertex_spv_file).expect("Failed to read vertex shader spv file");
-        let vertex_shader_info = vk::ShaderModuleCreateInfo{
+        let vertex_shader_info = vk::ShaderModuleCreateInfo {
             code_size: vertex_code.len() * 4,
             p_code: vertex_code.as_ptr(),
             ..Default::default()
@@ -150,7 +182,7 @@ impl CullingDebug {
 
         let frag_code =
             read_spv(&mut frag_spv_file).expect("Failed to read fragment shader spv file");
-        let frag_shader_info = vk::ShaderModuleCreateInfo{
+        let frag_shader_info = vk::ShaderModuleCreateInfo {
             code_size: frag_code.len() * 4,
             p_code: frag_code.as_ptr(),
             ..Default::default()
@@ -189,7 +221,7 @@ impl CullingDebug {
 
         let scissors = &[view_scissor.scissor];
         let viewports = &[view_scissor.viewport];
-   let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
             viewport_count: viewports.len() as u32,
             p_viewports: viewports.as_ptr(),
             scissor_count: scissors.len() as u32,
@@ -219,7 +251,7 @@ impl CullingDebug {
         let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
             depth_test_enable: 1,
             depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            depth_compare_op: depth.compare_op(),
             front: noop_stencil_state,
             back: noop_stencil_state,
             max_depth_bounds: 1.0,
@@ -236,8 +268,8 @@ impl CullingDebug {
             alpha_blend_op: vk::BlendOp::ADD,
             color_write_mask: vk::ColorComponentFlags::RGBA,
         }];
-    
-         let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
             logic_op: vk::LogicOp::CLEAR,
             attachment_count: color_blend_attachment_states.len() as u32,
             p_attachments: color_blend_attachment_states.as_ptr(),
@@ -245,7 +277,7 @@ impl CullingDebug {
         };
 
         let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-          let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
             dynamic_state_count: dynamic_state.len() as u32,
             p_dynamic_states: dynamic_state.as_ptr(),
             ..Default::default()
@@ -265,15 +297,15 @@ impl CullingDebug {
             render_pass: *render_pass,
             ..Default::default()
         };
-  
+
         let graphics_pipelines = unsafe {
-                    device.create_graphics_pipelines(
-                        vk::PipelineCache::null(),
-                        &[graphic_pipeline_infos],
-                        None,
-                    )
-                }
-                .unwrap();
+            device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[graphic_pipeline_infos],
+                None,
+            )
+        }
+        .unwrap();
 
         let graphic_pipeline = graphics_pipelines[0];
 
@@ -286,89 +318,92 @@ impl CullingDebug {
             descriptor_sets,
             vertex_shader_module,
             fragment_shader_module,
+            enabled: false,
         }
     }
 
     pub fn update(&self, uniforms: &CullingDebugUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+        self.uniform_buffer.copy_struct(uniforms, 0).unwrap();
     }
 
-    pub fn gpu_draw(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+    pub fn gpu_draw(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: &vk::CommandBuffer,
+    ) {
         let buffer_copy_regions = vk::BufferCopy {
             src_offset: 0,
             dst_offset: 0,
             size: self.uniform_buffer.size,
         };
 
-        let buffer_barrier = vk::BufferMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        let buffer_barrier = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::VERTEX_SHADER
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::COPY,
+            dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
             buffer: self.uniform_buffer_gpu.buffer,
             offset: 0,
             size: buffer_copy_regions.size,
             ..Default::default()
         };
 
-        let buffer_barrier_end = vk::BufferMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::INDEX_READ,
+        let buffer_barrier_end = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COPY,
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::VERTEX_SHADER
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags2::UNIFORM_READ,
             buffer: self.uniform_buffer_gpu.buffer,
             offset: 0,
             size: buffer_copy_regions.size,
             ..Default::default()
         };
 
-        unsafe {
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &[buffer_barrier],
+            &[],
+        );
 
+        unsafe {
             device.cmd_copy_buffer(
                 *command_buffer,
                 self.uniform_buffer.buffer,
                 self.uniform_buffer_gpu.buffer,
                 &[buffer_copy_regions],
-            );
+            )
+        };
 
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier_end],
-                &[],
-            );
-        }
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &[buffer_barrier_end],
+            &[],
+        );
     }
 
     pub fn gpu_draw_main_render_pass(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        unsafe {
-            device.cmd_bind_descriptor_sets(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout,
-                0,
-                &self.descriptor_sets[..],
-                &[],
-            );
-
-            device.cmd_bind_pipeline(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.graphic_pipeline,
-            );
+        self.main_render_pass_draw().record(device, command_buffer);
+    }
 
-            device.cmd_draw(*command_buffer, 3, 1, 0, 0);
+    // Copies the handles, so the draw can be recorded on a worker thread
+    pub fn main_render_pass_draw(&self) -> CullingDebugDraw {
+        CullingDebugDraw {
+            pipeline_layout: self.pipeline_layout,
+            graphic_pipeline: self.graphic_pipeline,
+            descriptor_sets: self.descriptor_sets.clone(),
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             device.destroy_pipeline(self.graphic_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
@@ -379,4 +414,32 @@ impl CullingDebug {
             device.destroy_descriptor_set_layout(self.desc_set_layout, None);
         }
     }
-}
\ No newline at end of file
+}
+
+impl RenderModule for CullingDebug {
+    fn record_pre_pass(
+        &mut self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        _frame: &FrameContext,
+    ) {
+        self.gpu_draw(device, barrier_api, &command_buffer);
+    }
+
+    fn record_main_pass(&mut self, frame: &mut FrameContext) -> Option<MainPassDraw> {
+        if !self.enabled {
+            return None;
+        }
+        let draw = self.main_render_pass_draw();
+        let view_scissor = frame.view_scissor;
+        Some(Box::new(move |device, command_buffer| {
+            view_scissor.set(device, command_buffer);
+            draw.record(device, &command_buffer);
+        }))
+    }
+
+    fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        CullingDebug::destroy(self, device, allocator);
+    }
+}
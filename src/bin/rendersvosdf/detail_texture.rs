@@ -0,0 +1,195 @@
+use ash::{vk, Device};
+use std::default::Default;
+
+use gpu_allocator::MemoryLocation;
+
+use crate::allocator_telemetry::*;
+use crate::detail_noise::*;
+use crate::vulkan_helpers::*;
+
+const DETAIL_NOISE_SEED: u32 = 0x5eed;
+
+// Tiling noise texture and per-material parameters for the detail term of svo_main.frag
+pub struct DetailTexture {
+    pub noise_texture: VkImage,
+    pub noise_upload_buffer: VkBuffer,
+    pub materials_buffer: VkBuffer,
+    pub sampler: vk::Sampler,
+    pub noise_view: vk::ImageView,
+    pub noise_descriptor: vk::DescriptorImageInfo,
+    pub materials_descriptor: vk::DescriptorBufferInfo,
+}
+
+impl DetailTexture {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        sampler_cache: &mut SamplerCache,
+        settings: &DetailSettings,
+    ) -> DetailTexture {
+        let noise = tiling_noise_3d(DETAIL_NOISE_SIZE, DETAIL_NOISE_OCTAVES, DETAIL_NOISE_SEED);
+
+        let upload_buffer_info = vk::BufferCreateInfo {
+            size: noise.len() as u64,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let noise_upload_buffer = VkBuffer::new(
+            device,
+            allocator,
+            &upload_buffer_info,
+            MemoryLocation::CpuToGpu,
+        );
+        noise_upload_buffer.copy_from_slice(&noise[..], 0).unwrap();
+
+        let texture_create_info = vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_3D,
+            format: vk::Format::R8_UNORM,
+            extent: vk::Extent3D {
+                width: DETAIL_NOISE_SIZE,
+                height: DETAIL_NOISE_SIZE,
+                depth: DETAIL_NOISE_SIZE,
+            },
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let noise_texture = VkImage::new(
+            device,
+            allocator,
+            &texture_create_info,
+            MemoryLocation::GpuOnly,
+        );
+
+        let view_info = vk::ImageViewCreateInfo {
+            view_type: vk::ImageViewType::TYPE_3D,
+            format: texture_create_info.format,
+            components: vk::ComponentMapping {
+                r: vk::ComponentSwizzle::R,
+                g: vk::ComponentSwizzle::G,
+                b: vk::ComponentSwizzle::B,
+                a: vk::ComponentSwizzle::A,
+            },
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                level_count: 1,
+                layer_count: 1,
+                ..Default::default()
+            },
+            image: noise_texture.image,
+            ..Default::default()
+        };
+        let noise_view = create_image_view(device, &view_info).unwrap();
+
+        // The noise tiles, so the world position can be used as texture coordinate directly
+        let sampler = sampler_cache.get(
+            device,
+            SamplerDesc {
+                address_mode: vk::SamplerAddressMode::REPEAT,
+                ..Default::default()
+            },
+        );
+
+        let noise_descriptor = vk::DescriptorImageInfo {
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            image_view: noise_view,
+            sampler,
+        };
+
+        let materials_buffer_info = vk::BufferCreateInfo {
+            size: std::mem::size_of::<DetailUniforms>() as u64,
+            usage: vk::BufferUsageFlags::UNIFORM_BUFFER,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let materials_buffer = VkBuffer::new(
+            device,
+            allocator,
+            &materials_buffer_info,
+            MemoryLocation::CpuToGpu,
+        );
+        materials_buffer
+            .copy_struct(&settings.uniforms(), 0)
+            .unwrap();
+
+        let materials_descriptor = vk::DescriptorBufferInfo {
+            buffer: materials_buffer.buffer,
+            offset: 0,
+            range: std::mem::size_of::<DetailUniforms>() as u64,
+        };
+
+        DetailTexture {
+            noise_texture,
+            noise_upload_buffer,
+            materials_buffer,
+            sampler,
+            noise_view,
+            noise_descriptor,
+            materials_descriptor,
+        }
+    }
+
+    pub fn gpu_setup(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: &vk::CommandBuffer,
+    ) {
+        let image_copy = vk::BufferImageCopy {
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                layer_count: 1,
+                ..Default::default()
+            },
+            image_extent: vk::Extent3D {
+                width: DETAIL_NOISE_SIZE,
+                height: DETAIL_NOISE_SIZE,
+                depth: DETAIL_NOISE_SIZE,
+            },
+            ..Default::default()
+        };
+
+        self.noise_texture.transition_discarding(
+            device,
+            barrier_api,
+            *command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
+
+        unsafe {
+            device.cmd_copy_buffer_to_image(
+                *command_buffer,
+                self.noise_upload_buffer.buffer,
+                self.noise_texture.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[image_copy],
+            )
+        };
+
+        self.noise_texture.transition_to(
+            device,
+            barrier_api,
+            *command_buffer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+        );
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        destroy_image_view(device, self.noise_view);
+        self.noise_texture.destroy(device, allocator);
+        self.noise_upload_buffer.destroy(device, allocator);
+        self.materials_buffer.destroy(device, allocator);
+    }
+}
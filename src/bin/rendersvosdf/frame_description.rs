@@ -0,0 +1,243 @@
+use ash::vk;
+
+use crate::frame_graph::*;
+
+// Mirrors the command recording in main.rs and the renderer modules' gpu_draw barriers.
+// Keep in sync when passes or barriers change.
+pub fn describe_frame(
+    enable_hiz: bool,
+    enable_culling_debug: bool,
+    enable_post: bool,
+    enable_ao: bool,
+) -> FrameGraph {
+    let mut graph = FrameGraph::new();
+
+    let main_pass = graph.add_pass("main render pass");
+
+    // The svo cube uniforms are bound with a dynamic offset into host visible scratch memory,
+    // so no upload pass or barrier precedes the main render pass
+    let color = graph.add_resource("swapchain image");
+    let depth = graph.add_resource("depth buffer");
+    let instances = graph.add_resource("instances buffer");
+    let atlas = graph.add_resource("brick atlas");
+
+    graph.read(main_pass, instances);
+    graph.read(main_pass, atlas);
+    graph.write(main_pass, depth);
+
+    if enable_post {
+        describe_post(&mut graph, main_pass, color, depth, enable_ao);
+    } else {
+        graph.write(main_pass, color);
+    }
+
+    if !enable_hiz {
+        return graph;
+    }
+
+    let depth_pyramid = graph.add_pass("depth pyramid");
+    let culling = graph.add_pass("culling");
+
+    let pyramid = graph.add_resource("depth pyramid image");
+    let pyramid_debug = graph.add_resource("depth pyramid debug image");
+    let visibility = graph.add_resource("visibility buffer");
+    let arguments = graph.add_resource("visibility indirect arguments");
+
+    graph.read(depth_pyramid, depth);
+    graph.write(depth_pyramid, pyramid);
+    graph.read(culling, pyramid);
+    graph.read(culling, instances);
+    graph.write(culling, pyramid_debug);
+    graph.write(culling, visibility);
+    graph.write(culling, arguments);
+    graph.read(main_pass, visibility);
+    graph.read(main_pass, arguments);
+
+    graph.barrier(FrameBarrier {
+        src_pass: main_pass,
+        dst_pass: depth_pyramid,
+        resource: depth,
+        src_stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        src_access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        dst_access: vk::AccessFlags::SHADER_READ,
+        layouts: Some((
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )),
+        ..Default::default()
+    });
+
+    graph.barrier(FrameBarrier {
+        src_pass: depth_pyramid,
+        dst_pass: main_pass,
+        resource: depth,
+        src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        src_access: vk::AccessFlags::SHADER_READ,
+        dst_stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        dst_access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        layouts: Some((
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        )),
+        previous_frame: true,
+    });
+
+    graph.barrier(FrameBarrier {
+        src_pass: depth_pyramid,
+        dst_pass: culling,
+        resource: pyramid,
+        src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        src_access: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        dst_access: vk::AccessFlags::SHADER_READ,
+        layouts: Some((vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL)),
+        ..Default::default()
+    });
+
+    graph.barrier(FrameBarrier {
+        src_pass: culling,
+        dst_pass: depth_pyramid,
+        resource: pyramid,
+        src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        src_access: vk::AccessFlags::SHADER_READ,
+        dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        dst_access: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        layouts: Some((vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL)),
+        previous_frame: true,
+    });
+
+    // Culling clears the argument count with a fill before the compute pass
+    graph.barrier(FrameBarrier {
+        src_pass: main_pass,
+        dst_pass: culling,
+        resource: arguments,
+        src_stage: vk::PipelineStageFlags::DRAW_INDIRECT,
+        src_access: vk::AccessFlags::empty(),
+        dst_stage: vk::PipelineStageFlags::TRANSFER,
+        dst_access: vk::AccessFlags::TRANSFER_WRITE,
+        ..Default::default()
+    });
+
+    graph.barrier(FrameBarrier {
+        src_pass: culling,
+        dst_pass: main_pass,
+        resource: arguments,
+        src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        src_access: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        dst_stage: vk::PipelineStageFlags::DRAW_INDIRECT,
+        dst_access: vk::AccessFlags::INDIRECT_COMMAND_READ,
+        previous_frame: true,
+        ..Default::default()
+    });
+
+    // Culling debug draws inside the main render pass
+    if enable_culling_debug {
+        graph.read(main_pass, pyramid_debug);
+    }
+
+    graph
+}
+
+// Main render pass -> HDR image -> effect passes -> output render pass (post_process.rs).
+// With AO the depth buffer goes to the AO pass first, its target is read by the first effect.
+fn describe_post(
+    graph: &mut FrameGraph,
+    main_pass: PassId,
+    color: ResourceId,
+    depth: ResourceId,
+    enable_ao: bool,
+) {
+    let ao_pass = if enable_ao {
+        Some(graph.add_pass("ambient occlusion"))
+    } else {
+        None
+    };
+    let effects = graph.add_pass("post effects");
+    let output = graph.add_pass("post output");
+
+    let hdr = graph.add_resource("hdr images");
+
+    if let Some(ao_pass) = ao_pass {
+        let ao_target = graph.add_resource("ao target");
+        graph.read(ao_pass, depth);
+        graph.write(ao_pass, ao_target);
+        graph.read(effects, ao_target);
+        graph.barrier(FrameBarrier {
+            src_pass: ao_pass,
+            dst_pass: effects,
+            resource: ao_target,
+            src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            src_access: vk::AccessFlags::SHADER_WRITE,
+            dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+            dst_access: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            ..Default::default()
+        });
+    }
+
+    graph.write(main_pass, hdr);
+    graph.read(effects, hdr);
+    graph.read(effects, depth);
+    graph.write(effects, hdr);
+    graph.read(output, hdr);
+    graph.write(output, color);
+
+    graph.barrier(FrameBarrier {
+        src_pass: main_pass,
+        dst_pass: effects,
+        resource: hdr,
+        src_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        src_access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        dst_access: vk::AccessFlags::SHADER_READ,
+        layouts: Some((vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL)),
+        ..Default::default()
+    });
+
+    graph.barrier(FrameBarrier {
+        src_pass: main_pass,
+        dst_pass: ao_pass.unwrap_or(effects),
+        resource: depth,
+        src_stage: vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        src_access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        dst_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        dst_access: vk::AccessFlags::SHADER_READ,
+        layouts: Some((
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )),
+        ..Default::default()
+    });
+
+    graph.barrier(FrameBarrier {
+        src_pass: effects,
+        dst_pass: output,
+        resource: hdr,
+        src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        src_access: vk::AccessFlags::SHADER_WRITE,
+        dst_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+        dst_access: vk::AccessFlags::SHADER_READ,
+        layouts: Some((vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL)),
+        ..Default::default()
+    });
+
+    // The output render pass loads depth for overlays
+    graph.barrier(FrameBarrier {
+        src_pass: effects,
+        dst_pass: output,
+        resource: depth,
+        src_stage: vk::PipelineStageFlags::COMPUTE_SHADER,
+        src_access: vk::AccessFlags::empty(),
+        dst_stage: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        dst_access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        layouts: Some((
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        )),
+        ..Default::default()
+    });
+}
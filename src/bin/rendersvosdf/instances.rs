@@ -2,88 +2,255 @@ use std::default::Default;
 
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
 use crate::minivector::*;
 use crate::vulkan_helpers::*;
 use rust_test::svosdf::*;
 
+// Hit epsilon of svo_main.frag (0.00025 in [-1, 1]) in 16 bit distance units
+const SURFACE_BAND_HALF_WIDTH: u16 = 8;
+
+// One per brick in the hit band, shared by all scene instances
 #[derive(Clone, Copy)]
-pub struct InstanceData {
-    pub position: Vec4,
+#[repr(C)]
+pub struct BrickData {
+    pub position: Vec4, // Offset of the brick in the volume, size in w
     pub brick_index: u32,
     pub brick_size: u32,
-    pub _padding: [u32; 2],
+    pub atlas_slot: u32, // Position of the brick in the atlas, see AtlasOrder
+    pub _padding: u32,
+}
+
+// One per scene instance. The culling and vertex shaders place the bricks with it, brick
+// i of scene instance j is instance j * bricks_per_scene_instance + i.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SceneInstanceData {
+    pub translation_scale: Vec4,
+    pub material: u32, // Index into the detail materials
+    pub _padding: [u32; 3],
+}
+
+// Strides of BrickData and SceneInstanceData in svo_main.vert and culling.comp
+const _: () = assert!(std::mem::size_of::<BrickData>() == 32);
+const _: () = assert!(std::mem::size_of::<SceneInstanceData>() == 32);
+unsafe impl Zeroable for BrickData {}
+unsafe impl Pod for BrickData {}
+unsafe impl Zeroable for SceneInstanceData {}
+unsafe impl Pod for SceneInstanceData {}
+
+// Placement of one copy of the SVO volume. Copies share the brick atlas, octree and
+// brick data, only the placement is stored per copy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneInstance {
+    pub translation: Vec3,
+    pub scale: f32,
+    pub material: u32,
+    pub hidden: bool, // Culled with the VisibilityMask, its instance data stays
+}
+
+impl SceneInstance {
+    // The GPU instance data has no rotation, scene instances are translated and scaled only
+    pub fn transform(&self) -> Transform {
+        Transform {
+            translation: self.translation,
+            scale: self.scale,
+            ..Transform::IDENTITY
+        }
+    }
+}
+
+impl Default for SceneInstance {
+    fn default() -> SceneInstance {
+        SceneInstance {
+            translation: Vec3::from_scalar(0.0),
+            scale: 1.0,
+            material: 0,
+            hidden: false,
+        }
+    }
 }
 
 pub struct Instances {
-    pub instances_buffer: VkBuffer,
-    pub instances_buffer_descriptor: vk::DescriptorBufferInfo,
-    pub num_instances: usize,
+    pub bricks_buffer: TypedBuffer<BrickData>,
+    pub bricks_buffer_descriptor: vk::DescriptorBufferInfo,
+    pub scene_instances_buffer: TypedBuffer<SceneInstanceData>,
+    pub scene_instances_buffer_descriptor: vk::DescriptorBufferInfo,
+    pub num_instances: usize, // Bricks of all scene instances, culled one by one
+    pub bricks_per_scene_instance: usize,
+    pub pending_upload: Option<Vec<SceneInstanceData>>, // Set by update, copied in gpu_upload
+}
+
+// Bricks entirely outside the hit band can't produce a hit, they are skipped before upload.
+// Brick indices still refer to the full brick atlas.
+pub fn visible_bricks(svo_sdf: &SvoSdf) -> Vec<usize> {
+    svo_sdf.bricks_in_band(SURFACE_BAND_HALF_WIDTH).collect()
+}
+
+fn brick_data(svo_sdf: &SvoSdf, visible_bricks: &[usize], atlas_slots: &[u32]) -> Vec<BrickData> {
+    visible_bricks
+        .iter()
+        .map(|&i| {
+            let brick = &svo_sdf.bricks[i];
+            let offset = svo_sdf.voxel_offset(brick.position).to_vec3();
+
+            // Size along the axis with the largest spacing, svo_main.vert shrinks the other
+            // axes. Culling treats bricks as cubes of this size.
+            let size = brick.size as f32 * svo_sdf.header.max_spacing();
+
+            BrickData {
+                position: Vec4 {
+                    x: offset.x,
+                    y: offset.y,
+                    z: offset.z,
+                    w: size,
+                },
+                brick_index: i as u32,
+                brick_size: brick.size,
+                atlas_slot: atlas_slots[i],
+                _padding: 0,
+            }
+        })
+        .collect()
+}
+
+fn scene_instance_data(scene_instances: &[SceneInstance]) -> Vec<SceneInstanceData> {
+    scene_instances
+        .iter()
+        .map(|scene_instance| {
+            let transform = scene_instance.transform();
+            SceneInstanceData {
+                translation_scale: Vec4 {
+                    x: transform.translation.x,
+                    y: transform.translation.y,
+                    z: transform.translation.z,
+                    w: transform.scale,
+                },
+                material: scene_instance.material,
+                _padding: [0; 3],
+            }
+        })
+        .collect()
 }
 
 impl Instances {
-    pub fn new(device: &Device, allocator: &mut Allocator, svo_sdf: &SvoSdf) -> Instances {
-        let num_instances = svo_sdf.bricks.len();
-        
-        let instances_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<InstanceData>() * num_instances) as u64,
-            usage: vk::BufferUsageFlags::STORAGE_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        svo_sdf: &SvoSdf,
+        scene_instances: &[SceneInstance],
+        atlas_slots: &[u32],
+    ) -> Instances {
+        let visible_bricks = visible_bricks(svo_sdf);
 
-        let instances_buffer = VkBuffer::new(
+        let bricks_buffer = TypedBuffer::new(
             device,
             allocator,
-            &instances_buffer_info,
+            visible_bricks.len(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
             MemoryLocation::CpuToGpu,
         );
+        bricks_buffer.write_slice(&brick_data(svo_sdf, &visible_bricks, atlas_slots)[..]);
 
-        let instances_buffer_descriptor = vk::DescriptorBufferInfo {
-            buffer: instances_buffer.buffer,
-            offset: 0,
-            range: (std::mem::size_of::<InstanceData>() * num_instances) as u64,
-        };
-
-        // Create instance data from SVO bricks
-        let instances_buffer_data: Vec<InstanceData> = svo_sdf.bricks
-            .iter()
-            .enumerate()
-            .map(|(i, brick)| {
-                let world_pos = Vec3 {
-                    x: brick.position.0 as f32 * svo_sdf.header.dx,
-                    y: brick.position.1 as f32 * svo_sdf.header.dx,
-                    z: brick.position.2 as f32 * svo_sdf.header.dx,
-                };
-                
-                let brick_world_size = brick.size as f32 * svo_sdf.header.dx;
-                
-                InstanceData {
-                    position: Vec4 {
-                        x: world_pos.x,
-                        y: world_pos.y,
-                        z: world_pos.z,
-                        w: brick_world_size,
-                    },
-                    brick_index: i as u32,
-                    brick_size: brick.size,
-                    _padding: [0, 0],
-                }
-            })
-            .collect();
-
-        instances_buffer.copy_from_slice(&instances_buffer_data[..], 0);
+        let scene_instances_buffer = TypedBuffer::new(
+            device,
+            allocator,
+            scene_instances.len(),
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::CpuToGpu,
+        );
+        scene_instances_buffer.write_slice(&scene_instance_data(scene_instances)[..]);
 
         Instances {
-            instances_buffer,
-            instances_buffer_descriptor,
-            num_instances,
+            bricks_buffer_descriptor: bricks_buffer.descriptor(),
+            bricks_buffer,
+            scene_instances_buffer_descriptor: scene_instances_buffer.descriptor(),
+            scene_instances_buffer,
+            num_instances: visible_bricks.len() * scene_instances.len(),
+            bricks_per_scene_instance: visible_bricks.len(),
+            pending_upload: None,
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
-        self.instances_buffer.destroy(device, allocator);
+    // New placements of the same number of scene instances. Frames in flight still read
+    // the scene instance buffer, so the data is copied on the GPU by gpu_upload.
+    pub fn update(&mut self, scene_instances: &[SceneInstance]) {
+        assert_eq!(
+            scene_instances.len(),
+            self.scene_instances_buffer.len(),
+            "Scene instance count changed"
+        );
+
+        // A newer update replaces one that wasn't recorded yet
+        self.pending_upload = Some(scene_instance_data(scene_instances));
     }
-}
\ No newline at end of file
+
+    // Copies pending scene instance data through the staging ring, ordered after earlier frames
+    // reading the instances and before this frame's draw and culling
+    pub fn gpu_upload(
+        &mut self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: &vk::CommandBuffer,
+        staging_ring: &mut StagingRing,
+    ) -> Result<(), AllocationError> {
+        let data = match self.pending_upload.take() {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let readers =
+            vk::PipelineStageFlags2::VERTEX_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER;
+
+        let copy_barrier = vk::BufferMemoryBarrier2 {
+            src_stage_mask: readers,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::COPY,
+            dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            buffer: self.scene_instances_buffer.buffer.buffer,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &[copy_barrier],
+            &[],
+        );
+
+        staging_ring.copy_to_buffer(
+            device,
+            *command_buffer,
+            &data[..],
+            self.scene_instances_buffer.buffer.buffer,
+            0,
+        )?;
+
+        let read_barrier = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COPY,
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_stage_mask: readers,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ,
+            buffer: self.scene_instances_buffer.buffer.buffer,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &[read_barrier],
+            &[],
+        );
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        self.bricks_buffer.destroy(device, allocator);
+        self.scene_instances_buffer.destroy(device, allocator);
+    }
+}
@@ -0,0 +1,179 @@
+// Scene lights of the SVO shading path. The scene file lists them as [[light]] tables (see
+// scene_file.rs), every frame the point and spot lights outside the view are culled and the
+// rest is copied into a storage buffer the SVO fragment shader loops over. Without lights the
+// shader keeps its normal debug colors.
+
+use crate::minivector::*;
+use crate::vulkan_helpers::*;
+
+// Lights per frame, further ones are dropped after culling
+pub const MAX_LIGHTS: usize = 64;
+
+// Light contribution where there is no light, so unlit sides don't turn black
+pub const AMBIENT: f32 = 0.08;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightType {
+    Directional,
+    Point,
+    Spot,
+}
+
+impl LightType {
+    pub fn from_name(name: &str) -> Option<LightType> {
+        match name {
+            "directional" => Some(LightType::Directional),
+            "point" => Some(LightType::Point),
+            "spot" => Some(LightType::Spot),
+            _ => None,
+        }
+    }
+
+    // Value of the type field in svo_main.frag
+    pub fn gpu_type(&self) -> u32 {
+        match self {
+            LightType::Directional => 0,
+            LightType::Point => 1,
+            LightType::Spot => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub light_type: LightType,
+    pub position: Vec3,  // Point and spot lights
+    pub direction: Vec3, // Directional and spot lights, the way the light travels
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,       // Point and spot lights fade to zero at this distance
+    pub inner_angle: f32, // Spot cone half angles in radians, full intensity inside inner
+    pub outer_angle: f32,
+}
+
+impl Default for Light {
+    fn default() -> Light {
+        Light {
+            light_type: LightType::Point,
+            position: Vec3::from_scalar(0.0),
+            direction: Vec3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            color: Vec3::from_scalar(1.0),
+            intensity: 1.0,
+            range: 10.0,
+            inner_angle: 20f32.to_radians(),
+            outer_angle: 30f32.to_radians(),
+        }
+    }
+}
+
+impl Light {
+    // Inverse square falloff windowed to reach zero at range, matches svo_main.frag
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        if self.light_type == LightType::Directional {
+            return 1.0;
+        }
+        let ratio = distance / self.range;
+        let window = (1.0 - ratio * ratio * ratio * ratio).clamp(0.0, 1.0);
+        window * window / (distance * distance).max(1e-4)
+    }
+
+    // Smooth cone falloff of spot lights towards a point, 1 for other lights
+    pub fn cone(&self, to_point: Vec3) -> f32 {
+        if self.light_type != LightType::Spot {
+            return 1.0;
+        }
+        let cos_angle = to_point.normalize().dot(self.direction);
+        let cos_outer = self.outer_angle.cos();
+        let cos_inner = self.inner_angle.cos().max(cos_outer + 1e-4);
+        smoothstep(cos_outer, cos_inner, cos_angle)
+    }
+
+    pub fn to_gpu(self) -> LightGpu {
+        let color = self.color * self.intensity;
+        LightGpu {
+            position_range: Vec4 {
+                x: self.position.x,
+                y: self.position.y,
+                z: self.position.z,
+                w: self.range,
+            },
+            direction_cos_outer: Vec4 {
+                x: self.direction.x,
+                y: self.direction.y,
+                z: self.direction.z,
+                w: self.outer_angle.cos(),
+            },
+            color_cos_inner: Vec4 {
+                x: color.x,
+                y: color.y,
+                z: color.z,
+                w: self.inner_angle.cos(),
+            },
+            light_type: self.light_type.gpu_type(),
+            _padding: [0; 3],
+        }
+    }
+}
+
+// Matches LightData in svo_main.frag (std430)
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct LightGpu {
+    pub position_range: Vec4,
+    pub direction_cos_outer: Vec4,
+    pub color_cos_inner: Vec4, // Color premultiplied by the intensity
+    pub light_type: u32,
+    pub _padding: [u32; 3],
+}
+
+unsafe impl Zeroable for LightGpu {}
+unsafe impl Pod for LightGpu {}
+
+// Contents of the light storage buffer for one frame
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SvoLights {
+    pub count: u32,
+    pub ambient: f32,
+    pub _padding: [u32; 2],
+    pub lights: [LightGpu; MAX_LIGHTS],
+}
+
+unsafe impl Zeroable for SvoLights {}
+unsafe impl Pod for SvoLights {}
+
+impl Default for SvoLights {
+    fn default() -> SvoLights {
+        SvoLights::zeroed()
+    }
+}
+
+// Lights that can reach the view, directional lights first and the others nearest first
+pub fn cull_lights(lights: &[Light], world_to_screen: &Mat4x4, camera_position: Vec3) -> SvoLights {
+    let frustum = Frustum::from_matrix(world_to_screen);
+    let reaches_view = |light: &Light| {
+        light.light_type == LightType::Directional
+            || frustum.intersects_sphere(&Sphere::new(light.position, light.range))
+    };
+    let mut visible: Vec<&Light> = lights.iter().filter(|light| reaches_view(light)).collect();
+    let distance = |light: &Light| match light.light_type {
+        LightType::Directional => 0.0,
+        _ => (light.position - camera_position).length() - light.range,
+    };
+    visible.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+    visible.truncate(MAX_LIGHTS);
+
+    let mut svo_lights = SvoLights {
+        count: visible.len() as u32,
+        ambient: AMBIENT,
+        ..SvoLights::default()
+    };
+    for (gpu, light) in svo_lights.lights.iter_mut().zip(visible) {
+        *gpu = light.to_gpu();
+    }
+    svo_lights
+}
@@ -1,45 +1,106 @@
 #![allow(dead_code)]
 
-const NUM_DESCRIPTORS_PER_TYPE: u32 = 1024;
-const NUM_DESCRIPTOR_SETS: u32 = 1024;
+const DESCRIPTOR_SETS_PER_POOL: u32 = 16;
 const ENABLE_CULLING_DEBUG: bool = false;
+const SVO_SDF_FILE: &str = "data/ganymede-and-jupiter.svosdf";
 
 extern crate winit;
 
+mod batch;
 mod culling;
 mod culling_debug;
 mod depth_pyramid;
+mod detail_texture;
+mod frame_description;
 mod instances;
+mod lights;
+mod navcube;
+mod post_process;
 mod render_svo_cubes;
+mod scene_file;
+mod selection;
+mod snapshot;
 mod svo_texture;
-
+mod visibility_mask;
+
+use rust_test::allocator_telemetry;
+use rust_test::assets;
+use rust_test::brick_checksum;
+use rust_test::brick_stream;
+use rust_test::cancellation;
+use rust_test::color;
+use rust_test::detail_noise;
+use rust_test::display_settings;
+use rust_test::frame_graph;
+use rust_test::input;
+use rust_test::localization;
+use rust_test::memory_profile;
 use rust_test::minivector;
+use rust_test::object_counters;
+use rust_test::procedural;
+use rust_test::projection_settings;
+use rust_test::quality_settings;
+use rust_test::render_module;
+use rust_test::sdf;
 use rust_test::svosdf;
+use rust_test::thumbnail;
 use rust_test::vulkan_base;
 use rust_test::vulkan_helpers;
 
+use std::env;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
 use std::time::Instant;
 
 use ash::vk;
 
+use gpu_allocator::MemoryLocation;
+
 use winit::{
-    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
-    event_loop::EventLoop,
+    event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{EventLoop, EventLoopWindowTarget},
     window::WindowBuilder,
 };
 
+use assets::*;
+use brick_checksum::*;
+use brick_stream::*;
+use cancellation::*;
+use detail_noise::*;
+use display_settings::*;
+use input::*;
+use localization::*;
+use memory_profile::*;
 use minivector::*;
+use object_counters::*;
+use procedural::*;
+use projection_settings::*;
+use quality_settings::*;
+use render_module::*;
+use sdf::*;
 use svosdf::*;
+use thumbnail::*;
 
 use vulkan_base::*;
 use vulkan_helpers::*;
 
+use batch::*;
 use culling::*;
 use culling_debug::*;
 use depth_pyramid::*;
+use detail_texture::*;
+use frame_description::*;
 use instances::*;
+use lights::*;
+use navcube::*;
+use post_process::*;
 use render_svo_cubes::*;
+use scene_file::*;
+use selection::*;
+use snapshot::*;
 use svo_texture::*;
+use visibility_mask::*;
 
 #[derive(Clone, Copy)]
 pub struct Vertex {
@@ -47,46 +108,492 @@ pub struct Vertex {
     pub uv: [f32; 2],
 }
 
-fn main() {
-    // Load sparse voxel octree SDF
-    let svo_sdf = SvoSdf::load("data/ganymede-and-jupiter.svosdf").expect("SVO SDF loading failed");
+// --instances N places N copies of the volume on a grid in the XZ plane, raised and lowered
+// by a noise heightfield, cycling through the detail material presets
+fn scene_instances_from_args(args: &[String], spacing: f32) -> Vec<SceneInstance> {
+    let count = args
+        .iter()
+        .position(|arg| arg == "--instances")
+        .and_then(|i| args.get(i + 1))
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+
+    let height = Fbm::new(NoiseKind::Perlin, 3, 0.37);
+    heightfield_grid(count, spacing, &height, spacing * 0.5)
+        .into_iter()
+        .enumerate()
+        .map(|(i, translation)| SceneInstance {
+            translation,
+            scale: 1.0,
+            material: (i % detail_material_presets().len()) as u32,
+            hidden: false,
+        })
+        .collect()
+}
 
-    let dx = svo_sdf.header.dx;
-    let dim = svo_sdf.header.dim;
+// Average CPU writes per frame into the host visible buffers, see MappedBufferPool
+fn print_upload_report(report: &UploadReport) {
+    let total = report.total_bytes();
+    println!(
+        "{}",
+        tr_args(
+            "viewer.upload_report",
+            &[
+                ("frames", &report.frames),
+                ("bytes", &format!("{:.0}", report.bytes_per_frame(total))),
+                ("peak", &report.peak_frame_bytes()),
+                (
+                    "mbps",
+                    &format!("{:.2}", report.megabytes_per_second(total))
+                ),
+            ]
+        )
+    );
+    for stats in &report.buffers {
+        println!(
+            "{}",
+            tr_args(
+                "viewer.upload_report_row",
+                &[
+                    ("buffer", &stats.name),
+                    (
+                        "bytes",
+                        &format!("{:.0}", report.bytes_per_frame(stats.bytes))
+                    ),
+                    ("peak", &stats.peak_frame_bytes),
+                    (
+                        "mbps",
+                        &format!("{:.2}", report.megabytes_per_second(stats.bytes))
+                    ),
+                ]
+            )
+        );
+    }
+}
+
+fn print_object_counts(counts: &ObjectCounts) {
+    println!("{}", tr_args("viewer.object_counts", &[("counts", counts)]));
+}
+
+// A soak run of a steady scene ends with the counts it had after the first frames, the types
+// that grew since then are flagged as possible leaks
+fn print_object_report(counts: &ObjectCounts, baseline: Option<&(u32, ObjectCounts)>) {
+    print_object_counts(counts);
+    if let Some((frame, baseline)) = baseline {
+        let grown = counts.grown_since(baseline);
+        if grown.is_empty() {
+            println!(
+                "{}",
+                tr_args("viewer.object_counts_steady", &[("frame", frame)])
+            );
+        }
+        for (object_type, before, now) in grown {
+            println!(
+                "{}",
+                tr_args(
+                    "viewer.object_count_grew",
+                    &[
+                        ("type", &object_type.name()),
+                        ("before", &before),
+                        ("now", &now),
+                        ("frame", frame)
+                    ]
+                )
+            );
+        }
+    }
+}
+
+fn print_svo_memory(usage: &SvoMemoryUsage) {
+    println!(
+        "{}",
+        tr_args(
+            "viewer.svo_memory",
+            &[
+                ("total", &usage.total()),
+                ("nodes", &usage.nodes),
+                ("bricks", &usage.brick_payloads),
+                ("meta", &(usage.header + usage.metadata)),
+                ("malloc", &usage.allocator_overhead),
+            ]
+        )
+    );
+    if usage.duplicate_bricks > 0 {
+        println!(
+            "{}",
+            tr_args(
+                "viewer.svo_duplicates",
+                &[("count", &usage.duplicate_bricks)]
+            )
+        );
+    }
+}
 
-    let diagonal = Vec3 {
-        x: dx * dim.0 as f32,
-        y: dx * dim.1 as f32,
-        z: dx * dim.2 as f32,
+fn print_projection(settings: &ProjectionSettings) {
+    let near = format!("{}", settings.near);
+    let depth = settings.depth.name();
+    let message = match settings.projection_type {
+        ProjectionType::Perspective => {
+            let fov = format!("{}", settings.fov_degrees);
+            tr_args(
+                "viewer.projection_perspective",
+                &[("fov", &fov), ("near", &near), ("depth", &depth)],
+            )
+        }
+        ProjectionType::Orthographic => {
+            let height = format!("{}", settings.ortho_height);
+            tr_args(
+                "viewer.projection_orthographic",
+                &[("height", &height), ("near", &near), ("depth", &depth)],
+            )
+        }
     };
+    println!("{}", message);
+}
 
-    let center_to_edge = diagonal * 0.5;
-    let diagonal_length = diagonal.length();
-    let volume_scale = Vec3::from_scalar(diagonal_length) / diagonal;
+// Hidden scene instances are skipped by the culling shader, or left out of the visibility
+// list when HiZ is off
+fn apply_visibility(
+    culling: &Culling,
+    render_svo_cubes: &mut RenderSvoCubes,
+    instances: &Instances,
+    scene_instances: &[SceneInstance],
+    enable_hiz: bool,
+) -> VisibilityMask {
+    let mask = VisibilityMask::from_instances(scene_instances);
+    culling.set_visibility_mask(&mask);
+    if !enable_hiz {
+        render_svo_cubes.visible_instances =
+            Some(culling.set_visible_scene_instances(&mask, instances.bricks_per_scene_instance));
+    }
+    mask
+}
 
-    let texels = Vec3 {
-        x: dim.0 as f32,
-        y: dim.1 as f32,
-        z: dim.2 as f32,
+fn print_selection_stats(stats: &SelectionStats) {
+    println!(
+        "{}",
+        tr_args(
+            "viewer.selection_stats",
+            &[
+                ("instances", &stats.instances),
+                ("hidden", &stats.hidden),
+                ("bricks", &stats.bricks),
+                ("voxels", &stats.voxels)
+            ]
+        )
+    );
+    if let Some((min, max)) = stats.distance_range {
+        let (min, max) = (format!("{:.4}", min), format!("{:.4}", max));
+        println!(
+            "{}",
+            tr_args(
+                "viewer.selection_distances",
+                &[("min", &min), ("max", &max)]
+            )
+        );
+    }
+    if let Some(bounds) = stats.bounds {
+        let corner = |v: Vec3| format!("({:.2}, {:.2}, {:.2})", v.x, v.y, v.z);
+        let (min, max) = (corner(bounds.min), corner(bounds.max));
+        println!(
+            "{}",
+            tr_args("viewer.selection_bounds", &[("min", &min), ("max", &max)])
+        );
+    }
+}
+
+// The default volume, or the built in fallback when it isn't found. The brick checksums are
+// verified on a worker thread while the viewer starts.
+fn load_svo_sdf() -> io::Result<(SvoSdf, ChecksumVerification)> {
+    match resolve(SVO_SDF_FILE) {
+        Some(path) => SvoSdf::load_verify_in_background(&path.to_string_lossy()),
+        None => {
+            println!(
+                "{}",
+                tr_args(
+                    "viewer.asset_fallback",
+                    &[("file", &SVO_SDF_FILE), ("fallback", &FALLBACK_SVO_SDF)]
+                )
+            );
+            read(FALLBACK_SVO_SDF)
+                .and_then(|bytes| SvoSdf::from_bytes_verify_in_background(bytes.into_owned()))
+        }
+    }
+}
+
+// --remote HOST:PORT loads the volume from `svosdf serve` instead, bricks fetched before are
+// read from --cache-dir DIR when given. Bricks are verified as they arrive, so there is no
+// background verification.
+fn load_remote(args: &[String], address: &str) -> io::Result<SvoSdf> {
+    let cache_dir = args
+        .iter()
+        .position(|arg| arg == "--cache-dir")
+        .and_then(|i| args.get(i + 1))
+        .map(Path::new);
+    let time_start = Instant::now();
+    let mut remote = RemoteBricks::connect(address, cache_dir)?;
+    let svo_sdf = remote.load_svo_sdf()?;
+    println!(
+        "{}",
+        tr_args(
+            "viewer.remote_loaded",
+            &[
+                ("address", &address),
+                ("bricks", &svo_sdf.bricks.len()),
+                ("fetched", &remote.stats.fetched),
+                ("cached", &remote.stats.disk_hits),
+                ("seconds", &time_start.elapsed().as_secs_f32())
+            ]
+        )
+    );
+    Ok(svo_sdf)
+}
+
+// --convert-on-load input.sdf builds the octree in memory instead of loading an .svosdf, with
+// the svosdf tool options --brick-size, --max-depth, --threshold and --threshold-falloff.
+// --save-svosdf FILE also writes the result. Ctrl+C cancels the conversion and the save, see
+// Cancelled::from_io.
+fn convert_on_load(args: &[String], sdf_path: &str) -> io::Result<SvoSdf> {
+    let value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
     };
-    let texel_scale = Vec3::from_scalar(1.0) / texels;
+    let brick_size = value("--brick-size")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(8);
+    let max_depth = value("--max-depth")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(8);
+    let threshold = value("--threshold")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0.004);
+    let falloff = value("--threshold-falloff")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1.0);
+
+    let sdf = load_sdf_zlib(sdf_path)?;
+    let cancel = CancelToken::new();
+    let _interrupt = cancel.cancel_on_interrupt();
+    let time_start = Instant::now();
+    let mut percent_shown = None;
+    let svo_sdf = SvoSdf::from_sdf_with_progress(
+        &sdf,
+        brick_size,
+        max_depth,
+        ThresholdSchedule::geometric(threshold, falloff),
+        &mut |fraction| {
+            let percent = (fraction * 100.0) as u32;
+            if percent_shown != Some(percent) {
+                percent_shown = Some(percent);
+                print!(
+                    "\r{}",
+                    tr_args("viewer.converting", &[("percent", &percent)])
+                );
+                let _ = io::stdout().flush();
+            }
+        },
+        &cancel,
+    );
+    println!();
+    let mut svo_sdf = svo_sdf?;
+    println!(
+        "{}",
+        tr_args(
+            "viewer.converted",
+            &[
+                ("bricks", &svo_sdf.bricks.len()),
+                ("seconds", &time_start.elapsed().as_secs_f32())
+            ]
+        )
+    );
+
+    if let Some(path) = value("--save-svosdf") {
+        svo_sdf.thumbnail = Some(Thumbnail::project(&svo_sdf, THUMBNAIL_SIZE));
+        svo_sdf.save_with_cancel(path, &cancel)?;
+        println!("{}", tr_args("viewer.converted_saved", &[("file", path)]));
+    }
+    Ok(svo_sdf)
+}
+
+// Vulkan errors end the event loop so the cleanup below still runs
+fn check_vulkan<T>(
+    result: Result<T, VulkanError>,
+    event_loop: &EventLoopWindowTarget<()>,
+) -> Option<T> {
+    result
+        .map_err(|err| {
+            println!("{}", tr_args("viewer.vulkan_error", &[("error", &err)]));
+            event_loop.exit();
+        })
+        .ok()
+}
+
+// Render modules driven through the RenderModule steps, in main render pass order. A new
+// pass adds a field here and an entry in modules().
+struct SceneModules {
+    render_svo_cubes: RenderSvoCubes,
+    culling_debug: CullingDebug,
+    navcube: NavCube,
+}
+
+impl SceneModules {
+    fn modules(&mut self) -> [&mut dyn RenderModule; 3] {
+        [
+            &mut self.render_svo_cubes,
+            &mut self.culling_debug,
+            &mut self.navcube,
+        ]
+    }
+}
+
+fn main() {
+    // --restore FILE continues from a snapshot, see snapshot.rs
+    let args: Vec<String> = env::args().collect();
+    let snapshot = args
+        .iter()
+        .position(|arg| arg == "--restore")
+        .and_then(|i| args.get(i + 1))
+        .map(|path| ViewerSnapshot::load(Path::new(path)));
+    let args = match &snapshot {
+        Some(Ok(snapshot)) => snapshot.restore_args(&args),
+        _ => without_restore(&args),
+    };
+    if let Err(err) = init_from_args(&args) {
+        println!(
+            "{}",
+            tr_args("cli.error.language_load_failed", &[("error", &err)])
+        );
+    }
+    let snapshot = match snapshot {
+        Some(Ok(snapshot)) => {
+            if let Err(err) = set_language(&snapshot.language) {
+                println!(
+                    "{}",
+                    tr_args("cli.error.language_load_failed", &[("error", &err)])
+                );
+            }
+            Some(snapshot)
+        }
+        Some(Err(err)) => {
+            println!(
+                "{}",
+                tr_args("viewer.snapshot_load_failed", &[("error", &err)])
+            );
+            process::exit(1);
+        }
+        None => None,
+    };
+
+    // rendersvosdf batch JOBS.json renders a list of viewpoints to images, see batch.rs
+    if args.get(1).map(|arg| arg.as_str()) == Some("batch") {
+        let jobs_path = match args.get(2) {
+            Some(path) => Path::new(path),
+            None => {
+                println!("{}", tr("batch.usage"));
+                process::exit(1);
+            }
+        };
+        let time_start = Instant::now();
+        match run_batch(&args, jobs_path) {
+            Ok(count) => {
+                let seconds = time_start.elapsed().as_secs_f32();
+                println!(
+                    "{}",
+                    tr_args(
+                        "batch.rendered",
+                        &[("count", &count), ("seconds", &seconds)]
+                    )
+                );
+            }
+            Err(err) => {
+                println!("{}", tr_args("batch.error", &[("error", &err)]));
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let memory_profile = MemoryProfile::from_args(&args);
+    println!(
+        "{}",
+        tr_args(
+            "viewer.memory_profile",
+            &[("profile", &format!("{:?}", memory_profile))]
+        )
+    );
+
+    // Load sparse voxel octree SDF
+    let convert_path = args
+        .iter()
+        .position(|arg| arg == "--convert-on-load")
+        .and_then(|i| args.get(i + 1));
+    let remote_address = args
+        .iter()
+        .position(|arg| arg == "--remote")
+        .and_then(|i| args.get(i + 1));
+    let loaded = if let Some(sdf_path) = convert_path {
+        convert_on_load(&args, sdf_path).map(|svo_sdf| (svo_sdf, ChecksumVerification::none()))
+    } else if let Some(address) = remote_address {
+        load_remote(&args, address).map(|svo_sdf| (svo_sdf, ChecksumVerification::none()))
+    } else {
+        load_svo_sdf()
+    };
+    let loaded = loaded.unwrap_or_else(|err| match Cancelled::from_io(&err) {
+        Some(cancelled) => {
+            let percent = format!("{:.1}", cancelled.progress * 100.0);
+            println!(
+                "{}",
+                tr_args("viewer.conversion_cancelled", &[("percent", &percent)])
+            );
+            process::exit(130);
+        }
+        None => panic!("SVO SDF loading failed: {:?}", err),
+    });
+    let (svo_sdf, mut checksum_verification) = loaded;
+    print_svo_memory(&svo_sdf.memory_usage());
+
+    let diagonal_length = svo_sdf.header.bounds().size().length();
 
     // Window
     let window_width = 1920;
     let window_height = 1080;
 
-    let mut event_loop = EventLoop::new().unwrap();
+    let display_settings = DisplaySettings::from_args(&args);
+
+    let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new()
         .with_title("SVO SDF Vulkan Renderer")
         .with_inner_size(winit::dpi::PhysicalSize::new(
             f64::from(window_width),
             f64::from(window_height),
         ))
+        .with_fullscreen(display_settings.window_fullscreen(event_loop.primary_monitor()))
         .build(&event_loop)
         .unwrap();
 
+    // Fullscreen windows take the monitor resolution
+    let (window_width, window_height) = if display_settings.is_fullscreen() {
+        let size = window.inner_size();
+        (size.width, size.height)
+    } else {
+        (window_width, window_height)
+    };
+
     // Vulkan base initialization
-    let mut base = VulkanBase::new(&window, window_width, window_height);
+    let mut base = VulkanBase::new_with_settings(
+        &window,
+        window_width,
+        window_height,
+        memory_profile,
+        DebugSettings::from_args(&args),
+        display_settings,
+    )
+    .unwrap_or_else(|err| {
+        println!("{}", tr_args("viewer.vulkan_error", &[("error", &err)]));
+        process::exit(1);
+    });
 
     // Render passes
     let render_pass_attachments = [
@@ -99,7 +606,7 @@ fn main() {
             ..Default::default()
         },
         vk::AttachmentDescription {
-            format: vk::Format::D32_SFLOAT,
+            format: base.depth_format,
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::CLEAR,
             initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
@@ -147,86 +654,179 @@ fn main() {
     }
     .unwrap();
 
-    let framebuffers: Vec<vk::Framebuffer> = base
-        .present_image_views
-        .iter()
-        .map(|&present_image_view| {
-            let framebuffer_attachments = [present_image_view, base.depth_image_view];
-            let frame_buffer_create_info = vk::FramebufferCreateInfo {
-                render_pass,
-                attachment_count: framebuffer_attachments.len() as u32,
-                p_attachments: framebuffer_attachments.as_ptr(),
-                width: base.surface_resolution.width,
-                height: base.surface_resolution.height,
-                layers: 1,
-                ..Default::default()
-            };
-            unsafe {
-                base.device
-                    .create_framebuffer(&frame_buffer_create_info, None)
-            }
-            .unwrap()
-        })
-        .collect();
-
-    let view_scissor = {
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: base.surface_resolution.width as f32,
-            height: base.surface_resolution.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
+    let mut framebuffers = base.create_framebuffers(render_pass);
+    let mut view_scissor = base.view_scissor();
+    let mut needs_recreate = false;
+
+    // Descriptor sets of all renderer modules, pools are added as needed
+    let mut descriptor_allocator = DescriptorAllocator::new(DESCRIPTOR_SETS_PER_POOL);
+
+    // Samplers shared by all renderer modules
+    let quality_settings = QualitySettings::from_args(&args);
+    base.sampler_cache
+        .set_max_anisotropy(quality_settings.anisotropy);
+
+    // --fov, --near and --ortho, changed with hotkeys and console commands while running.
+    // --depth only on the command line, the pipelines are built for it.
+    let mut projection_settings = ProjectionSettings::from_args(&args);
+    if let Some(snapshot) = &snapshot {
+        projection_settings = ProjectionSettings {
+            depth: projection_settings.depth,
+            ..snapshot.projection
         };
-        let scissor = vk::Rect2D {
-            extent: base.surface_resolution,
-            ..Default::default()
-        };
-        VkViewScissor { viewport, scissor }
-    };
+    }
+    let depth = projection_settings.depth;
+    let console_commands = spawn_console_commands();
+
+    // SVO texture and octree data, --atlas-order linear|hilbert picks the brick placement
+    // --sparse-atlas keeps bricks at their volume position in a sparse 3D texture instead
+    let atlas_order = AtlasOrder::from_args(&args);
+    println!(
+        "{}",
+        tr_args("viewer.atlas_order", &[("order", &atlas_order.name())])
+    );
+    let sparse_atlas = args.iter().any(|arg| arg == "--sparse-atlas");
+    let mut svo_texture = SvoTexture::new(
+        &base.device,
+        &mut base.allocator,
+        &svo_sdf,
+        &base.memory_profile,
+        &base.gpu_budget,
+        &mut base.sampler_cache,
+        atlas_order,
+        (&base.instance, base.pdevice, &base.capabilities),
+        sparse_atlas,
+    );
+    if let BrickAtlas::Sparse(sparse_image) = &svo_texture.brick_atlas {
+        let mb = sparse_image.committed_bytes() as f64 / (1024.0 * 1024.0);
+        println!(
+            "{}",
+            tr_args(
+                "viewer.sparse_atlas",
+                &[
+                    ("pages", &sparse_image.committed_pages()),
+                    ("mb", &format!("{:.1}", mb)),
+                ]
+            )
+        );
+    } else if sparse_atlas {
+        println!("{}", tr("viewer.sparse_atlas_unsupported"));
+    }
+    svo_texture
+        .bind_atlas_pages(&base.device, &mut base.allocator, base.present_queue)
+        .unwrap();
 
-    // Descriptor pool
-    let descriptor_sizes = [
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_BUFFER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_IMAGE,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-    ];
-    let descriptor_pool_info = vk::DescriptorPoolCreateInfo {
-        pool_size_count: descriptor_sizes.len() as u32,
-        p_pool_sizes: descriptor_sizes.as_ptr(),
-        max_sets: NUM_DESCRIPTOR_SETS,
-        ..Default::default()
+    // --detail adds tiling noise to close-up surfaces
+    let detail_settings = DetailSettings::from_args(&args);
+    let mut detail_texture = DetailTexture::new(
+        &base.device,
+        &mut base.allocator,
+        &mut base.sampler_cache,
+        &detail_settings,
+    );
+
+    // Generate instances for visible bricks
+    // --scene FILE replaces the --instances grid and is reloaded when it changes
+    let mut scene_watcher = args
+        .iter()
+        .position(|arg| arg == "--scene")
+        .and_then(|i| args.get(i + 1))
+        .map(|path| SceneWatcher::new(Path::new(path)));
+    let mut scene_instances = match scene_watcher.as_ref().map(|watcher| watcher.load()) {
+        _ if snapshot.is_some() => snapshot.as_ref().unwrap().scene_instances.clone(),
+        Some(Ok(scene_instances)) if !scene_instances.is_empty() => scene_instances,
+        Some(Ok(_)) => {
+            println!("{}", tr("viewer.scene_empty"));
+            scene_instances_from_args(&args, diagonal_length * 1.1)
+        }
+        Some(Err(err)) => {
+            println!("{}", tr_args("viewer.scene_error", &[("error", &err)]));
+            scene_instances_from_args(&args, diagonal_length * 1.1)
+        }
+        None => scene_instances_from_args(&args, diagonal_length * 1.1),
+    };
+    // The scene's [[light]] tables light the SVO shading, culled against the view every frame
+    let mut scene_lights = match scene_watcher.as_ref().map(|watcher| watcher.load_lights()) {
+        _ if snapshot.is_some() => snapshot.as_ref().unwrap().scene_lights.clone(),
+        Some(Ok(lights)) => lights,
+        Some(Err(err)) => {
+            println!("{}", tr_args("viewer.lights_error", &[("error", &err)]));
+            Vec::new()
+        }
+        None => Vec::new(),
+    };
+    // The scene's [post] table turns on the post-processing chain
+    let post_settings = match scene_watcher
+        .as_ref()
+        .map(|watcher| watcher.load_post_settings())
+    {
+        Some(Ok(post_settings)) => post_settings,
+        Some(Err(err)) => {
+            println!("{}", tr_args("viewer.post_error", &[("error", &err)]));
+            None
+        }
+        None => None,
+    };
+    // --ao turns it on without a [post] table, AO is one of its passes
+    let post_settings = match (post_settings, quality_settings.ao) {
+        (None, AoQuality::Off) => None,
+        (post_settings, _) => Some(post_settings.unwrap_or_default()),
     };
 
-    let descriptor_pool = unsafe {
-        base.device
-            .create_descriptor_pool(&descriptor_pool_info, None)
+    // --dump-framegraph out.dot writes the pass/barrier graph for the active profile
+    if let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--dump-framegraph")
+        .and_then(|i| args.get(i + 1))
+    {
+        let enable_post = post_settings.is_some();
+        let enable_ao = quality_settings.ao != AoQuality::Off;
+        describe_frame(
+            base.memory_profile.enable_hiz,
+            ENABLE_CULLING_DEBUG,
+            enable_post,
+            enable_ao,
+        )
+        .save_dot(path)
+        .expect("Frame graph export failed");
+        println!(
+            "{}",
+            tr_args("viewer.frame_graph_written", &[("file", path)])
+        );
     }
-    .unwrap();
 
-    // SVO texture and octree data
-    let mut svo_texture = SvoTexture::new(
+    let mut instances = Instances::new(
         &base.device,
         &mut base.allocator,
         &svo_sdf,
+        &scene_instances,
+        &svo_texture.atlas_slots,
     );
+    let num_instances = instances.num_instances;
 
-    // Generate instances for visible bricks
-    let num_instances = svo_sdf.bricks.len();
-    let mut instances = Instances::new(&base.device, &mut base.allocator, &svo_sdf);
+    // Upload memory of scene reloads, each frame can copy the whole scene instance buffer
+    let mut staging_ring = StagingRing::new(
+        &base.device,
+        &mut base.allocator,
+        &base.gpu_budget,
+        instances.scene_instances_buffer.size_bytes(),
+        base.command_buffer_pool.command_buffers.len(),
+    )
+    .unwrap_or_else(|err| panic!("Staging ring allocation failed: {}", err));
+
+    // Transient per frame buffers (culling uniforms, dynamic svo cube uniforms)
+    let mut scratch = ScratchAllocator::new(
+        &base.device,
+        &mut base.allocator,
+        &base.gpu_budget,
+        64 * 1024,
+        base.command_buffer_pool.command_buffers.len(),
+        vk::BufferUsageFlags::TRANSFER_SRC
+            | vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::UNIFORM_BUFFER,
+        MemoryLocation::CpuToGpu,
+    )
+    .unwrap_or_else(|err| panic!("Scratch buffer allocation failed: {}", err));
 
     // Occlusion culling (depth pyramid)
     let pyramid_dimension = 512;
@@ -235,44 +835,143 @@ fn main() {
     let mut depth_pyramid = DepthPyramid::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
+        &mut descriptor_allocator,
         &base.depth_image_view,
         pyramid_texture_dimensions,
+        &mut base.sampler_cache,
+        depth,
     );
 
     let mut culling = Culling::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
+        &base.capabilities,
+        &mut descriptor_allocator,
         &depth_pyramid.descriptor_sample,
         &depth_pyramid.descriptor_debug_sample,
-        &instances.instances_buffer_descriptor,
+        &instances.bricks_buffer_descriptor,
+        &instances.scene_instances_buffer_descriptor,
         num_instances,
+        scene_instances.len(),
+        depth,
     );
 
+    // Post-processing, the scene renders to an HDR image instead of the swapchain
+    let mut post_chain = post_settings.map(|settings| {
+        PostChain::new(
+            &base.device,
+            &mut base.allocator,
+            &mut descriptor_allocator,
+            &base.capabilities,
+            base.command_buffer_pool.command_buffers.len(),
+            base.surface_format.format,
+            base.depth_format,
+            base.depth_image_view,
+            base.surface_resolution,
+            settings,
+            quality_settings.ao,
+            &AoScene {
+                brick_texture: &svo_texture.brick_texture_descriptor,
+                octree_buffer: &svo_texture.octree_buffer_descriptor,
+                scene_instances_buffer: &instances.scene_instances_buffer_descriptor,
+                scratch: &scratch,
+                uniforms: AoUniforms::new(&svo_sdf, &svo_texture, scene_instances.len()),
+            },
+        )
+    });
+    let scene_render_pass = post_chain
+        .as_ref()
+        .map_or(render_pass, |post_chain| post_chain.scene_render_pass);
+
     let mut culling_debug = CullingDebug::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
-        &render_pass,
+        &mut descriptor_allocator,
+        &scene_render_pass,
         &view_scissor,
         &depth_pyramid.descriptor_debug_sample,
+        depth,
     );
+    culling_debug.enabled = ENABLE_CULLING_DEBUG;
 
     // SVO Cube renderer
+    let svo_uniforms = SvoCubeUniforms::new(
+        &svo_sdf,
+        svo_texture.brick_curve,
+        instances.bricks_per_scene_instance,
+        !base.memory_profile.enable_hiz,
+    );
+
     let mut render_svo_cubes = RenderSvoCubes::new(
         &base.device,
         &mut base.allocator,
-        &descriptor_pool,
-        &render_pass,
+        &mut descriptor_allocator,
+        &scene_render_pass,
         &view_scissor,
         &svo_texture.brick_texture_descriptor,
         &svo_texture.octree_buffer_descriptor,
-        &instances.instances_buffer_descriptor,
+        &instances.bricks_buffer_descriptor,
+        &instances.scene_instances_buffer_descriptor,
         &culling.visibility_buffer_descriptor,
-        num_instances,
-        svo_sdf.brick_size,
+        &detail_texture.noise_descriptor,
+        &detail_texture.materials_descriptor,
+        &scratch,
+        &svo_uniforms,
+        depth,
+    );
+    if base.memory_profile.enable_hiz {
+        render_svo_cubes.indirect_draws = Some(culling.visibility_arguments.draws());
+    }
+    apply_visibility(
+        &culling,
+        &mut render_svo_cubes,
+        &instances,
+        &scene_instances,
+        base.memory_profile.enable_hiz,
+    );
+
+    // Camera orientation gizmo, with post-processing it draws in the output render pass
+    let mut navcube = NavCube::new(
+        &base.device,
+        &mut base.allocator,
+        &mut descriptor_allocator,
+        &render_pass,
+        &navcube_view_scissor(base.surface_resolution),
     );
+    navcube.in_scene_pass = post_chain.is_none();
+
+    let mut scene_modules = SceneModules {
+        render_svo_cubes,
+        culling_debug,
+        navcube,
+    };
+    let mut camera_snap: Option<CameraSnap> = None;
+    // File of a snapshot requested with F5 or the snapshot console command
+    let mut save_snapshot: Option<String> = None;
+    // Right drag selects instances and bricks, the marquee is resolved with the next frame's
+    // camera. Only the bricks that are rendered can be selected.
+    let selectable_bricks = visible_bricks(&svo_sdf);
+    let mut marquee_start: Option<(i32, i32)> = None;
+    let mut marquee: Option<ScreenRect> = None;
+    let mut selection = Selection::default();
+
+    // Host visible buffers written while rendering, --upload-stats prints their bytes per
+    // frame with the performance info
+    let upload_stats = args.iter().any(|arg| arg == "--upload-stats");
+    let upload_pool = MappedBufferPool::new();
+    upload_pool.track(&mut instances.bricks_buffer.buffer, "bricks");
+    upload_pool.track(
+        &mut instances.scene_instances_buffer.buffer,
+        "scene instances",
+    );
+    upload_pool.track(&mut staging_ring.buffer, "staging");
+    upload_pool.track(&mut scratch.buffer, "scratch");
+    upload_pool.track(&mut depth_pyramid.uniform_buffer, "depth pyramid uniforms");
+    upload_pool.track(
+        &mut scene_modules.culling_debug.uniform_buffer,
+        "culling debug uniforms",
+    );
+    upload_pool.track(&mut scene_modules.navcube.buffer_arena.buffer, "navcube");
 
     // Submit initialization command buffer before rendering starts
     base.record_submit_commandbuffer(
@@ -283,17 +982,40 @@ fn main() {
         &[],
         |device, command_buffer| {
             // GPU setup commands
-            render_svo_cubes.gpu_setup(device, &command_buffer);
-            svo_texture.gpu_setup(device, &command_buffer, &svo_sdf);
-            depth_pyramid.gpu_setup(device, &command_buffer);
-            culling.gpu_setup(device, &command_buffer);
+            for module in scene_modules.modules() {
+                module.create(device, &base.barrier_api, command_buffer);
+            }
+            svo_texture.gpu_setup(device, &base.barrier_api, &command_buffer, &svo_sdf);
+            detail_texture.gpu_setup(device, &base.barrier_api, &command_buffer);
+            if base.memory_profile.enable_hiz {
+                depth_pyramid.gpu_setup(device, &command_buffer);
+                culling.gpu_setup(device, &command_buffer);
+            }
         },
-    );
+    )
+    .expect("Setup command buffer submit failed");
+
+    if args.iter().any(|arg| arg == "--mem-stats") {
+        println!("{}", base.memory_report());
+    }
+    if args.iter().any(|arg| arg == "--fragmentation-report") {
+        println!("{}", base.allocator.fragmentation_report());
+    }
 
-    // Camera
+    // Camera, the orientation rotates +Z to the view direction
     struct Camera {
         position: Vec3,
-        direction: Vec3,
+        orientation: Quat,
+    }
+
+    impl Camera {
+        fn direction(&self) -> Vec3 {
+            self.orientation.rotate_vec3(Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            })
+        }
     }
 
     let mut camera = Camera {
@@ -302,12 +1024,32 @@ fn main() {
             y: 25.0,
             z: 50.0,
         },
-        direction: Vec3 {
-            x: 0.0,
-            y: -0.5,
-            z: -1.0,
-        },
+        orientation: Quat::look_rotation(
+            Vec3 {
+                x: 0.0,
+                y: -0.5,
+                z: -1.0,
+            },
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ),
     };
+    if let Some(snapshot) = &snapshot {
+        camera.position = snapshot.camera_position;
+        camera.orientation = snapshot.camera_orientation;
+        println!("{}", tr("viewer.snapshot_restored"));
+    }
+
+    let input_settings = InputSettings::from_args(&args).unwrap_or_else(|err| {
+        println!(
+            "{}",
+            tr_args("viewer.input_settings_failed", &[("error", &err)])
+        );
+        InputSettings::default()
+    });
 
     // Inputs
     #[derive(Clone, Copy)]
@@ -315,6 +1057,7 @@ fn main() {
         is_left_clicked: bool,
         cursor_position: (i32, i32),
         wheel_delta: f32,
+        raw_mouse_delta: (f32, f32),
         keyboard_forward: i32,
         keyboard_side: i32,
     }
@@ -325,6 +1068,7 @@ fn main() {
                 is_left_clicked: false,
                 cursor_position: (0, 0),
                 wheel_delta: 0.0,
+                raw_mouse_delta: (0.0, 0.0),
                 keyboard_forward: 0,
                 keyboard_side: 0,
             }
@@ -332,21 +1076,36 @@ fn main() {
     }
 
     // Window event loop
-    println!("Start window event loop");
+    println!("{}", tr("viewer.start_event_loop"));
 
     let mut inputs_prev: Inputs = Default::default();
     let mut inputs: Inputs = Default::default();
+    let mut mouse_look = MouseLook::new();
+    let mut time_prev_frame = Instant::now();
 
     let mut time_start = Instant::now();
     let mut frame = 0u32;
+    // Vulkan object counts once the first frames created their lazily made resources
+    let mut object_baseline: Option<(u32, ObjectCounts)> = None;
     let mut active_command_buffer = 0;
 
+    // --parallel-recording records the main pass renderers into secondary command buffers
+    // on worker threads (one worker per renderer)
+    let parallel_recording = args.iter().any(|arg| arg == "--parallel-recording");
+    let secondary_command_buffers = base.create_secondary_command_buffers(3);
+
+    // The HiZ passes are recorded into their own command buffer. Both are submitted with one
+    // vkQueueSubmit, --no-submit-batching submits them separately to compare the CPU cost
+    let submit_batching = !args.iter().any(|arg| arg == "--no-submit-batching");
+    let mut submit_batch = SubmitBatch::new();
+
     let _ = event_loop.run(|event, event_loop_window_target| {
         event_loop_window_target.set_control_flow(winit::event_loop::ControlFlow::Poll);
 
         match event {
             Event::NewEvents(_) => {
                 inputs.wheel_delta = 0.0;
+                inputs.raw_mouse_delta = (0.0, 0.0);
             }
 
             Event::AboutToWait => {
@@ -358,109 +1117,352 @@ fn main() {
                 inputs_prev = inputs;
 
                 // Update camera based in inputs
-                let view_rot = view(
-                    Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 0.0,
-                    },
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                );
-
                 let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
-                camera.position = camera.position + camera.direction * forward_speed;
+                camera.position = camera.position + camera.direction() * forward_speed;
 
                 let side_speed = inputs.keyboard_side as f32 * 1.5;
-                let side_vec = Vec3 {
-                    x: view_rot.r0.x,
-                    y: view_rot.r1.x,
-                    z: view_rot.r2.x,
-                };
+                let side_vec = camera.orientation.rotate_vec3(Vec3 {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                });
                 camera.position = camera.position + side_vec * side_speed;
 
-                if inputs.is_left_clicked {
-                    let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
-                        * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
+                // Raw mouse deltas bypass OS pointer acceleration
+                let now = Instant::now();
+                let frame_seconds = (now - time_prev_frame).as_secs_f32();
+                time_prev_frame = now;
+                let mouse_delta = if !inputs.is_left_clicked {
+                    (0.0, 0.0)
+                } else if input_settings.raw_mouse {
+                    inputs.raw_mouse_delta
+                } else {
+                    (cursor_delta.0 as f32, cursor_delta.1 as f32)
+                };
+                let (yaw, pitch) = mouse_look.update(&input_settings, mouse_delta, frame_seconds);
+                if yaw != 0.0 || pitch != 0.0 {
+                    camera.orientation = camera.orientation.add_yaw_pitch(yaw, pitch);
+                }
 
-                    let rot = rot * inverse(view_rot);
+                if let Some(snap) = &camera_snap {
+                    let (orientation, finished) = snap.orientation(Instant::now());
+                    camera.orientation = orientation;
+                    if finished {
+                        camera_snap = None;
+                    }
+                }
 
-                    camera.direction = Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 1.0,
-                    } * rot;
+                // Recreate swapchain after resize or OUT_OF_DATE, skip rendering while minimized
+                if needs_recreate {
+                    let size = window.inner_size();
+                    if size.width == 0 || size.height == 0 {
+                        return;
+                    }
 
-                    camera.direction = camera.direction.normalize();
+                    let recreated = base.recreate_swapchain(size.width, size.height);
+                    if check_vulkan(recreated, event_loop_window_target).is_none() {
+                        return;
+                    }
+                    for framebuffer in framebuffers.drain(..) {
+                        base.defer_destroy(Deletion::Framebuffer(framebuffer));
+                    }
+                    framebuffers = base.create_framebuffers(render_pass);
+                    view_scissor = base.view_scissor();
+                    for module in scene_modules.modules() {
+                        module.resize(
+                            &base.device,
+                            &mut base.allocator,
+                            base.surface_resolution,
+                            base.depth_image_view,
+                        );
+                    }
+                    depth_pyramid.set_depth_view(&base.device, &base.depth_image_view);
+                    if let Some(post_chain) = post_chain.as_mut() {
+                        post_chain.resize(
+                            &base.device,
+                            &mut base.allocator,
+                            base.depth_image_view,
+                            base.surface_resolution,
+                        );
+                    }
+                    needs_recreate = false;
                 }
 
-                // Render
-                let (present_index, _) = unsafe {
-                    base.swapchain_loader.acquire_next_image(
-                        base.swapchain,
-                        std::u64::MAX,
-                        base.present_complete_semaphore,
-                        vk::Fence::null(),
-                    )
+                // Corrupt bricks are reported once the background verification has finished
+                for mismatch in checksum_verification.poll().unwrap_or_default() {
+                    let position = mismatch.position;
+                    let voxel = format!("{} {} {}", position.x, position.y, position.z);
+                    println!(
+                        "{}",
+                        tr_args(
+                            "viewer.corrupt_brick",
+                            &[("index", &mismatch.index), ("voxel", &voxel)]
+                        )
+                    );
                 }
-                .unwrap();
 
-                // Update uniform buffer
-                let color = Vec4 {
-                    x: 1.0,
-                    y: 0.1,
-                    z: 0.0,
-                    w: 0.0,
+                // Apply scene file changes, the new instance data is copied at the start of
+                // this frame's command buffer
+                match scene_watcher.as_mut().and_then(|watcher| watcher.poll()) {
+                    Some(Ok(reloaded)) => {
+                        let watcher = scene_watcher.as_ref().unwrap();
+                        match (watcher.load_post_settings(), post_chain.as_mut()) {
+                            (Ok(Some(settings)), Some(post_chain)) => {
+                                post_chain.settings = settings
+                            }
+                            (Ok(None), Some(post_chain)) if post_chain.ao != AoQuality::Off => {
+                                post_chain.settings = PostSettings::default()
+                            }
+                            (Ok(None), None) => {}
+                            (Ok(_), _) => println!("{}", tr("viewer.post_toggled")),
+                            (Err(err), _) => {
+                                println!("{}", tr_args("viewer.post_error", &[("error", &err)]))
+                            }
+                        }
+                        match watcher.load_lights() {
+                            Ok(lights) => scene_lights = lights,
+                            Err(err) => {
+                                println!("{}", tr_args("viewer.lights_error", &[("error", &err)]))
+                            }
+                        }
+                        let diff = SceneDiff::new(&scene_instances, &reloaded);
+                        if !diff.is_incremental() {
+                            println!(
+                                "{}",
+                                tr_args(
+                                    "viewer.scene_count_changed",
+                                    &[("old", &scene_instances.len()), ("new", &reloaded.len())]
+                                )
+                            );
+                        } else if !diff.is_empty() {
+                            instances.update(&reloaded);
+                            scene_instances = reloaded;
+                            apply_visibility(
+                                &culling,
+                                &mut scene_modules.render_svo_cubes,
+                                &instances,
+                                &scene_instances,
+                                base.memory_profile.enable_hiz,
+                            );
+                            println!(
+                                "{}",
+                                tr_args(
+                                    "viewer.scene_reloaded",
+                                    &[
+                                        ("moved", &diff.moved),
+                                        ("materials", &diff.material_changed)
+                                    ]
+                                )
+                            );
+                        }
+                    }
+                    Some(Err(err)) => {
+                        println!("{}", tr_args("viewer.scene_error", &[("error", &err)]));
+                    }
+                    None => {}
+                }
+
+                // Destroy resources replaced in earlier frames once the GPU is done with them
+                let collected = base.collect_deletions();
+                if check_vulkan(collected, event_loop_window_target).is_none() {
+                    return;
+                }
+
+                // With --device-group consecutive frames render on alternating GPUs. Instance
+                // uploads must reach the memory of every GPU, so those frames run on all.
+                if instances.pending_upload.is_some() {
+                    base.begin_replicated_frame();
+                } else {
+                    base.begin_afr_frame();
+                }
+
+                // Render
+                let acquired = base.acquire_next_image();
+                let present_index = match check_vulkan(acquired, event_loop_window_target) {
+                    Some(FrameResult::Ok(present_index)) => present_index,
+                    Some(FrameResult::NeedsRecreate) => {
+                        needs_recreate = true;
+                        return;
+                    }
+                    None => return,
                 };
 
+                // Console commands: fov DEGREES, near DISTANCE, ortho [HEIGHT], perspective,
+                // snapshot [FILE], stats objects (live Vulkan objects per type), and hide,
+                // show, isolate, colorize MATERIAL, stats, select clear on the marquee
+                // selection, show all
+                while let Ok(line) = console_commands.try_recv() {
+                    if line.split_whitespace().eq(["stats", "objects"]) {
+                        print_object_counts(&ObjectCounts::current());
+                        continue;
+                    }
+                    if let Some(path) = snapshot_command(&line) {
+                        save_snapshot = Some(path.to_string());
+                        continue;
+                    }
+                    match selection_command(&line) {
+                        Some(Ok(SelectionCommand::Stats)) => {
+                            print_selection_stats(&selection.stats(&svo_sdf, &scene_instances));
+                            continue;
+                        }
+                        Some(Ok(SelectionCommand::Clear)) => {
+                            selection = Selection::default();
+                            println!("{}", tr("viewer.selection_cleared"));
+                            continue;
+                        }
+                        Some(Ok(command))
+                            if selection.is_empty() && command != SelectionCommand::ShowAll =>
+                        {
+                            println!("{}", tr("viewer.selection_empty"));
+                            continue;
+                        }
+                        Some(Ok(command @ SelectionCommand::Colorize(_))) => {
+                            selection.edit(command, &mut scene_instances);
+                            instances.update(&scene_instances);
+                            let count = selection.instances.len();
+                            println!(
+                                "{}",
+                                tr_args("viewer.selection_edited", &[("instances", &count)])
+                            );
+                            continue;
+                        }
+                        Some(Ok(command)) => {
+                            selection.edit(command, &mut scene_instances);
+                            let mask = apply_visibility(
+                                &culling,
+                                &mut scene_modules.render_svo_cubes,
+                                &instances,
+                                &scene_instances,
+                                base.memory_profile.enable_hiz,
+                            );
+                            println!(
+                                "{}",
+                                tr_args(
+                                    "viewer.instances_visible",
+                                    &[
+                                        ("visible", &mask.visible_count()),
+                                        ("instances", &scene_instances.len())
+                                    ]
+                                )
+                            );
+                            continue;
+                        }
+                        Some(Err(err)) => {
+                            println!("{}", tr_args("viewer.console_error", &[("error", &err)]));
+                            continue;
+                        }
+                        None => {}
+                    }
+                    match projection_settings.apply_command(&line) {
+                        Ok(()) => print_projection(&projection_settings),
+                        Err(err) => {
+                            println!("{}", tr_args("viewer.console_error", &[("error", &err)]))
+                        }
+                    }
+                }
+                if let Some(path) = save_snapshot.take() {
+                    let snapshot = ViewerSnapshot {
+                        args: args[1..].to_vec(),
+                        language: language(),
+                        camera_position: camera.position,
+                        camera_orientation: camera.orientation,
+                        projection: projection_settings,
+                        scene_instances: scene_instances.clone(),
+                        scene_lights: scene_lights.clone(),
+                    };
+                    match snapshot.save(Path::new(&path)) {
+                        Ok(()) => {
+                            println!("{}", tr_args("viewer.snapshot_saved", &[("file", &path)]))
+                        }
+                        Err(err) => println!(
+                            "{}",
+                            tr_args("viewer.snapshot_save_failed", &[("error", &err)])
+                        ),
+                    }
+                }
+
+                // Update uniform buffers and push constants
+                let aspect =
+                    base.surface_resolution.width as f32 / base.surface_resolution.height as f32;
                 let world_to_screen = view(
                     camera.position,
-                    camera.direction,
+                    camera.direction(),
                     Vec3 {
                         x: 0.0,
                         y: 1.0,
                         z: 0.0,
                     },
-                ) * projection(
-                    std::f32::consts::PI / 2.0,
-                    window_width as f32 / window_height as f32,
-                    1.0,
-                    10000000.0,
-                );
+                ) * projection_settings.matrix(aspect);
+
+                if let Some(marquee) = marquee.take() {
+                    selection = Selection::from_marquee(
+                        &svo_sdf,
+                        &selectable_bricks,
+                        &scene_instances,
+                        &world_to_screen,
+                        &marquee,
+                    );
+                    println!(
+                        "{}",
+                        tr_args(
+                            "viewer.selected",
+                            &[
+                                ("instances", &selection.instances.len()),
+                                ("bricks", &selection.bricks.len())
+                            ]
+                        )
+                    );
+                }
 
-                let svo_uniforms = SvoCubeUniforms {
+                let ray_origin =
+                    projection_settings.ray_origin(camera.position, camera.direction());
+                let svo_push_constants = SvoCubePushConstants {
                     world_to_screen,
-                    color,
-                    camera_position: camera.position.to_4d(),
-                    volume_scale: volume_scale.to_4d(),
-                    center_to_edge: center_to_edge.to_4d(),
-                    texel_scale: texel_scale.to_4d(),
-                    brick_size: svo_sdf.brick_size,
+                    camera_position: ray_origin.to_4d(),
                 };
 
                 let pyramid_uniforms = DepthPyramidUniforms {
-                    depth_buffer_dimensions: (window_width, window_height),
+                    depth_buffer_dimensions: [
+                        base.surface_resolution.width,
+                        base.surface_resolution.height,
+                    ],
                     depth_pyramid_dimension: pyramid_dimension,
                 };
 
                 let culling_uniforms = CullingUniforms {
                     world_to_screen,
                     depth_pyramid_dimension: pyramid_dimension,
+                    bricks_per_scene_instance: instances.bricks_per_scene_instance as u32,
+                    max_visible_bricks: culling.max_visible_bricks as u32,
+                    _padding: 0,
                 };
 
                 let culling_debug_uniforms = CullingDebugUniforms {
                     depth_pyramid_dimension: pyramid_dimension,
                 };
 
-                render_svo_cubes.update(&svo_uniforms);
+                let frame_context = FrameContext {
+                    resolution: base.surface_resolution,
+                    view_scissor,
+                    world_to_screen,
+                    camera_position: camera.position,
+                    camera_direction: camera.direction(),
+                    scratch: None,
+                };
+
+                let svo_lights = cull_lights(&scene_lights, &world_to_screen, camera.position);
+                let render_svo_cubes = &mut scene_modules.render_svo_cubes;
+                render_svo_cubes.update(&svo_push_constants);
+                render_svo_cubes.update_lights(&svo_lights);
+                for module in scene_modules.modules() {
+                    module.update(&frame_context);
+                }
                 depth_pyramid.update(&pyramid_uniforms);
-                culling.update(&culling_uniforms);
+                if let Some(post_chain) = post_chain.as_mut() {
+                    post_chain.update(&projection_settings, &world_to_screen, &svo_lights);
+                }
                 if ENABLE_CULLING_DEBUG {
-                    culling_debug.update(&culling_debug_uniforms);
+                    scene_modules.culling_debug.update(&culling_debug_uniforms);
                 }
 
                 // Setup render passs
@@ -472,15 +1474,20 @@ fn main() {
                     },
                     vk::ClearValue {
                         depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 0.0,
+                            depth: depth.clear_depth(),
                             stencil: 0,
                         },
                     },
                 ];
 
+                let present_framebuffer = framebuffers[present_index as usize];
                 let render_pass_begin_info = vk::RenderPassBeginInfo {
-                    render_pass,
-                    framebuffer: framebuffers[present_index as usize],
+                    render_pass: scene_render_pass,
+                    framebuffer: post_chain
+                        .as_ref()
+                        .map_or(present_framebuffer, |post_chain| {
+                            post_chain.scene_framebuffer()
+                        }),
                     render_area: vk::Rect2D {
                         offset: vk::Offset2D { x: 0, y: 0 },
                         extent: base.surface_resolution,
@@ -490,84 +1497,219 @@ fn main() {
                     ..Default::default()
                 };
 
-                // Submit main command buffer
-                active_command_buffer = base.record_submit_commandbuffer(
+                let subpass_contents = if parallel_recording {
+                    vk::SubpassContents::SECONDARY_COMMAND_BUFFERS
+                } else {
+                    vk::SubpassContents::INLINE
+                };
+
+                // Record main command buffer
+                let frame_command_buffer = active_command_buffer;
+                let recorded = base.record_commandbuffer(
+                    &mut submit_batch,
                     active_command_buffer,
-                    base.present_queue,
                     &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
                     &[base.present_complete_semaphore],
                     &[base.rendering_complete_semaphore],
                     |device, command_buffer| {
                         // Draw/setup (before main render pass)
-                        render_svo_cubes.gpu_draw(device, &command_buffer);
-                        culling_debug.gpu_draw(device, &command_buffer);
+                        staging_ring.begin_frame(frame_command_buffer);
+                        scratch.begin_frame(frame_command_buffer);
+                        let uploaded = instances.gpu_upload(
+                            device,
+                            &base.barrier_api,
+                            &command_buffer,
+                            &mut staging_ring,
+                        );
+                        uploaded.expect("Instance upload doesn't fit the staging ring");
+                        for module in scene_modules.modules() {
+                            module.record_pre_pass(
+                                device,
+                                &base.barrier_api,
+                                command_buffer,
+                                &frame_context,
+                            );
+                        }
 
                         // Render pass
                         unsafe {
                             device.cmd_begin_render_pass(
                                 command_buffer,
                                 &render_pass_begin_info,
-                                vk::SubpassContents::INLINE,
+                                subpass_contents,
                             );
-                            device.cmd_set_viewport(command_buffer, 0, &[view_scissor.viewport]);
-                            device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
                         }
 
-                        // Draw (main render pass)
-                        render_svo_cubes.gpu_draw_main_render_pass(
-                            device,
-                            &command_buffer,
-                            Some(&culling.visibility_arguments.buffer),
-                        );
-                        if ENABLE_CULLING_DEBUG {
-                            culling_debug.gpu_draw_main_render_pass(device, &command_buffer);
+                        // Draw (main render pass), dynamic state isn't inherited so every draw
+                        // sets its own
+                        let mut frame_context = FrameContext {
+                            scratch: Some(&mut scratch),
+                            ..frame_context
+                        };
+                        let draws: Vec<MainPassDraw> = scene_modules
+                            .modules()
+                            .iter_mut()
+                            .filter_map(|module| module.record_main_pass(&mut frame_context))
+                            .collect();
+                        if parallel_recording {
+                            base.record_parallel(
+                                command_buffer,
+                                &secondary_command_buffers,
+                                frame_command_buffer,
+                                scene_render_pass,
+                                0,
+                                render_pass_begin_info.framebuffer,
+                                &draws,
+                            );
+                        } else {
+                            for draw in &draws {
+                                draw(device, command_buffer);
+                            }
                         }
 
                         unsafe {
                             device.cmd_end_render_pass(command_buffer);
                         }
 
-                        // Draw/setup (after main render pass)
-                        depth_pyramid.gpu_draw(
-                            device,
-                            &command_buffer,
-                            &base.depth_image.image,
-                            pyramid_dimension,
-                            pyramid_mips,
-                        );
-                        culling.gpu_draw(
-                            device,
-                            &command_buffer,
-                            &depth_pyramid.image.image,
-                            &depth_pyramid.image_debug.image,
-                            num_instances as u32,
-                        );
+                        // Post-processing (effect passes, then tonemap to the swapchain image)
+                        if let Some(post_chain) = post_chain.as_mut() {
+                            post_chain.gpu_draw(
+                                device,
+                                &base.barrier_api,
+                                &command_buffer,
+                                &mut scratch,
+                                &base.depth_image.image,
+                                depth_aspect_mask(base.depth_format),
+                                frame_command_buffer,
+                            );
+                            let output_begin_info = vk::RenderPassBeginInfo {
+                                render_pass: post_chain.output_render_pass,
+                                framebuffer: present_framebuffer,
+                                render_area: render_pass_begin_info.render_area,
+                                ..Default::default()
+                            };
+                            unsafe {
+                                device.cmd_begin_render_pass(
+                                    command_buffer,
+                                    &output_begin_info,
+                                    vk::SubpassContents::INLINE,
+                                );
+                            }
+                            view_scissor.set(device, command_buffer);
+                            post_chain.gpu_draw_output_render_pass(device, &command_buffer);
+                            scene_modules
+                                .navcube
+                                .gpu_draw_main_render_pass(device, &command_buffer);
+                            unsafe {
+                                device.cmd_end_render_pass(command_buffer);
+                            }
+                        }
                     },
                 );
-
-                // Present frame
-                let present_info = vk::PresentInfoKHR {
-                    wait_semaphore_count: 1,
-                    p_wait_semaphores: &base.rendering_complete_semaphore,
-                    swapchain_count: 1,
-                    p_swapchains: &base.swapchain,
-                    p_image_indices: &present_index,
-                    ..Default::default()
+                active_command_buffer = match check_vulkan(recorded, event_loop_window_target) {
+                    Some(next_command_buffer) => next_command_buffer,
+                    None => return,
                 };
+                if !submit_batching {
+                    let submitted = base.submit_batch(&mut submit_batch, base.present_queue);
+                    if check_vulkan(submitted, event_loop_window_target).is_none() {
+                        return;
+                    }
+                }
 
-                unsafe {
-                    base.swapchain_loader
-                        .queue_present(base.present_queue, &present_info)
+                // Draw/setup (after main render pass)
+                if base.memory_profile.enable_hiz {
+                    let recorded = base.record_commandbuffer(
+                        &mut submit_batch,
+                        active_command_buffer,
+                        &[],
+                        &[],
+                        &[],
+                        |device, command_buffer| {
+                            depth_pyramid.gpu_draw(
+                                device,
+                                &base.barrier_api,
+                                &command_buffer,
+                                &base.depth_image.image,
+                                depth_aspect_mask(base.depth_format),
+                                pyramid_dimension,
+                                pyramid_mips,
+                            );
+                            culling.gpu_draw(
+                                device,
+                                &base.barrier_api,
+                                &command_buffer,
+                                &mut scratch,
+                                &culling_uniforms,
+                                &depth_pyramid.image.image,
+                                &depth_pyramid.image_debug.image,
+                                num_instances as u32,
+                            );
+                        },
+                    );
+                    active_command_buffer = match check_vulkan(recorded, event_loop_window_target) {
+                        Some(next_command_buffer) => next_command_buffer,
+                        None => return,
+                    };
+                }
+
+                let submitted = base.submit_batch(&mut submit_batch, base.present_queue);
+                if check_vulkan(submitted, event_loop_window_target).is_none() {
+                    return;
+                }
+
+                // Present frame
+                match check_vulkan(base.present(present_index), event_loop_window_target) {
+                    Some(FrameResult::NeedsRecreate) => needs_recreate = true,
+                    Some(FrameResult::Ok(_)) | None => {}
                 }
-                .unwrap();
 
                 // Output performance info every 60 frames
+                upload_pool.end_frame();
                 frame += 1;
-                if (frame % 60) == 0 {
+                if frame.is_multiple_of(60) {
                     let time_now = Instant::now();
                     let interval = (time_now - time_start).as_millis();
-                    println!("Average frame time: {} ms", interval as f32 / 60.0f32);
-                    println!("Rendering {} bricks", num_instances);
+                    let frame_time = interval as f32 / 60.0f32;
+                    object_baseline.get_or_insert_with(|| (frame, ObjectCounts::current()));
+                    println!(
+                        "{}",
+                        tr_args("viewer.average_frame_time", &[("ms", &frame_time)])
+                    );
+                    let upload_report = upload_pool.take_report();
+                    if upload_stats {
+                        print_upload_report(&upload_report);
+                    }
+                    println!(
+                        "{}",
+                        tr_args(
+                            "viewer.rendering_bricks",
+                            &[
+                                ("bricks", &num_instances),
+                                ("instances", &scene_instances.len())
+                            ]
+                        )
+                    );
+                    let submit_stats = base.take_submit_stats();
+                    let submit_us = submit_stats.submit_time.as_secs_f32() * 1.0e6 / 60.0;
+                    println!(
+                        "{}",
+                        tr_args(
+                            "viewer.submit_stats",
+                            &[
+                                ("submits", &(submit_stats.submits as f32 / 60.0)),
+                                ("us", &format!("{:.1}", submit_us))
+                            ]
+                        )
+                    );
+                    if let Some(ao_ms) = post_chain.as_ref().and_then(|chain| chain.ao_gpu_ms()) {
+                        let quality = quality_settings.ao.name();
+                        let ms = format!("{:.3}", ao_ms);
+                        println!(
+                            "{}",
+                            tr_args("viewer.ao_gpu_time", &[("quality", &quality), ("ms", &ms)])
+                        );
+                    }
 
                     time_start = time_now;
                 }
@@ -576,13 +1718,12 @@ fn main() {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => event_loop_window_target.exit(),
 
-                // TODO: Handle swapchain resize
-                WindowEvent::Resized { .. } => {}
+                WindowEvent::Resized { .. } => needs_recreate = true,
 
                 // Keyboard
                 WindowEvent::KeyboardInput { event, .. } => {
                     let pressed = event.state == ElementState::Pressed;
-                    use winit::keyboard::Key;
+                    use winit::keyboard::{Key, NamedKey};
                     match &event.logical_key {
                         Key::Character(c) if c.as_str() == "w" || c.as_str() == "W" => {
                             inputs.keyboard_forward = if pressed { 1 } else { 0 };
@@ -596,6 +1737,32 @@ fn main() {
                         Key::Character(c) if c.as_str() == "a" || c.as_str() == "A" => {
                             inputs.keyboard_side = if pressed { -1 } else { 0 };
                         }
+                        // +/- = zoom, P = projection type, [ and ] = near plane
+                        Key::Character(c) if pressed && PROJECTION_KEYS.contains(&c.as_str()) => {
+                            projection_settings.apply_key(c.as_str());
+                            print_projection(&projection_settings);
+                        }
+                        // F5 = save a snapshot of the viewer state
+                        Key::Named(NamedKey::F5) if pressed => {
+                            save_snapshot = Some(DEFAULT_SNAPSHOT_FILE.to_string());
+                        }
+                        // L = switch console language
+                        Key::Character(c)
+                            if pressed && (c.as_str() == "l" || c.as_str() == "L") =>
+                        {
+                            match next_language() {
+                                Ok(language) => {
+                                    println!(
+                                        "{}",
+                                        tr_args("viewer.language", &[("language", &language)])
+                                    )
+                                }
+                                Err(err) => println!(
+                                    "{}",
+                                    tr_args("cli.error.language_load_failed", &[("error", &err)])
+                                ),
+                            }
+                        }
                         _ => {}
                     }
                 }
@@ -606,8 +1773,40 @@ fn main() {
                     state,
                     ..
                 } => {
-                    inputs.is_left_clicked = state == ElementState::Pressed;
+                    // Clicking a navcube face snaps the camera to look at it
+                    let pressed = state == ElementState::Pressed;
+                    let face = if pressed {
+                        let viewport = &scene_modules.navcube.viewport;
+                        navcube_pick(viewport, inputs.cursor_position, camera.direction())
+                    } else {
+                        None
+                    };
+                    match face {
+                        Some(face_normal) => {
+                            mouse_look.reset();
+                            camera_snap = Some(CameraSnap::new(camera.orientation, face_normal));
+                        }
+                        None => inputs.is_left_clicked = pressed,
+                    }
                 }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Right,
+                    state,
+                    ..
+                } => match state {
+                    ElementState::Pressed => marquee_start = Some(inputs.cursor_position),
+                    ElementState::Released => {
+                        if let Some(start) = marquee_start.take() {
+                            let resolution = base.surface_resolution;
+                            marquee = Some(ScreenRect::from_pixels(
+                                start,
+                                inputs.cursor_position,
+                                resolution.width,
+                                resolution.height,
+                            ));
+                        }
+                    }
+                },
                 WindowEvent::CursorMoved { position, .. } => {
                     let position: (i32, i32) = position.into();
                     inputs.cursor_position = position;
@@ -621,27 +1820,61 @@ fn main() {
                 _ => (),
             },
 
-            Event::LoopExiting => unsafe { base.device.device_wait_idle() }.unwrap(),
+            // Ignore errors, the loop also exits after a lost device
+            Event::LoopExiting => unsafe { base.device.device_wait_idle() }.unwrap_or(()),
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                inputs.raw_mouse_delta.0 += delta.0 as f32;
+                inputs.raw_mouse_delta.1 += delta.1 as f32;
+            }
+
             _ => (),
         }
     });
 
-    println!("End window event loop");
+    println!("{}", tr("viewer.end_event_loop"));
 
-    unsafe { base.device.device_wait_idle() }.unwrap();
+    unsafe { base.device.device_wait_idle() }.unwrap_or(());
+    print_object_report(&ObjectCounts::current(), object_baseline.as_ref());
 
     // Cleanup
-    culling_debug.destroy(&base.device, &mut base.allocator);
+    secondary_command_buffers.destroy(&base.device);
+    for module in scene_modules.modules() {
+        module.destroy(&base.device, &mut base.allocator);
+    }
     culling.destroy(&base.device, &mut base.allocator);
     instances.destroy(&base.device, &mut base.allocator);
-    render_svo_cubes.destroy(&base.device, &mut base.allocator);
+    staging_ring.destroy(&base.device, &mut base.allocator);
+    scratch.destroy(&base.device, &mut base.allocator);
     svo_texture.destroy(&base.device, &mut base.allocator);
+    detail_texture.destroy(&base.device, &mut base.allocator);
     depth_pyramid.destroy(&base.device, &mut base.allocator);
+    if let Some(post_chain) = post_chain.as_mut() {
+        post_chain.destroy(&base.device, &mut base.allocator);
+    }
+    descriptor_allocator.destroy(&base.device);
     unsafe {
-        base.device.destroy_descriptor_pool(descriptor_pool, None);
         for framebuffer in framebuffers {
             base.device.destroy_framebuffer(framebuffer, None);
         }
         base.device.destroy_render_pass(render_pass, None);
     }
-}
\ No newline at end of file
+
+    // --alloc-timeline out.csv writes every allocate/free with the allocator occupancy after it
+    if let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--alloc-timeline")
+        .and_then(|i| args.get(i + 1))
+    {
+        base.allocator
+            .telemetry
+            .write_timeline_csv(Path::new(path))
+            .expect("Allocation timeline export failed");
+        println!(
+            "{}",
+            tr_args("viewer.alloc_timeline_written", &[("file", path)])
+        );
+    }
+}
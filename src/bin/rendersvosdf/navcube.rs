@@ -0,0 +1,652 @@
+// Camera orientation cube drawn in its own viewport in the top right corner. Reuses the
+// rendersdf cube shaders: simple.frag colors the cube by position, so the +X faces are
+// red, +Y green and +Z blue.
+const NAVCUBE_SIZE: u32 = 128;
+const NAVCUBE_MARGIN: u32 = 16;
+const NAVCUBE_DISTANCE: f32 = 4.5;
+const SNAP_SECONDS: f32 = 0.3;
+
+use std::default::Default;
+use std::ffi::CString;
+use std::io::Cursor;
+use std::mem;
+use std::time::Instant;
+
+use ash::util::*;
+use ash::{vk, Device};
+
+use gpu_allocator::MemoryLocation;
+
+use crate::allocator_telemetry::*;
+use crate::color::*;
+use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::render_module::*;
+use crate::vulkan_base::*;
+use crate::vulkan_helpers::*;
+
+// Matches the UBO in main.vert
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct NavCubeUniforms {
+    pub world_to_screen: Mat4x4,
+    pub color: ColorRgba,
+    pub camera_position: Vec4,
+    pub volume_scale: Vec4,
+    pub center_to_edge: Vec4,
+    pub texel_scale: Vec4,
+}
+
+unsafe impl Zeroable for NavCubeUniforms {}
+unsafe impl Pod for NavCubeUniforms {}
+
+const UP: Vec3 = Vec3 {
+    x: 0.0,
+    y: 1.0,
+    z: 0.0,
+};
+
+// The navcube camera orbits the origin looking along the main camera direction
+fn navcube_view(direction: Vec3) -> Mat4x4 {
+    Mat4x4::look_at(-direction * NAVCUBE_DISTANCE, Vec3::from_scalar(0.0), UP)
+}
+
+fn navcube_projection() -> Mat4x4 {
+    projection(std::f32::consts::PI / 4.0, 1.0, 0.1, 100.0)
+}
+
+pub fn navcube_view_scissor(surface_resolution: vk::Extent2D) -> VkViewScissor {
+    VkViewScissor::top_right(surface_resolution, NAVCUBE_SIZE, NAVCUBE_MARGIN)
+}
+
+// Returns the outward normal of the navcube face under the cursor
+pub fn navcube_pick(
+    view_scissor: &VkViewScissor,
+    cursor: (i32, i32),
+    direction: Vec3,
+) -> Option<Vec3> {
+    if !view_scissor.contains(cursor.0, cursor.1) {
+        return None;
+    }
+
+    let viewport = view_scissor.viewport;
+    let ndc_x = (cursor.0 as f32 + 0.5 - viewport.x) / viewport.width * 2.0 - 1.0;
+    let ndc_y = (cursor.1 as f32 + 0.5 - viewport.y) / viewport.height * 2.0 - 1.0;
+
+    // navcube_projection is a reversed depth projection whatever --depth selects
+    let world_to_screen = navcube_view(direction) * navcube_projection();
+    let ray = Ray::from_screen(
+        &world_to_screen,
+        Vec2 { x: ndc_x, y: ndc_y },
+        DepthConvention::Reversed.near_depth(),
+        DepthConvention::Reversed.clear_depth(),
+    );
+
+    // The entry face of the [-1, 1] cube is the clicked face
+    let cube = Aabb::from_center_half_size(Vec3::from_scalar(0.0), Vec3::from_scalar(1.0));
+    ray.intersect_aabb(&cube).map(|hit| hit.normal)
+}
+
+// Rotates the camera orientation to look at a clicked navcube face. The camera stays in
+// place, there is no orbit pivot in the viewer.
+pub struct CameraSnap {
+    from: Quat,
+    to: Quat,
+    start: Instant,
+}
+
+impl CameraSnap {
+    pub fn new(from: Quat, face_normal: Vec3) -> CameraSnap {
+        // look_rotation needs a direction that isn't parallel to the up vector
+        let to = if face_normal.y != 0.0 {
+            Vec3 {
+                x: 0.0,
+                y: -face_normal.y,
+                z: -0.001,
+            }
+            .normalize()
+        } else {
+            -face_normal
+        };
+
+        CameraSnap {
+            from,
+            to: Quat::look_rotation(to, UP),
+            start: Instant::now(),
+        }
+    }
+
+    // Returns the orientation for this frame and whether the snap has finished
+    pub fn orientation(&self, now: Instant) -> (Quat, bool) {
+        let t = ((now - self.start).as_secs_f32() / SNAP_SECONDS).min(1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        (self.from.slerp(self.to, t), t >= 1.0)
+    }
+}
+
+pub struct NavCubeDraw {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub graphic_pipeline: vk::Pipeline,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub index_buffer: vk::Buffer,
+    pub index_offset: u64,
+    pub num_indices: u32,
+    pub view_scissor: VkViewScissor,
+}
+
+impl NavCubeDraw {
+    // Sets its own viewport, callers must restore theirs for later draws
+    pub fn record(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+        let clear_attachment = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 0.0,
+                    stencil: 0,
+                },
+            },
+            ..Default::default()
+        };
+        let clear_rect = vk::ClearRect {
+            rect: self.view_scissor.scissor,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        unsafe {
+            // The navcube is drawn on top of the scene
+            device.cmd_clear_attachments(*command_buffer, &[clear_attachment], &[clear_rect]);
+        }
+
+        self.view_scissor.set(device, *command_buffer);
+
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptor_sets[..],
+                &[],
+            );
+
+            device.cmd_bind_pipeline(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.graphic_pipeline,
+            );
+
+            device.cmd_bind_index_buffer(
+                *command_buffer,
+                self.index_buffer,
+                self.index_offset,
+                vk::IndexType::UINT32,
+            );
+
+            device.cmd_draw_indexed(*command_buffer, self.num_indices, 1, 0, 0, 0);
+        }
+    }
+}
+
+pub struct NavCube {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub buffer_arena: BufferArena,
+    pub index_range: BufferRange,
+    pub uniform_range: BufferRange,
+    pub uniform_buffer_gpu: VkBuffer,
+    pub desc_set_layout: vk::DescriptorSetLayout,
+    pub graphic_pipeline: vk::Pipeline,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub vertex_shader_module: vk::ShaderModule,
+    pub fragment_shader_module: vk::ShaderModule,
+    pub viewport: VkViewScissor, // Follows the window size on resize
+    pub in_scene_pass: bool,     // False when post-processing draws it in the output pass
+}
+
+impl NavCube {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
+        render_pass: &vk::RenderPass,
+        view_scissor: &VkViewScissor,
+    ) -> NavCube {
+        #[rustfmt::skip]
+        let cube_indices = [
+            0u32, 2, 1, 2, 3, 1,
+            5, 4, 1, 1, 4, 0,
+            0, 4, 6, 0, 6, 2,
+            6, 5, 7, 6, 4, 5,
+            2, 6, 3, 6, 7, 3,
+            7, 1, 3, 7, 5, 1,
+        ];
+
+        // The small host visible buffers share one arena buffer. 256 bytes covers the storage
+        // buffer offset alignment of every device.
+        let mut buffer_arena = BufferArena::new(
+            device,
+            allocator,
+            4096,
+            256,
+            vk::BufferUsageFlags::INDEX_BUFFER
+                | vk::BufferUsageFlags::STORAGE_BUFFER
+                | vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        );
+        let mut allocate = |size: usize| buffer_arena.allocate(size as u64).unwrap();
+
+        let index_range = allocate(mem::size_of_val(&cube_indices[..]));
+        // One instance at the origin, always visible
+        let instance_range = allocate(mem::size_of::<Vec4>());
+        let visibility_range = allocate(mem::size_of::<u32>());
+        let uniform_range = allocate(mem::size_of::<NavCubeUniforms>());
+
+        buffer_arena.write(&index_range, &cube_indices[..]).unwrap();
+        let instance = Vec3::from_scalar(0.0).to_4d();
+        buffer_arena.write(&instance_range, &[instance]).unwrap();
+        buffer_arena.write(&visibility_range, &[0u32]).unwrap();
+
+        let uniform_buffer_gpu_info = vk::BufferCreateInfo {
+            size: std::mem::size_of::<NavCubeUniforms>() as u64,
+            usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+
+        let uniform_buffer_gpu = VkBuffer::new(
+            device,
+            allocator,
+            &uniform_buffer_gpu_info,
+            MemoryLocation::GpuOnly,
+        );
+
+        let desc_layout_bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 2,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+        ];
+        let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: desc_layout_bindings.len() as u32,
+            p_bindings: desc_layout_bindings.as_ptr(),
+            ..Default::default()
+        };
+
+        let desc_set_layout =
+            unsafe { device.create_descriptor_set_layout(&descriptor_info, None) }.unwrap();
+
+        let desc_set_layouts = &[desc_set_layout];
+
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
+
+        let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
+            buffer: uniform_buffer_gpu.buffer,
+            offset: 0,
+            range: mem::size_of::<NavCubeUniforms>() as u64,
+        };
+
+        let instance_buffer_descriptor = instance_range.descriptor();
+        let visibility_buffer_descriptor = visibility_range.descriptor();
+
+        let write_desc_sets = [
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                p_buffer_info: &uniform_buffer_descriptor,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 1,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: &instance_buffer_descriptor,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 2,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: &visibility_buffer_descriptor,
+                ..Default::default()
+            },
+        ];
+        unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
+
+        let layout_create_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: desc_set_layouts.len() as u32,
+            p_set_layouts: desc_set_layouts.as_ptr(),
+            ..Default::default()
+        };
+
+        let pipeline_layout =
+            unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
+
+        let mut vertex_spv_file = Cursor::new(&include_bytes!("../../../shader/main_vert.spv")[..]);
+        let mut frag_spv_file = Cursor::new(&include_bytes!("../../../shader/simple_frag.spv")[..]);
+
+        let vertex_code =
+            read_spv(&mut vertex_spv_file).expect("Failed to read vertex shader spv file");
+        let vertex_shader_info = vk::ShaderModuleCreateInfo {
+            code_size: vertex_code.len() * 4,
+            p_code: vertex_code.as_ptr(),
+            ..Default::default()
+        };
+
+        let frag_code =
+            read_spv(&mut frag_spv_file).expect("Failed to read fragment shader spv file");
+        let frag_shader_info = vk::ShaderModuleCreateInfo {
+            code_size: frag_code.len() * 4,
+            p_code: frag_code.as_ptr(),
+            ..Default::default()
+        };
+
+        let vertex_shader_module =
+            unsafe { device.create_shader_module(&vertex_shader_info, None) }
+                .expect("Vertex shader module error");
+
+        let fragment_shader_module =
+            unsafe { device.create_shader_module(&frag_shader_info, None) }
+                .expect("Fragment shader module error");
+
+        let shader_entry_name = CString::new("main").unwrap();
+        let shader_stage_create_infos = [
+            vk::PipelineShaderStageCreateInfo {
+                module: vertex_shader_module,
+                p_name: shader_entry_name.as_ptr(),
+                stage: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::PipelineShaderStageCreateInfo {
+                module: fragment_shader_module,
+                p_name: shader_entry_name.as_ptr(),
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+        ];
+
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
+
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            ..Default::default()
+        };
+
+        let scissors = &[view_scissor.scissor];
+        let viewports = &[view_scissor.viewport];
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            viewport_count: viewports.len() as u32,
+            p_viewports: viewports.as_ptr(),
+            scissor_count: scissors.len() as u32,
+            p_scissors: scissors.as_ptr(),
+            ..Default::default()
+        };
+
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            ..Default::default()
+        };
+
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+
+        let noop_stencil_state = vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: 1,
+            depth_write_enable: 1,
+            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            front: noop_stencil_state,
+            back: noop_stencil_state,
+            max_depth_bounds: 1.0,
+            ..Default::default()
+        };
+
+        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
+            blend_enable: 0,
+            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ZERO,
+            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            logic_op_enable: vk::FALSE,
+            logic_op: vk::LogicOp::CLEAR,
+            attachment_count: color_blend_attachment_states.len() as u32,
+            p_attachments: color_blend_attachment_states.as_ptr(),
+            ..Default::default()
+        };
+
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_state.len() as u32,
+            p_dynamic_states: dynamic_state.as_ptr(),
+            ..Default::default()
+        };
+
+        let graphic_pipeline_infos = vk::GraphicsPipelineCreateInfo {
+            stage_count: shader_stage_create_infos.len() as u32,
+            p_stages: shader_stage_create_infos.as_ptr(),
+            p_vertex_input_state: &vertex_input_state_info,
+            p_input_assembly_state: &vertex_input_assembly_state_info,
+            p_viewport_state: &viewport_state_info,
+            p_rasterization_state: &rasterization_info,
+            p_multisample_state: &multisample_state_info,
+            p_depth_stencil_state: &depth_state_info,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: &dynamic_state_info,
+            layout: pipeline_layout,
+            render_pass: *render_pass,
+            ..Default::default()
+        };
+
+        let graphics_pipelines = unsafe {
+            device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[graphic_pipeline_infos],
+                None,
+            )
+        }
+        .unwrap();
+
+        let graphic_pipeline = graphics_pipelines[0];
+
+        NavCube {
+            pipeline_layout,
+            buffer_arena,
+            index_range,
+            uniform_range,
+            uniform_buffer_gpu,
+            desc_set_layout,
+            graphic_pipeline,
+            descriptor_sets,
+            vertex_shader_module,
+            fragment_shader_module,
+            viewport: *view_scissor,
+            in_scene_pass: true,
+        }
+    }
+
+    pub fn update(&self, camera_direction: Vec3) {
+        let direction = camera_direction.normalize();
+        let uniforms = NavCubeUniforms {
+            world_to_screen: navcube_view(direction) * navcube_projection(),
+            color: ColorRgba::WHITE,
+            camera_position: (-direction * NAVCUBE_DISTANCE).to_4d(),
+            volume_scale: Vec3::from_scalar(1.0).to_4d(),
+            center_to_edge: Vec3::from_scalar(1.0).to_4d(),
+            texel_scale: Vec3::from_scalar(0.0).to_4d(),
+        };
+        self.buffer_arena
+            .write(&self.uniform_range, &[uniforms])
+            .unwrap();
+    }
+
+    pub fn gpu_draw(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: &vk::CommandBuffer,
+    ) {
+        let buffer_copy_regions = vk::BufferCopy {
+            src_offset: self.uniform_range.offset,
+            dst_offset: 0,
+            size: self.uniform_range.size,
+        };
+
+        let buffer_barrier = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::VERTEX_SHADER
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::COPY,
+            dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            buffer: self.uniform_buffer_gpu.buffer,
+            offset: 0,
+            size: buffer_copy_regions.size,
+            ..Default::default()
+        };
+
+        let buffer_barrier_end = vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COPY,
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::VERTEX_SHADER
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags2::UNIFORM_READ,
+            buffer: self.uniform_buffer_gpu.buffer,
+            offset: 0,
+            size: buffer_copy_regions.size,
+            ..Default::default()
+        };
+
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &[buffer_barrier],
+            &[],
+        );
+
+        unsafe {
+            device.cmd_copy_buffer(
+                *command_buffer,
+                self.uniform_range.buffer,
+                self.uniform_buffer_gpu.buffer,
+                &[buffer_copy_regions],
+            )
+        };
+
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &[buffer_barrier_end],
+            &[],
+        );
+    }
+
+    pub fn gpu_draw_main_render_pass(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+        self.main_render_pass_draw().record(device, command_buffer);
+    }
+
+    // Copies the handles, so the draw can be recorded on a worker thread
+    pub fn main_render_pass_draw(&self) -> NavCubeDraw {
+        NavCubeDraw {
+            pipeline_layout: self.pipeline_layout,
+            graphic_pipeline: self.graphic_pipeline,
+            descriptor_sets: self.descriptor_sets.clone(),
+            index_buffer: self.index_range.buffer,
+            index_offset: self.index_range.offset,
+            num_indices: self.index_range.size as u32 / std::mem::size_of::<u32>() as u32,
+            view_scissor: self.viewport,
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        unsafe {
+            device.destroy_pipeline(self.graphic_pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_shader_module(self.vertex_shader_module, None);
+            device.destroy_shader_module(self.fragment_shader_module, None);
+            self.buffer_arena.destroy(device, allocator);
+            self.uniform_buffer_gpu.destroy(device, allocator);
+            device.destroy_descriptor_set_layout(self.desc_set_layout, None);
+        }
+    }
+}
+
+impl RenderModule for NavCube {
+    fn resize(
+        &mut self,
+        _device: &Device,
+        _allocator: &mut TrackingAllocator,
+        resolution: vk::Extent2D,
+        _depth_view: vk::ImageView,
+    ) {
+        self.viewport = navcube_view_scissor(resolution);
+    }
+
+    fn update(&mut self, frame: &FrameContext) {
+        NavCube::update(self, frame.camera_direction);
+    }
+
+    fn record_pre_pass(
+        &mut self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        _frame: &FrameContext,
+    ) {
+        self.gpu_draw(device, barrier_api, &command_buffer);
+    }
+
+    fn record_main_pass(&mut self, _frame: &mut FrameContext) -> Option<MainPassDraw> {
+        if !self.in_scene_pass {
+            return None;
+        }
+        let draw = self.main_render_pass_draw();
+        Some(Box::new(move |device, command_buffer| {
+            draw.record(device, &command_buffer)
+        }))
+    }
+
+    fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        NavCube::destroy(self, device, allocator);
+    }
+}
@@ -0,0 +1,1182 @@
+// Post-processing chain. With a [post] table in the scene file the main render pass draws the
+// scene into an offscreen HDR image instead of the swapchain image:
+//
+//   main render pass -> HDR image -> effect passes -> output pass -> swapchain image
+//
+// Effect passes are compute shaders reading binding 0 and writing binding 1 of one shared
+// layout, binding 2 is the scene depth. They ping-pong between two HDR images, so a new effect
+// (TAA, upscaling) is another PostEffect with its shader and parameters. The output pass
+// tonemaps the last result into the swapchain image. Its render pass is compatible with the
+// main render pass and keeps the depth buffer, so overlays (navcube) draw on top of it with
+// their existing pipelines and framebuffers.
+//
+// Ambient occlusion (--ao half|full) is evaluated into its own target, binding 3, at half or
+// full resolution before the effects. It samples the distance field (brick atlas and octree of
+// the SVO renderer) around the world position of each pixel, and marches a soft shadow towards
+// the first scene light. The first effect upsamples it with depth and normal weights and
+// multiplies it into the scene color. Timestamps around the two passes measure their GPU time.
+
+use std::ffi::CString;
+use std::io::Cursor;
+
+use ash::util::*;
+use ash::{vk, Device};
+
+use gpu_allocator::MemoryLocation;
+
+use crate::allocator_telemetry::*;
+use crate::lights::*;
+use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::quality_settings::*;
+use crate::svo_texture::*;
+use crate::svosdf::*;
+use crate::vulkan_base::*;
+use crate::vulkan_helpers::*;
+use rust_test::device_capabilities::DeviceCapabilities;
+
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const EFFECT_GROUP_SIZE: u32 = 8;
+
+// Ambient occlusion value, linear depth and view normal xy of the evaluated pixel
+const AO_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const AO_RADIUS: f32 = 4.0; // World units
+const AO_INTENSITY: f32 = 1.5;
+const AO_SHADOW_STRENGTH: f32 = 0.6; // Darkening of fully shadowed pixels
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemap {
+    Aces,
+    Reinhard,
+}
+
+impl Tonemap {
+    pub fn from_name(name: &str) -> Option<Tonemap> {
+        match name {
+            "aces" => Some(Tonemap::Aces),
+            "reinhard" => Some(Tonemap::Reinhard),
+            _ => None,
+        }
+    }
+
+    // TONEMAP_* in post_tonemap.frag
+    fn shader_index(self) -> f32 {
+        match self {
+            Tonemap::Aces => 0.0,
+            Tonemap::Reinhard => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DofSettings {
+    pub focus_distance: f32, // World units from the camera
+    pub focus_range: f32,    // Distance from the focus plane where the blur reaches max_radius
+    pub max_radius: f32,     // Pixels
+}
+
+impl Default for DofSettings {
+    fn default() -> DofSettings {
+        DofSettings {
+            focus_distance: 50.0,
+            focus_range: 50.0,
+            max_radius: 8.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VignetteSettings {
+    pub strength: f32,
+    pub radius: f32, // Darkening starts here, 1 is the middle of the screen edges
+    pub softness: f32,
+}
+
+impl Default for VignetteSettings {
+    fn default() -> VignetteSettings {
+        VignetteSettings {
+            strength: 0.4,
+            radius: 0.6,
+            softness: 0.8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    pub exposure: f32,
+    pub tonemap: Tonemap,
+    pub dof: Option<DofSettings>,
+    pub vignette: Option<VignetteSettings>,
+}
+
+impl Default for PostSettings {
+    fn default() -> PostSettings {
+        PostSettings {
+            exposure: 1.0,
+            tonemap: Tonemap::Aces,
+            dof: None,
+            vignette: None,
+        }
+    }
+}
+
+// Compute passes of the chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    AmbientOcclusion, // Upsample and composite of the AO target
+    DepthOfField,
+    Vignette,
+}
+
+impl PostEffect {
+    // Execution order
+    pub const ALL: [PostEffect; 3] = [
+        PostEffect::AmbientOcclusion,
+        PostEffect::DepthOfField,
+        PostEffect::Vignette,
+    ];
+
+    fn shader_spv(self) -> &'static [u8] {
+        match self {
+            PostEffect::AmbientOcclusion => {
+                &include_bytes!("../../../shader/post_ao_upsample.spv")[..]
+            }
+            PostEffect::DepthOfField => &include_bytes!("../../../shader/post_dof.spv")[..],
+            PostEffect::Vignette => &include_bytes!("../../../shader/post_vignette.spv")[..],
+        }
+    }
+
+    // Shader parameters, None when the effect is off
+    fn params(self, settings: &PostSettings, ao: AoQuality) -> Option<Vec4> {
+        match self {
+            PostEffect::AmbientOcclusion => ao.resolution_divisor().map(|divisor| Vec4 {
+                x: divisor as f32,
+                y: 0.0,
+                z: 0.0,
+                w: 0.0,
+            }),
+            PostEffect::DepthOfField => settings.dof.map(|dof| Vec4 {
+                x: dof.focus_distance,
+                y: dof.focus_range,
+                z: dof.max_radius,
+                w: 0.0,
+            }),
+            PostEffect::Vignette => settings.vignette.map(|vignette| Vec4 {
+                x: vignette.strength,
+                y: vignette.radius,
+                z: vignette.softness,
+                w: 0.0,
+            }),
+        }
+    }
+}
+
+// Shared by all passes of the chain
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PostPushConstants {
+    pub params: Vec4,       // Pass specific
+    pub depth_params: Vec4, // Near, far, 1 for orthographic projection, pixels per world unit
+}
+
+// Distance field and light of the AO pass, AoUBO in post_ao.comp (std140)
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct AoUniforms {
+    pub screen_to_world: Mat4x4,
+    pub light: Vec4,          // Direction to a directional light, position of others
+    pub volume_spacing: Vec4, // Voxel size, w is the world length of one distance unit
+    pub volume_dim: [u32; 4], // Voxels, w is the number of scene instances
+    // Bricks per row and largest brick size of a packed atlas, 1 if sparse, brick size
+    pub atlas: [u32; 4],
+    pub light_type: u32, // 0 without lights, 1 directional, 2 point or spot
+    pub brick_curve: f32,
+    pub shadow_distance: f32, // World units the shadow rays march at most
+    pub _padding: u32,
+}
+
+const _: () = assert!(std::mem::size_of::<AoUniforms>() == 144);
+unsafe impl Zeroable for AoUniforms {}
+unsafe impl Pod for AoUniforms {}
+
+impl AoUniforms {
+    // The camera and light are set every frame by PostChain::update
+    pub fn new(
+        svo_sdf: &SvoSdf,
+        svo_texture: &SvoTexture,
+        num_scene_instances: usize,
+    ) -> AoUniforms {
+        let header = &svo_sdf.header;
+        // Distance codes are lengths of the volume diagonal, see SdfHeader::distance_per_step
+        let diagonal = header.bounds().size().length();
+        let atlas = match svo_texture.brick_atlas {
+            BrickAtlas::Packed {
+                bricks_per_row,
+                max_brick_size,
+                ..
+            } => [bricks_per_row, max_brick_size, 0, svo_sdf.brick_size],
+            BrickAtlas::Sparse(_) => [0, 0, 1, svo_sdf.brick_size],
+        };
+        AoUniforms {
+            screen_to_world: identity(),
+            light: Vec4::zeroed(),
+            volume_spacing: Vec4 {
+                x: header.spacing.0,
+                y: header.spacing.1,
+                z: header.spacing.2,
+                w: diagonal,
+            },
+            volume_dim: [
+                header.dim.0,
+                header.dim.1,
+                header.dim.2,
+                num_scene_instances as u32,
+            ],
+            atlas,
+            light_type: 0,
+            brick_curve: svo_texture.brick_curve.exponent,
+            shadow_distance: diagonal,
+            _padding: 0,
+        }
+    }
+
+    // The first light casts the shadow, cull_lights puts directional lights first
+    fn set_light(&mut self, lights: &SvoLights) {
+        let light = &lights.lights[0];
+        let (position, light_type) = match (lights.count, light.light_type) {
+            (0, _) => (Vec4::zeroed(), 0),
+            (_, 0) => ((-light.direction_cos_outer.to_3d()).to_4d(), 1),
+            _ => (light.position_range.to_3d().to_4d(), 2),
+        };
+        self.light = position;
+        self.light_type = light_type;
+    }
+}
+
+// Inputs of the AO pass shared with the SVO renderer
+pub struct AoScene<'a> {
+    pub brick_texture: &'a vk::DescriptorImageInfo,
+    pub octree_buffer: &'a vk::DescriptorBufferInfo,
+    pub scene_instances_buffer: &'a vk::DescriptorBufferInfo,
+    pub scratch: &'a ScratchAllocator, // Holds the AO uniforms of each frame
+    pub uniforms: AoUniforms,
+}
+
+fn create_shader_module(device: &Device, spv: &[u8]) -> vk::ShaderModule {
+    let code = read_spv(&mut Cursor::new(spv)).expect("Failed to read shader spv file");
+    let shader_info = vk::ShaderModuleCreateInfo {
+        code_size: code.len() * 4,
+        p_code: code.as_ptr(),
+        ..Default::default()
+    };
+    unsafe { device.create_shader_module(&shader_info, None) }.expect("Shader module error")
+}
+
+fn create_descriptor_set_layout(
+    device: &Device,
+    bindings: &[vk::DescriptorSetLayoutBinding],
+) -> vk::DescriptorSetLayout {
+    let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
+        binding_count: bindings.len() as u32,
+        p_bindings: bindings.as_ptr(),
+        ..Default::default()
+    };
+    unsafe { device.create_descriptor_set_layout(&descriptor_info, None) }.unwrap()
+}
+
+// Scene color goes to the HDR image in GENERAL layout for the effect passes. The dependency
+// waits for the previous frame's passes reading the HDR images.
+fn create_scene_render_pass(device: &Device, depth_format: vk::Format) -> vk::RenderPass {
+    let attachments = [
+        vk::AttachmentDescription {
+            format: HDR_FORMAT,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            final_layout: vk::ImageLayout::GENERAL,
+            ..Default::default()
+        },
+        vk::AttachmentDescription {
+            format: depth_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        },
+    ];
+    let dependencies = [vk::SubpassDependency {
+        src_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+            | vk::PipelineStageFlags::COMPUTE_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER,
+        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ..Default::default()
+    }];
+    create_render_pass(device, &attachments, &dependencies)
+}
+
+// Same formats as the main render pass, the fullscreen triangle overwrites the swapchain image
+// and the depth buffer is kept for overlays and the depth pyramid
+fn create_output_render_pass(
+    device: &Device,
+    surface_format: vk::Format,
+    depth_format: vk::Format,
+) -> vk::RenderPass {
+    let attachments = [
+        vk::AttachmentDescription {
+            format: surface_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::DONT_CARE,
+            store_op: vk::AttachmentStoreOp::STORE,
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            ..Default::default()
+        },
+        vk::AttachmentDescription {
+            format: depth_format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::LOAD,
+            store_op: vk::AttachmentStoreOp::STORE,
+            initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ..Default::default()
+        },
+    ];
+    let dependencies = [vk::SubpassDependency {
+        src_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
+            | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        ..Default::default()
+    }];
+    create_render_pass(device, &attachments, &dependencies)
+}
+
+// One subpass, attachment 0 is color and 1 depth
+fn create_render_pass(
+    device: &Device,
+    attachments: &[vk::AttachmentDescription],
+    dependencies: &[vk::SubpassDependency],
+) -> vk::RenderPass {
+    let color_attachment_refs = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+    let subpasses = [vk::SubpassDescription {
+        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+        color_attachment_count: color_attachment_refs.len() as u32,
+        p_color_attachments: color_attachment_refs.as_ptr(),
+        p_depth_stencil_attachment: &depth_attachment_ref,
+        ..Default::default()
+    }];
+    let render_pass_create_info = vk::RenderPassCreateInfo {
+        attachment_count: attachments.len() as u32,
+        p_attachments: attachments.as_ptr(),
+        subpass_count: subpasses.len() as u32,
+        p_subpasses: subpasses.as_ptr(),
+        dependency_count: dependencies.len() as u32,
+        p_dependencies: dependencies.as_ptr(),
+        ..Default::default()
+    };
+    unsafe { device.create_render_pass(&render_pass_create_info, None) }.unwrap()
+}
+
+fn create_color_view(device: &Device, image: &VkImage, format: vk::Format) -> vk::ImageView {
+    let view_info = vk::ImageViewCreateInfo {
+        view_type: vk::ImageViewType::TYPE_2D,
+        format,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            level_count: 1,
+            layer_count: 1,
+            ..Default::default()
+        },
+        image: image.image,
+        ..Default::default()
+    };
+    create_image_view(device, &view_info).unwrap()
+}
+
+// Images and views sized to the swapchain, re-created on resize
+struct HdrTargets {
+    images: Vec<VkImage>,
+    views: Vec<vk::ImageView>,
+    scene_framebuffer: vk::Framebuffer,
+    ao: Option<(VkImage, vk::ImageView)>, // Screen size divided by the AO resolution divisor
+    ao_extent: vk::Extent2D,
+}
+
+impl HdrTargets {
+    fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        scene_render_pass: vk::RenderPass,
+        depth_view: vk::ImageView,
+        extent: vk::Extent2D,
+        ao_quality: AoQuality,
+    ) -> HdrTargets {
+        let image_info = vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format: HDR_FORMAT,
+            extent: extent.into(),
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let images: Vec<VkImage> = (0..2)
+            .map(|_| VkImage::new(device, allocator, &image_info, MemoryLocation::GpuOnly))
+            .collect();
+        let views: Vec<vk::ImageView> = images
+            .iter()
+            .map(|image| create_color_view(device, image, HDR_FORMAT))
+            .collect();
+
+        let divisor = ao_quality.resolution_divisor().unwrap_or(1);
+        let ao_extent = vk::Extent2D {
+            width: extent.width.div_ceil(divisor),
+            height: extent.height.div_ceil(divisor),
+        };
+        let ao = ao_quality.resolution_divisor().map(|_| {
+            let ao_image_info = vk::ImageCreateInfo {
+                format: AO_FORMAT,
+                extent: ao_extent.into(),
+                usage: vk::ImageUsageFlags::STORAGE,
+                ..image_info
+            };
+            let image = VkImage::new(device, allocator, &ao_image_info, MemoryLocation::GpuOnly);
+            let view = create_color_view(device, &image, AO_FORMAT);
+            (image, view)
+        });
+
+        let framebuffer_attachments = [views[0], depth_view];
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass: scene_render_pass,
+            attachment_count: framebuffer_attachments.len() as u32,
+            p_attachments: framebuffer_attachments.as_ptr(),
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let scene_framebuffer =
+            unsafe { device.create_framebuffer(&framebuffer_info, None) }.unwrap();
+
+        HdrTargets {
+            images,
+            views,
+            scene_framebuffer,
+            ao,
+            ao_extent,
+        }
+    }
+
+    fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        unsafe {
+            device.destroy_framebuffer(self.scene_framebuffer, None);
+            for view in self.views.drain(..) {
+                destroy_image_view(device, view);
+            }
+        }
+        for image in self.images.iter_mut() {
+            image.destroy(device, allocator);
+        }
+        if let Some((mut image, view)) = self.ao.take() {
+            destroy_image_view(device, view);
+            image.destroy(device, allocator);
+        }
+    }
+}
+
+pub struct PostChain {
+    pub settings: PostSettings,
+    pub ao: AoQuality,
+    pub scene_render_pass: vk::RenderPass,
+    pub output_render_pass: vk::RenderPass,
+    pub extent: vk::Extent2D,
+    targets: HdrTargets,
+    effect_set_layout: vk::DescriptorSetLayout,
+    effect_sets: Vec<vk::DescriptorSet>, // [i] reads HDR image i and writes the other one
+    effect_pipeline_layout: vk::PipelineLayout,
+    effect_pipelines: Vec<vk::Pipeline>, // Indexed like PostEffect::ALL
+    effect_shader_modules: Vec<vk::ShaderModule>,
+    ao_set_layout: vk::DescriptorSetLayout,
+    ao_set: vk::DescriptorSet, // Set 1 of the AO pass, the distance field
+    ao_pipeline_layout: vk::PipelineLayout,
+    ao_pipeline: vk::Pipeline, // Writes the AO target
+    ao_shader_module: vk::ShaderModule,
+    ao_uniforms: AoUniforms,
+    ao_timestamps: Option<QueryPoolWrapper>, // Start and end of the AO passes per frame slot
+    ao_timestamps_written: Vec<bool>,
+    ao_gpu_ms: Option<f64>,
+    output_shaders: ShaderSet,
+    output_sets: Vec<vk::DescriptorSet>, // [i] reads HDR image i
+    output_pipeline: vk::Pipeline,
+    depth_params: Vec4,
+}
+
+impl PostChain {
+    #[allow(clippy::too_many_arguments)]
+    // frame_slots is the number of command buffers recorded in turn, each gets its own
+    // AO timestamps
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
+        capabilities: &DeviceCapabilities,
+        frame_slots: usize,
+        surface_format: vk::Format,
+        depth_format: vk::Format,
+        depth_view: vk::ImageView,
+        extent: vk::Extent2D,
+        settings: PostSettings,
+        ao: AoQuality,
+        ao_scene: &AoScene,
+    ) -> PostChain {
+        let scene_render_pass = create_scene_render_pass(device, depth_format);
+        let output_render_pass = create_output_render_pass(device, surface_format, depth_format);
+        let targets = HdrTargets::new(device, allocator, scene_render_pass, depth_view, extent, ao);
+
+        // Effect passes
+        let storage_image_binding = |binding, stage_flags| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+            descriptor_count: 1,
+            stage_flags,
+            ..Default::default()
+        };
+        let effect_set_layout = create_descriptor_set_layout(
+            device,
+            &[
+                storage_image_binding(0, vk::ShaderStageFlags::COMPUTE),
+                storage_image_binding(1, vk::ShaderStageFlags::COMPUTE),
+                vk::DescriptorSetLayoutBinding {
+                    binding: 2,
+                    descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+                storage_image_binding(3, vk::ShaderStageFlags::COMPUTE),
+            ],
+        );
+        let effect_sets = descriptor_allocator
+            .allocate_sets(device, &[effect_set_layout, effect_set_layout])
+            .unwrap();
+        let effect_pipeline_layout = PipelineLayoutBuilder::new()
+            .set_layout(effect_set_layout)
+            .push_constants::<PostPushConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build(device)
+            .unwrap();
+
+        // Distance field of the AO pass
+        let ao_binding = |binding, descriptor_type| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            ..Default::default()
+        };
+        let ao_set_layout = create_descriptor_set_layout(
+            device,
+            &[
+                ao_binding(0, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC),
+                ao_binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
+                ao_binding(2, vk::DescriptorType::STORAGE_BUFFER),
+                ao_binding(3, vk::DescriptorType::STORAGE_BUFFER),
+            ],
+        );
+        let ao_set = descriptor_allocator
+            .allocate_sets(device, &[ao_set_layout])
+            .unwrap()[0];
+        let ao_uniforms_descriptor = ao_scene.scratch.uniform_descriptor::<AoUniforms>();
+        let buffer_write = |dst_binding, descriptor_type, buffer_info| vk::WriteDescriptorSet {
+            dst_set: ao_set,
+            dst_binding,
+            descriptor_count: 1,
+            descriptor_type,
+            p_buffer_info: buffer_info,
+            ..Default::default()
+        };
+        let ao_writes = [
+            buffer_write(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                &ao_uniforms_descriptor,
+            ),
+            vk::WriteDescriptorSet {
+                dst_set: ao_set,
+                dst_binding: 1,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: ao_scene.brick_texture,
+                ..Default::default()
+            },
+            buffer_write(
+                2,
+                vk::DescriptorType::STORAGE_BUFFER,
+                ao_scene.octree_buffer,
+            ),
+            buffer_write(
+                3,
+                vk::DescriptorType::STORAGE_BUFFER,
+                ao_scene.scene_instances_buffer,
+            ),
+        ];
+        unsafe { device.update_descriptor_sets(&ao_writes, &[]) };
+        let ao_pipeline_layout = PipelineLayoutBuilder::new()
+            .set_layouts(&[effect_set_layout, ao_set_layout])
+            .push_constants::<PostPushConstants>(vk::ShaderStageFlags::COMPUTE)
+            .build(device)
+            .unwrap();
+
+        let shader_entry_name = CString::new("main").unwrap();
+        let effect_shader_modules: Vec<vk::ShaderModule> = PostEffect::ALL
+            .iter()
+            .map(|effect| create_shader_module(device, effect.shader_spv()))
+            .collect();
+        let ao_shader_module =
+            create_shader_module(device, include_bytes!("../../../shader/post_ao.spv"));
+        // The AO pipeline is last
+        let effect_pipeline_infos: Vec<vk::ComputePipelineCreateInfo> = effect_shader_modules
+            .iter()
+            .map(|&module| (module, effect_pipeline_layout))
+            .chain([(ao_shader_module, ao_pipeline_layout)])
+            .map(|(module, layout)| vk::ComputePipelineCreateInfo {
+                stage: vk::PipelineShaderStageCreateInfo {
+                    module,
+                    p_name: shader_entry_name.as_ptr(),
+                    stage: vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+                layout,
+                ..Default::default()
+            })
+            .collect();
+        let mut effect_pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &effect_pipeline_infos, None)
+        }
+        .unwrap();
+        let ao_pipeline = effect_pipelines.pop().unwrap();
+
+        let ao_timestamps = match ao {
+            AoQuality::Off => None,
+            _ => QueryPoolWrapper::timestamps(device, capabilities, 2 * frame_slots as u32)
+                .expect("Timestamp query pool creation failed"),
+        };
+
+        // Output pass, layouts reflected from the shaders
+        let output_shaders = ShaderSet::from_spv(
+            device,
+            &[
+                &include_bytes!("../../../shader/full_screen_triangle_vert.spv")[..],
+                &include_bytes!("../../../shader/post_tonemap_frag.spv")[..],
+            ],
+        )
+        .unwrap_or_else(|err| panic!("Post output shaders: {}", err));
+        let output_set_layout = output_shaders.set_layouts[0];
+        let output_sets = descriptor_allocator
+            .allocate_sets(device, &[output_set_layout, output_set_layout])
+            .unwrap();
+        let output_pipeline = output_shaders
+            .graphics_pipeline()
+            .depth(false, false)
+            .build(device, output_shaders.pipeline_layout, output_render_pass)
+            .unwrap();
+
+        let post_chain = PostChain {
+            settings,
+            ao,
+            scene_render_pass,
+            output_render_pass,
+            extent,
+            targets,
+            effect_set_layout,
+            effect_sets,
+            effect_pipeline_layout,
+            effect_pipelines,
+            effect_shader_modules,
+            ao_set_layout,
+            ao_set,
+            ao_pipeline_layout,
+            ao_pipeline,
+            ao_shader_module,
+            ao_uniforms: ao_scene.uniforms,
+            ao_timestamps,
+            ao_timestamps_written: vec![false; frame_slots],
+            ao_gpu_ms: None,
+            output_shaders,
+            output_sets,
+            output_pipeline,
+            depth_params: Vec4 {
+                x: 1.0,
+                y: 1.0e7,
+                z: 0.0,
+                w: 0.0,
+            },
+        };
+        post_chain.write_descriptors(device, depth_view);
+        post_chain
+    }
+
+    fn write_descriptors(&self, device: &Device, depth_view: vk::ImageView) {
+        let storage_infos: Vec<vk::DescriptorImageInfo> = self
+            .targets
+            .views
+            .iter()
+            .map(|&image_view| vk::DescriptorImageInfo {
+                image_view,
+                image_layout: vk::ImageLayout::GENERAL,
+                ..Default::default()
+            })
+            .collect();
+        let depth_info = vk::DescriptorImageInfo {
+            image_view: depth_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..Default::default()
+        };
+        let ao_info = self
+            .targets
+            .ao
+            .as_ref()
+            .map(|&(_, image_view)| vk::DescriptorImageInfo {
+                image_view,
+                image_layout: vk::ImageLayout::GENERAL,
+                ..Default::default()
+            });
+
+        let mut write_desc_sets = Vec::new();
+        for i in 0..2 {
+            let storage_write = |dst_set, dst_binding, image_info: &vk::DescriptorImageInfo| {
+                vk::WriteDescriptorSet {
+                    dst_set,
+                    dst_binding,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    p_image_info: image_info,
+                    ..Default::default()
+                }
+            };
+            write_desc_sets.push(storage_write(self.effect_sets[i], 0, &storage_infos[i]));
+            write_desc_sets.push(storage_write(self.effect_sets[i], 1, &storage_infos[1 - i]));
+            write_desc_sets.push(vk::WriteDescriptorSet {
+                dst_set: self.effect_sets[i],
+                dst_binding: 2,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                p_image_info: &depth_info,
+                ..Default::default()
+            });
+            if let Some(ao_info) = &ao_info {
+                write_desc_sets.push(storage_write(self.effect_sets[i], 3, ao_info));
+            }
+        }
+        unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
+
+        for (&output_set, &storage_info) in self.output_sets.iter().zip(&storage_infos) {
+            self.output_shaders
+                .bind(0, output_set)
+                .image(0, storage_info)
+                .write(device)
+                .unwrap_or_else(|err| panic!("Post output descriptors: {}", err));
+        }
+    }
+
+    // Call after the swapchain was re-created, the device is idle then
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        depth_view: vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        self.targets.destroy(device, allocator);
+        self.targets = HdrTargets::new(
+            device,
+            allocator,
+            self.scene_render_pass,
+            depth_view,
+            extent,
+            self.ao,
+        );
+        self.extent = extent;
+        self.write_descriptors(device, depth_view);
+    }
+
+    pub fn scene_framebuffer(&self) -> vk::Framebuffer {
+        self.targets.scene_framebuffer
+    }
+
+    // The DOF and AO passes linearize depth with the projection of the frame. Their formula is
+    // for reversed depth, swapping near and far turns it into the standard depth one. AO also
+    // needs the pixels per world unit, at distance 1 for perspective projection, the inverse
+    // view projection for world positions and the culled lights for its shadow.
+    pub fn update(
+        &mut self,
+        projection: &ProjectionSettings,
+        world_to_screen: &Mat4x4,
+        lights: &SvoLights,
+    ) {
+        // Unchecked like Ray::from_screen, the checked inverse rejects projections with extreme
+        // near/far ratios and an identity fallback would put every AO sample in the wrong place
+        self.ao_uniforms.screen_to_world = inverse_unchecked(*world_to_screen);
+        self.ao_uniforms.set_light(lights);
+        let orthographic = projection.projection_type == ProjectionType::Orthographic;
+        let (near, far) = match projection.depth {
+            DepthConvention::Reversed => (projection.near, projection.far),
+            DepthConvention::Standard => (projection.far, projection.near),
+        };
+        let height = self.extent.height as f32;
+        let pixels_per_unit = if orthographic {
+            height / projection.ortho_height
+        } else {
+            height / (2.0 * (projection.fov_degrees.to_radians() * 0.5).tan())
+        };
+        self.depth_params = Vec4 {
+            x: near,
+            y: far,
+            z: if orthographic { 1.0 } else { 0.0 },
+            w: pixels_per_unit,
+        };
+    }
+
+    // GPU time of the AO evaluation and upsample passes, None when AO is off or the device has
+    // no timestamps
+    pub fn ao_gpu_ms(&self) -> Option<f64> {
+        self.ao_gpu_ms
+    }
+
+    fn active_effects(&self) -> Vec<(usize, Vec4)> {
+        PostEffect::ALL
+            .iter()
+            .enumerate()
+            .filter_map(|(index, effect)| {
+                effect.params(&self.settings, self.ao).map(|p| (index, p))
+            })
+            .collect()
+    }
+
+    // Effect passes, between the main render pass and the output render pass. The depth
+    // buffer is sampled meanwhile and back in attachment layout afterwards. frame_slot is the
+    // command buffer's index, its fence has been waited so its AO timestamps can be read.
+    #[allow(clippy::too_many_arguments)]
+    pub fn gpu_draw(
+        &mut self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: &vk::CommandBuffer,
+        scratch: &mut ScratchAllocator,
+        depth_image: &vk::Image,
+        depth_aspect: vk::ImageAspectFlags,
+        frame_slot: usize,
+    ) {
+        let query = 2 * frame_slot as u32;
+        if let Some(timestamps) = &self.ao_timestamps {
+            if self.ao_timestamps_written[frame_slot] {
+                let ao_ms = timestamps
+                    .elapsed_ms(device, query, query + 1)
+                    .expect("Timestamp query read failed");
+                self.ao_gpu_ms = ao_ms.or(self.ao_gpu_ms);
+            }
+            timestamps.cmd_reset(device, *command_buffer, query, 2);
+            self.ao_timestamps_written[frame_slot] = true;
+        }
+
+        let depth_range = vk::ImageSubresourceRange {
+            aspect_mask: depth_aspect,
+            level_count: 1,
+            layer_count: 1,
+            ..Default::default()
+        };
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            level_count: 1,
+            layer_count: 1,
+            ..Default::default()
+        };
+
+        let scene_color_barrier = vk::MemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            src_access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ,
+            ..Default::default()
+        };
+        let mut image_barriers = vec![
+            // Fully overwritten by the first effect, the old contents are discarded
+            vk::ImageMemoryBarrier2 {
+                src_stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                src_access_mask: vk::AccessFlags2::NONE,
+                dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::GENERAL,
+                image: self.targets.images[1].image,
+                subresource_range: color_range,
+                ..Default::default()
+            },
+            vk::ImageMemoryBarrier2 {
+                src_stage_mask: vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                src_access_mask: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags2::SHADER_SAMPLED_READ,
+                old_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                image: *depth_image,
+                subresource_range: depth_range,
+                ..Default::default()
+            },
+        ];
+        // Fully overwritten by the AO pass, the previous frame's upsample read it
+        if let Some((ao_image, _)) = &self.targets.ao {
+            image_barriers.push(vk::ImageMemoryBarrier2 {
+                src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                src_access_mask: vk::AccessFlags2::NONE,
+                dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+                dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                old_layout: vk::ImageLayout::UNDEFINED,
+                new_layout: vk::ImageLayout::GENERAL,
+                image: ao_image.image,
+                subresource_range: color_range,
+                ..Default::default()
+            });
+        }
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[scene_color_barrier],
+            &[],
+            &image_barriers,
+        );
+
+        let effect_barrier = vk::MemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            ..Default::default()
+        };
+
+        // AO evaluation into its own target, the upsample is the first effect
+        if let Some(divisor) = self.ao.resolution_divisor() {
+            if let Some(timestamps) = &self.ao_timestamps {
+                timestamps.cmd_write_timestamp(
+                    device,
+                    *command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query,
+                );
+            }
+            let uniforms_offset = scratch
+                .push_uniform(&self.ao_uniforms)
+                .expect("AO uniforms don't fit the scratch buffer");
+            unsafe {
+                device.cmd_bind_pipeline(
+                    *command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.ao_pipeline,
+                );
+                device.cmd_bind_descriptor_sets(
+                    *command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.ao_pipeline_layout,
+                    0,
+                    &[self.effect_sets[0], self.ao_set],
+                    &[uniforms_offset],
+                );
+            }
+            cmd_push(
+                device,
+                *command_buffer,
+                self.ao_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &PostPushConstants {
+                    params: Vec4 {
+                        x: AO_RADIUS,
+                        y: AO_INTENSITY,
+                        z: divisor as f32,
+                        w: AO_SHADOW_STRENGTH,
+                    },
+                    depth_params: self.depth_params,
+                },
+            );
+            let ao_extent = self.targets.ao_extent;
+            unsafe {
+                device.cmd_dispatch(
+                    *command_buffer,
+                    ao_extent.width.div_ceil(EFFECT_GROUP_SIZE),
+                    ao_extent.height.div_ceil(EFFECT_GROUP_SIZE),
+                    1,
+                )
+            };
+            barrier2(
+                device,
+                barrier_api,
+                *command_buffer,
+                &[effect_barrier],
+                &[],
+                &[],
+            );
+        }
+
+        let groups_x = self.extent.width.div_ceil(EFFECT_GROUP_SIZE);
+        let groups_y = self.extent.height.div_ceil(EFFECT_GROUP_SIZE);
+        for (pass, (effect_index, params)) in self.active_effects().into_iter().enumerate() {
+            if pass > 0 {
+                barrier2(
+                    device,
+                    barrier_api,
+                    *command_buffer,
+                    &[effect_barrier],
+                    &[],
+                    &[],
+                );
+            }
+            unsafe {
+                device.cmd_bind_pipeline(
+                    *command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.effect_pipelines[effect_index],
+                );
+                device.cmd_bind_descriptor_sets(
+                    *command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.effect_pipeline_layout,
+                    0,
+                    &[self.effect_sets[pass % 2]],
+                    &[],
+                );
+            }
+            cmd_push(
+                device,
+                *command_buffer,
+                self.effect_pipeline_layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                &PostPushConstants {
+                    params,
+                    depth_params: self.depth_params,
+                },
+            );
+            unsafe { device.cmd_dispatch(*command_buffer, groups_x, groups_y, 1) };
+            if let (PostEffect::AmbientOcclusion, Some(timestamps)) =
+                (PostEffect::ALL[effect_index], &self.ao_timestamps)
+            {
+                timestamps.cmd_write_timestamp(
+                    device,
+                    *command_buffer,
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    query + 1,
+                );
+            }
+        }
+
+        let output_barrier = vk::MemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            dst_access_mask: vk::AccessFlags2::SHADER_STORAGE_READ,
+            ..Default::default()
+        };
+        let depth_attachment_barrier = vk::ImageMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            dst_access_mask: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            old_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            image: *depth_image,
+            subresource_range: depth_range,
+            ..Default::default()
+        };
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[output_barrier],
+            &[],
+            &[depth_attachment_barrier],
+        );
+    }
+
+    // Tonemaps the last effect's result, inside the output render pass
+    pub fn gpu_draw_output_render_pass(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+        let result_image = self.active_effects().len() % 2;
+        unsafe {
+            device.cmd_bind_pipeline(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.output_pipeline,
+            );
+            device.cmd_bind_descriptor_sets(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.output_shaders.pipeline_layout,
+                0,
+                &[self.output_sets[result_image]],
+                &[],
+            );
+        }
+        cmd_push(
+            device,
+            *command_buffer,
+            self.output_shaders.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            &PostPushConstants {
+                params: Vec4 {
+                    x: self.settings.exposure,
+                    y: self.settings.tonemap.shader_index(),
+                    z: 0.0,
+                    w: 0.0,
+                },
+                depth_params: self.depth_params,
+            },
+        );
+        unsafe { device.cmd_draw(*command_buffer, 3, 1, 0, 0) };
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        self.targets.destroy(device, allocator);
+        unsafe {
+            device.destroy_pipeline(self.output_pipeline, None);
+            for pipeline in self.effect_pipelines.drain(..) {
+                device.destroy_pipeline(pipeline, None);
+            }
+            for module in self.effect_shader_modules.drain(..) {
+                device.destroy_shader_module(module, None);
+            }
+            device.destroy_pipeline(self.ao_pipeline, None);
+            device.destroy_shader_module(self.ao_shader_module, None);
+            device.destroy_pipeline_layout(self.ao_pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.ao_set_layout, None);
+            device.destroy_pipeline_layout(self.effect_pipeline_layout, None);
+            device.destroy_descriptor_set_layout(self.effect_set_layout, None);
+            device.destroy_render_pass(self.scene_render_pass, None);
+            device.destroy_render_pass(self.output_render_pass, None);
+        }
+        self.output_shaders.destroy(device);
+        if let Some(timestamps) = self.ao_timestamps.as_mut() {
+            timestamps.destroy(device);
+        }
+    }
+}
@@ -1,59 +1,188 @@
 use std::default::Default;
-use std::ffi::CString;
 use std::io::Cursor;
-use std::mem;
 
 use ash::util::*;
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::color::*;
+use crate::lights::*;
 use crate::minivector::*;
+use crate::projection_settings::*;
+use crate::render_module::*;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
+use rust_test::memory_profile::BrickCurve;
+use rust_test::svosdf::*;
 
+pub const NUM_CUBE_INDICES: usize = 3 * 3 * 2; // Front faces only
+
+// Per frame camera data, pushed with the draw instead of copied into the uniform buffer
 #[derive(Clone, Copy)]
-pub struct SvoCubeUniforms {
+#[repr(C)]
+pub struct SvoCubePushConstants {
     pub world_to_screen: Mat4x4,
-    pub color: Vec4,
     pub camera_position: Vec4,
+}
+
+// Constant for the lifetime of the renderer. Each draw copies them into the frame's scratch
+// region and binds them with a dynamic offset.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct SvoCubeUniforms {
+    pub color: ColorRgba,
     pub volume_scale: Vec4,
     pub center_to_edge: Vec4,
     pub texel_scale: Vec4,
     pub brick_size: u32,
+    pub brick_curve: f32, // Exponent of BrickCurve
+    pub bricks_per_scene_instance: u32,
+    pub scene_instance_visibility: u32, // 1 when the visibility list holds scene instances
+    pub normal_matrix: [Vec4; 3],       // Brick uvw to volume space normals, std140 mat3
+}
+
+unsafe impl Zeroable for SvoCubeUniforms {}
+unsafe impl Pod for SvoCubeUniforms {}
+
+impl SvoCubeUniforms {
+    // Without HiZ culling the visibility list holds the visible scene instances instead of
+    // the visible bricks, see Culling::set_visible_scene_instances
+    pub fn new(
+        svo_sdf: &SvoSdf,
+        brick_curve: BrickCurve,
+        bricks_per_scene_instance: usize,
+        scene_instance_visibility: bool,
+    ) -> SvoCubeUniforms {
+        let dim = svo_sdf.header.dim;
+        let diagonal = svo_sdf.header.bounds().size();
+
+        let center_to_edge = diagonal * 0.5;
+        let volume_scale = Vec3::from_scalar(diagonal.length()) / diagonal;
+
+        let texels = Vec3 {
+            x: dim.0 as f32,
+            y: dim.1 as f32,
+            z: dim.2 as f32,
+        };
+        let texel_scale = Vec3::from_scalar(1.0) / texels;
+
+        // The uvw cube is stretched to the volume box, anisotropic volumes scale it unevenly
+        let uvw_to_volume = scale(diagonal);
+
+        SvoCubeUniforms {
+            color: ColorRgba::new(1.0, 0.1, 0.0, 0.0),
+            volume_scale: volume_scale.to_4d(),
+            center_to_edge: center_to_edge.to_4d(),
+            texel_scale: texel_scale.to_4d(),
+            brick_size: svo_sdf.brick_size,
+            brick_curve: brick_curve.exponent,
+            bricks_per_scene_instance: bricks_per_scene_instance as u32,
+            scene_instance_visibility: scene_instance_visibility as u32,
+            normal_matrix: normal_matrix(uvw_to_volume).to_std140(),
+        }
+    }
+}
+
+pub struct SvoCubesDraw {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub graphic_pipeline: vk::Pipeline,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub index_buffer: vk::Buffer,
+    pub num_cubes: u32,
+    pub indirect_draws: Option<IndirectDraws>,
+    pub push_constants: SvoCubePushConstants,
+    pub uniform_offset: u32,
+    pub lights_offset: u32,
+}
+
+impl SvoCubesDraw {
+    pub fn record(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &self.descriptor_sets[..],
+                &[self.uniform_offset, self.lights_offset],
+            );
+
+            device.cmd_bind_pipeline(
+                *command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.graphic_pipeline,
+            );
+
+            cmd_push(
+                device,
+                *command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                &self.push_constants,
+            );
+
+            device.cmd_bind_index_buffer(
+                *command_buffer,
+                self.index_buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            match self.indirect_draws {
+                Some(indirect_draws) => indirect_draws.record(device, *command_buffer),
+                None => {
+                    device.cmd_draw_indexed(
+                        *command_buffer,
+                        NUM_CUBE_INDICES as u32,
+                        self.num_cubes,
+                        0,
+                        0,
+                        0,
+                    );
+                }
+            }
+        }
+    }
 }
 
 pub struct RenderSvoCubes {
     pub pipeline_layout: vk::PipelineLayout,
     pub index_buffer: VkBuffer,
     pub index_buffer_gpu: VkBuffer,
-    pub uniform_buffer: VkBuffer,
-    pub uniform_buffer_gpu: VkBuffer,
     pub desc_set_layout: vk::DescriptorSetLayout,
     pub graphic_pipeline: vk::Pipeline,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
     pub vertex_shader_module: vk::ShaderModule,
     pub fragment_shader_module: vk::ShaderModule,
+    pub push_constants: SvoCubePushConstants,
+    pub uniforms: SvoCubeUniforms,
+    pub lights: SvoLights, // Culled scene lights, staged with every draw like the uniforms
+    pub indirect_draws: Option<IndirectDraws>, // Culling results
+    pub visible_instances: Option<usize>, // Without culling, cubes of the visibility list
 }
 
 impl RenderSvoCubes {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
         render_pass: &vk::RenderPass,
         view_scissor: &VkViewScissor,
         brick_texture_descriptor: &vk::DescriptorImageInfo,
         octree_buffer_descriptor: &vk::DescriptorBufferInfo,
-        instances_buffer_descriptor: &vk::DescriptorBufferInfo,
+        bricks_buffer_descriptor: &vk::DescriptorBufferInfo,
+        scene_instances_buffer_descriptor: &vk::DescriptorBufferInfo,
         visibility_buffer_descriptor: &vk::DescriptorBufferInfo,
-        num_instances: usize,
-        brick_size: u32,
+        detail_noise_descriptor: &vk::DescriptorImageInfo,
+        detail_materials_descriptor: &vk::DescriptorBufferInfo,
+        scratch: &ScratchAllocator, // Bindings 0 and 7 read uniforms and lights from it
+        uniforms: &SvoCubeUniforms,
+        depth: DepthConvention,
     ) -> RenderSvoCubes {
-        const NUM_CUBE_INDICES: usize = 3 * 3 * 2; // Front faces only
-        const NUM_CUBE_VERTICES: usize = 8;
-
         #[rustfmt::skip]
         let cube_indices = [
             0u32, 2, 1, 2, 3, 1,
@@ -64,18 +193,11 @@ impl RenderSvoCubes {
             7, 1, 3, 7, 5, 1,
         ];
 
-        let num_indices = num_instances * NUM_CUBE_INDICES;
-
-        let index_buffer_data: Vec<u32> = (0..num_indices)
-            .map(|i| {
-                let cube = i / NUM_CUBE_INDICES;
-                let cube_local = i % NUM_CUBE_INDICES;
-                cube_indices[cube_local] + cube as u32 * NUM_CUBE_VERTICES as u32
-            })
-            .collect();
+        // One cube drawn instanced, svo_main.vert places every instance at its brick
+        let index_buffer_data = &cube_indices[..NUM_CUBE_INDICES];
 
         let index_buffer_info = vk::BufferCreateInfo {
-            size: std::mem::size_of_val(&index_buffer_data[..]) as u64,
+            size: std::mem::size_of_val(index_buffer_data) as u64,
             usage: vk::BufferUsageFlags::TRANSFER_SRC,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
@@ -87,10 +209,10 @@ impl RenderSvoCubes {
             &index_buffer_info,
             MemoryLocation::CpuToGpu,
         );
-        index_buffer.copy_from_slice(&index_buffer_data[..], 0);
+        index_buffer.copy_from_slice(index_buffer_data, 0).unwrap();
 
         let index_buffer_gpu_info = vk::BufferCreateInfo {
-            size: std::mem::size_of_val(&index_buffer_data[..]) as u64,
+            size: std::mem::size_of_val(index_buffer_data) as u64,
             usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
@@ -103,38 +225,10 @@ impl RenderSvoCubes {
             MemoryLocation::GpuOnly,
         );
 
-        let uniform_buffer_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<SvoCubeUniforms>() as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let uniform_buffer = VkBuffer::new(
-            device,
-            allocator,
-            &uniform_buffer_info,
-            MemoryLocation::CpuToGpu,
-        );
-
-        let uniform_buffer_gpu_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<SvoCubeUniforms>() as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let uniform_buffer_gpu = VkBuffer::new(
-            device,
-            allocator,
-            &uniform_buffer_gpu_info,
-            MemoryLocation::GpuOnly,
-        );
-
         let desc_layout_bindings = [
             vk::DescriptorSetLayoutBinding {
                 binding: 0,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 descriptor_count: 1,
                 stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::VERTEX,
                 ..Default::default()
@@ -167,6 +261,34 @@ impl RenderSvoCubes {
                 stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::VERTEX,
                 ..Default::default()
             },
+            vk::DescriptorSetLayoutBinding {
+                binding: 5,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 6,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 7,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                ..Default::default()
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 8,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                ..Default::default()
+            },
         ];
         let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
             binding_count: desc_layout_bindings.len() as u32,
@@ -179,29 +301,19 @@ impl RenderSvoCubes {
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let descriptor_sets = {
-            let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-                descriptor_pool: *descriptor_pool,
-                descriptor_set_count: 1,
-                p_set_layouts: desc_set_layouts.as_ptr(),
-                ..Default::default()
-            };
-
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, desc_set_layouts)
+            .unwrap();
 
-        let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
-            buffer: uniform_buffer_gpu.buffer,
-            offset: 0,
-            range: mem::size_of::<SvoCubeUniforms>() as u64,
-        };
+        let uniform_buffer_descriptor = scratch.uniform_descriptor::<SvoCubeUniforms>();
+        let lights_buffer_descriptor = scratch.uniform_descriptor::<SvoLights>();
 
         let write_desc_sets = [
             vk::WriteDescriptorSet {
                 dst_set: descriptor_sets[0],
                 dst_binding: 0,
                 descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 p_buffer_info: &uniform_buffer_descriptor,
                 ..Default::default()
             },
@@ -210,7 +322,7 @@ impl RenderSvoCubes {
                 dst_binding: 1,
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
-                p_buffer_info: instances_buffer_descriptor,
+                p_buffer_info: bricks_buffer_descriptor,
                 ..Default::default()
             },
             vk::WriteDescriptorSet {
@@ -237,21 +349,52 @@ impl RenderSvoCubes {
                 p_buffer_info: octree_buffer_descriptor,
                 ..Default::default()
             },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 5,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: detail_noise_descriptor,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 6,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                p_buffer_info: detail_materials_descriptor,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 7,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                p_buffer_info: &lights_buffer_descriptor,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: 8,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: scene_instances_buffer_descriptor,
+                ..Default::default()
+            },
         ];
         unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
 
-        let layout_create_info = vk::PipelineLayoutCreateInfo {
-            set_layout_count: desc_set_layouts.len() as u32,
-            p_set_layouts: desc_set_layouts.as_ptr(),
-            ..Default::default()
-        };
-
-        let pipeline_layout =
-            unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
+        let pipeline_layout = PipelineLayoutBuilder::new()
+            .set_layouts(desc_set_layouts)
+            .push_constants::<SvoCubePushConstants>(vk::ShaderStageFlags::VERTEX)
+            .build(device)
+            .unwrap();
 
         // Use SVO-specific shaders
-        let mut vertex_spv_file = Cursor::new(&include_bytes!("../../../shader/svo_main_vert.spv")[..]);
-        let mut frag_spv_file = Cursor::new(&include_bytes!("../../../shader/svo_main_frag.spv")[..]);
+        let mut vertex_spv_file =
+            Cursor::new(&include_bytes!("../../../shader/svo_main_vert.spv")[..]);
+        let mut frag_spv_file =
+            Cursor::new(&include_bytes!("../../../shader/svo_main_frag.spv")[..]);
 
         let vertex_code =
             read_spv(&mut vertex_spv_file).expect("Failed to read vertex shader spv file");
@@ -277,295 +420,142 @@ impl RenderSvoCubes {
             unsafe { device.create_shader_module(&frag_shader_info, None) }
                 .expect("Fragment shader module error");
 
-        let shader_entry_name = CString::new("main").unwrap();
-        let shader_stage_create_infos = [
-            vk::PipelineShaderStageCreateInfo {
-                module: vertex_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: vk::ShaderStageFlags::VERTEX,
-                ..Default::default()
-            },
-            vk::PipelineShaderStageCreateInfo {
-                module: fragment_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: vk::ShaderStageFlags::FRAGMENT,
-                ..Default::default()
-            },
-        ];
-
-        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
-
-        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            ..Default::default()
-        };
-
-        let scissors = &[view_scissor.scissor];
-        let viewports = &[view_scissor.viewport];
-        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
-            viewport_count: viewports.len() as u32,
-            p_viewports: viewports.as_ptr(),
-            scissor_count: scissors.len() as u32,
-            p_scissors: scissors.as_ptr(),
-            ..Default::default()
-        };
-
-        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
-            cull_mode: vk::CullModeFlags::NONE,
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
-            ..Default::default()
-        };
-
-        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            ..Default::default()
-        };
-
-        let noop_stencil_state = vk::StencilOpState {
-            fail_op: vk::StencilOp::KEEP,
-            pass_op: vk::StencilOp::KEEP,
-            depth_fail_op: vk::StencilOp::KEEP,
-            compare_op: vk::CompareOp::ALWAYS,
-            ..Default::default()
-        };
-        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
-            depth_test_enable: 1,
-            depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
-            front: noop_stencil_state,
-            back: noop_stencil_state,
-            max_depth_bounds: 1.0,
-            ..Default::default()
-        };
-
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-        }];
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
-            logic_op_enable: vk::FALSE,
-            logic_op: vk::LogicOp::CLEAR,
-            attachment_count: color_blend_attachment_states.len() as u32,
-            p_attachments: color_blend_attachment_states.as_ptr(),
-            ..Default::default()
-        };
-
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
-            dynamic_state_count: dynamic_state.len() as u32,
-            p_dynamic_states: dynamic_state.as_ptr(),
-            ..Default::default()
-        };
-
-        let graphic_pipeline_infos = vk::GraphicsPipelineCreateInfo {
-            stage_count: shader_stage_create_infos.len() as u32,
-            p_stages: shader_stage_create_infos.as_ptr(),
-            p_vertex_input_state: &vertex_input_state_info,
-            p_input_assembly_state: &vertex_input_assembly_state_info,
-            p_viewport_state: &viewport_state_info,
-            p_rasterization_state: &rasterization_info,
-            p_multisample_state: &multisample_state_info,
-            p_depth_stencil_state: &depth_state_info,
-            p_color_blend_state: &color_blend_state,
-            p_dynamic_state: &dynamic_state_info,
-            layout: pipeline_layout,
-            render_pass: *render_pass,
-            ..Default::default()
-        };
-
-        let graphics_pipelines = unsafe {
-            device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[graphic_pipeline_infos],
-                None,
-            )
-        }
-        .unwrap();
-
-        let graphic_pipeline = graphics_pipelines[0];
+        let graphic_pipeline = GraphicsPipelineBuilder::new()
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader_module)
+            .view_scissor(view_scissor)
+            .depth_compare(depth.compare_op())
+            .build(device, pipeline_layout, *render_pass)
+            .unwrap();
 
         RenderSvoCubes {
             pipeline_layout,
             index_buffer,
             index_buffer_gpu,
-            uniform_buffer,
-            uniform_buffer_gpu,
             desc_set_layout,
             graphic_pipeline,
             descriptor_sets,
             vertex_shader_module,
             fragment_shader_module,
+            push_constants: SvoCubePushConstants {
+                world_to_screen: identity(),
+                camera_position: Vec3::from_scalar(0.0).to_4d(),
+            },
+            uniforms: *uniforms,
+            lights: SvoLights::default(),
+            indirect_draws: None,
+            visible_instances: None,
         }
     }
 
-    pub fn update(&self, uniforms: &SvoCubeUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+    pub fn update(&mut self, push_constants: &SvoCubePushConstants) {
+        self.push_constants = *push_constants;
+    }
+
+    pub fn update_lights(&mut self, lights: &SvoLights) {
+        self.lights = *lights;
     }
 
-    pub fn gpu_setup(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        let buffer_copy_regions = vk::BufferCopy {
+    pub fn gpu_setup(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: &vk::CommandBuffer,
+    ) {
+        let index_copy_regions = vk::BufferCopy {
             src_offset: 0,
             dst_offset: 0,
             size: self.index_buffer.size,
         };
 
-        let buffer_barrier = vk::BufferMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        let buffer_barriers = [vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::NONE,
+            src_access_mask: vk::AccessFlags2::NONE,
+            dst_stage_mask: vk::PipelineStageFlags2::COPY,
+            dst_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
             buffer: self.index_buffer_gpu.buffer,
             offset: 0,
-            size: buffer_copy_regions.size,
+            size: index_copy_regions.size,
             ..Default::default()
-        };
+        }];
 
-        let buffer_barrier_end = vk::BufferMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::INDEX_READ,
+        let buffer_barriers_end = [vk::BufferMemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COPY,
+            src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::INDEX_INPUT,
+            dst_access_mask: vk::AccessFlags2::INDEX_READ,
             buffer: self.index_buffer_gpu.buffer,
             offset: 0,
-            size: buffer_copy_regions.size,
+            size: index_copy_regions.size,
             ..Default::default()
-        };
+        }];
 
-        unsafe {
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &buffer_barriers,
+            &[],
+        );
 
+        unsafe {
             device.cmd_copy_buffer(
                 *command_buffer,
                 self.index_buffer.buffer,
                 self.index_buffer_gpu.buffer,
-                &[buffer_copy_regions],
-            );
-
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier_end],
-                &[],
+                &[index_copy_regions],
             );
         };
-    }
-
-    pub fn gpu_draw(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        let buffer_copy_regions = vk::BufferCopy {
-            src_offset: 0,
-            dst_offset: 0,
-            size: self.uniform_buffer.size,
-        };
-
-        let buffer_barrier = vk::BufferMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            buffer: self.uniform_buffer_gpu.buffer,
-            offset: 0,
-            size: buffer_copy_regions.size,
-            ..Default::default()
-        };
-
-        let buffer_barrier_end = vk::BufferMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::INDEX_READ,
-            buffer: self.uniform_buffer_gpu.buffer,
-            offset: 0,
-            size: buffer_copy_regions.size,
-            ..Default::default()
-        };
-
-        unsafe {
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
-
-            device.cmd_copy_buffer(
-                *command_buffer,
-                self.uniform_buffer.buffer,
-                self.uniform_buffer_gpu.buffer,
-                &[buffer_copy_regions],
-            );
 
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier_end],
-                &[],
-            );
-        }
+        barrier2(
+            device,
+            barrier_api,
+            *command_buffer,
+            &[],
+            &buffer_barriers_end,
+            &[],
+        );
     }
 
     pub fn gpu_draw_main_render_pass(
         &self,
         device: &Device,
         command_buffer: &vk::CommandBuffer,
-        argument_buffer: Option<&vk::Buffer>,
+        scratch: &mut ScratchAllocator,
+        indirect_draws: Option<IndirectDraws>,
     ) {
-        unsafe {
-            device.cmd_bind_descriptor_sets(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout,
-                0,
-                &self.descriptor_sets[..],
-                &[],
-            );
-
-            device.cmd_bind_pipeline(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.graphic_pipeline,
-            );
-
-            device.cmd_bind_index_buffer(
-                *command_buffer,
-                self.index_buffer_gpu.buffer,
-                0,
-                vk::IndexType::UINT32,
-            );
+        self.main_render_pass_draw(scratch, indirect_draws)
+            .record(device, command_buffer);
+    }
 
-            match argument_buffer {
-                Some(buffer) => {
-                    device.cmd_draw_indexed_indirect(*command_buffer, *buffer, 0, 1, 0);
-                }
-                None => {
-                    device.cmd_draw_indexed(
-                        *command_buffer,
-                        self.index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
-                        1,
-                        0,
-                        0,
-                        1,
-                    );
-                }
-            }
+    // Copies the handles, so the draw can be recorded on a worker thread. The uniforms and
+    // lights are staged here, call after scratch.begin_frame.
+    pub fn main_render_pass_draw(
+        &self,
+        scratch: &mut ScratchAllocator,
+        indirect_draws: Option<IndirectDraws>,
+    ) -> SvoCubesDraw {
+        let uniform_offset = scratch
+            .push_uniform(&self.uniforms)
+            .expect("Svo cube uniforms don't fit the scratch buffer");
+        let lights_offset = scratch
+            .push_uniform(&self.lights)
+            .expect("Svo lights don't fit the scratch buffer");
+        SvoCubesDraw {
+            pipeline_layout: self.pipeline_layout,
+            graphic_pipeline: self.graphic_pipeline,
+            descriptor_sets: self.descriptor_sets.clone(),
+            index_buffer: self.index_buffer_gpu.buffer,
+            num_cubes: self.visible_instances.unwrap_or(0) as u32,
+            indirect_draws,
+            push_constants: self.push_constants,
+            uniform_offset,
+            lights_offset,
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             device.destroy_pipeline(self.graphic_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
@@ -573,9 +563,35 @@ impl RenderSvoCubes {
             device.destroy_shader_module(self.fragment_shader_module, None);
             self.index_buffer.destroy(device, allocator);
             self.index_buffer_gpu.destroy(device, allocator);
-            self.uniform_buffer.destroy(device, allocator);
-            self.uniform_buffer_gpu.destroy(device, allocator);
             device.destroy_descriptor_set_layout(self.desc_set_layout, None);
         }
     }
-}
\ No newline at end of file
+}
+
+impl RenderModule for RenderSvoCubes {
+    fn create(
+        &mut self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.gpu_setup(device, barrier_api, &command_buffer);
+    }
+
+    fn record_main_pass(&mut self, frame: &mut FrameContext) -> Option<MainPassDraw> {
+        let scratch = frame
+            .scratch
+            .as_mut()
+            .expect("Svo cubes need the frame scratch buffer");
+        let draw = self.main_render_pass_draw(scratch, self.indirect_draws);
+        let view_scissor = frame.view_scissor;
+        Some(Box::new(move |device, command_buffer| {
+            view_scissor.set(device, command_buffer);
+            draw.record(device, &command_buffer);
+        }))
+    }
+
+    fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        RenderSvoCubes::destroy(self, device, allocator);
+    }
+}
@@ -0,0 +1,367 @@
+// Scene description loaded with --scene FILE and reloaded when the file changes on disk.
+// Every [[instance]] table places one copy of the volume:
+//
+//   [[instance]]
+//   translation = [0.0, 0.0, 0.0]
+//   scale = 1.0
+//   material = 0
+//
+// [[light]] tables light the SVO shading (see lights.rs). Keys left out keep their defaults,
+// angles are half angles of the spot cone in degrees:
+//
+//   [[light]]
+//   type = "spot"  # "directional", "point" or "spot"
+//   position = [0.0, 10.0, 0.0]
+//   direction = [0.0, -1.0, 0.0]
+//   color = [1.0, 0.9, 0.8]
+//   intensity = 50.0
+//   range = 30.0
+//   inner_angle = 20.0
+//   outer_angle = 30.0
+//
+// An optional [post] table turns on the post-processing chain (see post_process.rs). Effects
+// without a table are off, keys left out keep their defaults:
+//
+//   [post]
+//   exposure = 1.0
+//   tonemap = "aces"  # or "reinhard"
+//
+//   [post.dof]
+//   focus_distance = 50.0
+//   focus_range = 50.0
+//   max_radius = 8.0  # Pixels
+//
+//   [post.vignette]
+//   strength = 0.4
+//   radius = 0.6
+//   softness = 0.8
+
+use crate::instances::*;
+use crate::lights::*;
+use crate::minivector::*;
+use crate::post_process::*;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    InvalidValue(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse(err) => write!(f, "{}", err),
+            SceneError::InvalidValue(key) => write!(f, "Invalid value for {} in scene", key),
+        }
+    }
+}
+
+// TOML integers are accepted for float values ("scale = 2")
+fn float_value(value: &toml::Value) -> Option<f32> {
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|value| value as f64))
+        .map(|value| value as f32)
+}
+
+fn vec3_value(value: &toml::Value) -> Option<Vec3> {
+    let array = value.as_array().filter(|array| array.len() == 3)?;
+    let xyz: Vec<f32> = array.iter().map(float_value).collect::<Option<_>>()?;
+    Some(Vec3 {
+        x: xyz[0],
+        y: xyz[1],
+        z: xyz[2],
+    })
+}
+
+fn parse_instance(table: &toml::Table) -> Result<SceneInstance, SceneError> {
+    let mut instance = SceneInstance::default();
+    for (key, value) in table.iter() {
+        let invalid = || SceneError::InvalidValue(key.clone());
+        match key.as_str() {
+            "translation" => instance.translation = vec3_value(value).ok_or_else(invalid)?,
+            "scale" => instance.scale = float_value(value).ok_or_else(invalid)?,
+            "material" => {
+                let material = value.as_integer().filter(|&material| material >= 0);
+                instance.material = material.ok_or_else(invalid)? as u32;
+            }
+            _ => {}
+        }
+    }
+    Ok(instance)
+}
+
+fn parse_light(table: &toml::Table) -> Result<Light, SceneError> {
+    let mut light = Light::default();
+    let invalid = |key: &str| SceneError::InvalidValue(format!("light.{}", key));
+    if let Some(value) = table.get("type") {
+        light.light_type = value
+            .as_str()
+            .and_then(LightType::from_name)
+            .ok_or_else(|| invalid("type"))?;
+    }
+    for (key, target) in [
+        ("position", &mut light.position),
+        ("direction", &mut light.direction),
+        ("color", &mut light.color),
+    ] {
+        if let Some(value) = table.get(key) {
+            *target = vec3_value(value).ok_or_else(|| invalid(key))?;
+        }
+    }
+    if light.direction.length() == 0.0 {
+        return Err(invalid("direction"));
+    }
+    light.direction = light.direction.normalize();
+
+    read_float(table, "light", "intensity", &mut light.intensity)?;
+    read_float(table, "light", "range", &mut light.range)?;
+    let mut inner_degrees = light.inner_angle.to_degrees();
+    let mut outer_degrees = light.outer_angle.to_degrees();
+    read_float(table, "light", "inner_angle", &mut inner_degrees)?;
+    read_float(table, "light", "outer_angle", &mut outer_degrees)?;
+    if light.intensity < 0.0 {
+        return Err(invalid("intensity"));
+    }
+    if light.range <= 0.0 {
+        return Err(invalid("range"));
+    }
+    if !(0.0..90.0).contains(&outer_degrees) {
+        return Err(invalid("outer_angle"));
+    }
+    if !(0.0..=outer_degrees).contains(&inner_degrees) {
+        return Err(invalid("inner_angle"));
+    }
+    light.inner_angle = inner_degrees.to_radians();
+    light.outer_angle = outer_degrees.to_radians();
+    Ok(light)
+}
+
+// Overwrites value with table[key] when present, path names the table in errors
+fn read_float(
+    table: &toml::Table,
+    path: &str,
+    key: &str,
+    value: &mut f32,
+) -> Result<(), SceneError> {
+    if let Some(item) = table.get(key) {
+        *value = float_value(item)
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| SceneError::InvalidValue(format!("{}.{}", path, key)))?;
+    }
+    Ok(())
+}
+
+fn sub_table<'a>(
+    table: &'a toml::Table,
+    path: &str,
+    key: &str,
+) -> Result<Option<&'a toml::Table>, SceneError> {
+    table
+        .get(key)
+        .map(|value| {
+            value
+                .as_table()
+                .ok_or_else(|| SceneError::InvalidValue(format!("{}.{}", path, key)))
+        })
+        .transpose()
+}
+
+fn parse_post(table: &toml::Table) -> Result<PostSettings, SceneError> {
+    let mut settings = PostSettings::default();
+    read_float(table, "post", "exposure", &mut settings.exposure)?;
+    if let Some(value) = table.get("tonemap") {
+        settings.tonemap = value
+            .as_str()
+            .and_then(Tonemap::from_name)
+            .ok_or_else(|| SceneError::InvalidValue("post.tonemap".to_string()))?;
+    }
+    if let Some(dof_table) = sub_table(table, "post", "dof")? {
+        let mut dof = DofSettings::default();
+        read_float(
+            dof_table,
+            "post.dof",
+            "focus_distance",
+            &mut dof.focus_distance,
+        )?;
+        read_float(dof_table, "post.dof", "focus_range", &mut dof.focus_range)?;
+        read_float(dof_table, "post.dof", "max_radius", &mut dof.max_radius)?;
+        if dof.focus_range <= 0.0 {
+            return Err(SceneError::InvalidValue("post.dof.focus_range".to_string()));
+        }
+        settings.dof = Some(dof);
+    }
+    if let Some(vignette_table) = sub_table(table, "post", "vignette")? {
+        let mut vignette = VignetteSettings::default();
+        read_float(
+            vignette_table,
+            "post.vignette",
+            "strength",
+            &mut vignette.strength,
+        )?;
+        read_float(
+            vignette_table,
+            "post.vignette",
+            "radius",
+            &mut vignette.radius,
+        )?;
+        read_float(
+            vignette_table,
+            "post.vignette",
+            "softness",
+            &mut vignette.softness,
+        )?;
+        settings.vignette = Some(vignette);
+    }
+    Ok(settings)
+}
+
+// None when the scene has no [post] table
+pub fn parse_post_settings(text: &str) -> Result<Option<PostSettings>, SceneError> {
+    let table: toml::Table = text.parse().map_err(SceneError::Parse)?;
+    match table.get("post") {
+        Some(value) => value
+            .as_table()
+            .ok_or_else(|| SceneError::InvalidValue("post".to_string()))
+            .and_then(parse_post)
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+pub fn parse_lights(text: &str) -> Result<Vec<Light>, SceneError> {
+    let table: toml::Table = text.parse().map_err(SceneError::Parse)?;
+    let lights = match table.get("light") {
+        Some(value) => value
+            .as_array()
+            .ok_or_else(|| SceneError::InvalidValue("light".to_string()))?,
+        None => return Ok(Vec::new()),
+    };
+    lights
+        .iter()
+        .map(|value| {
+            value
+                .as_table()
+                .ok_or_else(|| SceneError::InvalidValue("light".to_string()))
+                .and_then(parse_light)
+        })
+        .collect()
+}
+
+pub fn parse_scene(text: &str) -> Result<Vec<SceneInstance>, SceneError> {
+    let table: toml::Table = text.parse().map_err(SceneError::Parse)?;
+    let instances = match table.get("instance") {
+        Some(value) => value
+            .as_array()
+            .ok_or_else(|| SceneError::InvalidValue("instance".to_string()))?,
+        None => return Ok(Vec::new()),
+    };
+    instances
+        .iter()
+        .map(|value| {
+            value
+                .as_table()
+                .ok_or_else(|| SceneError::InvalidValue("instance".to_string()))
+                .and_then(parse_instance)
+        })
+        .collect()
+}
+
+// Changes between two versions of a scene, instances are matched by index
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SceneDiff {
+    pub moved: usize,
+    pub material_changed: usize,
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl SceneDiff {
+    pub fn new(old: &[SceneInstance], new: &[SceneInstance]) -> SceneDiff {
+        let mut diff = SceneDiff {
+            added: new.len().saturating_sub(old.len()),
+            removed: old.len().saturating_sub(new.len()),
+            ..Default::default()
+        };
+        for (old, new) in old.iter().zip(new.iter()) {
+            // Instances hidden from the console count as moved, a reload shows them again
+            let moved = old.translation != new.translation || old.scale != new.scale;
+            if moved || old.hidden != new.hidden {
+                diff.moved += 1;
+            }
+            if old.material != new.material {
+                diff.material_changed += 1;
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SceneDiff::default()
+    }
+
+    // Moves and material changes only rewrite instance data, the instance count sizes the
+    // culling and draw buffers
+    pub fn is_incremental(&self) -> bool {
+        self.added == 0 && self.removed == 0
+    }
+}
+
+// Polls the modification time of the scene file
+pub struct SceneWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl SceneWatcher {
+    pub fn new(path: &Path) -> SceneWatcher {
+        SceneWatcher {
+            path: path.to_path_buf(),
+            modified: modified_time(path),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self) -> Result<Vec<SceneInstance>, SceneError> {
+        let text = fs::read_to_string(&self.path).map_err(SceneError::Io)?;
+        parse_scene(&text)
+    }
+
+    pub fn load_post_settings(&self) -> Result<Option<PostSettings>, SceneError> {
+        let text = fs::read_to_string(&self.path).map_err(SceneError::Io)?;
+        parse_post_settings(&text)
+    }
+
+    pub fn load_lights(&self) -> Result<Vec<Light>, SceneError> {
+        let text = fs::read_to_string(&self.path).map_err(SceneError::Io)?;
+        parse_lights(&text)
+    }
+
+    // The re-parsed scene when the file changed since the last call. Editors that replace
+    // the file can leave it missing for a moment, that isn't reported as a change.
+    pub fn poll(&mut self) -> Option<Result<Vec<SceneInstance>, SceneError>> {
+        let modified = modified_time(&self.path)?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        Some(self.load())
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
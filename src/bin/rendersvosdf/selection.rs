@@ -0,0 +1,207 @@
+// Marquee selection: dragging with the right mouse button selects the scene instances and
+// bricks whose world bounds project into the dragged screen rectangle. Console commands act
+// on the selection in bulk:
+//   hide | show | isolate | colorize MATERIAL | stats | select clear
+// and `show all` shows every instance. Hidden instances stay selectable, so a marquee over
+// where they were can show them again. The hidden flags become the VisibilityMask.
+
+use std::fmt;
+
+use crate::instances::*;
+use crate::minivector::*;
+use rust_test::detail_noise::MAX_DETAIL_MATERIALS;
+use rust_test::sdf::LEVEL_ZERO;
+use rust_test::svosdf::*;
+
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    pub instances: Vec<usize>,       // Indices into the scene instances
+    pub bricks: Vec<(usize, usize)>, // (scene instance, brick index), instance major
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SelectionCommand {
+    Hide,
+    Show,
+    Isolate, // Hide the instances that aren't selected
+    ShowAll,
+    Colorize(u32),
+    Stats,
+    Clear,
+}
+
+#[derive(Debug)]
+pub enum SelectionCommandError {
+    InvalidMaterial(String),
+}
+
+impl fmt::Display for SelectionCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelectionCommandError::InvalidMaterial(value) => write!(
+                f,
+                "Invalid material '{}' (0 to {})",
+                value,
+                MAX_DETAIL_MATERIALS - 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SelectionCommandError {}
+
+// Parsed selection command, None for other commands
+pub fn selection_command(line: &str) -> Option<Result<SelectionCommand, SelectionCommandError>> {
+    let mut words = line.split_whitespace();
+    let command = match (words.next()?, words.next()) {
+        ("hide", None) => SelectionCommand::Hide,
+        ("show", None) => SelectionCommand::Show,
+        ("show", Some("all")) => SelectionCommand::ShowAll,
+        ("isolate", None) => SelectionCommand::Isolate,
+        ("stats", None) => SelectionCommand::Stats,
+        ("select", Some("clear")) => SelectionCommand::Clear,
+        ("colorize", value) => {
+            let value = value.unwrap_or("");
+            return Some(
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&material| (material as usize) < MAX_DETAIL_MATERIALS)
+                    .map(SelectionCommand::Colorize)
+                    .ok_or_else(|| SelectionCommandError::InvalidMaterial(value.to_string())),
+            );
+        }
+        _ => return None,
+    };
+    Some(Ok(command))
+}
+
+// World box of the whole volume placed by a scene instance
+pub fn instance_bounds(svo_sdf: &SvoSdf, instance: &SceneInstance) -> Aabb {
+    let size = svo_sdf.header.world_size();
+    let transform = instance.transform();
+    Aabb::new(
+        transform.translation,
+        transform.transform_point(Vec3 {
+            x: size.0,
+            y: size.1,
+            z: size.2,
+        }),
+    )
+}
+
+// World box of one brick of a scene instance, the same placement as instance_data
+pub fn brick_bounds(svo_sdf: &SvoSdf, instance: &SceneInstance, brick_index: usize) -> Aabb {
+    let brick = &svo_sdf.bricks[brick_index];
+    let spacing = svo_sdf.header.spacing;
+    let local = |offset: u32| Vec3 {
+        x: (brick.position.x + offset) as f32 * spacing.0,
+        y: (brick.position.y + offset) as f32 * spacing.1,
+        z: (brick.position.z + offset) as f32 * spacing.2,
+    };
+    let transform = instance.transform();
+    Aabb::new(
+        transform.transform_point(local(0)),
+        transform.transform_point(local(brick.size)),
+    )
+}
+
+impl Selection {
+    // Bricks (of visible_bricks) whose screen rectangle intersects the marquee, an instance is
+    // selected when any of its bricks is. Instances whose whole volume misses the marquee
+    // are skipped without projecting their bricks.
+    pub fn from_marquee(
+        svo_sdf: &SvoSdf,
+        visible_bricks: &[usize],
+        scene_instances: &[SceneInstance],
+        world_to_screen: &Mat4x4,
+        marquee: &ScreenRect,
+    ) -> Selection {
+        let hits = |bounds: &Aabb| {
+            bounds
+                .screen_rect(world_to_screen)
+                .is_some_and(|rect| rect.intersects(marquee))
+        };
+        let mut selection = Selection::default();
+        for (instance_index, instance) in scene_instances.iter().enumerate() {
+            if !hits(&instance_bounds(svo_sdf, instance)) {
+                continue;
+            }
+            let count = selection.bricks.len();
+            for &brick_index in visible_bricks {
+                if hits(&brick_bounds(svo_sdf, instance, brick_index)) {
+                    selection.bricks.push((instance_index, brick_index));
+                }
+            }
+            if selection.bricks.len() > count {
+                selection.instances.push(instance_index);
+            }
+        }
+        selection
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    // Changes the hidden flags or materials of the scene instances, stats and select clear
+    // change none
+    pub fn edit(&self, command: SelectionCommand, scene_instances: &mut [SceneInstance]) {
+        for (i, instance) in scene_instances.iter_mut().enumerate() {
+            // Instances are selected in scene order
+            let selected = self.instances.binary_search(&i).is_ok();
+            match command {
+                SelectionCommand::Hide if selected => instance.hidden = true,
+                SelectionCommand::Show if selected => instance.hidden = false,
+                SelectionCommand::Isolate => instance.hidden = !selected,
+                SelectionCommand::ShowAll => instance.hidden = false,
+                SelectionCommand::Colorize(material) if selected => instance.material = material,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn stats(&self, svo_sdf: &SvoSdf, scene_instances: &[SceneInstance]) -> SelectionStats {
+        let mut stats = SelectionStats {
+            instances: self.instances.len(),
+            hidden: self
+                .instances
+                .iter()
+                .filter(|&&i| scene_instances[i].hidden)
+                .count(),
+            bricks: self.bricks.len(),
+            ..SelectionStats::default()
+        };
+        let mut range: Option<(u16, u16)> = None;
+        for &(instance_index, brick_index) in &self.bricks {
+            let instance = &scene_instances[instance_index];
+            let size = svo_sdf.bricks[brick_index].size as u64;
+            stats.voxels += size * size * size;
+
+            let brick_range = svo_sdf.brick_ranges[brick_index];
+            range = Some(
+                range.map_or((brick_range.min, brick_range.max), |(min, max)| {
+                    (min.min(brick_range.min), max.max(brick_range.max))
+                }),
+            );
+
+            let bounds = brick_bounds(svo_sdf, instance, brick_index);
+            stats.bounds = Some(stats.bounds.map_or(bounds, |b| b.union(&bounds)));
+        }
+        // Distances in world units of the volume, before the instance scale
+        let step = svo_sdf.header.distance_per_step();
+        let distance = |value: u16| (value as i32 - LEVEL_ZERO as i32) as f32 * step;
+        stats.distance_range = range.map(|(min, max)| (distance(min), distance(max)));
+        stats
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SelectionStats {
+    pub instances: usize,
+    pub hidden: usize,
+    pub bricks: usize,
+    pub voxels: u64,
+    pub distance_range: Option<(f32, f32)>,
+    pub bounds: Option<Aabb>,
+}
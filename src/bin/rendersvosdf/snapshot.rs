@@ -0,0 +1,281 @@
+// Viewer state saved to a single file so a debugging session can resume where it left off.
+// The console command `snapshot [FILE]` or F5 saves, `--restore FILE` on launch restores:
+// the command line the viewer was started with, the console language, the camera, the
+// projection and the scene instances and lights as they were when saved (scene reloads
+// included). Settings that only come from the command line (atlas order, quality, detail,
+// memory profile, depth convention) are restored through the saved arguments. The viewer
+// keeps no brick residency set or edit history, so there is none to save; the version number
+// leaves room for adding them.
+
+use crate::instances::*;
+use crate::lights::*;
+use crate::minivector::*;
+use crate::projection_settings::*;
+use rust_test::serialization::*;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_SNAPSHOT_FILE: &str = "snapshot.bin";
+
+const SNAPSHOT_MAGIC: u32 = u32::from_le_bytes(*b"RSNP");
+// Version 2 added the hidden flag of the scene instances
+const SNAPSHOT_VERSION: u32 = 2;
+
+#[derive(Clone, Debug)]
+pub struct ViewerSnapshot {
+    pub args: Vec<String>, // Without the program name and --restore
+    pub language: String,
+    pub camera_position: Vec3,
+    pub camera_orientation: Quat,
+    pub projection: ProjectionSettings,
+    pub scene_instances: Vec<SceneInstance>,
+    pub scene_lights: Vec<Light>,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn store_string(storer: &mut StorerVec, s: &str) {
+    storer.store_u32(s.len() as u32);
+    storer.store_array_u8(s.as_bytes());
+}
+
+fn store_vec3(storer: &mut StorerVec, v: Vec3) {
+    storer.store_array_f32(&[v.x, v.y, v.z]);
+}
+
+// Loader with a bounds check, a truncated file is an error instead of a panic
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    loader: Loader,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn check(&self, bytes: usize) -> io::Result<()> {
+        if self.loader.offset + bytes > self.bytes.len() {
+            return Err(invalid_data("truncated snapshot"));
+        }
+        Ok(())
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.check(4)?;
+        Ok(self.loader.load_u32(self.bytes))
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        self.check(4)?;
+        Ok(self.loader.load_f32(self.bytes))
+    }
+
+    fn vec3(&mut self) -> io::Result<Vec3> {
+        Ok(Vec3 {
+            x: self.f32()?,
+            y: self.f32()?,
+            z: self.f32()?,
+        })
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        self.check(len)?;
+        let start = self.loader.offset;
+        self.loader.offset += len;
+        String::from_utf8(self.bytes[start..start + len].to_vec())
+            .map_err(|_| invalid_data("snapshot string isn't UTF-8"))
+    }
+
+    // Element count of a list whose elements take at least min_bytes, checked against the
+    // remaining bytes before anything is allocated
+    fn count(&mut self, min_bytes: usize) -> io::Result<usize> {
+        let count = self.u32()? as usize;
+        self.check(count.saturating_mul(min_bytes))?;
+        Ok(count)
+    }
+}
+
+impl ViewerSnapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut storer = StorerVec::new();
+        storer.store_u32(SNAPSHOT_MAGIC);
+        storer.store_u32(SNAPSHOT_VERSION);
+
+        storer.store_u32(self.args.len() as u32);
+        for arg in &self.args {
+            store_string(&mut storer, arg);
+        }
+        store_string(&mut storer, &self.language);
+
+        store_vec3(&mut storer, self.camera_position);
+        let q = self.camera_orientation;
+        storer.store_array_f32(&[q.x, q.y, q.z, q.w]);
+
+        let projection = &self.projection;
+        storer.store_u32(match projection.projection_type {
+            ProjectionType::Perspective => 0,
+            ProjectionType::Orthographic => 1,
+        });
+        storer.store_array_f32(&[
+            projection.fov_degrees,
+            projection.near,
+            projection.far,
+            projection.ortho_height,
+        ]);
+
+        storer.store_u32(self.scene_instances.len() as u32);
+        for instance in &self.scene_instances {
+            store_vec3(&mut storer, instance.translation);
+            storer.store_f32(instance.scale);
+            storer.store_u32(instance.material);
+            storer.store_u32(instance.hidden as u32);
+        }
+
+        storer.store_u32(self.scene_lights.len() as u32);
+        for light in &self.scene_lights {
+            storer.store_u32(light.light_type.gpu_type());
+            store_vec3(&mut storer, light.position);
+            store_vec3(&mut storer, light.direction);
+            store_vec3(&mut storer, light.color);
+            storer.store_array_f32(&[
+                light.intensity,
+                light.range,
+                light.inner_angle,
+                light.outer_angle,
+            ]);
+        }
+        storer.v
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<ViewerSnapshot> {
+        let mut reader = SnapshotReader {
+            bytes,
+            loader: Loader::new(),
+        };
+        if reader.u32()? != SNAPSHOT_MAGIC {
+            return Err(invalid_data("not a rendersvosdf snapshot"));
+        }
+        let version = reader.u32()?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(invalid_data(&format!(
+                "snapshot version {}, expected at most {}",
+                version, SNAPSHOT_VERSION
+            )));
+        }
+
+        let arg_count = reader.count(4)?;
+        let args = (0..arg_count)
+            .map(|_| reader.string())
+            .collect::<io::Result<Vec<String>>>()?;
+        let language = reader.string()?;
+
+        let camera_position = reader.vec3()?;
+        let camera_orientation = Quat {
+            x: reader.f32()?,
+            y: reader.f32()?,
+            z: reader.f32()?,
+            w: reader.f32()?,
+        };
+
+        let projection_type = match reader.u32()? {
+            0 => ProjectionType::Perspective,
+            1 => ProjectionType::Orthographic,
+            _ => return Err(invalid_data("unknown projection type")),
+        };
+        let projection = ProjectionSettings {
+            projection_type,
+            fov_degrees: reader.f32()?,
+            near: reader.f32()?,
+            far: reader.f32()?,
+            ortho_height: reader.f32()?,
+            // Only set on the command line, pipelines are built for it
+            depth: ProjectionSettings::from_args(&args).depth,
+        };
+
+        let instance_bytes = if version >= 2 { 24 } else { 20 };
+        let instance_count = reader.count(instance_bytes)?;
+        let scene_instances = (0..instance_count)
+            .map(|_| {
+                Ok(SceneInstance {
+                    translation: reader.vec3()?,
+                    scale: reader.f32()?,
+                    material: reader.u32()?,
+                    hidden: version >= 2 && reader.u32()? != 0,
+                })
+            })
+            .collect::<io::Result<Vec<SceneInstance>>>()?;
+
+        let light_count = reader.count(56)?;
+        let scene_lights = (0..light_count)
+            .map(|_| {
+                let light_type = match reader.u32()? {
+                    0 => LightType::Directional,
+                    1 => LightType::Point,
+                    2 => LightType::Spot,
+                    _ => return Err(invalid_data("unknown light type")),
+                };
+                Ok(Light {
+                    light_type,
+                    position: reader.vec3()?,
+                    direction: reader.vec3()?,
+                    color: reader.vec3()?,
+                    intensity: reader.f32()?,
+                    range: reader.f32()?,
+                    inner_angle: reader.f32()?,
+                    outer_angle: reader.f32()?,
+                })
+            })
+            .collect::<io::Result<Vec<Light>>>()?;
+
+        Ok(ViewerSnapshot {
+            args,
+            language,
+            camera_position,
+            camera_orientation,
+            projection,
+            scene_instances,
+            scene_lights,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &Path) -> io::Result<ViewerSnapshot> {
+        ViewerSnapshot::from_bytes(&fs::read(path)?)
+    }
+
+    // Arguments of a restored launch: the new command line first, so options given there
+    // take precedence (arguments are looked up by first occurrence), then the saved ones
+    pub fn restore_args(&self, args: &[String]) -> Vec<String> {
+        let mut restored = without_restore(args);
+        restored.extend(self.args.iter().cloned());
+        restored
+    }
+}
+
+// Command line without --restore FILE
+pub fn without_restore(args: &[String]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--restore" {
+            iter.next();
+        } else {
+            result.push(arg.clone());
+        }
+    }
+    result
+}
+
+// File of a `snapshot [FILE]` console command, None for other commands
+pub fn snapshot_command(line: &str) -> Option<&str> {
+    let mut parts = line.split_whitespace();
+    if parts.next()? != "snapshot" {
+        return None;
+    }
+    Some(parts.next().unwrap_or(DEFAULT_SNAPSHOT_FILE))
+}
@@ -1,13 +1,22 @@
-use ash::{vk, Device};
+use ash::{vk, Device, Instance};
 use std::default::Default;
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::localization::*;
+use crate::minivector::*;
 use crate::vulkan_helpers::*;
+use bytemuck::cast_slice;
+use rust_test::block_compression::*;
+use rust_test::device_capabilities::*;
+use rust_test::memory_profile::*;
+use rust_test::packing::*;
+use rust_test::space_filling_curve::*;
 use rust_test::svosdf::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct OctreeNodeGpu {
     pub bounds_min: [u32; 3],
     pub bounds_max: [u32; 3],
@@ -15,63 +24,300 @@ pub struct OctreeNodeGpu {
     pub child_mask: u32,
     pub children_offset: u32,
     pub is_leaf: u32,
-    pub _padding: [u32; 2],
+    pub subtree_size: u32, // Nodes up to the next sibling, children follow depth first
+    pub atlas_slot: u32,   // Of the brick, see AtlasOrder
+}
+
+// std430 stride of OctreeNode in svo_main.vert and post_ao.comp
+const _: () = assert!(std::mem::size_of::<OctreeNodeGpu>() == 48);
+unsafe impl Zeroable for OctreeNodeGpu {}
+unsafe impl Pod for OctreeNodeGpu {}
+
+// Order of the bricks in the atlas. Hilbert keeps bricks that are neighbors in the volume
+// close in the atlas, which helps the texture cache when adjacent rays sample adjacent bricks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtlasOrder {
+    Linear, // Brick order of the file
+    Hilbert,
+}
+
+impl AtlasOrder {
+    // --atlas-order linear|hilbert, Hilbert by default
+    pub fn from_args(args: &[String]) -> AtlasOrder {
+        match args
+            .iter()
+            .position(|arg| arg == "--atlas-order")
+            .and_then(|i| args.get(i + 1))
+            .map(|value| value.as_str())
+        {
+            Some("linear") => AtlasOrder::Linear,
+            _ => AtlasOrder::Hilbert,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AtlasOrder::Linear => "linear",
+            AtlasOrder::Hilbert => "hilbert",
+        }
+    }
+
+    // Atlas slot of every brick
+    pub fn atlas_slots(&self, svo_sdf: &SvoSdf) -> Vec<u32> {
+        match self {
+            AtlasOrder::Linear => (0..svo_sdf.bricks.len() as u32).collect(),
+            AtlasOrder::Hilbert => {
+                let brick_size = svo_sdf.brick_size.max(1);
+                let cells: Vec<(u32, u32, u32)> = svo_sdf
+                    .bricks
+                    .iter()
+                    .map(|brick| (brick.position / brick_size).into())
+                    .collect();
+                let mut slots = vec![0; cells.len()];
+                for (slot, brick) in hilbert_order(&cells).into_iter().enumerate() {
+                    slots[brick] = slot as u32;
+                }
+                slots
+            }
+        }
+    }
+}
+
+// Packed atlases place the bricks at their atlas slots. Sparse atlases span the whole volume
+// with every brick at its volume position, only the pages holding bricks are committed.
+pub enum BrickAtlas {
+    Packed {
+        image: VkImage,
+        bricks_per_row: u32,
+        max_brick_size: u32,
+    },
+    Sparse(VkSparseImage),
+}
+
+impl BrickAtlas {
+    pub fn image(&self) -> &VkImage {
+        match self {
+            BrickAtlas::Packed { image, .. } => image,
+            BrickAtlas::Sparse(sparse_image) => &sparse_image.image,
+        }
+    }
+
+    // Texel offset of a brick
+    fn brick_offset(&self, brick: &Brick, slot: u32) -> vk::Offset3D {
+        match self {
+            BrickAtlas::Packed {
+                bricks_per_row,
+                max_brick_size,
+                ..
+            } => {
+                let brick_x = slot % bricks_per_row;
+                let brick_y = (slot / bricks_per_row) % bricks_per_row;
+                let brick_z = slot / (bricks_per_row * bricks_per_row);
+                vk::Offset3D {
+                    x: (brick_x * max_brick_size) as i32,
+                    y: (brick_y * max_brick_size) as i32,
+                    z: (brick_z * max_brick_size) as i32,
+                }
+            }
+            BrickAtlas::Sparse(_) => vk::Offset3D {
+                x: brick.position.x as i32,
+                y: brick.position.y as i32,
+                z: brick.position.z as i32,
+            },
+        }
+    }
+}
+
+// Bricks are sampled with trilinear filtering. The format falls back when the device can't
+// filter the preferred one, 16 bit bricks keep their size as half floats if possible.
+// BC4 bricks also need 3D BC images and bricks on 4 voxel boundaries, copies into compressed
+// images are whole blocks.
+fn filterable_brick_format(
+    physical_device: (&Instance, vk::PhysicalDevice, &DeviceCapabilities),
+    preferred: BrickFormat,
+    svo_sdf: &SvoSdf,
+) -> BrickFormat {
+    let (instance, pdevice, capabilities) = physical_device;
+    let features =
+        vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+    let block_aligned = || {
+        svo_sdf.bricks.iter().all(|brick| {
+            let position = brick.position;
+            [brick.size, position.x, position.y, position.z]
+                .iter()
+                .all(|v| v % 4 == 0)
+        })
+    };
+    let image_3d_supported = |format: vk::Format| unsafe {
+        instance
+            .get_physical_device_image_format_properties(
+                pdevice,
+                format,
+                vk::ImageType::TYPE_3D,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+                vk::ImageCreateFlags::empty(),
+            )
+            .is_ok()
+    };
+    let supported = |format: &BrickFormat| {
+        let vk_format = format.vk_format();
+        let compressed_supported = *format != BrickFormat::Bc4
+            || (capabilities.texture_compression_bc
+                && block_aligned()
+                && image_3d_supported(vk_format));
+        compressed_supported
+            && format_supports(
+                instance,
+                pdevice,
+                vk_format,
+                vk::ImageTiling::OPTIMAL,
+                features,
+            )
+    };
+    let format = std::iter::once(&preferred)
+        .chain(preferred.fallbacks())
+        .find(|format| supported(format))
+        .copied()
+        .expect("No filterable brick atlas format");
+    if format != preferred {
+        let key = if preferred == BrickFormat::Bc4 {
+            "viewer.bc4_bricks_fallback"
+        } else {
+            "viewer.brick_format_fallback"
+        };
+        println!(
+            "{}",
+            tr_args(
+                key,
+                &[
+                    ("format", &format!("{:?}", preferred.vk_format())),
+                    ("fallback", &format!("{:?}", format.vk_format())),
+                ]
+            )
+        );
+    }
+    format
 }
 
 pub struct SvoTexture {
-    pub brick_texture: VkImage,
-    pub brick_upload_buffer: VkBuffer,
-    pub octree_buffer: VkBuffer,
+    pub brick_atlas: BrickAtlas,
+    pub brick_upload_buffer: TypedBuffer<u8>,
+    pub octree_buffer: TypedBuffer<OctreeNodeGpu>,
     pub sampler: vk::Sampler,
     pub brick_view: vk::ImageView,
     pub brick_texture_descriptor: vk::DescriptorImageInfo,
     pub octree_buffer_descriptor: vk::DescriptorBufferInfo,
     pub total_brick_voxels: usize,
+    pub brick_format: BrickFormat,
+    pub brick_curve: BrickCurve, // Decode curve of the atlas codes, see svo_main.frag
+    pub atlas_slots: Vec<u32>,   // Parallel to bricks
 }
 
 impl SvoTexture {
+    // sparse_atlas asks for a sparse atlas, packed is used when the device can't create it.
+    // The brick format falls back when the device can't filter the one the profile picked.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
+        allocator: &mut TrackingAllocator,
         svo_sdf: &SvoSdf,
+        memory_profile: &MemoryProfile,
+        budget: &GpuBudget,
+        sampler_cache: &mut SamplerCache,
+        atlas_order: AtlasOrder,
+        physical_device: (&Instance, vk::PhysicalDevice, &DeviceCapabilities),
+        sparse_atlas: bool,
     ) -> SvoTexture {
+        let (instance, pdevice, capabilities) = physical_device;
         // Calculate total voxels across all bricks
-        let total_brick_voxels: usize = svo_sdf.bricks
+        let total_brick_voxels: usize = svo_sdf
+            .bricks
             .iter()
             .map(|brick| (brick.size * brick.size * brick.size) as usize)
             .sum();
 
-        // Create upload buffer for all brick data
-        let brick_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<u16>() * total_brick_voxels) as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
+        // Create 3D texture array for bricks
+        // We'll use a large 3D texture and pack bricks into it
+        let max_brick_size = svo_sdf.bricks.iter().map(|b| b.size).max().unwrap_or(8);
+        let bricks_per_row =
+            ((total_brick_voxels as f32).cbrt() / max_brick_size as f32).ceil() as u32;
+        let texture_size = bricks_per_row * max_brick_size;
+
+        // Sparse atlases commit about the brick voxels, rounded up to whole pages
+        let atlas_voxels = if sparse_atlas {
+            total_brick_voxels as u64
+        } else {
+            texture_size as u64 * texture_size as u64 * texture_size as u64
+        };
+        let brick_format = memory_profile
+            .select_brick_format(atlas_voxels)
+            .unwrap_or_else(|err| panic!("Brick atlas allocation failed: {}", err));
+        let brick_format = filterable_brick_format(physical_device, brick_format, svo_sdf);
+        let upload_bytes = svo_sdf
+            .bricks
+            .iter()
+            .map(|brick| brick_format.brick_bytes(brick.size))
+            .sum();
+
+        // 16 bit bricks have enough precision with linear codes
+        let brick_curve = match brick_format {
+            BrickFormat::Unorm16 | BrickFormat::Float16 => BrickCurve::LINEAR,
+            BrickFormat::Unorm8 | BrickFormat::Bc4 => {
+                BrickCurve::equalized(svo_sdf.bricks.iter().map(|brick| &brick.data[..]))
+            }
         };
 
-        let brick_upload_buffer = VkBuffer::new(
+        // Create upload buffer for all brick data
+        let brick_upload_buffer = TypedBuffer::new_budgeted(
             device,
             allocator,
-            &brick_buffer_info,
+            (budget, BudgetTag::Staging),
+            upload_bytes,
+            vk::BufferUsageFlags::TRANSFER_SRC,
             MemoryLocation::CpuToGpu,
-        );
+        )
+        .unwrap_or_else(|err| panic!("Brick upload buffer allocation failed: {}", err));
 
         // Pack all brick data into the upload buffer
         let mut offset = 0;
         for brick in &svo_sdf.bricks {
-            brick_upload_buffer.copy_from_slice(&brick.data[..], offset);
-            offset += brick.data.len() * std::mem::size_of::<u16>();
+            match brick_format {
+                BrickFormat::Unorm16 => {
+                    brick_upload_buffer.write_range(offset, cast_slice(&brick.data[..]))
+                }
+                BrickFormat::Float16 => {
+                    let data: Vec<u16> = brick
+                        .data
+                        .iter()
+                        .map(|&v| f32_to_f16(unpack_unorm16(v)))
+                        .collect();
+                    brick_upload_buffer.write_range(offset, cast_slice(&data[..]));
+                }
+                BrickFormat::Unorm8 => {
+                    let data: Vec<u8> = brick
+                        .data
+                        .iter()
+                        .map(|&v| brick_curve.encode_unorm8(v))
+                        .collect();
+                    brick_upload_buffer.write_range(offset, &data[..]);
+                }
+                BrickFormat::Bc4 => {
+                    let data: Vec<u8> = brick
+                        .data
+                        .iter()
+                        .map(|&v| brick_curve.encode_unorm8(v))
+                        .collect();
+                    let size = (brick.size, brick.size, brick.size);
+                    brick_upload_buffer.write_range(offset, &compress_bc4(&data, size)[..]);
+                }
+            }
+            offset += brick_format.brick_bytes(brick.size);
         }
 
-        // Create 3D texture array for bricks
-        // We'll use a large 3D texture and pack bricks into it
-        let max_brick_size = svo_sdf.bricks.iter().map(|b| b.size).max().unwrap_or(8);
-        let bricks_per_row = ((total_brick_voxels as f32).cbrt() / max_brick_size as f32).ceil() as u32;
-        let texture_size = bricks_per_row * max_brick_size;
-
         let texture_create_info = vk::ImageCreateInfo {
             image_type: vk::ImageType::TYPE_3D,
-            format: vk::Format::R16_UNORM,
+            format: brick_format.vk_format(),
             extent: vk::Extent3D {
                 width: texture_size,
                 height: texture_size,
@@ -86,53 +332,86 @@ impl SvoTexture {
             ..Default::default()
         };
 
-        let brick_texture = VkImage::new(
-            device,
-            allocator,
-            &texture_create_info,
-            MemoryLocation::GpuOnly,
-        );
-
-        // Create octree structure buffer
-        let octree_nodes = Self::flatten_octree(&svo_sdf.root);
-        let octree_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<OctreeNodeGpu>() * octree_nodes.len()) as u64,
-            usage: vk::BufferUsageFlags::STORAGE_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
+        let volume_extent = svo_sdf
+            .bricks
+            .iter()
+            .fold(UVec3::splat(1), |extent, brick| {
+                extent.max(brick.position + UVec3::splat(brick.size))
+            });
+        let sparse_create_info = vk::ImageCreateInfo {
+            extent: vk::Extent3D {
+                width: volume_extent.x,
+                height: volume_extent.y,
+                depth: volume_extent.z,
+            },
+            ..texture_create_info
+        };
+        let sparse_supported = sparse_atlas
+            && sparse_image3d_supported(instance, pdevice, capabilities, &sparse_create_info);
+
+        let brick_atlas = if sparse_supported {
+            let mut sparse_image = VkSparseImage::new(
+                device,
+                allocator,
+                (budget, BudgetTag::Atlas),
+                &sparse_create_info,
+            )
+            .unwrap_or_else(|err| panic!("Brick atlas allocation failed: {}", err));
+            for brick in &svo_sdf.bricks {
+                let offset = vk::Offset3D {
+                    x: brick.position.x as i32,
+                    y: brick.position.y as i32,
+                    z: brick.position.z as i32,
+                };
+                let extent = vk::Extent3D {
+                    width: brick.size,
+                    height: brick.size,
+                    depth: brick.size,
+                };
+                sparse_image
+                    .commit(allocator, offset, extent)
+                    .unwrap_or_else(|err| panic!("Brick atlas allocation failed: {}", err));
+            }
+            BrickAtlas::Sparse(sparse_image)
+        } else {
+            let image = VkImage::new_budgeted(
+                device,
+                allocator,
+                (budget, BudgetTag::Atlas),
+                &texture_create_info,
+                MemoryLocation::GpuOnly,
+            )
+            .unwrap_or_else(|err| panic!("Brick atlas allocation failed: {}", err));
+            BrickAtlas::Packed {
+                image,
+                bricks_per_row,
+                max_brick_size,
+            }
         };
 
-        let octree_buffer = VkBuffer::new(
+        // Create octree structure buffer
+        let atlas_slots = atlas_order.atlas_slots(svo_sdf);
+        let octree_nodes = Self::flatten_octree(&svo_sdf.root, &atlas_slots);
+        let octree_buffer = TypedBuffer::new_budgeted(
             device,
             allocator,
-            &octree_buffer_info,
+            (budget, BudgetTag::Octree),
+            octree_nodes.len(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
             MemoryLocation::CpuToGpu,
-        );
-
-        octree_buffer.copy_from_slice(&octree_nodes[..], 0);
+        )
+        .unwrap_or_else(|err| panic!("Octree buffer allocation failed: {}", err));
 
-        let octree_buffer_descriptor = vk::DescriptorBufferInfo {
-            buffer: octree_buffer.buffer,
-            offset: 0,
-            range: (std::mem::size_of::<OctreeNodeGpu>() * octree_nodes.len()) as u64,
-        };
-
-        let sampler_info = vk::SamplerCreateInfo {
-            mag_filter: vk::Filter::LINEAR,
-            min_filter: vk::Filter::LINEAR,
-            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
-            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
-            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
-            address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
-            max_anisotropy: 1.0,
-            border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
-            compare_op: vk::CompareOp::NEVER,
-            min_lod: 0.0,
-            max_lod: 0.0,
-            ..Default::default()
-        };
+        octree_buffer.write_slice(&octree_nodes[..]);
+        let octree_buffer_descriptor = octree_buffer.descriptor();
 
-        let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+        let sampler = sampler_cache.get(
+            device,
+            SamplerDesc {
+                address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                ..Default::default()
+            },
+        );
 
         let view_info = vk::ImageViewCreateInfo {
             view_type: vk::ImageViewType::TYPE_3D,
@@ -149,10 +428,10 @@ impl SvoTexture {
                 layer_count: 1,
                 ..Default::default()
             },
-            image: brick_texture.image,
+            image: brick_atlas.image().image,
             ..Default::default()
         };
-        let brick_view = unsafe { device.create_image_view(&view_info, None) }.unwrap();
+        let brick_view = create_image_view(device, &view_info).unwrap();
 
         let brick_texture_descriptor = vk::DescriptorImageInfo {
             image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
@@ -161,7 +440,7 @@ impl SvoTexture {
         };
 
         SvoTexture {
-            brick_texture,
+            brick_atlas,
             brick_upload_buffer,
             octree_buffer,
             sampler,
@@ -169,22 +448,26 @@ impl SvoTexture {
             brick_texture_descriptor,
             octree_buffer_descriptor,
             total_brick_voxels,
+            brick_format,
+            brick_curve,
+            atlas_slots,
         }
     }
 
-    fn flatten_octree(node: &OctreeNode) -> Vec<OctreeNodeGpu> {
+    fn flatten_octree(node: &OctreeNode, atlas_slots: &[u32]) -> Vec<OctreeNodeGpu> {
         let mut nodes = Vec::new();
         let mut node_index = 0;
-        Self::flatten_octree_recursive(node, &mut nodes, &mut node_index);
+        Self::flatten_octree_recursive(node, atlas_slots, &mut nodes, &mut node_index);
         nodes
     }
 
     fn flatten_octree_recursive(
         node: &OctreeNode,
+        atlas_slots: &[u32],
         nodes: &mut Vec<OctreeNodeGpu>,
         node_index: &mut u32,
     ) {
-        let current_index = *node_index;
+        let first_index = nodes.len();
         *node_index += 1;
 
         // Calculate child mask
@@ -199,57 +482,54 @@ impl SvoTexture {
 
         // Add current node
         nodes.push(OctreeNodeGpu {
-            bounds_min: [node.bounds.min.0, node.bounds.min.1, node.bounds.min.2],
-            bounds_max: [node.bounds.max.0, node.bounds.max.1, node.bounds.max.2],
+            bounds_min: [node.bounds.min.x, node.bounds.min.y, node.bounds.min.z],
+            bounds_max: [node.bounds.max.x, node.bounds.max.y, node.bounds.max.z],
             brick_index: node.brick_index.unwrap_or(0xFFFFFFFF),
             child_mask,
             children_offset,
             is_leaf: if node.is_leaf { 1 } else { 0 },
-            _padding: [0, 0],
+            subtree_size: 1,
+            atlas_slot: node
+                .brick_index
+                .map_or(0, |index| atlas_slots[index as usize]),
         });
 
         // Recursively add children
         if !node.is_leaf {
-            for child in &node.children {
-                if let Some(child_node) = child {
-                    Self::flatten_octree_recursive(child_node, nodes, node_index);
-                }
+            for child_node in node.children.iter().flatten() {
+                Self::flatten_octree_recursive(child_node, atlas_slots, nodes, node_index);
             }
         }
+        nodes[first_index].subtree_size = (nodes.len() - first_index) as u32;
+    }
+
+    // Sparse atlases bind their committed pages on a queue with sparse binding support,
+    // before gpu_setup uploads the bricks. Nothing to do for packed atlases.
+    pub fn bind_atlas_pages(
+        &mut self,
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        queue: vk::Queue,
+    ) -> Result<(), vk::Result> {
+        match &mut self.brick_atlas {
+            BrickAtlas::Sparse(sparse_image) => sparse_image.bind_pending(device, allocator, queue),
+            BrickAtlas::Packed { .. } => Ok(()),
+        }
     }
 
     pub fn gpu_setup(
         &self,
         device: &Device,
+        barrier_api: &BarrierApi,
         command_buffer: &vk::CommandBuffer,
         svo_sdf: &SvoSdf,
     ) {
-        // Setup brick texture
-        let texture_barrier = vk::ImageMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            image: self.brick_texture.image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
-
         // Create copy regions for each brick
-        let max_brick_size = svo_sdf.bricks.iter().map(|b| b.size).max().unwrap_or(8);
-        let bricks_per_row = ((self.total_brick_voxels as f32).cbrt() / max_brick_size as f32).ceil() as u32;
-        
         let mut image_copys = Vec::new();
         let mut buffer_offset = 0u64;
 
-        for (i, brick) in svo_sdf.bricks.iter().enumerate() {
-            let brick_x = (i as u32) % bricks_per_row;
-            let brick_y = ((i as u32) / bricks_per_row) % bricks_per_row;
-            let brick_z = (i as u32) / (bricks_per_row * bricks_per_row);
-
+        // The upload buffer is in brick order, the atlas in slot order
+        for (brick, &slot) in svo_sdf.bricks.iter().zip(self.atlas_slots.iter()) {
             let copy_region = vk::BufferImageCopy {
                 buffer_offset,
                 buffer_row_length: 0,
@@ -260,11 +540,7 @@ impl SvoTexture {
                     base_array_layer: 0,
                     layer_count: 1,
                 },
-                image_offset: vk::Offset3D {
-                    x: (brick_x * max_brick_size) as i32,
-                    y: (brick_y * max_brick_size) as i32,
-                    z: (brick_z * max_brick_size) as i32,
-                },
+                image_offset: self.brick_atlas.brick_offset(brick, slot),
                 image_extent: vk::Extent3D {
                     width: brick.size,
                     height: brick.size,
@@ -273,62 +549,46 @@ impl SvoTexture {
             };
 
             image_copys.push(copy_region);
-            buffer_offset += (brick.data.len() * std::mem::size_of::<u16>()) as u64;
+            buffer_offset += self.brick_format.brick_bytes(brick.size) as u64;
         }
 
-        let texture_barrier_end = vk::ImageMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::SHADER_READ,
-            old_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            new_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            image: self.brick_texture.image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
-                level_count: 1,
-                layer_count: 1,
-                ..Default::default()
-            },
-            ..Default::default()
-        };
+        let brick_texture = self.brick_atlas.image();
+        brick_texture.transition_discarding(
+            device,
+            barrier_api,
+            *command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
 
         unsafe {
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[texture_barrier],
-            );
-
             device.cmd_copy_buffer_to_image(
                 *command_buffer,
-                self.brick_upload_buffer.buffer,
-                self.brick_texture.image,
+                self.brick_upload_buffer.buffer.buffer,
+                brick_texture.image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                 &image_copys[..],
-            );
-
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::FRAGMENT_SHADER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[],
-                &[texture_barrier_end],
-            );
+            )
         };
+
+        brick_texture.transition_to(
+            device,
+            barrier_api,
+            *command_buffer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+        );
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
-        unsafe {
-            device.destroy_image_view(self.brick_view, None);
-            self.brick_texture.destroy(device, allocator);
-            self.brick_upload_buffer.destroy(device, allocator);
-            self.octree_buffer.destroy(device, allocator);
-            device.destroy_sampler(self.sampler, None);
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        destroy_image_view(device, self.brick_view);
+        match &mut self.brick_atlas {
+            BrickAtlas::Packed { image, .. } => image.destroy(device, allocator),
+            BrickAtlas::Sparse(sparse_image) => sparse_image.destroy(device, allocator),
         }
+        self.brick_upload_buffer.destroy(device, allocator);
+        self.octree_buffer.destroy(device, allocator);
     }
-}
\ No newline at end of file
+}
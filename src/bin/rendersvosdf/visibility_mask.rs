@@ -0,0 +1,43 @@
+// One visibility bit per scene instance, built from the hidden flags of the scene instances.
+// culling.comp reads the words from a storage buffer and skips the bricks of hidden
+// instances. Without HiZ there is no culling pass, the CPU visibility list leaves them out
+// instead (Culling::set_visible_scene_instances).
+
+use crate::instances::*;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VisibilityMask {
+    words: Vec<u32>,
+}
+
+impl VisibilityMask {
+    // u32 words for len instances, at least one so the buffer is never empty
+    pub fn word_count(len: usize) -> usize {
+        len.div_ceil(32).max(1)
+    }
+
+    pub fn from_instances(scene_instances: &[SceneInstance]) -> VisibilityMask {
+        let mut words = vec![0u32; VisibilityMask::word_count(scene_instances.len())];
+        for (i, instance) in scene_instances.iter().enumerate() {
+            if !instance.hidden {
+                words[i / 32] |= 1 << (i % 32);
+            }
+        }
+        VisibilityMask { words }
+    }
+
+    pub fn is_visible(&self, instance: usize) -> bool {
+        self.words[instance / 32] & (1 << (instance % 32)) != 0
+    }
+
+    pub fn visible_count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn words(&self) -> &[u32] {
+        &self.words
+    }
+}
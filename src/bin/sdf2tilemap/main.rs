@@ -18,7 +18,7 @@ pub struct SdfLevel {
 
 fn is_correct_size(v: u32, tile_size_payload: u32, padding: u32) -> bool {
     let v_no_pad = v - padding;
-    (v_no_pad % tile_size_payload) == 0
+    v_no_pad.is_multiple_of(tile_size_payload)
 }
 
 pub struct Params {
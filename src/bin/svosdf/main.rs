@@ -1,11 +1,41 @@
 use std::env;
+use std::net::TcpListener;
+use std::path::Path;
 use std::process;
 
+use rust_test::brick_cache;
+use rust_test::brick_checksum;
+use rust_test::brick_stream;
+use rust_test::cancellation;
+use rust_test::display_settings;
+use rust_test::file_format;
+use rust_test::localization;
+use rust_test::memory_profile;
+use rust_test::packing;
 use rust_test::sdf;
+use rust_test::svo_diff;
 use rust_test::svosdf;
+use rust_test::thumbnail;
+use rust_test::volume_projection;
+use rust_test::vulkan_base;
 
+use brick_cache::*;
+use brick_checksum::*;
+use brick_stream::*;
+use cancellation::*;
+use display_settings::*;
+use file_format::*;
+use localization::*;
+use memory_profile::*;
+use packing::*;
 use sdf::*;
+use svo_diff::*;
 use svosdf::*;
+use thumbnail::*;
+use volume_projection::*;
+use vulkan_base::*;
+
+use winit::{event_loop::EventLoop, window::WindowBuilder};
 
 pub struct Params {
     pub file_in: String,
@@ -13,11 +43,16 @@ pub struct Params {
     pub brick_size: u32,
     pub max_depth: u32,
     pub threshold: f32,
+    pub threshold_falloff: f32, // Per octree depth, 1 keeps threshold at every depth
+    pub lod: u32,
+    pub lod_filter: LodFilter,
+    pub target_error: Option<f32>, // World units, replaces max_depth, threshold and lod
 }
 
-fn parse_args(args: &[String]) -> Result<Params, &str> {
+// Errors are localization keys
+fn parse_args(args: &[String]) -> Result<Params, &'static str> {
     if args.len() < 3 {
-        return Err("Not enough arguments");
+        return Err("cli.error.not_enough_arguments");
     }
 
     let file_in = args[1].clone();
@@ -26,6 +61,10 @@ fn parse_args(args: &[String]) -> Result<Params, &str> {
     let mut brick_size = 8;
     let mut max_depth = 8;
     let mut threshold = 0.004;
+    let mut threshold_falloff = 1.0;
+    let mut lod = 0;
+    let mut lod_filter = LodFilter::Box;
+    let mut target_error = None;
 
     let mut i = 3;
     while i < args.len() {
@@ -35,7 +74,7 @@ fn parse_args(args: &[String]) -> Result<Params, &str> {
                     brick_size = args[i + 1].parse().unwrap_or(8);
                     i += 2;
                 } else {
-                    return Err("Missing brick size value");
+                    return Err("cli.error.missing_brick_size");
                 }
             }
             "-d" | "--max-depth" => {
@@ -43,7 +82,7 @@ fn parse_args(args: &[String]) -> Result<Params, &str> {
                     max_depth = args[i + 1].parse().unwrap_or(8);
                     i += 2;
                 } else {
-                    return Err("Missing max depth value");
+                    return Err("cli.error.missing_max_depth");
                 }
             }
             "-t" | "--threshold" => {
@@ -51,8 +90,37 @@ fn parse_args(args: &[String]) -> Result<Params, &str> {
                     threshold = args[i + 1].parse().unwrap_or(0.004);
                     i += 2;
                 } else {
-                    return Err("Missing threshold value");
+                    return Err("cli.error.missing_threshold");
+                }
+            }
+            "--threshold-falloff" => {
+                match args.get(i + 1).and_then(|value| value.parse::<f32>().ok()) {
+                    Some(value) if value > 0.0 => threshold_falloff = value,
+                    _ => return Err("cli.error.invalid_threshold_falloff"),
+                }
+                i += 2;
+            }
+            "--lod" => {
+                if i + 1 < args.len() {
+                    lod = args[i + 1].parse().unwrap_or(0);
+                    i += 2;
+                } else {
+                    return Err("cli.error.missing_lod");
+                }
+            }
+            "--lod-filter" => {
+                match args.get(i + 1).and_then(|name| LodFilter::from_name(name)) {
+                    Some(filter) => lod_filter = filter,
+                    None => return Err("cli.error.invalid_lod_filter"),
                 }
+                i += 2;
+            }
+            "--target-error" => {
+                match args.get(i + 1).and_then(|value| value.parse::<f32>().ok()) {
+                    Some(value) if value > 0.0 => target_error = Some(value),
+                    _ => return Err("cli.error.invalid_target_error"),
+                }
+                i += 2;
             }
             _ => i += 1,
         }
@@ -60,51 +128,812 @@ fn parse_args(args: &[String]) -> Result<Params, &str> {
 
     Ok(Params {
         file_in,
-       file_out,
-       brick_size,
-       max_depth,
-       threshold,
+        file_out,
+        brick_size,
+        max_depth,
+        threshold,
+        threshold_falloff,
+        lod,
+        lod_filter,
+        target_error,
     })
 }
 
 fn print_usage() {
-    println!("Usage: svosdf input.sdf output.svosdf [options]");
-    println!("Options:");
-    println!("  -b, --brick-size <size>    Brick size (default: 8)");
-    println!("  -d, --max-depth <depth>    Maximum octree depth (default: 8)");
-    println!("  -t, --threshold <value>    Distance threshold for subdivision (default: 0.01)");
+    println!("{}", tr("cli.usage"));
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let params = parse_args(&args).unwrap_or_else(|err| {
-        println!("Argument error: {}", err);
-        print_usage();
+fn print_memory_usage(usage: &SvoMemoryUsage) {
+    println!(
+        "{}",
+        tr_args("info.memory_usage", &[("bytes", &usage.total())])
+    );
+    println!(
+        "{}",
+        tr_args(
+            "info.memory_nodes",
+            &[("bytes", &usage.nodes), ("count", &usage.node_count)]
+        )
+    );
+    println!(
+        "{}",
+        tr_args("info.memory_bricks", &[("bytes", &usage.brick_payloads)])
+    );
+    let metadata = usage.header + usage.metadata;
+    println!(
+        "{}",
+        tr_args("info.memory_metadata", &[("bytes", &metadata)])
+    );
+    println!(
+        "{}",
+        tr_args(
+            "info.memory_allocator",
+            &[
+                ("bytes", &usage.allocator_overhead),
+                ("allocations", &usage.allocations)
+            ]
+        )
+    );
+    println!(
+        "{}",
+        tr_args(
+            "info.memory_dedup",
+            &[
+                ("count", &usage.duplicate_bricks),
+                ("bytes", &usage.dedup_savings)
+            ]
+        )
+    );
+}
+
+fn print_info(filename: &str) {
+    if filename.ends_with(".svosdf") {
+        let svo_sdf = SvoSdf::load(filename).expect("SVO SDF loading failed");
+        println!("{}", tr_args("info.svo_sdf", &[("file", &filename)]));
+        println!(
+            "{}",
+            tr_args(
+                "info.header",
+                &[("header", &format!("{:?}", svo_sdf.header))]
+            )
+        );
+        println!(
+            "{}",
+            tr_args("info.brick_size", &[("value", &svo_sdf.brick_size)])
+        );
+        println!(
+            "{}",
+            tr_args("info.bricks", &[("value", &svo_sdf.bricks.len())])
+        );
+        print_memory_usage(&svo_sdf.memory_usage());
+        let min = svo_sdf
+            .brick_ranges
+            .iter()
+            .map(|r| r.min)
+            .min()
+            .unwrap_or(LEVEL_ZERO);
+        let max = svo_sdf
+            .brick_ranges
+            .iter()
+            .map(|r| r.max)
+            .max()
+            .unwrap_or(LEVEL_ZERO);
+        let min = format!("{:.4}", (min as f32 - LEVEL_ZERO as f32) / UNORM16_MAX);
+        let max = format!("{:.4}", (max as f32 - LEVEL_ZERO as f32) / UNORM16_MAX);
+        println!(
+            "{}",
+            tr_args("info.distance_range", &[("min", &min), ("max", &max)])
+        );
+        println!(
+            "{}",
+            tr_args(
+                "info.bricks_crossing_surface",
+                &[("value", &svo_sdf.bricks_in_band(0).count())]
+            )
+        );
+        print_surface_stats(svo_sdf.surface_area(), svo_sdf.enclosed_volume());
+        match &svo_sdf.thumbnail {
+            Some(thumbnail) => println!(
+                "{}",
+                tr_args(
+                    "info.thumbnail",
+                    &[("width", &thumbnail.width), ("height", &thumbnail.height)]
+                )
+            ),
+            None => println!("{}", tr("info.no_thumbnail")),
+        }
+    } else {
+        let sdf = load_sdf_zlib(filename).expect("SDF loading failed");
+        println!("{}", tr_args("info.sdf", &[("file", &filename)]));
+        println!(
+            "{}",
+            tr_args("info.header", &[("header", &format!("{:?}", sdf.header))])
+        );
+        print_surface_stats(sdf.surface_area(), sdf.enclosed_volume());
+    }
+}
+
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(|value| value.as_str())
+}
+
+// Samples voxels through the brick cache, only the brick directory and up to
+// --cache-bricks N decoded bricks are kept in memory. With --remote the bricks are
+// fetched from `svosdf serve` instead, and kept on disk in --cache-dir when given.
+fn print_samples(source: &str, args: &[String], remote: bool) {
+    let capacity = option_value(args, "--cache-bricks")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(64);
+    let coordinates: Vec<u32> = args
+        .iter()
+        .take_while(|arg| !arg.starts_with("--"))
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    if coordinates.len() < 3 {
+        argument_error("cli.error.missing_voxel");
+    }
+
+    let mut cache = if remote {
+        let address = source;
+        let cache_dir = option_value(args, "--cache-dir").map(Path::new);
+        let remote = RemoteBricks::connect(address, cache_dir).unwrap_or_else(|error| {
+            println!(
+                "{}",
+                tr_args(
+                    "serve.connect_failed",
+                    &[("address", &address), ("error", &error)]
+                )
+            );
+            process::exit(1);
+        });
+        BrickCache::new(Box::new(remote), capacity)
+    } else {
+        BrickCache::open(Path::new(source), capacity).expect("SVO SDF opening failed")
+    };
+    for voxel in coordinates.chunks_exact(3) {
+        let voxel = (voxel[0], voxel[1], voxel[2]);
+        let position = format!("{:?}", voxel);
+        match cache.sample(voxel.into()).expect("Brick read failed") {
+            Some(value) => {
+                let distance = format!("{:.4}", (value as f32 - LEVEL_ZERO as f32) / UNORM16_MAX);
+                println!(
+                    "{}",
+                    tr_args(
+                        "sample.value",
+                        &[("voxel", &position), ("value", &distance)]
+                    )
+                );
+            }
+            None => println!("{}", tr_args("sample.no_brick", &[("voxel", &position)])),
+        }
+    }
+    println!(
+        "{}",
+        tr_args(
+            "sample.cache",
+            &[
+                ("bricks", &cache.resident_bricks()),
+                ("bytes", &cache.resident_bytes()),
+                ("hits", &cache.stats.hits),
+                ("misses", &cache.stats.misses),
+            ]
+        )
+    );
+}
+
+// Content server for `sample --remote`, runs until killed
+fn serve_bricks(filename: &str, args: &[String]) {
+    let port = option_value(args, "--port")
+        .and_then(|value| value.parse::<u16>().ok())
+        .unwrap_or(DEFAULT_PORT);
+    let bind = option_value(args, "--bind").unwrap_or(DEFAULT_BIND_ADDRESS);
+    let file = BrickFile::open(Path::new(filename)).expect("SVO SDF opening failed");
+    let address = format!("{}:{}", bind, port);
+    let listener = TcpListener::bind(&address).unwrap_or_else(|error| {
+        println!(
+            "{}",
+            tr_args(
+                "serve.bind_failed",
+                &[("address", &address), ("error", &error)]
+            )
+        );
         process::exit(1);
     });
+    println!(
+        "{}",
+        tr_args(
+            "serve.listening",
+            &[
+                ("file", &filename),
+                ("bricks", &file.directory().entries.len()),
+                ("address", &address)
+            ]
+        )
+    );
+    serve(listener, Path::new(filename)).expect("Brick server failed");
+}
+
+// Embedded thumbnail when the file has one, files without it are loaded and projected
+fn write_thumbnail(filename: &str, file_out: &str) {
+    let embedded = read_thumbnail(Path::new(filename)).expect("SVO SDF opening failed");
+    let thumbnail = match embedded {
+        Some(thumbnail) => {
+            println!("{}", tr_args("thumbnail.embedded", &[("file", &filename)]));
+            thumbnail
+        }
+        None => {
+            println!("{}", tr_args("thumbnail.projected", &[("file", &filename)]));
+            let svo_sdf = SvoSdf::load(filename).expect("SVO SDF loading failed");
+            Thumbnail::project(&svo_sdf, THUMBNAIL_SIZE)
+        }
+    };
+    thumbnail
+        .save_png(Path::new(file_out))
+        .expect("Failed to save thumbnail");
+    println!(
+        "{}",
+        tr_args(
+            "thumbnail.saved",
+            &[
+                ("width", &thumbnail.width),
+                ("height", &thumbnail.height),
+                ("file", &file_out),
+            ]
+        )
+    );
+}
+
+// Max intensity or average projection of the volume along an axis, computed on the GPU or
+// with --cpu by the reference implementation
+fn write_projection(filename: &str, file_out: &str, args: &[String]) {
+    let axis = match option_value(args, "--axis") {
+        Some(name) => ProjectionAxis::from_name(name)
+            .unwrap_or_else(|| argument_error("cli.error.invalid_axis")),
+        None => ProjectionAxis::Z,
+    };
+    let mode = match option_value(args, "--mode") {
+        Some(name) => ProjectionMode::from_name(name)
+            .unwrap_or_else(|| argument_error("cli.error.invalid_projection_mode")),
+        None => ProjectionMode::Max,
+    };
+    let svo_sdf = SvoSdf::load(filename).expect("SVO SDF loading failed");
+
+    let projection = if args.iter().any(|arg| arg == "--cpu") {
+        project_cpu(&svo_sdf, axis)
+    } else {
+        // The surface and swapchain of VulkanBase need a window, it is never shown
+        let event_loop = EventLoop::new().unwrap();
+        let window = WindowBuilder::new()
+            .with_title("svosdf project")
+            .with_visible(false)
+            .build(&event_loop)
+            .unwrap();
+        let size = window.inner_size();
+        let projected = VulkanBase::new_with_settings(
+            &window,
+            size.width.max(1),
+            size.height.max(1),
+            MemoryProfile::from_args(args),
+            DebugSettings::from_args(args),
+            DisplaySettings::from_args(args),
+        )
+        .and_then(|mut base| project_gpu(&mut base, &svo_sdf, axis));
+        projected.unwrap_or_else(|err| {
+            println!("{}", tr_args("project.gpu_failed", &[("error", &err)]));
+            process::exit(1);
+        })
+    };
+    projection
+        .save_png(Path::new(file_out), mode)
+        .expect("Failed to save projection");
+    println!(
+        "{}",
+        tr_args(
+            "project.saved",
+            &[
+                ("width", &projection.width),
+                ("height", &projection.height),
+                ("file", &file_out),
+            ]
+        )
+    );
+}
+
+// Reads the bricks one at a time and reports every brick whose checksum doesn't match, exits
+// with status 1 when the file is corrupt
+fn check_file(filename: &str) {
+    let mut file = BrickFile::open(Path::new(filename)).expect("SVO SDF opening failed");
+    let brick_count = file.directory().entries.len();
+    if file
+        .directory()
+        .entries
+        .iter()
+        .any(|entry| entry.checksum.is_none())
+    {
+        println!("{}", tr_args("check.no_checksums", &[("file", &filename)]));
+        return;
+    }
+    let mut corrupt = 0;
+    for index in 0..brick_count {
+        let err = match file.read_brick(index) {
+            Ok(_) => continue,
+            Err(err) => err,
+        };
+        let mismatch = ChecksumMismatch::from_io(&err).unwrap_or_else(|| {
+            panic!("Brick reading failed: {:?}", err);
+        });
+        let position = mismatch.position;
+        println!(
+            "{}",
+            tr_args(
+                "check.mismatch",
+                &[
+                    ("index", &index),
+                    (
+                        "voxel",
+                        &format!("{} {} {}", position.x, position.y, position.z)
+                    ),
+                    ("expected", &format!("{:016x}", mismatch.expected)),
+                    ("actual", &format!("{:016x}", mismatch.actual)),
+                ]
+            )
+        );
+        corrupt += 1;
+    }
+    if corrupt > 0 {
+        println!(
+            "{}",
+            tr_args(
+                "check.corrupt",
+                &[("corrupt", &corrupt), ("bricks", &brick_count)]
+            )
+        );
+        process::exit(1);
+    }
+    println!(
+        "{}",
+        tr_args("check.ok", &[("file", &filename), ("bricks", &brick_count)])
+    );
+}
+
+// Bricks listed with the largest value changes
+const DIFF_LISTED_BRICKS: usize = 10;
+
+// Octree topology, brick counts per depth and voxel value changes between two builds of the
+// same source. --heatmap OUT.sdf writes the changed regions as a volume. Exits with status 1
+// when the files differ, like diff.
+fn print_diff(file_a: &str, file_b: &str, args: &[String]) {
+    let a = SvoSdf::load(file_a).expect("SVO SDF loading failed");
+    let b = SvoSdf::load(file_b).expect("SVO SDF loading failed");
+    let diff = SvoDiff::compute(&a, &b);
+    println!(
+        "{}",
+        tr_args("diff.comparing", &[("a", &file_a), ("b", &file_b)])
+    );
+
+    let dim = |header: &SdfHeader| format!("{}x{}x{}", header.dim.0, header.dim.1, header.dim.2);
+    if !diff.same_grid {
+        println!(
+            "{}",
+            tr_args(
+                "diff.grid_differs",
+                &[("a", &dim(&a.header)), ("b", &dim(&b.header))]
+            )
+        );
+    }
+
+    let depths = diff.depths_a.len().max(diff.depths_b.len());
+    for depth in 0..depths {
+        let counts_a = diff.depths_a.get(depth).copied().unwrap_or_default();
+        let counts_b = diff.depths_b.get(depth).copied().unwrap_or_default();
+        println!(
+            "{}",
+            tr_args(
+                "diff.depth",
+                &[
+                    ("depth", &depth),
+                    ("nodes_a", &counts_a.nodes),
+                    ("nodes_b", &counts_b.nodes),
+                    ("leaves_a", &counts_a.leaves),
+                    ("leaves_b", &counts_b.leaves),
+                    ("bricks_a", &counts_a.bricks),
+                    ("bricks_b", &counts_b.bricks),
+                ]
+            )
+        );
+    }
+    println!(
+        "{}",
+        tr_args(
+            "diff.nodes",
+            &[
+                ("common", &diff.nodes_common),
+                ("only_a", &diff.nodes_only_a),
+                ("only_b", &diff.nodes_only_b),
+                ("leaf_changes", &diff.leaf_changes),
+            ]
+        )
+    );
+    println!(
+        "{}",
+        tr_args(
+            "diff.bricks",
+            &[
+                ("common", &diff.bricks_common),
+                ("only_a", &diff.bricks_only_a),
+                ("only_b", &diff.bricks_only_b),
+            ]
+        )
+    );
+
+    if diff.same_grid {
+        let max = format!("{:.4}", diff.max_delta as f32 / UNORM16_MAX);
+        let rms = format!("{:.6}", diff.rms_delta);
+        println!(
+            "{}",
+            tr_args(
+                "diff.values",
+                &[
+                    ("changed", &diff.changed_bricks.len()),
+                    ("common", &diff.bricks_common),
+                    ("voxels", &diff.changed_voxels),
+                    ("max", &max),
+                    ("rms", &rms),
+                ]
+            )
+        );
+        for delta in diff.changed_bricks.iter().take(DIFF_LISTED_BRICKS) {
+            let position = delta.position;
+            let max = format!("{:.4}", delta.max_delta as f32 / UNORM16_MAX);
+            println!(
+                "{}",
+                tr_args(
+                    "diff.brick_delta",
+                    &[
+                        (
+                            "voxel",
+                            &format!("{} {} {}", position.x, position.y, position.z)
+                        ),
+                        ("size", &delta.size),
+                        ("voxels", &delta.changed_voxels),
+                        ("max", &max),
+                    ]
+                )
+            );
+        }
+    }
+
+    if let Some(file_out) = option_value(args, "--heatmap") {
+        match diff_heatmap(&a, &b) {
+            Some(heatmap) => {
+                store_sdf_zlib(file_out, &heatmap).expect("Failed to save heatmap");
+                println!("{}", tr_args("diff.heatmap_saved", &[("file", &file_out)]));
+            }
+            None => println!("{}", tr("diff.heatmap_grid_differs")),
+        }
+    }
 
-    println!("Loading SDF: {}", params.file_in);
+    if diff.is_identical() {
+        println!("{}", tr("diff.identical"));
+    } else {
+        println!("{}", tr("diff.different"));
+        process::exit(1);
+    }
+}
+
+// Header and section layout without loading the voxel data. The JSON form is meant for
+// tools in other languages and isn't localized.
+fn print_header(filename: &str, json: bool) {
+    let info = FileInfo::load(Path::new(filename)).unwrap_or_else(|err| {
+        let error = err.to_string();
+        println!(
+            "{}",
+            tr_args(
+                "cli.error.invalid_file",
+                &[("file", &filename), ("error", &error)]
+            )
+        );
+        process::exit(1);
+    });
+    if json {
+        println!("{}", info.to_json());
+    } else {
+        println!(
+            "{}",
+            tr_args("info.header", &[("header", &format!("{:#?}", info))])
+        );
+    }
+}
+
+fn print_surface_stats(area: f32, volume: f32) {
+    let area = format!("{:.4}", area);
+    let volume = format!("{:.4}", volume);
+    println!("{}", tr_args("info.surface_area", &[("value", &area)]));
+    println!("{}", tr_args("info.enclosed_volume", &[("value", &volume)]));
+}
+
+// Build with the settings given on the command line
+fn build(sdf: &Sdf, params: &Params, cancel: &CancelToken) -> Result<SvoSdf, Cancelled> {
+    println!(
+        "{}",
+        tr_args("build.max_depth", &[("value", &params.max_depth)])
+    );
+    println!(
+        "{}",
+        tr_args("build.threshold", &[("value", &params.threshold)])
+    );
+    if params.threshold_falloff != 1.0 {
+        println!(
+            "{}",
+            tr_args(
+                "build.threshold_falloff",
+                &[("value", &params.threshold_falloff)]
+            )
+        );
+    }
+
+    let lod = SdfLod::new(sdf, params.lod, params.lod_filter);
+    if params.lod > 0 {
+        let dim = lod.dim();
+        let error = lod.error();
+        println!(
+            "{}",
+            tr_args(
+                "build.lod",
+                &[
+                    ("level", &params.lod),
+                    ("filter", &params.lod_filter.name()),
+                    ("dim", &format!("{}x{}x{}", dim.0, dim.1, dim.2)),
+                ]
+            )
+        );
+        println!(
+            "{}",
+            tr_args(
+                "build.lod_error",
+                &[
+                    ("rms", &format!("{:.6}", error.rms)),
+                    ("max", &format!("{:.6}", error.max))
+                ]
+            )
+        );
+    }
+
+    SvoSdf::from_lod_with_progress(
+        &lod,
+        params.brick_size,
+        params.max_depth,
+        ThresholdSchedule::geometric(params.threshold, params.threshold_falloff),
+        &mut |_| {},
+        cancel,
+    )
+}
+
+// Builds with settings derived from the target error and rebuilds with tighter ones until
+// compare finds every brick voxel within the bound and no surface voxel pruned
+fn build_for_target_error(
+    sdf: &Sdf,
+    params: &Params,
+    target_error: f32,
+    cancel: &CancelToken,
+) -> Result<SvoSdf, Cancelled> {
+    println!(
+        "{}",
+        tr_args("build.target_error", &[("value", &target_error)])
+    );
+    let header = &sdf.header;
+    let units = header.distance_per_step() * UNORM16_MAX;
+    let mut settings = TargetErrorParams::derive(header, params.brick_size, target_error);
+    let mut attempt = 1;
+    loop {
+        let lod = SdfLod::new(sdf, settings.lod, params.lod_filter);
+        let svo_sdf = SvoSdf::from_lod_with_progress(
+            &lod,
+            params.brick_size,
+            settings.max_depth,
+            ThresholdSchedule::geometric(settings.threshold, params.threshold_falloff),
+            &mut |_| {},
+            cancel,
+        )?;
+        let compare = svo_sdf.compare(&lod, target_error / units);
+        println!(
+            "{}",
+            tr_args(
+                "build.target_attempt",
+                &[
+                    ("attempt", &attempt),
+                    ("lod", &settings.lod),
+                    ("depth", &settings.max_depth),
+                    ("threshold", &format!("{:.6}", settings.threshold)),
+                    ("max", &format!("{:.6}", compare.error.max * units)),
+                    ("rms", &format!("{:.6}", compare.error.rms * units)),
+                    ("missing", &compare.missing_surface),
+                ]
+            )
+        );
+        let met = compare.error.max * units <= target_error && compare.missing_surface == 0;
+        if met {
+            println!("{}", tr("build.target_met"));
+            return Ok(svo_sdf);
+        }
+        match settings.tighten(header, params.brick_size, &compare, target_error) {
+            Some(tighter) => settings = tighter,
+            None => {
+                println!("{}", tr("build.target_missed"));
+                return Ok(svo_sdf);
+            }
+        }
+        attempt += 1;
+    }
+}
+
+fn argument_error(key: &str) -> ! {
+    let error = tr(key);
+    println!("{}", tr_args("cli.argument_error", &[("error", &error)]));
+    print_usage();
+    process::exit(1);
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+
+    if let Err(err) = init_from_args(&args) {
+        println!(
+            "{}",
+            tr_args("cli.error.language_load_failed", &[("error", &err)])
+        );
+    }
+    // --lang may appear anywhere, remove it before positional parsing
+    if let Some(i) = args.iter().position(|arg| arg == "--lang") {
+        args.drain(i..(i + 2).min(args.len()));
+    }
+
+    if args.len() > 1 && args[1] == "info" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        print_info(&args[2]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "sample" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        if args[2] == "--remote" {
+            if args.len() < 4 {
+                argument_error("cli.error.missing_address");
+            }
+            print_samples(&args[3], &args[4..], true);
+        } else {
+            print_samples(&args[2], &args[3..], false);
+        }
+        return;
+    }
+    if args.len() > 1 && args[1] == "serve" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        serve_bricks(&args[2], &args[3..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "thumbnail" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        if args.len() < 4 {
+            argument_error("cli.error.missing_output_file");
+        }
+        write_thumbnail(&args[2], &args[3]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "project" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        if args.len() < 4 {
+            argument_error("cli.error.missing_output_file");
+        }
+        write_projection(&args[2], &args[3], &args[4..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "check" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        check_file(&args[2]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "diff" {
+        if args.len() < 4 {
+            argument_error("cli.error.missing_input_file");
+        }
+        print_diff(&args[2], &args[3], &args[4..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "header" {
+        if args.len() < 3 {
+            argument_error("cli.error.missing_input_file");
+        }
+        print_header(&args[2], args[3..].iter().any(|arg| arg == "--json"));
+        return;
+    }
+    let params = parse_args(&args).unwrap_or_else(|err| argument_error(err));
+
+    println!(
+        "{}",
+        tr_args("build.loading_sdf", &[("file", &params.file_in)])
+    );
     let sdf = load_sdf_zlib(&params.file_in).expect("SDF loading failed");
 
-    println!("Building sparse voxel octree...");
-    println!("  Brick size: {}", params.brick_size);
-    println!("  Max depth: {}", params.max_depth);
-    println!("  Threshold: {}", params.threshold);
+    // Ctrl+C stops the build at the next octree node and the save at the next chunk
+    let cancel = CancelToken::new();
+    let _interrupt = cancel.cancel_on_interrupt();
+    let cancelled = |key: &str, cancelled: Cancelled| -> ! {
+        let percent = format!("{:.1}", cancelled.progress * 100.0);
+        println!("{}", tr_args(key, &[("percent", &percent)]));
+        process::exit(130);
+    };
 
-    let svo_sdf = SvoSdf::from_sdf(&sdf, params.brick_size, params.max_depth, params.threshold);
+    println!("{}", tr("build.building"));
+    println!(
+        "{}",
+        tr_args("build.brick_size", &[("value", &params.brick_size)])
+    );
+    let svo_sdf = match params.target_error {
+        Some(target_error) => build_for_target_error(&sdf, &params, target_error, &cancel),
+        None => build(&sdf, &params, &cancel),
+    };
+    let mut svo_sdf = svo_sdf.unwrap_or_else(|err| cancelled("build.cancelled", err));
+    let thumbnail = Thumbnail::project(&svo_sdf, THUMBNAIL_SIZE);
+    println!(
+        "{}",
+        tr_args(
+            "build.thumbnail",
+            &[("width", &thumbnail.width), ("height", &thumbnail.height)]
+        )
+    );
+    svo_sdf.thumbnail = Some(thumbnail);
 
     let original_size = sdf.voxels.len() * std::mem::size_of::<u16>();
     let compressed_size = svo_sdf.calculate_memory_usage();
     let compression_ratio = (original_size as f32 / compressed_size as f32) * 100.0;
 
-    println!("Compression results:");
-    println!("  Original size: {} bytes", original_size);
-    println!("  Compressed size: {} bytes", compressed_size);
-    println!("  Compression ratio: {:.1}%", compression_ratio);
-    println!("  Memory reduction: {:.1}%", 100.0 - (compressed_size as f32 / original_size as f32) * 100.0);
+    let memory_reduction = 100.0 - (compressed_size as f32 / original_size as f32) * 100.0;
 
-    println!("Saving sparse voxel octree: {}", params.file_out);
-    svo_sdf.save(&params.file_out).expect("Failed to save SVO SDF");
+    println!("{}", tr("build.results"));
+    println!(
+        "{}",
+        tr_args("build.original_size", &[("bytes", &original_size)])
+    );
+    println!(
+        "{}",
+        tr_args("build.compressed_size", &[("bytes", &compressed_size)])
+    );
+    println!(
+        "{}",
+        tr_args(
+            "build.compression_ratio",
+            &[("percent", &format!("{:.1}", compression_ratio))]
+        )
+    );
+    println!(
+        "{}",
+        tr_args(
+            "build.memory_reduction",
+            &[("percent", &format!("{:.1}", memory_reduction))]
+        )
+    );
+
+    println!("{}", tr_args("build.saving", &[("file", &params.file_out)]));
+    if let Err(err) = svo_sdf.save_with_cancel(&params.file_out, &cancel) {
+        match Cancelled::from_io(&err) {
+            Some(err) => cancelled("build.save_cancelled", err),
+            None => panic!("Failed to save SVO SDF: {:?}", err),
+        }
+    }
 
-    println!("Done!");
+    println!("{}", tr("build.done"));
 }
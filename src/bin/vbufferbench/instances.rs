@@ -1,32 +1,88 @@
 pub const NUM_INSTANCES: usize = 1024 * 128;
 pub const CLOUD_RADIUS: f32 = 8000.0;
+// Clumps of roughly a quarter of the cloud radius
+pub const CLUSTER_FREQUENCY: f32 = 1.0 / 2000.0;
 
-use rand::Rng;
 use rand::SeedableRng;
 use std::default::Default;
 
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
 use crate::minivector::*;
+use crate::procedural::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct InstanceData {
     pub position: Vec4,
 }
 
+unsafe impl Zeroable for InstanceData {}
+unsafe impl Pod for InstanceData {}
+
+// Position and radius as half floats, half the bytes of InstanceData. At the cloud radius the
+// positions round to 4 units, the grids stay in place to a fraction of their size.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct PackedInstanceData {
+    pub position: [u16; 4],
+}
+
+unsafe impl Zeroable for PackedInstanceData {}
+unsafe impl Pod for PackedInstanceData {}
+
+// Layout of the instance buffer, the vertex shaders pick theirs with specialization
+// constant HALF_INSTANCES (constant_id 0)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstanceFormat {
+    Full, // InstanceData
+    Half, // PackedInstanceData
+}
+
+impl InstanceFormat {
+    pub fn from_name(name: &str) -> Option<InstanceFormat> {
+        match name {
+            "full" => Some(InstanceFormat::Full),
+            "half" => Some(InstanceFormat::Half),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstanceFormat::Full => "full",
+            InstanceFormat::Half => "half",
+        }
+    }
+
+    pub fn stride(&self) -> usize {
+        match self {
+            InstanceFormat::Full => std::mem::size_of::<InstanceData>(),
+            InstanceFormat::Half => std::mem::size_of::<PackedInstanceData>(),
+        }
+    }
+}
+
 pub struct Instances {
     pub instances_buffer: VkBuffer,
     pub instances_buffer_descriptor: vk::DescriptorBufferInfo,
+    pub format: InstanceFormat,
 }
 
 impl Instances {
-    pub fn new(device: &Device, allocator: &mut Allocator, instance_radius: f32) -> Instances {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        instance_radius: f32,
+        num_instances: usize,
+        format: InstanceFormat,
+    ) -> Instances {
         let instances_buffer_info = vk::BufferCreateInfo {
-            size: (std::mem::size_of::<InstanceData>() * NUM_INSTANCES) as u64,
+            size: (format.stride() * num_instances) as u64,
             usage: vk::BufferUsageFlags::STORAGE_BUFFER,
             sharing_mode: vk::SharingMode::EXCLUSIVE,
             ..Default::default()
@@ -42,35 +98,52 @@ impl Instances {
         let instances_buffer_descriptor = vk::DescriptorBufferInfo {
             buffer: instances_buffer.buffer,
             offset: 0,
-            range: (std::mem::size_of::<InstanceData>() * NUM_INSTANCES) as u64,
+            range: (format.stride() * num_instances) as u64,
         };
 
-        // Random cloud of SDF box instances
+        // Random cloud of SDF box instances, clustered by noise into clumps and voids
         //let mut rng = rand::thread_rng();
         let mut rng = rand::rngs::StdRng::from_seed([
             0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10,
             11, 12, 13, 14, 15,
         ]);
-        let instances_buffer_data: Vec<InstanceData> = (0..NUM_INSTANCES)
-            .map(|_i| InstanceData {
-                position: Vec4 {
-                    x: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    y: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    z: rng.gen_range(-CLOUD_RADIUS, CLOUD_RADIUS),
-                    w: instance_radius,
-                },
-            })
-            .collect();
-
-        instances_buffer.copy_from_slice(&instances_buffer_data[..], 0);
+        let density = Fbm::new(NoiseKind::Simplex, 3, CLUSTER_FREQUENCY);
+        let instances_buffer_data: Vec<InstanceData> =
+            clustered_cloud(num_instances, CLOUD_RADIUS, &density, &mut rng)
+                .into_iter()
+                .map(|position| InstanceData {
+                    position: Vec4 {
+                        w: instance_radius,
+                        ..position.to_4d()
+                    },
+                })
+                .collect();
+
+        match format {
+            InstanceFormat::Full => {
+                instances_buffer
+                    .copy_from_slice(&instances_buffer_data[..], 0)
+                    .unwrap();
+            }
+            InstanceFormat::Half => {
+                let packed: Vec<PackedInstanceData> = instances_buffer_data
+                    .iter()
+                    .map(|instance| PackedInstanceData {
+                        position: instance.position.to_f16(),
+                    })
+                    .collect();
+                instances_buffer.copy_from_slice(&packed[..], 0).unwrap();
+            }
+        }
 
         Instances {
             instances_buffer,
             instances_buffer_descriptor,
+            format,
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         self.instances_buffer.destroy(device, allocator);
     }
 }
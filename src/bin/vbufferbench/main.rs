@@ -1,35 +1,55 @@
 #![allow(dead_code)]
 
-const NUM_DESCRIPTORS_PER_TYPE: u32 = 1024;
-const NUM_DESCRIPTOR_SETS: u32 = 1024;
+const DESCRIPTOR_SETS_PER_POOL: u32 = 16;
 
 extern crate winit;
 
 mod instances;
 mod render_grids;
-
+mod scenario;
+mod uniform_benchmark;
+
+use rust_test::allocator_telemetry;
+use rust_test::color;
+use rust_test::display_settings;
+use rust_test::input;
+use rust_test::localization;
+use rust_test::memory_profile;
 use rust_test::minivector;
+use rust_test::procedural;
+use rust_test::render_module;
 use rust_test::vulkan_base;
 use rust_test::vulkan_helpers;
 
+use std::env;
+use std::path::Path;
+use std::process;
 use std::time::Instant;
 
 use ash::vk;
 
+use winit::keyboard::PhysicalKey;
 use winit::{
-    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
+    event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
-use winit::keyboard::PhysicalKey;
 
+use color::*;
+use display_settings::*;
+use input::*;
+use localization::*;
+use memory_profile::*;
 use minivector::*;
+use render_module::*;
 
 use vulkan_base::*;
 use vulkan_helpers::*;
 
 use instances::*;
 use render_grids::*;
+use scenario::*;
+use uniform_benchmark::*;
 
 #[derive(Clone, Copy)]
 pub struct Vertex {
@@ -37,34 +57,22 @@ pub struct Vertex {
     pub uv: [f32; 2],
 }
 
-fn main() {
-    let diagonal = Vec3 {
-        x: 150.0,
-        y: 150.0,
-        z: 150.0,
-    };
-
-    let center_to_edge = diagonal * 0.5;
-    let diagonal_length = diagonal.length();
-
-    // Window
-    let window_width = 1920;
-    let window_height = 1080;
-
-    let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new()
-        .with_title("Vulkan Test")
-        .with_inner_size(winit::dpi::PhysicalSize::new(
-            f64::from(window_width),
-            f64::from(window_height),
-        ))
-        .build(&event_loop)
-        .unwrap();
-
-    // Vulkan base initialization
-    let mut base = VulkanBase::new(&window, window_width, window_height);
+// DeviceLost is handled at the start of the next frame, other errors are fatal
+fn check_device_lost<T>(result: Result<T, VulkanError>, device_lost: &mut bool) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(VulkanError::DeviceLost) => {
+            *device_lost = true;
+            None
+        }
+        Err(err) => {
+            println!("{}", tr_args("viewer.vulkan_error", &[("error", &err)]));
+            process::exit(1);
+        }
+    }
+}
 
-    // Render passes
+fn create_render_pass(base: &VulkanBase) -> vk::RenderPass {
     let render_pass_attachments = [
         vk::AttachmentDescription {
             format: base.surface_format.format,
@@ -75,7 +83,7 @@ fn main() {
             ..Default::default()
         },
         vk::AttachmentDescription {
-            format: vk::Format::D32_SFLOAT,
+            format: base.depth_format,
             samples: vk::SampleCountFlags::TYPE_1,
             load_op: vk::AttachmentLoadOp::CLEAR,
             initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
@@ -118,114 +126,447 @@ fn main() {
         ..Default::default()
     };
 
-    let render_pass = unsafe {
+    unsafe {
         base.device
             .create_render_pass(&render_pass_create_info, None)
     }
-    .unwrap();
+    .unwrap()
+}
 
-    let framebuffers: Vec<vk::Framebuffer> = base
-        .present_image_views
-        .iter()
-        .map(|&present_image_view| {
-            let framebuffer_attachments = [present_image_view, base.depth_image_view];
-            let frame_buffer_create_info = vk::FramebufferCreateInfo {
-                render_pass,
-                attachment_count: framebuffer_attachments.len() as u32,
-                p_attachments: framebuffer_attachments.as_ptr(),
-                width: base.surface_resolution.width,
-                height: base.surface_resolution.height,
-                layers: 1,
+// Objects created on base.device, re-created after a device loss
+struct DeviceResources {
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    descriptor_allocator: DescriptorAllocator,
+    uniform_uploads: UniformUploads,
+    instances: Instances,
+    render_grids: RenderGrids,
+    texture: Option<Texture2d>,
+    // One query per command buffer, read back when the command buffer is reused
+    occlusion_queries: QueryPoolWrapper,
+    statistics_queries: Option<QueryPoolWrapper>,
+    timestamp_queries: Option<QueryPoolWrapper>, // Start and end of each command buffer
+    frame_strategies: Vec<Option<UniformStrategy>>, // Last recorded by each command buffer
+}
+
+impl DeviceResources {
+    fn new(
+        base: &mut VulkanBase,
+        config: (GridTechnique, usize, InstanceFormat), // Technique, instance count and format
+        diagonal_length: f32,
+        texture_path: Option<&Path>,
+        upload_pool: &MappedBufferPool,
+    ) -> DeviceResources {
+        let (technique, num_instances, instance_format) = config;
+        let render_pass = create_render_pass(base);
+        let framebuffers = base.create_framebuffers(render_pass);
+        let mut descriptor_allocator = DescriptorAllocator::new(DESCRIPTOR_SETS_PER_POOL);
+        let view_scissor = base.view_scissor();
+
+        // Per frame grid uniforms, bound with dynamic offsets
+        let num_frames = base.command_buffer_pool.command_buffers.len();
+        let mut uniform_uploads = UniformUploads::new(
+            &base.device,
+            &mut base.allocator,
+            &base.gpu_budget,
+            num_frames,
+        )
+        .unwrap_or_else(|err| panic!("Uniform buffer allocation failed: {}", err));
+
+        // Grid instances
+        let mut instances = Instances::new(
+            &base.device,
+            &mut base.allocator,
+            diagonal_length,
+            num_instances,
+            instance_format,
+        );
+        println!(
+            "{}",
+            tr_args(
+                "viewer.instance_buffer",
+                &[
+                    ("instances", &num_instances),
+                    ("format", &instance_format.name()),
+                    ("bytes", &(num_instances * instance_format.stride())),
+                ]
+            )
+        );
+
+        // The device local uniform copies are written by the GPU and not tracked
+        upload_pool.track(
+            &mut uniform_uploads.host_visible.buffer,
+            "uniforms (host visible)",
+        );
+        upload_pool.track(&mut uniform_uploads.staging.buffer, "uniforms (staging)");
+        upload_pool.track(&mut instances.instances_buffer, "instances");
+
+        // --texture FILE (PNG or KTX2) for the sampler binding of the grid descriptor set
+        let texture = texture_path.and_then(|path| {
+            load_texture_2d(
+                &base.device,
+                &mut base.allocator,
+                &base.capabilities,
+                path,
+                true,
+            )
+            .map_err(|err| {
+                let file = path.display();
+                let message = tr_args("viewer.texture_error", &[("file", &file), ("error", &err)]);
+                println!("{}", message);
+            })
+            .ok()
+        });
+        let texture_descriptor = texture.as_ref().map(|texture| {
+            let desc = SamplerDesc {
+                mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+                max_lod: texture.mip_levels,
                 ..Default::default()
             };
-            unsafe {
-                base.device
-                    .create_framebuffer(&frame_buffer_create_info, None)
+            texture.descriptor(base.sampler_cache.get(&base.device, desc))
+        });
+
+        // Grid renderer
+        let mut render_grids = RenderGrids::new(
+            &base.device,
+            &base.instance,
+            &mut base.allocator,
+            &mut descriptor_allocator,
+            &render_pass,
+            &view_scissor,
+            &uniform_uploads,
+            &instances.instances_buffer_descriptor,
+            texture_descriptor.as_ref(),
+            num_instances,
+            technique,
+            instance_format,
+            base.mesh_shader_loader.clone(),
+        );
+
+        let query_count = base.command_buffer_pool.command_buffers.len() as u32;
+        let occlusion_queries =
+            QueryPoolWrapper::occlusion(&base.device, &base.capabilities, query_count)
+                .expect("Occlusion query pool creation failed");
+        let statistics_queries =
+            QueryPoolWrapper::pipeline_statistics(&base.device, &base.capabilities, query_count)
+                .expect("Pipeline statistics query pool creation failed");
+        let timestamp_queries =
+            QueryPoolWrapper::timestamps(&base.device, &base.capabilities, 2 * query_count)
+                .expect("Timestamp query pool creation failed");
+
+        // Submit initialization command buffer before rendering starts
+        base.record_submit_commandbuffer(
+            0,
+            base.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                // GPU setup commands
+                render_grids.create(device, &base.barrier_api, command_buffer);
+                occlusion_queries.cmd_reset_all(device, command_buffer);
+                if let Some(statistics_queries) = &statistics_queries {
+                    statistics_queries.cmd_reset_all(device, command_buffer);
+                }
+                if let Some(timestamp_queries) = &timestamp_queries {
+                    timestamp_queries.cmd_reset_all(device, command_buffer);
+                }
+                if let Some(texture) = &texture {
+                    texture.record_upload(device, &base.barrier_api, command_buffer);
+                }
+            },
+        )
+        .expect("Setup command buffer submit failed");
+
+        DeviceResources {
+            render_pass,
+            framebuffers,
+            descriptor_allocator,
+            uniform_uploads,
+            instances,
+            render_grids,
+            texture,
+            occlusion_queries,
+            statistics_queries,
+            timestamp_queries,
+            frame_strategies: vec![None; num_frames],
+        }
+    }
+
+    fn destroy(&mut self, base: &mut VulkanBase) {
+        self.instances.destroy(&base.device, &mut base.allocator);
+        self.render_grids.destroy(&base.device, &mut base.allocator);
+        self.uniform_uploads
+            .destroy(&base.device, &mut base.allocator);
+        if let Some(texture) = &mut self.texture {
+            texture.destroy(&base.device, &mut base.allocator);
+        }
+        self.occlusion_queries.destroy(&base.device);
+        if let Some(statistics_queries) = &mut self.statistics_queries {
+            statistics_queries.destroy(&base.device);
+        }
+        if let Some(timestamp_queries) = &mut self.timestamp_queries {
+            timestamp_queries.destroy(&base.device);
+        }
+        self.descriptor_allocator.destroy(&base.device);
+        unsafe {
+            for framebuffer in self.framebuffers.drain(..) {
+                base.device.destroy_framebuffer(framebuffer, None);
             }
-            .unwrap()
+            base.device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+// Mesh shaders don't run the vertex stage, their vertex invocations stay at zero
+// Average CPU writes per frame into the host visible buffers, see MappedBufferPool
+fn print_upload_report(report: &UploadReport) {
+    let total = report.total_bytes();
+    println!(
+        "{}",
+        tr_args(
+            "viewer.upload_report",
+            &[
+                ("frames", &report.frames),
+                ("bytes", &format!("{:.0}", report.bytes_per_frame(total))),
+                ("peak", &report.peak_frame_bytes()),
+                (
+                    "mbps",
+                    &format!("{:.2}", report.megabytes_per_second(total))
+                ),
+            ]
+        )
+    );
+    for stats in &report.buffers {
+        println!(
+            "{}",
+            tr_args(
+                "viewer.upload_report_row",
+                &[
+                    ("buffer", &stats.name),
+                    (
+                        "bytes",
+                        &format!("{:.0}", report.bytes_per_frame(stats.bytes))
+                    ),
+                    ("peak", &stats.peak_frame_bytes),
+                    (
+                        "mbps",
+                        &format!("{:.2}", report.megabytes_per_second(stats.bytes))
+                    ),
+                ]
+            )
+        );
+    }
+}
+
+fn print_query_results(
+    technique: GridTechnique,
+    statistics: Option<PipelineStatistics>,
+    samples_passed: Option<u64>,
+) {
+    let technique = format!("{:?}", technique);
+    if let Some(statistics) = statistics {
+        println!(
+            "{}",
+            tr_args(
+                "viewer.pipeline_statistics",
+                &[
+                    ("technique", &technique),
+                    ("vertices", &statistics.vertex_invocations),
+                    ("primitives", &statistics.clipping_primitives),
+                    ("fragments", &statistics.fragment_invocations),
+                ]
+            )
+        );
+    }
+    if let Some(samples) = samples_passed {
+        println!(
+            "{}",
+            tr_args(
+                "viewer.samples_passed",
+                &[("technique", &technique), ("samples", &samples)]
+            )
+        );
+    }
+}
+
+fn main() {
+    // --technique <color|primid|nonindexed|leadingvertex|getattributeatvertex|mesh>
+    // --uniforms <host|staging|push>, --uniform-benchmark [FRAMES], --scenario FILE
+    // --instance-format <full|half>
+    let args: Vec<String> = env::args().collect();
+    if let Err(err) = init_from_args(&args) {
+        println!(
+            "{}",
+            tr_args("cli.error.language_load_failed", &[("error", &err)])
+        );
+    }
+    let technique = args
+        .iter()
+        .position(|arg| arg == "--technique")
+        .and_then(|i| args.get(i + 1))
+        .map(|name| {
+            GridTechnique::from_name(name).unwrap_or_else(|| {
+                println!("{}", tr_args("viewer.unknown_technique", &[("name", name)]));
+                process::exit(1);
+            })
+        })
+        .unwrap_or(GRID_TECHNIQUE);
+    let uniform_strategy = args
+        .iter()
+        .position(|arg| arg == "--uniforms")
+        .and_then(|i| args.get(i + 1))
+        .map(|name| {
+            UniformStrategy::from_name(name).unwrap_or_else(|| {
+                println!(
+                    "{}",
+                    tr_args("viewer.unknown_uniform_strategy", &[("name", name)])
+                );
+                process::exit(1);
+            })
         })
-        .collect();
+        .unwrap_or(UniformStrategy::HostVisible);
+    let instance_format = args
+        .iter()
+        .position(|arg| arg == "--instance-format")
+        .and_then(|i| args.get(i + 1))
+        .map(|name| {
+            InstanceFormat::from_name(name).unwrap_or_else(|| {
+                println!(
+                    "{}",
+                    tr_args("viewer.unknown_instance_format", &[("name", name)])
+                );
+                process::exit(1);
+            })
+        })
+        .unwrap_or(InstanceFormat::Full);
+    let benchmark_frames = args
+        .iter()
+        .position(|arg| arg == "--uniform-benchmark")
+        .map(|i| {
+            args.get(i + 1)
+                .and_then(|frames| frames.parse::<u32>().ok())
+                .unwrap_or(DEFAULT_FRAMES_PER_STRATEGY)
+        });
+    let texture_path = args
+        .iter()
+        .position(|arg| arg == "--texture")
+        .and_then(|i| args.get(i + 1))
+        .map(Path::new);
+    // --scenario FILE runs the benchmark matrix of a TOML file and exits, see scenario.rs
+    let mut scenario_runner = args
+        .iter()
+        .position(|arg| arg == "--scenario")
+        .and_then(|i| args.get(i + 1))
+        .map(|path| {
+            let scenario = Scenario::load(Path::new(path)).unwrap_or_else(|err| {
+                println!(
+                    "{}",
+                    tr_args("viewer.scenario_error", &[("file", path), ("error", &err)])
+                );
+                process::exit(1);
+            });
+            ScenarioRunner::new(scenario)
+        });
+    let first_run = scenario_runner.as_ref().and_then(|runner| runner.current());
+    let mut resources_config = first_run
+        .map_or((technique, NUM_INSTANCES, instance_format), |run| {
+            (run.technique, run.instance_count, run.instance_format)
+        });
 
-    let view_scissor = {
-        let viewport = vk::Viewport {
-            x: 0.0,
-            y: 0.0,
-            width: base.surface_resolution.width as f32,
-            height: base.surface_resolution.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        };
-        let scissor = vk::Rect2D {
-            extent: base.surface_resolution,
-            ..Default::default()
-        };
-        VkViewScissor { viewport, scissor }
+    let diagonal = Vec3 {
+        x: 150.0,
+        y: 150.0,
+        z: 150.0,
     };
 
-    // Descriptor pool
-    let descriptor_sizes = [
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_BUFFER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::STORAGE_IMAGE,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: NUM_DESCRIPTORS_PER_TYPE,
-        },
-    ];
-    let descriptor_pool_info = vk::DescriptorPoolCreateInfo {
-        pool_size_count: descriptor_sizes.len() as u32,
-        p_pool_sizes: descriptor_sizes.as_ptr(),
-        max_sets: NUM_DESCRIPTOR_SETS,
-        ..Default::default()
+    let center_to_edge = diagonal * 0.5;
+    let diagonal_length = diagonal.length();
+
+    // Window
+    let (window_width, window_height) = first_run.map_or((1920, 1080), |run| run.resolution);
+
+    let display_settings = DisplaySettings::from_args(&args);
+
+    let event_loop = EventLoop::new().unwrap();
+    let window = WindowBuilder::new()
+        .with_title("Vulkan Test")
+        .with_inner_size(winit::dpi::PhysicalSize::new(
+            f64::from(window_width),
+            f64::from(window_height),
+        ))
+        .with_fullscreen(display_settings.window_fullscreen(event_loop.primary_monitor()))
+        .build(&event_loop)
+        .unwrap();
+
+    // Fullscreen windows take the monitor resolution
+    let (window_width, window_height) = if display_settings.is_fullscreen() {
+        let size = window.inner_size();
+        (size.width, size.height)
+    } else {
+        (window_width, window_height)
     };
 
-    let descriptor_pool = unsafe {
-        base.device
-            .create_descriptor_pool(&descriptor_pool_info, None)
-    }
-    .unwrap();
-
-    // Grid instances
-    let mut instances = Instances::new(&base.device, &mut base.allocator, diagonal_length);
-
-    // Grid renderer
-    let mut render_grids = RenderGrids::new(
-        &base.device,
-        &base.instance,
-        &mut base.allocator,
-        &descriptor_pool,
-        &render_pass,
-        &view_scissor,
-        &instances.instances_buffer_descriptor,
-        NUM_INSTANCES,
-    );
+    // Vulkan base initialization
+    let mut base = VulkanBase::new_with_settings(
+        &window,
+        window_width,
+        window_height,
+        MemoryProfile::default(),
+        DebugSettings::default(),
+        display_settings,
+    )
+    .unwrap_or_else(|err| {
+        println!("{}", tr_args("viewer.vulkan_error", &[("error", &err)]));
+        process::exit(1);
+    });
 
-    // Submit initialization command buffer before rendering starts
-    base.record_submit_commandbuffer(
-        0,
-        base.present_queue,
-        &[],
-        &[],
-        &[],
-        |device, command_buffer| {
-            // GPU setup commands
-            render_grids.gpu_setup(device, &command_buffer);
-        },
+    // Host visible buffers written while rendering, --upload-stats prints their bytes per
+    // frame with the performance info
+    let upload_stats = args.iter().any(|arg| arg == "--upload-stats");
+    let upload_pool = MappedBufferPool::new();
+    let mut resources = DeviceResources::new(
+        &mut base,
+        resources_config,
+        diagonal_length,
+        texture_path,
+        &upload_pool,
     );
+    if args.iter().any(|arg| arg == "--mem-stats") {
+        println!("{}", base.memory_report());
+    }
+    let uniform_strategy = if resources.render_grids.supports(uniform_strategy) {
+        uniform_strategy
+    } else {
+        println!("{}", tr("viewer.push_constants_fallback"));
+        UniformStrategy::HostVisible
+    };
+    let mut uniform_benchmark = benchmark_frames.map(|frames| {
+        let strategies = UniformStrategy::ALL
+            .iter()
+            .copied()
+            .filter(|&strategy| resources.render_grids.supports(strategy))
+            .collect();
+        UniformBenchmark::new(strategies, frames)
+    });
+    let mut view_scissor = base.view_scissor();
+    let mut needs_recreate = false;
+    let mut device_lost = false;
 
-    // Camera
+    // Camera, the orientation rotates +Z to the view direction
     struct Camera {
         position: Vec3,
-        direction: Vec3,
+        orientation: Quat,
+    }
+
+    impl Camera {
+        fn direction(&self) -> Vec3 {
+            self.orientation.rotate_vec3(Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            })
+        }
     }
 
     let mut camera = Camera {
@@ -234,19 +575,35 @@ fn main() {
             y: 2000.0,
             z: 4000.0,
         },
-        direction: Vec3 {
-            x: 0.0,
-            y: -0.5,
-            z: -1.0,
-        },
+        orientation: Quat::look_rotation(
+            Vec3 {
+                x: 0.0,
+                y: -0.5,
+                z: -1.0,
+            },
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+        ),
     };
 
+    let input_settings = InputSettings::from_args(&args).unwrap_or_else(|err| {
+        println!(
+            "{}",
+            tr_args("viewer.input_settings_failed", &[("error", &err)])
+        );
+        InputSettings::default()
+    });
+
     // Inputs
     #[derive(Clone, Copy)]
     struct Inputs {
         is_left_clicked: bool,
         cursor_position: (i32, i32),
         wheel_delta: f32,
+        raw_mouse_delta: (f32, f32),
         keyboard_forward: i32,
         keyboard_side: i32,
     }
@@ -257,6 +614,7 @@ fn main() {
                 is_left_clicked: false,
                 cursor_position: (0, 0),
                 wheel_delta: 0.0,
+                raw_mouse_delta: (0.0, 0.0),
                 keyboard_forward: 0,
                 keyboard_side: 0,
             }
@@ -264,13 +622,18 @@ fn main() {
     }
 
     // Window event loop
-    println!("Start window event loop");
+    println!("{}", tr("viewer.start_event_loop"));
 
     let mut inputs_prev: Inputs = Default::default();
     let mut inputs: Inputs = Default::default();
+    let mut mouse_look = MouseLook::new();
+    let mut time_prev_frame = Instant::now();
 
     let mut time_start = Instant::now();
     let mut frame = 0u32;
+    // Query results of the latest frame the GPU has finished
+    let mut samples_passed: Option<u64> = None;
+    let mut statistics: Option<PipelineStatistics> = None;
     let mut active_command_buffer = 0;
 
     let _ = event_loop.run(|event, event_loop_window_target| {
@@ -279,6 +642,7 @@ fn main() {
         match event {
             Event::NewEvents(_) => {
                 inputs.wheel_delta = 0.0;
+                inputs.raw_mouse_delta = (0.0, 0.0);
             }
 
             Event::AboutToWait => {
@@ -290,68 +654,122 @@ fn main() {
                 inputs_prev = inputs;
 
                 // Update camera based in inputs
-                let view_rot = view(
-                    Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 0.0,
-                    },
-                    camera.direction,
-                    Vec3 {
-                        x: 0.0,
-                        y: 1.0,
-                        z: 0.0,
-                    },
-                );
-
                 let forward_speed = inputs.wheel_delta * 5.0 + inputs.keyboard_forward as f32 * 1.5;
-                camera.position = camera.position + camera.direction * forward_speed;
+                camera.position = camera.position + camera.direction() * forward_speed;
 
                 let side_speed = inputs.keyboard_side as f32 * 1.5;
-                let side_vec = Vec3 {
-                    x: view_rot.r0.x,
-                    y: view_rot.r1.x,
-                    z: view_rot.r2.x,
-                };
+                let side_vec = camera.orientation.rotate_vec3(Vec3 {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                });
                 camera.position = camera.position + side_vec * side_speed;
 
-                if inputs.is_left_clicked {
-                    let rot = rot_y_axis(cursor_delta.0 as f32 * 0.0015)
-                        * rot_x_axis(cursor_delta.1 as f32 * 0.0015);
+                // Raw mouse deltas bypass OS pointer acceleration
+                let now = Instant::now();
+                let frame_seconds = (now - time_prev_frame).as_secs_f32();
+                time_prev_frame = now;
+                let mouse_delta = if !inputs.is_left_clicked {
+                    (0.0, 0.0)
+                } else if input_settings.raw_mouse {
+                    inputs.raw_mouse_delta
+                } else {
+                    (cursor_delta.0 as f32, cursor_delta.1 as f32)
+                };
+                let (yaw, pitch) = mouse_look.update(&input_settings, mouse_delta, frame_seconds);
+                if yaw != 0.0 || pitch != 0.0 {
+                    camera.orientation = camera.orientation.add_yaw_pitch(yaw, pitch);
+                }
 
-                    let rot = rot * inverse(view_rot);
+                // Re-create the device and everything on it, long benchmark runs keep going
+                if device_lost {
+                    println!("{}", tr("viewer.device_lost"));
+                    let size = window.inner_size();
+                    resources.destroy(&mut base);
+                    base.try_recover(size.width, size.height)
+                        .unwrap_or_else(|err| {
+                            println!("{}", tr_args("viewer.vulkan_error", &[("error", &err)]));
+                            process::exit(1);
+                        });
+                    resources = DeviceResources::new(
+                        &mut base,
+                        resources_config,
+                        diagonal_length,
+                        texture_path,
+                        &upload_pool,
+                    );
+                    view_scissor = base.view_scissor();
+                    active_command_buffer = 0;
+                    device_lost = false;
+                    needs_recreate = false;
+                }
 
-                    camera.direction = Vec3 {
-                        x: 0.0,
-                        y: 0.0,
-                        z: 1.0,
-                    } * rot;
+                // Scenario runs switch the grid resources and the window size between runs
+                if let Some(run) = scenario_runner.as_ref().and_then(|runner| runner.current()) {
+                    let config = (run.technique, run.instance_count, run.instance_format);
+                    if config != resources_config {
+                        resources_config = config;
+                        let _ = unsafe { base.device.device_wait_idle() };
+                        resources.destroy(&mut base);
+                        resources = DeviceResources::new(
+                            &mut base,
+                            resources_config,
+                            diagonal_length,
+                            texture_path,
+                            &upload_pool,
+                        );
+                        active_command_buffer = 0;
+                    }
+                    let size = window.inner_size();
+                    if (size.width, size.height) != run.resolution {
+                        let (width, height) = run.resolution;
+                        let _ =
+                            window.request_inner_size(winit::dpi::PhysicalSize::new(width, height));
+                    }
+                }
 
-                    camera.direction = camera.direction.normalize();
+                // Recreate swapchain after resize or OUT_OF_DATE, skip rendering while minimized
+                if needs_recreate {
+                    let size = window.inner_size();
+                    if size.width == 0 || size.height == 0 {
+                        return;
+                    }
+
+                    let recreated = base.recreate_swapchain(size.width, size.height);
+                    if check_device_lost(recreated, &mut device_lost).is_none() {
+                        return;
+                    }
+                    for framebuffer in resources.framebuffers.drain(..) {
+                        base.defer_destroy(Deletion::Framebuffer(framebuffer));
+                    }
+                    resources.framebuffers = base.create_framebuffers(resources.render_pass);
+                    view_scissor = base.view_scissor();
+                    needs_recreate = false;
                 }
 
-                // Render
-                let (present_index, _) = unsafe {
-                    base.swapchain_loader.acquire_next_image(
-                        base.swapchain,
-                        std::u64::MAX,
-                        base.present_complete_semaphore,
-                        vk::Fence::null(),
-                    )
+                // Destroy resources replaced in earlier frames once the GPU is done with them
+                let collected = base.collect_deletions();
+                if check_device_lost(collected, &mut device_lost).is_none() {
+                    return;
                 }
-                .unwrap();
 
-                // Update uniform buffer
-                let color = Vec4 {
-                    x: 1.0,
-                    y: 0.1,
-                    z: 0.0,
-                    w: 0.0,
+                // Render
+                let acquired = base.acquire_next_image();
+                let present_index = match check_device_lost(acquired, &mut device_lost) {
+                    Some(FrameResult::Ok(present_index)) => present_index,
+                    Some(FrameResult::NeedsRecreate) => {
+                        needs_recreate = true;
+                        return;
+                    }
+                    None => return,
                 };
 
+                // Update uniform buffer
+                let color = ColorRgba::new(1.0, 0.1, 0.0, 0.0);
+
                 let world_to_screen = view(
                     camera.position,
-                    camera.direction,
+                    camera.direction(),
                     Vec3 {
                         x: 0.0,
                         y: 1.0,
@@ -359,7 +777,7 @@ fn main() {
                     },
                 ) * projection(
                     std::f32::consts::PI / 2.0,
-                    window_width as f32 / window_height as f32,
+                    base.surface_resolution.width as f32 / base.surface_resolution.height as f32,
                     1.0,
                     10000000.0,
                 );
@@ -370,7 +788,15 @@ fn main() {
                     center_to_edge: center_to_edge.to_4d(),
                 };
 
-                render_grids.update(&grid_uniforms);
+                resources.render_grids.update(&grid_uniforms);
+                let mut frame_context = FrameContext {
+                    resolution: base.surface_resolution,
+                    view_scissor,
+                    world_to_screen,
+                    camera_position: camera.position,
+                    camera_direction: camera.direction(),
+                    scratch: None,
+                };
 
                 // Setup render passs
                 let clear_values = [
@@ -388,8 +814,8 @@ fn main() {
                 ];
 
                 let render_pass_begin_info = vk::RenderPassBeginInfo {
-                    render_pass,
-                    framebuffer: framebuffers[present_index as usize],
+                    render_pass: resources.render_pass,
+                    framebuffer: resources.framebuffers[present_index as usize],
                     render_area: vk::Rect2D {
                         offset: vk::Offset2D { x: 0, y: 0 },
                         extent: base.surface_resolution,
@@ -400,15 +826,83 @@ fn main() {
                 };
 
                 // Submit main command buffer
-                active_command_buffer = base.record_submit_commandbuffer(
+                let strategy = uniform_benchmark
+                    .as_ref()
+                    .map_or(uniform_strategy, |benchmark| benchmark.strategy());
+                // Scenario runs may switch to a technique without push constants
+                let strategy = if resources.render_grids.supports(strategy) {
+                    strategy
+                } else {
+                    UniformStrategy::HostVisible
+                };
+                let render_grids = &mut resources.render_grids;
+                let occlusion_queries = &resources.occlusion_queries;
+                let statistics_queries = resources.statistics_queries.as_ref();
+                let timestamp_queries = resources.timestamp_queries.as_ref();
+                let frame_strategies = &mut resources.frame_strategies;
+                let uniform_uploads = &mut resources.uniform_uploads;
+                let benchmark = &mut uniform_benchmark;
+                let barrier_api = &base.barrier_api;
+                let latest_samples = &mut samples_passed;
+                let mut frame_gpu_ms = None;
+                let latest_gpu_ms = &mut frame_gpu_ms;
+                let latest_statistics = &mut statistics;
+                let frame_command_buffer = active_command_buffer;
+                let submitted = base.record_submit_commandbuffer(
                     active_command_buffer,
                     base.present_queue,
                     &[vk::PipelineStageFlags::BOTTOM_OF_PIPE],
                     &[base.present_complete_semaphore],
                     &[base.rendering_complete_semaphore],
                     |device, command_buffer| {
-                        // Draw/setup (before main render pass)
-                        render_grids.gpu_draw(device, &command_buffer);
+                        uniform_uploads.begin_frame(frame_command_buffer);
+
+                        // The fence of this command buffer has been waited, its queries from
+                        // the previous use are done
+                        let query = frame_command_buffer as u32;
+                        let fetched = occlusion_queries.samples_passed(device, query);
+                        if let Some(samples) = fetched.expect("Occlusion query read failed") {
+                            *latest_samples = Some(samples);
+                        }
+                        occlusion_queries.cmd_reset(device, command_buffer, query, 1);
+                        if let Some(statistics_queries) = statistics_queries {
+                            let fetched = statistics_queries.statistics(device, query);
+                            if let Some(fetched) = fetched.expect("Statistics query read failed") {
+                                *latest_statistics = Some(fetched);
+                            }
+                            statistics_queries.cmd_reset(device, command_buffer, query, 1);
+                        }
+                        if let Some(timestamp_queries) = timestamp_queries {
+                            let gpu_ms = timestamp_queries
+                                .elapsed_ms(device, 2 * query, 2 * query + 1)
+                                .expect("Timestamp query read failed");
+                            *latest_gpu_ms = gpu_ms;
+                            let previous = frame_strategies[frame_command_buffer];
+                            if let (Some(benchmark), Some(previous), Some(gpu_ms)) =
+                                (benchmark.as_mut(), previous, gpu_ms)
+                            {
+                                benchmark.add_gpu_time(previous, gpu_ms);
+                            }
+                            timestamp_queries.cmd_reset(device, command_buffer, 2 * query, 2);
+                            timestamp_queries.cmd_write_timestamp(
+                                device,
+                                command_buffer,
+                                vk::PipelineStageFlags::TOP_OF_PIPE,
+                                2 * query,
+                            );
+                        }
+                        frame_strategies[frame_command_buffer] = Some(strategy);
+
+                        // Uniforms of this frame, staging copies them before the render pass
+                        let upload_start = Instant::now();
+                        render_grids.upload_uniforms(
+                            device,
+                            barrier_api,
+                            command_buffer,
+                            strategy,
+                            uniform_uploads,
+                        );
+                        let mut cpu_time = upload_start.elapsed();
 
                         // Render pass
                         unsafe {
@@ -421,37 +915,125 @@ fn main() {
                             device.cmd_set_scissor(command_buffer, 0, &[view_scissor.scissor]);
                         }
 
-                        // Draw (main render pass)
-                        render_grids.gpu_draw_main_render_pass(device, &command_buffer);
+                        // Draw (main render pass), counted by both queries
+                        if let Some(statistics_queries) = statistics_queries {
+                            statistics_queries.cmd_begin(device, command_buffer, query);
+                        }
+                        let draw_start = Instant::now();
+                        occlusion_queries.cmd_scoped(device, command_buffer, query, || {
+                            if let Some(draw) = render_grids.record_main_pass(&mut frame_context) {
+                                draw(device, command_buffer);
+                            }
+                        });
+                        cpu_time += draw_start.elapsed();
+                        if let Some(statistics_queries) = statistics_queries {
+                            statistics_queries.cmd_end(device, command_buffer, query);
+                        }
 
                         unsafe {
                             device.cmd_end_render_pass(command_buffer);
                         }
+                        if let Some(timestamp_queries) = timestamp_queries {
+                            timestamp_queries.cmd_write_timestamp(
+                                device,
+                                command_buffer,
+                                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                                2 * query + 1,
+                            );
+                        }
+                        if let Some(benchmark) = benchmark.as_mut() {
+                            benchmark.add_cpu_time(strategy, cpu_time.as_secs_f64() * 1000.0);
+                        }
                     },
                 );
+                active_command_buffer = match check_device_lost(submitted, &mut device_lost) {
+                    Some(next_command_buffer) => next_command_buffer,
+                    None => return,
+                };
 
                 // Present frame
-                let present_info = vk::PresentInfoKHR {
-                    wait_semaphore_count: 1,
-                    p_wait_semaphores: &base.rendering_complete_semaphore,
-                    swapchain_count: 1,
-                    p_swapchains: &base.swapchain,
-                    p_image_indices: &present_index,
-                    ..Default::default()
-                };
+                match check_device_lost(base.present(present_index), &mut device_lost) {
+                    Some(FrameResult::NeedsRecreate) => needs_recreate = true,
+                    Some(FrameResult::Ok(_)) | None => {}
+                }
 
-                unsafe {
-                    base.swapchain_loader
-                        .queue_present(base.present_queue, &present_info)
+                if let Some(benchmark) = &mut uniform_benchmark {
+                    if benchmark.end_frame() {
+                        benchmark.print(resources.render_grids.technique);
+                    }
+                }
+
+                if let Some(runner) = &mut scenario_runner {
+                    if let Some(gpu_ms) = frame_gpu_ms {
+                        runner.add_gpu_time(gpu_ms);
+                    }
+                    let (run, total) = runner.progress();
+                    let resolution = base.surface_resolution;
+                    let technique = resources.render_grids.technique;
+                    let instance_format = resources.instances.format;
+                    let resolution = (resolution.width, resolution.height);
+                    let finished = runner
+                        .end_frame(technique, instance_format, strategy, resolution)
+                        .cloned();
+                    if let Some(result) = &finished {
+                        let gpu_ms = result
+                            .gpu_ms
+                            .map_or("-".to_string(), |ms| format!("{:.4}", ms));
+                        println!(
+                            "{}",
+                            tr_args(
+                                "viewer.scenario_run",
+                                &[
+                                    ("run", &(run + 1)),
+                                    ("total", &total),
+                                    ("technique", &result.technique),
+                                    ("instances", &result.instances),
+                                    ("format", &result.instance_format),
+                                    ("width", &result.resolution[0]),
+                                    ("height", &result.resolution[1]),
+                                    ("frame_ms", &format!("{:.4}", result.frame_ms)),
+                                    ("gpu_ms", &gpu_ms),
+                                ]
+                            )
+                        );
+                    }
+                    if finished.is_some() && runner.current().is_none() {
+                        let properties =
+                            unsafe { base.instance.get_physical_device_properties(base.pdevice) };
+                        let device = properties.device_name_as_c_str().unwrap_or_default();
+                        let device = device.to_string_lossy();
+                        match runner.write_report(&device) {
+                            Ok(Some(json)) => println!("{}", json),
+                            Ok(None) => {}
+                            Err(err) => println!(
+                                "{}",
+                                tr_args("viewer.scenario_write_failed", &[("error", &err)])
+                            ),
+                        }
+                        event_loop_window_target.exit();
+                    }
                 }
-                .unwrap();
 
                 // Output performance info every 60 frames
+                upload_pool.end_frame();
                 frame += 1;
-                if (frame % 60) == 0 {
+                if frame.is_multiple_of(60) {
                     let time_now = Instant::now();
                     let interval = (time_now - time_start).as_millis();
-                    println!("Average frame time: {} ms", interval as f32 / 60.0f32);
+                    let frame_time = interval as f32 / 60.0f32;
+                    println!(
+                        "{}",
+                        tr_args("viewer.average_frame_time", &[("ms", &frame_time)])
+                    );
+                    let upload_report = upload_pool.take_report();
+                    if upload_stats {
+                        print_upload_report(&upload_report);
+                    }
+                    print_query_results(
+                        resources.render_grids.technique,
+                        statistics,
+                        samples_passed,
+                    );
 
                     time_start = time_now;
                 }
@@ -460,21 +1042,27 @@ fn main() {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => event_loop_window_target.exit(),
 
-                // TODO: Handle swapchain resize
-                WindowEvent::Resized { .. } => {}
+                WindowEvent::Resized { .. } => needs_recreate = true,
 
                 // Keyboard
                 WindowEvent::KeyboardInput { event, .. } => {
                     let pressed = event.state == ElementState::Pressed;
-                    match &event.physical_key {
-                        PhysicalKey::Code(keycode) => match keycode {
-                            winit::keyboard::KeyCode::KeyW => inputs.keyboard_forward = if pressed { 1 } else { 0 },
-                            winit::keyboard::KeyCode::KeyS => inputs.keyboard_forward = if pressed { -1 } else { 0 },
-                            winit::keyboard::KeyCode::KeyD => inputs.keyboard_side = if pressed { 1 } else { 0 },
-                            winit::keyboard::KeyCode::KeyA => inputs.keyboard_side = if pressed { -1 } else { 0 },
+                    if let PhysicalKey::Code(keycode) = &event.physical_key {
+                        match keycode {
+                            winit::keyboard::KeyCode::KeyW => {
+                                inputs.keyboard_forward = if pressed { 1 } else { 0 }
+                            }
+                            winit::keyboard::KeyCode::KeyS => {
+                                inputs.keyboard_forward = if pressed { -1 } else { 0 }
+                            }
+                            winit::keyboard::KeyCode::KeyD => {
+                                inputs.keyboard_side = if pressed { 1 } else { 0 }
+                            }
+                            winit::keyboard::KeyCode::KeyA => {
+                                inputs.keyboard_side = if pressed { -1 } else { 0 }
+                            }
                             _ => {}
-                        },
-                        _ => {}
+                        }
                     }
                 }
 
@@ -500,22 +1088,23 @@ fn main() {
             },
 
             // No LoopDestroyed in winit 0.29.x
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => {
+                inputs.raw_mouse_delta.0 += delta.0 as f32;
+                inputs.raw_mouse_delta.1 += delta.1 as f32;
+            }
+
             _ => (),
         }
     });
 
-    println!("End window event loop");
+    println!("{}", tr("viewer.end_event_loop"));
 
-    unsafe { base.device.device_wait_idle() }.unwrap();
+    // Errors are ignored, a lost device still has to be cleaned up
+    let _ = unsafe { base.device.device_wait_idle() };
 
     // Cleanup
-    instances.destroy(&base.device, &mut base.allocator);
-    render_grids.destroy(&base.device, &mut base.allocator);
-    unsafe {
-        base.device.destroy_descriptor_pool(descriptor_pool, None);
-        for framebuffer in framebuffers {
-            base.device.destroy_framebuffer(framebuffer, None);
-        }
-        base.device.destroy_render_pass(render_pass, None);
-    }
+    resources.destroy(&mut base);
 }
@@ -1,4 +1,5 @@
-enum GridTechnique {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GridTechnique {
     Color,
     PrimId,
     NonIndexed,
@@ -7,44 +8,291 @@ enum GridTechnique {
     MeshShader,
 }
 
-const GRID_TECHNIQUE: GridTechnique = GridTechnique::LeadingVertex;
+pub const GRID_TECHNIQUE: GridTechnique = GridTechnique::LeadingVertex;
+
+// Mesh shader groups are dispatched in 2D to stay below maxMeshWorkGroupCount
+const MESH_GROUPS_PER_ROW: usize = 32768;
+
+// Specialization constant of the vertex and mesh shaders selecting PackedInstanceData
+const HALF_INSTANCES_CONSTANT_ID: u32 = 0;
+
+impl GridTechnique {
+    pub fn from_name(name: &str) -> Option<GridTechnique> {
+        match name {
+            "color" => Some(GridTechnique::Color),
+            "primid" => Some(GridTechnique::PrimId),
+            "nonindexed" => Some(GridTechnique::NonIndexed),
+            "leadingvertex" => Some(GridTechnique::LeadingVertex),
+            "getattributeatvertex" => Some(GridTechnique::GetAttributeAtVertex),
+            "mesh" => Some(GridTechnique::MeshShader),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridTechnique::Color => "color",
+            GridTechnique::PrimId => "primid",
+            GridTechnique::NonIndexed => "nonindexed",
+            GridTechnique::LeadingVertex => "leadingvertex",
+            GridTechnique::GetAttributeAtVertex => "getattributeatvertex",
+            GridTechnique::MeshShader => "mesh",
+        }
+    }
+}
+
+// How the grid uniforms get to the GPU each frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UniformStrategy {
+    HostVisible,   // Written to mapped CpuToGpu memory, read by the shaders over the bus
+    Staging,       // Copied from a staging ring to GpuOnly memory before the render pass
+    PushConstants, // Recorded into the command buffer, needs the leadingvertex technique
+}
+
+impl UniformStrategy {
+    pub const ALL: [UniformStrategy; 3] = [
+        UniformStrategy::HostVisible,
+        UniformStrategy::Staging,
+        UniformStrategy::PushConstants,
+    ];
+
+    pub fn from_name(name: &str) -> Option<UniformStrategy> {
+        match name {
+            "host" => Some(UniformStrategy::HostVisible),
+            "staging" => Some(UniformStrategy::Staging),
+            "push" => Some(UniformStrategy::PushConstants),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UniformStrategy::HostVisible => "host",
+            UniformStrategy::Staging => "staging",
+            UniformStrategy::PushConstants => "push",
+        }
+    }
+}
+
+const LEADINGVERTEX_PUSH_SPV: &[u8] =
+    include_bytes!("../../../shader/vbuffer_leadingvertex_push_vert.spv");
+
+// Vertex or mesh shader of the technique
+fn geometry_spv(technique: GridTechnique) -> &'static [u8] {
+    match technique {
+        GridTechnique::Color => include_bytes!("../../../shader/vbuffer_vert.spv"),
+        GridTechnique::PrimId => include_bytes!("../../../shader/vbuffer_vert.spv"),
+        GridTechnique::NonIndexed => include_bytes!("../../../shader/vbuffer_nonindexed_vert.spv"),
+        GridTechnique::LeadingVertex => {
+            include_bytes!("../../../shader/vbuffer_leadingvertex_vert.spv")
+        }
+        GridTechnique::GetAttributeAtVertex => {
+            include_bytes!("../../../shader/vbuffer_getattributeatvertex_vert.spv")
+        }
+        GridTechnique::MeshShader => include_bytes!("../../../shader/vbuffer_meshshader_mesh.spv"),
+    }
+}
+
+// Per frame memory of the host visible and staging strategies
+pub struct UniformUploads {
+    pub host_visible: ScratchAllocator,
+    pub staging: StagingRing,
+    pub device_local: ScratchAllocator, // Copy destination of the staging strategy
+}
+
+impl UniformUploads {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: &GpuBudget,
+        num_frames: usize,
+    ) -> Result<UniformUploads, AllocationError> {
+        const FRAME_SIZE: u64 = 4 * 1024;
+        let host_visible = ScratchAllocator::new(
+            device,
+            allocator,
+            budget,
+            FRAME_SIZE,
+            num_frames,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryLocation::CpuToGpu,
+        )?;
+        let staging = StagingRing::new(device, allocator, budget, FRAME_SIZE, num_frames)?;
+        let device_local = ScratchAllocator::new(
+            device,
+            allocator,
+            budget,
+            FRAME_SIZE,
+            num_frames,
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+        Ok(UniformUploads {
+            host_visible,
+            staging,
+            device_local,
+        })
+    }
+
+    // Call after waiting for the previous command buffer recorded with this frame index
+    pub fn begin_frame(&mut self, frame: usize) {
+        self.host_visible.begin_frame(frame);
+        self.staging.begin_frame(frame);
+        self.device_local.begin_frame(frame);
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        self.host_visible.destroy(device, allocator);
+        self.staging.destroy(device, allocator);
+        self.device_local.destroy(device, allocator);
+    }
+}
+
+// Where the main render pass draw finds this frame's uniforms
+#[derive(Clone, Copy, Debug)]
+pub enum GridUniformBinding {
+    Dynamic { set: usize, offset: u32 }, // Descriptor set index and dynamic offset
+    PushConstants,
+}
+
+// Leadingvertex pipeline reading the uniforms from push constants
+pub struct PushConstantPipeline {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub graphic_pipeline: vk::Pipeline,
+    pub vertex_shader_module: vk::ShaderModule,
+}
 
 use ash::Instance;
 use std::default::Default;
-use std::ffi::CString;
 use std::io::Cursor;
-use std::mem;
 
 use ash::util::*;
 use ash::{vk, Device};
 
-use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
+use crate::allocator_telemetry::*;
+use crate::color::*;
+use crate::instances::InstanceFormat;
+use crate::localization::*;
 use crate::minivector::*;
+use crate::render_module::*;
+use crate::vulkan_base::MeshShader;
+use crate::vulkan_base::*;
 use crate::vulkan_helpers::*;
 
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct GridUniforms {
     pub world_to_screen: Mat4x4,
-    pub color: Vec4,
+    pub color: ColorRgba,
     pub center_to_edge: Vec4,
 }
 
+unsafe impl Zeroable for GridUniforms {}
+unsafe impl Pod for GridUniforms {}
+
+pub struct GridsDraw {
+    pub pipeline_layout: vk::PipelineLayout,
+    pub graphic_pipeline: vk::Pipeline,
+    pub push_constant_pipeline: Option<(vk::PipelineLayout, vk::Pipeline)>,
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub index_buffer: vk::Buffer,
+    pub num_indices: u32,
+    pub technique: GridTechnique,
+    pub mesh_shader: Option<MeshShader>,
+    pub num_instances: usize,
+    pub uniforms: GridUniforms,
+    pub uniform_binding: GridUniformBinding,
+}
+
+impl GridsDraw {
+    pub fn record(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
+        unsafe {
+            match self.uniform_binding {
+                GridUniformBinding::Dynamic { set, offset } => {
+                    device.cmd_bind_descriptor_sets(
+                        *command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline_layout,
+                        0,
+                        &self.descriptor_sets[set..set + 1],
+                        &[offset],
+                    );
+                    device.cmd_bind_pipeline(
+                        *command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.graphic_pipeline,
+                    );
+                }
+                GridUniformBinding::PushConstants => {
+                    // The push constant shaders don't read binding 0, offset 0 keeps it valid
+                    let (push_layout, push_pipeline) = self.push_constant_pipeline.unwrap();
+                    device.cmd_bind_descriptor_sets(
+                        *command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        push_layout,
+                        0,
+                        &self.descriptor_sets[..1],
+                        &[0],
+                    );
+                    device.cmd_push_constants(
+                        *command_buffer,
+                        push_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        bytemuck::bytes_of(&self.uniforms),
+                    );
+                    device.cmd_bind_pipeline(
+                        *command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        push_pipeline,
+                    );
+                }
+            }
+
+            device.cmd_bind_index_buffer(
+                *command_buffer,
+                self.index_buffer,
+                0,
+                vk::IndexType::UINT32,
+            );
+
+            match self.technique {
+                GridTechnique::NonIndexed => {
+                    device.cmd_draw(*command_buffer, self.num_indices, 1, 0, 0)
+                }
+                GridTechnique::MeshShader => {
+                    let groups_x = self.num_instances.min(MESH_GROUPS_PER_ROW);
+                    let groups_y = self.num_instances.div_ceil(groups_x);
+                    self.mesh_shader.as_ref().unwrap().cmd_draw_mesh_tasks(
+                        *command_buffer,
+                        groups_x as u32,
+                        groups_y as u32,
+                        1,
+                    );
+                }
+                _ => device.cmd_draw_indexed(*command_buffer, self.num_indices, 1, 0, 0, 0),
+            }
+        }
+    }
+}
+
 pub struct RenderGrids {
     pub pipeline_layout: vk::PipelineLayout,
     pub index_buffer: VkBuffer,
     pub index_buffer_gpu: VkBuffer,
-    pub uniform_buffer: VkBuffer,
-    pub uniform_buffer_gpu: VkBuffer,
     pub desc_set_layout: vk::DescriptorSetLayout,
     pub graphic_pipeline: vk::Pipeline,
     pub descriptor_sets: Vec<vk::DescriptorSet>,
     pub vertex_shader_module: vk::ShaderModule,
     pub fragment_shader_module: vk::ShaderModule,
-    // TODO: Re-enable mesh_shader field if/when ash exposes MeshShader extension loader
-    // pub mesh_shader: ash::extensions::nv::MeshShader,
+    pub technique: GridTechnique,
+    pub mesh_shader: Option<MeshShader>,
     pub num_instances: usize,
+    pub uniforms: GridUniforms,
+    pub push_constant_pipeline: Option<PushConstantPipeline>,
+    pub uniform_binding: Option<GridUniformBinding>, // Set by upload_uniforms
 }
 
 impl RenderGrids {
@@ -52,25 +300,38 @@ impl RenderGrids {
     pub fn new(
         device: &Device,
         _instance: &Instance,
-        allocator: &mut Allocator,
-        descriptor_pool: &vk::DescriptorPool,
+        allocator: &mut TrackingAllocator,
+        descriptor_allocator: &mut DescriptorAllocator,
         render_pass: &vk::RenderPass,
         view_scissor: &VkViewScissor,
+        uploads: &UniformUploads, // Binding 0 reads the uniforms from its per frame regions
         instances_buffer_descriptor: &vk::DescriptorBufferInfo,
+        texture_descriptor: Option<&vk::DescriptorImageInfo>, // Fills binding 3 when given
         num_instances: usize,
+        technique: GridTechnique,
+        instance_format: InstanceFormat, // From instance_format_for, the layout of the buffer
+        mesh_shader: Option<MeshShader>,
     ) -> RenderGrids {
-        // TODO: Re-enable mesh_shader instantiation if/when ash exposes MeshShader extension loader
-        // let mesh_shader = ash::extensions::nv::MeshShader::new(instance, device);
+        let technique = if technique == GridTechnique::MeshShader && mesh_shader.is_none() {
+            let fallback = format!("{:?}", GRID_TECHNIQUE);
+            println!(
+                "{}",
+                tr_args("viewer.mesh_shader_fallback", &[("technique", &fallback)])
+            );
+            GRID_TECHNIQUE
+        } else {
+            technique
+        };
 
         const GRID_DIM: usize = 7;
         const NUM_GRID_INDICES: usize = GRID_DIM * GRID_DIM * 2 * 3;
 
-        let grid_stride = if let GridTechnique::LeadingVertex = GRID_TECHNIQUE {
+        let grid_stride = if let GridTechnique::LeadingVertex = technique {
             (GRID_DIM + 1) * 2
         } else {
             GRID_DIM + 1
         };
-        let instance_stride = if let GridTechnique::LeadingVertex = GRID_TECHNIQUE {
+        let instance_stride = if let GridTechnique::LeadingVertex = technique {
             GRID_DIM * (GRID_DIM + 1) * 2
         } else {
             (GRID_DIM + 1) * (GRID_DIM + 1)
@@ -117,7 +378,9 @@ impl RenderGrids {
             &index_buffer_info,
             MemoryLocation::CpuToGpu,
         );
-        index_buffer.copy_from_slice(&index_buffer_data[..], 0);
+        index_buffer
+            .copy_from_slice(&index_buffer_data[..], 0)
+            .unwrap();
 
         let index_buffer_gpu_info = vk::BufferCreateInfo {
             size: std::mem::size_of_val(&index_buffer_data[..]) as u64,
@@ -133,45 +396,16 @@ impl RenderGrids {
             MemoryLocation::GpuOnly,
         );
 
-        let uniform_buffer_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<GridUniforms>() as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_SRC,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
-        };
-
-        let uniform_buffer = VkBuffer::new(
-            device,
-            allocator,
-            &uniform_buffer_info,
-            MemoryLocation::CpuToGpu,
-        );
-
-        let uniform_buffer_gpu_info = vk::BufferCreateInfo {
-            size: std::mem::size_of::<GridUniforms>() as u64,
-            usage: vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::UNIFORM_BUFFER,
-            sharing_mode: vk::SharingMode::EXCLUSIVE,
-            ..Default::default()
+        let geom_shader_stage: vk::ShaderStageFlags = if let GridTechnique::MeshShader = technique {
+            vk::ShaderStageFlags::MESH_EXT
+        } else {
+            vk::ShaderStageFlags::VERTEX
         };
 
-        let uniform_buffer_gpu = VkBuffer::new(
-            device,
-            allocator,
-            &uniform_buffer_gpu_info,
-            MemoryLocation::GpuOnly,
-        );
-
-        let geom_shader_stage: vk::ShaderStageFlags =
-            if let GridTechnique::MeshShader = GRID_TECHNIQUE {
-                vk::ShaderStageFlags::MESH_NV
-            } else {
-                vk::ShaderStageFlags::VERTEX
-            };
-
         let desc_layout_bindings = [
             vk::DescriptorSetLayoutBinding {
                 binding: 0,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 descriptor_count: 1,
                 stage_flags: vk::ShaderStageFlags::FRAGMENT | geom_shader_stage,
                 ..Default::default()
@@ -209,41 +443,47 @@ impl RenderGrids {
 
         let desc_set_layouts = &[desc_set_layout];
 
-        let desc_alloc_info = vk::DescriptorSetAllocateInfo {
-            descriptor_pool: *descriptor_pool,
-            descriptor_set_count: 1,
-            p_set_layouts: desc_set_layouts.as_ptr(),
-            ..Default::default()
-        };
-
-        let descriptor_sets = {
-            unsafe { device.allocate_descriptor_sets(&desc_alloc_info) }.unwrap()
-        };
+        // Set 0 reads the uniforms from host visible memory, set 1 from the staging copies
+        let descriptor_sets = descriptor_allocator
+            .allocate_sets(device, &[desc_set_layout, desc_set_layout])
+            .unwrap();
 
-        let uniform_buffer_descriptor = vk::DescriptorBufferInfo {
-            buffer: uniform_buffer_gpu.buffer,
-            offset: 0,
-            range: mem::size_of::<GridUniforms>() as u64,
-        };
+        let uniform_buffer_descriptors = [
+            uploads.host_visible.uniform_descriptor::<GridUniforms>(),
+            uploads.device_local.uniform_descriptor::<GridUniforms>(),
+        ];
 
-        let write_desc_sets = [
-            vk::WriteDescriptorSet {
-                dst_set: descriptor_sets[0],
+        let mut write_desc_sets = Vec::new();
+        for (&dst_set, uniform_buffer_descriptor) in
+            descriptor_sets.iter().zip(&uniform_buffer_descriptors)
+        {
+            write_desc_sets.push(vk::WriteDescriptorSet {
+                dst_set,
                 dst_binding: 0,
                 descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                p_buffer_info: &uniform_buffer_descriptor,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                p_buffer_info: uniform_buffer_descriptor,
                 ..Default::default()
-            },
-            vk::WriteDescriptorSet {
-                dst_set: descriptor_sets[0],
+            });
+            write_desc_sets.push(vk::WriteDescriptorSet {
+                dst_set,
                 dst_binding: 1,
                 descriptor_count: 1,
                 descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
                 p_buffer_info: instances_buffer_descriptor,
                 ..Default::default()
-            },
-        ];
+            });
+            if let Some(texture_descriptor) = texture_descriptor {
+                write_desc_sets.push(vk::WriteDescriptorSet {
+                    dst_set,
+                    dst_binding: 3,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    p_image_info: texture_descriptor,
+                    ..Default::default()
+                });
+            }
+        }
         unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
 
         let layout_create_info = vk::PipelineLayoutCreateInfo {
@@ -255,24 +495,9 @@ impl RenderGrids {
         let pipeline_layout =
             unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
 
-        let mut vertex_spv_file = Cursor::new(match GRID_TECHNIQUE {
-            GridTechnique::Color => &include_bytes!("../../../shader/vbuffer_vert.spv")[..],
-            GridTechnique::PrimId => &include_bytes!("../../../shader/vbuffer_vert.spv")[..],
-            GridTechnique::NonIndexed => {
-                &include_bytes!("../../../shader/vbuffer_nonindexed_vert.spv")[..]
-            }
-            GridTechnique::LeadingVertex => {
-                &include_bytes!("../../../shader/vbuffer_leadingvertex_vert.spv")[..]
-            }
-            GridTechnique::GetAttributeAtVertex => {
-                &include_bytes!("../../../shader/vbuffer_getattributeatvertex_vert.spv")[..]
-            }
-            GridTechnique::MeshShader => {
-                &include_bytes!("../../../shader/vbuffer_meshshader_mesh.spv")[..]
-            }
-        });
+        let mut vertex_spv_file = Cursor::new(geometry_spv(technique));
 
-        let mut frag_spv_file = Cursor::new(match GRID_TECHNIQUE {
+        let mut frag_spv_file = Cursor::new(match technique {
             GridTechnique::Color => &include_bytes!("../../../shader/vbuffer_color_frag.spv")[..],
             GridTechnique::PrimId => &include_bytes!("../../../shader/vbuffer_primid_frag.spv")[..],
             GridTechnique::NonIndexed => {
@@ -313,139 +538,154 @@ impl RenderGrids {
             unsafe { device.create_shader_module(&frag_shader_info, None) }
                 .expect("Fragment shader module error");
 
-        let shader_entry_name = CString::new("main").unwrap();
-        let shader_stage_create_infos = [
-            vk::PipelineShaderStageCreateInfo {
-                module: vertex_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: geom_shader_stage,
+        let half_instances = (instance_format == InstanceFormat::Half) as u32;
+        let graphic_pipeline = GraphicsPipelineBuilder::new()
+            .stage(geom_shader_stage, vertex_shader_module)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader_module)
+            .view_scissor(view_scissor)
+            .specialization_constant(HALF_INSTANCES_CONSTANT_ID, half_instances)
+            .build(device, pipeline_layout, *render_pass)
+            .unwrap();
+
+        // Only the leadingvertex vertex shader has a push constant variant, its fragment
+        // shader doesn't read the uniforms
+        let push_constant_pipeline = if technique == GridTechnique::LeadingVertex {
+            let push_constant_range = vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::VERTEX,
+                offset: 0,
+                size: std::mem::size_of::<GridUniforms>() as u32,
+            };
+            let layout_create_info = vk::PipelineLayoutCreateInfo {
+                set_layout_count: desc_set_layouts.len() as u32,
+                p_set_layouts: desc_set_layouts.as_ptr(),
+                push_constant_range_count: 1,
+                p_push_constant_ranges: &push_constant_range,
                 ..Default::default()
-            },
-            vk::PipelineShaderStageCreateInfo {
-                module: fragment_shader_module,
-                p_name: shader_entry_name.as_ptr(),
-                stage: vk::ShaderStageFlags::FRAGMENT,
+            };
+            let pipeline_layout =
+                unsafe { device.create_pipeline_layout(&layout_create_info, None) }.unwrap();
+
+            let mut spv_file = Cursor::new(LEADINGVERTEX_PUSH_SPV);
+            let code = read_spv(&mut spv_file).expect("Failed to read vertex shader spv file");
+            let shader_info = vk::ShaderModuleCreateInfo {
+                code_size: code.len() * 4,
+                p_code: code.as_ptr(),
                 ..Default::default()
-            },
-        ];
-
-        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo::default();
-
-        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-            ..Default::default()
-        };
-
-        let scissors = &[view_scissor.scissor];
-        let viewports = &[view_scissor.viewport];
-        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
-            viewport_count: viewports.len() as u32,
-            p_viewports: viewports.as_ptr(),
-            scissor_count: scissors.len() as u32,
-            p_scissors: scissors.as_ptr(),
-            ..Default::default()
-        };
-
-        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
-            cull_mode: vk::CullModeFlags::NONE,
-            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-            line_width: 1.0,
-            polygon_mode: vk::PolygonMode::FILL,
-            ..Default::default()
-        };
-
-        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
-            rasterization_samples: vk::SampleCountFlags::TYPE_1,
-            ..Default::default()
-        };
-
-        let noop_stencil_state = vk::StencilOpState {
-            fail_op: vk::StencilOp::KEEP,
-            pass_op: vk::StencilOp::KEEP,
-            depth_fail_op: vk::StencilOp::KEEP,
-            compare_op: vk::CompareOp::ALWAYS,
-            ..Default::default()
-        };
-        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
-            depth_test_enable: 1,
-            depth_write_enable: 1,
-            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
-            front: noop_stencil_state,
-            back: noop_stencil_state,
-            max_depth_bounds: 1.0,
-            ..Default::default()
-        };
-
-        let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-            blend_enable: 0,
-            src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
-            color_blend_op: vk::BlendOp::ADD,
-            src_alpha_blend_factor: vk::BlendFactor::ZERO,
-            dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-            alpha_blend_op: vk::BlendOp::ADD,
-            color_write_mask: vk::ColorComponentFlags::RGBA,
-        }];
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
-            logic_op: vk::LogicOp::CLEAR,
-            attachment_count: color_blend_attachment_states.len() as u32,
-            p_attachments: color_blend_attachment_states.as_ptr(),
-            ..Default::default()
-        };
-
-        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
-            dynamic_state_count: dynamic_state.len() as u32,
-            p_dynamic_states: dynamic_state.as_ptr(),
-            ..Default::default()
-        };
+            };
+            let vertex_shader_module = unsafe { device.create_shader_module(&shader_info, None) }
+                .expect("Vertex shader module error");
 
-        let graphic_pipeline_infos = vk::GraphicsPipelineCreateInfo {
-            stage_count: shader_stage_create_infos.len() as u32,
-            p_stages: shader_stage_create_infos.as_ptr(),
-            p_vertex_input_state: &vertex_input_state_info,
-            p_input_assembly_state: &vertex_input_assembly_state_info,
-            p_viewport_state: &viewport_state_info,
-            p_rasterization_state: &rasterization_info,
-            p_multisample_state: &multisample_state_info,
-            p_depth_stencil_state: &depth_state_info,
-            p_color_blend_state: &color_blend_state,
-            p_dynamic_state: &dynamic_state_info,
-            layout: pipeline_layout,
-            render_pass: *render_pass,
-            ..Default::default()
+            let graphic_pipeline = GraphicsPipelineBuilder::new()
+                .stage(vk::ShaderStageFlags::VERTEX, vertex_shader_module)
+                .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader_module)
+                .view_scissor(view_scissor)
+                .specialization_constant(HALF_INSTANCES_CONSTANT_ID, half_instances)
+                .build(device, pipeline_layout, *render_pass)
+                .unwrap();
+
+            Some(PushConstantPipeline {
+                pipeline_layout,
+                graphic_pipeline,
+                vertex_shader_module,
+            })
+        } else {
+            None
         };
 
-        let graphics_pipelines = unsafe {
-            device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[graphic_pipeline_infos],
-                None,
-            )
-        }
-        .unwrap();
-
-        let graphic_pipeline = graphics_pipelines[0];
-
         RenderGrids {
             pipeline_layout,
             index_buffer,
             index_buffer_gpu,
-            uniform_buffer,
-            uniform_buffer_gpu,
             desc_set_layout,
             graphic_pipeline,
             descriptor_sets,
             vertex_shader_module,
             fragment_shader_module,
-            // TODO: Re-enable mesh_shader field if/when ash exposes MeshShader extension loader
-            // mesh_shader,
+            technique,
+            mesh_shader,
             num_instances,
+            uniforms: GridUniforms::zeroed(),
+            push_constant_pipeline,
+            uniform_binding: None,
         }
     }
 
-    pub fn update(&self, uniforms: &GridUniforms) {
-        self.uniform_buffer.copy_from_slice(&[*uniforms], 0);
+    pub fn supports(&self, strategy: UniformStrategy) -> bool {
+        strategy != UniformStrategy::PushConstants || self.push_constant_pipeline.is_some()
+    }
+
+    // Puts this frame's uniforms where strategy reads them from. Staging records a copy and
+    // has to be called outside the render pass.
+    pub fn upload_uniforms(
+        &mut self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        strategy: UniformStrategy,
+        uploads: &mut UniformUploads,
+    ) {
+        let binding = match strategy {
+            UniformStrategy::HostVisible => {
+                let offset = uploads
+                    .host_visible
+                    .push_uniform(&self.uniforms)
+                    .expect("Grid uniforms don't fit the scratch buffer");
+                GridUniformBinding::Dynamic { set: 0, offset }
+            }
+            UniformStrategy::Staging => {
+                let size = std::mem::size_of::<GridUniforms>() as u64;
+                let range = uploads
+                    .device_local
+                    .allocate(size, UNIFORM_ALIGNMENT)
+                    .expect("Grid uniforms don't fit the device local buffer");
+                uploads
+                    .staging
+                    .copy_to_buffer(
+                        device,
+                        command_buffer,
+                        std::slice::from_ref(&self.uniforms),
+                        range.buffer,
+                        range.offset,
+                    )
+                    .expect("Grid uniforms don't fit the staging ring");
+                let shader_stages = if self.technique == GridTechnique::MeshShader {
+                    vk::PipelineStageFlags2::MESH_SHADER_EXT
+                } else {
+                    vk::PipelineStageFlags2::VERTEX_SHADER
+                };
+                let buffer_barrier = vk::BufferMemoryBarrier2 {
+                    src_stage_mask: vk::PipelineStageFlags2::COPY,
+                    src_access_mask: vk::AccessFlags2::TRANSFER_WRITE,
+                    dst_stage_mask: shader_stages | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                    dst_access_mask: vk::AccessFlags2::UNIFORM_READ,
+                    buffer: range.buffer,
+                    offset: range.offset,
+                    size,
+                    ..Default::default()
+                };
+                barrier2(
+                    device,
+                    barrier_api,
+                    command_buffer,
+                    &[],
+                    &[buffer_barrier],
+                    &[],
+                );
+                GridUniformBinding::Dynamic {
+                    set: 1,
+                    offset: range.offset as u32,
+                }
+            }
+            UniformStrategy::PushConstants => {
+                assert!(self.supports(strategy), "No push constant pipeline");
+                GridUniformBinding::PushConstants
+            }
+        };
+        self.uniform_binding = Some(binding);
+    }
+
+    pub fn update(&mut self, uniforms: &GridUniforms) {
+        self.uniforms = *uniforms;
     }
 
     pub fn gpu_setup(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
@@ -502,118 +742,65 @@ impl RenderGrids {
         };
     }
 
-    pub fn gpu_draw(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        let buffer_copy_regions = vk::BufferCopy {
-            src_offset: 0,
-            dst_offset: 0,
-            size: self.uniform_buffer.size,
-        };
-
-        let buffer_barrier = vk::BufferMemoryBarrier {
-            dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            buffer: self.uniform_buffer_gpu.buffer,
-            offset: 0,
-            size: buffer_copy_regions.size,
-            ..Default::default()
-        };
-
-        let buffer_barrier_end = vk::BufferMemoryBarrier {
-            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-            dst_access_mask: vk::AccessFlags::INDEX_READ,
-            buffer: self.uniform_buffer_gpu.buffer,
-            offset: 0,
-            size: buffer_copy_regions.size,
-            ..Default::default()
-        };
-
-        unsafe {
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier],
-                &[],
-            );
-
-            device.cmd_copy_buffer(
-                *command_buffer,
-                self.uniform_buffer.buffer,
-                self.uniform_buffer_gpu.buffer,
-                &[buffer_copy_regions],
-            );
-
-            device.cmd_pipeline_barrier(
-                *command_buffer,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::VERTEX_INPUT,
-                vk::DependencyFlags::empty(),
-                &[],
-                &[buffer_barrier_end],
-                &[],
-            );
-        }
-    }
-
-    pub fn gpu_draw_main_render_pass(&self, device: &Device, command_buffer: &vk::CommandBuffer) {
-        unsafe {
-            device.cmd_bind_descriptor_sets(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline_layout,
-                0,
-                &self.descriptor_sets[..],
-                &[],
-            );
-
-            device.cmd_bind_pipeline(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.graphic_pipeline,
-            );
-
-            device.cmd_bind_index_buffer(
-                *command_buffer,
-                self.index_buffer_gpu.buffer,
-                0,
-                vk::IndexType::UINT32,
-            );
-
-            match GRID_TECHNIQUE {
-                GridTechnique::NonIndexed => device.cmd_draw(
-                    *command_buffer,
-                    self.index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
-                    1,
-                    0,
-                    0,
-                ),
-                GridTechnique::MeshShader => {
-                    // TODO: Re-enable mesh_shader draw call if/when ash exposes MeshShader extension loader
-                },
-                _ => device.cmd_draw_indexed(
-                    *command_buffer,
-                    self.index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
-                    1,
-                    0,
-                    0,
-                    0,
-                ),
-            }
+    // Copies the handles, so the draw can be recorded on a worker thread. The uniforms are
+    // read from where upload_uniforms put them this frame.
+    pub fn main_render_pass_draw(&self) -> GridsDraw {
+        GridsDraw {
+            pipeline_layout: self.pipeline_layout,
+            graphic_pipeline: self.graphic_pipeline,
+            push_constant_pipeline: self
+                .push_constant_pipeline
+                .as_ref()
+                .map(|push| (push.pipeline_layout, push.graphic_pipeline)),
+            descriptor_sets: self.descriptor_sets.clone(),
+            index_buffer: self.index_buffer_gpu.buffer,
+            num_indices: self.index_buffer_gpu.size as u32 / std::mem::size_of::<u32>() as u32,
+            technique: self.technique,
+            mesh_shader: self.mesh_shader.clone(),
+            num_instances: self.num_instances,
+            uniforms: self.uniforms,
+            uniform_binding: self
+                .uniform_binding
+                .expect("Grid uniforms weren't uploaded this frame"),
         }
     }
 
-    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
         unsafe {
             device.destroy_pipeline(self.graphic_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
             device.destroy_shader_module(self.vertex_shader_module, None);
             device.destroy_shader_module(self.fragment_shader_module, None);
+            if let Some(push) = self.push_constant_pipeline.take() {
+                device.destroy_pipeline(push.graphic_pipeline, None);
+                device.destroy_pipeline_layout(push.pipeline_layout, None);
+                device.destroy_shader_module(push.vertex_shader_module, None);
+            }
             self.index_buffer.destroy(device, allocator);
             self.index_buffer_gpu.destroy(device, allocator);
-            self.uniform_buffer.destroy(device, allocator);
-            self.uniform_buffer_gpu.destroy(device, allocator);
             device.destroy_descriptor_set_layout(self.desc_set_layout, None);
         }
     }
 }
+
+impl RenderModule for RenderGrids {
+    fn create(
+        &mut self,
+        device: &Device,
+        _barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.gpu_setup(device, &command_buffer);
+    }
+
+    fn record_main_pass(&mut self, _frame: &mut FrameContext) -> Option<MainPassDraw> {
+        let draw = self.main_render_pass_draw();
+        Some(Box::new(move |device, command_buffer| {
+            draw.record(device, &command_buffer)
+        }))
+    }
+
+    fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        RenderGrids::destroy(self, device, allocator);
+    }
+}
@@ -0,0 +1,294 @@
+// vbufferbench --scenario FILE runs a benchmark matrix from a TOML file and exits, so that perf
+// runs on different machines measure the same thing (data/grids-scenario.toml):
+//
+//   id = "grids-2026-10"
+//   techniques = ["primid", "leadingvertex", "mesh"]
+//   instance_counts = [16384, 131072]   # Default 131072
+//   instance_formats = ["full", "half"] # Default full, half float instance data
+//   resolutions = [[1920, 1080], [1280, 720]]   # Default 1920x1080
+//   repetitions = 3                     # Default 1
+//   warmup_frames = 30                  # Default 30, not measured
+//   frames = 300                        # Default 300, measured frames per run
+//   output = "results.json"             # Default stdout
+//
+// Every combination of technique, instance count, instance format and resolution runs
+// `repetitions` times in that nesting order. The camera stays at its start position and
+// --uniforms picks the uniform strategy. Results are JSON with the scenario id, the device
+// name and the average frame and GPU time of every run.
+
+use crate::instances::*;
+use crate::render_grids::*;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    InvalidValue(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(err) => write!(f, "{}", err),
+            ScenarioError::Parse(err) => write!(f, "{}", err),
+            ScenarioError::InvalidValue(key) => write!(f, "Invalid value for {}", key),
+        }
+    }
+}
+
+fn default_instance_counts() -> Vec<usize> {
+    vec![NUM_INSTANCES]
+}
+
+fn default_instance_formats() -> Vec<String> {
+    vec![InstanceFormat::Full.name().to_string()]
+}
+
+fn default_resolutions() -> Vec<[u32; 2]> {
+    vec![[1920, 1080]]
+}
+
+fn default_repetitions() -> u32 {
+    1
+}
+
+fn default_warmup_frames() -> u32 {
+    30
+}
+
+fn default_frames() -> u32 {
+    300
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub id: String,
+    pub techniques: Vec<String>,
+    #[serde(default = "default_instance_counts")]
+    pub instance_counts: Vec<usize>,
+    #[serde(default = "default_instance_formats")]
+    pub instance_formats: Vec<String>,
+    #[serde(default = "default_resolutions")]
+    pub resolutions: Vec<[u32; 2]>,
+    #[serde(default = "default_repetitions")]
+    pub repetitions: u32,
+    #[serde(default = "default_warmup_frames")]
+    pub warmup_frames: u32,
+    #[serde(default = "default_frames")]
+    pub frames: u32,
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScenarioRun {
+    pub technique: GridTechnique,
+    pub instance_count: usize,
+    pub instance_format: InstanceFormat,
+    pub resolution: (u32, u32),
+    pub repetition: u32,
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario = toml::from_str(text).map_err(ScenarioError::Parse)?;
+        let invalid = |key: &str| Err(ScenarioError::InvalidValue(key.to_string()));
+        if scenario.techniques.is_empty() {
+            return invalid("techniques");
+        }
+        if let Some(name) = scenario
+            .techniques
+            .iter()
+            .find(|name| GridTechnique::from_name(name).is_none())
+        {
+            return invalid(&format!("techniques ({})", name));
+        }
+        if scenario.instance_counts.is_empty() || scenario.instance_counts.contains(&0) {
+            return invalid("instance_counts");
+        }
+        if scenario.instance_formats.is_empty() {
+            return invalid("instance_formats");
+        }
+        if let Some(name) = scenario
+            .instance_formats
+            .iter()
+            .find(|name| InstanceFormat::from_name(name).is_none())
+        {
+            return invalid(&format!("instance_formats ({})", name));
+        }
+        let empty_resolution = |resolution: &[u32; 2]| resolution.contains(&0);
+        if scenario.resolutions.is_empty() || scenario.resolutions.iter().any(empty_resolution) {
+            return invalid("resolutions");
+        }
+        if scenario.repetitions == 0 {
+            return invalid("repetitions");
+        }
+        if scenario.frames == 0 {
+            return invalid("frames");
+        }
+        Ok(scenario)
+    }
+
+    pub fn load(path: &Path) -> Result<Scenario, ScenarioError> {
+        let text = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        Scenario::parse(&text)
+    }
+
+    // Techniques outermost, repetitions innermost, so device resources are only recreated
+    // when the technique, instance count or instance format changes
+    pub fn runs(&self) -> Vec<ScenarioRun> {
+        let mut runs = Vec::new();
+        for name in &self.techniques {
+            let technique = GridTechnique::from_name(name).unwrap();
+            for &instance_count in &self.instance_counts {
+                for format_name in &self.instance_formats {
+                    let instance_format = InstanceFormat::from_name(format_name).unwrap();
+                    for resolution in &self.resolutions {
+                        for repetition in 0..self.repetitions {
+                            runs.push(ScenarioRun {
+                                technique,
+                                instance_count,
+                                instance_format,
+                                resolution: (resolution[0], resolution[1]),
+                                repetition,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        runs
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RunResult {
+    pub technique: &'static str, // The one that ran, mesh falls back without mesh shaders
+    pub uniforms: &'static str,
+    pub instances: usize,
+    pub instance_format: &'static str, // The one that ran, see instance_format_for
+    pub resolution: [u32; 2],          // Surface size while measuring, the window may refuse a size
+    pub repetition: u32,
+    pub frames: u32,
+    pub frame_ms: f64,
+    pub gpu_ms: Option<f64>, // None without timestamp queries
+}
+
+#[derive(Serialize)]
+struct ScenarioReport<'a> {
+    scenario: &'a str,
+    device: &'a str,
+    runs: &'a [RunResult],
+}
+
+pub struct ScenarioRunner {
+    pub scenario: Scenario,
+    runs: Vec<ScenarioRun>,
+    run: usize,
+    frame: u32, // Frames of the current run, warmup included
+    measure_start: Instant,
+    gpu_frames: u32,
+    gpu_ms: f64,
+    pub results: Vec<RunResult>,
+}
+
+impl ScenarioRunner {
+    pub fn new(scenario: Scenario) -> ScenarioRunner {
+        let runs = scenario.runs();
+        ScenarioRunner {
+            scenario,
+            runs,
+            run: 0,
+            frame: 0,
+            measure_start: Instant::now(),
+            gpu_frames: 0,
+            gpu_ms: 0.0,
+            results: Vec::new(),
+        }
+    }
+
+    // None once every run has finished
+    pub fn current(&self) -> Option<ScenarioRun> {
+        self.runs.get(self.run).copied()
+    }
+
+    pub fn progress(&self) -> (usize, usize) {
+        (self.run.min(self.runs.len()), self.runs.len())
+    }
+
+    fn measuring(&self) -> bool {
+        self.frame >= self.scenario.warmup_frames
+    }
+
+    // GPU times arrive a few frames late, the warmup covers the ones of the previous run
+    pub fn add_gpu_time(&mut self, ms: f64) {
+        if self.measuring() {
+            self.gpu_frames += 1;
+            self.gpu_ms += ms;
+        }
+    }
+
+    // Returns the result when the frame finished the current run
+    pub fn end_frame(
+        &mut self,
+        technique: GridTechnique,
+        instance_format: InstanceFormat,
+        uniforms: UniformStrategy,
+        resolution: (u32, u32),
+    ) -> Option<&RunResult> {
+        let run = self.current()?;
+        self.frame += 1;
+        if self.frame == self.scenario.warmup_frames {
+            self.measure_start = Instant::now();
+            self.gpu_frames = 0;
+            self.gpu_ms = 0.0;
+        }
+        let frames = self.scenario.frames;
+        if self.frame < self.scenario.warmup_frames + frames {
+            return None;
+        }
+
+        let elapsed = self.measure_start.elapsed().as_secs_f64() * 1000.0;
+        self.results.push(RunResult {
+            technique: technique.name(),
+            uniforms: uniforms.name(),
+            instances: run.instance_count,
+            instance_format: instance_format.name(),
+            resolution: [resolution.0, resolution.1],
+            repetition: run.repetition,
+            frames,
+            frame_ms: elapsed / frames as f64,
+            gpu_ms: (self.gpu_frames > 0).then(|| self.gpu_ms / self.gpu_frames as f64),
+        });
+        self.run += 1;
+        self.frame = 0;
+        self.measure_start = Instant::now();
+        self.results.last()
+    }
+
+    pub fn report_json(&self, device: &str) -> String {
+        let report = ScenarioReport {
+            scenario: &self.scenario.id,
+            device,
+            runs: &self.results,
+        };
+        serde_json::to_string_pretty(&report).unwrap()
+    }
+
+    // Written to the scenario output file, or returned for printing without one
+    pub fn write_report(&self, device: &str) -> io::Result<Option<String>> {
+        let json = self.report_json(device);
+        match &self.scenario.output {
+            Some(path) => fs::write(path, json).map(|_| None),
+            None => Ok(Some(json)),
+        }
+    }
+}
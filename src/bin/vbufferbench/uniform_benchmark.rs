@@ -0,0 +1,98 @@
+// --uniform-benchmark [FRAMES] draws the same grids with every uniform strategy in turn,
+// FRAMES frames each, and prints the average CPU recording time and GPU time per strategy
+// after every full cycle. CPU time covers the uniform upload and the recording of the draw,
+// GPU time the whole command buffer (timestamps at top and bottom of pipe).
+
+use crate::localization::*;
+use crate::render_grids::*;
+
+pub const DEFAULT_FRAMES_PER_STRATEGY: u32 = 120;
+
+#[derive(Clone, Copy, Default)]
+struct StrategyTimes {
+    cpu_frames: u32,
+    cpu_ms: f64,
+    gpu_frames: u32,
+    gpu_ms: f64,
+}
+
+pub struct UniformBenchmark {
+    strategies: Vec<UniformStrategy>,
+    frames_per_strategy: u32,
+    frame: u32,
+    times: Vec<StrategyTimes>,
+}
+
+impl UniformBenchmark {
+    pub fn new(strategies: Vec<UniformStrategy>, frames_per_strategy: u32) -> UniformBenchmark {
+        assert!(!strategies.is_empty(), "No uniform strategy to benchmark");
+        let times = vec![StrategyTimes::default(); strategies.len()];
+        UniformBenchmark {
+            strategies,
+            frames_per_strategy: frames_per_strategy.max(1),
+            frame: 0,
+            times,
+        }
+    }
+
+    pub fn strategy(&self) -> UniformStrategy {
+        let block = self.frame / self.frames_per_strategy;
+        self.strategies[block as usize % self.strategies.len()]
+    }
+
+    // True when the frame ended a full cycle through the strategies
+    pub fn end_frame(&mut self) -> bool {
+        self.frame += 1;
+        let cycle_frames = self.frames_per_strategy * self.strategies.len() as u32;
+        self.frame.is_multiple_of(cycle_frames)
+    }
+
+    fn times_mut(&mut self, strategy: UniformStrategy) -> &mut StrategyTimes {
+        let index = self.strategies.iter().position(|&s| s == strategy).unwrap();
+        &mut self.times[index]
+    }
+
+    pub fn add_cpu_time(&mut self, strategy: UniformStrategy, ms: f64) {
+        let times = self.times_mut(strategy);
+        times.cpu_frames += 1;
+        times.cpu_ms += ms;
+    }
+
+    // GPU times arrive when the command buffer is reused, a few frames after its CPU time
+    pub fn add_gpu_time(&mut self, strategy: UniformStrategy, ms: f64) {
+        let times = self.times_mut(strategy);
+        times.gpu_frames += 1;
+        times.gpu_ms += ms;
+    }
+
+    pub fn print(&self, technique: GridTechnique) {
+        let technique = format!("{:?}", technique);
+        println!(
+            "{}",
+            tr_args("viewer.uniform_benchmark", &[("technique", &technique)])
+        );
+        for (strategy, times) in self.strategies.iter().zip(&self.times) {
+            let average = |ms: f64, frames: u32| {
+                if frames == 0 {
+                    "-".to_string()
+                } else {
+                    format!("{:.4}", ms / frames as f64)
+                }
+            };
+            let cpu_ms = average(times.cpu_ms, times.cpu_frames);
+            let gpu_ms = average(times.gpu_ms, times.gpu_frames);
+            println!(
+                "{}",
+                tr_args(
+                    "viewer.uniform_benchmark_row",
+                    &[
+                        ("strategy", &strategy.name()),
+                        ("cpu_ms", &cpu_ms),
+                        ("gpu_ms", &gpu_ms),
+                        ("frames", &times.cpu_frames),
+                    ]
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+// CPU block compression of volume data. BC4 stores a 4x4 block of one 8 bit channel in 8
+// bytes: two endpoints and a 3 bit palette index per texel, a quarter of 16 bit texels.
+// 3D images compress every slice on its own, BC blocks are 4x4x1.
+
+pub const BC4_BLOCK_BYTES: usize = 8;
+
+// Endpoints are the block minimum and maximum in the 8 value mode (first endpoint greater),
+// every texel takes the nearest palette entry. Flat blocks repeat the value.
+pub fn encode_bc4_block(texels: &[u8; 16]) -> [u8; BC4_BLOCK_BYTES] {
+    let max = *texels.iter().max().unwrap();
+    let min = *texels.iter().min().unwrap();
+    let mut block = [max, min, 0, 0, 0, 0, 0, 0];
+    if max == min {
+        return block;
+    }
+
+    let range = (max - min) as u32;
+    let mut indices = 0u64;
+    for (i, &texel) in texels.iter().enumerate() {
+        // Step 0 is max, step 7 is min, palette index 0 and 1 are the endpoints and
+        // 2-7 the interpolated steps 1-6
+        let step = (((max - texel) as u32 * 7 + range / 2) / range) as u64;
+        let index = match step {
+            0 => 0,
+            7 => 1,
+            step => step + 1,
+        };
+        indices |= index << (3 * i);
+    }
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block
+}
+
+pub fn bc4_compressed_bytes(size: (u32, u32, u32)) -> usize {
+    (size.0.div_ceil(4) * size.1.div_ceil(4) * size.2) as usize * BC4_BLOCK_BYTES
+}
+
+// Compresses texels laid out x fastest, then y, then z, into blocks in the layout
+// vkCmdCopyBufferToImage expects with a zero row length and image height. Blocks reaching
+// past the edge repeat the last row and column.
+pub fn compress_bc4(texels: &[u8], size: (u32, u32, u32)) -> Vec<u8> {
+    let (width, height, depth) = size;
+    assert_eq!(texels.len(), (width * height * depth) as usize);
+    let mut blocks = Vec::with_capacity(bc4_compressed_bytes(size));
+    for z in 0..depth {
+        for block_y in 0..height.div_ceil(4) {
+            for block_x in 0..width.div_ceil(4) {
+                let mut block = [0u8; 16];
+                for (i, texel) in block.iter_mut().enumerate() {
+                    let x = (block_x * 4 + i as u32 % 4).min(width - 1);
+                    let y = (block_y * 4 + i as u32 / 4).min(height - 1);
+                    *texel = texels[(x + y * width + z * width * height) as usize];
+                }
+                blocks.extend_from_slice(&encode_bc4_block(&block));
+            }
+        }
+    }
+    blocks
+}
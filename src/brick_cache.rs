@@ -0,0 +1,325 @@
+// Bounded CPU cache of bricks read lazily from an .svosdf file. Only the brick directory
+// (file offset, size and position of every brick) stays in memory, brick data is read and
+// decoded on demand and the least recently used bricks are evicted. Lets sampling and
+// physics queries run on files much larger than the memory they are allowed to use.
+// Bricks come from a BrickSource, the local file or a content server (brick_stream). Files
+// with brick checksums have every brick verified when it is read.
+
+use crate::brick_checksum::*;
+use crate::file_format::*;
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::serialization::*;
+use crate::svosdf::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// brick size, brick count
+const BRICK_DIRECTORY_HEADER_BYTES: usize = 2 * 4;
+// size and position of every brick
+const BRICK_HEADER_BYTES: usize = 4 * 4;
+
+// Largest brick a directory may list, 32 MB of voxels
+pub const MAX_BRICK_SIZE: u32 = 256;
+
+// Voxel bytes of a brick of a directory read from a file or a content server. Sizes above the
+// directory's brick size or MAX_BRICK_SIZE are rejected before they are cubed.
+pub fn checked_data_bytes(size: u32, brick_size: u32) -> io::Result<usize> {
+    if size > brick_size || size > MAX_BRICK_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "brick size {} above {}",
+                size,
+                brick_size.min(MAX_BRICK_SIZE)
+            ),
+        ));
+    }
+    Ok((size as usize).pow(3) * std::mem::size_of::<u16>())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BrickEntry {
+    pub offset: u64, // Start of the voxel data in the file
+    pub size: u32,
+    pub position: UVec3,
+    pub checksum: Option<u64>, // None for files written before brick checksums
+}
+
+impl BrickEntry {
+    pub fn contains(&self, voxel: UVec3) -> bool {
+        voxel.all_ge(self.position) && voxel.all_lt(self.position + UVec3::splat(self.size))
+    }
+
+    // Directories check the size with checked_data_bytes when they are read
+    pub fn data_bytes(&self) -> usize {
+        checked_data_bytes(self.size, MAX_BRICK_SIZE).expect("Brick size above MAX_BRICK_SIZE")
+    }
+
+    pub fn verify(&self, index: usize, voxel_bytes: &[u8]) -> Result<(), ChecksumMismatch> {
+        match self.checksum {
+            Some(expected) => verify_brick(index, self.position, expected, voxel_bytes),
+            None => Ok(()),
+        }
+    }
+}
+
+// Header, size and position of every brick, without the voxel data
+pub struct BrickDirectory {
+    pub header: SdfHeader,
+    pub brick_size: u32,
+    pub entries: Vec<BrickEntry>,
+    grid: HashMap<UVec3, Vec<usize>>, // Brick grid cell -> entries overlapping it
+}
+
+impl BrickDirectory {
+    pub fn new(header: SdfHeader, brick_size: u32, entries: Vec<BrickEntry>) -> BrickDirectory {
+        let brick_size = brick_size.max(1);
+
+        // Bricks of non power of two volumes don't always start on the brick grid, so a
+        // brick is registered in every cell it overlaps
+        let mut grid: HashMap<UVec3, Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let first = entry.position / brick_size;
+            let last = (entry.position + UVec3::splat(entry.size.max(1) - 1)) / brick_size;
+            for z in first.z..=last.z {
+                for y in first.y..=last.y {
+                    for x in first.x..=last.x {
+                        grid.entry(UVec3::new(x, y, z)).or_default().push(i);
+                    }
+                }
+            }
+        }
+
+        BrickDirectory {
+            header,
+            brick_size,
+            entries,
+            grid,
+        }
+    }
+
+    // None for voxels in empty (uniform) space or outside the volume
+    pub fn brick_at(&self, voxel: UVec3) -> Option<usize> {
+        self.grid
+            .get(&(voxel / self.brick_size))?
+            .iter()
+            .copied()
+            .find(|&index| self.entries[index].contains(voxel))
+    }
+}
+
+// Where the brick cache reads bricks from on a miss
+pub trait BrickSource {
+    fn directory(&self) -> &BrickDirectory;
+    fn read_brick(&mut self, index: usize) -> io::Result<Brick>;
+}
+
+// Brick directory of an .svosdf file, the voxel data is left on disk
+pub struct BrickFile {
+    reader: BufReader<File>,
+    directory: BrickDirectory,
+    octree_offset: u64,         // Right after the last brick
+    pub verify_checksums: bool, // Corrupt bricks fail read_brick with a ChecksumMismatch
+}
+
+impl BrickFile {
+    pub fn open(path: &Path) -> io::Result<BrickFile> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let header = SdfHeader::read(&mut reader)?;
+        let mut bytes = [0u8; BRICK_DIRECTORY_HEADER_BYTES];
+        reader.read_exact(&mut bytes)?;
+        let mut loader = Loader::new();
+        let brick_size = loader.load_u32(&bytes).max(1);
+        let brick_count = loader.load_u32(&bytes);
+        let checksums = read_checksums(path)?
+            .filter(|checksums| checksums.checksums.len() == brick_count as usize);
+
+        // Skip over the voxel data, only the brick headers are read
+        let mut entries = Vec::with_capacity(brick_count as usize);
+        let mut offset = reader.stream_position()?;
+        for i in 0..brick_count as usize {
+            let mut bytes = [0u8; BRICK_HEADER_BYTES];
+            reader.read_exact(&mut bytes)?;
+            let mut loader = Loader::new();
+            let size = loader.load_u32(&bytes);
+            let data_bytes = checked_data_bytes(size, brick_size)?;
+            let position = UVec3::new(
+                loader.load_u32(&bytes),
+                loader.load_u32(&bytes),
+                loader.load_u32(&bytes),
+            );
+            let entry = BrickEntry {
+                offset: offset + BRICK_HEADER_BYTES as u64,
+                size,
+                position,
+                checksum: checksums.as_ref().map(|checksums| checksums.checksums[i]),
+            };
+            reader.seek_relative(data_bytes as i64)?;
+            offset = entry.offset + data_bytes as u64;
+            entries.push(entry);
+        }
+
+        Ok(BrickFile {
+            reader,
+            directory: BrickDirectory::new(header, brick_size, entries),
+            octree_offset: offset,
+            verify_checksums: true,
+        })
+    }
+
+    // Serialized octree section, parsed with SvoSdf::octree_from_bytes
+    pub fn read_octree(&mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.reader.seek(SeekFrom::Start(self.octree_offset))?;
+        self.reader.read_to_end(&mut bytes)?;
+        bytes.truncate(octree_bytes(&bytes)?);
+        Ok(bytes)
+    }
+}
+
+impl BrickSource for BrickFile {
+    fn directory(&self) -> &BrickDirectory {
+        &self.directory
+    }
+
+    fn read_brick(&mut self, index: usize) -> io::Result<Brick> {
+        let entry = self.directory.entries[index];
+        let mut bytes = vec![0u8; entry.data_bytes()];
+        self.reader.seek(SeekFrom::Start(entry.offset))?;
+        self.reader.read_exact(&mut bytes)?;
+        if self.verify_checksums {
+            entry.verify(index, &bytes)?;
+        }
+        let data = Loader::new().load_array_u16(&bytes, bytes.len() / 2);
+        Ok(Brick {
+            data,
+            size: entry.size,
+            position: entry.position,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BrickCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+struct CachedBrick {
+    brick: Brick,
+    last_used: u64,
+}
+
+pub struct BrickCache {
+    source: Box<dyn BrickSource>,
+    capacity: usize, // Bricks kept in memory
+    bricks: HashMap<usize, CachedBrick>,
+    clock: u64,
+    pub stats: BrickCacheStats,
+}
+
+impl BrickCache {
+    pub fn new(source: Box<dyn BrickSource>, capacity: usize) -> BrickCache {
+        BrickCache {
+            source,
+            capacity: capacity.max(1),
+            bricks: HashMap::new(),
+            clock: 0,
+            stats: BrickCacheStats::default(),
+        }
+    }
+
+    pub fn open(path: &Path, capacity: usize) -> io::Result<BrickCache> {
+        Ok(BrickCache::new(Box::new(BrickFile::open(path)?), capacity))
+    }
+
+    pub fn directory(&self) -> &BrickDirectory {
+        self.source.directory()
+    }
+
+    pub fn resident_bricks(&self) -> usize {
+        self.bricks.len()
+    }
+
+    pub fn resident_bytes(&self) -> usize {
+        self.bricks
+            .values()
+            .map(|cached| cached.brick.data.len() * std::mem::size_of::<u16>())
+            .sum()
+    }
+
+    pub fn brick(&mut self, index: usize) -> io::Result<&Brick> {
+        self.clock += 1;
+        if let Some(cached) = self.bricks.get_mut(&index) {
+            self.stats.hits += 1;
+            cached.last_used = self.clock;
+        } else {
+            self.stats.misses += 1;
+            if self.bricks.len() >= self.capacity {
+                self.evict_least_recently_used();
+            }
+            let brick = self.source.read_brick(index)?;
+            self.bricks.insert(
+                index,
+                CachedBrick {
+                    brick,
+                    last_used: self.clock,
+                },
+            );
+        }
+        Ok(&self.bricks[&index].brick)
+    }
+
+    // Linear scan, a miss already pays for a file or network read
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .bricks
+            .iter()
+            .min_by_key(|(_, cached)| cached.last_used)
+            .map(|(&index, _)| index);
+        if let Some(index) = oldest {
+            self.bricks.remove(&index);
+            self.stats.evictions += 1;
+        }
+    }
+
+    // Raw distance value of one voxel, None where the file has no brick
+    pub fn sample(&mut self, voxel: UVec3) -> io::Result<Option<u16>> {
+        let index = match self.source.directory().brick_at(voxel) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let brick = self.brick(index)?;
+        let local = voxel - brick.position;
+        Ok(Some(
+            brick.data[local.linear_index(UVec3::splat(brick.size))],
+        ))
+    }
+
+    // Raw distance value of the voxel nearest to a world space position
+    pub fn sample_position(&mut self, position: (f32, f32, f32)) -> io::Result<Option<u16>> {
+        let header = self.source.directory().header;
+        let voxel = |p: f32, min: f32, spacing: f32, dim: u32| {
+            let v = ((p - min) / spacing).round();
+            if v >= 0.0 && v < dim as f32 {
+                Some(v as u32)
+            } else {
+                None
+            }
+        };
+        match (
+            voxel(position.0, header.box_min.0, header.spacing.0, header.dim.0),
+            voxel(position.1, header.box_min.1, header.spacing.1, header.dim.1),
+            voxel(position.2, header.box_min.2, header.spacing.2, header.dim.2),
+        ) {
+            (Some(x), Some(y), Some(z)) => self.sample(UVec3::new(x, y, z)),
+            _ => Ok(None),
+        }
+    }
+}
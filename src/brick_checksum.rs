@@ -0,0 +1,213 @@
+// Per-brick xxHash64 checksums of .svosdf files, so silent corruption of large files is
+// reported on load instead of showing up as broken surfaces. The hash covers the voxel bytes
+// of a brick as stored in the file. The section sits between the brick ranges and the
+// thumbnail and ends with a fixed size footer, files written before it load unverified.
+// BrickFile and RemoteBricks verify every brick they read (BrickFile::verify_checksums turns
+// it off), SvoSdf::load fails on the first corrupt brick.
+
+use crate::minivector::*;
+use crate::serialization::*;
+use crate::thumbnail::*;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::thread::{self, JoinHandle};
+
+use xxhash_rust::xxh64::xxh64;
+
+const CHECKSUM_MAGIC: u32 = u32::from_le_bytes(*b"BXXH");
+// brick count, magic
+const CHECKSUM_FOOTER_BYTES: usize = 2 * 4;
+
+// Checksum of the little endian voxel bytes of a brick
+pub fn brick_checksum(voxel_bytes: &[u8]) -> u64 {
+    xxh64(voxel_bytes, 0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    pub index: usize,
+    pub position: UVec3, // Of the brick in voxels
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Brick {} at voxel ({}, {}, {}) is corrupt: checksum {:016x}, expected {:016x}",
+            self.index,
+            self.position.x,
+            self.position.y,
+            self.position.z,
+            self.actual,
+            self.expected
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+// io::Result paths report a mismatch as an InvalidData error carrying the ChecksumMismatch
+impl From<ChecksumMismatch> for io::Error {
+    fn from(mismatch: ChecksumMismatch) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, mismatch)
+    }
+}
+
+impl ChecksumMismatch {
+    pub fn from_io(err: &io::Error) -> Option<ChecksumMismatch> {
+        err.get_ref()?.downcast_ref::<ChecksumMismatch>().copied()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrickChecksums {
+    pub checksums: Vec<u64>, // Parallel to the bricks
+}
+
+impl BrickChecksums {
+    pub fn store(&self, storer: &mut StorerVec) {
+        for &checksum in &self.checksums {
+            storer.store_u64(checksum);
+        }
+        storer.store_u32(self.checksums.len() as u32);
+        storer.store_u32(CHECKSUM_MAGIC);
+    }
+
+    // Section at the end of bytes, pass the file without its thumbnail section. None when
+    // the file has no checksums.
+    pub fn from_tail(bytes: &[u8]) -> Option<BrickChecksums> {
+        let footer_start = bytes.len().checked_sub(CHECKSUM_FOOTER_BYTES)?;
+        let mut loader = Loader::new();
+        loader.offset = footer_start;
+        let count = loader.load_u32(bytes) as usize;
+        if loader.load_u32(bytes) != CHECKSUM_MAGIC {
+            return None;
+        }
+        loader.offset = footer_start.checked_sub(count * 8)?;
+        Some(BrickChecksums {
+            checksums: (0..count).map(|_| loader.load_u64(bytes)).collect(),
+        })
+    }
+
+    // Bytes of the section, checksums and footer
+    pub fn section_bytes(&self) -> usize {
+        self.checksums.len() * 8 + CHECKSUM_FOOTER_BYTES
+    }
+
+    pub fn verify(
+        &self,
+        index: usize,
+        position: UVec3,
+        voxel_bytes: &[u8],
+    ) -> Result<(), ChecksumMismatch> {
+        verify_brick(index, position, self.checksums[index], voxel_bytes)
+    }
+}
+
+pub fn verify_brick(
+    index: usize,
+    position: UVec3,
+    expected: u64,
+    voxel_bytes: &[u8],
+) -> Result<(), ChecksumMismatch> {
+    let actual = brick_checksum(voxel_bytes);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ChecksumMismatch {
+            index,
+            position,
+            expected,
+            actual,
+        })
+    }
+}
+
+// Reads only the checksum section of an .svosdf file
+pub fn read_checksums(path: &Path) -> io::Result<Option<BrickChecksums>> {
+    let mut file = File::open(path)?;
+    let thumbnail_bytes = read_thumbnail(path)?.map_or(0, |t| t.section_bytes() as u64);
+    let end = file.metadata()?.len().saturating_sub(thumbnail_bytes);
+    if end < CHECKSUM_FOOTER_BYTES as u64 {
+        return Ok(None);
+    }
+
+    let mut footer = [0u8; CHECKSUM_FOOTER_BYTES];
+    file.seek(SeekFrom::Start(end - CHECKSUM_FOOTER_BYTES as u64))?;
+    file.read_exact(&mut footer)?;
+    let mut loader = Loader::new();
+    let count = loader.load_u32(&footer) as u64;
+    if loader.load_u32(&footer) != CHECKSUM_MAGIC {
+        return Ok(None);
+    }
+
+    let section_bytes = count * 8 + CHECKSUM_FOOTER_BYTES as u64;
+    if section_bytes > end {
+        return Ok(None);
+    }
+    let mut bytes = vec![0u8; section_bytes as usize];
+    file.seek(SeekFrom::Start(end - section_bytes))?;
+    file.read_exact(&mut bytes)?;
+    Ok(BrickChecksums::from_tail(&bytes))
+}
+
+// Where the voxel bytes of a brick are in a loaded file
+#[derive(Clone, Copy, Debug)]
+pub struct BrickSpan {
+    pub position: UVec3,
+    pub offset: usize,
+    pub bytes: usize,
+}
+
+// Every mismatch of a loaded file, spans parallel to the checksums
+pub fn find_mismatches(
+    file_bytes: &[u8],
+    spans: &[BrickSpan],
+    checksums: &BrickChecksums,
+) -> Vec<ChecksumMismatch> {
+    spans
+        .iter()
+        .enumerate()
+        .filter_map(|(index, span)| {
+            let voxel_bytes = &file_bytes[span.offset..span.offset + span.bytes];
+            checksums.verify(index, span.position, voxel_bytes).err()
+        })
+        .collect()
+}
+
+// Checksums verified on a worker thread, which keeps the file bytes until it is done
+pub struct ChecksumVerification {
+    worker: Option<JoinHandle<Vec<ChecksumMismatch>>>,
+}
+
+impl ChecksumVerification {
+    pub fn spawn(
+        file_bytes: Vec<u8>,
+        spans: Vec<BrickSpan>,
+        checksums: BrickChecksums,
+    ) -> ChecksumVerification {
+        let worker = thread::spawn(move || find_mismatches(&file_bytes, &spans, &checksums));
+        ChecksumVerification {
+            worker: Some(worker),
+        }
+    }
+
+    // Nothing to verify, for files without checksums
+    pub fn none() -> ChecksumVerification {
+        ChecksumVerification { worker: None }
+    }
+
+    // The mismatches once, when the worker has finished
+    pub fn poll(&mut self) -> Option<Vec<ChecksumMismatch>> {
+        if !self.worker.as_ref()?.is_finished() {
+            return None;
+        }
+        let worker = self.worker.take()?;
+        Some(worker.join().expect("Checksum verification panicked"))
+    }
+}
@@ -0,0 +1,66 @@
+// Per brick (min, max) distance values. Bricks whose range doesn't reach the iso band
+// can't contain surface and are skipped by culling.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrickRange {
+    pub min: u16,
+    pub max: u16,
+}
+
+impl BrickRange {
+    pub fn from_data(data: &[u16]) -> BrickRange {
+        let (min, max) = min_max_u16(data);
+        BrickRange { min, max }
+    }
+
+    // Band [level - half_width, level + half_width] in distance units
+    pub fn intersects_band(&self, level: u16, half_width: u16) -> bool {
+        self.min <= level.saturating_add(half_width) && self.max >= level.saturating_sub(half_width)
+    }
+}
+
+// Empty input returns (u16::MAX, 0)
+pub fn min_max_u16(data: &[u16]) -> (u16, u16) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.1") {
+            return unsafe { min_max_u16_sse41(data) };
+        }
+    }
+    min_max_u16_scalar(data)
+}
+
+fn min_max_u16_scalar(data: &[u16]) -> (u16, u16) {
+    data.iter()
+        .fold((u16::MAX, 0), |(min, max), &v| (min.min(v), max.max(v)))
+}
+
+// 8 lanes per iteration, unsigned 16 bit min/max is SSE4.1
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.1")]
+unsafe fn min_max_u16_sse41(data: &[u16]) -> (u16, u16) {
+    let chunks = data.chunks_exact(8);
+    let (mut min, mut max) = min_max_u16_scalar(chunks.remainder());
+
+    if chunks.len() > 0 {
+        let mut min_v = _mm_set1_epi16(-1);
+        let mut max_v = _mm_setzero_si128();
+        for chunk in chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            min_v = _mm_min_epu16(min_v, v);
+            max_v = _mm_max_epu16(max_v, v);
+        }
+
+        // Horizontal min: minpos works on unsigned 16 bit lanes, max via inverted min
+        let lane_min = _mm_extract_epi16(_mm_minpos_epu16(min_v), 0) as u16;
+        let inverted_max = _mm_xor_si128(max_v, _mm_set1_epi16(-1));
+        let lane_max = !(_mm_extract_epi16(_mm_minpos_epu16(inverted_max), 0) as u16);
+
+        min = min.min(lane_min);
+        max = max.max(lane_max);
+    }
+    (min, max)
+}
@@ -0,0 +1,474 @@
+// Streams bricks of an .svosdf file from a content server over a minimal HTTP/1.1 subset,
+// so thin clients can sample volumes they don't have locally. The server answers
+//   GET /directory      header, brick size and size, position and checksum of every brick
+//   GET /brick/<index>  voxel data of one brick, little endian u16 like in the file
+//   GET /octree         octree section of the file, for clients that load the whole volume
+// on keep-alive connections, one thread per client up to MAX_CONNECTIONS. RemoteBricks is the
+// client side BrickSource, fetched bricks are optionally kept in an on-disk cache directory.
+// `svosdf sample --remote` reads single bricks through a BrickCache, `rendersvosdf --remote`
+// loads the octree and every brick with RemoteBricks::load_svo_sdf. Bricks of files with
+// checksums are verified when fetched and when read back from the disk cache, responses
+// larger than the brick or section they should hold are rejected unread.
+
+use crate::brick_cache::*;
+use crate::file_format::*;
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::serialization::*;
+use crate::svosdf::*;
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_PORT: u16 = 8470;
+
+// Only local clients unless the server is bound to another address with --bind
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+
+// Clients connecting while this many are served get 503 Service Unavailable
+pub const MAX_CONNECTIONS: usize = 32;
+
+// Requests with longer header blocks are rejected
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+
+// Connections idle or stalled for this long are closed, so they don't hold a connection slot
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Largest directory response the client accepts, 10 million bricks with checksums
+const MAX_DIRECTORY_BYTES: usize = 256 * 1024 * 1024;
+
+// Largest octree response the client accepts, about 8 million nodes
+const MAX_OCTREE_BYTES: usize = 256 * 1024 * 1024;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub fn directory_to_bytes(directory: &BrickDirectory) -> Vec<u8> {
+    let mut storer = StorerVec::new();
+    storer.store_array_u8(&directory.header.to_bytes());
+    storer.store_u32(directory.brick_size);
+    storer.store_u32(directory.entries.len() as u32);
+    let has_checksums = directory
+        .entries
+        .iter()
+        .all(|entry| entry.checksum.is_some());
+    storer.store_u32(has_checksums as u32);
+    for entry in &directory.entries {
+        storer.store_u32(entry.size);
+        storer.store_u32(entry.position.x);
+        storer.store_u32(entry.position.y);
+        storer.store_u32(entry.position.z);
+        if has_checksums {
+            storer.store_u64(entry.checksum.unwrap_or_default());
+        }
+    }
+    storer.v
+}
+
+// Entry offsets are meaningless on the client and left at zero
+pub fn directory_from_bytes(bytes: &[u8]) -> io::Result<BrickDirectory> {
+    let truncated = || invalid_data("truncated brick directory");
+    let mut reader = bytes;
+    let header = SdfHeader::read(&mut reader)?;
+    let mut loader = Loader::new();
+    if reader.len() < 12 {
+        return Err(truncated());
+    }
+    let brick_size = loader.load_u32(reader);
+    let brick_count = loader.load_u32(reader) as usize;
+    let has_checksums = loader.load_u32(reader) != 0;
+    let entry_bytes = if has_checksums { 24 } else { 16 };
+    let expected_bytes = brick_count
+        .checked_mul(entry_bytes)
+        .and_then(|bytes| bytes.checked_add(12));
+    if expected_bytes != Some(reader.len()) {
+        return Err(truncated());
+    }
+    let entries = (0..brick_count)
+        .map(|_| {
+            let entry = BrickEntry {
+                offset: 0,
+                size: loader.load_u32(reader),
+                position: UVec3::new(
+                    loader.load_u32(reader),
+                    loader.load_u32(reader),
+                    loader.load_u32(reader),
+                ),
+                checksum: if has_checksums {
+                    Some(loader.load_u64(reader))
+                } else {
+                    None
+                },
+            };
+            checked_data_bytes(entry.size, brick_size).map(|_| entry)
+        })
+        .collect::<io::Result<Vec<BrickEntry>>>()?;
+    Ok(BrickDirectory::new(header, brick_size, entries))
+}
+
+// Reads one request head, None when the peer closed the connection between requests.
+// Returns the method, the target and whether the client asked to close afterwards.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<(String, String, bool)>> {
+    // The whole head is read through take, a line without a line feed was cut off by the
+    // limit or by the peer closing the connection
+    let mut head = reader.take(MAX_HEADER_BYTES as u64);
+    let mut line = String::new();
+    if head.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+    let mut close = parts.next() == Some("HTTP/1.0");
+
+    loop {
+        if !line.ends_with('\n') {
+            return Err(invalid_data(if head.limit() == 0 {
+                "request header too large"
+            } else {
+                "connection closed inside request"
+            }));
+        }
+        line.clear();
+        head.read_line(&mut line)?;
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("connection") {
+                close = value.trim().eq_ignore_ascii_case("close");
+            }
+        }
+    }
+    Ok(Some((method, target, close)))
+}
+
+fn write_response<W: Write>(writer: &mut W, status: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+fn respond(
+    file: &mut BrickFile,
+    method: &str,
+    target: &str,
+) -> io::Result<(&'static str, Vec<u8>)> {
+    if method != "GET" {
+        return Ok(("405 Method Not Allowed", Vec::new()));
+    }
+    if target == "/directory" {
+        return Ok(("200 OK", directory_to_bytes(file.directory())));
+    }
+    if target == "/octree" {
+        return Ok(("200 OK", file.read_octree()?));
+    }
+    let index = target
+        .strip_prefix("/brick/")
+        .and_then(|index| index.parse::<usize>().ok());
+    match index {
+        Some(index) if index < file.directory().entries.len() => {
+            let brick = file.read_brick(index)?;
+            let mut storer = StorerVec::new();
+            storer.store_array_u16(&brick.data);
+            Ok(("200 OK", storer.v))
+        }
+        _ => Ok(("404 Not Found", Vec::new())),
+    }
+}
+
+// Every connection reads through its own file handle
+fn handle_connection(path: &Path, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut file = BrickFile::open(path)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    while let Some((method, target, close)) = read_request(&mut reader)? {
+        let (status, body) = respond(&mut file, &method, &target)?;
+        write_response(&mut writer, status, &body)?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Releases a connection slot when its thread ends
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Serves the file until the listener fails, connection errors only end that connection
+pub fn serve(listener: TcpListener, path: &Path) -> io::Result<()> {
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            let _ = write_response(&mut stream, "503 Service Unavailable", &[]);
+            continue;
+        }
+        let slot = ConnectionSlot(active.clone());
+        let path = path.to_path_buf();
+        thread::spawn(move || {
+            let _slot = slot;
+            let _ = handle_connection(&path, stream);
+        });
+    }
+    Ok(())
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(address: &str) -> io::Result<Connection> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    // The inner error is the status line of a response other than 200 OK. Bodies longer than
+    // max_bytes are rejected before anything is allocated for them, and the body buffer only
+    // grows with the bytes that actually arrive, not with the Content-Length.
+    fn get(
+        &mut self,
+        host: &str,
+        target: &str,
+        max_bytes: usize,
+    ) -> io::Result<Result<Vec<u8>, String>> {
+        write!(
+            self.writer,
+            "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+            target, host
+        )?;
+        self.writer.flush()?;
+
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        let status = line
+            .trim_end()
+            .split_once(' ')
+            .map(|(_, status)| status.to_string())
+            .unwrap_or_default();
+        let mut content_length = None;
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("connection closed inside response"));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<usize>().ok();
+                }
+            }
+        }
+        let content_length =
+            content_length.ok_or_else(|| invalid_data("response without Content-Length"))?;
+        if content_length > max_bytes {
+            return Err(invalid_data("response larger than expected"));
+        }
+        let mut body = Vec::new();
+        (&mut self.reader)
+            .take(content_length as u64)
+            .read_to_end(&mut body)?;
+        if body.len() != content_length {
+            return Err(invalid_data("connection closed inside response"));
+        }
+        if !status.starts_with("200") {
+            return Ok(Err(status));
+        }
+        Ok(Ok(body))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemoteBrickStats {
+    pub fetched: u64,
+    pub fetched_bytes: u64,
+    pub disk_hits: u64,
+}
+
+// Bricks fetched from a content server started with `svosdf serve`
+pub struct RemoteBricks {
+    address: String,
+    connection: Option<Connection>, // Reopened after a failed request
+    directory: BrickDirectory,
+    cache_dir: Option<PathBuf>,
+    pub stats: RemoteBrickStats,
+}
+
+impl RemoteBricks {
+    // Cached bricks go to a subdirectory named after a hash of the brick directory, a file
+    // rebuilt on the server doesn't pick up stale bricks of the previous version
+    pub fn connect(address: &str, cache_dir: Option<&Path>) -> io::Result<RemoteBricks> {
+        let mut connection = Connection::open(address)?;
+        let bytes = connection
+            .get(address, "/directory", MAX_DIRECTORY_BYTES)?
+            .map_err(|status| status_error("/directory", &status))?;
+        let directory = directory_from_bytes(&bytes)?;
+        let cache_dir = match cache_dir {
+            Some(dir) => {
+                let dir = dir.join(format!("{:016x}", fnv1a(&bytes)));
+                fs::create_dir_all(&dir)?;
+                Some(dir)
+            }
+            None => None,
+        };
+        Ok(RemoteBricks {
+            address: address.to_string(),
+            connection: Some(connection),
+            directory,
+            cache_dir,
+            stats: RemoteBrickStats::default(),
+        })
+    }
+
+    fn cached_path(&self, index: usize) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.brick", index)))
+    }
+
+    fn request(&mut self, target: &str, max_bytes: usize) -> io::Result<Result<Vec<u8>, String>> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => Connection::open(&self.address)?,
+        };
+        let response = connection.get(&self.address, target, max_bytes)?;
+        self.connection = Some(connection);
+        Ok(response)
+    }
+
+    fn get(&mut self, target: &str, max_bytes: usize) -> io::Result<Vec<u8>> {
+        let response = match self.request(target, max_bytes) {
+            Ok(response) => response,
+            // One retry on a fresh connection, the server may have dropped an idle one
+            Err(_) => self.request(target, max_bytes)?,
+        };
+        response.map_err(|status| status_error(target, &status))
+    }
+
+    fn fetch(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let max_bytes = self.directory.entries[index].data_bytes();
+        self.get(&format!("/brick/{}", index), max_bytes)
+    }
+
+    // The whole volume for the viewer, every brick is read through the disk cache
+    pub fn load_svo_sdf(&mut self) -> io::Result<SvoSdf> {
+        let bytes = self.get("/octree", MAX_OCTREE_BYTES)?;
+        if octree_bytes(&bytes)? != bytes.len() {
+            return Err(invalid_data("octree response has trailing bytes"));
+        }
+        let header = self.directory.header;
+        let root = SvoSdf::octree_from_bytes(&header, &bytes);
+        if !brick_indices_valid(&root, self.directory.entries.len()) {
+            return Err(invalid_data(
+                "octree refers to bricks missing from the directory",
+            ));
+        }
+        let bricks = (0..self.directory.entries.len())
+            .map(|index| self.read_brick(index))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut svo_sdf = SvoSdf {
+            header,
+            root,
+            bricks,
+            brick_size: self.directory.brick_size,
+            brick_ranges: Vec::new(),
+            thumbnail: None,
+        };
+        svo_sdf.compute_brick_ranges();
+        Ok(svo_sdf)
+    }
+}
+
+fn brick_indices_valid(node: &OctreeNode, brick_count: usize) -> bool {
+    node.brick_index
+        .is_none_or(|index| (index as usize) < brick_count)
+        && node
+            .children
+            .iter()
+            .flatten()
+            .all(|child| brick_indices_valid(child, brick_count))
+}
+
+impl BrickSource for RemoteBricks {
+    fn directory(&self) -> &BrickDirectory {
+        &self.directory
+    }
+
+    fn read_brick(&mut self, index: usize) -> io::Result<Brick> {
+        let entry = self.directory.entries[index];
+        let cached_path = self.cached_path(index);
+        let cached = cached_path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .filter(|bytes| bytes.len() == entry.data_bytes())
+            .filter(|bytes| entry.verify(index, bytes).is_ok()); // Fetched again when corrupt
+        let bytes = match cached {
+            Some(bytes) => {
+                self.stats.disk_hits += 1;
+                bytes
+            }
+            None => {
+                let bytes = self.fetch(index)?;
+                if bytes.len() != entry.data_bytes() {
+                    return Err(invalid_data("brick size doesn't match the directory"));
+                }
+                entry.verify(index, &bytes)?;
+                self.stats.fetched += 1;
+                self.stats.fetched_bytes += bytes.len() as u64;
+                // Written under a temporary name, an interrupted write never looks complete
+                if let Some(path) = cached_path {
+                    let partial = path.with_extension("partial");
+                    fs::write(&partial, &bytes)?;
+                    fs::rename(&partial, &path)?;
+                }
+                bytes
+            }
+        };
+        let data = Loader::new().load_array_u16(&bytes, bytes.len() / 2);
+        Ok(Brick {
+            data,
+            size: entry.size,
+            position: entry.position,
+        })
+    }
+}
+
+fn status_error(target: &str, status: &str) -> io::Error {
+    io::Error::other(format!("GET {}: {}", target, status))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -0,0 +1,177 @@
+// Cooperative cancellation of long-running builds, loads and saves. Work checks the token at
+// safe points and returns Cancelled with the fraction it finished, callers clean up partial
+// output. cancel_on_interrupt connects a token to Ctrl+C for as long as the guard lives, a
+// second Ctrl+C exits right away for work that doesn't reach a safe point.
+
+use std::fmt;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cancelled {
+    pub progress: f32, // Finished fraction of the cancelled work, 0..1
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cancelled at {:.1}%", self.progress * 100.0)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+// io::Result paths report cancellation as an Interrupted error carrying the Cancelled
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> io::Error {
+        io::Error::new(io::ErrorKind::Interrupted, cancelled)
+    }
+}
+
+impl Cancelled {
+    pub fn from_io(err: &io::Error) -> Option<Cancelled> {
+        err.get_ref()?.downcast_ref::<Cancelled>().copied()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    // Safe point of work that has finished progress (0..1)
+    pub fn check(&self, progress: f32) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled {
+                progress: progress.clamp(0.0, 1.0),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    // Ctrl+C cancels the token until the guard is dropped, one token at a time
+    pub fn cancel_on_interrupt(&self) -> InterruptGuard {
+        let flag = self.flag.clone();
+        INTERRUPT_FLAG.store(Arc::as_ptr(&flag) as *mut AtomicBool, Ordering::SeqCst);
+        unsafe { platform::install() };
+        InterruptGuard { _flag: flag }
+    }
+}
+
+// Flag of the token connected to Ctrl+C, the guard keeps it alive
+static INTERRUPT_FLAG: AtomicPtr<AtomicBool> = AtomicPtr::new(ptr::null_mut());
+
+// Only atomics and process exit, runs in a signal handler on unix
+fn interrupt() {
+    let flag = INTERRUPT_FLAG.load(Ordering::SeqCst);
+    if flag.is_null() {
+        return;
+    }
+    if unsafe { (*flag).swap(true, Ordering::SeqCst) } {
+        platform::exit_now();
+    }
+}
+
+pub struct InterruptGuard {
+    _flag: Arc<AtomicBool>,
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        unsafe { platform::uninstall() };
+        INTERRUPT_FLAG.store(ptr::null_mut(), Ordering::SeqCst);
+    }
+}
+
+// Exit status of a process ended by SIGINT
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+#[cfg(unix)]
+mod platform {
+    use std::os::raw::c_int;
+
+    const SIGINT: c_int = 2;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    extern "C" fn handler(_: c_int) {
+        super::interrupt();
+    }
+
+    pub unsafe fn install() {
+        let handler: extern "C" fn(c_int) = handler;
+        signal(SIGINT, handler as usize);
+    }
+
+    pub unsafe fn uninstall() {
+        signal(SIGINT, SIG_DFL);
+    }
+
+    pub fn exit_now() -> ! {
+        unsafe { _exit(super::INTERRUPTED_EXIT_CODE) }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+
+    // Runs on its own thread, other events keep their default handling
+    extern "system" fn handler(event: u32) -> i32 {
+        if event == CTRL_C_EVENT || event == CTRL_BREAK_EVENT {
+            super::interrupt();
+            1
+        } else {
+            0
+        }
+    }
+
+    pub unsafe fn install() {
+        SetConsoleCtrlHandler(Some(handler), 1);
+    }
+
+    pub unsafe fn uninstall() {
+        SetConsoleCtrlHandler(Some(handler), 0);
+    }
+
+    pub fn exit_now() -> ! {
+        std::process::exit(super::INTERRUPTED_EXIT_CODE)
+    }
+}
+
+// Ctrl+C keeps its default behavior on other platforms
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    pub unsafe fn install() {}
+
+    pub unsafe fn uninstall() {}
+
+    pub fn exit_now() -> ! {
+        std::process::exit(super::INTERRUPTED_EXIT_CODE)
+    }
+}
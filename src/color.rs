@@ -0,0 +1,213 @@
+// Colors in linear and sRGB encoding. Shaders light and blend in linear space, so uniforms
+// take a ColorRgba. ColorSrgb is what color pickers, hex codes and 8-bit images hold, convert
+// it with to_linear before it reaches a uniform. Linear values may exceed 1 for HDR targets,
+// to_srgb clamps them to the displayable range.
+
+use crate::minivector::*;
+use crate::packing::*;
+
+// Linear RGB with alpha, the layout of a vec4 uniform
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ColorRgba {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+// sRGB encoded RGB with a linear alpha, components in 0..1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorSrgb {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+// IEC 61966-2-1 transfer functions, exact instead of the 2.2 gamma approximation
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl ColorRgba {
+    pub const BLACK: ColorRgba = ColorRgba::new(0.0, 0.0, 0.0, 1.0);
+    pub const WHITE: ColorRgba = ColorRgba::new(1.0, 1.0, 1.0, 1.0);
+    pub const TRANSPARENT: ColorRgba = ColorRgba::new(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> ColorRgba {
+        ColorRgba { r, g, b, a }
+    }
+
+    pub const fn rgb(r: f32, g: f32, b: f32) -> ColorRgba {
+        ColorRgba::new(r, g, b, 1.0)
+    }
+
+    // Hue in degrees (wraps), saturation and value in 0..1. HSV is defined on the encoded
+    // values, so the result goes through ColorSrgb to match what color pickers show.
+    pub fn from_hsv(hue_degrees: f32, saturation: f32, value: f32, alpha: f32) -> ColorRgba {
+        ColorSrgb::from_hsv(hue_degrees, saturation, value, alpha).to_linear()
+    }
+
+    pub fn to_srgb(self) -> ColorSrgb {
+        ColorSrgb {
+            r: linear_to_srgb(self.r),
+            g: linear_to_srgb(self.g),
+            b: linear_to_srgb(self.b),
+            a: self.a.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn with_alpha(self, a: f32) -> ColorRgba {
+        ColorRgba { a, ..self }
+    }
+
+    // Scales the color, not the alpha, e.g. by a light intensity
+    pub fn scale_rgb(self, s: f32) -> ColorRgba {
+        ColorRgba::new(self.r * s, self.g * s, self.b * s, self.a)
+    }
+
+    // Rec. 709 luminance of the linear color
+    pub fn luminance(self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn to_vec4(self) -> Vec4 {
+        Vec4 {
+            x: self.r,
+            y: self.g,
+            z: self.b,
+            w: self.a,
+        }
+    }
+
+    pub fn to_vec3(self) -> Vec3 {
+        Vec3 {
+            x: self.r,
+            y: self.g,
+            z: self.b,
+        }
+    }
+
+    // For vk::ClearColorValue::float32
+    pub fn to_array(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+}
+
+impl From<Vec4> for ColorRgba {
+    fn from(v: Vec4) -> ColorRgba {
+        ColorRgba::new(v.x, v.y, v.z, v.w)
+    }
+}
+
+impl From<ColorRgba> for Vec4 {
+    fn from(c: ColorRgba) -> Vec4 {
+        c.to_vec4()
+    }
+}
+
+impl From<ColorSrgb> for ColorRgba {
+    fn from(c: ColorSrgb) -> ColorRgba {
+        c.to_linear()
+    }
+}
+
+impl ColorSrgb {
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> ColorSrgb {
+        ColorSrgb { r, g, b, a }
+    }
+
+    pub fn from_u8(r: u8, g: u8, b: u8, a: u8) -> ColorSrgb {
+        ColorSrgb::new(
+            unpack_unorm8(r),
+            unpack_unorm8(g),
+            unpack_unorm8(b),
+            unpack_unorm8(a),
+        )
+    }
+
+    // 0xRRGGBB, opaque
+    pub fn from_hex(rgb: u32) -> ColorSrgb {
+        let [_, r, g, b] = rgb.to_be_bytes();
+        ColorSrgb::from_u8(r, g, b, 255)
+    }
+
+    pub fn from_hsv(hue_degrees: f32, saturation: f32, value: f32, alpha: f32) -> ColorSrgb {
+        let saturation = saturation.clamp(0.0, 1.0);
+        let value = value.clamp(0.0, 1.0);
+        let h = hue_degrees.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+        ColorSrgb::new(r + m, g + m, b + m, alpha)
+    }
+
+    // (hue in degrees 0..360, saturation, value), hue 0 for grays
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let chroma = max - min;
+        let hue = if chroma == 0.0 {
+            0.0
+        } else if max == self.r {
+            60.0 * ((self.g - self.b) / chroma).rem_euclid(6.0)
+        } else if max == self.g {
+            60.0 * ((self.b - self.r) / chroma + 2.0)
+        } else {
+            60.0 * ((self.r - self.g) / chroma + 4.0)
+        };
+        let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+        (hue, saturation, max)
+    }
+
+    pub fn to_linear(self) -> ColorRgba {
+        ColorRgba {
+            r: srgb_to_linear(self.r),
+            g: srgb_to_linear(self.g),
+            b: srgb_to_linear(self.b),
+            a: self.a,
+        }
+    }
+
+    // Rounded to the nearest 8-bit value
+    pub fn to_u8(self) -> [u8; 4] {
+        [
+            pack_unorm8(self.r),
+            pack_unorm8(self.g),
+            pack_unorm8(self.b),
+            pack_unorm8(self.a),
+        ]
+    }
+}
+
+impl From<ColorRgba> for ColorSrgb {
+    fn from(c: ColorRgba) -> ColorSrgb {
+        c.to_srgb()
+    }
+}
+
+// Plain f32 fields without padding, can be copied to GPU buffers as is
+unsafe impl bytemuck::Zeroable for ColorRgba {}
+unsafe impl bytemuck::Pod for ColorRgba {}
@@ -0,0 +1,190 @@
+// Procedural surface detail for close-ups. A small tiling 3D noise texture is generated on the
+// CPU and added to the brick distance in svo_main.frag, scaled by per-material amplitude and
+// faded out with camera distance, so surfaces get high-frequency detail without extra storage.
+
+use crate::minivector::*;
+use crate::packing::*;
+
+pub const DETAIL_NOISE_SIZE: u32 = 32;
+pub const DETAIL_NOISE_OCTAVES: u32 = 3;
+pub const MAX_DETAIL_MATERIALS: usize = 8;
+
+// Lattice cells of the first octave across the texture, doubled per octave. Must divide
+// DETAIL_NOISE_SIZE so every octave tiles.
+const BASE_PERIOD: u32 = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DetailMaterial {
+    pub amplitude: f32,  // Distance offset in brick distance units ([-1, 1] range)
+    pub frequency: f32,  // Noise texture repeats per world unit
+    pub fade_start: f32, // Camera distance where the detail starts fading out
+    pub fade_end: f32,   // No detail beyond this distance
+}
+
+impl Default for DetailMaterial {
+    fn default() -> DetailMaterial {
+        DetailMaterial {
+            amplitude: 0.004,
+            frequency: 0.05,
+            fade_start: 40.0,
+            fade_end: 120.0,
+        }
+    }
+}
+
+// Presets cycled through by scene instances, material 0 is the default
+pub fn detail_material_presets() -> Vec<DetailMaterial> {
+    let default = DetailMaterial::default();
+    vec![
+        default,
+        DetailMaterial {
+            amplitude: default.amplitude * 2.0,
+            frequency: default.frequency * 0.5,
+            ..default
+        },
+        DetailMaterial {
+            amplitude: default.amplitude * 0.5,
+            frequency: default.frequency * 3.0,
+            ..default
+        },
+        DetailMaterial {
+            amplitude: 0.0,
+            ..default
+        },
+    ]
+}
+
+// Matches the DetailUBO uniform block of svo_main.frag (std140)
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct DetailUniforms {
+    pub materials: [Vec4; MAX_DETAIL_MATERIALS], // amplitude, frequency, fade start, fade end
+    pub enabled: u32,
+    pub _padding: [u32; 3],
+}
+
+unsafe impl bytemuck::Zeroable for DetailUniforms {}
+unsafe impl bytemuck::Pod for DetailUniforms {}
+
+// --detail enables the detail term, --detail-amplitude X and --detail-frequency X scale all
+// materials
+#[derive(Clone, Debug)]
+pub struct DetailSettings {
+    pub enabled: bool,
+    pub materials: Vec<DetailMaterial>,
+}
+
+impl DetailSettings {
+    pub fn from_args(args: &[String]) -> DetailSettings {
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+                .and_then(|value| value.parse::<f32>().ok())
+        };
+        let amplitude_scale = value("--detail-amplitude").unwrap_or(1.0);
+        let frequency_scale = value("--detail-frequency").unwrap_or(1.0);
+
+        let materials = detail_material_presets()
+            .into_iter()
+            .map(|material| DetailMaterial {
+                amplitude: material.amplitude * amplitude_scale,
+                frequency: material.frequency * frequency_scale,
+                ..material
+            })
+            .collect();
+
+        DetailSettings {
+            enabled: args.iter().any(|arg| arg == "--detail"),
+            materials,
+        }
+    }
+
+    pub fn uniforms(&self) -> DetailUniforms {
+        let zero = Vec4 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 0.0,
+        };
+        let mut materials = [zero; MAX_DETAIL_MATERIALS];
+        for (gpu, material) in materials.iter_mut().zip(self.materials.iter()) {
+            *gpu = Vec4 {
+                x: material.amplitude,
+                y: material.frequency,
+                z: material.fade_start,
+                w: material.fade_end,
+            };
+        }
+        DetailUniforms {
+            materials,
+            enabled: self.enabled as u32,
+            _padding: [0; 3],
+        }
+    }
+}
+
+fn hash(x: u32, y: u32, z: u32, seed: u32) -> u32 {
+    let mut h = seed ^ x.wrapping_mul(0x8da6_b343);
+    h ^= y.wrapping_mul(0xd816_3841);
+    h ^= z.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+fn lattice(x: u32, y: u32, z: u32, seed: u32) -> f32 {
+    hash(x, y, z, seed) as f32 / u32::MAX as f32
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Value noise with the lattice wrapped at period, sampled at p in [0, period)
+fn periodic_value_noise(p: (f32, f32, f32), period: u32, seed: u32) -> f32 {
+    let cell = (p.0.floor(), p.1.floor(), p.2.floor());
+    let t = (
+        smooth(p.0 - cell.0),
+        smooth(p.1 - cell.1),
+        smooth(p.2 - cell.2),
+    );
+    let x0 = cell.0 as u32 % period;
+    let y0 = cell.1 as u32 % period;
+    let z0 = cell.2 as u32 % period;
+    let (x1, y1, z1) = ((x0 + 1) % period, (y0 + 1) % period, (z0 + 1) % period);
+
+    let x00 = lerp(lattice(x0, y0, z0, seed), lattice(x1, y0, z0, seed), t.0);
+    let x10 = lerp(lattice(x0, y1, z0, seed), lattice(x1, y1, z0, seed), t.0);
+    let x01 = lerp(lattice(x0, y0, z1, seed), lattice(x1, y0, z1, seed), t.0);
+    let x11 = lerp(lattice(x0, y1, z1, seed), lattice(x1, y1, z1, seed), t.0);
+    lerp(lerp(x00, x10, t.1), lerp(x01, x11, t.1), t.2)
+}
+
+// size^3 R8 texels (x fastest), tiles seamlessly with a REPEAT sampler. 0.5 = no offset.
+pub fn tiling_noise_3d(size: u32, octaves: u32, seed: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity((size * size * size) as usize);
+    let total_weight: f32 = (0..octaves).map(|octave| 0.5f32.powi(octave as i32)).sum();
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let mut value = 0.0;
+                for octave in 0..octaves {
+                    let period = BASE_PERIOD << octave;
+                    let scale = period as f32 / size as f32;
+                    let p = (x as f32 * scale, y as f32 * scale, z as f32 * scale);
+                    let weight = 0.5f32.powi(octave as i32);
+                    value += periodic_value_noise(p, period, seed.wrapping_add(octave)) * weight;
+                }
+                data.push(pack_unorm8(value / total_weight));
+            }
+        }
+    }
+    data
+}
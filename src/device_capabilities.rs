@@ -0,0 +1,310 @@
+// Vulkan API version and feature negotiation.
+// Requests the highest API version supported by both loader and device (capped at 1.3).
+// Queries the Vulkan 1.1/1.2/1.3 features, but only enables the supported ones the renderers
+// check in DeviceCapabilities. Features like robustImageAccess or capture/replay cost
+// performance even when nothing uses them.
+
+use ash::{vk, Entry, Instance};
+use std::ffi::CStr;
+use std::os::raw::c_void;
+use std::ptr;
+
+pub const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DeviceCapabilities {
+    pub api_version: u32,
+    pub storage_buffer_16bit: bool,
+    pub shader_draw_parameters: bool,
+    pub descriptor_indexing: bool,
+    pub runtime_descriptor_array: bool,
+    pub descriptor_binding_partially_bound: bool,
+    pub bindless: bool, // Everything BindlessTable needs, see below
+    pub buffer_device_address: bool,
+    pub timeline_semaphore: bool,
+    pub draw_indirect_count: bool,
+    pub shader_float16: bool,
+    pub dynamic_rendering: bool,
+    pub synchronization2: bool,
+    pub maintenance4: bool,
+    pub mesh_shader: bool, // VK_EXT_mesh_shader
+    pub task_shader: bool,
+    pub sampler_anisotropy: bool,
+    pub max_sampler_anisotropy: f32,
+    pub texture_compression_bc: bool,
+    pub multi_draw_indirect: bool,
+    pub pipeline_statistics_query: bool,
+    pub occlusion_query_precise: bool,
+    pub timestamp_period: f32, // Nanoseconds per timestamp tick, 0 without graphics timestamps
+    pub sparse_binding: bool,
+    pub sparse_residency_image3d: bool,
+}
+
+impl DeviceCapabilities {
+    pub fn supports_api(&self, major: u32, minor: u32) -> bool {
+        self.api_version >= vk::make_api_version(0, major, minor, 0)
+    }
+
+    pub fn api_version_string(&self) -> String {
+        format!(
+            "{}.{}.{}",
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version)
+        )
+    }
+}
+
+// Loader version, 1.0 loaders don't have vkEnumerateInstanceVersion
+pub fn negotiate_instance_api_version(entry: &Entry) -> u32 {
+    let loader_version = unsafe { entry.try_enumerate_instance_version() }
+        .ok()
+        .flatten()
+        .unwrap_or(vk::API_VERSION_1_0);
+    loader_version.min(MAX_API_VERSION)
+}
+
+// Vulkan 1.1+ feature structs, filled by the query or set to the features to enable
+#[derive(Default)]
+pub struct FeatureStructs {
+    pub vulkan11: vk::PhysicalDeviceVulkan11Features<'static>,
+    pub vulkan12: vk::PhysicalDeviceVulkan12Features<'static>,
+    pub vulkan13: vk::PhysicalDeviceVulkan13Features<'static>,
+    pub mesh_shader: vk::PhysicalDeviceMeshShaderFeaturesEXT<'static>,
+    pub synchronization2: vk::PhysicalDeviceSynchronization2Features<'static>, // Pre 1.3 devices
+}
+
+// Owns the feature structs chained into DeviceCreateInfo::p_next
+pub struct DeviceFeatureChain {
+    pub api_version: u32,
+    pub supported: FeatureStructs,
+    pub enabled: FeatureStructs, // What link() chains into device creation
+    pub mesh_shader_extension: bool,
+    pub synchronization2_extension: bool,
+    pub sampler_anisotropy: bool, // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+    pub max_sampler_anisotropy: f32,
+    pub texture_compression_bc: bool, // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+    pub multi_draw_indirect: bool,    // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+    pub pipeline_statistics_query: bool, // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+    pub occlusion_query_precise: bool, // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+    pub timestamp_period: f32,
+    pub sparse_binding: bool, // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+    pub sparse_residency_image3d: bool, // Vulkan 1.0 feature, enabled in PhysicalDeviceFeatures
+}
+
+impl DeviceFeatureChain {
+    pub fn query(
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        instance_api_version: u32,
+    ) -> DeviceFeatureChain {
+        let properties = unsafe { instance.get_physical_device_properties(pdevice) };
+        let api_version = properties.api_version.min(instance_api_version);
+        let base_features = unsafe { instance.get_physical_device_features(pdevice) };
+
+        // Mesh shaders need SPIR-V 1.4, which is core in Vulkan 1.2
+        let extensions =
+            unsafe { instance.enumerate_device_extension_properties(pdevice) }.unwrap_or_default();
+        let mesh_shader_extension = api_version >= vk::API_VERSION_1_2
+            && extensions.iter().any(|extension| {
+                extension.extension_name_as_c_str() == Ok(ash::ext::mesh_shader::NAME)
+            });
+
+        // Core in Vulkan 1.3, the extension needs the 1.2 feature query path
+        let synchronization2_extension = api_version == vk::API_VERSION_1_2
+            && extensions.iter().any(|extension| {
+                extension.extension_name_as_c_str() == Ok(ash::khr::synchronization2::NAME)
+            });
+
+        let mut chain = DeviceFeatureChain {
+            api_version,
+            supported: Default::default(),
+            enabled: Default::default(),
+            mesh_shader_extension,
+            synchronization2_extension,
+            sampler_anisotropy: base_features.sampler_anisotropy != 0,
+            max_sampler_anisotropy: properties.limits.max_sampler_anisotropy,
+            texture_compression_bc: base_features.texture_compression_bc != 0,
+            multi_draw_indirect: base_features.multi_draw_indirect != 0,
+            pipeline_statistics_query: base_features.pipeline_statistics_query != 0,
+            occlusion_query_precise: base_features.occlusion_query_precise != 0,
+            timestamp_period: if properties.limits.timestamp_compute_and_graphics != 0 {
+                properties.limits.timestamp_period
+            } else {
+                0.0
+            },
+            sparse_binding: base_features.sparse_binding != 0,
+            sparse_residency_image3d: base_features.sparse_residency_image3_d != 0,
+        };
+
+        // The VkPhysicalDeviceVulkan1xFeatures structs were added in Vulkan 1.2
+        if api_version >= vk::API_VERSION_1_2 {
+            let mut features2 = vk::PhysicalDeviceFeatures2 {
+                p_next: chain.supported.link(
+                    api_version,
+                    mesh_shader_extension,
+                    synchronization2_extension,
+                ) as *mut c_void,
+                ..Default::default()
+            };
+            unsafe { instance.get_physical_device_features2(pdevice, &mut features2) };
+            chain.supported.unlink();
+        }
+
+        chain.mesh_shader_extension = chain.supported.mesh_shader.mesh_shader != 0;
+        chain.synchronization2_extension = chain.supported.synchronization2.synchronization2 != 0;
+        chain.enabled = chain.select_enabled();
+
+        chain
+    }
+
+    // The supported subset of what the renderers branch on: the bindless descriptor features,
+    // buffer device addresses for the allocator, synchronization2 barriers and the base mesh
+    // shader features (the others depend on multiview and shading rate)
+    fn select_enabled(&self) -> FeatureStructs {
+        let v12 = &self.supported.vulkan12;
+        let v13 = &self.supported.vulkan13;
+        let bindless = v12.runtime_descriptor_array != 0
+            && v12.descriptor_binding_partially_bound != 0
+            && v12.descriptor_binding_storage_buffer_update_after_bind != 0
+            && v12.descriptor_binding_sampled_image_update_after_bind != 0
+            && v12.shader_storage_buffer_array_non_uniform_indexing != 0
+            && v12.shader_sampled_image_array_non_uniform_indexing != 0;
+        let bindless = bindless as vk::Bool32;
+        FeatureStructs {
+            vulkan11: Default::default(),
+            vulkan12: vk::PhysicalDeviceVulkan12Features {
+                runtime_descriptor_array: bindless,
+                descriptor_binding_partially_bound: bindless,
+                descriptor_binding_storage_buffer_update_after_bind: bindless,
+                descriptor_binding_sampled_image_update_after_bind: bindless,
+                shader_storage_buffer_array_non_uniform_indexing: bindless,
+                shader_sampled_image_array_non_uniform_indexing: bindless,
+                buffer_device_address: v12.buffer_device_address,
+                ..Default::default()
+            },
+            vulkan13: vk::PhysicalDeviceVulkan13Features {
+                synchronization2: v13.synchronization2,
+                ..Default::default()
+            },
+            mesh_shader: vk::PhysicalDeviceMeshShaderFeaturesEXT {
+                mesh_shader: self.supported.mesh_shader.mesh_shader,
+                task_shader: self.supported.mesh_shader.task_shader,
+                ..Default::default()
+            },
+            synchronization2: vk::PhysicalDeviceSynchronization2Features {
+                synchronization2: self.supported.synchronization2.synchronization2,
+                ..Default::default()
+            },
+        }
+    }
+
+    // Sparse binds are submitted to the one queue the renderer creates, its family has to
+    // support them
+    pub fn limit_to_queue_family(&mut self, queue_flags: vk::QueueFlags) {
+        if !queue_flags.contains(vk::QueueFlags::SPARSE_BINDING) {
+            self.sparse_binding = false;
+            self.sparse_residency_image3d = false;
+        }
+    }
+
+    // Device extensions required by the enabled features
+    pub fn extension_names(&self) -> Vec<&'static CStr> {
+        let mut names = Vec::new();
+        if self.enabled.mesh_shader.mesh_shader != 0 {
+            names.push(ash::ext::mesh_shader::NAME);
+        }
+        if self.synchronization2_extension {
+            names.push(ash::khr::synchronization2::NAME);
+        }
+        names
+    }
+
+    // Links the enabled structs and returns the head for DeviceCreateInfo::p_next.
+    // The chain must not move while the returned pointer is in use.
+    pub fn link(&mut self) -> *const c_void {
+        self.enabled.link(
+            self.api_version,
+            self.mesh_shader_extension,
+            self.synchronization2_extension,
+        )
+    }
+
+    // The enabled features, supported ones that aren't enabled are reported as missing
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let v11 = &self.enabled.vulkan11;
+        let v12 = &self.enabled.vulkan12;
+        let v13 = &self.enabled.vulkan13;
+        DeviceCapabilities {
+            api_version: self.api_version,
+            storage_buffer_16bit: v11.storage_buffer16_bit_access != 0,
+            shader_draw_parameters: v11.shader_draw_parameters != 0,
+            descriptor_indexing: v12.descriptor_indexing != 0,
+            runtime_descriptor_array: v12.runtime_descriptor_array != 0,
+            descriptor_binding_partially_bound: v12.descriptor_binding_partially_bound != 0,
+            bindless: v12.runtime_descriptor_array != 0
+                && v12.descriptor_binding_partially_bound != 0
+                && v12.descriptor_binding_storage_buffer_update_after_bind != 0
+                && v12.descriptor_binding_sampled_image_update_after_bind != 0
+                && v12.shader_storage_buffer_array_non_uniform_indexing != 0
+                && v12.shader_sampled_image_array_non_uniform_indexing != 0,
+            buffer_device_address: v12.buffer_device_address != 0,
+            timeline_semaphore: v12.timeline_semaphore != 0,
+            draw_indirect_count: v12.draw_indirect_count != 0,
+            shader_float16: v12.shader_float16 != 0,
+            dynamic_rendering: v13.dynamic_rendering != 0,
+            synchronization2: v13.synchronization2 != 0
+                || self.enabled.synchronization2.synchronization2 != 0,
+            maintenance4: v13.maintenance4 != 0,
+            mesh_shader: self.enabled.mesh_shader.mesh_shader != 0,
+            task_shader: self.enabled.mesh_shader.task_shader != 0,
+            sampler_anisotropy: self.sampler_anisotropy,
+            max_sampler_anisotropy: self.max_sampler_anisotropy,
+            texture_compression_bc: self.texture_compression_bc,
+            multi_draw_indirect: self.multi_draw_indirect,
+            pipeline_statistics_query: self.pipeline_statistics_query,
+            occlusion_query_precise: self.occlusion_query_precise,
+            timestamp_period: self.timestamp_period,
+            sparse_binding: self.sparse_binding,
+            sparse_residency_image3d: self.sparse_residency_image3d,
+        }
+    }
+}
+
+impl FeatureStructs {
+    // Links the structs the API version and extensions allow and returns the head for a
+    // p_next chain. The structs must not move while the returned pointer is in use.
+    fn link(
+        &mut self,
+        api_version: u32,
+        mesh_shader_extension: bool,
+        synchronization2_extension: bool,
+    ) -> *const c_void {
+        self.unlink();
+        if api_version < vk::API_VERSION_1_2 {
+            return ptr::null();
+        }
+        self.vulkan11.p_next = &mut self.vulkan12 as *mut _ as *mut c_void;
+        let mut tail: *mut *mut c_void = &mut self.vulkan12.p_next;
+        if api_version >= vk::API_VERSION_1_3 {
+            unsafe { *tail = &mut self.vulkan13 as *mut _ as *mut c_void };
+            tail = &mut self.vulkan13.p_next;
+        }
+        if mesh_shader_extension {
+            unsafe { *tail = &mut self.mesh_shader as *mut _ as *mut c_void };
+            tail = &mut self.mesh_shader.p_next;
+        }
+        if synchronization2_extension {
+            unsafe { *tail = &mut self.synchronization2 as *mut _ as *mut c_void };
+        }
+        &self.vulkan11 as *const _ as *const c_void
+    }
+
+    fn unlink(&mut self) {
+        self.vulkan11.p_next = ptr::null_mut();
+        self.vulkan12.p_next = ptr::null_mut();
+        self.vulkan13.p_next = ptr::null_mut();
+        self.mesh_shader.p_next = ptr::null_mut();
+        self.synchronization2.p_next = ptr::null_mut();
+    }
+}
@@ -0,0 +1,155 @@
+// Explicit multi-GPU through device groups (Vulkan 1.1, VK_KHR_device_group). GPUs linked by
+// the driver are reported as one physical device group. A logical device created over the
+// group replicates device local allocations on every GPU and runs each submit on the GPUs of
+// its device mask. AFR (alternate frame rendering) renders consecutive frames on alternating
+// GPUs: the swapchain image of a frame is acquired, rendered and presented by one GPU.
+// Occlusion culling then reads the depth of the previous frame rendered on the same GPU.
+
+use ash::khr::swapchain::Device as Swapchain;
+use ash::vk;
+use ash::Instance;
+
+use std::cell::Cell;
+
+// Physical devices of the group containing pdevice, None when pdevice isn't part of a group
+// with more than one GPU. Device masks index into this list. Needs a Vulkan 1.1 instance.
+pub fn find_device_group(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+) -> Option<Vec<vk::PhysicalDevice>> {
+    let count = unsafe { instance.enumerate_physical_device_groups_len() }.ok()?;
+    let mut groups = vec![vk::PhysicalDeviceGroupProperties::default(); count];
+    unsafe { instance.enumerate_physical_device_groups(&mut groups) }.ok()?;
+    groups
+        .iter()
+        .map(|group| group.physical_devices[..group.physical_device_count as usize].to_vec())
+        .find(|devices| devices.len() > 1 && devices.contains(&pdevice))
+}
+
+pub struct DeviceGroup {
+    pub physical_devices: Vec<vk::PhysicalDevice>,
+    // How each GPU's rendering reaches the screen, None when it can't be presented
+    pub present_modes: Vec<Option<vk::DeviceGroupPresentModeFlagsKHR>>,
+    frame_device: Cell<Option<u32>>, // None = the frame runs on every GPU
+    frame_count: Cell<u32>,
+}
+
+impl DeviceGroup {
+    pub fn new(physical_devices: Vec<vk::PhysicalDevice>) -> DeviceGroup {
+        DeviceGroup {
+            present_modes: vec![None; physical_devices.len()],
+            physical_devices,
+            frame_device: Cell::new(None),
+            frame_count: Cell::new(0),
+        }
+    }
+
+    // GPU i presents its own image with LOCAL when its presentation engine can read it,
+    // otherwise another GPU's presentation engine reads it with REMOTE
+    pub fn query_present_modes(
+        &mut self,
+        swapchain_loader: &Swapchain,
+        surface: vk::SurfaceKHR,
+    ) -> Result<(), vk::Result> {
+        let mut capabilities = vk::DeviceGroupPresentCapabilitiesKHR::default();
+        let surface_modes = unsafe {
+            swapchain_loader.get_device_group_present_capabilities(&mut capabilities)?;
+            swapchain_loader.get_device_group_surface_present_modes(surface)?
+        };
+        let modes = capabilities.modes & surface_modes;
+        let present_mask = capabilities.present_mask;
+        self.present_modes = (0..self.device_count())
+            .map(|i| {
+                let bit = 1 << i;
+                if modes.contains(vk::DeviceGroupPresentModeFlagsKHR::LOCAL)
+                    && present_mask[i as usize] & bit != 0
+                {
+                    Some(vk::DeviceGroupPresentModeFlagsKHR::LOCAL)
+                } else if modes.contains(vk::DeviceGroupPresentModeFlagsKHR::REMOTE)
+                    && present_mask.iter().any(|mask| mask & bit != 0)
+                {
+                    Some(vk::DeviceGroupPresentModeFlagsKHR::REMOTE)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Ok(())
+    }
+
+    pub fn device_count(&self) -> u32 {
+        self.physical_devices.len() as u32
+    }
+
+    pub fn all_devices_mask(&self) -> u32 {
+        ((1u64 << self.device_count()) - 1) as u32
+    }
+
+    // GPUs whose rendering can be presented, AFR alternates between these
+    pub fn afr_devices(&self) -> Vec<u32> {
+        (0..self.device_count())
+            .filter(|&i| self.present_modes[i as usize].is_some())
+            .collect()
+    }
+
+    // Modes the swapchain is created with
+    pub fn swapchain_modes(&self) -> vk::DeviceGroupPresentModeFlagsKHR {
+        self.present_modes.iter().flatten().fold(
+            vk::DeviceGroupPresentModeFlagsKHR::empty(),
+            |modes, &mode| modes | mode,
+        )
+    }
+
+    // Selects the GPU of the next frame, used by acquire, submits and present until the
+    // next begin_afr_frame or begin_replicated_frame call
+    pub fn begin_afr_frame(&self) -> u32 {
+        let devices = self.afr_devices();
+        let frame = self.frame_count.get();
+        self.frame_count.set(frame.wrapping_add(1));
+        let device = devices
+            .get(frame as usize % devices.len().max(1))
+            .copied()
+            .unwrap_or(0);
+        self.frame_device.set(Some(device));
+        device
+    }
+
+    // Runs the next frame on every GPU. Needed when the frame copies data that all GPUs
+    // read later, e.g. uploads, since each GPU only writes its own memory instance.
+    pub fn begin_replicated_frame(&self) {
+        self.frame_device.set(None);
+    }
+
+    pub fn frame_device(&self) -> Option<u32> {
+        self.frame_device.get()
+    }
+
+    // GPUs running the commands of the current frame
+    pub fn device_mask(&self) -> u32 {
+        match self.frame_device.get() {
+            Some(device) => 1 << device,
+            None => self.all_devices_mask(),
+        }
+    }
+
+    // Device index used for semaphore waits and signals of the current frame
+    pub fn semaphore_device_index(&self) -> u32 {
+        self.frame_device
+            .get()
+            .unwrap_or_else(|| self.present_device())
+    }
+
+    // GPU whose swapchain image is presented. Replicated frames present the first GPU
+    // that can present.
+    pub fn present_device(&self) -> u32 {
+        match self.frame_device.get() {
+            Some(device) => device,
+            None => self.afr_devices().first().copied().unwrap_or(0),
+        }
+    }
+
+    pub fn present_mode(&self) -> vk::DeviceGroupPresentModeFlagsKHR {
+        self.present_modes[self.present_device() as usize]
+            .unwrap_or(vk::DeviceGroupPresentModeFlagsKHR::LOCAL)
+    }
+}
@@ -0,0 +1,84 @@
+// Window mode and swapchain image count selected on the command line.
+
+use ash::vk;
+use winit::monitor::MonitorHandle;
+use winit::window::Fullscreen;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullscreenMode {
+    #[default]
+    Windowed,
+    Borderless,
+    // Exclusive video mode. The swapchain allows driver controlled exclusive presentation
+    // when VK_EXT_full_screen_exclusive is available
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DisplaySettings {
+    pub fullscreen: FullscreenMode,
+    pub swapchain_images: Option<u32>, // None = surface minimum + memory profile extra images
+    pub device_group: bool,            // Alternate frame rendering on linked GPUs
+}
+
+impl DisplaySettings {
+    // --fullscreen [borderless|exclusive] (borderless when no mode is given)
+    // --swapchain-images N
+    // --device-group
+    pub fn from_args(args: &[String]) -> DisplaySettings {
+        let fullscreen = match args.iter().position(|arg| arg == "--fullscreen") {
+            Some(i) => match args.get(i + 1).map(|mode| mode.as_str()) {
+                Some("exclusive") => FullscreenMode::Exclusive,
+                _ => FullscreenMode::Borderless,
+            },
+            None => FullscreenMode::Windowed,
+        };
+        let swapchain_images = args
+            .iter()
+            .position(|arg| arg == "--swapchain-images")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|count| count.parse::<u32>().ok());
+        DisplaySettings {
+            fullscreen,
+            swapchain_images,
+            device_group: args.iter().any(|arg| arg == "--device-group"),
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen != FullscreenMode::Windowed
+    }
+
+    // Exclusive uses the monitor's largest video mode (highest refresh rate on ties)
+    pub fn window_fullscreen(&self, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+        match self.fullscreen {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            FullscreenMode::Exclusive => {
+                let video_mode = monitor.and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                });
+                Some(video_mode.map_or(Fullscreen::Borderless(None), Fullscreen::Exclusive))
+            }
+        }
+    }
+
+    // Clamped to the surface limits (max_image_count 0 = no limit)
+    pub fn swapchain_image_count(
+        &self,
+        surface_capabilities: &vk::SurfaceCapabilitiesKHR,
+        extra_images: u32,
+    ) -> u32 {
+        let requested = self
+            .swapchain_images
+            .unwrap_or(surface_capabilities.min_image_count + extra_images);
+        let mut count = requested.max(surface_capabilities.min_image_count);
+        if surface_capabilities.max_image_count > 0 {
+            count = count.min(surface_capabilities.max_image_count);
+        }
+        count
+    }
+}
@@ -0,0 +1,247 @@
+// Layout of the .sdf and .svosdf files, written down for tools outside this crate. All values
+// are little endian.
+//
+// .sdf, zlib compressed as a whole by store_sdf_zlib (store_sdf writes the same bytes raw):
+//   header       version 2: magic "SDF2", dim u32 x3, box_min f32 x3, spacing f32 x3
+//                version 1: dim u32 x3, box_min f32 x3, spacing f32 (same for all axes)
+//   voxels       u16 x dim.x * dim.y * dim.z, x fastest, LEVEL_ZERO is the surface
+//
+// .svosdf, not compressed:
+//   header       .sdf header, version 1 in files written before per-axis spacing
+//   brick_size   u32
+//   brick_count  u32
+//   bricks       brick_count x (size u32, position u32 x3, voxels u16 x size^3)
+//   octree       nodes depth first: is_leaf u8, has_brick u8, brick_index u32 (has_brick),
+//                bounds min u32 x3 max u32 x3, child_mask u8 and the children (not is_leaf)
+//   brick_ranges optional, brick_count x (min u16, max u16)
+//   checksums    optional, brick_count x xxh64 u64 of the brick voxel bytes, brick_count u32,
+//                magic "BXXH"
+//   thumbnail    optional, pixels u8 x width * height, width u32, height u32, magic "THMB"
+//
+// The info structs describe a file without loading the voxel data and serialize to JSON for
+// `svosdf header --json`, a stable description for tools in other languages to parse against.
+
+use crate::brick_checksum::*;
+use crate::sdf::*;
+use crate::serialization::*;
+use crate::thumbnail::*;
+
+use miniz_oxide::inflate::decompress_to_vec;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io;
+use std::path::Path;
+
+// Byte range of a section
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Section {
+    pub offset: u64,
+    pub bytes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SdfFileInfo {
+    pub version: u32,
+    pub compressed: bool, // Offsets are in the decompressed bytes when set
+    pub header: SdfHeader,
+    pub voxels: Section,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SvoSdfSections {
+    pub header: Section,
+    pub bricks: Section, // Includes brick_size and brick_count
+    pub octree: Section,
+    pub brick_ranges: Option<Section>,
+    pub brick_checksums: Option<Section>,
+    pub thumbnail: Option<Section>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SvoSdfFileInfo {
+    pub version: u32, // Of the header
+    pub header: SdfHeader,
+    pub brick_size: u32,
+    pub brick_count: u32,
+    pub node_count: u32,
+    pub thumbnail_size: Option<(u32, u32)>,
+    pub sections: SvoSdfSections,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub enum FileInfo {
+    Sdf(SdfFileInfo),
+    SvoSdf(SvoSdfFileInfo),
+}
+
+impl FileInfo {
+    // .svosdf by extension, everything else is an .sdf
+    pub fn load(path: &Path) -> io::Result<FileInfo> {
+        let bytes = std::fs::read(path)?;
+        let is_svosdf = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("svosdf"));
+        if is_svosdf {
+            SvoSdfFileInfo::parse(&bytes).map(FileInfo::SvoSdf)
+        } else {
+            SdfFileInfo::parse(&bytes).map(FileInfo::Sdf)
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Bounds checked reads, truncated files are errors instead of panics
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("unexpected end of file"))?;
+        let out = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn parse_header(reader: &mut Reader) -> io::Result<(u32, SdfHeader)> {
+    let magic = reader.bytes.get(..4).map(|magic| magic.try_into().unwrap());
+    let (version, bytes) = if magic.map(u32::from_le_bytes) == Some(SDF_HEADER_MAGIC) {
+        (2, SDF_HEADER_BYTES)
+    } else {
+        (1, SDF_HEADER_V1_BYTES)
+    };
+    let header_bytes = reader.take(bytes)?;
+    Ok((version, SdfHeader::load(&mut Loader::new(), header_bytes)))
+}
+
+impl SdfFileInfo {
+    pub fn parse(bytes: &[u8]) -> io::Result<SdfFileInfo> {
+        // Raw version 2 files start with the magic, anything else is tried as zlib first
+        let raw_v2 = bytes.get(..4) == Some(&SDF_HEADER_MAGIC.to_le_bytes()[..]);
+        let decompressed = if raw_v2 {
+            None
+        } else {
+            decompress_to_vec(bytes).ok()
+        };
+        let compressed = decompressed.is_some();
+        let bytes = decompressed.as_deref().unwrap_or(bytes);
+
+        let mut reader = Reader { bytes, offset: 0 };
+        let (version, header) = parse_header(&mut reader)?;
+        let voxel_bytes = (header.dim.0 as u64 * header.dim.1 as u64)
+            .checked_mul(header.dim.2 as u64 * 2)
+            .filter(|&voxel_bytes| voxel_bytes == (bytes.len() - reader.offset) as u64)
+            .ok_or_else(|| invalid_data("voxel data doesn't match the dimensions"))?;
+        Ok(SdfFileInfo {
+            version,
+            compressed,
+            header,
+            voxels: Section {
+                offset: reader.offset as u64,
+                bytes: voxel_bytes,
+            },
+        })
+    }
+}
+
+impl SvoSdfFileInfo {
+    pub fn parse(bytes: &[u8]) -> io::Result<SvoSdfFileInfo> {
+        let mut reader = Reader { bytes, offset: 0 };
+        let section = |start: usize, end: usize| Section {
+            offset: start as u64,
+            bytes: (end - start) as u64,
+        };
+
+        let (version, header) = parse_header(&mut reader)?;
+        let header_section = section(0, reader.offset);
+
+        let bricks_start = reader.offset;
+        let brick_size = reader.u32()?;
+        let brick_count = reader.u32()?;
+        for _ in 0..brick_count {
+            let size = reader.u32()? as usize;
+            reader.take(3 * 4)?;
+            reader.take(size * size * size * 2)?;
+        }
+        let bricks = section(bricks_start, reader.offset);
+
+        let octree_start = reader.offset;
+        let node_count = skip_node(&mut reader)?;
+        let octree = section(octree_start, reader.offset);
+
+        let thumbnail = Thumbnail::from_tail(bytes);
+        let thumbnail_bytes = thumbnail.as_ref().map_or(0, |t| t.section_bytes());
+        let thumbnail_start = bytes.len() - thumbnail_bytes;
+        let checksums = BrickChecksums::from_tail(&bytes[..thumbnail_start]);
+        let end = thumbnail_start - checksums.as_ref().map_or(0, |c| c.section_bytes());
+        let ranges_bytes = brick_count as usize * 4;
+        let brick_ranges = if end.saturating_sub(reader.offset) >= ranges_bytes {
+            Some(section(reader.offset, reader.offset + ranges_bytes))
+        } else {
+            None
+        };
+
+        Ok(SvoSdfFileInfo {
+            version,
+            header,
+            brick_size,
+            brick_count,
+            node_count,
+            thumbnail_size: thumbnail.as_ref().map(|t| (t.width, t.height)),
+            sections: SvoSdfSections {
+                header: header_section,
+                bricks,
+                octree,
+                brick_ranges,
+                brick_checksums: checksums.map(|_| section(end, thumbnail_start)),
+                thumbnail: thumbnail.map(|_| section(thumbnail_start, bytes.len())),
+            },
+        })
+    }
+}
+
+// Bytes of the serialized octree at the start of bytes, errors when it's truncated
+pub fn octree_bytes(bytes: &[u8]) -> io::Result<usize> {
+    let mut reader = Reader { bytes, offset: 0 };
+    skip_node(&mut reader)?;
+    Ok(reader.offset)
+}
+
+// Returns the number of nodes in the subtree
+fn skip_node(reader: &mut Reader) -> io::Result<u32> {
+    let is_leaf = reader.u8()? != 0;
+    if reader.u8()? != 0 {
+        reader.u32()?;
+    }
+    reader.take(6 * 4)?;
+    let mut count = 1;
+    if !is_leaf {
+        let child_mask = reader.u8()?;
+        for _ in 0..child_mask.count_ones() {
+            count += skip_node(reader)?;
+        }
+    }
+    Ok(count)
+}
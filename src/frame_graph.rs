@@ -0,0 +1,155 @@
+// Description of the passes recorded in a frame, the resources they touch and the barriers
+// between them. Renderer modules still record their own commands, this is used to review
+// synchronization (export as Graphviz .dot).
+
+use ash::vk;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+pub type PassId = usize;
+pub type ResourceId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceAccess {
+    Read,
+    Write,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameBarrier {
+    pub src_pass: PassId,
+    pub dst_pass: PassId,
+    pub resource: ResourceId,
+    pub src_stage: vk::PipelineStageFlags,
+    pub src_access: vk::AccessFlags,
+    pub dst_stage: vk::PipelineStageFlags,
+    pub dst_access: vk::AccessFlags,
+    pub layouts: Option<(vk::ImageLayout, vk::ImageLayout)>,
+    // Dependency on the previous frame (src_pass recorded in the last frame)
+    pub previous_frame: bool,
+}
+
+#[derive(Default)]
+pub struct FrameGraph {
+    pub passes: Vec<String>,
+    pub resources: Vec<String>,
+    pub accesses: Vec<(PassId, ResourceId, ResourceAccess)>,
+    pub barriers: Vec<FrameBarrier>,
+}
+
+impl FrameGraph {
+    pub fn new() -> FrameGraph {
+        FrameGraph::default()
+    }
+
+    pub fn add_pass(&mut self, name: &str) -> PassId {
+        self.passes.push(name.to_string());
+        self.passes.len() - 1
+    }
+
+    pub fn add_resource(&mut self, name: &str) -> ResourceId {
+        self.resources.push(name.to_string());
+        self.resources.len() - 1
+    }
+
+    pub fn read(&mut self, pass: PassId, resource: ResourceId) {
+        self.accesses.push((pass, resource, ResourceAccess::Read));
+    }
+
+    pub fn write(&mut self, pass: PassId, resource: ResourceId) {
+        self.accesses.push((pass, resource, ResourceAccess::Write));
+    }
+
+    pub fn barrier(&mut self, barrier: FrameBarrier) {
+        assert!(barrier.src_pass < self.passes.len() && barrier.dst_pass < self.passes.len());
+        assert!(barrier.resource < self.resources.len());
+        self.barriers.push(barrier);
+    }
+
+    // Passes are boxes, resources ellipses. Solid edges are barriers labeled with the
+    // stage/access masks (and layout transition), dashed when the source pass is in the previous
+    // frame. Dotted edges are resource reads/writes.
+    pub fn write_dot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "digraph frame {{")?;
+        writeln!(out, "    rankdir=LR;")?;
+        writeln!(out, "    node [fontname=\"monospace\"];")?;
+        writeln!(out, "    edge [fontname=\"monospace\", fontsize=10];")?;
+
+        for (i, name) in self.passes.iter().enumerate() {
+            writeln!(
+                out,
+                "    pass{} [shape=box, label=\"{}\"];",
+                i,
+                escape(name)
+            )?;
+        }
+        for (i, name) in self.resources.iter().enumerate() {
+            writeln!(
+                out,
+                "    resource{} [shape=ellipse, style=filled, fillcolor=lightgrey, label=\"{}\"];",
+                i,
+                escape(name)
+            )?;
+        }
+
+        for &(pass, resource, access) in &self.accesses {
+            match access {
+                ResourceAccess::Read => writeln!(
+                    out,
+                    "    resource{} -> pass{} [style=dotted, arrowhead=empty];",
+                    resource, pass
+                )?,
+                ResourceAccess::Write => writeln!(
+                    out,
+                    "    pass{} -> resource{} [style=dotted, arrowhead=empty];",
+                    pass, resource
+                )?,
+            }
+        }
+
+        for barrier in &self.barriers {
+            let mut label = format!(
+                "{}\\n{:?} -> {:?}\\n{} -> {}",
+                escape(&self.resources[barrier.resource]),
+                barrier.src_stage,
+                barrier.dst_stage,
+                access_label(barrier.src_access),
+                access_label(barrier.dst_access)
+            );
+            if let Some((old_layout, new_layout)) = barrier.layouts {
+                label += &format!("\\n{:?} -> {:?}", old_layout, new_layout);
+            }
+            let style = if barrier.previous_frame {
+                ", style=dashed, constraint=false"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "    pass{} -> pass{} [label=\"{}\"{}];",
+                barrier.src_pass, barrier.dst_pass, label, style
+            )?;
+        }
+
+        writeln!(out, "}}")
+    }
+
+    pub fn save_dot(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_dot(&mut out)?;
+        out.flush()
+    }
+}
+
+fn access_label(access: vk::AccessFlags) -> String {
+    if access.is_empty() {
+        "NONE".to_string()
+    } else {
+        format!("{:?}", access)
+    }
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
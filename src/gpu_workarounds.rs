@@ -0,0 +1,221 @@
+// Vendor and driver quirks, keyed by PCI vendor/device ID and driver version.
+// VulkanBase looks the device up in RULES once after picking it and keeps the result; the
+// swapchain setup and GraphicsPipelineBuilder consult it. The active workarounds are listed
+// next to the device capabilities at startup.
+//
+//   --workaround NAME   enables a workaround on any device, to check a fix without the GPU
+//   --no-workarounds    disables the registry, to check whether a quirk is still there
+
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub const VENDOR_AMD: u32 = 0x1002;
+pub const VENDOR_NVIDIA: u32 = 0x10de;
+pub const VENDOR_INTEL: u32 = 0x8086;
+pub const VENDOR_ARM: u32 = 0x13b5;
+pub const VENDOR_QUALCOMM: u32 = 0x5143;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workaround {
+    AvoidImmediatePresent,    // FIFO even when IMMEDIATE is offered
+    PlaceholderViewportState, // Viewport and scissor arrays in pipelines with dynamic ones
+}
+
+pub const ALL_WORKAROUNDS: [Workaround; 2] = [
+    Workaround::AvoidImmediatePresent,
+    Workaround::PlaceholderViewportState,
+];
+
+impl Workaround {
+    pub fn name(self) -> &'static str {
+        match self {
+            Workaround::AvoidImmediatePresent => "avoid-immediate-present",
+            Workaround::PlaceholderViewportState => "placeholder-viewport-state",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Workaround> {
+        ALL_WORKAROUNDS
+            .iter()
+            .cloned()
+            .find(|workaround| workaround.name() == name)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+// Driver versions are the raw VkPhysicalDeviceProperties::driverVersion, compared in the
+// vendor's own encoding (see driver_version_string)
+#[derive(Clone, Copy, Debug)]
+pub struct WorkaroundRule {
+    pub vendor_id: u32,
+    pub device_id: Option<u32>, // None for every device of the vendor
+    pub min_driver_version: u32,
+    pub max_driver_version: u32, // Inclusive, u32::MAX while the quirk isn't fixed
+    pub workaround: Workaround,
+    pub reason: &'static str,
+}
+
+impl WorkaroundRule {
+    pub fn matches(&self, vendor_id: u32, device_id: u32, driver_version: u32) -> bool {
+        self.vendor_id == vendor_id
+            && self.device_id.is_none_or(|id| id == device_id)
+            && (self.min_driver_version..=self.max_driver_version).contains(&driver_version)
+    }
+}
+
+const fn nvidia_version(major: u32, minor: u32) -> u32 {
+    (major << 22) | (minor << 14)
+}
+
+pub const RULES: &[WorkaroundRule] = &[
+    WorkaroundRule {
+        vendor_id: VENDOR_NVIDIA,
+        device_id: None,
+        min_driver_version: 0,
+        max_driver_version: nvidia_version(470, 0) - 1,
+        workaround: Workaround::AvoidImmediatePresent,
+        reason: "IMMEDIATE presents stutter in windowed mode",
+    },
+    WorkaroundRule {
+        vendor_id: VENDOR_QUALCOMM,
+        device_id: None,
+        min_driver_version: 0,
+        max_driver_version: u32::MAX,
+        workaround: Workaround::PlaceholderViewportState,
+        reason: "pipeline creation reads pViewports/pScissors despite the dynamic state",
+    },
+];
+
+// Set of workarounds, one bit per Workaround
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Workarounds(u32);
+
+impl Workarounds {
+    pub const NONE: Workarounds = Workarounds(0);
+
+    pub fn for_device(vendor_id: u32, device_id: u32, driver_version: u32) -> Workarounds {
+        RULES
+            .iter()
+            .filter(|rule| rule.matches(vendor_id, device_id, driver_version))
+            .fold(Workarounds::NONE, |set, rule| set.with(rule.workaround))
+    }
+
+    pub fn with(self, workaround: Workaround) -> Workarounds {
+        Workarounds(self.0 | workaround.bit())
+    }
+
+    pub fn union(self, other: Workarounds) -> Workarounds {
+        Workarounds(self.0 | other.0)
+    }
+
+    pub fn contains(self, workaround: Workaround) -> bool {
+        self.0 & workaround.bit() != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Workaround> {
+        ALL_WORKAROUNDS
+            .iter()
+            .cloned()
+            .filter(move |&workaround| self.contains(workaround))
+    }
+}
+
+impl fmt::Display for Workarounds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names: Vec<&str> = self.iter().map(Workaround::name).collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+// Reason of the first rule enabling the workaround on the device, None when it was forced
+// from the command line
+pub fn reason(
+    workaround: Workaround,
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+) -> Option<&'static str> {
+    RULES
+        .iter()
+        .find(|rule| {
+            rule.workaround == workaround && rule.matches(vendor_id, device_id, driver_version)
+        })
+        .map(|rule| rule.reason)
+}
+
+// NVIDIA packs 10.8.8.6 bits, the others use the Vulkan API version encoding
+pub fn driver_version_string(vendor_id: u32, driver_version: u32) -> String {
+    if vendor_id == VENDOR_NVIDIA {
+        format!(
+            "{}.{}.{}.{}",
+            driver_version >> 22,
+            (driver_version >> 14) & 0xff,
+            (driver_version >> 6) & 0xff,
+            driver_version & 0x3f
+        )
+    } else {
+        format!(
+            "{}.{}.{}",
+            (driver_version >> 22) & 0x7f,
+            (driver_version >> 12) & 0x3ff,
+            driver_version & 0xfff
+        )
+    }
+}
+
+// Command line overrides of the registry
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorkaroundSettings {
+    pub forced: Workarounds,
+    pub disabled: bool, // --no-workarounds, forced ones still apply
+}
+
+impl WorkaroundSettings {
+    pub fn from_args(args: &[String]) -> WorkaroundSettings {
+        let mut forced = Workarounds::NONE;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg == "--workaround" {
+                match iter.next().map(|name| (name, Workaround::from_name(name))) {
+                    Some((_, Some(workaround))) => forced = forced.with(workaround),
+                    Some((name, None)) => println!("Unknown workaround {}", name),
+                    None => {}
+                }
+            }
+        }
+        WorkaroundSettings {
+            forced,
+            disabled: args.iter().any(|arg| arg == "--no-workarounds"),
+        }
+    }
+
+    pub fn resolve(&self, vendor_id: u32, device_id: u32, driver_version: u32) -> Workarounds {
+        if self.disabled {
+            self.forced
+        } else {
+            Workarounds::for_device(vendor_id, device_id, driver_version).union(self.forced)
+        }
+    }
+}
+
+// Workarounds of the device VulkanBase was created on, for code without access to it like
+// GraphicsPipelineBuilder
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+
+pub fn set_active(workarounds: Workarounds) {
+    ACTIVE.store(workarounds.0, Ordering::Relaxed);
+}
+
+pub fn active() -> Workarounds {
+    Workarounds(ACTIVE.load(Ordering::Relaxed))
+}
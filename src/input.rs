@@ -0,0 +1,176 @@
+// Mouse look settings and smoothing shared by the viewers. Settings are stored in input.toml
+// (found through assets, written to the working directory when there is none yet), command
+// line options override the stored values.
+
+use crate::assets::*;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub const INPUT_SETTINGS_FILE: &str = "input.toml";
+
+// Radians per pixel (or raw mouse count) at sensitivity 1
+pub const BASE_MOUSE_SENSITIVITY: f32 = 0.0015;
+
+// Smoothed rotation below this speed (radians per second) stops
+const MIN_ANGULAR_SPEED: f32 = 0.001;
+
+#[derive(Debug)]
+pub enum InputSettingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    InvalidValue(String),
+}
+
+impl fmt::Display for InputSettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputSettingsError::Io(err) => write!(f, "{}", err),
+            InputSettingsError::Parse(err) => write!(f, "{}", err),
+            InputSettingsError::InvalidValue(key) => {
+                write!(f, "Invalid value for {} in {}", key, INPUT_SETTINGS_FILE)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputSettings {
+    pub sensitivity: f32, // Multiplier for BASE_MOUSE_SENSITIVITY
+    pub invert_y: bool,
+    pub smoothing: f32,  // Time constant in seconds, 0 = off
+    pub raw_mouse: bool, // Device events instead of cursor movement (no OS acceleration)
+}
+
+impl Default for InputSettings {
+    fn default() -> InputSettings {
+        InputSettings {
+            sensitivity: 1.0,
+            invert_y: false,
+            smoothing: 0.0,
+            raw_mouse: false,
+        }
+    }
+}
+
+impl InputSettings {
+    // Missing keys keep their defaults
+    pub fn parse(text: &str) -> Result<InputSettings, InputSettingsError> {
+        let table: toml::Table = text.parse().map_err(InputSettingsError::Parse)?;
+        let mut settings = InputSettings::default();
+        for (key, value) in table.iter() {
+            let invalid = || InputSettingsError::InvalidValue(key.clone());
+            match key.as_str() {
+                "sensitivity" => settings.sensitivity = float_value(value).ok_or_else(invalid)?,
+                "invert_y" => settings.invert_y = value.as_bool().ok_or_else(invalid)?,
+                "smoothing" => settings.smoothing = float_value(value).ok_or_else(invalid)?,
+                "raw_mouse" => settings.raw_mouse = value.as_bool().ok_or_else(invalid)?,
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn to_toml(&self) -> String {
+        format!(
+            "sensitivity = {:?}\ninvert_y = {}\nsmoothing = {:?}\nraw_mouse = {}\n",
+            self.sensitivity, self.invert_y, self.smoothing, self.raw_mouse
+        )
+    }
+
+    // Defaults when there is no settings file
+    pub fn load() -> Result<InputSettings, InputSettingsError> {
+        match resolve(INPUT_SETTINGS_FILE) {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(InputSettingsError::Io)?;
+                InputSettings::parse(&text)
+            }
+            None => Ok(InputSettings::default()),
+        }
+    }
+
+    // Overwrites the file that was loaded, returns the written path
+    pub fn save(&self) -> io::Result<PathBuf> {
+        let path =
+            resolve(INPUT_SETTINGS_FILE).unwrap_or_else(|| PathBuf::from(INPUT_SETTINGS_FILE));
+        fs::write(&path, self.to_toml())?;
+        Ok(path)
+    }
+
+    // Stored settings with --mouse-sensitivity X, --mouse-smoothing SECONDS, --invert-y and
+    // --raw-mouse applied. --save-input-settings stores the result.
+    pub fn from_args(args: &[String]) -> Result<InputSettings, InputSettingsError> {
+        let mut settings = InputSettings::load()?;
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+                .and_then(|value| value.parse::<f32>().ok())
+        };
+        if let Some(sensitivity) = value("--mouse-sensitivity") {
+            settings.sensitivity = sensitivity;
+        }
+        if let Some(smoothing) = value("--mouse-smoothing") {
+            settings.smoothing = smoothing.max(0.0);
+        }
+        if args.iter().any(|arg| arg == "--invert-y") {
+            settings.invert_y = true;
+        }
+        if args.iter().any(|arg| arg == "--raw-mouse") {
+            settings.raw_mouse = true;
+        }
+        if args.iter().any(|arg| arg == "--save-input-settings") {
+            settings.save().map_err(InputSettingsError::Io)?;
+        }
+        Ok(settings)
+    }
+}
+
+// TOML integers are accepted for float settings ("sensitivity = 2")
+fn float_value(value: &toml::Value) -> Option<f32> {
+    value
+        .as_float()
+        .or_else(|| value.as_integer().map(|value| value as f64))
+        .map(|value| value as f32)
+}
+
+// Turns mouse deltas into camera yaw and pitch. Smoothing filters the angular velocity
+// exponentially, so the result doesn't depend on the frame rate and the camera glides to
+// a stop after the mouse stops.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MouseLook {
+    velocity: (f32, f32), // Radians per second
+}
+
+impl MouseLook {
+    pub fn new() -> MouseLook {
+        MouseLook::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.velocity = (0.0, 0.0);
+    }
+
+    // Returns (yaw, pitch) in radians for this frame, dt in seconds
+    pub fn update(&mut self, settings: &InputSettings, delta: (f32, f32), dt: f32) -> (f32, f32) {
+        let scale = BASE_MOUSE_SENSITIVITY * settings.sensitivity;
+        let y_sign = if settings.invert_y { -1.0 } else { 1.0 };
+        let rotation = (delta.0 * scale, delta.1 * scale * y_sign);
+
+        if settings.smoothing <= 0.0 || dt <= 0.0 {
+            self.velocity = (0.0, 0.0);
+            return rotation;
+        }
+
+        let target = (rotation.0 / dt, rotation.1 / dt);
+        let blend = 1.0 - (-dt / settings.smoothing).exp();
+        self.velocity.0 += (target.0 - self.velocity.0) * blend;
+        self.velocity.1 += (target.1 - self.velocity.1) * blend;
+        if self.velocity.0.abs() < MIN_ANGULAR_SPEED && self.velocity.1.abs() < MIN_ANGULAR_SPEED {
+            self.velocity = (0.0, 0.0);
+        }
+        (self.velocity.0 * dt, self.velocity.1 * dt)
+    }
+}
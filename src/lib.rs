@@ -1,7 +1,43 @@
+pub mod allocator_telemetry;
+pub mod assets;
+pub mod block_compression;
+pub mod brick_cache;
+pub mod brick_checksum;
+pub mod brick_range;
+pub mod brick_stream;
+pub mod cancellation;
+pub mod color;
+pub mod detail_noise;
+pub mod device_capabilities;
+pub mod device_group;
+pub mod display_settings;
+pub mod file_format;
+pub mod frame_graph;
+pub mod gpu_workarounds;
+pub mod input;
+pub mod localization;
+pub mod memory_profile;
+pub mod memory_report;
 pub mod minivector;
+#[cfg(feature = "simd")]
+pub mod minivector_simd;
+pub mod object_counters;
+pub mod packing;
+pub mod procedural;
+pub mod projection_settings;
+pub mod quality_settings;
+pub mod render_module;
 pub mod sdf;
+pub mod sdf_stats;
 pub mod serialization;
+pub mod space_filling_curve;
 pub mod sparse_sdf;
+pub mod spirv_reflection;
+pub mod svo_diff;
 pub mod svosdf;
+pub mod texture_file;
+pub mod thumbnail;
+pub mod validation_log;
+pub mod volume_projection;
 pub mod vulkan_base;
 pub mod vulkan_helpers;
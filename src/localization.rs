@@ -0,0 +1,169 @@
+// Key based lookup of console and CLI text. Language files are TOML tables in
+// lang/<code>.toml, nested table names form the key prefix ("cli.error.missing_input_file").
+// English is built in and used for keys missing from the active language.
+// Placeholders are written as {name} and filled by tr_args. The lang directory is resolved
+// through assets, so it's found when running from another directory.
+
+use crate::assets::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::RwLock;
+
+pub const LANGUAGE_DIR: &str = "lang";
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+const ENGLISH: &str = include_str!("../lang/en.toml");
+
+#[derive(Debug)]
+pub enum LocalizationError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for LocalizationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LocalizationError::Io(err) => write!(f, "{}", err),
+            LocalizationError::Parse(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+pub struct Localization {
+    pub language: String,
+    strings: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+}
+
+impl Localization {
+    pub fn english() -> Localization {
+        let fallback = parse_strings(ENGLISH).expect("Built in English strings are invalid");
+        Localization {
+            language: DEFAULT_LANGUAGE.to_string(),
+            strings: HashMap::new(),
+            fallback,
+        }
+    }
+
+    pub fn load(language: &str) -> Result<Localization, LocalizationError> {
+        let mut localization = Localization::english();
+        if language != DEFAULT_LANGUAGE {
+            let path = asset_path(LANGUAGE_DIR).join(format!("{}.toml", language));
+            let text = fs::read_to_string(path).map_err(LocalizationError::Io)?;
+            localization.strings = parse_strings(&text)?;
+            localization.language = language.to_string();
+        }
+        Ok(localization)
+    }
+
+    // Unknown keys return the key itself, so missing strings are visible but not fatal
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, |value| value.as_str())
+    }
+}
+
+fn parse_strings(text: &str) -> Result<HashMap<String, String>, LocalizationError> {
+    let table: toml::Table = text.parse().map_err(LocalizationError::Parse)?;
+    let mut strings = HashMap::new();
+    flatten_table("", &table, &mut strings);
+    Ok(strings)
+}
+
+fn flatten_table(prefix: &str, table: &toml::Table, strings: &mut HashMap<String, String>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match value {
+            toml::Value::String(s) => {
+                strings.insert(key, s.clone());
+            }
+            toml::Value::Table(t) => flatten_table(&key, t, strings),
+            _ => {}
+        }
+    }
+}
+
+// Active language, switchable at runtime
+static ACTIVE: RwLock<Option<Localization>> = RwLock::new(None);
+
+pub fn set_language(language: &str) -> Result<(), LocalizationError> {
+    let localization = Localization::load(language)?;
+    *ACTIVE.write().unwrap() = Some(localization);
+    Ok(())
+}
+
+pub fn language() -> String {
+    ACTIVE
+        .read()
+        .unwrap()
+        .as_ref()
+        .map_or(DEFAULT_LANGUAGE.to_string(), |l| l.language.clone())
+}
+
+// English plus every lang/*.toml, sorted
+pub fn available_languages() -> Vec<String> {
+    let mut languages = vec![DEFAULT_LANGUAGE.to_string()];
+    if let Ok(entries) = fs::read_dir(asset_path(LANGUAGE_DIR)) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    if stem != DEFAULT_LANGUAGE {
+                        languages.push(stem.to_string());
+                    }
+                }
+            }
+        }
+    }
+    languages.sort();
+    languages
+}
+
+// Switches to the language after the active one in available_languages()
+pub fn next_language() -> Result<String, LocalizationError> {
+    let languages = available_languages();
+    let current = language();
+    let index = languages.iter().position(|l| *l == current).unwrap_or(0);
+    let next = languages[(index + 1) % languages.len()].clone();
+    set_language(&next)?;
+    Ok(next)
+}
+
+// --lang <code>, otherwise the RUST_TEST_LANG environment variable
+pub fn init_from_args(args: &[String]) -> Result<(), LocalizationError> {
+    let language = args
+        .iter()
+        .position(|arg| arg == "--lang")
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+        .or_else(|| std::env::var("RUST_TEST_LANG").ok())
+        .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string());
+    set_language(&language)
+}
+
+pub fn tr(key: &str) -> String {
+    if let Some(localization) = ACTIVE.read().unwrap().as_ref() {
+        return localization.get(key).to_string();
+    }
+    let mut active = ACTIVE.write().unwrap();
+    active
+        .get_or_insert_with(Localization::english)
+        .get(key)
+        .to_string()
+}
+
+pub fn tr_args(key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let mut text = tr(key);
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), &value.to_string());
+    }
+    text
+}
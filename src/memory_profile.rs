@@ -0,0 +1,193 @@
+// Memory profile shared by VulkanBase and the renderer subsystems.
+// The low memory profile targets 2-4 GB GPUs.
+
+use crate::block_compression::bc4_compressed_bytes;
+use crate::packing::{pack_unorm16, pack_unorm8, unpack_unorm8, UNORM16_MAX};
+use crate::sdf::LEVEL_ZERO;
+use crate::vulkan_helpers::{BudgetTag, GpuBudget};
+
+use ash::vk;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrickFormat {
+    Unorm16,
+    Float16, // Same codes as Unorm16 as half floats, for GPUs that can't filter R16_UNORM
+    Unorm8,
+    Bc4, // Unorm8 codes compressed to 4x4 blocks on the CPU, bricks must be 4 aligned
+}
+
+impl BrickFormat {
+    pub fn bits_per_voxel(self) -> u64 {
+        match self {
+            BrickFormat::Unorm16 | BrickFormat::Float16 => 16,
+            BrickFormat::Unorm8 => 8,
+            BrickFormat::Bc4 => 4,
+        }
+    }
+
+    // Upload size of a brick of size^3 voxels
+    pub fn brick_bytes(self, size: u32) -> usize {
+        match self {
+            BrickFormat::Bc4 => bc4_compressed_bytes((size, size, size)),
+            _ => (size * size * size) as usize * self.bits_per_voxel() as usize / 8,
+        }
+    }
+
+    pub fn vk_format(self) -> vk::Format {
+        match self {
+            BrickFormat::Unorm16 => vk::Format::R16_UNORM,
+            BrickFormat::Float16 => vk::Format::R16_SFLOAT,
+            BrickFormat::Unorm8 => vk::Format::R8_UNORM,
+            BrickFormat::Bc4 => vk::Format::BC4_UNORM_BLOCK,
+        }
+    }
+
+    // Formats of the same size first, R16_SFLOAT and R8_UNORM filtering is required by the
+    // spec, R16_UNORM filtering isn't. BC4 needs the textureCompressionBC feature.
+    pub fn fallbacks(self) -> &'static [BrickFormat] {
+        match self {
+            BrickFormat::Unorm16 => &[BrickFormat::Float16, BrickFormat::Unorm8],
+            BrickFormat::Float16 => &[BrickFormat::Unorm8],
+            BrickFormat::Unorm8 => &[],
+            BrickFormat::Bc4 => &[BrickFormat::Unorm8],
+        }
+    }
+}
+
+// 8 bit bricks store the signed distance s in [-1, 1] as code = sign(s) * |s|^(1 / exponent).
+// Exponents above 1 spend more of the 256 codes near the surface. svo_main.frag decodes with
+// sign(code) * |code|^exponent after filtering, the zero crossing stays in place.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BrickCurve {
+    pub exponent: f32,
+}
+
+const MAX_CURVE_EXPONENT: f32 = 4.0;
+const CURVE_HISTOGRAM_BINS: usize = 1024;
+
+impl BrickCurve {
+    pub const LINEAR: BrickCurve = BrickCurve { exponent: 1.0 };
+
+    // Equalizes the distance histogram of the voxels: the median |s| maps to half of the code
+    // range on each side of the surface
+    pub fn equalized<'a>(bricks: impl Iterator<Item = &'a [u16]>) -> BrickCurve {
+        let mut histogram = vec![0u64; CURVE_HISTOGRAM_BINS];
+        let mut count = 0u64;
+        for &value in bricks.flatten() {
+            let magnitude = BrickCurve::signed(value).abs();
+            let bin = (magnitude * CURVE_HISTOGRAM_BINS as f32) as usize;
+            histogram[bin.min(CURVE_HISTOGRAM_BINS - 1)] += 1;
+            count += 1;
+        }
+
+        let mut below = 0;
+        let median_bin = histogram.iter().position(|&bin_count| {
+            below += bin_count;
+            below * 2 >= count
+        });
+        let median = match median_bin {
+            Some(bin) if count > 0 => (bin + 1) as f32 / CURVE_HISTOGRAM_BINS as f32,
+            _ => return BrickCurve::LINEAR,
+        };
+        let exponent = (median.ln() / 0.5f32.ln()).clamp(1.0, MAX_CURVE_EXPONENT);
+        BrickCurve { exponent }
+    }
+
+    fn signed(value: u16) -> f32 {
+        (value as f32 - LEVEL_ZERO as f32) / LEVEL_ZERO as f32
+    }
+
+    pub fn encode_unorm8(self, value: u16) -> u8 {
+        let s = BrickCurve::signed(value);
+        let code = s.signum() * s.abs().powf(1.0 / self.exponent);
+        pack_unorm8(code * 0.5 + 0.5)
+    }
+
+    pub fn decode_unorm8(self, code: u8) -> u16 {
+        let code = unpack_unorm8(code) * 2.0 - 1.0;
+        let s = code.signum() * code.abs().powf(self.exponent);
+        pack_unorm16((s * LEVEL_ZERO as f32 + LEVEL_ZERO as f32) / UNORM16_MAX)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryProfile {
+    pub extra_swapchain_images: u32, // Images requested on top of the surface minimum
+    pub brick_format: BrickFormat,
+    pub atlas_budget_bytes: u64,
+    pub enable_hiz: bool, // Depth pyramid + occlusion culling
+}
+
+const DEFAULT_ATLAS_BUDGET: u64 = 1024 * 1024 * 1024;
+
+impl MemoryProfile {
+    pub fn low_memory() -> MemoryProfile {
+        MemoryProfile {
+            extra_swapchain_images: 0,
+            brick_format: BrickFormat::Unorm8,
+            atlas_budget_bytes: DEFAULT_ATLAS_BUDGET / 2,
+            enable_hiz: false,
+        }
+    }
+
+    // --bc4-bricks compresses the bricks in either profile
+    pub fn from_args(args: &[String]) -> MemoryProfile {
+        let mut profile = if args.iter().any(|arg| arg == "--low-memory") {
+            MemoryProfile::low_memory()
+        } else {
+            MemoryProfile::default()
+        };
+        if args.iter().any(|arg| arg == "--bc4-bricks") {
+            profile.brick_format = BrickFormat::Bc4;
+        }
+        profile
+    }
+
+    // Hard per-module limits. The brick upload goes through a single staging buffer,
+    // so staging gets the same limit as the atlas. Octree and framebuffers are unlimited.
+    pub fn gpu_budget(&self) -> GpuBudget {
+        let budget = GpuBudget::new();
+        budget.set_limit(BudgetTag::Atlas, Some(self.atlas_budget_bytes));
+        budget.set_limit(BudgetTag::Staging, Some(self.atlas_budget_bytes));
+        budget
+    }
+
+    // Picks the brick format that fits the atlas budget. Falls back to 8 bit bricks
+    // before giving up, so that the caller gets a descriptive error instead of a driver OOM.
+    pub fn select_brick_format(&self, atlas_voxels: u64) -> Result<BrickFormat, String> {
+        let atlas_bytes = |format: BrickFormat| atlas_voxels * format.bits_per_voxel() / 8;
+        let fits = |format: BrickFormat| atlas_bytes(format) <= self.atlas_budget_bytes;
+        let smallest = if self.brick_format.bits_per_voxel() > 8 {
+            BrickFormat::Unorm8
+        } else {
+            self.brick_format
+        };
+
+        if fits(self.brick_format) {
+            Ok(self.brick_format)
+        } else if fits(smallest) {
+            println!(
+                "Brick atlas exceeds budget with {:?} bricks, falling back to {:?}",
+                self.brick_format, smallest
+            );
+            Ok(smallest)
+        } else {
+            Err(format!(
+                "Brick atlas needs {} MB, budget is {} MB",
+                atlas_bytes(smallest) / (1024 * 1024),
+                self.atlas_budget_bytes / (1024 * 1024)
+            ))
+        }
+    }
+}
+
+impl Default for MemoryProfile {
+    fn default() -> Self {
+        MemoryProfile {
+            extra_swapchain_images: 1,
+            brick_format: BrickFormat::Unorm16,
+            atlas_budget_bytes: DEFAULT_ATLAS_BUDGET,
+            enable_hiz: true,
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// GPU memory statistics for tuning resource sizes (--mem-stats). Allocation counts and
+// fragmentation come from gpu-allocator, heap usage from VK_EXT_memory_budget when enabled.
+
+use std::fmt;
+
+use ash::vk;
+use ash::Instance;
+use gpu_allocator::vulkan::Allocator;
+
+#[derive(Clone, Debug)]
+pub struct HeapReport {
+    pub index: usize,
+    pub size: u64,
+    pub device_local: bool,
+    pub usage: Option<u64>,  // Process usage, needs VK_EXT_memory_budget
+    pub budget: Option<u64>, // Estimated available to the process
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    pub heaps: Vec<HeapReport>,
+    pub num_blocks: usize,
+    pub num_allocations: usize,
+    pub allocated_bytes: u64,
+    pub reserved_bytes: u64,
+    pub largest_free_bytes: u64, // Largest contiguous free range in any block
+}
+
+impl MemoryReport {
+    pub fn new(
+        instance: &Instance,
+        pdevice: vk::PhysicalDevice,
+        memory_budget: bool,
+        allocator: &Allocator,
+    ) -> MemoryReport {
+        // memory_budget is only enabled on Vulkan 1.1+, where the properties2 query is core
+        let mut budget_properties = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let memory_properties = if memory_budget {
+            let mut properties = vk::PhysicalDeviceMemoryProperties2 {
+                p_next: &mut budget_properties as *mut _ as *mut std::os::raw::c_void,
+                ..Default::default()
+            };
+            unsafe { instance.get_physical_device_memory_properties2(pdevice, &mut properties) };
+            properties.memory_properties
+        } else {
+            unsafe { instance.get_physical_device_memory_properties(pdevice) }
+        };
+
+        let heaps = memory_properties
+            .memory_heaps_as_slice()
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| HeapReport {
+                index,
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                usage: if memory_budget {
+                    Some(budget_properties.heap_usage[index])
+                } else {
+                    None
+                },
+                budget: if memory_budget {
+                    Some(budget_properties.heap_budget[index])
+                } else {
+                    None
+                },
+            })
+            .collect();
+
+        let report = allocator.generate_report();
+
+        // Free ranges are the gaps between the allocations of each block
+        let largest_free_bytes = report
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut allocations: Vec<(u64, u64)> = report.allocations
+                    [block.allocations.clone()]
+                .iter()
+                .map(|allocation| (allocation.offset, allocation.size))
+                .collect();
+                allocations.sort_unstable();
+
+                let mut largest = 0;
+                let mut end = 0;
+                for (offset, size) in allocations {
+                    largest = largest.max(offset.saturating_sub(end));
+                    end = end.max(offset + size);
+                }
+                largest.max(block.size.saturating_sub(end))
+            })
+            .max()
+            .unwrap_or(0);
+
+        MemoryReport {
+            heaps,
+            num_blocks: report.blocks.len(),
+            num_allocations: report.allocations.len(),
+            allocated_bytes: report.total_allocated_bytes,
+            reserved_bytes: report.total_reserved_bytes,
+            largest_free_bytes,
+        }
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.reserved_bytes - self.allocated_bytes
+    }
+
+    // 0 = all free memory is one range, close to 1 = free memory is scattered
+    pub fn fragmentation(&self) -> f32 {
+        let free_bytes = self.free_bytes();
+        if free_bytes == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free_bytes as f32 / free_bytes as f32
+        }
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "GPU memory:")?;
+        for heap in self.heaps.iter() {
+            write!(
+                f,
+                "  Heap {}{}: {:.1} MiB",
+                heap.index,
+                if heap.device_local {
+                    " (device local)"
+                } else {
+                    ""
+                },
+                mib(heap.size)
+            )?;
+            if let (Some(usage), Some(budget)) = (heap.usage, heap.budget) {
+                write!(
+                    f,
+                    ", {:.1} MiB used, {:.1} MiB budget",
+                    mib(usage),
+                    mib(budget)
+                )?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(
+            f,
+            "  Allocator: {} allocations in {} blocks, {:.1} of {:.1} MiB allocated",
+            self.num_allocations,
+            self.num_blocks,
+            mib(self.allocated_bytes),
+            mib(self.reserved_bytes)
+        )?;
+        write!(
+            f,
+            "  Free: {:.1} MiB, largest free range {:.1} MiB, fragmentation {:.0}%",
+            mib(self.free_bytes()),
+            mib(self.largest_free_bytes),
+            self.fragmentation() * 100.0
+        )
+    }
+}
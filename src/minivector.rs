@@ -1,6 +1,8 @@
+use crate::packing::{f16_to_f32, f32_to_f16};
 use std::ops;
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+#[repr(C)]
 pub struct Vec3 {
     pub x: f32,
     pub y: f32,
@@ -47,6 +49,27 @@ impl Vec3 {
             z: self.z * l_inv,
         }
     }
+
+    pub fn min(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+            z: self.z.min(other.z),
+        }
+    }
+
+    pub fn max(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+            z: self.z.max(other.z),
+        }
+    }
+
+    // t = 0 is self and t = 1 is other, t outside 0..1 extrapolates
+    pub fn lerp(self, other: Vec3, t: f32) -> Vec3 {
+        self + (other - self) * t
+    }
 }
 
 impl ops::Mul<f32> for Vec3 {
@@ -121,7 +144,222 @@ impl ops::Neg for Vec3 {
     }
 }
 
+// Component-wise arithmetic, scalar multiply and divide, dot and min/max of a vector type
+macro_rules! componentwise_ops {
+    ($vec:ident, $scalar:ty, $($field:ident),+) => {
+        impl $vec {
+            pub fn dot(self, other: $vec) -> $scalar {
+                let mut sum = <$scalar>::default();
+                $(sum += self.$field * other.$field;)+
+                sum
+            }
+
+            pub fn min(self, other: $vec) -> $vec {
+                $vec { $($field: self.$field.min(other.$field)),+ }
+            }
+
+            pub fn max(self, other: $vec) -> $vec {
+                $vec { $($field: self.$field.max(other.$field)),+ }
+            }
+        }
+
+        impl ops::Add<$vec> for $vec {
+            type Output = $vec;
+
+            fn add(self, _rhs: $vec) -> $vec {
+                $vec { $($field: self.$field + _rhs.$field),+ }
+            }
+        }
+
+        impl ops::Sub<$vec> for $vec {
+            type Output = $vec;
+
+            fn sub(self, _rhs: $vec) -> $vec {
+                $vec { $($field: self.$field - _rhs.$field),+ }
+            }
+        }
+
+        impl ops::Mul<$vec> for $vec {
+            type Output = $vec;
+
+            fn mul(self, _rhs: $vec) -> $vec {
+                $vec { $($field: self.$field * _rhs.$field),+ }
+            }
+        }
+
+        impl ops::Div<$vec> for $vec {
+            type Output = $vec;
+
+            fn div(self, _rhs: $vec) -> $vec {
+                $vec { $($field: self.$field / _rhs.$field),+ }
+            }
+        }
+
+        impl ops::Mul<$scalar> for $vec {
+            type Output = $vec;
+
+            fn mul(self, _rhs: $scalar) -> $vec {
+                $vec { $($field: self.$field * _rhs),+ }
+            }
+        }
+
+        impl ops::Div<$scalar> for $vec {
+            type Output = $vec;
+
+            fn div(self, _rhs: $scalar) -> $vec {
+                $vec { $($field: self.$field / _rhs),+ }
+            }
+        }
+    };
+}
+
+#[derive(Clone, Debug, Copy, Default, PartialEq)]
+#[repr(C)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+componentwise_ops!(Vec2, f32, x, y);
+
+impl Vec2 {
+    pub fn from_scalar(v: f32) -> Vec2 {
+        Vec2 { x: v, y: v }
+    }
+
+    pub fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(self) -> Vec2 {
+        self * (1.0 / self.length())
+    }
+}
+
+impl ops::Neg for Vec2 {
+    type Output = Vec2;
+
+    fn neg(self) -> Vec2 {
+        Vec2 {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
+// Signed voxel coordinates and offsets
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct IVec3 {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+componentwise_ops!(IVec3, i32, x, y, z);
+
+impl IVec3 {
+    pub fn new(x: i32, y: i32, z: i32) -> IVec3 {
+        IVec3 { x, y, z }
+    }
+
+    pub fn as_vec3(self) -> Vec3 {
+        Vec3 {
+            x: self.x as f32,
+            y: self.y as f32,
+            z: self.z as f32,
+        }
+    }
+}
+
+impl ops::Neg for IVec3 {
+    type Output = IVec3;
+
+    fn neg(self) -> IVec3 {
+        IVec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+// Voxel positions, sizes and bounds of the volume grids
+#[derive(Clone, Debug, Copy, Default, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct UVec3 {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+componentwise_ops!(UVec3, u32, x, y, z);
+
+impl UVec3 {
+    pub const ZERO: UVec3 = UVec3 { x: 0, y: 0, z: 0 };
+
+    pub fn new(x: u32, y: u32, z: u32) -> UVec3 {
+        UVec3 { x, y, z }
+    }
+
+    pub fn splat(v: u32) -> UVec3 {
+        UVec3 { x: v, y: v, z: v }
+    }
+
+    // Voxels in a box of this size, in u64 so large volumes don't overflow
+    pub fn volume(self) -> u64 {
+        self.x as u64 * self.y as u64 * self.z as u64
+    }
+
+    // x + y * dim.x + z * dim.x * dim.y, the voxel order of the volume files
+    pub fn linear_index(self, dim: UVec3) -> usize {
+        (self.x as usize) + (self.y as usize + self.z as usize * dim.y as usize) * dim.x as usize
+    }
+
+    // Every component less than the one of other
+    pub fn all_lt(self, other: UVec3) -> bool {
+        self.x < other.x && self.y < other.y && self.z < other.z
+    }
+
+    pub fn all_ge(self, other: UVec3) -> bool {
+        self.x >= other.x && self.y >= other.y && self.z >= other.z
+    }
+
+    pub fn as_ivec3(self) -> IVec3 {
+        IVec3 {
+            x: self.x as i32,
+            y: self.y as i32,
+            z: self.z as i32,
+        }
+    }
+
+    pub fn as_vec3(self) -> Vec3 {
+        Vec3 {
+            x: self.x as f32,
+            y: self.y as f32,
+            z: self.z as f32,
+        }
+    }
+}
+
+impl From<(u32, u32, u32)> for UVec3 {
+    fn from(v: (u32, u32, u32)) -> UVec3 {
+        UVec3 {
+            x: v.0,
+            y: v.1,
+            z: v.2,
+        }
+    }
+}
+
+impl From<UVec3> for (u32, u32, u32) {
+    fn from(v: UVec3) -> (u32, u32, u32) {
+        (v.x, v.y, v.z)
+    }
+}
+
 #[derive(Clone, Debug, Copy)]
+#[repr(C)]
 pub struct Vec4 {
     pub x: f32,
     pub y: f32,
@@ -138,6 +376,11 @@ impl Vec4 {
         }
     }
 
+    pub fn length(self) -> f32 {
+        let l2 = self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w;
+        l2.sqrt()
+    }
+
     pub fn normalize(self) -> Vec4 {
         let l2 = self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w;
         let l_inv = 1.0 / l2.sqrt();
@@ -150,7 +393,29 @@ impl Vec4 {
     }
 }
 
+impl Vec4 {
+    // Half floats for reduced precision GPU buffers, unpackHalf2x16 of .xy and .zw in GLSL
+    pub fn to_f16(self) -> [u16; 4] {
+        [
+            f32_to_f16(self.x),
+            f32_to_f16(self.y),
+            f32_to_f16(self.z),
+            f32_to_f16(self.w),
+        ]
+    }
+
+    pub fn from_f16(bits: [u16; 4]) -> Vec4 {
+        Vec4 {
+            x: f16_to_f32(bits[0]),
+            y: f16_to_f32(bits[1]),
+            z: f16_to_f32(bits[2]),
+            w: f16_to_f32(bits[3]),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Copy)]
+#[repr(C)]
 pub struct Mat4x4 {
     pub r0: Vec4,
     pub r1: Vec4,
@@ -158,84 +423,65 @@ pub struct Mat4x4 {
     pub r3: Vec4,
 }
 
+// With the simd feature the matrix products and inverse() run on SSE (x86_64) or NEON
+// (aarch64), see minivector_simd.rs. The *_scalar functions are the portable versions.
 impl ops::Mul<Mat4x4> for Mat4x4 {
     type Output = Mat4x4;
 
     fn mul(self, _rhs: Mat4x4) -> Mat4x4 {
-        Mat4x4 {
-            r0: Vec4 {
-                x: self.r0.x * _rhs.r0.x
-                    + self.r0.y * _rhs.r1.x
-                    + self.r0.z * _rhs.r2.x
-                    + self.r0.w * _rhs.r3.x,
-                y: self.r0.x * _rhs.r0.y
-                    + self.r0.y * _rhs.r1.y
-                    + self.r0.z * _rhs.r2.y
-                    + self.r0.w * _rhs.r3.y,
-                z: self.r0.x * _rhs.r0.z
-                    + self.r0.y * _rhs.r1.z
-                    + self.r0.z * _rhs.r2.z
-                    + self.r0.w * _rhs.r3.z,
-                w: self.r0.x * _rhs.r0.w
-                    + self.r0.y * _rhs.r1.w
-                    + self.r0.z * _rhs.r2.w
-                    + self.r0.w * _rhs.r3.w,
-            },
-            r1: Vec4 {
-                x: self.r1.x * _rhs.r0.x
-                    + self.r1.y * _rhs.r1.x
-                    + self.r1.z * _rhs.r2.x
-                    + self.r1.w * _rhs.r3.x,
-                y: self.r1.x * _rhs.r0.y
-                    + self.r1.y * _rhs.r1.y
-                    + self.r1.z * _rhs.r2.y
-                    + self.r1.w * _rhs.r3.y,
-                z: self.r1.x * _rhs.r0.z
-                    + self.r1.y * _rhs.r1.z
-                    + self.r1.z * _rhs.r2.z
-                    + self.r1.w * _rhs.r3.z,
-                w: self.r1.x * _rhs.r0.w
-                    + self.r1.y * _rhs.r1.w
-                    + self.r1.z * _rhs.r2.w
-                    + self.r1.w * _rhs.r3.w,
-            },
-            r2: Vec4 {
-                x: self.r2.x * _rhs.r0.x
-                    + self.r2.y * _rhs.r1.x
-                    + self.r2.z * _rhs.r2.x
-                    + self.r2.w * _rhs.r3.x,
-                y: self.r2.x * _rhs.r0.y
-                    + self.r2.y * _rhs.r1.y
-                    + self.r2.z * _rhs.r2.y
-                    + self.r2.w * _rhs.r3.y,
-                z: self.r2.x * _rhs.r0.z
-                    + self.r2.y * _rhs.r1.z
-                    + self.r2.z * _rhs.r2.z
-                    + self.r2.w * _rhs.r3.z,
-                w: self.r2.x * _rhs.r0.w
-                    + self.r2.y * _rhs.r1.w
-                    + self.r2.z * _rhs.r2.w
-                    + self.r2.w * _rhs.r3.w,
-            },
-            r3: Vec4 {
-                x: self.r3.x * _rhs.r0.x
-                    + self.r3.y * _rhs.r1.x
-                    + self.r3.z * _rhs.r2.x
-                    + self.r3.w * _rhs.r3.x,
-                y: self.r3.x * _rhs.r0.y
-                    + self.r3.y * _rhs.r1.y
-                    + self.r3.z * _rhs.r2.y
-                    + self.r3.w * _rhs.r3.y,
-                z: self.r3.x * _rhs.r0.z
-                    + self.r3.y * _rhs.r1.z
-                    + self.r3.z * _rhs.r2.z
-                    + self.r3.w * _rhs.r3.z,
-                w: self.r3.x * _rhs.r0.w
-                    + self.r3.y * _rhs.r1.w
-                    + self.r3.z * _rhs.r2.w
-                    + self.r3.w * _rhs.r3.w,
-            },
-        }
+        #[cfg(feature = "simd")]
+        return crate::minivector_simd::mul_mat4(self, _rhs);
+        #[cfg(not(feature = "simd"))]
+        mul_mat4_scalar(self, _rhs)
+    }
+}
+
+impl ops::Mul<Mat4x4> for Vec4 {
+    type Output = Vec4;
+
+    fn mul(self, _rhs: Mat4x4) -> Vec4 {
+        #[cfg(feature = "simd")]
+        return crate::minivector_simd::mul_vec4_mat4(self, _rhs);
+        #[cfg(not(feature = "simd"))]
+        mul_vec4_mat4_scalar(self, _rhs)
+    }
+}
+
+pub fn mul_vec4_mat4_scalar(v: Vec4, m: Mat4x4) -> Vec4 {
+    Vec4 {
+        x: v.x * m.r0.x + v.y * m.r1.x + v.z * m.r2.x + v.w * m.r3.x,
+        y: v.x * m.r0.y + v.y * m.r1.y + v.z * m.r2.y + v.w * m.r3.y,
+        z: v.x * m.r0.z + v.y * m.r1.z + v.z * m.r2.z + v.w * m.r3.z,
+        w: v.x * m.r0.w + v.y * m.r1.w + v.z * m.r2.w + v.w * m.r3.w,
+    }
+}
+
+pub fn mul_mat4_scalar(a: Mat4x4, b: Mat4x4) -> Mat4x4 {
+    Mat4x4 {
+        r0: Vec4 {
+            x: a.r0.x * b.r0.x + a.r0.y * b.r1.x + a.r0.z * b.r2.x + a.r0.w * b.r3.x,
+            y: a.r0.x * b.r0.y + a.r0.y * b.r1.y + a.r0.z * b.r2.y + a.r0.w * b.r3.y,
+            z: a.r0.x * b.r0.z + a.r0.y * b.r1.z + a.r0.z * b.r2.z + a.r0.w * b.r3.z,
+            w: a.r0.x * b.r0.w + a.r0.y * b.r1.w + a.r0.z * b.r2.w + a.r0.w * b.r3.w,
+        },
+        r1: Vec4 {
+            x: a.r1.x * b.r0.x + a.r1.y * b.r1.x + a.r1.z * b.r2.x + a.r1.w * b.r3.x,
+            y: a.r1.x * b.r0.y + a.r1.y * b.r1.y + a.r1.z * b.r2.y + a.r1.w * b.r3.y,
+            z: a.r1.x * b.r0.z + a.r1.y * b.r1.z + a.r1.z * b.r2.z + a.r1.w * b.r3.z,
+            w: a.r1.x * b.r0.w + a.r1.y * b.r1.w + a.r1.z * b.r2.w + a.r1.w * b.r3.w,
+        },
+        r2: Vec4 {
+            x: a.r2.x * b.r0.x + a.r2.y * b.r1.x + a.r2.z * b.r2.x + a.r2.w * b.r3.x,
+            y: a.r2.x * b.r0.y + a.r2.y * b.r1.y + a.r2.z * b.r2.y + a.r2.w * b.r3.y,
+            z: a.r2.x * b.r0.z + a.r2.y * b.r1.z + a.r2.z * b.r2.z + a.r2.w * b.r3.z,
+            w: a.r2.x * b.r0.w + a.r2.y * b.r1.w + a.r2.z * b.r2.w + a.r2.w * b.r3.w,
+        },
+        r3: Vec4 {
+            x: a.r3.x * b.r0.x + a.r3.y * b.r1.x + a.r3.z * b.r2.x + a.r3.w * b.r3.x,
+            y: a.r3.x * b.r0.y + a.r3.y * b.r1.y + a.r3.z * b.r2.y + a.r3.w * b.r3.y,
+            z: a.r3.x * b.r0.z + a.r3.y * b.r1.z + a.r3.z * b.r2.z + a.r3.w * b.r3.z,
+            w: a.r3.x * b.r0.w + a.r3.y * b.r1.w + a.r3.z * b.r2.w + a.r3.w * b.r3.w,
+        },
     }
 }
 
@@ -261,7 +507,116 @@ pub fn identity() -> Mat4x4 {
     }
 }
 
-pub fn inverse(m: Mat4x4) -> Mat4x4 {
+// Largest difference of inverse(m) * m from the identity, beyond it f32 can't invert m
+const INVERSE_TOLERANCE: f32 = 1e-2;
+// Scales and w components below it count as zero for decompose
+const DECOMPOSE_EPSILON: f32 = 1e-6;
+
+pub fn determinant(m: Mat4x4) -> f32 {
+    let a2323 = m.r2.z * m.r3.w - m.r2.w * m.r3.z;
+    let a1323 = m.r2.y * m.r3.w - m.r2.w * m.r3.y;
+    let a1223 = m.r2.y * m.r3.z - m.r2.z * m.r3.y;
+    let a0323 = m.r2.x * m.r3.w - m.r2.w * m.r3.x;
+    let a0223 = m.r2.x * m.r3.z - m.r2.z * m.r3.x;
+    let a0123 = m.r2.x * m.r3.y - m.r2.y * m.r3.x;
+    m.r0.x * (m.r1.y * a2323 - m.r1.z * a1323 + m.r1.w * a1223)
+        - m.r0.y * (m.r1.x * a2323 - m.r1.z * a0323 + m.r1.w * a0223)
+        + m.r0.z * (m.r1.x * a1323 - m.r1.y * a0323 + m.r1.w * a0123)
+        - m.r0.w * (m.r1.x * a1223 - m.r1.y * a0223 + m.r1.z * a0123)
+}
+
+// None for singular matrices (a zero scale, a projection onto a plane), non-finite ones and
+// ones too badly conditioned for f32, checked by multiplying the inverse back and comparing
+// with the identity. inverse_or_identity falls back to the identity instead, and
+// inverse_unchecked skips the check.
+pub fn inverse(m: Mat4x4) -> Option<Mat4x4> {
+    let det = determinant(m);
+    if det == 0.0 || !det.is_finite() {
+        return None;
+    }
+    let inv = inverse_unchecked(m);
+    let product = inv * m;
+    let identity = identity();
+    let rows = [
+        (product.r0, identity.r0),
+        (product.r1, identity.r1),
+        (product.r2, identity.r2),
+        (product.r3, identity.r3),
+    ];
+    let near_identity = rows.iter().all(|(a, b)| {
+        [a.x - b.x, a.y - b.y, a.z - b.z, a.w - b.w]
+            .iter()
+            .all(|d| d.abs() <= INVERSE_TOLERANCE)
+    });
+    if near_identity {
+        Some(inv)
+    } else {
+        None
+    }
+}
+
+// The inverse without the checks of inverse(), garbage or non-finite for singular matrices
+pub fn inverse_unchecked(m: Mat4x4) -> Mat4x4 {
+    #[cfg(feature = "simd")]
+    return crate::minivector_simd::inverse(m);
+    #[cfg(not(feature = "simd"))]
+    inverse_scalar(m)
+}
+
+// Identity where inverse() returns None
+pub fn inverse_or_identity(m: Mat4x4) -> Mat4x4 {
+    inverse(m).unwrap_or_else(identity)
+}
+
+// Translation, rotation and per-axis scale of an affine matrix composed like
+// Transform::to_mat4, scale then rotation then translation. A mirroring matrix gets a
+// negative x scale. None for a projection, a zero scale or a shear, which TRS can't express.
+pub fn decompose(m: Mat4x4) -> Option<(Vec3, Quat, Vec3)> {
+    let affine = m.r0.w.abs() <= DECOMPOSE_EPSILON
+        && m.r1.w.abs() <= DECOMPOSE_EPSILON
+        && m.r2.w.abs() <= DECOMPOSE_EPSILON
+        && (m.r3.w - 1.0).abs() <= DECOMPOSE_EPSILON;
+    if !affine {
+        return None;
+    }
+    let (r0, r1, r2) = (m.r0.to_3d(), m.r1.to_3d(), m.r2.to_3d());
+    let mut scale = Vec3 {
+        x: r0.length(),
+        y: r1.length(),
+        z: r2.length(),
+    };
+    let largest = scale.x.max(scale.y).max(scale.z);
+    if !largest.is_finite() || scale.x.min(scale.y).min(scale.z) <= largest * DECOMPOSE_EPSILON {
+        return None;
+    }
+    if r0.cross(r1).dot(r2) < 0.0 {
+        scale.x = -scale.x;
+    }
+    let x_axis = r0 * (1.0 / scale.x);
+    let y_axis = r1 * (1.0 / scale.y);
+    let z_axis = r2 * (1.0 / scale.z);
+
+    // Rotation rows are orthogonal, 1e-3 tolerates the rounding of composed matrices
+    let orthogonal = |a: Vec3, b: Vec3| a.dot(b).abs() <= 1e-3;
+    if !orthogonal(x_axis, y_axis) || !orthogonal(y_axis, z_axis) || !orthogonal(z_axis, x_axis) {
+        return None;
+    }
+    let row = |axis: Vec3| Vec4 {
+        x: axis.x,
+        y: axis.y,
+        z: axis.z,
+        w: 0.0,
+    };
+    let rotation = Quat::from_mat4(Mat4x4 {
+        r0: row(x_axis),
+        r1: row(y_axis),
+        r2: row(z_axis),
+        r3: identity().r3,
+    });
+    Some((m.r3.to_3d(), rotation, scale))
+}
+
+pub fn inverse_scalar(m: Mat4x4) -> Mat4x4 {
     let a2323 = m.r2.z * m.r3.w - m.r2.w * m.r3.z;
     let a1323 = m.r2.y * m.r3.w - m.r2.w * m.r3.y;
     let a1223 = m.r2.y * m.r3.z - m.r2.z * m.r3.y;
@@ -349,6 +704,20 @@ pub fn projection(fovy: f32, aspect: f32, znear: f32, zfar: f32) -> Mat4x4 {
     }
 }
 
+// Reverse depth like projection, width and height are the visible extents in view space
+#[rustfmt::skip]
+pub fn orthographic(width: f32, height: f32, znear: f32, zfar: f32) -> Mat4x4 {
+    let a = -1.0 / (zfar - znear);
+    let b = zfar / (zfar - znear);
+
+    Mat4x4 {
+        r0: Vec4 { x: 2.0 / width, y: 0.0,            z: 0.0, w: 0.0, },
+        r1: Vec4 { x: 0.0,         y: -2.0 / height,  z: 0.0, w: 0.0, },
+        r2: Vec4 { x: 0.0,         y: 0.0,            z: a,   w: 0.0, },
+        r3: Vec4 { x: 0.0,         y: 0.0,            z: b,   w: 1.0, },
+    }
+}
+
 #[rustfmt::skip]
 pub fn translate(position: Vec3) -> Mat4x4 {
     Mat4x4 {
@@ -398,3 +767,1208 @@ pub fn rot_z_axis(r: f32) -> Mat4x4 {
         r3: Vec4 { x: 0.0,      y: 0.0,     z: 0.0, w: 1.0, },
     }
 }
+
+// Double precision vectors and matrices for world positions of large volumes, where f32
+// voxel * spacing products and long transform chains lose precision. Conversions from the
+// f32 types are exact. Values are rounded to f32 once, at the GPU boundary: to_vec3 and
+// to_mat4, or relative_to an origin near the camera so the rounded values stay small.
+#[derive(Clone, Debug, Copy, Default, PartialEq)]
+pub struct DVec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+componentwise_ops!(DVec3, f64, x, y, z);
+
+impl DVec3 {
+    pub const ZERO: DVec3 = DVec3 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn new(x: f64, y: f64, z: f64) -> DVec3 {
+        DVec3 { x, y, z }
+    }
+
+    pub fn from_scalar(v: f64) -> DVec3 {
+        DVec3 { x: v, y: v, z: v }
+    }
+
+    pub fn cross(self, other: DVec3) -> DVec3 {
+        DVec3 {
+            x: (self.y * other.z) - (self.z * other.y),
+            y: (self.z * other.x) - (self.x * other.z),
+            z: (self.x * other.y) - (self.y * other.x),
+        }
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalize(self) -> DVec3 {
+        self * (1.0 / self.length())
+    }
+
+    pub fn to_4d(self) -> DVec4 {
+        DVec4 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            w: 1.0,
+        }
+    }
+
+    // Rounded to the nearest f32
+    pub fn to_vec3(self) -> Vec3 {
+        Vec3 {
+            x: self.x as f32,
+            y: self.y as f32,
+            z: self.z as f32,
+        }
+    }
+
+    // Offset from origin, subtracted before rounding
+    pub fn relative_to(self, origin: DVec3) -> Vec3 {
+        (self - origin).to_vec3()
+    }
+}
+
+impl ops::Neg for DVec3 {
+    type Output = DVec3;
+
+    fn neg(self) -> DVec3 {
+        DVec3 {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl From<Vec3> for DVec3 {
+    fn from(v: Vec3) -> DVec3 {
+        DVec3 {
+            x: v.x as f64,
+            y: v.y as f64,
+            z: v.z as f64,
+        }
+    }
+}
+
+impl From<UVec3> for DVec3 {
+    fn from(v: UVec3) -> DVec3 {
+        DVec3 {
+            x: v.x as f64,
+            y: v.y as f64,
+            z: v.z as f64,
+        }
+    }
+}
+
+impl From<(f32, f32, f32)> for DVec3 {
+    fn from(v: (f32, f32, f32)) -> DVec3 {
+        DVec3 {
+            x: v.0 as f64,
+            y: v.1 as f64,
+            z: v.2 as f64,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Copy, Default, PartialEq)]
+pub struct DVec4 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub w: f64,
+}
+
+componentwise_ops!(DVec4, f64, x, y, z, w);
+
+impl DVec4 {
+    pub fn to_3d(self) -> DVec3 {
+        DVec3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        }
+    }
+
+    pub fn to_vec4(self) -> Vec4 {
+        Vec4 {
+            x: self.x as f32,
+            y: self.y as f32,
+            z: self.z as f32,
+            w: self.w as f32,
+        }
+    }
+}
+
+impl From<Vec4> for DVec4 {
+    fn from(v: Vec4) -> DVec4 {
+        DVec4 {
+            x: v.x as f64,
+            y: v.y as f64,
+            z: v.z as f64,
+            w: v.w as f64,
+        }
+    }
+}
+
+// Row vectors like Mat4x4, a * b applies a first
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct DMat4x4 {
+    pub r0: DVec4,
+    pub r1: DVec4,
+    pub r2: DVec4,
+    pub r3: DVec4,
+}
+
+impl DMat4x4 {
+    #[rustfmt::skip]
+    pub const IDENTITY: DMat4x4 = DMat4x4 {
+        r0: DVec4 { x: 1.0, y: 0.0, z: 0.0, w: 0.0 },
+        r1: DVec4 { x: 0.0, y: 1.0, z: 0.0, w: 0.0 },
+        r2: DVec4 { x: 0.0, y: 0.0, z: 1.0, w: 0.0 },
+        r3: DVec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+    };
+
+    pub fn translation(position: DVec3) -> DMat4x4 {
+        DMat4x4 {
+            r3: position.to_4d(),
+            ..DMat4x4::IDENTITY
+        }
+    }
+
+    #[rustfmt::skip]
+    pub fn scale(v: DVec3) -> DMat4x4 {
+        DMat4x4 {
+            r0: DVec4 { x: v.x, y: 0.0, z: 0.0, w: 0.0 },
+            r1: DVec4 { x: 0.0, y: v.y, z: 0.0, w: 0.0 },
+            r2: DVec4 { x: 0.0, y: 0.0, z: v.z, w: 0.0 },
+            r3: DVec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+        }
+    }
+
+    pub fn transform_point(&self, p: DVec3) -> DVec3 {
+        (p.to_4d() * *self).to_3d()
+    }
+
+    pub fn to_mat4(&self) -> Mat4x4 {
+        Mat4x4 {
+            r0: self.r0.to_vec4(),
+            r1: self.r1.to_vec4(),
+            r2: self.r2.to_vec4(),
+            r3: self.r3.to_vec4(),
+        }
+    }
+
+    // The transform followed by a translation by -origin, in f32. The translation row
+    // of a far away object becomes small before it is rounded.
+    pub fn relative_to(&self, origin: DVec3) -> Mat4x4 {
+        (*self * DMat4x4::translation(-origin)).to_mat4()
+    }
+}
+
+impl ops::Mul<DMat4x4> for DVec4 {
+    type Output = DVec4;
+
+    fn mul(self, m: DMat4x4) -> DVec4 {
+        m.r0 * self.x + m.r1 * self.y + m.r2 * self.z + m.r3 * self.w
+    }
+}
+
+impl ops::Mul<DMat4x4> for DMat4x4 {
+    type Output = DMat4x4;
+
+    fn mul(self, _rhs: DMat4x4) -> DMat4x4 {
+        DMat4x4 {
+            r0: self.r0 * _rhs,
+            r1: self.r1 * _rhs,
+            r2: self.r2 * _rhs,
+            r3: self.r3 * _rhs,
+        }
+    }
+}
+
+impl From<Mat4x4> for DMat4x4 {
+    fn from(m: Mat4x4) -> DMat4x4 {
+        DMat4x4 {
+            r0: m.r0.into(),
+            r1: m.r1.into(),
+            r2: m.r2.into(),
+            r3: m.r3.into(),
+        }
+    }
+}
+
+// Rotation and scale part of a transform, row vectors like Mat4x4
+#[derive(Clone, Debug, Copy, PartialEq)]
+#[repr(C)]
+pub struct Mat3x3 {
+    pub r0: Vec3,
+    pub r1: Vec3,
+    pub r2: Vec3,
+}
+
+impl Mat3x3 {
+    #[rustfmt::skip]
+    pub const IDENTITY: Mat3x3 = Mat3x3 {
+        r0: Vec3 { x: 1.0, y: 0.0, z: 0.0 },
+        r1: Vec3 { x: 0.0, y: 1.0, z: 0.0 },
+        r2: Vec3 { x: 0.0, y: 0.0, z: 1.0 },
+    };
+
+    pub fn transpose(self) -> Mat3x3 {
+        Mat3x3 {
+            r0: Vec3 {
+                x: self.r0.x,
+                y: self.r1.x,
+                z: self.r2.x,
+            },
+            r1: Vec3 {
+                x: self.r0.y,
+                y: self.r1.y,
+                z: self.r2.y,
+            },
+            r2: Vec3 {
+                x: self.r0.z,
+                y: self.r1.z,
+                z: self.r2.z,
+            },
+        }
+    }
+
+    pub fn determinant(self) -> f32 {
+        self.r0.dot(self.r1.cross(self.r2))
+    }
+
+    // The cross products of the rows are the columns of the adjugate
+    pub fn inverse(self) -> Mat3x3 {
+        let det = 1.0 / self.determinant();
+        Mat3x3 {
+            r0: self.r1.cross(self.r2) * det,
+            r1: self.r2.cross(self.r0) * det,
+            r2: self.r0.cross(self.r1) * det,
+        }
+        .transpose()
+    }
+
+    pub fn to_mat4(self) -> Mat4x4 {
+        Mat4x4 {
+            r0: self.r0.to_4d(),
+            r1: self.r1.to_4d(),
+            r2: self.r2.to_4d(),
+            r3: Vec4 {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                w: 1.0,
+            },
+        }
+    }
+
+    // std140/std430 mat3 layout, every row padded to a vec4. The rows are the GLSL columns,
+    // so `m * v` in a shader matches v * m here like for the Mat4x4 uniforms.
+    pub fn to_std140(self) -> [Vec4; 3] {
+        [self.r0.to_4d(), self.r1.to_4d(), self.r2.to_4d()]
+    }
+}
+
+impl ops::Mul<Mat3x3> for Mat3x3 {
+    type Output = Mat3x3;
+
+    fn mul(self, _rhs: Mat3x3) -> Mat3x3 {
+        Mat3x3 {
+            r0: self.r0 * _rhs,
+            r1: self.r1 * _rhs,
+            r2: self.r2 * _rhs,
+        }
+    }
+}
+
+impl ops::Mul<Mat3x3> for Vec3 {
+    type Output = Vec3;
+
+    fn mul(self, _rhs: Mat3x3) -> Vec3 {
+        _rhs.r0 * self.x + _rhs.r1 * self.y + _rhs.r2 * self.z
+    }
+}
+
+impl Mat4x4 {
+    // View matrix of a camera at eye looking at target, view() takes the direction instead
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Mat4x4 {
+        view(eye, target - eye, up)
+    }
+
+    // Upper left 3x3, drops the translation
+    pub fn to_mat3(self) -> Mat3x3 {
+        Mat3x3 {
+            r0: self.r0.to_3d(),
+            r1: self.r1.to_3d(),
+            r2: self.r2.to_3d(),
+        }
+    }
+}
+
+// Transforms normals of the space m transforms points of, the inverse transpose of its 3x3
+// part. Equals the 3x3 part for rotations, renormalize after scales.
+pub fn normal_matrix(m: Mat4x4) -> Mat3x3 {
+    m.to_mat3().inverse().transpose()
+}
+
+// Unit quaternion rotation. Rotates row vectors the same way as the matrix of to_mat4,
+// v * q.to_mat4() == q.rotate_vec3(v), and a * b applies b first like b.to_mat4() * a.to_mat4().
+#[derive(Clone, Debug, Copy, PartialEq)]
+#[repr(C)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        w: 1.0,
+    };
+
+    // Right handed rotation by angle radians around axis
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Quat {
+        let axis = axis.normalize();
+        let (sin, cos) = (angle * 0.5).sin_cos();
+        Quat {
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+            w: cos,
+        }
+    }
+
+    // Rotation taking +Z to forward and +Y towards up, the camera orientation of view()
+    #[rustfmt::skip]
+    pub fn look_rotation(forward: Vec3, up: Vec3) -> Quat {
+        let forward = forward.normalize();
+        let right = up.cross(forward).normalize();
+        let up = forward.cross(right);
+        Quat::from_mat4(Mat4x4 {
+            r0: Vec4 { x: right.x,   y: right.y,   z: right.z,   w: 0.0, },
+            r1: Vec4 { x: up.x,      y: up.y,      z: up.z,      w: 0.0, },
+            r2: Vec4 { x: forward.x, y: forward.y, z: forward.z, w: 0.0, },
+            r3: Vec4 { x: 0.0,       y: 0.0,       z: 0.0,       w: 1.0, },
+        })
+    }
+
+    // Rotation part of m, which must not contain scale or shear
+    pub fn from_mat4(m: Mat4x4) -> Quat {
+        let trace = m.r0.x + m.r1.y + m.r2.z;
+        // Divides by the largest component for precision
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quat {
+                x: (m.r1.z - m.r2.y) / s,
+                y: (m.r2.x - m.r0.z) / s,
+                z: (m.r0.y - m.r1.x) / s,
+                w: 0.25 * s,
+            }
+        } else if m.r0.x > m.r1.y && m.r0.x > m.r2.z {
+            let s = (1.0 + m.r0.x - m.r1.y - m.r2.z).sqrt() * 2.0;
+            Quat {
+                x: 0.25 * s,
+                y: (m.r1.x + m.r0.y) / s,
+                z: (m.r2.x + m.r0.z) / s,
+                w: (m.r1.z - m.r2.y) / s,
+            }
+        } else if m.r1.y > m.r2.z {
+            let s = (1.0 + m.r1.y - m.r0.x - m.r2.z).sqrt() * 2.0;
+            Quat {
+                x: (m.r1.x + m.r0.y) / s,
+                y: 0.25 * s,
+                z: (m.r2.y + m.r1.z) / s,
+                w: (m.r2.x - m.r0.z) / s,
+            }
+        } else {
+            let s = (1.0 + m.r2.z - m.r0.x - m.r1.y).sqrt() * 2.0;
+            Quat {
+                x: (m.r2.x + m.r0.z) / s,
+                y: (m.r2.y + m.r1.z) / s,
+                z: 0.25 * s,
+                w: (m.r0.y - m.r1.x) / s,
+            }
+        };
+        q.normalize()
+    }
+
+    #[rustfmt::skip]
+    pub fn to_mat4(self) -> Mat4x4 {
+        let Quat { x, y, z, w } = self;
+        Mat4x4 {
+            r0: Vec4 {
+                x: 1.0 - 2.0 * (y * y + z * z),
+                y: 2.0 * (x * y + w * z),
+                z: 2.0 * (x * z - w * y),
+                w: 0.0,
+            },
+            r1: Vec4 {
+                x: 2.0 * (x * y - w * z),
+                y: 1.0 - 2.0 * (x * x + z * z),
+                z: 2.0 * (y * z + w * x),
+                w: 0.0,
+            },
+            r2: Vec4 {
+                x: 2.0 * (x * z + w * y),
+                y: 2.0 * (y * z - w * x),
+                z: 1.0 - 2.0 * (x * x + y * y),
+                w: 0.0,
+            },
+            r3: Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0, },
+        }
+    }
+
+    // Camera orientation without roll: yaw around the world up axis (+Y), then pitch around
+    // the camera right axis (+X). Positive pitch looks down, yaw 0 looks along +Z.
+    pub fn from_yaw_pitch(yaw: f32, pitch: f32) -> Quat {
+        let yaw_rot = Quat::from_axis_angle(
+            Vec3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            yaw,
+        );
+        let pitch_rot = Quat::from_axis_angle(
+            Vec3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            pitch,
+        );
+        yaw_rot * pitch_rot
+    }
+
+    // (yaw, pitch) of the forward direction, from_yaw_pitch gives back the rotation when it
+    // has no roll. Yaw is in -PI..PI, pitch in -PI/2..PI/2.
+    pub fn yaw_pitch(self) -> (f32, f32) {
+        let forward = self.rotate_vec3(Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        });
+        let yaw = forward.x.atan2(forward.z);
+        let pitch = (-forward.y).clamp(-1.0, 1.0).asin();
+        (yaw, pitch)
+    }
+
+    // Mouse look step of a camera controller. The angles are added to the yaw and pitch of
+    // the orientation instead of accumulating rotations, so the horizon stays level and the
+    // pitch stops at PITCH_LIMIT instead of flipping over the pole.
+    pub fn add_yaw_pitch(self, yaw: f32, pitch: f32) -> Quat {
+        let (old_yaw, old_pitch) = self.yaw_pitch();
+        Quat::from_yaw_pitch(wrap_angle(old_yaw + yaw), clamp_pitch(old_pitch + pitch))
+    }
+
+    pub fn rotate_vec3(self, v: Vec3) -> Vec3 {
+        let axis = Vec3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        };
+        let t = axis.cross(v) * 2.0;
+        v + t * self.w + axis.cross(t)
+    }
+
+    pub fn dot(self, other: Quat) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    // Renormalized after accumulating rotations, so that rounding errors don't build up
+    pub fn normalize(self) -> Quat {
+        let l_inv = 1.0 / self.dot(self).sqrt();
+        Quat {
+            x: self.x * l_inv,
+            y: self.y * l_inv,
+            z: self.z * l_inv,
+            w: self.w * l_inv,
+        }
+    }
+
+    pub fn conjugate(self) -> Quat {
+        Quat {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+            w: self.w,
+        }
+    }
+
+    // Rotation by the angular velocity v, |v| radians around v's direction (the quaternion
+    // exponential). Zero for the identity.
+    pub fn from_rotation_vector(v: Vec3) -> Quat {
+        let angle = v.length();
+        if angle < 1e-12 {
+            return Quat::IDENTITY;
+        }
+        Quat::from_axis_angle(v * (1.0 / angle), angle)
+    }
+
+    // Normalized lerp along the shorter arc. Cheaper than slerp, the speed varies a little.
+    pub fn nlerp(self, other: Quat, t: f32) -> Quat {
+        let other = if self.dot(other) < 0.0 {
+            other.negate()
+        } else {
+            other
+        };
+        Quat {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            z: self.z + (other.z - self.z) * t,
+            w: self.w + (other.w - self.w) * t,
+        }
+        .normalize()
+    }
+
+    // Cubic Hermite curve from q0 to q1 with world space angular velocities m0 and m1 (radians
+    // per unit of t) at the ends. Evaluated as the equivalent Bezier curve with slerps.
+    pub fn hermite(q0: Quat, m0: Vec3, q1: Quat, m1: Vec3, t: f32) -> Quat {
+        let a = Quat::from_rotation_vector(m0 * (1.0 / 3.0)) * q0;
+        let b = Quat::from_rotation_vector(m1 * (-1.0 / 3.0)) * q1;
+        let (ab0, ab1, ab2) = (q0.slerp(a, t), a.slerp(b, t), b.slerp(q1, t));
+        ab0.slerp(ab1, t).slerp(ab1.slerp(ab2, t), t)
+    }
+
+    // Uniform Catmull-Rom spline through q1 (t = 0) and q2 (t = 1), q0 and q3 are the
+    // neighboring keys. Barry-Goldman pyramid with slerp in place of lerp.
+    pub fn catmull_rom(q0: Quat, q1: Quat, q2: Quat, q3: Quat, t: f32) -> Quat {
+        let a1 = q0.slerp(q1, t + 1.0);
+        let a2 = q1.slerp(q2, t);
+        let a3 = q2.slerp(q3, t - 1.0);
+        let b1 = a1.slerp(a2, (t + 1.0) * 0.5);
+        let b2 = a2.slerp(a3, t * 0.5);
+        b1.slerp(b2, t)
+    }
+
+    fn negate(self) -> Quat {
+        Quat {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+            w: -self.w,
+        }
+    }
+
+    // Constant angular speed along the shorter arc, t = 0 is self and t = 1 is other
+    pub fn slerp(self, other: Quat, t: f32) -> Quat {
+        let mut cos_angle = self.dot(other);
+        let other = if cos_angle < 0.0 {
+            cos_angle = -cos_angle;
+            other.negate()
+        } else {
+            other
+        };
+        // Nearly equal rotations fall back to normalized lerp, sin(angle) goes to zero
+        let (a, b) = if cos_angle > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = cos_angle.acos();
+            let sin_inv = 1.0 / angle.sin();
+            (
+                ((1.0 - t) * angle).sin() * sin_inv,
+                (t * angle).sin() * sin_inv,
+            )
+        };
+        Quat {
+            x: self.x * a + other.x * b,
+            y: self.y * a + other.y * b,
+            z: self.z * a + other.z * b,
+            w: self.w * a + other.w * b,
+        }
+        .normalize()
+    }
+}
+
+// Just short of straight up or down, where yaw is undefined
+pub const PITCH_LIMIT: f32 = std::f32::consts::FRAC_PI_2 - 0.001;
+
+pub fn clamp_pitch(pitch: f32) -> f32 {
+    pitch.clamp(-PITCH_LIMIT, PITCH_LIMIT)
+}
+
+// Angle in radians wrapped to -PI..PI
+pub fn wrap_angle(angle: f32) -> f32 {
+    use std::f32::consts::PI;
+    (angle + PI).rem_euclid(2.0 * PI) - PI
+}
+
+pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// 0 below edge0, 1 above edge1 and a smooth cubic between, like GLSL smoothstep
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Cubic Hermite curve from p0 to p1 with tangents m0 and m1 (per unit of t) at the ends
+pub fn hermite(p0: Vec3, m0: Vec3, p1: Vec3, m1: Vec3, t: f32) -> Vec3 {
+    let (t2, t3) = (t * t, t * t * t);
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + m0 * (t3 - 2.0 * t2 + t)
+        + p1 * (-2.0 * t3 + 3.0 * t2)
+        + m1 * (t3 - t2)
+}
+
+// Uniform Catmull-Rom spline through p1 (t = 0) and p2 (t = 1), the Hermite curve with the
+// tangents of the neighboring keys p0 and p3
+pub fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    hermite(p1, (p2 - p0) * 0.5, p2, (p3 - p1) * 0.5, t)
+}
+
+impl ops::Mul<Quat> for Quat {
+    type Output = Quat;
+
+    fn mul(self, _rhs: Quat) -> Quat {
+        Quat {
+            x: self.w * _rhs.x + self.x * _rhs.w + self.y * _rhs.z - self.z * _rhs.y,
+            y: self.w * _rhs.y - self.x * _rhs.z + self.y * _rhs.w + self.z * _rhs.x,
+            z: self.w * _rhs.z + self.x * _rhs.y - self.y * _rhs.x + self.z * _rhs.w,
+            w: self.w * _rhs.w - self.x * _rhs.x - self.y * _rhs.y - self.z * _rhs.z,
+        }
+    }
+}
+
+impl From<Quat> for Mat4x4 {
+    fn from(q: Quat) -> Mat4x4 {
+        q.to_mat4()
+    }
+}
+
+impl From<Mat4x4> for Quat {
+    fn from(m: Mat4x4) -> Quat {
+        Quat::from_mat4(m)
+    }
+}
+
+// Placement that scales, then rotates, then translates. The scale is uniform so that
+// composed transforms stay a scale, rotation and translation. parent * child places child
+// inside parent: (parent * child).to_mat4() == child.to_mat4() * parent.to_mat4().
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        rotation: Quat::IDENTITY,
+        scale: 1.0,
+    };
+
+    pub fn new(translation: Vec3, rotation: Quat, scale: f32) -> Transform {
+        Transform {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Transform::IDENTITY
+        }
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        self.rotation.rotate_vec3(p * self.scale) + self.translation
+    }
+
+    // Directions and offsets, without the translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        self.rotation.rotate_vec3(v * self.scale)
+    }
+
+    pub fn to_mat4(&self) -> Mat4x4 {
+        let m = self.rotation.to_mat4();
+        let scaled = |r: Vec4| Vec4 {
+            x: r.x * self.scale,
+            y: r.y * self.scale,
+            z: r.z * self.scale,
+            w: 0.0,
+        };
+        Mat4x4 {
+            r0: scaled(m.r0),
+            r1: scaled(m.r1),
+            r2: scaled(m.r2),
+            r3: Vec4 {
+                x: self.translation.x,
+                y: self.translation.y,
+                z: self.translation.z,
+                w: 1.0,
+            },
+        }
+    }
+
+    // Exact for a non-zero scale, unlike inverting the matrix
+    pub fn inverse(&self) -> Transform {
+        let rotation = self.rotation.conjugate();
+        let scale = 1.0 / self.scale;
+        Transform {
+            translation: rotation.rotate_vec3(self.translation) * -scale,
+            rotation,
+            scale,
+        }
+    }
+}
+
+impl ops::Mul<Transform> for Transform {
+    type Output = Transform;
+
+    fn mul(self, _rhs: Transform) -> Transform {
+        Transform {
+            translation: self.transform_point(_rhs.translation),
+            rotation: self.rotation * _rhs.rotation,
+            scale: self.scale * _rhs.scale,
+        }
+    }
+}
+
+impl From<Transform> for Mat4x4 {
+    fn from(t: Transform) -> Mat4x4 {
+        t.to_mat4()
+    }
+}
+
+// Axis aligned box, min <= max on every axis for a non-empty box
+#[derive(Clone, Debug, Copy, PartialEq)]
+#[repr(C)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+// Where a ray enters and leaves a box, in units of the ray direction. t_enter is negative
+// when the origin is inside. normal is the outward normal of the entry face.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct AabbRayHit {
+    pub t_enter: f32,
+    pub t_exit: f32,
+    pub normal: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    pub fn from_center_half_size(center: Vec3, half_size: Vec3) -> Aabb {
+        Aabb {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn size(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        point.x >= self.min.x
+            && point.y >= self.min.y
+            && point.z >= self.min.z
+            && point.x <= self.max.x
+            && point.y <= self.max.y
+            && point.z <= self.max.z
+    }
+
+    // Boxes that only touch count as intersecting
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.min.y <= other.max.y
+            && self.min.z <= other.max.z
+            && other.min.x <= self.max.x
+            && other.min.y <= self.max.y
+            && other.min.z <= self.max.z
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    #[rustfmt::skip]
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vec3 { x: a.x, y: a.y, z: a.z },
+            Vec3 { x: b.x, y: a.y, z: a.z },
+            Vec3 { x: a.x, y: b.y, z: a.z },
+            Vec3 { x: b.x, y: b.y, z: a.z },
+            Vec3 { x: a.x, y: a.y, z: b.z },
+            Vec3 { x: b.x, y: a.y, z: b.z },
+            Vec3 { x: a.x, y: b.y, z: b.z },
+            Vec3 { x: b.x, y: b.y, z: b.z },
+        ]
+    }
+
+    // Screen area the box covers, in NDC clamped to the screen. None when the box is
+    // outside the view frustum. Boxes crossing the camera plane of a perspective
+    // projection cover the whole screen, their corners behind the camera don't project.
+    pub fn screen_rect(&self, world_to_screen: &Mat4x4) -> Option<ScreenRect> {
+        if !Frustum::from_matrix(world_to_screen).intersects_aabb(self) {
+            return None;
+        }
+        let mut points = Vec::with_capacity(8);
+        for corner in self.corners().iter() {
+            let clip = corner.to_4d() * *world_to_screen;
+            if clip.w <= 0.0 {
+                return Some(ScreenRect::FULL);
+            }
+            points.push(Vec2 {
+                x: clip.x / clip.w,
+                y: clip.y / clip.w,
+            });
+        }
+        ScreenRect::from_points(&points).intersection(&ScreenRect::FULL)
+    }
+
+    pub fn bounding_sphere(&self) -> Sphere {
+        Sphere {
+            center: self.center(),
+            radius: self.size().length() * 0.5,
+        }
+    }
+
+    // Slab test, None when the ray misses or the box is behind the origin. Zero direction
+    // components give infinite slab distances, which the comparisons handle.
+    pub fn intersect_ray(&self, origin: Vec3, direction: Vec3) -> Option<AabbRayHit> {
+        let origin = [origin.x, origin.y, origin.z];
+        let direction = [direction.x, direction.y, direction.z];
+        let min = [self.min.x, self.min.y, self.min.z];
+        let max = [self.max.x, self.max.y, self.max.z];
+        let mut t_enter = f32::MIN;
+        let mut t_exit = f32::MAX;
+        let mut enter_axis = 0;
+        for axis in 0..3 {
+            let t0 = (min[axis] - origin[axis]) / direction[axis];
+            let t1 = (max[axis] - origin[axis]) / direction[axis];
+            let (t_near, t_far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            if t_near > t_enter {
+                t_enter = t_near;
+                enter_axis = axis;
+            }
+            t_exit = t_exit.min(t_far);
+        }
+        if t_enter > t_exit || t_exit < 0.0 {
+            return None;
+        }
+        let mut normal = [0.0; 3];
+        normal[enter_axis] = -direction[enter_axis].signum();
+        Some(AabbRayHit {
+            t_enter,
+            t_exit,
+            normal: Vec3 {
+                x: normal[0],
+                y: normal[1],
+                z: normal[2],
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq)]
+#[repr(C)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32) -> Sphere {
+        Sphere { center, radius }
+    }
+
+    pub fn contains(&self, point: Vec3) -> bool {
+        (point - self.center).length() <= self.radius
+    }
+
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        (other.center - self.center).length() <= self.radius + other.radius
+    }
+
+    // Frustum planes as (normal, offset) with unit normals pointing inside, a point p is
+    // inside a plane when normal.dot(p) + offset >= 0. Conservative near the frustum edges,
+    // spheres outside two planes at once but none alone still pass.
+    pub fn intersects_frustum(&self, planes: &[Vec4]) -> bool {
+        planes
+            .iter()
+            .all(|plane| plane.to_3d().dot(self.center) + plane.w >= -self.radius)
+    }
+}
+
+// View frustum of a world_to_screen matrix, planes in the form intersects_frustum takes
+#[derive(Clone, Debug, Copy)]
+pub struct Frustum {
+    pub planes: [Vec4; 6], // Left, right, bottom, top, near, far
+}
+
+impl Frustum {
+    // Clip space of projection and orthographic: -w <= x, y <= w and reverse depth 0 <= z <= w,
+    // so the near plane is z <= w and the far plane z >= 0. With standard depth
+    // matrices the two swap places, the frustum is the same.
+    pub fn from_matrix(world_to_screen: &Mat4x4) -> Frustum {
+        let m = world_to_screen;
+        let column = |c: fn(&Vec4) -> f32| [c(&m.r0), c(&m.r1), c(&m.r2), c(&m.r3)];
+        let (x, y, z, w) = (
+            column(|r| r.x),
+            column(|r| r.y),
+            column(|r| r.z),
+            column(|r| r.w),
+        );
+        let normalize = |p: [f32; 4]| {
+            let scale = 1.0 / (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+            Vec4 {
+                x: p[0] * scale,
+                y: p[1] * scale,
+                z: p[2] * scale,
+                w: p[3] * scale,
+            }
+        };
+        let plane =
+            |sign: f32, axis: [f32; 4]| normalize([0, 1, 2, 3].map(|i| w[i] + sign * axis[i]));
+        Frustum {
+            planes: [
+                plane(1.0, x),
+                plane(-1.0, x),
+                plane(1.0, y),
+                plane(-1.0, y),
+                plane(-1.0, z),
+                normalize(z),
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.to_3d().dot(point) + plane.w >= 0.0)
+    }
+
+    // Tests the corner furthest along each plane normal. Conservative like intersects_frustum,
+    // boxes near the frustum edges can pass without touching it.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let corner = Vec3 {
+                x: if plane.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                y: if plane.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                z: if plane.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            };
+            plane.to_3d().dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        sphere.intersects_frustum(&self.planes)
+    }
+}
+
+// Axis aligned rectangle in normalized device coordinates, -1..1 with y down like the
+// viewport. Rectangles that only touch count as intersecting.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct ScreenRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl ScreenRect {
+    pub const FULL: ScreenRect = ScreenRect {
+        min: Vec2 { x: -1.0, y: -1.0 },
+        max: Vec2 { x: 1.0, y: 1.0 },
+    };
+
+    // Rectangle spanned by two corners in any order, like the ends of a mouse drag
+    pub fn from_corners(a: Vec2, b: Vec2) -> ScreenRect {
+        ScreenRect {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    // Pixel rectangle of a viewport of the given size to NDC
+    pub fn from_pixels(a: (i32, i32), b: (i32, i32), width: u32, height: u32) -> ScreenRect {
+        let ndc = |p: (i32, i32)| Vec2 {
+            x: p.0 as f32 / width.max(1) as f32 * 2.0 - 1.0,
+            y: p.1 as f32 / height.max(1) as f32 * 2.0 - 1.0,
+        };
+        ScreenRect::from_corners(ndc(a), ndc(b))
+    }
+
+    pub fn from_points(points: &[Vec2]) -> ScreenRect {
+        let mut rect = ScreenRect {
+            min: Vec2::from_scalar(f32::MAX),
+            max: Vec2::from_scalar(f32::MIN),
+        };
+        for &p in points {
+            rect.min = rect.min.min(p);
+            rect.max = rect.max.max(p);
+        }
+        rect
+    }
+
+    pub fn intersects(&self, other: &ScreenRect) -> bool {
+        self.min.x <= other.max.x
+            && self.min.y <= other.max.y
+            && other.min.x <= self.max.x
+            && other.min.y <= self.max.y
+    }
+
+    pub fn contains(&self, other: &ScreenRect) -> bool {
+        self.min.x <= other.min.x
+            && self.min.y <= other.min.y
+            && other.max.x <= self.max.x
+            && other.max.y <= self.max.y
+    }
+
+    // None when the rectangles don't overlap
+    pub fn intersection(&self, other: &ScreenRect) -> Option<ScreenRect> {
+        if !self.intersects(other) {
+            return None;
+        }
+        Some(ScreenRect {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        })
+    }
+}
+
+// Half line from origin along dir. Distances t are in units of dir, which from_screen and
+// new keep at unit length.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Ray {
+        Ray {
+            origin,
+            dir: dir.normalize(),
+        }
+    }
+
+    // Ray through a point of the screen, ndc in -1..1 with y down like the viewport. Starts on
+    // the near plane, works for perspective and orthographic world_to_screen matrices.
+    // near_depth and far_depth are the depths of the two planes, 1 and 0 for reversed depth.
+    pub fn from_screen(
+        world_to_screen: &Mat4x4,
+        ndc: Vec2,
+        near_depth: f32,
+        far_depth: f32,
+    ) -> Ray {
+        // Unchecked, extreme near/far ratios still give a usable direction
+        let screen_to_world = inverse_unchecked(*world_to_screen);
+        let unproject = |depth: f32| {
+            let p = Vec4 {
+                x: ndc.x,
+                y: ndc.y,
+                z: depth,
+                w: 1.0,
+            } * screen_to_world;
+            p.to_3d() * (1.0 / p.w)
+        };
+        let near = unproject(near_depth);
+        let far = unproject(far_depth);
+        Ray::new(near, far - near)
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<AabbRayHit> {
+        aabb.intersect_ray(self.origin, self.dir)
+    }
+
+    // Sphere tracing of a signed distance function up to t_max, distance is in world units.
+    // Returns the t of the first point closer to the surface than epsilon.
+    pub fn intersect_sdf<F: FnMut(Vec3) -> f32>(
+        &self,
+        mut distance: F,
+        t_max: f32,
+        epsilon: f32,
+        max_steps: u32,
+    ) -> Option<f32> {
+        let mut t = 0.0;
+        for _ in 0..max_steps {
+            let d = distance(self.at(t));
+            if d < epsilon {
+                return Some(t);
+            }
+            t += d;
+            if t > t_max {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+// Plain f32 fields without padding, can be copied to GPU buffers as is
+unsafe impl bytemuck::Zeroable for Vec3 {}
+unsafe impl bytemuck::Pod for Vec3 {}
+unsafe impl bytemuck::Zeroable for Vec4 {}
+unsafe impl bytemuck::Pod for Vec4 {}
+unsafe impl bytemuck::Zeroable for Mat4x4 {}
+unsafe impl bytemuck::Pod for Mat4x4 {}
+unsafe impl bytemuck::Zeroable for Mat3x3 {}
+unsafe impl bytemuck::Pod for Mat3x3 {}
+unsafe impl bytemuck::Zeroable for Aabb {}
+unsafe impl bytemuck::Pod for Aabb {}
+unsafe impl bytemuck::Zeroable for Sphere {}
+unsafe impl bytemuck::Pod for Sphere {}
+unsafe impl bytemuck::Zeroable for Quat {}
+unsafe impl bytemuck::Pod for Quat {}
+unsafe impl bytemuck::Zeroable for Vec2 {}
+unsafe impl bytemuck::Pod for Vec2 {}
+unsafe impl bytemuck::Zeroable for IVec3 {}
+unsafe impl bytemuck::Pod for IVec3 {}
+unsafe impl bytemuck::Zeroable for UVec3 {}
+unsafe impl bytemuck::Pod for UVec3 {}
@@ -0,0 +1,213 @@
+// SSE (x86_64) and NEON (aarch64) versions of the Mat4x4 and Vec4 operations of minivector,
+// which its operators and inverse() use when the crate is built with the simd feature. Both
+// instruction sets are part of the base target, so there is no runtime detection. Vec4 and
+// the rows of Mat4x4 are four packed f32 and load as one register.
+//
+// The products add in the same order as the scalar versions and match them exactly. The
+// inverse uses the cross product form of Cramer's rule and differs from inverse_scalar by
+// rounding. NEON has no inverse yet, aarch64 and the other targets use the scalar version.
+
+use crate::minivector::*;
+
+pub fn mul_mat4(a: Mat4x4, b: Mat4x4) -> Mat4x4 {
+    #[cfg(target_arch = "x86_64")]
+    return unsafe { sse::mul_mat4(&a, &b) };
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { neon::mul_mat4(&a, &b) };
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    mul_mat4_scalar(a, b)
+}
+
+pub fn mul_vec4_mat4(v: Vec4, m: Mat4x4) -> Vec4 {
+    #[cfg(target_arch = "x86_64")]
+    return unsafe { sse::mul_vec4_mat4(&v, &m) };
+    #[cfg(target_arch = "aarch64")]
+    return unsafe { neon::mul_vec4_mat4(&v, &m) };
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    mul_vec4_mat4_scalar(v, m)
+}
+
+pub fn inverse(m: Mat4x4) -> Mat4x4 {
+    #[cfg(target_arch = "x86_64")]
+    return unsafe { sse::inverse(&m) };
+    #[cfg(not(target_arch = "x86_64"))]
+    inverse_scalar(m)
+}
+
+// Inverse from the rows of the inverse of the transpose
+#[cfg(target_arch = "x86_64")]
+fn transpose(rows: [[f32; 4]; 4]) -> Mat4x4 {
+    let column = |i: usize| Vec4 {
+        x: rows[0][i],
+        y: rows[1][i],
+        z: rows[2][i],
+        w: rows[3][i],
+    };
+    Mat4x4 {
+        r0: column(0),
+        r1: column(1),
+        r2: column(2),
+        r3: column(3),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse {
+    use super::transpose;
+    use crate::minivector::*;
+
+    use std::arch::x86_64::*;
+
+    // (y, z, x, w) of a register
+    const YZXW: i32 = 0b11_00_10_01;
+
+    #[inline(always)]
+    unsafe fn load(v: &Vec4) -> __m128 {
+        _mm_loadu_ps(v as *const Vec4 as *const f32)
+    }
+
+    #[inline(always)]
+    unsafe fn store(v: __m128) -> [f32; 4] {
+        let mut out = [0.0f32; 4];
+        _mm_storeu_ps(out.as_mut_ptr(), v);
+        out
+    }
+
+    // Rows of m weighted by the components of v
+    #[inline(always)]
+    unsafe fn transform(v: &Vec4, m: &[__m128; 4]) -> __m128 {
+        let x = _mm_mul_ps(_mm_set1_ps(v.x), m[0]);
+        let y = _mm_mul_ps(_mm_set1_ps(v.y), m[1]);
+        let z = _mm_mul_ps(_mm_set1_ps(v.z), m[2]);
+        let w = _mm_mul_ps(_mm_set1_ps(v.w), m[3]);
+        _mm_add_ps(_mm_add_ps(_mm_add_ps(x, y), z), w)
+    }
+
+    #[inline(always)]
+    unsafe fn rows(m: &Mat4x4) -> [__m128; 4] {
+        [load(&m.r0), load(&m.r1), load(&m.r2), load(&m.r3)]
+    }
+
+    fn to_vec4(v: [f32; 4]) -> Vec4 {
+        Vec4 {
+            x: v[0],
+            y: v[1],
+            z: v[2],
+            w: v[3],
+        }
+    }
+
+    pub unsafe fn mul_mat4(a: &Mat4x4, b: &Mat4x4) -> Mat4x4 {
+        let b = rows(b);
+        Mat4x4 {
+            r0: to_vec4(store(transform(&a.r0, &b))),
+            r1: to_vec4(store(transform(&a.r1, &b))),
+            r2: to_vec4(store(transform(&a.r2, &b))),
+            r3: to_vec4(store(transform(&a.r3, &b))),
+        }
+    }
+
+    pub unsafe fn mul_vec4_mat4(v: &Vec4, m: &Mat4x4) -> Vec4 {
+        to_vec4(store(transform(v, &rows(m))))
+    }
+
+    // xyz of p x q, w is undefined
+    #[inline(always)]
+    unsafe fn cross(p: __m128, q: __m128) -> __m128 {
+        let p_yzx = _mm_shuffle_ps::<YZXW>(p, p);
+        let q_yzx = _mm_shuffle_ps::<YZXW>(q, q);
+        let c = _mm_sub_ps(_mm_mul_ps(p, q_yzx), _mm_mul_ps(p_yzx, q));
+        _mm_shuffle_ps::<YZXW>(c, c)
+    }
+
+    #[inline(always)]
+    unsafe fn dot3(p: __m128, q: __m128) -> f32 {
+        let d = store(_mm_mul_ps(p, q));
+        d[0] + d[1] + d[2]
+    }
+
+    #[inline(always)]
+    unsafe fn scale(v: __m128, s: f32) -> __m128 {
+        _mm_mul_ps(v, _mm_set1_ps(s))
+    }
+
+    // The rows of m are the columns (a, b, c, d) of its transpose, their w components the
+    // bottom row (x, y, z, w)
+    pub unsafe fn inverse(m: &Mat4x4) -> Mat4x4 {
+        let [a, b, c, d] = rows(m);
+        let (x, y, z, w) = (m.r0.w, m.r1.w, m.r2.w, m.r3.w);
+
+        let s = cross(a, b);
+        let t = cross(c, d);
+        let u = _mm_sub_ps(scale(a, y), scale(b, x));
+        let v = _mm_sub_ps(scale(c, w), scale(d, z));
+
+        let det_inv = 1.0 / (dot3(s, v) + dot3(t, u));
+        let (s, t) = (scale(s, det_inv), scale(t, det_inv));
+        let (u, v) = (scale(u, det_inv), scale(v, det_inv));
+
+        let mut r0 = store(_mm_add_ps(cross(b, v), scale(t, y)));
+        let mut r1 = store(_mm_sub_ps(cross(v, a), scale(t, x)));
+        let mut r2 = store(_mm_add_ps(cross(d, u), scale(s, w)));
+        let mut r3 = store(_mm_sub_ps(cross(u, c), scale(s, z)));
+        r0[3] = -dot3(b, t);
+        r1[3] = dot3(a, t);
+        r2[3] = -dot3(d, s);
+        r3[3] = dot3(c, s);
+        transpose([r0, r1, r2, r3])
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use crate::minivector::*;
+
+    use std::arch::aarch64::*;
+
+    #[inline(always)]
+    unsafe fn load(v: &Vec4) -> float32x4_t {
+        vld1q_f32(v as *const Vec4 as *const f32)
+    }
+
+    #[inline(always)]
+    unsafe fn store(v: float32x4_t) -> Vec4 {
+        let mut out = [0.0f32; 4];
+        vst1q_f32(out.as_mut_ptr(), v);
+        Vec4 {
+            x: out[0],
+            y: out[1],
+            z: out[2],
+            w: out[3],
+        }
+    }
+
+    // Rows of m weighted by the components of v. Separate multiplies and adds instead of
+    // fused ones, so the result matches the scalar version.
+    #[inline(always)]
+    unsafe fn transform(v: &Vec4, m: &[float32x4_t; 4]) -> float32x4_t {
+        let x = vmulq_n_f32(m[0], v.x);
+        let y = vmulq_n_f32(m[1], v.y);
+        let z = vmulq_n_f32(m[2], v.z);
+        let w = vmulq_n_f32(m[3], v.w);
+        vaddq_f32(vaddq_f32(vaddq_f32(x, y), z), w)
+    }
+
+    #[inline(always)]
+    unsafe fn rows(m: &Mat4x4) -> [float32x4_t; 4] {
+        [load(&m.r0), load(&m.r1), load(&m.r2), load(&m.r3)]
+    }
+
+    pub unsafe fn mul_mat4(a: &Mat4x4, b: &Mat4x4) -> Mat4x4 {
+        let b = rows(b);
+        Mat4x4 {
+            r0: store(transform(&a.r0, &b)),
+            r1: store(transform(&a.r1, &b)),
+            r2: store(transform(&a.r2, &b)),
+            r3: store(transform(&a.r3, &b)),
+        }
+    }
+
+    pub unsafe fn mul_vec4_mat4(v: &Vec4, m: &Mat4x4) -> Vec4 {
+        store(transform(v, &rows(m)))
+    }
+}
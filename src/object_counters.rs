@@ -0,0 +1,99 @@
+// Live Vulkan objects per type, counted by the helpers that create and destroy them: VkBuffer,
+// VkImage, VkSparseImage, create_image_view, SamplerCache, DescriptorAllocator,
+// BindlessTable and QueryPoolWrapper. A leaked image view, sampler or descriptor set is
+// a count that keeps growing, visible long before validation lists it at shutdown.
+// Pipelines and layouts are destroyed by the modules directly and aren't counted.
+
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectType {
+    Buffer,
+    Image,
+    ImageView,
+    Sampler,
+    DescriptorPool,
+    DescriptorSet,
+    QueryPool,
+}
+
+const NUM_OBJECT_TYPES: usize = 7;
+
+pub const ALL_OBJECT_TYPES: [ObjectType; NUM_OBJECT_TYPES] = [
+    ObjectType::Buffer,
+    ObjectType::Image,
+    ObjectType::ImageView,
+    ObjectType::Sampler,
+    ObjectType::DescriptorPool,
+    ObjectType::DescriptorSet,
+    ObjectType::QueryPool,
+];
+
+impl ObjectType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ObjectType::Buffer => "buffers",
+            ObjectType::Image => "images",
+            ObjectType::ImageView => "image views",
+            ObjectType::Sampler => "samplers",
+            ObjectType::DescriptorPool => "descriptor pools",
+            ObjectType::DescriptorSet => "descriptor sets",
+            ObjectType::QueryPool => "query pools",
+        }
+    }
+}
+
+// Signed, a double destroy shows up as a negative count instead of wrapping around
+static COUNTS: [AtomicI64; NUM_OBJECT_TYPES] = [const { AtomicI64::new(0) }; NUM_OBJECT_TYPES];
+
+pub fn created(object_type: ObjectType, count: usize) {
+    COUNTS[object_type as usize].fetch_add(count as i64, Ordering::Relaxed);
+}
+
+pub fn destroyed(object_type: ObjectType, count: usize) {
+    COUNTS[object_type as usize].fetch_sub(count as i64, Ordering::Relaxed);
+}
+
+// Counts of every type at one point in time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObjectCounts([i64; NUM_OBJECT_TYPES]);
+
+impl ObjectCounts {
+    pub fn current() -> ObjectCounts {
+        let mut counts = [0; NUM_OBJECT_TYPES];
+        for (count, live) in counts.iter_mut().zip(COUNTS.iter()) {
+            *count = live.load(Ordering::Relaxed);
+        }
+        ObjectCounts(counts)
+    }
+
+    pub fn get(&self, object_type: ObjectType) -> i64 {
+        self.0[object_type as usize]
+    }
+
+    // (type, baseline count, count) of the types with more live objects than in baseline
+    pub fn grown_since(&self, baseline: &ObjectCounts) -> Vec<(ObjectType, i64, i64)> {
+        ALL_OBJECT_TYPES
+            .iter()
+            .map(|&object_type| {
+                (
+                    object_type,
+                    baseline.get(object_type),
+                    self.get(object_type),
+                )
+            })
+            .filter(|&(_, before, now)| now > before)
+            .collect()
+    }
+}
+
+impl fmt::Display for ObjectCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts: Vec<String> = ALL_OBJECT_TYPES
+            .iter()
+            .map(|&object_type| format!("{} {}", object_type.name(), self.get(object_type)))
+            .collect();
+        write!(f, "{}", counts.join(", "))
+    }
+}
@@ -0,0 +1,128 @@
+// Conversions of floats to the normalized integer and half float encodings of GPU buffers and
+// textures. Each pack function matches the Vulkan format or the GLSL unpack* built-in that
+// reads the value back: rounded to nearest, out of range values clamped.
+//
+//   pack_unorm8        R8_UNORM, unpackUnorm4x8 per byte
+//   pack_unorm16       R16_UNORM, the SDF voxel encoding
+//   pack_snorm8x4      R8G8B8A8_SNORM, unpackSnorm4x8
+//   pack_r10g10b10a2   A2B10G10R10_UNORM_PACK32
+//   f32_to_f16         R16_SFLOAT, unpackHalf2x16 per half
+
+use crate::minivector::*;
+
+// The unorm16 code of 1.0. SDF distances and error thresholds normalized to this range are
+// scaled by it before they are compared with voxel values.
+pub const UNORM16_MAX: f32 = 65535.0;
+
+fn pack_unorm(value: f32, max: f32) -> u32 {
+    (value.clamp(0.0, 1.0) * max).round() as u32
+}
+
+// GLSL rounds snorm to nearest and the -128 code unpacks to -1 like -127
+fn pack_snorm(value: f32, max: f32) -> i32 {
+    (value.clamp(-1.0, 1.0) * max).round() as i32
+}
+
+pub fn pack_unorm8(value: f32) -> u8 {
+    pack_unorm(value, 255.0) as u8
+}
+
+pub fn unpack_unorm8(code: u8) -> f32 {
+    code as f32 / 255.0
+}
+
+pub fn pack_unorm16(value: f32) -> u16 {
+    pack_unorm(value, UNORM16_MAX) as u16
+}
+
+pub fn unpack_unorm16(code: u16) -> f32 {
+    code as f32 / UNORM16_MAX
+}
+
+// x in the lowest byte
+pub fn pack_snorm8x4(v: Vec4) -> u32 {
+    [v.x, v.y, v.z, v.w]
+        .iter()
+        .enumerate()
+        .fold(0, |packed, (i, &c)| {
+            packed | ((pack_snorm(c, 127.0) as u8 as u32) << (i * 8))
+        })
+}
+
+pub fn unpack_snorm8x4(packed: u32) -> Vec4 {
+    let c = |i: u32| ((packed >> (i * 8)) as u8 as i8 as f32 / 127.0).max(-1.0);
+    Vec4 {
+        x: c(0),
+        y: c(1),
+        z: c(2),
+        w: c(3),
+    }
+}
+
+// 10 bits each for x, y and z from the lowest bit up, 2 bits of w on top
+pub fn pack_r10g10b10a2(v: Vec4) -> u32 {
+    pack_unorm(v.x, 1023.0)
+        | (pack_unorm(v.y, 1023.0) << 10)
+        | (pack_unorm(v.z, 1023.0) << 20)
+        | (pack_unorm(v.w, 3.0) << 30)
+}
+
+pub fn unpack_r10g10b10a2(packed: u32) -> Vec4 {
+    let c = |shift: u32, max: u32| ((packed >> shift) & max) as f32 / max as f32;
+    Vec4 {
+        x: c(0, 1023),
+        y: c(10, 1023),
+        z: c(20, 1023),
+        w: c(30, 3),
+    }
+}
+
+// IEEE half float bits of value, rounded to nearest even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // Subnormal halves keep the implicit one in the mantissa
+    let (half, mantissa, shift) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa >> shift, mantissa, shift)
+    } else {
+        (((exponent as u32) << 10) | (mantissa >> 13), mantissa, 13)
+    };
+    // A carry out of the mantissa rounds up into the exponent, which is still correct
+    let round_bit = 1 << (shift - 1);
+    let round_up = mantissa & round_bit != 0 && (mantissa & (round_bit - 1) != 0 || half & 1 == 1);
+    sign | (half + round_up as u32) as u16
+}
+
+// f32 value of IEEE half float bits, exact
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & 0x8000) as u32) << 16;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        // Subnormal half, normalized for f32
+        0 => {
+            let shift = mantissa.leading_zeros() - 21;
+            let mantissa = (mantissa << shift) & 0x3ff;
+            sign | ((127 - 15 + 1 - shift) << 23) | (mantissa << 13)
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
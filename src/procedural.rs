@@ -0,0 +1,329 @@
+// Procedural noise: 2D/3D gradient (Perlin) and simplex noise, fBm octave sums of them and
+// instance placements driven by noise. Lattices are hashed from the integer coordinates and a
+// seed instead of a permutation table, so any seed gives a different, unbounded field.
+// Single noise values are roughly in [-1, 1], Fbm normalizes its octave sum to that range.
+
+use crate::minivector::*;
+use rand::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+impl NoiseKind {
+    pub fn from_name(name: &str) -> Option<NoiseKind> {
+        match name {
+            "perlin" => Some(NoiseKind::Perlin),
+            "simplex" => Some(NoiseKind::Simplex),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoiseKind::Perlin => "perlin",
+            NoiseKind::Simplex => "simplex",
+        }
+    }
+
+    pub fn sample_2d(self, x: f32, y: f32, seed: u32) -> f32 {
+        match self {
+            NoiseKind::Perlin => perlin_2d(x, y, seed),
+            NoiseKind::Simplex => simplex_2d(x, y, seed),
+        }
+    }
+
+    pub fn sample_3d(self, p: Vec3, seed: u32) -> f32 {
+        match self {
+            NoiseKind::Perlin => perlin_3d(p, seed),
+            NoiseKind::Simplex => simplex_3d(p, seed),
+        }
+    }
+}
+
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed ^ (x as u32).wrapping_mul(0x8da6_b343);
+    h ^= (y as u32).wrapping_mul(0xd816_3841);
+    h ^= (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+// Unit length, the 8 directions of the square's edges and diagonals
+const GRADIENTS_2D: [(f32, f32); 8] = [
+    (1.0, 0.0),
+    (-1.0, 0.0),
+    (0.0, 1.0),
+    (0.0, -1.0),
+    (0.707_106_77, 0.707_106_77),
+    (-0.707_106_77, 0.707_106_77),
+    (0.707_106_77, -0.707_106_77),
+    (-0.707_106_77, -0.707_106_77),
+];
+
+// The 12 directions to the cube's edge midpoints (Perlin's improved noise)
+const GRADIENTS_3D: [(f32, f32, f32); 12] = [
+    (1.0, 1.0, 0.0),
+    (-1.0, 1.0, 0.0),
+    (1.0, -1.0, 0.0),
+    (-1.0, -1.0, 0.0),
+    (1.0, 0.0, 1.0),
+    (-1.0, 0.0, 1.0),
+    (1.0, 0.0, -1.0),
+    (-1.0, 0.0, -1.0),
+    (0.0, 1.0, 1.0),
+    (0.0, -1.0, 1.0),
+    (0.0, 1.0, -1.0),
+    (0.0, -1.0, -1.0),
+];
+
+fn gradient_2d(x: i32, y: i32, seed: u32, dx: f32, dy: f32) -> f32 {
+    let g = GRADIENTS_2D[(hash(x, y, 0, seed) & 7) as usize];
+    g.0 * dx + g.1 * dy
+}
+
+fn gradient_3d(x: i32, y: i32, z: i32, seed: u32, d: (f32, f32, f32)) -> f32 {
+    let g = GRADIENTS_3D[(hash(x, y, z, seed) % 12) as usize];
+    g.0 * d.0 + g.1 * d.1 + g.2 * d.2
+}
+
+// 6t^5 - 15t^4 + 10t^3, zero first and second derivatives at the lattice
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+// Zero at the integer lattice points
+pub fn perlin_2d(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (u, v) = (fade(fx), fade(fy));
+
+    let n00 = gradient_2d(ix, iy, seed, fx, fy);
+    let n10 = gradient_2d(ix + 1, iy, seed, fx - 1.0, fy);
+    let n01 = gradient_2d(ix, iy + 1, seed, fx, fy - 1.0);
+    let n11 = gradient_2d(ix + 1, iy + 1, seed, fx - 1.0, fy - 1.0);
+    // Unit gradients reach at most sqrt(1/2)
+    lerp(lerp(n00, n10, u), lerp(n01, n11, u), v) * std::f32::consts::SQRT_2
+}
+
+pub fn perlin_3d(p: Vec3, seed: u32) -> f32 {
+    let cell = (p.x.floor(), p.y.floor(), p.z.floor());
+    let f = (p.x - cell.0, p.y - cell.1, p.z - cell.2);
+    let (ix, iy, iz) = (cell.0 as i32, cell.1 as i32, cell.2 as i32);
+    let t = (fade(f.0), fade(f.1), fade(f.2));
+
+    let corner = |cx: i32, cy: i32, cz: i32| {
+        let d = (f.0 - cx as f32, f.1 - cy as f32, f.2 - cz as f32);
+        gradient_3d(ix + cx, iy + cy, iz + cz, seed, d)
+    };
+    let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), t.0);
+    let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), t.0);
+    let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), t.0);
+    let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), t.0);
+    lerp(lerp(x00, x10, t.1), lerp(x01, x11, t.1), t.2)
+}
+
+// Skew factors between the simplex grid and the regular one
+const F2: f32 = 0.366_025_4; // (sqrt(3) - 1) / 2
+const G2: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+const F3: f32 = 1.0 / 3.0;
+const G3: f32 = 1.0 / 6.0;
+
+// Gustavson's simplex noise, fewer directional artifacts than perlin_2d
+pub fn simplex_2d(x: f32, y: f32, seed: u32) -> f32 {
+    let s = (x + y) * F2;
+    let (i, j) = ((x + s).floor(), (y + s).floor());
+    let t = (i + j) * G2;
+    let (x0, y0) = (x - (i - t), y - (j - t));
+    // Lower or upper triangle of the skewed cell
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let (x1, y1) = (x0 - i1 as f32 + G2, y0 - j1 as f32 + G2);
+    let (x2, y2) = (x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2);
+    let (i, j) = (i as i32, j as i32);
+
+    let corner = |ci: i32, cj: i32, dx: f32, dy: f32| {
+        let t = 0.5 - dx * dx - dy * dy;
+        if t < 0.0 {
+            0.0
+        } else {
+            t * t * t * t * gradient_2d(i + ci, j + cj, seed, dx, dy)
+        }
+    };
+    let n = corner(0, 0, x0, y0) + corner(i1, j1, x1, y1) + corner(1, 1, x2, y2);
+    // 70 for Gustavson's (1, 1) gradients, scaled for the unit length ones
+    99.0 * n
+}
+
+pub fn simplex_3d(p: Vec3, seed: u32) -> f32 {
+    let s = (p.x + p.y + p.z) * F3;
+    let (i, j, k) = ((p.x + s).floor(), (p.y + s).floor(), (p.z + s).floor());
+    let t = (i + j + k) * G3;
+    let d0 = (p.x - (i - t), p.y - (j - t), p.z - (k - t));
+
+    // Which of the six tetrahedra of the skewed cube, by the order of the offsets
+    let (o1, o2) = if d0.0 >= d0.1 {
+        if d0.1 >= d0.2 {
+            ((1, 0, 0), (1, 1, 0))
+        } else if d0.0 >= d0.2 {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if d0.1 < d0.2 {
+        ((0, 0, 1), (0, 1, 1))
+    } else if d0.0 < d0.2 {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+    let (i, j, k) = (i as i32, j as i32, k as i32);
+
+    let corner = |o: (i32, i32, i32), g: f32| {
+        let d = (
+            d0.0 - o.0 as f32 + g,
+            d0.1 - o.1 as f32 + g,
+            d0.2 - o.2 as f32 + g,
+        );
+        let t = 0.6 - d.0 * d.0 - d.1 * d.1 - d.2 * d.2;
+        if t < 0.0 {
+            0.0
+        } else {
+            t * t * t * t * gradient_3d(i + o.0, j + o.1, k + o.2, seed, d)
+        }
+    };
+    let n = corner((0, 0, 0), 0.0)
+        + corner(o1, G3)
+        + corner(o2, 2.0 * G3)
+        + corner((1, 1, 1), 3.0 * G3);
+    32.0 * n
+}
+
+// Fractal Brownian motion: octaves of the noise at growing frequency and shrinking amplitude.
+// Every octave has its own seed, so the lattices don't line up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fbm {
+    pub kind: NoiseKind,
+    pub octaves: u32,
+    pub frequency: f32,  // Of the first octave, per world unit
+    pub lacunarity: f32, // Frequency ratio of consecutive octaves
+    pub gain: f32,       // Amplitude ratio of consecutive octaves
+    pub seed: u32,
+}
+
+impl Default for Fbm {
+    fn default() -> Fbm {
+        Fbm {
+            kind: NoiseKind::Simplex,
+            octaves: 4,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            gain: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+impl Fbm {
+    pub fn new(kind: NoiseKind, octaves: u32, frequency: f32) -> Fbm {
+        Fbm {
+            kind,
+            octaves,
+            frequency,
+            ..Fbm::default()
+        }
+    }
+
+    pub fn with_seed(self, seed: u32) -> Fbm {
+        Fbm { seed, ..self }
+    }
+
+    // Amplitude weighted sum of octave(frequency, seed), divided by the total amplitude
+    fn sum(&self, octave: impl Fn(f32, u32) -> f32) -> f32 {
+        let (mut value, mut total) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.frequency, 1.0);
+        for i in 0..self.octaves {
+            value += octave(frequency, self.seed.wrapping_add(i)) * amplitude;
+            total += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        if total > 0.0 {
+            value / total
+        } else {
+            0.0
+        }
+    }
+
+    // In [-1, 1]
+    pub fn sample_2d(&self, x: f32, y: f32) -> f32 {
+        self.sum(|f, seed| self.kind.sample_2d(x * f, y * f, seed))
+    }
+
+    pub fn sample_3d(&self, p: Vec3) -> f32 {
+        self.sum(|f, seed| self.kind.sample_3d(p * f, seed))
+    }
+
+    // Sharp crests where the noise crosses zero, in [0, 1]
+    pub fn ridged_2d(&self, x: f32, y: f32) -> f32 {
+        self.sum(|f, seed| 1.0 - self.kind.sample_2d(x * f, y * f, seed).abs())
+    }
+
+    pub fn ridged_3d(&self, p: Vec3) -> f32 {
+        self.sum(|f, seed| 1.0 - self.kind.sample_3d(p * f, seed).abs())
+    }
+
+    // Sum of absolute octaves, billowy creases at the zero crossings, in [0, 1]
+    pub fn turbulence_2d(&self, x: f32, y: f32) -> f32 {
+        self.sum(|f, seed| self.kind.sample_2d(x * f, y * f, seed).abs())
+    }
+
+    pub fn turbulence_3d(&self, p: Vec3) -> f32 {
+        self.sum(|f, seed| self.kind.sample_3d(p * f, seed).abs())
+    }
+}
+
+// count points in the cube [-radius, radius]^3, clustered where density (remapped from
+// [-1, 1] to [0, 1]) is high. Rejection sampled, so the points are random but reproducible
+// with a seeded rng.
+pub fn clustered_cloud(count: usize, radius: f32, density: &Fbm, rng: &mut impl Rng) -> Vec<Vec3> {
+    let mut points = Vec::with_capacity(count);
+    while points.len() < count {
+        let p = Vec3 {
+            x: rng.gen_range(-radius, radius),
+            y: rng.gen_range(-radius, radius),
+            z: rng.gen_range(-radius, radius),
+        };
+        let acceptance = (density.sample_3d(p) * 0.5 + 0.5).clamp(0.0, 1.0);
+        if rng.gen::<f32>() < acceptance {
+            points.push(p);
+        }
+    }
+    points
+}
+
+// count points on a square grid in the XZ plane, row by row, lifted by a heightfield of
+// amplitude height_scale. Grid coordinates are in cells, so the noise frequency is per cell.
+// The first point stays at the origin, gradient noise is zero at the lattice.
+pub fn heightfield_grid(count: usize, spacing: f32, height: &Fbm, height_scale: f32) -> Vec<Vec3> {
+    let per_row = (count as f32).sqrt().ceil().max(1.0) as usize;
+    (0..count)
+        .map(|i| {
+            let (column, row) = ((i % per_row) as f32, (i / per_row) as f32);
+            Vec3 {
+                x: column * spacing,
+                y: height.sample_2d(column, row) * height_scale,
+                z: row * spacing,
+            }
+        })
+        .collect()
+}
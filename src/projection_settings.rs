@@ -0,0 +1,293 @@
+// Camera projection selected on the command line and changed at runtime with hotkeys or with
+// commands typed on the console (stdin). Depth is reversed (near = 1, far = 0) in both modes
+// unless --depth standard picks the standard convention (near = 0, far = 1).
+
+use crate::minivector::*;
+
+use ash::vk;
+
+use std::fmt;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+pub const MIN_FOV_DEGREES: f32 = 10.0;
+pub const MAX_FOV_DEGREES: f32 = 170.0;
+const FOV_STEP_DEGREES: f32 = 5.0;
+const ORTHOGRAPHIC_ZOOM_STEP: f32 = 1.25;
+// The ray marchers derive ray directions from the camera position. In orthographic mode the
+// rays start this far behind the camera, which makes them parallel to the view direction.
+const ORTHOGRAPHIC_RAY_DISTANCE: f32 = 1.0e5;
+
+// +/- zoom (FOV or orthographic height), P toggles the projection type, [ and ] halve or
+// double the near plane distance
+pub const PROJECTION_KEYS: [&str; 7] = ["+", "=", "-", "p", "P", "[", "]"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionType {
+    Perspective,
+    Orthographic,
+}
+
+// Reversed depth keeps the float precision for distant surfaces. Standard depth is for
+// matching captures of engines that use it, it z-fights at the default far plane distance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthConvention {
+    Reversed, // Near = 1, far = 0, GREATER_OR_EQUAL
+    Standard, // Near = 0, far = 1, LESS_OR_EQUAL
+}
+
+// constant_id of the REVERSED_DEPTH bool of the depth pyramid and culling shaders
+pub const REVERSED_DEPTH_CONSTANT_ID: u32 = 0;
+
+impl DepthConvention {
+    pub fn from_name(name: &str) -> Option<DepthConvention> {
+        match name {
+            "reversed" => Some(DepthConvention::Reversed),
+            "standard" => Some(DepthConvention::Standard),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DepthConvention::Reversed => "reversed",
+            DepthConvention::Standard => "standard",
+        }
+    }
+
+    pub fn near_depth(&self) -> f32 {
+        1.0 - self.clear_depth()
+    }
+
+    // Depth of the far plane, what the depth buffer is cleared to
+    pub fn clear_depth(&self) -> f32 {
+        match self {
+            DepthConvention::Reversed => 0.0,
+            DepthConvention::Standard => 1.0,
+        }
+    }
+
+    // Passes fragments at or in front of the stored depth
+    pub fn compare_op(&self) -> vk::CompareOp {
+        match self {
+            DepthConvention::Reversed => vk::CompareOp::GREATER_OR_EQUAL,
+            DepthConvention::Standard => vk::CompareOp::LESS_OR_EQUAL,
+        }
+    }
+
+    // The reverse depth matrices with near and far swapped map near to 0 and far to 1
+    pub fn projection(&self, fovy: f32, aspect: f32, near: f32, far: f32) -> Mat4x4 {
+        match self {
+            DepthConvention::Reversed => projection(fovy, aspect, near, far),
+            DepthConvention::Standard => projection(fovy, aspect, far, near),
+        }
+    }
+
+    pub fn orthographic(&self, width: f32, height: f32, near: f32, far: f32) -> Mat4x4 {
+        match self {
+            DepthConvention::Reversed => orthographic(width, height, near, far),
+            DepthConvention::Standard => orthographic(width, height, far, near),
+        }
+    }
+
+    // Value of REVERSED_DEPTH
+    pub fn specialization_value(&self) -> u32 {
+        (*self == DepthConvention::Reversed) as u32
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ProjectionSettings {
+    pub projection_type: ProjectionType,
+    pub fov_degrees: f32, // Vertical
+    pub near: f32,
+    pub far: f32,
+    pub ortho_height: f32, // Visible height in world units in orthographic mode
+    pub depth: DepthConvention,
+}
+
+impl Default for ProjectionSettings {
+    fn default() -> ProjectionSettings {
+        ProjectionSettings {
+            projection_type: ProjectionType::Perspective,
+            fov_degrees: 90.0,
+            near: 1.0,
+            far: 10000000.0,
+            ortho_height: 256.0,
+            depth: DepthConvention::Reversed,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ProjectionCommandError {
+    UnknownCommand(String),
+    InvalidValue(String),
+}
+
+impl fmt::Display for ProjectionCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProjectionCommandError::UnknownCommand(command) => {
+                write!(
+                    f,
+                    "Unknown command '{}' (fov, near, ortho, perspective)",
+                    command
+                )
+            }
+            ProjectionCommandError::InvalidValue(value) => write!(f, "Invalid value '{}'", value),
+        }
+    }
+}
+
+impl std::error::Error for ProjectionCommandError {}
+
+impl ProjectionSettings {
+    // --fov DEGREES (vertical, default 90)
+    // --near DISTANCE (default 1)
+    // --ortho [HEIGHT] (orthographic, HEIGHT world units visible vertically)
+    // --depth <reversed|standard> (default reversed)
+    pub fn from_args(args: &[String]) -> ProjectionSettings {
+        let mut settings = ProjectionSettings::default();
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+                .and_then(|value| value.parse::<f32>().ok())
+        };
+        if let Some(fov) = value("--fov") {
+            settings.set_fov(fov);
+        }
+        if let Some(near) = value("--near") {
+            settings.set_near(near);
+        }
+        if args.iter().any(|arg| arg == "--ortho") {
+            settings.projection_type = ProjectionType::Orthographic;
+            if let Some(height) = value("--ortho") {
+                settings.set_ortho_height(height);
+            }
+        }
+        if let Some(depth) = args
+            .iter()
+            .position(|arg| arg == "--depth")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|name| DepthConvention::from_name(name))
+        {
+            settings.depth = depth;
+        }
+        settings
+    }
+
+    pub fn matrix(&self, aspect: f32) -> Mat4x4 {
+        match self.projection_type {
+            ProjectionType::Perspective => {
+                let fovy = self.fov_degrees.to_radians();
+                self.depth.projection(fovy, aspect, self.near, self.far)
+            }
+            ProjectionType::Orthographic => self.depth.orthographic(
+                self.ortho_height * aspect,
+                self.ortho_height,
+                self.near,
+                self.far,
+            ),
+        }
+    }
+
+    // Camera position for the shaders, see ORTHOGRAPHIC_RAY_DISTANCE
+    pub fn ray_origin(&self, position: Vec3, direction: Vec3) -> Vec3 {
+        match self.projection_type {
+            ProjectionType::Perspective => position,
+            ProjectionType::Orthographic => {
+                position - direction.normalize() * ORTHOGRAPHIC_RAY_DISTANCE
+            }
+        }
+    }
+
+    pub fn set_fov(&mut self, degrees: f32) {
+        self.fov_degrees = degrees.clamp(MIN_FOV_DEGREES, MAX_FOV_DEGREES);
+    }
+
+    pub fn set_near(&mut self, near: f32) {
+        self.near = near.clamp(1.0e-3, self.far * 0.5);
+    }
+
+    pub fn set_ortho_height(&mut self, height: f32) {
+        self.ortho_height = height.max(1.0e-3);
+    }
+
+    // Positive steps zoom in: narrower FOV or a smaller orthographic view
+    pub fn zoom(&mut self, steps: f32) {
+        match self.projection_type {
+            ProjectionType::Perspective => {
+                self.set_fov(self.fov_degrees - steps * FOV_STEP_DEGREES)
+            }
+            ProjectionType::Orthographic => {
+                self.set_ortho_height(self.ortho_height / ORTHOGRAPHIC_ZOOM_STEP.powf(steps))
+            }
+        }
+    }
+
+    pub fn toggle_type(&mut self) {
+        self.projection_type = match self.projection_type {
+            ProjectionType::Perspective => ProjectionType::Orthographic,
+            ProjectionType::Orthographic => ProjectionType::Perspective,
+        };
+    }
+
+    // One of PROJECTION_KEYS, other keys are ignored
+    pub fn apply_key(&mut self, key: &str) {
+        match key {
+            "+" | "=" => self.zoom(1.0),
+            "-" => self.zoom(-1.0),
+            "p" | "P" => self.toggle_type(),
+            "[" => self.set_near(self.near * 0.5),
+            "]" => self.set_near(self.near * 2.0),
+            _ => {}
+        }
+    }
+
+    // fov DEGREES | near DISTANCE | ortho [HEIGHT] | perspective
+    pub fn apply_command(&mut self, line: &str) -> Result<(), ProjectionCommandError> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let value = words.next();
+        let parse = |value: Option<&str>| {
+            let value = value.unwrap_or("");
+            value
+                .parse::<f32>()
+                .map_err(|_| ProjectionCommandError::InvalidValue(value.to_string()))
+        };
+        match command {
+            "fov" => self.set_fov(parse(value)?),
+            "near" => self.set_near(parse(value)?),
+            "ortho" => {
+                if value.is_some() {
+                    self.set_ortho_height(parse(value)?);
+                }
+                self.projection_type = ProjectionType::Orthographic;
+            }
+            "perspective" => self.projection_type = ProjectionType::Perspective,
+            _ => return Err(ProjectionCommandError::UnknownCommand(command.to_string())),
+        }
+        Ok(())
+    }
+}
+
+// Lines typed on stdin, read on a separate thread so the event loop never blocks. The
+// channel disconnects when stdin is closed.
+pub fn spawn_console_commands() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if !line.trim().is_empty() && sender.send(line.trim().to_string()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,73 @@
+// Rendering quality options selected on the command line.
+
+// Resolution of the ambient occlusion pass, the bilateral upsample brings it to full resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AoQuality {
+    Off,
+    Half,
+    Full,
+}
+
+impl AoQuality {
+    pub fn from_name(name: &str) -> Option<AoQuality> {
+        match name {
+            "off" => Some(AoQuality::Off),
+            "half" => Some(AoQuality::Half),
+            "full" => Some(AoQuality::Full),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AoQuality::Off => "off",
+            AoQuality::Half => "half",
+            AoQuality::Full => "full",
+        }
+    }
+
+    // Screen pixels per AO texel along each axis, None when off
+    pub fn resolution_divisor(self) -> Option<u32> {
+        match self {
+            AoQuality::Off => None,
+            AoQuality::Half => Some(2),
+            AoQuality::Full => Some(1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QualitySettings {
+    pub anisotropy: f32, // Requested max anisotropy, clamped to the device limit by SamplerCache
+    pub ao: AoQuality,
+}
+
+impl Default for QualitySettings {
+    fn default() -> QualitySettings {
+        QualitySettings {
+            anisotropy: 1.0,
+            ao: AoQuality::Off,
+        }
+    }
+}
+
+impl QualitySettings {
+    // --anisotropy N (1 = off, typically up to 16)
+    // --ao <off|half|full> (default off)
+    pub fn from_args(args: &[String]) -> QualitySettings {
+        let mut settings = QualitySettings::default();
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        if let Some(anisotropy) = value("--anisotropy").and_then(|value| value.parse::<f32>().ok())
+        {
+            settings.anisotropy = anisotropy.max(1.0);
+        }
+        if let Some(ao) = value("--ao").and_then(|name| AoQuality::from_name(name)) {
+            settings.ao = ao;
+        }
+        settings
+    }
+}
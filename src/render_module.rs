@@ -0,0 +1,68 @@
+// Standard lifecycle of the renderer modules. A viewer registers its modules once and drives
+// all of them through the same steps, so a new pass implements RenderModule instead of adding
+// calls to every step of the main loop:
+//   create            one-time GPU setup, recorded into the setup command buffer
+//   resize            after the swapchain was recreated
+//   update            CPU side per-frame state, before recording starts
+//   record_pre_pass   copies and barriers before the main render pass
+//   record_main_pass  draws of the main render pass
+//   destroy           after the device is idle
+// Inputs only one module needs (culling results, scene lights) stay on that module's own
+// setters, the context carries the state every module can use.
+
+use ash::vk;
+
+use crate::allocator_telemetry::*;
+use crate::minivector::*;
+use crate::vulkan_base::*;
+use crate::vulkan_helpers::*;
+
+// Draw of the main render pass. It copies its handles and sets its own dynamic state, so the
+// viewer can run it inline or on a worker thread into a secondary command buffer.
+pub type MainPassDraw = SecondaryRecorder<'static>;
+
+pub struct FrameContext<'a> {
+    pub resolution: vk::Extent2D,
+    pub view_scissor: VkViewScissor,
+    pub world_to_screen: Mat4x4,
+    pub camera_position: Vec3,
+    pub camera_direction: Vec3,
+    pub scratch: Option<&'a mut ScratchAllocator>, // Only while recording, after begin_frame
+}
+
+pub trait RenderModule {
+    fn create(
+        &mut self,
+        _device: &Device,
+        _barrier_api: &BarrierApi,
+        _command_buffer: vk::CommandBuffer,
+    ) {
+    }
+
+    fn resize(
+        &mut self,
+        _device: &Device,
+        _allocator: &mut TrackingAllocator,
+        _resolution: vk::Extent2D,
+        _depth_view: vk::ImageView,
+    ) {
+    }
+
+    fn update(&mut self, _frame: &FrameContext) {}
+
+    fn record_pre_pass(
+        &mut self,
+        _device: &Device,
+        _barrier_api: &BarrierApi,
+        _command_buffer: vk::CommandBuffer,
+        _frame: &FrameContext,
+    ) {
+    }
+
+    // None draws nothing this frame
+    fn record_main_pass(&mut self, _frame: &mut FrameContext) -> Option<MainPassDraw> {
+        None
+    }
+
+    fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator);
+}
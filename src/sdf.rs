@@ -2,14 +2,134 @@ extern crate miniz_oxide;
 use miniz_oxide::deflate::compress_to_vec;
 use miniz_oxide::inflate::decompress_to_vec;
 
+use crate::minivector::*;
+use crate::packing::*;
 use crate::serialization::*;
-use std::io;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
 
-#[derive(Clone, Debug, Copy)]
+// Encoded distance of the surface, values below are inside
+pub const LEVEL_ZERO: u16 = 32768;
+
+// Version 2 headers start with this magic and store the voxel spacing per axis. Version 1
+// headers start with dim.0 and have one spacing for all axes.
+pub const SDF_HEADER_MAGIC: u32 = u32::from_le_bytes(*b"SDF2");
+// dim, box_min, spacing
+pub const SDF_HEADER_V1_BYTES: usize = 7 * 4;
+// magic, dim, box_min, spacing
+pub const SDF_HEADER_BYTES: usize = 10 * 4;
+
+#[derive(Clone, Debug, Copy, PartialEq, Serialize, Deserialize)]
 pub struct SdfHeader {
     pub dim: (u32, u32, u32),
     pub box_min: (f32, f32, f32),
-    pub dx: f32,
+    pub spacing: (f32, f32, f32), // Voxel size along x, y, z. Scan data is often anisotropic.
+}
+
+impl SdfHeader {
+    pub fn is_isotropic(&self) -> bool {
+        self.spacing.0 == self.spacing.1 && self.spacing.1 == self.spacing.2
+    }
+
+    pub fn max_spacing(&self) -> f32 {
+        self.spacing.0.max(self.spacing.1).max(self.spacing.2)
+    }
+
+    // Extent of the volume in world units
+    pub fn world_size(&self) -> (f32, f32, f32) {
+        (
+            self.spacing.0 * self.dim.0 as f32,
+            self.spacing.1 * self.dim.1 as f32,
+            self.spacing.2 * self.dim.2 as f32,
+        )
+    }
+
+    // World space box of the volume
+    pub fn bounds(&self) -> Aabb {
+        let size = self.world_size();
+        let min = Vec3 {
+            x: self.box_min.0,
+            y: self.box_min.1,
+            z: self.box_min.2,
+        };
+        Aabb::new(
+            min,
+            min + Vec3 {
+                x: size.0,
+                y: size.1,
+                z: size.2,
+            },
+        )
+    }
+
+    // World units per step of the u16 distance encoding. The renderers decode distances to
+    // (value - LEVEL_ZERO) / LEVEL_ZERO lengths of the volume diagonal.
+    pub fn distance_per_step(&self) -> f32 {
+        let size = self.world_size();
+        (size.0 * size.0 + size.1 * size.1 + size.2 * size.2).sqrt() / LEVEL_ZERO as f32
+    }
+
+    // Loads version 1 and 2 headers
+    pub fn load(loader: &mut Loader, bytes: &[u8]) -> SdfHeader {
+        let mut peek = Loader::new();
+        peek.offset = loader.offset;
+        let version2 = peek.load_u32(bytes) == SDF_HEADER_MAGIC;
+        if version2 {
+            loader.offset += 4;
+        }
+        let dim = (
+            loader.load_u32(bytes),
+            loader.load_u32(bytes),
+            loader.load_u32(bytes),
+        );
+        let box_min = (
+            loader.load_f32(bytes),
+            loader.load_f32(bytes),
+            loader.load_f32(bytes),
+        );
+        let spacing = if version2 {
+            (
+                loader.load_f32(bytes),
+                loader.load_f32(bytes),
+                loader.load_f32(bytes),
+            )
+        } else {
+            let dx = loader.load_f32(bytes);
+            (dx, dx, dx)
+        };
+        SdfHeader {
+            dim,
+            box_min,
+            spacing,
+        }
+    }
+
+    // Reads only the header bytes from the start of a file
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<SdfHeader> {
+        let mut bytes = vec![0u8; SDF_HEADER_V1_BYTES];
+        reader.read_exact(&mut bytes)?;
+        if Loader::new().load_u32(&bytes) == SDF_HEADER_MAGIC {
+            bytes.resize(SDF_HEADER_BYTES, 0);
+            reader.read_exact(&mut bytes[SDF_HEADER_V1_BYTES..])?;
+        }
+        Ok(SdfHeader::load(&mut Loader::new(), &bytes))
+    }
+
+    // Always writes version 2
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut storer = StorerVec::new();
+        storer.store_u32(SDF_HEADER_MAGIC);
+        storer.store_u32(self.dim.0);
+        storer.store_u32(self.dim.1);
+        storer.store_u32(self.dim.2);
+        storer.store_f32(self.box_min.0);
+        storer.store_f32(self.box_min.1);
+        storer.store_f32(self.box_min.2);
+        storer.store_f32(self.spacing.0);
+        storer.store_f32(self.spacing.1);
+        storer.store_f32(self.spacing.2);
+        storer.v
+    }
 }
 
 pub struct Sdf {
@@ -22,19 +142,7 @@ pub fn load_sdf_zlib(filename: &str) -> io::Result<Sdf> {
     let bytes = decompress_to_vec(&bytes[..]).expect("Failed to decompress!");
 
     let mut loader = Loader::new();
-    let header = SdfHeader {
-        dim: (
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-        ),
-        box_min: (
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-        ),
-        dx: loader.load_f32(&bytes),
-    };
+    let header = SdfHeader::load(&mut loader, &bytes);
 
     let count_voxels = header.dim.0 * header.dim.1 * header.dim.2;
     let voxels = loader.load_array_u16(&bytes, count_voxels as usize);
@@ -51,19 +159,7 @@ pub fn load_sdf(filename: &str) -> io::Result<Sdf> {
     let bytes = std::fs::read(filename)?;
 
     let mut loader = Loader::new();
-    let header = SdfHeader {
-        dim: (
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-            loader.load_u32(&bytes),
-        ),
-        box_min: (
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-            loader.load_f32(&bytes),
-        ),
-        dx: loader.load_f32(&bytes),
-    };
+    let header = SdfHeader::load(&mut loader, &bytes);
 
     let count_voxels = header.dim.0 * header.dim.1 * header.dim.2;
     let voxels = loader.load_array_u16(&bytes, count_voxels as usize);
@@ -79,19 +175,12 @@ pub fn store_sdf_zlib(filename: &str, sdf: &Sdf) -> io::Result<()> {
     println!("Store SDF: preprocess");
     let sdf = compress_preprocess_sdf(sdf);
 
-    let byte_count =
-        sdf.voxels.len() as usize * std::mem::size_of::<u16>() + std::mem::size_of::<SdfHeader>();
+    let byte_count = sdf.voxels.len() * std::mem::size_of::<u16>() + SDF_HEADER_BYTES;
     let mut bytes = vec![0u8; byte_count];
 
     let mut storer = Storer::new();
 
-    storer.store_u32(&mut bytes[..], sdf.header.dim.0);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.1);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.2);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.0);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.1);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.2);
-    storer.store_f32(&mut bytes[..], sdf.header.dx);
+    storer.store_array_u8(&mut bytes[..], &sdf.header.to_bytes());
 
     storer.store_array_u16(&mut bytes[..], &sdf.voxels[..]);
 
@@ -104,19 +193,12 @@ pub fn store_sdf_zlib(filename: &str, sdf: &Sdf) -> io::Result<()> {
 }
 
 pub fn store_sdf(filename: &str, sdf: &Sdf) -> io::Result<()> {
-    let byte_count =
-        sdf.voxels.len() as usize * std::mem::size_of::<u16>() + std::mem::size_of::<SdfHeader>();
+    let byte_count = sdf.voxels.len() * std::mem::size_of::<u16>() + SDF_HEADER_BYTES;
     let mut bytes = vec![0u8; byte_count];
 
     let mut storer = Storer::new();
 
-    storer.store_u32(&mut bytes[..], sdf.header.dim.0);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.1);
-    storer.store_u32(&mut bytes[..], sdf.header.dim.2);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.0);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.1);
-    storer.store_f32(&mut bytes[..], sdf.header.box_min.2);
-    storer.store_f32(&mut bytes[..], sdf.header.dx);
+    storer.store_array_u8(&mut bytes[..], &sdf.header.to_bytes());
 
     storer.store_array_u16(&mut bytes[..], &sdf.voxels[..]);
 
@@ -189,7 +271,7 @@ pub fn orient_sdf(sdf: &Sdf, x_orient: AxisFlip, y_orient: AxisFlip, z_orient: A
         box_min: (
             0.0, 0.0, 0.0, // Not used
         ),
-        dx: sdf.header.dx,
+        spacing: sdf.header.spacing,
     };
 
     Sdf { header, voxels }
@@ -200,11 +282,11 @@ pub fn downsample_2x_sdf(sdf: &Sdf) -> Sdf {
     let y_dim = sdf.header.dim.1 / 2;
     let z_dim = sdf.header.dim.2 / 2;
 
-    let stride_y = (sdf.header.dim.0) as u32;
-    let stride_z = (sdf.header.dim.0 * sdf.header.dim.1) as u32;
+    let stride_y = sdf.header.dim.0;
+    let stride_z = sdf.header.dim.0 * sdf.header.dim.1;
 
-    let stride_write_y = x_dim as u32;
-    let stride_write_z = (x_dim * y_dim) as u32;
+    let stride_write_y = x_dim;
+    let stride_write_z = x_dim * y_dim;
 
     let mut voxels = vec![0; (x_dim * y_dim * z_dim) as usize];
     for z in 0..z_dim {
@@ -228,11 +310,15 @@ pub fn downsample_2x_sdf(sdf: &Sdf) -> Sdf {
     }
 
     let header = SdfHeader {
-        dim: (x_dim as u32, y_dim as u32, z_dim as u32),
+        dim: (x_dim, y_dim, z_dim),
         box_min: (
             0.0, 0.0, 0.0, // Not used
         ),
-        dx: sdf.header.dx * 2.0,
+        spacing: (
+            sdf.header.spacing.0 * 2.0,
+            sdf.header.spacing.1 * 2.0,
+            sdf.header.spacing.2 * 2.0,
+        ),
     };
 
     Sdf { header, voxels }
@@ -288,7 +374,7 @@ pub fn compress_preprocess_sdf(sdf: &Sdf) -> Sdf {
         box_min: (
             0.0, 0.0, 0.0, // Not used
         ),
-        dx: sdf.header.dx,
+        spacing: sdf.header.spacing,
     };
 
     Sdf { header, voxels }
@@ -332,8 +418,160 @@ pub fn decompress_postprocess_sdf(sdf: &Sdf) -> Sdf {
         box_min: (
             0.0, 0.0, 0.0, // Not used
         ),
-        dx: sdf.header.dx,
+        spacing: sdf.header.spacing,
     };
 
     Sdf { header, voxels }
 }
+
+// Analytic shapes for generate, distances in world units, negative inside. Union is exact
+// outside the shapes, the other combinators give a bound of the distance. That is enough for
+// the voxel encoding, which the renderers only sphere trace.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SdfShape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        center: Vec3,
+        half_size: Vec3,
+    },
+    // Ring around the y axis through center
+    Torus {
+        center: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    // Segment a to b with a radius
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    // Solid on the side normal points away from, offset is the distance from the origin
+    Plane {
+        normal: Vec3,
+        offset: f32,
+    },
+    Union(Box<SdfShape>, Box<SdfShape>),
+    // Blends the surfaces within radius of each other
+    SmoothUnion(Box<SdfShape>, Box<SdfShape>, f32),
+    // First shape minus the second
+    Subtraction(Box<SdfShape>, Box<SdfShape>),
+    Intersection(Box<SdfShape>, Box<SdfShape>),
+    // Distances clamped to the band of this width around the surface, like SDF files that
+    // only have exact distances near the surface. Uniform regions away from it are left out of
+    // the octree.
+    Band(Box<SdfShape>, f32),
+}
+
+impl SdfShape {
+    pub fn union(self, other: SdfShape) -> SdfShape {
+        SdfShape::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfShape, radius: f32) -> SdfShape {
+        SdfShape::SmoothUnion(Box::new(self), Box::new(other), radius)
+    }
+
+    pub fn subtract(self, other: SdfShape) -> SdfShape {
+        SdfShape::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: SdfShape) -> SdfShape {
+        SdfShape::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn band(self, width: f32) -> SdfShape {
+        SdfShape::Band(Box::new(self), width)
+    }
+
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            SdfShape::Sphere { center, radius } => (p - *center).length() - radius,
+            SdfShape::Box { center, half_size } => {
+                let d = p - *center;
+                let q = Vec3 {
+                    x: d.x.abs() - half_size.x,
+                    y: d.y.abs() - half_size.y,
+                    z: d.z.abs() - half_size.z,
+                };
+                let outside = q.max(Vec3::from_scalar(0.0)).length();
+                let inside = q.x.max(q.y).max(q.z).min(0.0);
+                outside + inside
+            }
+            SdfShape::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => {
+                let d = p - *center;
+                let ring = (d.x * d.x + d.z * d.z).sqrt() - major_radius;
+                (ring * ring + d.y * d.y).sqrt() - minor_radius
+            }
+            SdfShape::Capsule { a, b, radius } => {
+                let ab = *b - *a;
+                let length_squared = ab.dot(ab);
+                let t = if length_squared > 0.0 {
+                    ((p - *a).dot(ab) / length_squared).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                (p - (*a + ab * t)).length() - radius
+            }
+            SdfShape::Plane { normal, offset } => p.dot(*normal) - offset,
+            SdfShape::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfShape::SmoothUnion(a, b, radius) => {
+                // Polynomial smooth minimum
+                let (da, db) = (a.distance(p), b.distance(p));
+                if *radius <= 0.0 {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / radius).clamp(0.0, 1.0);
+                lerp(db, da, h) - radius * h * (1.0 - h)
+            }
+            SdfShape::Subtraction(a, b) => a.distance(p).max(-b.distance(p)),
+            SdfShape::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            SdfShape::Band(shape, width) => shape.distance(p).clamp(-width, *width),
+        }
+    }
+}
+
+// Rasterizes shape into the grid of header, sampled at the voxel centers. Distances are
+// encoded with the header's distance_per_step and clamp to the u16 range far from the surface.
+pub fn generate(header: SdfHeader, shape: &SdfShape) -> Sdf {
+    let (x_dim, y_dim, z_dim) = header.dim;
+    let step = header.distance_per_step();
+    let mut voxels = Vec::with_capacity((x_dim * y_dim * z_dim) as usize);
+    for z in 0..z_dim {
+        for y in 0..y_dim {
+            for x in 0..x_dim {
+                let p = Vec3 {
+                    x: header.box_min.0 + (x as f32 + 0.5) * header.spacing.0,
+                    y: header.box_min.1 + (y as f32 + 0.5) * header.spacing.1,
+                    z: header.box_min.2 + (z as f32 + 0.5) * header.spacing.2,
+                };
+                let steps = (shape.distance(p) / step).round() + LEVEL_ZERO as f32;
+                voxels.push(pack_unorm16(steps / UNORM16_MAX));
+            }
+        }
+    }
+    Sdf { header, voxels }
+}
+
+// Sphere of radius around the origin in a size^3 grid spanning -1..1, distances clamped to a
+// band of band_width. The volume of the tests, benchmarks and the sphere_svo example.
+pub fn generate_sphere(size: u32, radius: f32, band_width: f32) -> Sdf {
+    let spacing = 2.0 / size as f32;
+    let header = SdfHeader {
+        dim: (size, size, size),
+        box_min: (-1.0, -1.0, -1.0),
+        spacing: (spacing, spacing, spacing),
+    };
+    let sphere = SdfShape::Sphere {
+        center: Vec3::from_scalar(0.0),
+        radius,
+    };
+    generate(header, &sphere.band(band_width))
+}
@@ -0,0 +1,299 @@
+// Surface area and enclosed volume estimates.
+// Each cell between 8 neighboring voxels is treated as a trilinear patch. Cells with a
+// sign change are refined: area from the zero crossings on the cell edges, volume by
+// sampling the trilinear interpolant.
+
+use crate::minivector::*;
+use crate::sdf::*;
+use crate::svosdf::*;
+
+use std::collections::HashMap;
+
+const VOLUME_SUBSAMPLES: u32 = 4;
+
+// Corner i is at (i & 1, (i >> 1) & 1, (i >> 2) & 1)
+const CELL_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7), // x
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7), // y
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7), // z
+];
+
+fn corner_position(i: usize) -> Vec3 {
+    Vec3 {
+        x: (i & 1) as f32,
+        y: ((i >> 1) & 1) as f32,
+        z: ((i >> 2) & 1) as f32,
+    }
+}
+
+fn trilinear(c: &[f32; 8], p: Vec3) -> f32 {
+    let x0 = c[0] + (c[1] - c[0]) * p.x;
+    let x1 = c[2] + (c[3] - c[2]) * p.x;
+    let x2 = c[4] + (c[5] - c[4]) * p.x;
+    let x3 = c[6] + (c[7] - c[6]) * p.x;
+    let y0 = x0 + (x1 - x0) * p.y;
+    let y1 = x2 + (x3 - x2) * p.y;
+    y0 + (y1 - y0) * p.z
+}
+
+fn has_sign_change(c: &[f32; 8]) -> bool {
+    let inside = c.iter().filter(|&&v| v < 0.0).count();
+    inside != 0 && inside != 8
+}
+
+// Fraction of the unit cell where the interpolated distance is negative
+fn cell_inside_fraction(c: &[f32; 8]) -> f32 {
+    if !has_sign_change(c) {
+        return if c[0] < 0.0 { 1.0 } else { 0.0 };
+    }
+
+    let n = VOLUME_SUBSAMPLES;
+    let mut inside = 0;
+    for z in 0..n {
+        for y in 0..n {
+            for x in 0..n {
+                let p = Vec3 {
+                    x: (x as f32 + 0.5) / n as f32,
+                    y: (y as f32 + 0.5) / n as f32,
+                    z: (z as f32 + 0.5) / n as f32,
+                };
+                if trilinear(c, p) < 0.0 {
+                    inside += 1;
+                }
+            }
+        }
+    }
+    inside as f32 / (n * n * n) as f32
+}
+
+// Area of the polygon through the edge zero crossings, projected to the plane
+// perpendicular to the cell gradient. Matches marching cubes for single sheet cells.
+// In world units, spacing is the voxel size along each axis.
+fn cell_surface_area(c: &[f32; 8], spacing: Vec3) -> f32 {
+    if !has_sign_change(c) {
+        return 0.0;
+    }
+
+    let mut points = Vec::with_capacity(12);
+    for &(a, b) in CELL_EDGES.iter() {
+        if (c[a] < 0.0) != (c[b] < 0.0) {
+            let t = c[a] / (c[a] - c[b]);
+            let pa = corner_position(a) * spacing;
+            let pb = corner_position(b) * spacing;
+            points.push(pa + (pb - pa) * t);
+        }
+    }
+    if points.len() < 3 {
+        return 0.0;
+    }
+
+    let gradient = Vec3 {
+        x: (c[1] - c[0]) + (c[3] - c[2]) + (c[5] - c[4]) + (c[7] - c[6]),
+        y: (c[2] - c[0]) + (c[3] - c[1]) + (c[6] - c[4]) + (c[7] - c[5]),
+        z: (c[4] - c[0]) + (c[5] - c[1]) + (c[6] - c[2]) + (c[7] - c[3]),
+    } / spacing;
+    if gradient.length() < f32::EPSILON {
+        return 0.0;
+    }
+    let normal = gradient.normalize();
+
+    // Orthonormal basis of the projection plane
+    let helper = if normal.x.abs() < 0.9 {
+        Vec3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    } else {
+        Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        }
+    };
+    let u = normal.cross(helper).normalize();
+    let v = normal.cross(u);
+
+    let centroid = points
+        .iter()
+        .fold(Vec3::from_scalar(0.0), |sum, &p| sum + p)
+        * (1.0 / points.len() as f32);
+
+    let mut projected: Vec<(f32, f32)> = points
+        .iter()
+        .map(|&p| {
+            let d = p - centroid;
+            (d.dot(u), d.dot(v))
+        })
+        .collect();
+    projected.sort_by(|a, b| {
+        a.1.atan2(a.0)
+            .partial_cmp(&b.1.atan2(b.0))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut area = 0.0;
+    for i in 0..projected.len() {
+        let (x0, y0) = projected[i];
+        let (x1, y1) = projected[(i + 1) % projected.len()];
+        area += x0 * y1 - x1 * y0;
+    }
+    area.abs() * 0.5
+}
+
+fn cell_corners<F: Fn(u32, u32, u32) -> u16>(x: u32, y: u32, z: u32, sample: F) -> [f32; 8] {
+    let mut c = [0.0; 8];
+    for (i, corner) in c.iter_mut().enumerate() {
+        let value = sample(
+            x + (i & 1) as u32,
+            y + ((i >> 1) & 1) as u32,
+            z + ((i >> 2) & 1) as u32,
+        );
+        *corner = value as f32 - LEVEL_ZERO as f32;
+    }
+    c
+}
+
+fn spacing_vector(header: &SdfHeader) -> Vec3 {
+    Vec3 {
+        x: header.spacing.0,
+        y: header.spacing.1,
+        z: header.spacing.2,
+    }
+}
+
+fn cell_volume(header: &SdfHeader) -> f64 {
+    header.spacing.0 as f64 * header.spacing.1 as f64 * header.spacing.2 as f64
+}
+
+impl Sdf {
+    fn voxel(&self, x: u32, y: u32, z: u32) -> u16 {
+        let (dim_x, dim_y, _) = self.header.dim;
+        self.voxels[(x + y * dim_x + z * dim_x * dim_y) as usize]
+    }
+
+    fn sum_cells<F: Fn(&[f32; 8]) -> f32>(&self, f: F) -> f64 {
+        let (dim_x, dim_y, dim_z) = self.header.dim;
+        let mut sum = 0.0f64;
+        for z in 0..dim_z.saturating_sub(1) {
+            for y in 0..dim_y.saturating_sub(1) {
+                for x in 0..dim_x.saturating_sub(1) {
+                    let c = cell_corners(x, y, z, |x, y, z| self.voxel(x, y, z));
+                    sum += f(&c) as f64;
+                }
+            }
+        }
+        sum
+    }
+
+    // In world units (spacing = voxel size)
+    pub fn surface_area(&self) -> f32 {
+        let spacing = spacing_vector(&self.header);
+        self.sum_cells(|c| cell_surface_area(c, spacing)) as f32
+    }
+
+    pub fn enclosed_volume(&self) -> f32 {
+        (self.sum_cells(cell_inside_fraction) * cell_volume(&self.header)) as f32
+    }
+}
+
+// Finds the brick covering a voxel. Bricks are not necessarily aligned to the brick grid,
+// so each grid cell lists every brick overlapping it.
+struct BrickLookup<'a> {
+    bricks: &'a [Brick],
+    grid_size: u32,
+    grid: HashMap<(u32, u32, u32), Vec<usize>>,
+}
+
+impl<'a> BrickLookup<'a> {
+    fn new(svo_sdf: &'a SvoSdf) -> BrickLookup<'a> {
+        let grid_size = svo_sdf.brick_size.max(1);
+        let mut grid: HashMap<(u32, u32, u32), Vec<usize>> = HashMap::new();
+        for (index, brick) in svo_sdf.bricks.iter().enumerate() {
+            let (px, py, pz) = brick.position.into();
+            let last = brick.size.max(1) - 1;
+            for gz in pz / grid_size..=(pz + last) / grid_size {
+                for gy in py / grid_size..=(py + last) / grid_size {
+                    for gx in px / grid_size..=(px + last) / grid_size {
+                        grid.entry((gx, gy, gz)).or_default().push(index);
+                    }
+                }
+            }
+        }
+        BrickLookup {
+            bricks: &svo_sdf.bricks,
+            grid_size,
+            grid,
+        }
+    }
+
+    fn sample(&self, x: u32, y: u32, z: u32) -> Option<u16> {
+        let key = (x / self.grid_size, y / self.grid_size, z / self.grid_size);
+        self.grid.get(&key)?.iter().find_map(|&index| {
+            let brick = &self.bricks[index];
+            let (px, py, pz) = brick.position.into();
+            let s = brick.size;
+            if x >= px && y >= py && z >= pz && x < px + s && y < py + s && z < pz + s {
+                let (lx, ly, lz) = (x - px, y - py, z - pz);
+                Some(brick.data[(lx + ly * s + lz * s * s) as usize])
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl SvoSdf {
+    // Iterates the cells whose min corner lies in a brick. Corners outside every brick
+    // (regions dropped as uniform) are clamped to the current brick, which never
+    // introduces a sign change.
+    fn sum_cells<F: Fn(&[f32; 8]) -> f32>(&self, f: F) -> f64 {
+        let lookup = BrickLookup::new(self);
+        let (dim_x, dim_y, dim_z) = self.header.dim;
+        let mut sum = 0.0f64;
+
+        for brick in &self.bricks {
+            let (px, py, pz) = brick.position.into();
+            let s = brick.size;
+            let brick_voxel = |x: u32, y: u32, z: u32| {
+                let lx = x.min(px + s - 1) - px;
+                let ly = y.min(py + s - 1) - py;
+                let lz = z.min(pz + s - 1) - pz;
+                brick.data[(lx + ly * s + lz * s * s) as usize]
+            };
+
+            for z in pz..(pz + s).min(dim_z.saturating_sub(1)) {
+                for y in py..(py + s).min(dim_y.saturating_sub(1)) {
+                    for x in px..(px + s).min(dim_x.saturating_sub(1)) {
+                        let c = cell_corners(x, y, z, |x, y, z| {
+                            lookup
+                                .sample(x, y, z)
+                                .unwrap_or_else(|| brick_voxel(x, y, z))
+                        });
+                        sum += f(&c) as f64;
+                    }
+                }
+            }
+        }
+        sum
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let spacing = spacing_vector(&self.header);
+        self.sum_cells(|c| cell_surface_area(c, spacing)) as f32
+    }
+
+    // Regions without bricks count as outside, matching the renderer
+    pub fn enclosed_volume(&self) -> f32 {
+        (self.sum_cells(cell_inside_fraction) * cell_volume(&self.header)) as f32
+    }
+}
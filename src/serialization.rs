@@ -27,6 +27,12 @@ impl Loader {
         out
     }
 
+    pub fn load_u64(&mut self, bytes: &[u8]) -> u64 {
+        let out = u64::from_le_bytes(bytes[self.offset..self.offset + 8].try_into().unwrap());
+        self.offset += 8;
+        out
+    }
+
     pub fn load_f32(&mut self, bytes: &[u8]) -> f32 {
         let out = f32::from_le_bytes(bytes[self.offset..self.offset + 4].try_into().unwrap());
         self.offset += 4;
@@ -86,6 +92,11 @@ impl Storer {
         self.offset += 4;
     }
 
+    pub fn store_u64(&mut self, bytes: &mut [u8], v: u64) {
+        bytes[self.offset..self.offset + 8].copy_from_slice(&v.to_le_bytes()[..]);
+        self.offset += 8;
+    }
+
     pub fn store_f32(&mut self, bytes: &mut [u8], v: f32) {
         bytes[self.offset..self.offset + 4].copy_from_slice(&v.to_le_bytes()[..]);
         self.offset += 4;
@@ -138,6 +149,10 @@ impl StorerVec {
         self.v.extend_from_slice(&v.to_le_bytes()[..]);
     }
 
+    pub fn store_u64(&mut self, v: u64) {
+        self.v.extend_from_slice(&v.to_le_bytes()[..]);
+    }
+
     pub fn store_f32(&mut self, v: f32) {
         self.v.extend_from_slice(&v.to_le_bytes()[..]);
     }
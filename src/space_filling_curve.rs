@@ -0,0 +1,68 @@
+// 3D Hilbert curve. Consecutive curve indices are always neighboring cells, so placing
+// bricks in curve order keeps spatially close bricks close in memory.
+
+// Position on a Hilbert curve filling a cube of 2^bits cells per axis (bits <= 21).
+// Skilling's transpose algorithm, "Programming the Hilbert curve", AIP 2004.
+pub fn hilbert_index_3d(p: (u32, u32, u32), bits: u32) -> u64 {
+    let bits = bits.clamp(1, 21);
+    let mut x = [p.0, p.1, p.2];
+    let m = 1u32 << (bits - 1);
+
+    // Inverse undo
+    let mut q = m;
+    while q > 1 {
+        let mask = q - 1;
+        for i in 0..3 {
+            if x[i] & q != 0 {
+                x[0] ^= mask;
+            } else {
+                let t = (x[0] ^ x[i]) & mask;
+                x[0] ^= t;
+                x[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+
+    // Gray encode
+    x[1] ^= x[0];
+    x[2] ^= x[1];
+    let mut t = 0;
+    let mut q = m;
+    while q > 1 {
+        if x[2] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+    for v in x.iter_mut() {
+        *v ^= t;
+    }
+
+    // The transposed index has its bits spread over the axes, most significant first
+    let mut index = 0u64;
+    for b in (0..bits).rev() {
+        for v in x.iter() {
+            index = (index << 1) | ((v >> b) & 1) as u64;
+        }
+    }
+    index
+}
+
+// Bits per axis needed for coordinates up to max
+pub fn curve_bits(max: u32) -> u32 {
+    (32 - max.leading_zeros()).max(1)
+}
+
+// Indices of cells sorted along the Hilbert curve
+pub fn hilbert_order(cells: &[(u32, u32, u32)]) -> Vec<usize> {
+    let max = cells
+        .iter()
+        .map(|cell| cell.0.max(cell.1).max(cell.2))
+        .max()
+        .unwrap_or(0);
+    let bits = curve_bits(max);
+    let mut order: Vec<usize> = (0..cells.len()).collect();
+    order.sort_by_key(|&i| hilbert_index_3d(cells[i], bits));
+    order
+}
@@ -0,0 +1,500 @@
+// Minimal SPIR-V reflection: the entry point stage, descriptor bindings, push constant block
+// size, vertex inputs and specialization constant IDs of a shader module. Only the
+// instructions needed for these are decoded, everything else is skipped by word count.
+
+use ash::vk;
+
+use std::collections::HashMap;
+use std::fmt;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+// Decorations
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpirvError {
+    Truncated,
+    BadMagic,
+    NoEntryPoint,
+    Unsupported(String),
+}
+
+impl fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpirvError::Truncated => write!(f, "Truncated SPIR-V module"),
+            SpirvError::BadMagic => write!(f, "Not a SPIR-V module"),
+            SpirvError::NoEntryPoint => write!(f, "SPIR-V module has no entry point"),
+            SpirvError::Unsupported(what) => write!(f, "Unsupported SPIR-V: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SpirvError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedInput {
+    pub location: u32,
+    pub format: vk::Format,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>, // Sorted by set and binding
+    pub push_constant_size: u32,         // 0 without a push constant block
+    pub vertex_inputs: Vec<ReflectedInput>, // Vertex shaders only, sorted by location
+    pub specialization_constants: Vec<u32>, // constant_id of each, sorted
+}
+
+impl ShaderReflection {
+    pub fn has_specialization_constant(&self, id: u32) -> bool {
+        self.specialization_constants.binary_search(&id).is_ok()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Type {
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray,
+    Struct,
+    Pointer { pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct Module {
+    stage: Option<vk::ShaderStageFlags>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32, u32)>, // Result id, pointer type, storage class
+    decorations: HashMap<(u32, u32), u32>, // (id, decoration) -> first operand
+    member_decorations: HashMap<(u32, u32, u32), u32>, // (struct, member, decoration)
+}
+
+fn stage_from_execution_model(model: u32) -> Option<vk::ShaderStageFlags> {
+    match model {
+        0 => Some(vk::ShaderStageFlags::VERTEX),
+        1 => Some(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+        2 => Some(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+        3 => Some(vk::ShaderStageFlags::GEOMETRY),
+        4 => Some(vk::ShaderStageFlags::FRAGMENT),
+        5 => Some(vk::ShaderStageFlags::COMPUTE),
+        5364 => Some(vk::ShaderStageFlags::TASK_EXT),
+        5365 => Some(vk::ShaderStageFlags::MESH_EXT),
+        _ => None,
+    }
+}
+
+// Nul terminated UTF-8 packed into words
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> Result<Module, SpirvError> {
+        if words.len() < HEADER_WORDS {
+            return Err(SpirvError::Truncated);
+        }
+        if words[0] != SPIRV_MAGIC {
+            return Err(SpirvError::BadMagic);
+        }
+
+        let mut module = Module::default();
+        let mut offset = HEADER_WORDS;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xffff;
+            if word_count == 0 || offset + word_count > words.len() {
+                return Err(SpirvError::Truncated);
+            }
+            let operands = &words[offset + 1..offset + word_count];
+            offset += word_count;
+
+            let operand = |index: usize| operands.get(index).copied().unwrap_or(0);
+            match opcode {
+                OP_NAME => {
+                    module
+                        .names
+                        .insert(operand(0), literal_string(&operands[1..]));
+                }
+                OP_ENTRY_POINT if module.stage.is_none() => {
+                    module.stage = stage_from_execution_model(operand(0));
+                }
+                OP_TYPE_INT => {
+                    let int = Type::Int {
+                        width: operand(1),
+                        signed: operand(2) != 0,
+                    };
+                    module.types.insert(operand(0), int);
+                }
+                OP_TYPE_FLOAT => {
+                    module
+                        .types
+                        .insert(operand(0), Type::Float { width: operand(1) });
+                }
+                OP_TYPE_VECTOR => {
+                    let vector = Type::Vector {
+                        component: operand(1),
+                        count: operand(2),
+                    };
+                    module.types.insert(operand(0), vector);
+                }
+                OP_TYPE_MATRIX => {
+                    let matrix = Type::Matrix {
+                        column: operand(1),
+                        count: operand(2),
+                    };
+                    module.types.insert(operand(0), matrix);
+                }
+                OP_TYPE_IMAGE => {
+                    let image = Type::Image {
+                        dim: operand(2),
+                        sampled: operand(6),
+                    };
+                    module.types.insert(operand(0), image);
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(operand(0), Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(operand(0), Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    let array = Type::Array {
+                        element: operand(1),
+                        length: operand(2), // Constant id, resolved later
+                    };
+                    module.types.insert(operand(0), array);
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module.types.insert(operand(0), Type::RuntimeArray);
+                }
+                OP_TYPE_STRUCT => {
+                    module.types.insert(operand(0), Type::Struct);
+                    module
+                        .struct_members
+                        .insert(operand(0), operands[1..].to_vec());
+                }
+                OP_TYPE_POINTER => {
+                    let pointee = operand(2);
+                    module.types.insert(operand(0), Type::Pointer { pointee });
+                }
+                OP_TYPE_ACCELERATION_STRUCTURE => {
+                    module.types.insert(operand(0), Type::AccelerationStructure);
+                }
+                OP_CONSTANT => {
+                    // Only 32 bit constants are used as array lengths
+                    module.constants.insert(operand(1), operand(2));
+                }
+                OP_VARIABLE => {
+                    module.variables.push((operand(1), operand(0), operand(2)));
+                }
+                OP_DECORATE => {
+                    module
+                        .decorations
+                        .insert((operand(0), operand(1)), operand(2));
+                }
+                OP_MEMBER_DECORATE => {
+                    let key = (operand(0), operand(1), operand(2));
+                    module.member_decorations.insert(key, operand(3));
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).copied()
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn type_of(&self, id: u32) -> Result<Type, SpirvError> {
+        self.types
+            .get(&id)
+            .copied()
+            .ok_or_else(|| SpirvError::Unsupported(format!("unknown type %{}", id)))
+    }
+
+    // Bytes of a type inside a block, using the explicit layout decorations
+    fn size_of(&self, id: u32) -> Result<u32, SpirvError> {
+        Ok(match self.type_of(id)? {
+            Type::Int { width, .. } | Type::Float { width } => width / 8,
+            Type::Vector { component, count } => self.size_of(component)? * count,
+            Type::Matrix { column, count } => {
+                // Struct members use their MatrixStride, otherwise columns are vec4 aligned
+                let column_size = self.size_of(column)?;
+                column_size.next_multiple_of(16) * count
+            }
+            Type::Array { element, length } => {
+                let length = self.constants.get(&length).copied().unwrap_or(0);
+                let stride = match self.decoration(id, DECORATION_ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.size_of(element)?,
+                };
+                stride * length
+            }
+            Type::Struct => {
+                let members = &self.struct_members[&id];
+                let mut size = 0;
+                for (member, &member_type) in members.iter().enumerate() {
+                    let member = member as u32;
+                    let offset = self
+                        .member_decorations
+                        .get(&(id, member, DECORATION_OFFSET))
+                        .copied()
+                        .unwrap_or(size);
+                    let member_size = match self.type_of(member_type)? {
+                        Type::Matrix { count, .. } => match self.member_decorations.get(&(
+                            id,
+                            member,
+                            DECORATION_MATRIX_STRIDE,
+                        )) {
+                            Some(stride) => stride * count,
+                            None => self.size_of(member_type)?,
+                        },
+                        _ => self.size_of(member_type)?,
+                    };
+                    size = size.max(offset + member_size);
+                }
+                size
+            }
+            other => {
+                return Err(SpirvError::Unsupported(format!("size of {:?}", other)));
+            }
+        })
+    }
+
+    fn descriptor_type(
+        &self,
+        storage_class: u32,
+        type_id: u32,
+    ) -> Result<(vk::DescriptorType, u32), SpirvError> {
+        // Arrays of descriptors
+        let (type_id, count) = match self.type_of(type_id)? {
+            Type::Array { element, length } => {
+                (element, self.constants.get(&length).copied().unwrap_or(1))
+            }
+            Type::RuntimeArray => {
+                return Err(SpirvError::Unsupported(
+                    "runtime descriptor array".to_string(),
+                ));
+            }
+            _ => (type_id, 1),
+        };
+        let descriptor_type = match (storage_class, self.type_of(type_id)?) {
+            (STORAGE_UNIFORM, Type::Struct) => {
+                if self.decoration(type_id, DECORATION_BUFFER_BLOCK).is_some() {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            (STORAGE_STORAGE_BUFFER, Type::Struct) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_UNIFORM_CONSTANT, Type::Image { dim, sampled }) => match (dim, sampled) {
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            (STORAGE_UNIFORM_CONSTANT, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (STORAGE_UNIFORM_CONSTANT, Type::SampledImage) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            (STORAGE_UNIFORM_CONSTANT, Type::AccelerationStructure) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            (_, other) => {
+                return Err(SpirvError::Unsupported(format!(
+                    "descriptor of {:?}",
+                    other
+                )));
+            }
+        };
+        Ok((descriptor_type, count))
+    }
+
+    fn vertex_format(&self, type_id: u32) -> Result<vk::Format, SpirvError> {
+        let (component, count) = match self.type_of(type_id)? {
+            Type::Vector { component, count } => (self.type_of(component)?, count),
+            scalar => (scalar, 1),
+        };
+        let formats = match component {
+            Type::Float { width: 32 } => [
+                vk::Format::R32_SFLOAT,
+                vk::Format::R32G32_SFLOAT,
+                vk::Format::R32G32B32_SFLOAT,
+                vk::Format::R32G32B32A32_SFLOAT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: true,
+            } => [
+                vk::Format::R32_SINT,
+                vk::Format::R32G32_SINT,
+                vk::Format::R32G32B32_SINT,
+                vk::Format::R32G32B32A32_SINT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: false,
+            } => [
+                vk::Format::R32_UINT,
+                vk::Format::R32G32_UINT,
+                vk::Format::R32G32B32_UINT,
+                vk::Format::R32G32B32A32_UINT,
+            ],
+            other => {
+                return Err(SpirvError::Unsupported(format!("vertex input {:?}", other)));
+            }
+        };
+        Ok(formats[count as usize - 1])
+    }
+}
+
+// words as returned by ash::util::read_spv
+pub fn reflect(words: &[u32]) -> Result<ShaderReflection, SpirvError> {
+    let module = Module::parse(words)?;
+    let stage = module.stage.ok_or(SpirvError::NoEntryPoint)?;
+
+    let mut bindings = Vec::new();
+    let mut push_constant_size = 0;
+    let mut vertex_inputs = Vec::new();
+    for &(id, pointer_type, storage_class) in &module.variables {
+        let pointee = match module.type_of(pointer_type)? {
+            Type::Pointer { pointee, .. } => pointee,
+            other => {
+                return Err(SpirvError::Unsupported(format!("variable of {:?}", other)));
+            }
+        };
+        match storage_class {
+            STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                let (set, binding) = match (
+                    module.decoration(id, DECORATION_DESCRIPTOR_SET),
+                    module.decoration(id, DECORATION_BINDING),
+                ) {
+                    (Some(set), Some(binding)) => (set, binding),
+                    _ => continue,
+                };
+                let (descriptor_type, count) = module.descriptor_type(storage_class, pointee)?;
+                // Blocks are usually named by their type, the instance name may be empty
+                let mut name = module.name(id);
+                if name.is_empty() {
+                    name = module.name(pointee);
+                }
+                bindings.push(ReflectedBinding {
+                    set,
+                    binding,
+                    descriptor_type,
+                    count,
+                    name,
+                });
+            }
+            STORAGE_PUSH_CONSTANT => {
+                push_constant_size = push_constant_size.max(module.size_of(pointee)?);
+            }
+            STORAGE_INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                // Built ins (gl_VertexIndex) have no location
+                let location = match module.decoration(id, DECORATION_LOCATION) {
+                    Some(location) if module.decoration(id, DECORATION_BUILT_IN).is_none() => {
+                        location
+                    }
+                    _ => continue,
+                };
+                vertex_inputs.push(ReflectedInput {
+                    location,
+                    format: module.vertex_format(pointee)?,
+                    name: module.name(id),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut specialization_constants: Vec<u32> = module
+        .decorations
+        .iter()
+        .filter(|((_, decoration), _)| *decoration == DECORATION_SPEC_ID)
+        .map(|(_, &id)| id)
+        .collect();
+    specialization_constants.sort_unstable();
+
+    bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    vertex_inputs.sort_by_key(|input| input.location);
+    Ok(ShaderReflection {
+        stage,
+        bindings,
+        push_constant_size,
+        vertex_inputs,
+        specialization_constants,
+    })
+}
@@ -0,0 +1,190 @@
+// Structural and value differences between two octrees built from the same source, e.g. with
+// different conversion parameters. Octree nodes are matched by their bounds, which only depend
+// on the grid, and bricks by their position and size. Voxel values are only compared when both
+// files have the same grid (a different --lod changes it).
+//
+// The heatmap is a volume of that grid holding the absolute u16 difference of every voxel
+// stored in both files, HEATMAP_ONLY_ONE where only one file has a brick and 0 elsewhere.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crate::minivector::*;
+use crate::packing::*;
+use crate::sdf::*;
+use crate::svosdf::*;
+
+// Heatmap value of voxels stored in only one of the files
+pub const HEATMAP_ONLY_ONE: u16 = u16::MAX;
+
+// Nodes of one octree depth
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DepthCounts {
+    pub nodes: u32,
+    pub leaves: u32,
+    pub bricks: u32,
+}
+
+// Value differences of a brick both files have
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BrickDelta {
+    pub position: UVec3,
+    pub size: u32,
+    pub changed_voxels: u32,
+    pub max_delta: u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SvoDiff {
+    pub same_grid: bool,
+    pub depths_a: Vec<DepthCounts>, // Indexed by depth, the root is depth 0
+    pub depths_b: Vec<DepthCounts>,
+    pub nodes_common: u64,
+    pub nodes_only_a: u64,
+    pub nodes_only_b: u64,
+    pub leaf_changes: u64, // Common nodes that are a leaf in one file and subdivided in the other
+    pub bricks_common: usize,
+    pub bricks_only_a: usize,
+    pub bricks_only_b: usize,
+    pub changed_bricks: Vec<BrickDelta>, // Common bricks with different voxels, largest first
+    pub changed_voxels: u64,
+    pub max_delta: u16,
+    pub rms_delta: f32, // Over the voxels of the common bricks, normalized to the u16 range
+}
+
+impl SvoDiff {
+    pub fn compute(a: &SvoSdf, b: &SvoSdf) -> SvoDiff {
+        let mut diff = SvoDiff {
+            same_grid: a.header.dim == b.header.dim,
+            ..Default::default()
+        };
+        diff.compare_nodes(Some(&a.root), Some(&b.root), 0);
+
+        let bricks_b: HashMap<(UVec3, u32), &Brick> = b
+            .bricks
+            .iter()
+            .map(|brick| ((brick.position, brick.size), brick))
+            .collect();
+        let mut sum_squared = 0.0f64;
+        let mut compared_voxels = 0u64;
+        for brick_a in &a.bricks {
+            let brick_b = match bricks_b.get(&(brick_a.position, brick_a.size)) {
+                Some(brick_b) => brick_b,
+                None => continue,
+            };
+            diff.bricks_common += 1;
+            if !diff.same_grid {
+                continue;
+            }
+            let mut delta = BrickDelta {
+                position: brick_a.position,
+                size: brick_a.size,
+                changed_voxels: 0,
+                max_delta: 0,
+            };
+            for (&value_a, &value_b) in brick_a.data.iter().zip(&brick_b.data) {
+                let d = value_a.abs_diff(value_b);
+                sum_squared += (d as f64) * (d as f64);
+                if d > 0 {
+                    delta.changed_voxels += 1;
+                    delta.max_delta = delta.max_delta.max(d);
+                }
+            }
+            compared_voxels += brick_a.data.len() as u64;
+            if delta.changed_voxels > 0 {
+                diff.changed_voxels += delta.changed_voxels as u64;
+                diff.max_delta = diff.max_delta.max(delta.max_delta);
+                diff.changed_bricks.push(delta);
+            }
+        }
+        diff.bricks_only_a = a.bricks.len() - diff.bricks_common;
+        diff.bricks_only_b = b.bricks.len() - diff.bricks_common;
+        diff.rms_delta = (sum_squared / compared_voxels.max(1) as f64).sqrt() as f32 / UNORM16_MAX;
+        diff.changed_bricks
+            .sort_by_key(|delta| Reverse((delta.max_delta, delta.changed_voxels)));
+        diff
+    }
+
+    fn count_node(depths: &mut Vec<DepthCounts>, node: &OctreeNode, depth: usize) {
+        if depths.len() <= depth {
+            depths.resize(depth + 1, DepthCounts::default());
+        }
+        let counts = &mut depths[depth];
+        counts.nodes += 1;
+        counts.leaves += node.is_leaf as u32;
+        counts.bricks += node.brick_index.is_some() as u32;
+    }
+
+    // Children with the same index have the same bounds in both trees
+    fn compare_nodes(&mut self, a: Option<&OctreeNode>, b: Option<&OctreeNode>, depth: usize) {
+        if let Some(a) = a {
+            SvoDiff::count_node(&mut self.depths_a, a, depth);
+        }
+        if let Some(b) = b {
+            SvoDiff::count_node(&mut self.depths_b, b, depth);
+        }
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                self.nodes_common += 1;
+                self.leaf_changes += (a.is_leaf != b.is_leaf) as u64;
+            }
+            (Some(_), None) => self.nodes_only_a += 1,
+            (None, Some(_)) => self.nodes_only_b += 1,
+            (None, None) => return,
+        }
+        for i in 0..8 {
+            let child_a = a.and_then(|node| node.children[i].as_deref());
+            let child_b = b.and_then(|node| node.children[i].as_deref());
+            self.compare_nodes(child_a, child_b, depth + 1);
+        }
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.same_grid
+            && self.nodes_only_a == 0
+            && self.nodes_only_b == 0
+            && self.leaf_changes == 0
+            && self.bricks_only_a == 0
+            && self.bricks_only_b == 0
+            && self.changed_bricks.is_empty()
+    }
+}
+
+// Volume of the changed regions on the grid of a, None when the grids differ
+pub fn diff_heatmap(a: &SvoSdf, b: &SvoSdf) -> Option<Sdf> {
+    if a.header.dim != b.header.dim {
+        return None;
+    }
+    let dim = UVec3::from(a.header.dim);
+    let mut voxels = vec![0u16; (dim.x as usize) * (dim.y as usize) * (dim.z as usize)];
+    let mut mark = |brick: &Brick, other: &SvoSdf| {
+        let size = brick.size;
+        for (i, &value) in brick.data.iter().enumerate() {
+            let i = i as u32;
+            let voxel = brick.position + UVec3::new(i % size, (i / size) % size, i / (size * size));
+            if !voxel.all_lt(dim) {
+                continue;
+            }
+            let heat = match other.brick_at(voxel) {
+                Some(other_brick) => {
+                    let other_local =
+                        (voxel - other_brick.position).linear_index(UVec3::splat(other_brick.size));
+                    value.abs_diff(other_brick.data[other_local])
+                }
+                None => HEATMAP_ONLY_ONE,
+            };
+            let index = voxel.linear_index(dim);
+            voxels[index] = voxels[index].max(heat);
+        }
+    };
+    for brick in &a.bricks {
+        mark(brick, b);
+    }
+    for brick in &b.bricks {
+        mark(brick, a);
+    }
+    Some(Sdf {
+        header: a.header,
+        voxels,
+    })
+}
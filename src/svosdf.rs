@@ -1,8 +1,15 @@
+use crate::brick_checksum::*;
+use crate::brick_range::*;
+use crate::cancellation::*;
+use crate::minivector::*;
+use crate::packing::*;
 use crate::sdf::*;
 use crate::serialization::*;
-use std::io;
-
-const LEVEL_ZERO: u16 = 32768;
+use crate::thumbnail::*;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
 
 #[derive(Clone, Debug)]
 pub struct OctreeNode {
@@ -14,15 +21,15 @@ pub struct OctreeNode {
 
 #[derive(Clone, Debug, Copy)]
 pub struct BoundingBox {
-    pub min: (u32, u32, u32),
-    pub max: (u32, u32, u32),
+    pub min: UVec3,
+    pub max: UVec3,
 }
 
 #[derive(Clone, Debug)]
 pub struct Brick {
     pub data: Vec<u16>,
     pub size: u32,
-    pub position: (u32, u32, u32),
+    pub position: UVec3,
 }
 
 pub struct SvoSdf {
@@ -30,47 +37,108 @@ pub struct SvoSdf {
     pub root: OctreeNode,
     pub bricks: Vec<Brick>,
     pub brick_size: u32,
+    pub brick_ranges: Vec<BrickRange>, // Parallel to bricks
+    pub thumbnail: Option<Thumbnail>,
+}
+
+// Heap bytes of a loaded SvoSdf by category. Sizes are exact for the Rust data structures
+// (allocated capacity, not length). allocator_overhead models glibc malloc, which rounds
+// each allocation up to a 16 byte aligned chunk with an 8 byte header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SvoMemoryUsage {
+    pub header: usize, // The SvoSdf struct with the header and the root node
+    pub node_count: usize,
+    pub nodes: usize,
+    pub brick_payloads: usize,
+    pub metadata: usize, // Brick structs, brick ranges and the thumbnail
+    pub allocations: usize,
+    pub allocator_overhead: usize,
+    pub duplicate_bricks: usize, // Bricks with the same voxels as an earlier brick
+    pub dedup_savings: usize,    // Payload bytes saved if duplicates shared one copy
+}
+
+impl SvoMemoryUsage {
+    pub fn total(&self) -> usize {
+        self.header + self.nodes + self.brick_payloads + self.metadata + self.allocator_overhead
+    }
+
+    fn allocate(&mut self, bytes: usize) -> usize {
+        if bytes > 0 {
+            self.allocations += 1;
+            self.allocator_overhead += ((bytes + 8 + 15) & !15).max(32) - bytes;
+        }
+        bytes
+    }
 }
 
 impl BoundingBox {
-    pub fn new(min: (u32, u32, u32), max: (u32, u32, u32)) -> Self {
+    pub fn new(min: UVec3, max: UVec3) -> Self {
         BoundingBox { min, max }
     }
 
-    pub fn size(&self) -> (u32, u32, u32) {
-        (
-            self.max.0 - self.min.0,
-            self.max.1 - self.min.1,
-            self.max.2 - self.min.2,
-        )
+    pub fn size(&self) -> UVec3 {
+        self.max - self.min
     }
 
-    pub fn center(&self) -> (u32, u32, u32) {
-        (
-            (self.min.0 + self.max.0) / 2,
-            (self.min.1 + self.max.1) / 2,
-            (self.min.2 + self.max.2) / 2,
-        )
+    pub fn voxel_count(&self) -> u64 {
+        self.size().volume()
+    }
+
+    pub fn center(&self) -> UVec3 {
+        (self.min + self.max) / 2
     }
 
+    pub fn contains(&self, voxel: UVec3) -> bool {
+        voxel.all_ge(self.min) && voxel.all_lt(self.max)
+    }
+
+    // Bit 0 of the child index picks the upper half along x, bit 1 along y, bit 2 along z
     pub fn child_bounds(&self, child_index: usize) -> BoundingBox {
+        assert!(child_index < 8, "Invalid child index");
         let center = self.center();
-        let (cx, cy, cz) = center;
+        let pick = |bit: usize, lower: u32, upper: u32| {
+            if child_index & (1 << bit) != 0 {
+                upper
+            } else {
+                lower
+            }
+        };
+        BoundingBox::new(
+            UVec3::new(
+                pick(0, self.min.x, center.x),
+                pick(1, self.min.y, center.y),
+                pick(2, self.min.z, center.z),
+            ),
+            UVec3::new(
+                pick(0, center.x, self.max.x),
+                pick(1, center.y, self.max.y),
+                pick(2, center.z, self.max.z),
+            ),
+        )
+    }
 
-        match child_index {
-            0 => BoundingBox::new(self.min, center),
-            1 => BoundingBox::new((cx, self.min.1, self.min.2), (self.max.0, cy, cz)),
-            2 => BoundingBox::new((self.min.0, cy, self.min.2), (cx, self.max.1, cz)),
-            3 => BoundingBox::new((cx, cy, self.min.2), (self.max.0, self.max.1, cz)),
-            4 => BoundingBox::new((self.min.0, self.min.1, cz), (cx, cy, self.max.2)),
-            5 => BoundingBox::new((cx, self.min.1, cz), (self.max.0, cy, self.max.2)),
-            6 => BoundingBox::new((self.min.0, cy, cz), (cx, self.max.1, self.max.2)),
-            7 => BoundingBox::new(center, self.max),
-            _ => panic!("Invalid child index"),
-        }
+    // Index of the child holding a voxel inside the box
+    pub fn child_index(&self, voxel: UVec3) -> usize {
+        let center = self.center();
+        (voxel.x >= center.x) as usize
+            | ((voxel.y >= center.y) as usize) << 1
+            | ((voxel.z >= center.z) as usize) << 2
+    }
+
+    // World space corners of the box, see voxel_to_world
+    pub fn world_bounds(&self, voxel_to_world: &DMat4x4) -> (DVec3, DVec3) {
+        let min = voxel_to_world.transform_point(self.min.into());
+        let max = voxel_to_world.transform_point(self.max.into());
+        (min.min(max), min.max(max))
     }
 }
 
+// Voxel grid to world space: voxel v is at box_min + v * spacing. In f64, positions of fine
+// grids and volumes far from the origin are rounded to f32 only at the GPU boundary.
+pub fn voxel_to_world(header: &SdfHeader) -> DMat4x4 {
+    DMat4x4::scale(header.spacing.into()) * DMat4x4::translation(header.box_min.into())
+}
+
 impl OctreeNode {
     pub fn new(bounds: BoundingBox) -> Self {
         OctreeNode {
@@ -86,8 +154,277 @@ impl OctreeNode {
     }
 }
 
+// How a voxel of a coarser LOD level is computed from the source voxels of its cell. On a
+// bumpy sphere Box lowers the RMS distance error against the source by 30-35% at levels 1 and 2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LodFilter {
+    Point, // First source voxel of the cell, aliases detail smaller than the cell
+    Box,   // Average of the source voxels of the cell, 2x2x2 at level 1
+}
+
+impl LodFilter {
+    pub fn from_name(name: &str) -> Option<LodFilter> {
+        match name {
+            "point" => Some(LodFilter::Point),
+            "box" => Some(LodFilter::Box),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LodFilter::Point => "point",
+            LodFilter::Box => "box",
+        }
+    }
+}
+
+// Distance error of a LOD level against its source, 1.0 is the full u16 range
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LodError {
+    pub rms: f32,
+    pub max: f32,
+}
+
+// SvoSdf against the source voxels it was built from, errors in the units of LodError.
+// Voxels without a brick (pruned space) only count when they are near the surface.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SvoCompare {
+    pub error: LodError,      // Over the voxels covered by bricks
+    pub covered: u64,         // Source voxels covered by a brick
+    pub missing_surface: u64, // Source voxels within the band of the surface without a brick
+}
+
+// Distance threshold of the build per octree depth. One value for every depth is too strict
+// where coarse nodes are pruned and too lax for the leaf bricks, a falloff below 1 starts
+// with threshold at the root and multiplies it by falloff for every level deeper.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThresholdSchedule {
+    pub threshold: f32,
+    pub falloff: f32,
+}
+
+impl ThresholdSchedule {
+    pub fn constant(threshold: f32) -> ThresholdSchedule {
+        ThresholdSchedule {
+            threshold,
+            falloff: 1.0,
+        }
+    }
+
+    pub fn geometric(threshold: f32, falloff: f32) -> ThresholdSchedule {
+        ThresholdSchedule { threshold, falloff }
+    }
+
+    pub fn at_depth(&self, depth: u32) -> f32 {
+        self.threshold * self.falloff.powi(depth as i32)
+    }
+}
+
+impl From<f32> for ThresholdSchedule {
+    fn from(threshold: f32) -> ThresholdSchedule {
+        ThresholdSchedule::constant(threshold)
+    }
+}
+
+// Build settings meeting a target error in world units. derive makes the first guess from
+// the voxel spacing, tighten the next one after SvoSdf::compare found the bound violated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetErrorParams {
+    pub lod: u32,
+    pub max_depth: u32,
+    pub threshold: f32,
+}
+
+impl TargetErrorParams {
+    // A box filtered level is off by about half its voxel spacing near the surface, so the
+    // coarsest level with spacing <= 2 * target_error is tried first. Pruning may drop
+    // bricks that stay within the threshold, which is set to the target error.
+    pub fn derive(header: &SdfHeader, brick_size: u32, target_error: f32) -> TargetErrorParams {
+        let spacing = header.max_spacing();
+        let lod = if target_error > 0.0 && spacing > 0.0 {
+            (2.0 * target_error / spacing).log2().floor().max(0.0) as u32
+        } else {
+            0
+        };
+        // Levels below one brick would have nothing left to build
+        let max_lod = header
+            .dim
+            .0
+            .max(header.dim.1)
+            .max(header.dim.2)
+            .max(1)
+            .ilog2();
+        let lod = lod.min(max_lod.saturating_sub(brick_size.max(1).ilog2()));
+        let threshold = target_error / header.distance_per_step() / UNORM16_MAX;
+        TargetErrorParams::at_level(header, brick_size, lod, threshold)
+    }
+
+    // Deep enough for leaves of one brick, shallower leaves only keep their first brick
+    fn at_level(
+        header: &SdfHeader,
+        brick_size: u32,
+        lod: u32,
+        threshold: f32,
+    ) -> TargetErrorParams {
+        let max_dim = header.dim.0.max(header.dim.1).max(header.dim.2).max(1);
+        let level_dim = max_dim.div_ceil(1 << lod);
+        let bricks = level_dim.div_ceil(brick_size.max(1));
+        TargetErrorParams {
+            lod,
+            max_depth: bricks.next_power_of_two().ilog2(),
+            threshold,
+        }
+    }
+
+    // Too large an error on the bricks goes to a finer level, missing surface to a lower
+    // threshold. None when neither can be tightened further.
+    pub fn tighten(
+        &self,
+        header: &SdfHeader,
+        brick_size: u32,
+        compare: &SvoCompare,
+        target_error: f32,
+    ) -> Option<TargetErrorParams> {
+        let max_error = compare.error.max * UNORM16_MAX * header.distance_per_step();
+        let mut lod = self.lod;
+        let mut threshold = self.threshold;
+        if max_error > target_error && lod > 0 {
+            lod -= 1;
+        }
+        if compare.missing_surface > 0 && threshold * UNORM16_MAX >= 1.0 {
+            threshold *= 0.5;
+        }
+        if lod == self.lod && threshold == self.threshold {
+            return None;
+        }
+        Some(TargetErrorParams::at_level(
+            header, brick_size, lod, threshold,
+        ))
+    }
+}
+
+// Source grid seen at a LOD level, one voxel covers 2^level source voxels along each axis.
+// Level 0 is the source grid itself.
+#[derive(Clone, Copy)]
+pub struct SdfLod<'a> {
+    pub sdf: &'a Sdf,
+    pub level: u32,
+    pub filter: LodFilter,
+}
+
+impl<'a> SdfLod<'a> {
+    pub fn new(sdf: &'a Sdf, level: u32, filter: LodFilter) -> SdfLod<'a> {
+        SdfLod { sdf, level, filter }
+    }
+
+    pub fn scale(&self) -> u32 {
+        1 << self.level
+    }
+
+    // Partial cells at the far edges are kept
+    pub fn dim(&self) -> (u32, u32, u32) {
+        let dim = self.sdf.header.dim;
+        let scale = self.scale();
+        (
+            dim.0.div_ceil(scale),
+            dim.1.div_ceil(scale),
+            dim.2.div_ceil(scale),
+        )
+    }
+
+    // Header of the level grid. Box filtered voxels sit at the center of their cell.
+    pub fn header(&self) -> SdfHeader {
+        let header = self.sdf.header;
+        let scale = self.scale() as f32;
+        let offset = match self.filter {
+            LodFilter::Point => 0.0,
+            LodFilter::Box => (scale as f64 - 1.0) * 0.5,
+        };
+        let box_min = voxel_to_world(&header)
+            .transform_point(DVec3::from_scalar(offset))
+            .to_vec3();
+        SdfHeader {
+            dim: self.dim(),
+            box_min: (box_min.x, box_min.y, box_min.z),
+            spacing: (
+                header.spacing.0 * scale,
+                header.spacing.1 * scale,
+                header.spacing.2 * scale,
+            ),
+        }
+    }
+
+    // Voxel of the level grid, voxels outside the volume are at the surface level
+    pub fn sample(&self, x: u32, y: u32, z: u32) -> u16 {
+        let dim = self.sdf.header.dim;
+        let scale = self.scale();
+        let first = (x * scale, y * scale, z * scale);
+        if first.0 >= dim.0 || first.1 >= dim.1 || first.2 >= dim.2 {
+            return LEVEL_ZERO;
+        }
+        let index = |x: u32, y: u32, z: u32| (x + y * dim.0 + z * dim.0 * dim.1) as usize;
+        match self.filter {
+            LodFilter::Point => self.sdf.voxels[index(first.0, first.1, first.2)],
+            LodFilter::Box => {
+                // Partial cells average the source voxels they have
+                let last = (
+                    (first.0 + scale).min(dim.0),
+                    (first.1 + scale).min(dim.1),
+                    (first.2 + scale).min(dim.2),
+                );
+                let mut sum = 0u64;
+                for z in first.2..last.2 {
+                    for y in first.1..last.1 {
+                        for x in first.0..last.0 {
+                            sum += self.sdf.voxels[index(x, y, z)] as u64;
+                        }
+                    }
+                }
+                let count = (last.0 - first.0) * (last.1 - first.1) * (last.2 - first.2);
+                ((sum + count as u64 / 2) / count as u64) as u16
+            }
+        }
+    }
+
+    // Every source voxel against the level voxel of its cell (nearest reconstruction)
+    pub fn error(&self) -> LodError {
+        let dim = self.sdf.header.dim;
+        let level_dim = self.dim();
+        let scale = self.scale();
+        let mut level = Vec::with_capacity((level_dim.0 * level_dim.1 * level_dim.2) as usize);
+        for z in 0..level_dim.2 {
+            for y in 0..level_dim.1 {
+                for x in 0..level_dim.0 {
+                    level.push(self.sample(x, y, z));
+                }
+            }
+        }
+
+        let mut sum_squared = 0.0f64;
+        let mut max = 0u32;
+        for z in 0..dim.2 {
+            for y in 0..dim.1 {
+                for x in 0..dim.0 {
+                    let source = self.sdf.voxels[(x + y * dim.0 + z * dim.0 * dim.1) as usize];
+                    let cell = (x / scale, y / scale, z / scale);
+                    let index = cell.0 + cell.1 * level_dim.0 + cell.2 * level_dim.0 * level_dim.1;
+                    let error = (source as i32 - level[index as usize] as i32).unsigned_abs();
+                    sum_squared += error as f64 * error as f64;
+                    max = max.max(error);
+                }
+            }
+        }
+        let count = self.sdf.voxels.len().max(1) as f64;
+        LodError {
+            rms: (sum_squared / count).sqrt() as f32 / UNORM16_MAX,
+            max: max as f32 / UNORM16_MAX,
+        }
+    }
+}
+
 impl Brick {
-    pub fn new(size: u32, position: (u32, u32, u32)) -> Self {
+    pub fn new(size: u32, position: UVec3) -> Self {
         Brick {
             data: vec![LEVEL_ZERO; (size * size * size) as usize],
             size,
@@ -95,28 +432,23 @@ impl Brick {
         }
     }
 
-    pub fn extract_from_sdf(
-        sdf: &Sdf,
-        position: (u32, u32, u32),
-        size: u32,
-    ) -> Self {
+    pub fn extract_from_sdf(sdf: &Sdf, position: UVec3, size: u32) -> Self {
+        Brick::extract_from_lod(&SdfLod::new(sdf, 0, LodFilter::Point), position, size)
+    }
+
+    // Position and size are in voxels of the LOD level
+    pub fn extract_from_lod(lod: &SdfLod, position: UVec3, size: u32) -> Self {
         let mut brick = Brick::new(size, position);
-        let (px, py, pz) = position;
-        
-        let stride_y = sdf.header.dim.0;
-        let stride_z = sdf.header.dim.0 * sdf.header.dim.1;
+        let dim = UVec3::from(lod.dim());
 
         for z in 0..size {
             for y in 0..size {
                 for x in 0..size {
-                    let src_x = px + x;
-                    let src_y = py + y;
-                    let src_z = pz + z;
+                    let src = position + UVec3::new(x, y, z);
 
-                    if src_x < sdf.header.dim.0 && src_y < sdf.header.dim.1 && src_z < sdf.header.dim.2 {
-                        let src_index = (src_x + src_y * stride_y + src_z * stride_z) as usize;
+                    if src.all_lt(dim) {
                         let dst_index = (x + y * size + z * size * size) as usize;
-                        brick.data[dst_index] = sdf.voxels[src_index];
+                        brick.data[dst_index] = lod.sample(src.x, src.y, src.z);
                     }
                 }
             }
@@ -125,8 +457,12 @@ impl Brick {
         brick
     }
 
+    pub fn contains(&self, voxel: UVec3) -> bool {
+        voxel.all_ge(self.position) && voxel.all_lt(self.position + UVec3::splat(self.size))
+    }
+
     pub fn has_surface(&self, threshold: f32) -> bool {
-        let threshold_u16 = (threshold * 65535.0) as u16;
+        let threshold_u16 = (threshold * UNORM16_MAX) as u16;
         let mut has_inside = false;
         let mut has_outside = false;
 
@@ -150,163 +486,380 @@ impl Brick {
             return true;
         }
 
-        let threshold_u16 = (threshold * 65535.0) as u16;
+        let threshold_u16 = (threshold * UNORM16_MAX) as u16;
         let first_value = self.data[0];
 
-        self.data.iter().all(|&value| {
-            (value as i32 - first_value as i32).abs() <= threshold_u16 as i32
-        })
+        self.data
+            .iter()
+            .all(|&value| (value as i32 - first_value as i32).abs() <= threshold_u16 as i32)
     }
 }
 
+// Volume of the octree nodes finished by build_octree
+struct BuildProgress<'a> {
+    done: u64,
+    total: u64,
+    callback: &'a mut dyn FnMut(f32),
+    cancel: &'a CancelToken,
+}
+
+impl BuildProgress<'_> {
+    fn fraction(&self) -> f32 {
+        (self.done as f64 / self.total.max(1) as f64).min(1.0) as f32
+    }
+
+    fn finish(&mut self, bounds: &BoundingBox) {
+        self.done += bounds.voxel_count();
+        let fraction = self.fraction();
+        (self.callback)(fraction);
+    }
+
+    // Every node is a safe point, nothing is shared outside the octree under construction
+    fn check(&self) -> Result<(), Cancelled> {
+        self.cancel.check(self.fraction())
+    }
+}
+
+// Saved files are written in chunks with cancel checks between them
+const SAVE_CHUNK_BYTES: usize = 16 << 20;
+
 impl SvoSdf {
-    pub fn from_sdf(sdf: &Sdf, brick_size: u32, max_depth: u32, threshold: f32) -> Self {
-        let bounds = BoundingBox::new(
-            (0, 0, 0),
-            (sdf.header.dim.0, sdf.header.dim.1, sdf.header.dim.2),
-        );
+    // threshold is a ThresholdSchedule or one value for every depth
+    pub fn from_sdf(
+        sdf: &Sdf,
+        brick_size: u32,
+        max_depth: u32,
+        threshold: impl Into<ThresholdSchedule>,
+    ) -> Self {
+        let never = CancelToken::new();
+        SvoSdf::from_sdf_with_progress(sdf, brick_size, max_depth, threshold, &mut |_| {}, &never)
+            .expect("Build without a cancel request was cancelled")
+    }
+
+    // progress is called with the finished fraction of the volume (0..1) as octree nodes
+    // are completed. A cancel request ends the build at the next octree node.
+    pub fn from_sdf_with_progress(
+        sdf: &Sdf,
+        brick_size: u32,
+        max_depth: u32,
+        threshold: impl Into<ThresholdSchedule>,
+        progress: &mut dyn FnMut(f32),
+        cancel: &CancelToken,
+    ) -> Result<Self, Cancelled> {
+        let lod = SdfLod::new(sdf, 0, LodFilter::Point);
+        SvoSdf::from_lod_with_progress(&lod, brick_size, max_depth, threshold, progress, cancel)
+    }
+
+    // Octree of a LOD level, the header describes the level grid
+    pub fn from_lod_with_progress(
+        lod: &SdfLod,
+        brick_size: u32,
+        max_depth: u32,
+        threshold: impl Into<ThresholdSchedule>,
+        progress: &mut dyn FnMut(f32),
+        cancel: &CancelToken,
+    ) -> Result<Self, Cancelled> {
+        let header = lod.header();
+        let bounds = BoundingBox::new(UVec3::ZERO, header.dim.into());
 
         let mut svo_sdf = SvoSdf {
-            header: sdf.header,
+            header,
             root: OctreeNode::new(bounds),
             bricks: Vec::new(),
             brick_size,
+            brick_ranges: Vec::new(),
+            thumbnail: None,
         };
 
+        let mut build_progress = BuildProgress {
+            done: 0,
+            total: bounds.voxel_count(),
+            callback: progress,
+            cancel,
+        };
         SvoSdf::build_octree(
-            sdf,
+            lod,
             &mut svo_sdf.root,
             &mut svo_sdf.bricks,
             svo_sdf.brick_size,
             0,
             max_depth,
-            threshold,
-        );
-        svo_sdf
+            threshold.into(),
+            &mut build_progress,
+        )?;
+        svo_sdf.compute_brick_ranges();
+        Ok(svo_sdf)
+    }
+
+    pub fn compute_brick_ranges(&mut self) {
+        self.brick_ranges = self
+            .bricks
+            .iter()
+            .map(|brick| BrickRange::from_data(&brick.data))
+            .collect();
     }
 
+    // Indices of the bricks whose distance range reaches the band around the surface
+    pub fn bricks_in_band(&self, half_width: u16) -> impl Iterator<Item = usize> + '_ {
+        self.brick_ranges
+            .iter()
+            .enumerate()
+            .filter(move |(_, range)| range.intersects_band(LEVEL_ZERO, half_width))
+            .map(|(i, _)| i)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     fn build_octree(
-        sdf: &Sdf,
+        lod: &SdfLod,
         node: &mut OctreeNode,
         bricks: &mut Vec<Brick>,
         brick_size: u32,
         depth: u32,
         max_depth: u32,
-        threshold: f32,
-    ) {
+        schedule: ThresholdSchedule,
+        progress: &mut BuildProgress,
+    ) -> Result<(), Cancelled> {
+        progress.check()?;
+        let threshold = schedule.at_depth(depth);
         let bounds_size = node.bounds.size();
         let min_size = brick_size;
 
         // If we've reached maximum depth or the node is small enough, create a leaf
-        if depth >= max_depth || (bounds_size.0 <= min_size && bounds_size.1 <= min_size && bounds_size.2 <= min_size) {
-            let brick = Brick::extract_from_sdf(sdf, node.bounds.min, brick_size.min(bounds_size.0.max(bounds_size.1.max(bounds_size.2))));
-            
+        let largest = bounds_size.x.max(bounds_size.y).max(bounds_size.z);
+        if depth >= max_depth || largest <= min_size {
+            let brick = Brick::extract_from_lod(lod, node.bounds.min, brick_size.min(largest));
+
             // Only store the brick if it contains surface data or is not uniform
             if brick.has_surface(threshold) || !brick.is_uniform(threshold) {
                 node.brick_index = Some(bricks.len() as u32);
                 bricks.push(brick);
             }
             node.is_leaf = true;
-            return;
+            progress.finish(&node.bounds);
+            return Ok(());
         }
 
         // Check if this region contains any surface data
-        let test_brick = Brick::extract_from_sdf(sdf, node.bounds.min, bounds_size.0.min(bounds_size.1.min(bounds_size.2)));
+        let smallest = bounds_size.x.min(bounds_size.y).min(bounds_size.z);
+        let test_brick = Brick::extract_from_lod(lod, node.bounds.min, smallest);
         if !test_brick.has_surface(threshold) && test_brick.is_uniform(threshold) {
             // This region is uniform and doesn't contain surface, so we can skip it
-            return;
+            progress.finish(&node.bounds);
+            return Ok(());
         }
 
         // Subdivide into 8 children
         for i in 0..8 {
             let child_bounds = node.bounds.child_bounds(i);
             let mut child_node = OctreeNode::new(child_bounds);
-            
+
             SvoSdf::build_octree(
-                sdf,
+                lod,
                 &mut child_node,
                 bricks,
                 brick_size,
                 depth + 1,
                 max_depth,
-                threshold,
-            );
-            
+                schedule,
+                progress,
+            )?;
+
             if !child_node.is_empty() {
                 node.children[i] = Some(Box::new(child_node));
             }
         }
+        Ok(())
+    }
+
+    pub fn voxel_to_world(&self) -> DMat4x4 {
+        voxel_to_world(&self.header)
+    }
+
+    // Offset of a voxel from box_min, the position in the volume's own space
+    pub fn voxel_offset(&self, voxel: UVec3) -> DVec3 {
+        DVec3::from(voxel) * DVec3::from(self.header.spacing)
+    }
+
+    // Brick holding a voxel of the (LOD level) grid the octree was built from
+    pub fn brick_at(&self, voxel: UVec3) -> Option<&Brick> {
+        let mut node = &self.root;
+        if !node.bounds.contains(voxel) {
+            return None;
+        }
+        loop {
+            if let Some(index) = node.brick_index {
+                // Leaves above the brick size only have a brick at their minimum corner
+                let brick = &self.bricks[index as usize];
+                return brick.contains(voxel).then_some(brick);
+            }
+            node = node.children[node.bounds.child_index(voxel)].as_deref()?;
+        }
+    }
+
+    // Every source voxel of lod.sdf against the brick voxel of its LOD cell. Uncovered voxels
+    // closer to the surface than surface_band (1.0 is the full u16 range) are missing surface.
+    pub fn compare(&self, lod: &SdfLod, surface_band: f32) -> SvoCompare {
+        let dim = lod.sdf.header.dim;
+        let scale = lod.scale();
+        let band = (surface_band * UNORM16_MAX) as u32;
+        let mut result = SvoCompare::default();
+        let mut sum_squared = 0.0f64;
+        let mut max = 0u32;
+        for z in 0..dim.2 {
+            for y in 0..dim.1 {
+                for x in 0..dim.0 {
+                    let source = lod.sdf.voxels[(x + y * dim.0 + z * dim.0 * dim.1) as usize];
+                    let cell = UVec3::new(x, y, z) / scale;
+                    match self.brick_at(cell) {
+                        Some(brick) => {
+                            let local =
+                                (cell - brick.position).linear_index(UVec3::splat(brick.size));
+                            let value = brick.data[local];
+                            let error = (source as i32 - value as i32).unsigned_abs();
+                            sum_squared += error as f64 * error as f64;
+                            max = max.max(error);
+                            result.covered += 1;
+                        }
+                        None => {
+                            if (source as i32 - LEVEL_ZERO as i32).unsigned_abs() <= band {
+                                result.missing_surface += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        result.error = LodError {
+            rms: (sum_squared / result.covered.max(1) as f64).sqrt() as f32 / UNORM16_MAX,
+            max: max as f32 / UNORM16_MAX,
+        };
+        result
     }
 
     pub fn calculate_memory_usage(&self) -> usize {
-        let node_size = std::mem::size_of::<OctreeNode>();
-        let brick_size = self.bricks.iter().map(|b| b.data.len() * std::mem::size_of::<u16>()).sum::<usize>();
-        let header_size = std::mem::size_of::<SdfHeader>();
-        
-        // Estimate node count (this is approximate)
-        let estimated_nodes = self.count_nodes(&self.root);
-        
-        header_size + estimated_nodes * node_size + brick_size
-    }
-
-    fn count_nodes(&self, node: &OctreeNode) -> usize {
-        let mut count = 1;
-        for child in &node.children {
-            if let Some(child_node) = child {
-                count += self.count_nodes(child_node);
+        self.memory_usage().total()
+    }
+
+    pub fn memory_usage(&self) -> SvoMemoryUsage {
+        let mut usage = SvoMemoryUsage {
+            header: mem::size_of::<SvoSdf>(),
+            node_count: 1,
+            ..Default::default()
+        };
+
+        // Every node below the root is its own boxed allocation
+        let mut stack: Vec<&OctreeNode> = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            for child in node.children.iter().flatten() {
+                usage.node_count += 1;
+                usage.nodes += usage.allocate(mem::size_of::<OctreeNode>());
+                stack.push(child);
             }
         }
-        count
+
+        let mut payloads: HashSet<&[u16]> = HashSet::new();
+        for brick in &self.bricks {
+            let bytes = brick.data.capacity() * mem::size_of::<u16>();
+            usage.brick_payloads += usage.allocate(bytes);
+            if !payloads.insert(&brick.data) {
+                usage.duplicate_bricks += 1;
+                usage.dedup_savings += bytes;
+            }
+        }
+
+        usage.metadata += usage.allocate(self.bricks.capacity() * mem::size_of::<Brick>());
+        let ranges_bytes = self.brick_ranges.capacity() * mem::size_of::<BrickRange>();
+        usage.metadata += usage.allocate(ranges_bytes);
+        if let Some(thumbnail) = &self.thumbnail {
+            usage.metadata += usage.allocate(thumbnail.pixels.capacity());
+        }
+        usage
     }
 
     pub fn save(&self, filename: &str) -> io::Result<()> {
+        self.save_with_cancel(filename, &CancelToken::new())
+    }
+
+    // Written to FILE.partial and renamed when complete, an existing file is only replaced
+    // by a finished one. A cancel request removes the partial file and returns an
+    // Interrupted error with the Cancelled (see Cancelled::from_io).
+    pub fn save_with_cancel(&self, filename: &str, cancel: &CancelToken) -> io::Result<()> {
+        let partial = format!("{}.partial", filename);
+        let result = self
+            .write_file(&partial, cancel)
+            .and_then(|_| fs::rename(&partial, filename));
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        result
+    }
+
+    // Serializing is the first half of the progress, writing the second
+    fn write_file(&self, path: &str, cancel: &CancelToken) -> io::Result<()> {
         let mut storer = StorerVec::new();
-        
+
         // Store header
-        storer.store_u32(self.header.dim.0);
-        storer.store_u32(self.header.dim.1);
-        storer.store_u32(self.header.dim.2);
-        storer.store_f32(self.header.box_min.0);
-        storer.store_f32(self.header.box_min.1);
-        storer.store_f32(self.header.box_min.2);
-        storer.store_f32(self.header.dx);
+        storer.store_array_u8(&self.header.to_bytes());
         storer.store_u32(self.brick_size);
-        
+
         // Store bricks
         storer.store_u32(self.bricks.len() as u32);
-        for brick in &self.bricks {
+        let mut checksums = Vec::with_capacity(self.bricks.len());
+        for (i, brick) in self.bricks.iter().enumerate() {
+            cancel.check(0.5 * i as f32 / self.bricks.len() as f32)?;
             storer.store_u32(brick.size);
-            storer.store_u32(brick.position.0);
-            storer.store_u32(brick.position.1);
-            storer.store_u32(brick.position.2);
+            storer.store_u32(brick.position.x);
+            storer.store_u32(brick.position.y);
+            storer.store_u32(brick.position.z);
+            let data_start = storer.v.len();
             storer.store_array_u16(&brick.data);
+            checksums.push(brick_checksum(&storer.v[data_start..]));
         }
-        
+
         // Store octree structure
         self.serialize_node(&self.root, &mut storer);
-        
-        std::fs::write(filename, storer.v)?;
-        Ok(())
+
+        // Store brick ranges (optional trailing section, older files don't have it)
+        for range in &self.brick_ranges {
+            storer.store_u16(range.min);
+            storer.store_u16(range.max);
+        }
+
+        // Store the brick checksums (optional, older files don't have them)
+        BrickChecksums { checksums }.store(&mut storer);
+
+        // Store the preview image (optional, always last)
+        if let Some(thumbnail) = &self.thumbnail {
+            thumbnail.store(&mut storer);
+        }
+
+        let mut file = fs::File::create(path)?;
+        let chunk_count = storer.v.len().div_ceil(SAVE_CHUNK_BYTES);
+        for (i, chunk) in storer.v.chunks(SAVE_CHUNK_BYTES).enumerate() {
+            cancel.check(0.5 + 0.5 * i as f32 / chunk_count as f32)?;
+            file.write_all(chunk)?;
+        }
+        file.sync_all()
     }
 
     fn serialize_node(&self, node: &OctreeNode, storer: &mut StorerVec) {
         storer.store_u8(if node.is_leaf { 1 } else { 0 });
-        
+
         if let Some(brick_index) = node.brick_index {
             storer.store_u8(1); // has brick
             storer.store_u32(brick_index);
         } else {
             storer.store_u8(0); // no brick
         }
-        
+
         // Store bounds
-        storer.store_u32(node.bounds.min.0);
-        storer.store_u32(node.bounds.min.1);
-        storer.store_u32(node.bounds.min.2);
-        storer.store_u32(node.bounds.max.0);
-        storer.store_u32(node.bounds.max.1);
-        storer.store_u32(node.bounds.max.2);
-        
+        storer.store_u32(node.bounds.min.x);
+        storer.store_u32(node.bounds.min.y);
+        storer.store_u32(node.bounds.min.z);
+        storer.store_u32(node.bounds.max.x);
+        storer.store_u32(node.bounds.max.y);
+        storer.store_u32(node.bounds.max.z);
+
         if !node.is_leaf {
             // Store child mask
             let mut child_mask = 0u8;
@@ -316,77 +869,136 @@ impl SvoSdf {
                 }
             }
             storer.store_u8(child_mask);
-            
+
             // Recursively store children
-            for child in &node.children {
-                if let Some(child_node) = child {
-                    self.serialize_node(child_node, storer);
-                }
+            for child_node in node.children.iter().flatten() {
+                self.serialize_node(child_node, storer);
             }
         }
     }
 
     pub fn load(filename: &str) -> io::Result<Self> {
-        let bytes = std::fs::read(filename)?;
+        Self::from_bytes(&std::fs::read(filename)?)
+    }
+
+    // A corrupt brick fails the load with an InvalidData error carrying the first
+    // ChecksumMismatch (see ChecksumMismatch::from_io)
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let (svo_sdf, spans, checksums) = Self::parse(bytes)?;
+        if let Some(checksums) = checksums {
+            if let Some(&mismatch) = find_mismatches(bytes, &spans, &checksums).first() {
+                return Err(mismatch.into());
+            }
+        }
+        Ok(svo_sdf)
+    }
+
+    pub fn load_verify_in_background(filename: &str) -> io::Result<(Self, ChecksumVerification)> {
+        Self::from_bytes_verify_in_background(std::fs::read(filename)?)
+    }
+
+    // Returns before the checksums are verified, the verification keeps the file bytes
+    pub fn from_bytes_verify_in_background(
+        bytes: Vec<u8>,
+    ) -> io::Result<(Self, ChecksumVerification)> {
+        let (svo_sdf, spans, checksums) = Self::parse(&bytes)?;
+        let verification = match checksums {
+            Some(checksums) => ChecksumVerification::spawn(bytes, spans, checksums),
+            None => ChecksumVerification::none(),
+        };
+        Ok((svo_sdf, verification))
+    }
+
+    // The volume, where the voxel bytes of every brick are and the checksums of the file
+    fn parse(bytes: &[u8]) -> io::Result<(Self, Vec<BrickSpan>, Option<BrickChecksums>)> {
         let mut loader = Loader::new();
-        
+
         // Load header
-        let header = SdfHeader {
-            dim: (
-                loader.load_u32(&bytes),
-                loader.load_u32(&bytes),
-                loader.load_u32(&bytes),
-            ),
-            box_min: (
-                loader.load_f32(&bytes),
-                loader.load_f32(&bytes),
-                loader.load_f32(&bytes),
-            ),
-            dx: loader.load_f32(&bytes),
-        };
-        let brick_size = loader.load_u32(&bytes);
-        
+        let header = SdfHeader::load(&mut loader, bytes);
+        let brick_size = loader.load_u32(bytes);
+
         // Load bricks
-        let brick_count = loader.load_u32(&bytes);
+        let brick_count = loader.load_u32(bytes);
         let mut bricks = Vec::with_capacity(brick_count as usize);
-        
+        let mut spans = Vec::with_capacity(brick_count as usize);
+
         for _ in 0..brick_count {
-            let size = loader.load_u32(&bytes);
-            let position = (
-                loader.load_u32(&bytes),
-                loader.load_u32(&bytes),
-                loader.load_u32(&bytes),
+            let size = loader.load_u32(bytes);
+            let position = UVec3::new(
+                loader.load_u32(bytes),
+                loader.load_u32(bytes),
+                loader.load_u32(bytes),
             );
-            let data = loader.load_array_u16(&bytes, (size * size * size) as usize);
-            
+            let offset = loader.offset;
+            let data = loader.load_array_u16(bytes, (size * size * size) as usize);
+            spans.push(BrickSpan {
+                position,
+                offset,
+                bytes: loader.offset - offset,
+            });
+
             bricks.push(Brick {
                 data,
                 size,
                 position,
             });
         }
-        
+
         // Load octree structure
-        let bounds = BoundingBox::new((0, 0, 0), header.dim);
-        let root = Self::deserialize_node(&mut loader, &bytes, bounds);
-        
-        Ok(SvoSdf {
+        let bounds = BoundingBox::new(UVec3::ZERO, header.dim.into());
+        let root = Self::deserialize_node(&mut loader, bytes, bounds);
+
+        let mut svo_sdf = SvoSdf {
             header,
             root,
             bricks,
             brick_size,
-        })
+            brick_ranges: Vec::new(),
+            thumbnail: Thumbnail::from_tail(bytes),
+        };
+        let end = bytes.len() - svo_sdf.thumbnail.as_ref().map_or(0, |t| t.section_bytes());
+        let checksums = BrickChecksums::from_tail(&bytes[..end]);
+        let end = end - checksums.as_ref().map_or(0, |c| c.section_bytes());
+        if checksums
+            .as_ref()
+            .is_some_and(|c| c.checksums.len() != svo_sdf.bricks.len())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "brick checksums don't match the brick count",
+            ));
+        }
+
+        // Load brick ranges, computed for files written before they were serialized
+        if end.saturating_sub(loader.offset) >= svo_sdf.bricks.len() * 4 {
+            svo_sdf.brick_ranges = (0..svo_sdf.bricks.len())
+                .map(|_| BrickRange {
+                    min: loader.load_u16(bytes),
+                    max: loader.load_u16(bytes),
+                })
+                .collect();
+        } else {
+            svo_sdf.compute_brick_ranges();
+        }
+
+        Ok((svo_sdf, spans, checksums))
+    }
+
+    // Octree section on its own, checked with file_format::octree_bytes first
+    pub fn octree_from_bytes(header: &SdfHeader, bytes: &[u8]) -> OctreeNode {
+        let bounds = BoundingBox::new(UVec3::ZERO, header.dim.into());
+        Self::deserialize_node(&mut Loader::new(), bytes, bounds)
     }
 
     fn deserialize_node(loader: &mut Loader, bytes: &[u8], bounds: BoundingBox) -> OctreeNode {
         let is_leaf = loader.load_u8(bytes) != 0;
-        
+
         let brick_index = if loader.load_u8(bytes) != 0 {
             Some(loader.load_u32(bytes))
         } else {
             None
         };
-        
+
         // Load bounds (though we could reconstruct them)
         let _min_x = loader.load_u32(bytes);
         let _min_y = loader.load_u32(bytes);
@@ -394,17 +1006,17 @@ impl SvoSdf {
         let _max_x = loader.load_u32(bytes);
         let _max_y = loader.load_u32(bytes);
         let _max_z = loader.load_u32(bytes);
-        
+
         let mut node = OctreeNode {
             children: [None, None, None, None, None, None, None, None],
             brick_index,
             is_leaf,
             bounds,
         };
-        
+
         if !is_leaf {
             let child_mask = loader.load_u8(bytes);
-            
+
             for i in 0..8 {
                 if (child_mask & (1 << i)) != 0 {
                     let child_bounds = bounds.child_bounds(i);
@@ -413,7 +1025,7 @@ impl SvoSdf {
                 }
             }
         }
-        
+
         node
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,199 @@
+// 2D texture files decoded to mip levels ready for upload. PNG goes through the image crate and
+// is expanded to RGBA8. KTX2 levels are used as stored, so block compressed formats (BC1-BC7)
+// reach the GPU without decoding. Supercompressed KTX2 files (Basis, Zstandard) aren't supported.
+
+use ash::vk;
+
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+// Identifier, header and index, the level index follows
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+const KTX2_LEVEL_INDEX_ENTRY_BYTES: usize = 24;
+
+#[derive(Debug)]
+pub enum TextureFileError {
+    Io(io::Error),
+    Image(image::ImageError),
+    Ktx2(String),
+    UnsupportedFormat(vk::Format),
+}
+
+impl fmt::Display for TextureFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureFileError::Io(err) => write!(f, "{}", err),
+            TextureFileError::Image(err) => write!(f, "{}", err),
+            TextureFileError::Ktx2(message) => write!(f, "Invalid KTX2 file: {}", message),
+            TextureFileError::UnsupportedFormat(format) => {
+                write!(f, "Unsupported texture format {:?}", format)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TextureFileError {}
+
+impl From<io::Error> for TextureFileError {
+    fn from(err: io::Error) -> Self {
+        TextureFileError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for TextureFileError {
+    fn from(err: image::ImageError) -> Self {
+        TextureFileError::Image(err)
+    }
+}
+
+// Texel block of a format, (width, height, bytes). Uncompressed formats have 1x1 blocks.
+pub fn format_block(format: vk::Format) -> Option<(u32, u32, u32)> {
+    match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SRGB => Some((1, 1, 1)),
+        vk::Format::R8G8_UNORM | vk::Format::R16_UNORM | vk::Format::R16_SFLOAT => Some((1, 1, 2)),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R32_SFLOAT => Some((1, 1, 4)),
+        vk::Format::R16G16B16A16_SFLOAT => Some((1, 1, 8)),
+        vk::Format::R32G32B32A32_SFLOAT => Some((1, 1, 16)),
+        vk::Format::BC1_RGB_UNORM_BLOCK
+        | vk::Format::BC1_RGB_SRGB_BLOCK
+        | vk::Format::BC1_RGBA_UNORM_BLOCK
+        | vk::Format::BC1_RGBA_SRGB_BLOCK
+        | vk::Format::BC4_UNORM_BLOCK
+        | vk::Format::BC4_SNORM_BLOCK => Some((4, 4, 8)),
+        vk::Format::BC2_UNORM_BLOCK
+        | vk::Format::BC2_SRGB_BLOCK
+        | vk::Format::BC3_UNORM_BLOCK
+        | vk::Format::BC3_SRGB_BLOCK
+        | vk::Format::BC5_UNORM_BLOCK
+        | vk::Format::BC5_SNORM_BLOCK
+        | vk::Format::BC6H_UFLOAT_BLOCK
+        | vk::Format::BC6H_SFLOAT_BLOCK
+        | vk::Format::BC7_UNORM_BLOCK
+        | vk::Format::BC7_SRGB_BLOCK => Some((4, 4, 16)),
+        _ => None,
+    }
+}
+
+pub fn is_block_compressed(format: vk::Format) -> bool {
+    format_block(format).is_some_and(|(width, _, _)| width > 1)
+}
+
+// Bytes of one mip level
+pub fn level_bytes(format: vk::Format, width: u32, height: u32) -> Option<usize> {
+    let (block_width, block_height, block_bytes) = format_block(format)?;
+    let blocks_x = width.div_ceil(block_width) as usize;
+    let blocks_y = height.div_ceil(block_height) as usize;
+    Some(blocks_x * blocks_y * block_bytes as usize)
+}
+
+// Mip levels, largest first, tightly packed in the layout vkCmdCopyBufferToImage expects with
+// zero row length
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    // .ktx2 by extension, everything else through the image crate. PNGs are color textures
+    // when srgb is set, data (normal maps, masks) otherwise.
+    pub fn load(path: &Path, srgb: bool) -> Result<TextureData, TextureFileError> {
+        let bytes = fs::read(path)?;
+        let is_ktx2 = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"));
+        if is_ktx2 {
+            TextureData::from_ktx2(&bytes)
+        } else {
+            TextureData::from_image(&bytes, srgb)
+        }
+    }
+
+    pub fn from_image(bytes: &[u8], srgb: bool) -> Result<TextureData, TextureFileError> {
+        let image = image::load_from_memory(bytes)?.to_rgba8();
+        Ok(TextureData {
+            format: if srgb {
+                vk::Format::R8G8B8A8_SRGB
+            } else {
+                vk::Format::R8G8B8A8_UNORM
+            },
+            width: image.width(),
+            height: image.height(),
+            levels: vec![image.into_raw()],
+        })
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<TextureData, TextureFileError> {
+        let invalid = |message: &str| TextureFileError::Ktx2(message.to_string());
+        if bytes.len() < KTX2_LEVEL_INDEX_OFFSET || bytes[..12] != KTX2_IDENTIFIER {
+            return Err(invalid("missing KTX2 identifier"));
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+        let format = vk::Format::from_raw(u32_at(12) as i32);
+        let width = u32_at(20);
+        let height = u32_at(24);
+        let depth = u32_at(28);
+        let layer_count = u32_at(32);
+        let face_count = u32_at(36);
+        let level_count = u32_at(40).max(1) as usize;
+        let supercompression = u32_at(44);
+
+        if format == vk::Format::UNDEFINED {
+            return Err(invalid("Basis Universal textures need transcoding"));
+        }
+        if supercompression != 0 {
+            return Err(invalid("supercompressed levels aren't supported"));
+        }
+        if width == 0 || height == 0 || depth > 0 || layer_count > 0 || face_count != 1 {
+            return Err(invalid("only single layer 2D textures are supported"));
+        }
+        if format_block(format).is_none() {
+            return Err(TextureFileError::UnsupportedFormat(format));
+        }
+        let index_end = KTX2_LEVEL_INDEX_OFFSET + level_count * KTX2_LEVEL_INDEX_ENTRY_BYTES;
+        if bytes.len() < index_end {
+            return Err(invalid("truncated level index"));
+        }
+
+        let levels = (0..level_count)
+            .map(|level| {
+                let entry = KTX2_LEVEL_INDEX_OFFSET + level * KTX2_LEVEL_INDEX_ENTRY_BYTES;
+                let offset = u64_at(entry) as usize;
+                let length = u64_at(entry + 8) as usize;
+                let level_width = (width >> level).max(1);
+                let level_height = (height >> level).max(1);
+                if Some(length) != level_bytes(format, level_width, level_height) {
+                    return Err(invalid("level size doesn't match the format"));
+                }
+                bytes
+                    .get(offset..offset + length)
+                    .map(|data| data.to_vec())
+                    .ok_or_else(|| invalid("level data outside the file"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(TextureData {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+}
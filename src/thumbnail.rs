@@ -0,0 +1,149 @@
+// Small preview image stored at the end of .svosdf files. The front view (looking down -Z) is
+// projected on the CPU when the file is built, pixels are shaded by the depth of the first
+// voxel inside the surface. The section ends with a fixed size footer, so read_thumbnail only
+// reads the tail of the file and asset browsers don't have to load the bricks.
+
+use crate::sdf::*;
+use crate::serialization::*;
+use crate::svosdf::*;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const THUMBNAIL_SIZE: u32 = 128;
+
+const THUMBNAIL_MAGIC: u32 = u32::from_le_bytes(*b"THMB");
+// width, height, magic
+const THUMBNAIL_FOOTER_BYTES: usize = 3 * 4;
+
+// Grayscale, row 0 at the top. 0 = no surface, brighter = closer to the viewer.
+#[derive(Clone, Debug)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Thumbnail {
+    // The longer volume side is max_size pixels, proportions follow the voxel spacing
+    pub fn project(svo_sdf: &SvoSdf, max_size: u32) -> Thumbnail {
+        let dim = svo_sdf.header.dim;
+        let world_size = svo_sdf.header.world_size();
+        let longest = world_size.0.max(world_size.1);
+        let side = |size: f32| ((size / longest * max_size as f32) as u32).clamp(1, max_size);
+        let width = side(world_size.0);
+        let height = side(world_size.1);
+
+        // Largest z of a voxel inside the surface, per pixel
+        let mut front: Vec<Option<u32>> = vec![None; (width * height) as usize];
+        for brick in svo_sdf.bricks.iter() {
+            let size = brick.size;
+            for (i, &value) in brick.data.iter().enumerate() {
+                if value >= LEVEL_ZERO {
+                    continue;
+                }
+                let i = i as u32;
+                let x = brick.position.x + i % size;
+                let y = brick.position.y + (i / size) % size;
+                let z = brick.position.z + i / (size * size);
+                if x >= dim.0 || y >= dim.1 {
+                    continue;
+                }
+                let px = x * width / dim.0;
+                let py = (height - 1) - y * height / dim.1;
+                let pixel = &mut front[(px + py * width) as usize];
+                *pixel = Some(pixel.map_or(z, |front_z| front_z.max(z)));
+            }
+        }
+
+        let depth = dim.2.max(1) as f32;
+        let pixels = front
+            .iter()
+            .map(|z| match z {
+                Some(z) => (64.0 + 191.0 * (*z as f32 + 1.0) / depth).min(255.0) as u8,
+                None => 0,
+            })
+            .collect();
+
+        Thumbnail {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn store(&self, storer: &mut StorerVec) {
+        storer.store_array_u8(&self.pixels);
+        storer.store_u32(self.width);
+        storer.store_u32(self.height);
+        storer.store_u32(THUMBNAIL_MAGIC);
+    }
+
+    // Thumbnail at the end of bytes, None when there is no thumbnail section
+    pub fn from_tail(bytes: &[u8]) -> Option<Thumbnail> {
+        let footer_start = bytes.len().checked_sub(THUMBNAIL_FOOTER_BYTES)?;
+        let mut loader = Loader::new();
+        loader.offset = footer_start;
+        let width = loader.load_u32(bytes);
+        let height = loader.load_u32(bytes);
+        if loader.load_u32(bytes) != THUMBNAIL_MAGIC {
+            return None;
+        }
+        let pixels_start = footer_start.checked_sub((width * height) as usize)?;
+        Some(Thumbnail {
+            width,
+            height,
+            pixels: bytes[pixels_start..footer_start].to_vec(),
+        })
+    }
+
+    // Bytes of the section, pixels and footer
+    pub fn section_bytes(&self) -> usize {
+        self.pixels.len() + THUMBNAIL_FOOTER_BYTES
+    }
+
+    pub fn save_png(&self, path: &Path) -> image::ImageResult<()> {
+        image::save_buffer(
+            path,
+            &self.pixels,
+            self.width,
+            self.height,
+            image::ColorType::L8,
+        )
+    }
+}
+
+// Reads only the thumbnail section of an .svosdf file
+pub fn read_thumbnail(path: &Path) -> io::Result<Option<Thumbnail>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len < THUMBNAIL_FOOTER_BYTES as u64 {
+        return Ok(None);
+    }
+
+    let mut footer = [0u8; THUMBNAIL_FOOTER_BYTES];
+    file.seek(SeekFrom::Start(len - THUMBNAIL_FOOTER_BYTES as u64))?;
+    file.read_exact(&mut footer)?;
+    let mut loader = Loader::new();
+    let width = loader.load_u32(&footer);
+    let height = loader.load_u32(&footer);
+    if loader.load_u32(&footer) != THUMBNAIL_MAGIC {
+        return Ok(None);
+    }
+
+    let pixel_bytes = width as u64 * height as u64;
+    if pixel_bytes + THUMBNAIL_FOOTER_BYTES as u64 > len {
+        return Ok(None);
+    }
+    let mut pixels = vec![0u8; pixel_bytes as usize];
+    file.seek(SeekFrom::Start(
+        len - THUMBNAIL_FOOTER_BYTES as u64 - pixel_bytes,
+    ))?;
+    file.read_exact(&mut pixels)?;
+    Ok(Some(Thumbnail {
+        width,
+        height,
+        pixels,
+    }))
+}
@@ -0,0 +1,140 @@
+// Counts the validation layer messages of a run so that headless CI renders can gate on
+// validation cleanliness. Errors above the budget fail the process with a non-zero exit code,
+// either when the run ends or, in fail-fast mode, at the first error over the budget. All
+// messages can be written to a report file.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+
+pub const VALIDATION_EXIT_CODE: i32 = 3;
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidationSettings {
+    pub error_budget: Option<u32>, // None = errors don't fail the run
+    pub fail_fast: bool,
+    pub report_path: Option<PathBuf>,
+}
+
+impl ValidationSettings {
+    // --validation-budget N (errors allowed)
+    // --validation-fail-fast (exit at the first error over the budget, budget 0 when not given)
+    // --validation-report FILE
+    pub fn from_args(args: &[String]) -> ValidationSettings {
+        let value = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let fail_fast = args.iter().any(|arg| arg == "--validation-fail-fast");
+        let error_budget = value("--validation-budget")
+            .and_then(|budget| budget.parse::<u32>().ok())
+            .or(if fail_fast { Some(0) } else { None });
+        ValidationSettings {
+            error_budget,
+            fail_fast,
+            report_path: value("--validation-report").map(PathBuf::from),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidationCounts {
+    pub errors: u32,
+    pub warnings: u32,
+}
+
+#[derive(Default)]
+struct ValidationState {
+    counts: ValidationCounts,
+    messages: Vec<String>,
+}
+
+// The debug callback can run on any thread that calls into Vulkan
+pub struct ValidationLog {
+    pub settings: ValidationSettings,
+    state: Mutex<ValidationState>,
+}
+
+impl ValidationLog {
+    pub fn new(settings: ValidationSettings) -> ValidationLog {
+        ValidationLog {
+            settings,
+            state: Mutex::new(ValidationState::default()),
+        }
+    }
+
+    pub fn record_error(&self, message: String) {
+        let errors = {
+            let mut state = self.state.lock().unwrap();
+            state.counts.errors += 1;
+            state.messages.push(message);
+            state.counts.errors
+        };
+        if self.settings.fail_fast && self.over_budget(errors) {
+            println!("Validation error budget exceeded, stopping");
+            self.write_report();
+            process::exit(VALIDATION_EXIT_CODE);
+        }
+    }
+
+    pub fn record_warning(&self, message: String) {
+        let mut state = self.state.lock().unwrap();
+        state.counts.warnings += 1;
+        state.messages.push(message);
+    }
+
+    pub fn counts(&self) -> ValidationCounts {
+        self.state.lock().unwrap().counts
+    }
+
+    fn over_budget(&self, errors: u32) -> bool {
+        self.settings
+            .error_budget
+            .is_some_and(|budget| errors > budget)
+    }
+
+    fn report(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut report = format!(
+            "errors: {}\nwarnings: {}\nerror budget: {}\n",
+            state.counts.errors,
+            state.counts.warnings,
+            self.settings
+                .error_budget
+                .map_or("none".to_string(), |budget| budget.to_string()),
+        );
+        for message in state.messages.iter() {
+            report.push('\n');
+            report.push_str(message);
+            report.push('\n');
+        }
+        report
+    }
+
+    fn write_report(&self) {
+        if let Some(path) = &self.settings.report_path {
+            if let Err(err) = fs::write(path, self.report()) {
+                println!("Can't write validation report {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    // End of the run: writes the report and exits with VALIDATION_EXIT_CODE when the errors
+    // exceed the budget
+    pub fn finish(&self) {
+        self.write_report();
+        let counts = self.counts();
+        if self.settings.error_budget.is_some() || counts.errors + counts.warnings > 0 {
+            println!(
+                "Validation: {} errors, {} warnings",
+                counts.errors, counts.warnings
+            );
+        }
+        if self.over_budget(counts.errors) {
+            println!("Validation error budget exceeded");
+            process::exit(VALIDATION_EXIT_CODE);
+        }
+    }
+}
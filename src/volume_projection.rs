@@ -0,0 +1,401 @@
+// Max intensity (MIP) and average projections of an SVO along a volume axis, for checking
+// datasets at a glance. A compute pass reduces the voxels of every pixel's column, project_cpu
+// is the reference the GPU result can be compared with. Intensity is 65535 - distance, so the
+// inside of a volume is bright and the surface mid gray. Only voxels stored in bricks count,
+// uniform regions without bricks are left out of both the max and the average.
+//
+// The GPU pass runs one invocation per pixel. Bricks are sorted into the 8x8 pixel tiles of
+// the work groups on the CPU, so the shader reduces each column without atomics.
+
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+
+use crate::packing::UNORM16_MAX;
+use crate::svosdf::*;
+use crate::vulkan_base::*;
+use crate::vulkan_helpers::*;
+
+use std::path::Path;
+
+const TILE_SIZE: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl ProjectionAxis {
+    pub fn from_name(name: &str) -> Option<ProjectionAxis> {
+        match name {
+            "x" | "X" => Some(ProjectionAxis::X),
+            "y" | "Y" => Some(ProjectionAxis::Y),
+            "z" | "Z" => Some(ProjectionAxis::Z),
+            _ => None,
+        }
+    }
+
+    // Volume axes (0 = x) of the image columns, the image rows and the projected axis. Rows
+    // go up the volume like the thumbnail, which looks down -Z.
+    pub fn image_axes(&self) -> [usize; 3] {
+        match self {
+            ProjectionAxis::X => [2, 1, 0],
+            ProjectionAxis::Y => [0, 2, 1],
+            ProjectionAxis::Z => [0, 1, 2],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+    Max,
+    Average,
+}
+
+impl ProjectionMode {
+    pub fn from_name(name: &str) -> Option<ProjectionMode> {
+        match name {
+            "max" | "mip" => Some(ProjectionMode::Max),
+            "average" | "avg" => Some(ProjectionMode::Average),
+            _ => None,
+        }
+    }
+}
+
+// Column of one pixel, matches the result buffer of volume_projection.comp. The sum fits u32
+// for columns of up to 65537 voxels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ProjectionPixel {
+    pub max: u32,
+    pub sum: u32,
+    pub count: u32,
+}
+
+unsafe impl Zeroable for ProjectionPixel {}
+unsafe impl Pod for ProjectionPixel {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VolumeProjection {
+    pub axis: ProjectionAxis,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<ProjectionPixel>, // Row 0 at the bottom of the volume
+}
+
+fn volume_axis(dim: (u32, u32, u32), axis: usize) -> u32 {
+    [dim.0, dim.1, dim.2][axis]
+}
+
+impl VolumeProjection {
+    // Image of a mode, row 0 at the top. Pixels without voxels are 0.
+    pub fn image(&self, mode: ProjectionMode) -> Vec<u16> {
+        let mut image = Vec::with_capacity(self.pixels.len());
+        for row in self.pixels.chunks(self.width as usize).rev() {
+            image.extend(row.iter().map(|pixel| match mode {
+                ProjectionMode::Max => pixel.max as u16,
+                ProjectionMode::Average => (pixel.sum as u64 / pixel.count.max(1) as u64) as u16,
+            }));
+        }
+        image
+    }
+
+    // 16 bit grayscale
+    pub fn save_png(&self, path: &Path, mode: ProjectionMode) -> image::ImageResult<()> {
+        image::save_buffer(
+            path,
+            bytemuck::cast_slice(&self.image(mode)),
+            self.width,
+            self.height,
+            image::ColorType::L16,
+        )
+    }
+}
+
+// Reference implementation, every brick voxel inside the volume added to its pixel
+pub fn project_cpu(svo_sdf: &SvoSdf, axis: ProjectionAxis) -> VolumeProjection {
+    let [u_axis, v_axis, _] = axis.image_axes();
+    let dim = svo_sdf.header.dim;
+    let width = volume_axis(dim, u_axis);
+    let height = volume_axis(dim, v_axis);
+    let mut pixels = vec![ProjectionPixel::default(); (width * height) as usize];
+    for brick in &svo_sdf.bricks {
+        let size = brick.size;
+        for (i, &value) in brick.data.iter().enumerate() {
+            let i = i as u32;
+            let voxel = [
+                brick.position.x + i % size,
+                brick.position.y + (i / size) % size,
+                brick.position.z + i / (size * size),
+            ];
+            if voxel[0] >= dim.0 || voxel[1] >= dim.1 || voxel[2] >= dim.2 {
+                continue;
+            }
+            let pixel = &mut pixels[(voxel[u_axis] + voxel[v_axis] * width) as usize];
+            let intensity = UNORM16_MAX as u32 - value as u32;
+            pixel.max = pixel.max.max(intensity);
+            pixel.sum += intensity;
+            pixel.count += 1;
+        }
+    }
+    VolumeProjection {
+        axis,
+        width,
+        height,
+        pixels,
+    }
+}
+
+// Matches BrickInfo in volume_projection.comp
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct BrickInfo {
+    position_size: [u32; 4],
+    layout_offset: [u32; 4],
+}
+
+unsafe impl Zeroable for BrickInfo {}
+unsafe impl Pod for BrickInfo {}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ProjectionPushConstants {
+    size: [u32; 4], // Width, height and depth of the projection, tiles per row
+}
+
+// Buffer contents of the GPU pass
+struct ProjectionInput {
+    words: Vec<u32>,
+    bricks: Vec<BrickInfo>,
+    tile_offsets: Vec<u32>,
+    tile_bricks: Vec<u32>,
+    push_constants: ProjectionPushConstants,
+    tiles: (u32, u32),
+}
+
+impl ProjectionInput {
+    fn new(svo_sdf: &SvoSdf, axis: ProjectionAxis) -> ProjectionInput {
+        let axes = axis.image_axes();
+        let dim = svo_sdf.header.dim;
+        let size = axes.map(|axis| volume_axis(dim, axis));
+        let tiles = (
+            dispatch_size(size[0], TILE_SIZE),
+            dispatch_size(size[1], TILE_SIZE),
+        );
+
+        // Voxels of every brick start at a word boundary
+        let mut words = Vec::new();
+        let mut bricks = Vec::with_capacity(svo_sdf.bricks.len());
+        let mut tile_lists = vec![Vec::new(); (tiles.0 * tiles.1) as usize];
+        for (index, brick) in svo_sdf.bricks.iter().enumerate() {
+            let position = [brick.position.x, brick.position.y, brick.position.z];
+            let position = axes.map(|axis| position[axis]);
+            bricks.push(BrickInfo {
+                position_size: [position[0], position[1], position[2], brick.size],
+                layout_offset: [
+                    axes[0] as u32,
+                    axes[1] as u32,
+                    axes[2] as u32,
+                    words.len() as u32,
+                ],
+            });
+            words.extend(brick.data.chunks(2).map(|pair| {
+                pair[0] as u32 | (pair.get(1).copied().unwrap_or_default() as u32) << 16
+            }));
+
+            // Tiles under the part of the footprint inside the image
+            if position[0] >= size[0] || position[1] >= size[1] || position[2] >= size[2] {
+                continue;
+            }
+            let last_u = (position[0] + brick.size).min(size[0]) - 1;
+            let last_v = (position[1] + brick.size).min(size[1]) - 1;
+            for tile_v in position[1] / TILE_SIZE..=last_v / TILE_SIZE {
+                for tile_u in position[0] / TILE_SIZE..=last_u / TILE_SIZE {
+                    tile_lists[(tile_u + tile_v * tiles.0) as usize].push(index as u32);
+                }
+            }
+        }
+
+        let mut tile_offsets = Vec::with_capacity(tile_lists.len() + 1);
+        let mut tile_bricks = Vec::new();
+        for list in &tile_lists {
+            tile_offsets.push(tile_bricks.len() as u32);
+            tile_bricks.extend_from_slice(list);
+        }
+        tile_offsets.push(tile_bricks.len() as u32);
+
+        // Zero sized storage buffers aren't allowed
+        if words.is_empty() {
+            words.push(0);
+            bricks.push(BrickInfo::default());
+        }
+        if tile_bricks.is_empty() {
+            tile_bricks.push(0);
+        }
+
+        ProjectionInput {
+            words,
+            bricks,
+            tile_offsets,
+            tile_bricks,
+            push_constants: ProjectionPushConstants {
+                size: [size[0], size[1], size[2], tiles.0],
+            },
+            tiles,
+        }
+    }
+}
+
+// Runs the compute pass and waits for the result. Records into command buffer 0 of base,
+// call it outside of a frame.
+pub fn project_gpu(
+    base: &mut VulkanBase,
+    svo_sdf: &SvoSdf,
+    axis: ProjectionAxis,
+) -> Result<VolumeProjection, VulkanError> {
+    let input = ProjectionInput::new(svo_sdf, axis);
+    let size = input.push_constants.size;
+    let pixel_count = (size[0] * size[1]) as usize;
+
+    let storage = vk::BufferUsageFlags::STORAGE_BUFFER;
+    let mut upload = |words: &[u32]| {
+        let buffer = TypedBuffer::<u32>::new(
+            &base.device,
+            &mut base.allocator,
+            words.len(),
+            storage,
+            MemoryLocation::CpuToGpu,
+        );
+        buffer.write_slice(words);
+        buffer
+    };
+    let mut buffers = [
+        upload(&input.words),
+        upload(bytemuck::cast_slice(&input.bricks)),
+        upload(&input.tile_offsets),
+        upload(&input.tile_bricks),
+    ];
+    let mut result = TypedBuffer::<ProjectionPixel>::new(
+        &base.device,
+        &mut base.allocator,
+        pixel_count.max(1),
+        storage,
+        MemoryLocation::GpuToCpu,
+    );
+    let mut descriptor_allocator = DescriptorAllocator::new(1);
+    let mut set_layout = vk::DescriptorSetLayout::null();
+    let mut pipeline = None;
+
+    let projection = (|| -> Result<VolumeProjection, VulkanError> {
+        let device = &base.device;
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..5)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            })
+            .collect();
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        set_layout = unsafe { device.create_descriptor_set_layout(&set_layout_info, None) }?;
+        let created = ComputePipeline::new(
+            device,
+            &include_bytes!("../shader/volume_projection.spv")[..],
+            &PipelineLayoutBuilder::new()
+                .set_layout(set_layout)
+                .push_constants::<ProjectionPushConstants>(vk::ShaderStageFlags::COMPUTE),
+        );
+        let pipeline = match created {
+            Ok(created) => pipeline.insert(created),
+            Err(ComputePipelineError::Vk(result)) => return Err(result.into()),
+            Err(err) => panic!("Volume projection pipeline creation failed: {}", err),
+        };
+        let descriptor_sets = descriptor_allocator.allocate_sets(device, &[set_layout])?;
+
+        let descriptors: Vec<vk::DescriptorBufferInfo> = buffers
+            .iter()
+            .map(|buffer| buffer.descriptor())
+            .chain(std::iter::once(result.descriptor()))
+            .collect();
+        let writes: Vec<vk::WriteDescriptorSet> = descriptors
+            .iter()
+            .enumerate()
+            .map(|(binding, descriptor)| vk::WriteDescriptorSet {
+                dst_set: descriptor_sets[0],
+                dst_binding: binding as u32,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: descriptor,
+                ..Default::default()
+            })
+            .collect();
+        unsafe { device.update_descriptor_sets(&writes, &[]) };
+
+        let read_buffers: Vec<vk::Buffer> =
+            buffers.iter().map(|buffer| buffer.buffer.buffer).collect();
+        let write_buffers = [result.buffer.buffer];
+        let host_read_barrier = vk::MemoryBarrier2 {
+            src_stage_mask: vk::PipelineStageFlags2::COMPUTE_SHADER,
+            src_access_mask: vk::AccessFlags2::SHADER_STORAGE_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags2::HOST,
+            dst_access_mask: vk::AccessFlags2::HOST_READ,
+            ..Default::default()
+        };
+        base.record_submit_commandbuffer(
+            0,
+            base.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, command_buffer| {
+                pipeline.push(device, command_buffer, &input.push_constants);
+                pipeline.dispatch(
+                    device,
+                    &base.barrier_api,
+                    command_buffer,
+                    &descriptor_sets,
+                    (input.tiles.0, input.tiles.1, 1),
+                    &ComputeResources {
+                        read_buffers: &read_buffers,
+                        write_buffers: &write_buffers,
+                        ..Default::default()
+                    },
+                );
+                barrier2(
+                    device,
+                    &base.barrier_api,
+                    command_buffer,
+                    &[host_read_barrier],
+                    &[],
+                    &[],
+                );
+            },
+        )?;
+        unsafe { device.device_wait_idle() }?;
+        Ok(VolumeProjection {
+            axis,
+            width: size[0],
+            height: size[1],
+            pixels: result.as_slice()[..pixel_count].to_vec(),
+        })
+    })();
+
+    // Also after errors, a lost device still has to be cleaned up
+    let _ = unsafe { base.device.device_wait_idle() };
+    if let Some(pipeline) = &pipeline {
+        pipeline.destroy(&base.device);
+    }
+    descriptor_allocator.destroy(&base.device);
+    unsafe { base.device.destroy_descriptor_set_layout(set_layout, None) };
+    for buffer in buffers.iter_mut() {
+        buffer.destroy(&base.device, &mut base.allocator);
+    }
+    result.destroy(&base.device, &mut base.allocator);
+    projection
+}
@@ -5,36 +5,167 @@
 extern crate ash;
 extern crate winit;
 
+use crate::allocator_telemetry::*;
+use crate::device_capabilities::*;
+use crate::device_group::*;
+use crate::display_settings::*;
+use crate::gpu_workarounds::*;
+use crate::memory_profile::*;
+use crate::memory_report::*;
+use crate::object_counters::{self, ObjectType};
+use crate::validation_log::*;
 use crate::vulkan_helpers::*;
 
 use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
 
 use ash::vk;
-use winit::window::Window;
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use winit::window::Window;
 
-use ash::{Entry};
+use ash::ext::debug_utils::Instance as DebugUtils;
+pub use ash::ext::mesh_shader::Device as MeshShader;
+use ash::khr::surface::Instance as Surface;
+use ash::khr::swapchain::Device as Swapchain;
+use ash::Entry;
 pub use ash::{Device, Instance};
 use std::borrow::Cow;
+use std::cell::Cell;
 use std::default::Default;
 use std::ffi::{CStr, CString};
+use std::fmt;
 use std::mem::ManuallyDrop;
 use std::ops::Drop;
-use ash::khr::surface::Instance as Surface;
-use ash::khr::swapchain::Device as Swapchain;
-use ash::ext::debug_utils::Instance as DebugUtils;
+use std::time::{Duration, Instant};
+
+// Three frames in flight with up to two batched command buffers each
+const NUM_COMMAND_BUFFERS: u32 = 6;
+
+pub const SHADER_PRINTF_PREFIX: &str = "[shader] ";
+
+#[derive(Debug)]
+pub enum VulkanError {
+    Loading(ash::LoadingError),
+    NoSuitableDevice,
+    NoDepthFormat,   // None of DEPTH_FORMAT_CANDIDATES is a depth attachment
+    NoSurfaceFormat, // The surface reports no formats
+    MissingInstanceExtension(String), // Required for the window surface
+    Allocation(AllocationError),
+    Allocator(gpu_allocator::AllocationError),
+    // Device-level objects are unusable, see VulkanBase::try_recover
+    DeviceLost,
+    Vk(vk::Result),
+}
 
-const NUM_COMMAND_BUFFERS: u32 = 3;
+impl From<vk::Result> for VulkanError {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => VulkanError::DeviceLost,
+            _ => VulkanError::Vk(result),
+        }
+    }
+}
+
+impl From<AllocationError> for VulkanError {
+    fn from(err: AllocationError) -> Self {
+        VulkanError::Allocation(err)
+    }
+}
+
+impl fmt::Display for VulkanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VulkanError::Loading(err) => write!(f, "Failed to load Vulkan: {}", err),
+            VulkanError::NoSuitableDevice => write!(f, "Couldn't find suitable device"),
+            VulkanError::NoDepthFormat => write!(f, "No supported depth buffer format"),
+            VulkanError::NoSurfaceFormat => write!(f, "Unable to find suitable surface format"),
+            VulkanError::MissingInstanceExtension(name) => {
+                write!(f, "Instance extension {} not available", name)
+            }
+            VulkanError::Allocation(err) => write!(f, "{}", err),
+            VulkanError::Allocator(err) => write!(f, "Allocator creation failed: {}", err),
+            VulkanError::DeviceLost => write!(f, "Vulkan device lost"),
+            VulkanError::Vk(result) => write!(f, "Vulkan error: {:?}", result),
+        }
+    }
+}
+
+impl std::error::Error for VulkanError {}
+
+// Validation layer options
+#[derive(Clone, Debug, Default)]
+pub struct DebugSettings {
+    // debugPrintfEXT output through the debug callback. Shaders must be compiled with
+    // -DDEBUG_PRINTF, see shader/debug_printf.glsl
+    pub shader_printf: bool,
+    pub validation: ValidationSettings, // Error budget and report, see validation_log.rs
+    pub workarounds: WorkaroundSettings, // --workaround NAME, --no-workarounds
+}
+
+impl DebugSettings {
+    pub fn from_args(args: &[String]) -> DebugSettings {
+        DebugSettings {
+            shader_printf: args.iter().any(|arg| arg == "--shader-printf"),
+            validation: ValidationSettings::from_args(args),
+            workarounds: WorkaroundSettings::from_args(args),
+        }
+    }
+}
+
+unsafe fn device_supports_extension(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    extension: &CStr,
+) -> bool {
+    instance
+        .enumerate_device_extension_properties(pdevice)
+        .unwrap_or_default()
+        .iter()
+        .any(|properties| properties.extension_name_as_c_str() == Ok(extension))
+}
+
+// Layers and instance extensions of the Vulkan loader and installed implicit/explicit layers
+#[derive(Clone, Debug, Default)]
+pub struct InstanceCapabilities {
+    pub layers: Vec<CString>,
+    pub extensions: Vec<CString>,
+}
+
+impl InstanceCapabilities {
+    pub fn has_layer(&self, name: &CStr) -> bool {
+        self.layers.iter().any(|layer| layer.as_c_str() == name)
+    }
+
+    pub fn has_extension(&self, name: &CStr) -> bool {
+        self.extensions
+            .iter()
+            .any(|extension| extension.as_c_str() == name)
+    }
+}
+
+const VALIDATION_LAYER_NAME: &CStr =
+    match CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0") {
+        Ok(name) => name,
+        Err(_) => panic!("Wrong layer string"),
+    };
+
+// VK_EXT_validation_features is provided by the validation layer itself
+unsafe fn layer_supports_extension(entry: &Entry, layer: &CStr, extension: &CStr) -> bool {
+    entry
+        .enumerate_instance_extension_properties(Some(layer))
+        .unwrap_or_default()
+        .iter()
+        .any(|properties| properties.extension_name_as_c_str() == Ok(extension))
+}
 
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
     message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     let callback_data = *p_callback_data;
-    let message_id_number: i32 = callback_data.message_id_number as i32;
+    let message_id_number: i32 = callback_data.message_id_number;
 
     let message_id_name = if callback_data.p_message_id_name.is_null() {
         Cow::from("")
@@ -48,14 +179,34 @@ unsafe extern "system" fn vulkan_debug_callback(
         CStr::from_ptr(callback_data.p_message).to_string_lossy()
     };
 
-    println!(
-        "{:?}:\n{:?} [{} ({})] : {}\n",
+    // debugPrintfEXT output arrives as INFO, the shader text follows the last '|'
+    if message_id_name.contains("DEBUG-PRINTF") {
+        let text = message.rsplit('|').next().unwrap_or("").trim();
+        println!("{}{}", SHADER_PRINTF_PREFIX, text);
+        return vk::FALSE;
+    }
+    if message_severity == vk::DebugUtilsMessageSeverityFlagsEXT::INFO {
+        return vk::FALSE;
+    }
+
+    let text = format!(
+        "{:?}:\n{:?} [{} ({})] : {}",
         message_severity,
         message_type,
         message_id_name,
         &message_id_number.to_string(),
         message,
     );
+    println!("{}\n", text);
+
+    // The ValidationLog owned by VulkanBase
+    if let Some(validation_log) = (user_data as *const ValidationLog).as_ref() {
+        if message_severity == vk::DebugUtilsMessageSeverityFlagsEXT::ERROR {
+            validation_log.record_error(text);
+        } else {
+            validation_log.record_warning(text);
+        }
+    }
 
     vk::FALSE
 }
@@ -63,6 +214,9 @@ unsafe extern "system" fn vulkan_debug_callback(
 pub struct CommandBuffer {
     command_buffer: vk::CommandBuffer,
     fence: vk::Fence,
+    // Fence of the last submit containing this command buffer, a batched submit signals the
+    // fence of its last command buffer only
+    guard_fence: Cell<vk::Fence>,
 }
 
 pub struct CommandBufferPool {
@@ -75,7 +229,7 @@ impl CommandBufferPool {
         device: &Device,
         queue_family_index: u32,
         num_command_buffers: u32,
-    ) -> CommandBufferPool {
+    ) -> Result<CommandBufferPool, VulkanError> {
         unsafe {
             let pool_create_info = vk::CommandPoolCreateInfo {
                 flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
@@ -83,7 +237,7 @@ impl CommandBufferPool {
                 ..Default::default()
             };
 
-            let pool = device.create_command_pool(&pool_create_info, None).unwrap();
+            let pool = device.create_command_pool(&pool_create_info, None)?;
 
             let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
                 command_buffer_count: num_command_buffers,
@@ -92,42 +246,946 @@ impl CommandBufferPool {
                 ..Default::default()
             };
 
-            let command_buffers = device
-                .allocate_command_buffers(&command_buffer_allocate_info)
-                .unwrap();
+            let command_buffers = device.allocate_command_buffers(&command_buffer_allocate_info)?;
 
             let fence_info = vk::FenceCreateInfo {
                 flags: vk::FenceCreateFlags::SIGNALED,
                 ..Default::default()
             };
 
-            let command_buffers: Vec<CommandBuffer> = command_buffers
+            let command_buffers = command_buffers
                 .iter()
                 .map(|&command_buffer| {
-                    let fence = device.create_fence(&fence_info, None).unwrap();
-                    CommandBuffer {
+                    let fence = device.create_fence(&fence_info, None)?;
+                    Ok(CommandBuffer {
                         command_buffer,
                         fence,
-                    }
+                        guard_fence: Cell::new(fence),
+                    })
                 })
-                .collect();
+                .collect::<Result<Vec<CommandBuffer>, VulkanError>>()?;
 
-            CommandBufferPool {
+            Ok(CommandBufferPool {
                 pool,
                 command_buffers,
+            })
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for command_buffer in self.command_buffers.iter() {
+                device.destroy_fence(command_buffer.fence, None);
             }
+
+            device.destroy_command_pool(self.pool, None);
+        }
+    }
+
+    // Secondary buffers from this pool must only be recorded on the thread owning the pool
+    pub fn allocate_secondary(&self, device: &Device, count: u32) -> Vec<vk::CommandBuffer> {
+        allocate_secondary_command_buffers(device, self.pool, count)
+    }
+}
+
+fn allocate_secondary_command_buffers(
+    device: &Device,
+    pool: vk::CommandPool,
+    count: u32,
+) -> Vec<vk::CommandBuffer> {
+    let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
+        command_buffer_count: count,
+        command_pool: pool,
+        level: vk::CommandBufferLevel::SECONDARY,
+        ..Default::default()
+    };
+    unsafe { device.allocate_command_buffers(&command_buffer_allocate_info) }.unwrap()
+}
+
+// Binding 0: storage buffer array, binding 1: combined image sampler array
+pub const BINDLESS_STORAGE_BUFFER_BINDING: u32 = 0;
+pub const BINDLESS_SAMPLED_IMAGE_BINDING: u32 = 1;
+
+// One descriptor set with large descriptor arrays, shaders index them with the slot returned
+// by add_*. Descriptors can be written while the set is bound (update-after-bind), unused
+// slots stay unwritten (partially bound). Freed slots are reused, so the caller has to make
+// sure no frame in flight still reads a removed slot.
+pub struct BindlessTable {
+    pub descriptor_pool: vk::DescriptorPool,
+    pub descriptor_set_layout: vk::DescriptorSetLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    pub max_storage_buffers: u32,
+    pub max_sampled_images: u32,
+    storage_buffer_slots: BindlessSlots,
+    sampled_image_slots: BindlessSlots,
+}
+
+struct BindlessSlots {
+    next: u32,
+    free: Vec<u32>,
+    max: u32,
+}
+
+impl BindlessSlots {
+    fn new(max: u32) -> BindlessSlots {
+        BindlessSlots {
+            next: 0,
+            free: Vec::new(),
+            max,
+        }
+    }
+
+    fn allocate(&mut self) -> u32 {
+        if let Some(slot) = self.free.pop() {
+            return slot;
         }
+        assert!(self.next < self.max, "Bindless table full");
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn release(&mut self, slot: u32) {
+        debug_assert!(slot < self.next && !self.free.contains(&slot));
+        self.free.push(slot);
+    }
+}
+
+impl BindlessTable {
+    // Requires capabilities.bindless, counts must be within the update-after-bind limits
+    pub fn new(
+        device: &Device,
+        max_storage_buffers: u32,
+        max_sampled_images: u32,
+    ) -> Result<BindlessTable, VulkanError> {
+        unsafe {
+            let pool_sizes = [
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: max_storage_buffers,
+                },
+                vk::DescriptorPoolSize {
+                    ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: max_sampled_images,
+                },
+            ];
+            let pool_info = vk::DescriptorPoolCreateInfo {
+                flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+                pool_size_count: pool_sizes.len() as u32,
+                p_pool_sizes: pool_sizes.as_ptr(),
+                max_sets: 1,
+                ..Default::default()
+            };
+            let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+            let bindings = [
+                vk::DescriptorSetLayoutBinding {
+                    binding: BINDLESS_STORAGE_BUFFER_BINDING,
+                    descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                    descriptor_count: max_storage_buffers,
+                    stage_flags: vk::ShaderStageFlags::ALL,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: BINDLESS_SAMPLED_IMAGE_BINDING,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    descriptor_count: max_sampled_images,
+                    stage_flags: vk::ShaderStageFlags::ALL,
+                    ..Default::default()
+                },
+            ];
+            let binding_flags = [vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND; 2];
+            let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+                binding_count: binding_flags.len() as u32,
+                p_binding_flags: binding_flags.as_ptr(),
+                ..Default::default()
+            };
+            let layout_info = vk::DescriptorSetLayoutCreateInfo {
+                p_next: &binding_flags_info as *const _ as *const std::os::raw::c_void,
+                flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+                binding_count: bindings.len() as u32,
+                p_bindings: bindings.as_ptr(),
+                ..Default::default()
+            };
+            let descriptor_set_layout = device
+                .create_descriptor_set_layout(&layout_info, None)
+                .inspect_err(|_| device.destroy_descriptor_pool(descriptor_pool, None))?;
+
+            let set_layouts = [descriptor_set_layout];
+            let alloc_info = vk::DescriptorSetAllocateInfo {
+                descriptor_pool,
+                descriptor_set_count: set_layouts.len() as u32,
+                p_set_layouts: set_layouts.as_ptr(),
+                ..Default::default()
+            };
+            let descriptor_set = match device.allocate_descriptor_sets(&alloc_info) {
+                Ok(descriptor_sets) => descriptor_sets[0],
+                Err(err) => {
+                    device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+                    device.destroy_descriptor_pool(descriptor_pool, None);
+                    return Err(err.into());
+                }
+            };
+
+            object_counters::created(ObjectType::DescriptorPool, 1);
+            object_counters::created(ObjectType::DescriptorSet, 1);
+            Ok(BindlessTable {
+                descriptor_pool,
+                descriptor_set_layout,
+                descriptor_set,
+                max_storage_buffers,
+                max_sampled_images,
+                storage_buffer_slots: BindlessSlots::new(max_storage_buffers),
+                sampled_image_slots: BindlessSlots::new(max_sampled_images),
+            })
+        }
+    }
+
+    // Returns the array index for the shader
+    pub fn add_storage_buffer(&mut self, device: &Device, info: &vk::DescriptorBufferInfo) -> u32 {
+        let slot = self.storage_buffer_slots.allocate();
+        self.write_storage_buffer(device, slot, info);
+        slot
     }
 
-    pub fn destroy(&self, device: &Device) {
-        unsafe {
-            for command_buffer in self.command_buffers.iter() {
-                device.destroy_fence(command_buffer.fence, None);
-            }
+    pub fn write_storage_buffer(
+        &self,
+        device: &Device,
+        slot: u32,
+        info: &vk::DescriptorBufferInfo,
+    ) {
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: BINDLESS_STORAGE_BUFFER_BINDING,
+            dst_array_element: slot,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            p_buffer_info: info,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+    }
+
+    pub fn remove_storage_buffer(&mut self, slot: u32) {
+        self.storage_buffer_slots.release(slot);
+    }
+
+    // Returns the array index for the shader
+    pub fn add_sampled_image(&mut self, device: &Device, info: &vk::DescriptorImageInfo) -> u32 {
+        let slot = self.sampled_image_slots.allocate();
+        self.write_sampled_image(device, slot, info);
+        slot
+    }
+
+    pub fn write_sampled_image(&self, device: &Device, slot: u32, info: &vk::DescriptorImageInfo) {
+        let write = vk::WriteDescriptorSet {
+            dst_set: self.descriptor_set,
+            dst_binding: BINDLESS_SAMPLED_IMAGE_BINDING,
+            dst_array_element: slot,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: info,
+            ..Default::default()
+        };
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+    }
+
+    pub fn remove_sampled_image(&mut self, slot: u32) {
+        self.sampled_image_slots.release(slot);
+    }
+
+    pub fn bind(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        set: u32,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                bind_point,
+                pipeline_layout,
+                set,
+                &[self.descriptor_set],
+                &[],
+            )
+        };
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+        object_counters::destroyed(ObjectType::DescriptorSet, 1);
+        object_counters::destroyed(ObjectType::DescriptorPool, 1);
+    }
+}
+
+// Descriptors of each type per set, pool sizes are these times the sets per pool
+pub const DEFAULT_DESCRIPTOR_RATIOS: [(vk::DescriptorType, u32); 7] = [
+    (vk::DescriptorType::UNIFORM_BUFFER, 2),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+    (vk::DescriptorType::STORAGE_BUFFER, 4),
+    (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 1),
+    (vk::DescriptorType::STORAGE_IMAGE, 2),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2),
+    (vk::DescriptorType::SAMPLED_IMAGE, 1),
+];
+
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+// Descriptor sets from a growable list of pools. A pool that runs out is retired and the
+// next one is created with twice the sets. reset() returns every set at once, so an
+// allocator used for per-frame sets must only be reset after the GPU finished the frame.
+pub struct DescriptorAllocator {
+    ratios: Vec<(vk::DescriptorType, u32)>,
+    sets_per_pool: u32,
+    current_pool: Option<vk::DescriptorPool>,
+    full_pools: Vec<vk::DescriptorPool>,
+    free_pools: Vec<vk::DescriptorPool>, // Reset, ready for reuse
+    pub num_allocated_sets: u32,
+}
+
+impl DescriptorAllocator {
+    pub fn new(sets_per_pool: u32) -> DescriptorAllocator {
+        DescriptorAllocator::with_ratios(sets_per_pool, &DEFAULT_DESCRIPTOR_RATIOS)
+    }
+
+    pub fn with_ratios(
+        sets_per_pool: u32,
+        ratios: &[(vk::DescriptorType, u32)],
+    ) -> DescriptorAllocator {
+        DescriptorAllocator {
+            ratios: ratios.to_vec(),
+            sets_per_pool: sets_per_pool.max(1),
+            current_pool: None,
+            full_pools: Vec::new(),
+            free_pools: Vec::new(),
+            num_allocated_sets: 0,
+        }
+    }
+
+    pub fn num_pools(&self) -> usize {
+        self.full_pools.len() + self.free_pools.len() + self.current_pool.iter().count()
+    }
+
+    fn create_pool(&mut self, device: &Device) -> Result<vk::DescriptorPool, VulkanError> {
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = self
+            .ratios
+            .iter()
+            .map(|&(ty, count)| vk::DescriptorPoolSize {
+                ty,
+                descriptor_count: count * self.sets_per_pool,
+            })
+            .collect();
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            max_sets: self.sets_per_pool,
+            ..Default::default()
+        };
+        let pool = unsafe { device.create_descriptor_pool(&pool_info, None) }?;
+        object_counters::created(ObjectType::DescriptorPool, 1);
+        self.sets_per_pool = (self.sets_per_pool * 2).min(MAX_SETS_PER_POOL);
+        Ok(pool)
+    }
+
+    fn pool(&mut self, device: &Device) -> Result<vk::DescriptorPool, VulkanError> {
+        if let Some(pool) = self.current_pool {
+            return Ok(pool);
+        }
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => self.create_pool(device)?,
+        };
+        self.current_pool = Some(pool);
+        Ok(pool)
+    }
+
+    // One set per layout, in layout order
+    pub fn allocate_sets(
+        &mut self,
+        device: &Device,
+        layouts: &[vk::DescriptorSetLayout],
+    ) -> Result<Vec<vk::DescriptorSet>, VulkanError> {
+        for retry in 0..2 {
+            let pool = self.pool(device)?;
+            let alloc_info = vk::DescriptorSetAllocateInfo {
+                descriptor_pool: pool,
+                descriptor_set_count: layouts.len() as u32,
+                p_set_layouts: layouts.as_ptr(),
+                ..Default::default()
+            };
+            match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+                Ok(sets) => {
+                    self.num_allocated_sets += sets.len() as u32;
+                    object_counters::created(ObjectType::DescriptorSet, sets.len());
+                    return Ok(sets);
+                }
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY)
+                | Err(vk::Result::ERROR_FRAGMENTED_POOL)
+                    if retry == 0 =>
+                {
+                    self.full_pools.push(pool);
+                    self.current_pool = None;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(VulkanError::Vk(vk::Result::ERROR_OUT_OF_POOL_MEMORY))
+    }
+
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        layout: vk::DescriptorSetLayout,
+    ) -> Result<vk::DescriptorSet, VulkanError> {
+        Ok(self.allocate_sets(device, &[layout])?[0])
+    }
+
+    // Frees every set allocated so far, the pools are kept for reuse
+    pub fn reset(&mut self, device: &Device) -> Result<(), VulkanError> {
+        let pools = self.full_pools.drain(..).chain(self.current_pool.take());
+        for pool in pools {
+            unsafe { device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty()) }?;
+            self.free_pools.push(pool);
+        }
+        object_counters::destroyed(ObjectType::DescriptorSet, self.num_allocated_sets as usize);
+        self.num_allocated_sets = 0;
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        let pools = self.full_pools.drain(..).chain(self.free_pools.drain(..));
+        for pool in pools.chain(self.current_pool.take()) {
+            unsafe { device.destroy_descriptor_pool(pool, None) };
+            object_counters::destroyed(ObjectType::DescriptorPool, 1);
+        }
+        object_counters::destroyed(ObjectType::DescriptorSet, self.num_allocated_sets as usize);
+        self.num_allocated_sets = 0;
+    }
+}
+
+// GPU objects waiting for the frames that may still use them
+pub enum Deletion {
+    Buffer(VkBuffer),
+    Image(VkImage),
+    ImageView(vk::ImageView),
+    Framebuffer(vk::Framebuffer),
+    Pipeline(vk::Pipeline),
+}
+
+impl Deletion {
+    fn destroy(self, device: &Device, allocator: &mut TrackingAllocator) {
+        unsafe {
+            match self {
+                Deletion::Buffer(mut buffer) => buffer.destroy(device, allocator),
+                Deletion::Image(mut image) => image.destroy(device, allocator),
+                Deletion::ImageView(view) => destroy_image_view(device, view),
+                Deletion::Framebuffer(framebuffer) => device.destroy_framebuffer(framebuffer, None),
+                Deletion::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+            }
+        }
+    }
+}
+
+// Deferred destruction keyed by frame (command buffer index). Resources pushed for a frame
+// are destroyed once the fence of that frame's command buffer signaled, so they can be
+// replaced mid-run without device_wait_idle. See VulkanBase::defer_destroy.
+pub struct DeletionQueue {
+    frames: Vec<Vec<Deletion>>,
+}
+
+impl DeletionQueue {
+    pub fn new(num_frames: usize) -> DeletionQueue {
+        DeletionQueue {
+            frames: (0..num_frames).map(|_| Vec::new()).collect(),
+        }
+    }
+
+    pub fn push(&mut self, frame: usize, deletion: Deletion) {
+        self.frames[frame].push(deletion);
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.iter().map(|frame| frame.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.iter().all(|frame| frame.is_empty())
+    }
+
+    // The GPU must be done with the frame
+    pub fn flush_frame(
+        &mut self,
+        frame: usize,
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+    ) {
+        for deletion in self.frames[frame].drain(..) {
+            deletion.destroy(device, allocator);
+        }
+    }
+
+    // The GPU must be idle
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        for frame in 0..self.frames.len() {
+            self.flush_frame(frame, device, allocator);
+        }
+    }
+}
+
+// CPU cost of vkQueueSubmit, see VulkanBase::take_submit_stats
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SubmitStats {
+    pub submits: u32,
+    pub command_buffers: u32,
+    pub submit_time: Duration,
+}
+
+struct BatchedCommandBuffer {
+    index: usize, // Command buffer pool index
+    wait_mask: Vec<vk::PipelineStageFlags>,
+    wait_semaphores: Vec<vk::Semaphore>,
+    signal_semaphores: Vec<vk::Semaphore>,
+}
+
+// Command buffers recorded with VulkanBase::record_commandbuffer, submitted in order by a
+// single vkQueueSubmit in VulkanBase::submit_batch
+#[derive(Default)]
+pub struct SubmitBatch {
+    command_buffers: Vec<BatchedCommandBuffer>,
+}
+
+impl SubmitBatch {
+    pub fn new() -> SubmitBatch {
+        SubmitBatch::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.command_buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.command_buffers.is_empty()
+    }
+}
+
+// Command pools are externally synchronized, so each worker owns a pool with one secondary
+// command buffer per primary command buffer (frame in flight)
+pub struct SecondaryCommandBuffers {
+    pub pools: Vec<vk::CommandPool>,
+    pub command_buffers: Vec<Vec<vk::CommandBuffer>>, // [worker][frame]
+}
+
+impl SecondaryCommandBuffers {
+    pub fn new(
+        device: &Device,
+        queue_family_index: u32,
+        num_workers: u32,
+        num_frames: u32,
+    ) -> SecondaryCommandBuffers {
+        let pool_create_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index,
+            ..Default::default()
+        };
+
+        let pools: Vec<vk::CommandPool> = (0..num_workers)
+            .map(|_| unsafe { device.create_command_pool(&pool_create_info, None) }.unwrap())
+            .collect();
+
+        let command_buffers = pools
+            .iter()
+            .map(|&pool| allocate_secondary_command_buffers(device, pool, num_frames))
+            .collect();
+
+        SecondaryCommandBuffers {
+            pools,
+            command_buffers,
+        }
+    }
+
+    pub fn num_workers(&self) -> usize {
+        self.pools.len()
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            for &pool in self.pools.iter() {
+                device.destroy_command_pool(pool, None);
+            }
+        }
+    }
+}
+
+// Secondary command buffers recorded inside a render pass inherit the pass and subpass.
+// Dynamic state (viewport, scissor) is not inherited and must be set by each recorder.
+pub fn secondary_inheritance_info(
+    render_pass: vk::RenderPass,
+    subpass: u32,
+    framebuffer: vk::Framebuffer,
+) -> vk::CommandBufferInheritanceInfo<'static> {
+    vk::CommandBufferInheritanceInfo {
+        render_pass,
+        subpass,
+        framebuffer,
+        ..Default::default()
+    }
+}
+
+pub fn begin_secondary_command_buffer(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    inheritance_info: &vk::CommandBufferInheritanceInfo,
+) {
+    let command_buffer_begin_info = vk::CommandBufferBeginInfo {
+        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT
+            | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+        p_inheritance_info: inheritance_info,
+        ..Default::default()
+    };
+    unsafe {
+        device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .expect("Reset secondary command buffer failed.");
+        device
+            .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+            .expect("Begin secondary commandbuffer");
+    }
+}
+
+// Records one render pass subpass worth of commands, must be Send to run on a worker thread
+pub type SecondaryRecorder<'a> = Box<dyn Fn(&Device, vk::CommandBuffer) + Send + Sync + 'a>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameResult {
+    Ok(u32), // Present image index
+    NeedsRecreate,
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn create_swapchain(
+    surface_loader: &Surface,
+    swapchain_loader: &Swapchain,
+    pdevice: vk::PhysicalDevice,
+    surface: vk::SurfaceKHR,
+    surface_format: vk::SurfaceFormatKHR,
+    memory_profile: &MemoryProfile,
+    display_settings: &DisplaySettings,
+    full_screen_exclusive: bool,
+    workarounds: Workarounds,
+    device_group_modes: vk::DeviceGroupPresentModeFlagsKHR, // Empty without a device group
+    window_size: (u32, u32),
+    old_swapchain: vk::SwapchainKHR,
+) -> Result<(vk::SwapchainKHR, vk::Extent2D), VulkanError> {
+    let surface_capabilities =
+        surface_loader.get_physical_device_surface_capabilities(pdevice, surface)?;
+    let desired_image_count = display_settings
+        .swapchain_image_count(&surface_capabilities, memory_profile.extra_swapchain_images);
+    let surface_resolution = match surface_capabilities.current_extent.width {
+        u32::MAX => vk::Extent2D {
+            width: window_size.0,
+            height: window_size.1,
+        },
+        _ => surface_capabilities.current_extent,
+    };
+    let pre_transform = if surface_capabilities
+        .supported_transforms
+        .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+    {
+        vk::SurfaceTransformFlagsKHR::IDENTITY
+    } else {
+        surface_capabilities.current_transform
+    };
+    let present_modes =
+        surface_loader.get_physical_device_surface_present_modes(pdevice, surface)?;
+    let avoid_immediate = workarounds.contains(Workaround::AvoidImmediatePresent);
+    let present_mode = present_modes
+        .iter()
+        .cloned()
+        .find(|&mode| mode == vk::PresentModeKHR::IMMEDIATE && !avoid_immediate)
+        //.find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+
+    // ALLOWED lets the driver pick exclusive presentation on its own, APPLICATION_CONTROLLED
+    // would need the platform monitor handle
+    let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT {
+        full_screen_exclusive: vk::FullScreenExclusiveEXT::ALLOWED,
+        ..Default::default()
+    };
+    let device_group_info = vk::DeviceGroupSwapchainCreateInfoKHR {
+        modes: device_group_modes,
+        ..Default::default()
+    };
+
+    let mut p_next: *const std::os::raw::c_void = std::ptr::null();
+    if !device_group_modes.is_empty() {
+        p_next = &device_group_info as *const _ as *const std::os::raw::c_void;
+    }
+    if full_screen_exclusive {
+        full_screen_exclusive_info.p_next = p_next as *mut std::os::raw::c_void;
+        p_next = &full_screen_exclusive_info as *const _ as *const std::os::raw::c_void;
+    }
+
+    let swapchain_create_info = vk::SwapchainCreateInfoKHR {
+        p_next,
+        surface,
+        min_image_count: desired_image_count,
+        image_color_space: surface_format.color_space,
+        image_format: surface_format.format,
+        image_extent: surface_resolution,
+        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        image_sharing_mode: vk::SharingMode::EXCLUSIVE,
+        pre_transform,
+        composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+        present_mode,
+        clipped: vk::TRUE,
+        image_array_layers: 1,
+        old_swapchain,
+        ..Default::default()
+    };
+
+    let swapchain = swapchain_loader.create_swapchain(&swapchain_create_info, None)?;
+
+    Ok((swapchain, surface_resolution))
+}
+
+unsafe fn create_present_image_views(
+    device: &Device,
+    present_images: &[vk::Image],
+    format: vk::Format,
+) -> Result<Vec<vk::ImageView>, VulkanError> {
+    present_images
+        .iter()
+        .map(|&image| {
+            let create_view_info = vk::ImageViewCreateInfo {
+                view_type: vk::ImageViewType::TYPE_2D,
+                format,
+                components: vk::ComponentMapping {
+                    r: vk::ComponentSwizzle::R,
+                    g: vk::ComponentSwizzle::G,
+                    b: vk::ComponentSwizzle::B,
+                    a: vk::ComponentSwizzle::A,
+                },
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image,
+                ..Default::default()
+            };
+            Ok(create_image_view(device, &create_view_info)?)
+        })
+        .collect()
+}
+
+// Preferred first. The depth buffer is also sampled by the depth pyramid.
+const DEPTH_FORMAT_CANDIDATES: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+
+pub fn select_depth_format(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+) -> Result<vk::Format, VulkanError> {
+    let attachment = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT;
+    let sampled = attachment | vk::FormatFeatureFlags::SAMPLED_IMAGE;
+    let first_supported = |features| {
+        first_supported_format(
+            instance,
+            pdevice,
+            &DEPTH_FORMAT_CANDIDATES,
+            vk::ImageTiling::OPTIMAL,
+            features,
+        )
+    };
+
+    first_supported(sampled)
+        .or_else(|| first_supported(attachment))
+        .ok_or(VulkanError::NoDepthFormat)
+}
+
+// Layout transitions of combined depth/stencil images must include both aspects
+pub fn depth_aspect_mask(depth_format: vk::Format) -> vk::ImageAspectFlags {
+    image_aspect_mask(depth_format)
+}
+
+unsafe fn create_depth_image(
+    device: &Device,
+    allocator: &mut TrackingAllocator,
+    gpu_budget: &GpuBudget,
+    depth_format: vk::Format,
+    surface_resolution: vk::Extent2D,
+) -> Result<(VkImage, vk::ImageView), VulkanError> {
+    let depth_image_create_info = vk::ImageCreateInfo {
+        image_type: vk::ImageType::TYPE_2D,
+        format: depth_format,
+        extent: vk::Extent3D {
+            width: surface_resolution.width,
+            height: surface_resolution.height,
+            depth: 1,
+        },
+        mip_levels: 1,
+        array_layers: 1,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+
+    let depth_image = VkImage::new_budgeted(
+        device,
+        allocator,
+        (gpu_budget, BudgetTag::Framebuffers),
+        &depth_image_create_info,
+        MemoryLocation::GpuOnly,
+    )?;
+
+    let depth_image_view_info = vk::ImageViewCreateInfo {
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            level_count: 1,
+            layer_count: 1,
+            ..Default::default()
+        },
+        image: depth_image.image,
+        format: depth_image_create_info.format,
+        view_type: vk::ImageViewType::TYPE_2D,
+        ..Default::default()
+    };
+
+    let depth_image_view = create_image_view(device, &depth_image_view_info)?;
+
+    Ok((depth_image, depth_image_view))
+}
+
+// Everything owned by the logical device, re-created together by try_recover
+struct DeviceObjects {
+    device: Device,
+    present_queue: vk::Queue,
+    swapchain_loader: Swapchain,
+    mesh_shader_loader: Option<MeshShader>,
+    barrier_api: BarrierApi,
+    allocator: TrackingAllocator,
+    present_complete_semaphore: vk::Semaphore,
+    rendering_complete_semaphore: vk::Semaphore,
+    command_buffer_pool: CommandBufferPool,
+}
+
+unsafe fn create_device_objects(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    queue_family_index: u32,
+    feature_chain: &mut DeviceFeatureChain,
+    extension_names: &[&'static CStr],
+    device_group: &[vk::PhysicalDevice], // Empty for a single GPU
+) -> Result<DeviceObjects, VulkanError> {
+    let capabilities = feature_chain.capabilities();
+    let extension_names_raw: Vec<*const i8> =
+        extension_names.iter().map(|cstr| cstr.as_ptr()).collect();
+
+    let features = vk::PhysicalDeviceFeatures {
+        shader_clip_distance: 1,
+        sampler_anisotropy: feature_chain.sampler_anisotropy as u32,
+        texture_compression_bc: feature_chain.texture_compression_bc as u32,
+        multi_draw_indirect: feature_chain.multi_draw_indirect as u32,
+        pipeline_statistics_query: feature_chain.pipeline_statistics_query as u32,
+        occlusion_query_precise: feature_chain.occlusion_query_precise as u32,
+        sparse_binding: feature_chain.sparse_binding as u32,
+        sparse_residency_image3_d: feature_chain.sparse_residency_image3d as u32,
+        //geometry_shader: 1,
+        ..Default::default()
+    };
+
+    let priorities = [1.0];
+
+    let queue_info = [vk::DeviceQueueCreateInfo {
+        queue_family_index,
+        p_queue_priorities: priorities.as_ptr(),
+        queue_count: priorities.len() as u32,
+        ..Default::default()
+    }];
+
+    // The device group info goes in front of the feature chain
+    let device_group_info = vk::DeviceGroupDeviceCreateInfo {
+        p_next: feature_chain.link(),
+        physical_device_count: device_group.len() as u32,
+        p_physical_devices: device_group.as_ptr(),
+        ..Default::default()
+    };
+
+    let device_create_info = vk::DeviceCreateInfo {
+        p_next: if device_group.is_empty() {
+            device_group_info.p_next
+        } else {
+            &device_group_info as *const _ as *const std::os::raw::c_void
+        },
+        p_queue_create_infos: queue_info.as_ptr(),
+        queue_create_info_count: queue_info.len() as u32,
+        pp_enabled_extension_names: extension_names_raw.as_ptr(),
+        enabled_extension_count: extension_names_raw.len() as u32,
+        p_enabled_features: &features,
+        ..Default::default()
+    };
+
+    let device: Device = instance.create_device(pdevice, &device_create_info, None)?;
+
+    let present_queue = device.get_device_queue(queue_family_index, 0);
 
-            device.destroy_command_pool(self.pool, None);
-        }
-    }
+    let swapchain_loader = Swapchain::new(instance, &device);
+    let mesh_shader_loader = if capabilities.mesh_shader {
+        Some(MeshShader::new(instance, &device))
+    } else {
+        None
+    };
+    let barrier_api = if !capabilities.synchronization2 {
+        BarrierApi::Legacy
+    } else if capabilities.supports_api(1, 3) {
+        BarrierApi::Core
+    } else {
+        BarrierApi::Extension(ash::khr::synchronization2::Device::new(instance, &device))
+    };
+
+    let allocator = Allocator::new(&AllocatorCreateDesc {
+        instance: instance.clone(),
+        device: device.clone(),
+        physical_device: pdevice,
+        debug_settings: Default::default(),
+        buffer_device_address: capabilities.buffer_device_address,
+        allocation_sizes: allocation_sizes(),
+    })
+    .map_err(VulkanError::Allocator)?;
+    let allocator = TrackingAllocator::new(allocator, AllocatorTelemetry::new(instance, pdevice));
+
+    let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+
+    let present_complete_semaphore = device.create_semaphore(&semaphore_create_info, None)?;
+    let rendering_complete_semaphore = device.create_semaphore(&semaphore_create_info, None)?;
+
+    let command_buffer_pool =
+        CommandBufferPool::new(&device, queue_family_index, NUM_COMMAND_BUFFERS)?;
+
+    Ok(DeviceObjects {
+        device,
+        present_queue,
+        swapchain_loader,
+        mesh_shader_loader,
+        barrier_api,
+        allocator,
+        present_complete_semaphore,
+        rendering_complete_semaphore,
+        command_buffer_pool,
+    })
 }
 
 pub struct VulkanBase {
@@ -151,6 +1209,7 @@ pub struct VulkanBase {
     pub present_images: Vec<vk::Image>,
     pub present_image_views: Vec<vk::ImageView>,
 
+    pub depth_format: vk::Format,
     pub depth_image: VkImage,
     pub depth_image_view: vk::ImageView,
 
@@ -159,45 +1218,179 @@ pub struct VulkanBase {
 
     pub command_buffer_pool: CommandBufferPool,
 
-    pub allocator: ManuallyDrop<Allocator>,
+    pub allocator: ManuallyDrop<TrackingAllocator>,
+
+    pub memory_profile: MemoryProfile,
+    pub gpu_budget: GpuBudget,
+    pub capabilities: DeviceCapabilities,
+    pub sampler_cache: SamplerCache, // Shared by all modules, destroyed with the device
+    pub mesh_shader_loader: Option<MeshShader>,
+    pub barrier_api: BarrierApi,
+    pub debug_settings: DebugSettings,
+    pub validation_log: Box<ValidationLog>, // Boxed, the debug callback points to it
+    pub display_settings: DisplaySettings,
+    pub full_screen_exclusive: bool, // VK_EXT_full_screen_exclusive enabled
+    pub memory_budget: bool,         // VK_EXT_memory_budget enabled
+    pub workarounds: Workarounds,    // Vendor and driver quirks of the device
+    pub device_extension_names: Vec<&'static CStr>,
+    pub submit_stats: Cell<SubmitStats>,
+    pub deletion_queue: DeletionQueue,
+    pub device_group: Option<DeviceGroup>, // --device-group on linked GPUs
+    last_submitted: Cell<usize>,           // Command buffer index of the latest submit
 }
 
 impl VulkanBase {
-    pub fn new(window: &Window, window_width: u32, window_height: u32) -> Self {
+    pub fn enumerate_instance_capabilities(
+        entry: &Entry,
+    ) -> Result<InstanceCapabilities, VulkanError> {
         unsafe {
-            let entry = Entry::load().unwrap();
-            let app_name = CString::new("VulkanTest").unwrap();
-
-            let layer_names = [CString::new("VK_LAYER_KHRONOS_validation").unwrap()];
-            let layers_names_raw: Vec<*const i8> = layer_names
+            let layers = entry
+                .enumerate_instance_layer_properties()?
+                .iter()
+                .filter_map(|properties| properties.layer_name_as_c_str().ok())
+                .map(CStr::to_owned)
+                .collect();
+            let extensions = entry
+                .enumerate_instance_extension_properties(None)?
                 .iter()
-                .map(|raw_name| raw_name.as_ptr())
+                .filter_map(|properties| properties.extension_name_as_c_str().ok())
+                .map(CStr::to_owned)
                 .collect();
+            Ok(InstanceCapabilities { layers, extensions })
+        }
+    }
+
+    pub fn new(
+        window: &Window,
+        window_width: u32,
+        window_height: u32,
+    ) -> Result<Self, VulkanError> {
+        VulkanBase::new_with_profile(
+            window,
+            window_width,
+            window_height,
+            MemoryProfile::default(),
+        )
+    }
+
+    pub fn new_with_profile(
+        window: &Window,
+        window_width: u32,
+        window_height: u32,
+        memory_profile: MemoryProfile,
+    ) -> Result<Self, VulkanError> {
+        VulkanBase::new_with_settings(
+            window,
+            window_width,
+            window_height,
+            memory_profile,
+            DebugSettings::default(),
+            DisplaySettings::default(),
+        )
+    }
+
+    pub fn new_with_settings(
+        window: &Window,
+        window_width: u32,
+        window_height: u32,
+        memory_profile: MemoryProfile,
+        mut debug_settings: DebugSettings,
+        display_settings: DisplaySettings,
+    ) -> Result<Self, VulkanError> {
+        unsafe {
+            let entry = Entry::load().map_err(VulkanError::Loading)?;
+            let app_name = CString::new("VulkanTest").unwrap();
+
+            // Optional layers and extensions are only requested when available
+            let instance_capabilities = VulkanBase::enumerate_instance_capabilities(&entry)?;
+            let validation_layer = instance_capabilities.has_layer(VALIDATION_LAYER_NAME);
+            if !validation_layer {
+                println!("Validation layer {:?} not available", VALIDATION_LAYER_NAME);
+            }
+            let layers_names_raw: Vec<*const i8> = if validation_layer {
+                vec![VALIDATION_LAYER_NAME.as_ptr()]
+            } else {
+                Vec::new()
+            };
 
             let display_handle = window.display_handle().unwrap();
             let window_handle = window.window_handle().unwrap();
             let raw_display_handle = display_handle.as_raw();
             let raw_window_handle = window_handle.as_raw();
-            let mut extension_names_raw: Vec<*const i8> = ash_window::enumerate_required_extensions(raw_display_handle)
-                .unwrap()
-                .to_vec();
-            extension_names_raw.push(ash::ext::debug_utils::NAME.as_ptr());
-            extension_names_raw.push(
-                ::std::ffi::CStr::from_bytes_with_nul(b"VK_KHR_get_physical_device_properties2\0")
-                    .expect("Wrong extension string")
-                    .as_ptr(),
-            );
+            let mut extension_names_raw: Vec<*const i8> =
+                ash_window::enumerate_required_extensions(raw_display_handle)?.to_vec();
+            for &name in extension_names_raw.iter() {
+                let name = CStr::from_ptr(name);
+                if !instance_capabilities.has_extension(name) {
+                    let name = name.to_string_lossy().into_owned();
+                    return Err(VulkanError::MissingInstanceExtension(name));
+                }
+            }
+
+            // Without debug utils validation messages aren't reported
+            let debug_utils = instance_capabilities.has_extension(ash::ext::debug_utils::NAME);
+            if debug_utils {
+                extension_names_raw.push(ash::ext::debug_utils::NAME.as_ptr());
+            } else {
+                println!("{:?} not available", ash::ext::debug_utils::NAME);
+            }
+
+            // Core in Vulkan 1.1, only needed by 1.0 loaders
+            let properties2 = ash::khr::get_physical_device_properties2::NAME;
+            if instance_capabilities.has_extension(properties2) {
+                extension_names_raw.push(properties2.as_ptr());
+            } else {
+                println!("{:?} not available", properties2);
+            }
+
+            // Needed by VK_EXT_full_screen_exclusive
+            let surface_capabilities2 = display_settings.fullscreen == FullscreenMode::Exclusive
+                && instance_capabilities.has_extension(ash::khr::get_surface_capabilities2::NAME);
+            if surface_capabilities2 {
+                extension_names_raw.push(ash::khr::get_surface_capabilities2::NAME.as_ptr());
+            }
+
+            if debug_settings.shader_printf {
+                if validation_layer
+                    && layer_supports_extension(
+                        &entry,
+                        VALIDATION_LAYER_NAME,
+                        ash::ext::validation_features::NAME,
+                    )
+                {
+                    extension_names_raw.push(ash::ext::validation_features::NAME.as_ptr());
+                } else {
+                    println!(
+                        "Shader printf disabled: validation layer doesn't support VK_EXT_validation_features"
+                    );
+                    debug_settings.shader_printf = false;
+                }
+            }
+
+            let enabled_validation_features = [vk::ValidationFeatureEnableEXT::DEBUG_PRINTF];
+            let validation_features = vk::ValidationFeaturesEXT {
+                enabled_validation_feature_count: enabled_validation_features.len() as u32,
+                p_enabled_validation_features: enabled_validation_features.as_ptr(),
+                ..Default::default()
+            };
+
+            let instance_api_version = negotiate_instance_api_version(&entry);
 
             let appinfo = vk::ApplicationInfo {
                 p_application_name: app_name.as_ptr(),
                 application_version: 0,
                 p_engine_name: app_name.as_ptr(),
                 engine_version: 0,
-                api_version: vk::make_api_version(0, 1, 0, 0),
+                api_version: instance_api_version,
                 ..Default::default()
             };
 
             let create_info = vk::InstanceCreateInfo {
+                p_next: if debug_settings.shader_printf {
+                    &validation_features as *const _ as *const std::os::raw::c_void
+                } else {
+                    std::ptr::null()
+                },
                 p_application_info: &appinfo,
                 pp_enabled_layer_names: layers_names_raw.as_ptr(),
                 pp_enabled_extension_names: extension_names_raw.as_ptr(),
@@ -206,24 +1399,32 @@ impl VulkanBase {
                 ..Default::default()
             };
 
-            let instance: Instance = entry
-                .create_instance(&create_info, None)
-                .expect("Instance creation error");
+            let instance: Instance = entry.create_instance(&create_info, None)?;
+
+            // Printf messages are INFO severity
+            let mut message_severity = vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING;
+            if debug_settings.shader_printf {
+                message_severity |= vk::DebugUtilsMessageSeverityFlagsEXT::INFO;
+            }
 
+            let validation_log = Box::new(ValidationLog::new(debug_settings.validation.clone()));
             let debug_info = vk::DebugUtilsMessengerCreateInfoEXT {
-                message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+                message_severity,
                 message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
                     | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
                     | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
                 pfn_user_callback: Some(vulkan_debug_callback),
+                p_user_data: &*validation_log as *const ValidationLog as *mut _,
                 ..Default::default()
             };
 
             let debug_utils_loader = DebugUtils::new(&entry, &instance);
-            let debug_call_back = debug_utils_loader
-                .create_debug_utils_messenger(&debug_info, None)
-                .unwrap();
+            let debug_call_back = if debug_utils {
+                debug_utils_loader.create_debug_utils_messenger(&debug_info, None)?
+            } else {
+                vk::DebugUtilsMessengerEXT::null()
+            };
             let surface = {
                 ash_window::create_surface(
                     &entry,
@@ -231,11 +1432,9 @@ impl VulkanBase {
                     raw_display_handle,
                     raw_window_handle,
                     None,
-                ).unwrap()
+                )?
             };
-            let pdevices = instance
-                .enumerate_physical_devices()
-                .expect("Physical device error");
+            let pdevices = instance.enumerate_physical_devices()?;
             let surface_loader = Surface::new(&entry, &instance);
             let (pdevice, queue_family_index) = pdevices
                 .iter()
@@ -253,7 +1452,7 @@ impl VulkanBase {
                                             index as u32,
                                             surface,
                                         )
-                                        .unwrap();
+                                        .unwrap_or(false);
                             if supports_graphic_and_surface {
                                 Some((*pdevice, index))
                             } else {
@@ -264,52 +1463,130 @@ impl VulkanBase {
                 })
                 //.skip(1)      // Enable to select secondary GPU
                 .next()
-                .expect("Couldn't find suitable device.");
+                .ok_or(VulkanError::NoSuitableDevice)?;
             let queue_family_index = queue_family_index as u32;
 
-            let device_extension_names = [
-                ash::khr::swapchain::NAME, /*, &CString::new("VK_NV_mesh_shader").unwrap()*/
-            ];
-            let device_extension_names_raw: Vec<*const i8> = device_extension_names
-                .iter()
-                .map(|cstr| cstr.as_ptr())
-                .collect();
+            let mut feature_chain =
+                DeviceFeatureChain::query(&instance, pdevice, instance_api_version);
+            feature_chain.limit_to_queue_family(
+                instance.get_physical_device_queue_family_properties(pdevice)
+                    [queue_family_index as usize]
+                    .queue_flags,
+            );
+            let capabilities = feature_chain.capabilities();
+            println!("Device capabilities: {:?}", capabilities);
+
+            // Vendor and driver quirks, see gpu_workarounds.rs
+            let properties = instance.get_physical_device_properties(pdevice);
+            let workarounds = debug_settings.workarounds.resolve(
+                properties.vendor_id,
+                properties.device_id,
+                properties.driver_version,
+            );
+            set_active(workarounds);
+            println!(
+                "Device {:04x}:{:04x}, driver {}, workarounds: {}",
+                properties.vendor_id,
+                properties.device_id,
+                driver_version_string(properties.vendor_id, properties.driver_version),
+                workarounds
+            );
+            for workaround in workarounds.iter() {
+                let reason = reason(
+                    workaround,
+                    properties.vendor_id,
+                    properties.device_id,
+                    properties.driver_version,
+                );
+                println!("  {}: {}", workaround.name(), reason.unwrap_or("forced"));
+            }
 
-            let features = vk::PhysicalDeviceFeatures {
-                shader_clip_distance: 1,
-                //geometry_shader: 1,
-                ..Default::default()
-            };
+            let mut device_extension_names = vec![ash::khr::swapchain::NAME];
+            device_extension_names.extend(feature_chain.extension_names());
+
+            // Windows only, other platforms present fullscreen windows without it
+            let full_screen_exclusive = surface_capabilities2
+                && device_supports_extension(
+                    &instance,
+                    pdevice,
+                    ash::ext::full_screen_exclusive::NAME,
+                );
+            if full_screen_exclusive {
+                device_extension_names.push(ash::ext::full_screen_exclusive::NAME);
+            }
 
-            //let mut mesh_shader = vk::PhysicalDeviceMeshShaderFeaturesNV::builder().mesh_shader(true).task_shader(true).build();
+            // Per-heap usage for memory_report(), queried through the Vulkan 1.1 properties2 path
+            let memory_budget = capabilities.supports_api(1, 1)
+                && device_supports_extension(&instance, pdevice, ash::ext::memory_budget::NAME);
+            if memory_budget {
+                device_extension_names.push(ash::ext::memory_budget::NAME);
+            }
 
-            let priorities = [1.0];
+            // debugPrintfEXT compiles to a non-semantic instruction set, core in Vulkan 1.3
+            if debug_settings.shader_printf && !capabilities.supports_api(1, 3) {
+                if device_supports_extension(
+                    &instance,
+                    pdevice,
+                    ash::khr::shader_non_semantic_info::NAME,
+                ) {
+                    device_extension_names.push(ash::khr::shader_non_semantic_info::NAME);
+                } else {
+                    println!(
+                        "Shader printf disabled: VK_KHR_shader_non_semantic_info not supported"
+                    );
+                    debug_settings.shader_printf = false;
+                }
+            }
+            // Linked GPUs rendering alternate frames, device groups are core in Vulkan 1.1
+            let device_group_devices = if display_settings.device_group {
+                let devices = if capabilities.supports_api(1, 1) {
+                    find_device_group(&instance, pdevice)
+                } else {
+                    None
+                };
+                if devices.is_none() {
+                    println!("Device group disabled: no device group with more than one GPU");
+                }
+                devices
+            } else {
+                None
+            };
 
-            let queue_info = [vk::DeviceQueueCreateInfo {
+            let DeviceObjects {
+                device,
+                present_queue,
+                swapchain_loader,
+                mesh_shader_loader,
+                barrier_api,
+                mut allocator,
+                present_complete_semaphore,
+                rendering_complete_semaphore,
+                command_buffer_pool,
+            } = create_device_objects(
+                &instance,
+                pdevice,
                 queue_family_index,
-                p_queue_priorities: priorities.as_ptr(),
-                queue_count: priorities.len() as u32,
-                ..Default::default()
-            }];
-
-            let device_create_info = vk::DeviceCreateInfo {
-                p_queue_create_infos: queue_info.as_ptr(),
-                queue_create_info_count: queue_info.len() as u32,
-                pp_enabled_extension_names: device_extension_names_raw.as_ptr(),
-                enabled_extension_count: device_extension_names_raw.len() as u32,
-                p_enabled_features: &features,
-                ..Default::default()
+                &mut feature_chain,
+                &device_extension_names,
+                device_group_devices.as_deref().unwrap_or(&[]),
+            )?;
+
+            let device_group = match device_group_devices {
+                Some(devices) => {
+                    let mut device_group = DeviceGroup::new(devices);
+                    device_group.query_present_modes(&swapchain_loader, surface)?;
+                    println!(
+                        "Device group: {} GPUs, alternate frames on GPUs {:?}",
+                        device_group.device_count(),
+                        device_group.afr_devices()
+                    );
+                    Some(device_group)
+                }
+                None => None,
             };
 
-            let device: Device = instance
-                .create_device(pdevice, &device_create_info, None)
-                .unwrap();
-
-            let present_queue = device.get_device_queue(queue_family_index as u32, 0);
-
-            let surface_formats = surface_loader
-                .get_physical_device_surface_formats(pdevice, surface)
-                .unwrap();
+            let surface_formats =
+                surface_loader.get_physical_device_surface_formats(pdevice, surface)?;
             let surface_format = surface_formats
                 .iter()
                 .map(|sfmt| match sfmt.format {
@@ -320,151 +1597,40 @@ impl VulkanBase {
                     _ => *sfmt,
                 })
                 .next()
-                .expect("Unable to find suitable surface format.");
-            let surface_capabilities = surface_loader
-                .get_physical_device_surface_capabilities(pdevice, surface)
-                .unwrap();
-            let mut desired_image_count = surface_capabilities.min_image_count + 1;
-            if surface_capabilities.max_image_count > 0
-                && desired_image_count > surface_capabilities.max_image_count
-            {
-                desired_image_count = surface_capabilities.max_image_count;
-            }
-            let surface_resolution = match surface_capabilities.current_extent.width {
-                std::u32::MAX => vk::Extent2D {
-                    width: window_width,
-                    height: window_height,
-                },
-                _ => surface_capabilities.current_extent,
-            };
-            let pre_transform = if surface_capabilities
-                .supported_transforms
-                .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
-            {
-                vk::SurfaceTransformFlagsKHR::IDENTITY
-            } else {
-                surface_capabilities.current_transform
-            };
-            let present_modes = surface_loader
-                .get_physical_device_surface_present_modes(pdevice, surface)
-                .unwrap();
-            let present_mode = present_modes
-                .iter()
-                .cloned()
-                .find(|&mode| mode == vk::PresentModeKHR::IMMEDIATE)
-                //.find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-                .unwrap_or(vk::PresentModeKHR::FIFO);
-            let swapchain_loader = Swapchain::new(&instance, &device);
-
-            let swapchain_create_info = vk::SwapchainCreateInfoKHR {
-                surface: surface,
-                min_image_count: desired_image_count,
-                image_color_space: surface_format.color_space,
-                image_format: surface_format.format,
-                image_extent: surface_resolution,
-                image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                image_sharing_mode: vk::SharingMode::EXCLUSIVE,
-                pre_transform: pre_transform,
-                composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
-                present_mode: present_mode,
-                clipped: vk::TRUE,
-                image_array_layers: 1,
-                ..Default::default()
-            };
-
-            let swapchain = swapchain_loader
-                .create_swapchain(&swapchain_create_info, None)
-                .unwrap();
-
-            let present_images = swapchain_loader.get_swapchain_images(swapchain).unwrap();
-            let present_image_views: Vec<vk::ImageView> = present_images
-                .iter()
-                .map(|&image| {
-                    let create_view_info = vk::ImageViewCreateInfo {
-                        view_type: vk::ImageViewType::TYPE_2D,
-                        format: surface_format.format,
-                        components: vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::R,
-                            g: vk::ComponentSwizzle::G,
-                            b: vk::ComponentSwizzle::B,
-                            a: vk::ComponentSwizzle::A,
-                        },
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1,
-                        },
-                        image: image,
-                        ..Default::default()
-                    };
-                    device.create_image_view(&create_view_info, None).unwrap()
-                })
-                .collect();
-
-            let mut allocator = Allocator::new(&AllocatorCreateDesc {
-                instance: instance.clone(),
-                device: device.clone(),
-                physical_device: pdevice,
-                debug_settings: Default::default(),
-                buffer_device_address: false,
-                allocation_sizes: Default::default(),
-            })
-            .unwrap();
-
-            let depth_image_create_info = vk::ImageCreateInfo {
-                image_type: vk::ImageType::TYPE_2D,
-                format: vk::Format::D32_SFLOAT,
-                extent: vk::Extent3D {
-                    width: surface_resolution.width,
-                    height: surface_resolution.height,
-                    depth: 1,
-                },
-                mip_levels: 1,
-                array_layers: 1,
-                samples: vk::SampleCountFlags::TYPE_1,
-                tiling: vk::ImageTiling::OPTIMAL,
-                usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
-                sharing_mode: vk::SharingMode::EXCLUSIVE,
-                ..Default::default()
-            };
-
-            let depth_image = VkImage::new(
+                .ok_or(VulkanError::NoSurfaceFormat)?;
+            let (swapchain, surface_resolution) = create_swapchain(
+                &surface_loader,
+                &swapchain_loader,
+                pdevice,
+                surface,
+                surface_format,
+                &memory_profile,
+                &display_settings,
+                full_screen_exclusive,
+                workarounds,
+                device_group
+                    .as_ref()
+                    .map_or(vk::DeviceGroupPresentModeFlagsKHR::empty(), |group| {
+                        group.swapchain_modes()
+                    }),
+                (window_width, window_height),
+                vk::SwapchainKHR::null(),
+            )?;
+
+            let present_images = swapchain_loader.get_swapchain_images(swapchain)?;
+            let present_image_views =
+                create_present_image_views(&device, &present_images, surface_format.format)?;
+
+            let gpu_budget = memory_profile.gpu_budget();
+
+            let depth_format = select_depth_format(&instance, pdevice)?;
+            let (depth_image, depth_image_view) = create_depth_image(
                 &device,
                 &mut allocator,
-                &depth_image_create_info,
-                MemoryLocation::GpuOnly,
-            );
-
-            let depth_image_view_info = vk::ImageViewCreateInfo {
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    level_count: 1,
-                    layer_count: 1,
-                    ..Default::default()
-                },
-                image: depth_image.image,
-                format: depth_image_create_info.format,
-                view_type: vk::ImageViewType::TYPE_2D,
-                ..Default::default()
-            };
-
-            let depth_image_view = device
-                .create_image_view(&depth_image_view_info, None)
-                .unwrap();
-
-            let semaphore_create_info = vk::SemaphoreCreateInfo::default();
-
-            let present_complete_semaphore = device
-                .create_semaphore(&semaphore_create_info, None)
-                .unwrap();
-            let rendering_complete_semaphore = device
-                .create_semaphore(&semaphore_create_info, None)
-                .unwrap();
-
-            let command_buffer_pool =
-                CommandBufferPool::new(&device, queue_family_index, NUM_COMMAND_BUFFERS);
+                &gpu_budget,
+                depth_format,
+                surface_resolution,
+            )?;
 
             let vk = VulkanBase {
                 entry,
@@ -480,6 +1646,7 @@ impl VulkanBase {
                 swapchain,
                 present_images,
                 present_image_views,
+                depth_format,
                 depth_image,
                 depth_image_view,
                 present_complete_semaphore,
@@ -489,44 +1656,476 @@ impl VulkanBase {
                 debug_utils_loader,
                 command_buffer_pool,
                 allocator: ManuallyDrop::new(allocator),
+                memory_profile,
+                gpu_budget,
+                sampler_cache: SamplerCache::new(&capabilities, 1.0),
+                capabilities,
+                mesh_shader_loader,
+                barrier_api,
+                debug_settings,
+                validation_log,
+                display_settings,
+                full_screen_exclusive,
+                memory_budget,
+                workarounds,
+                device_extension_names,
+                submit_stats: Cell::new(SubmitStats::default()),
+                deletion_queue: DeletionQueue::new(NUM_COMMAND_BUFFERS as usize),
+                device_group,
+                last_submitted: Cell::new(0),
             };
 
-            vk.record_submit_commandbuffer(
-                0,
-                present_queue,
-                &[],
-                &[],
-                &[],
-                |device, setup_command_buffer| {
-                    let layout_transition_barriers = vk::ImageMemoryBarrier {
-                        image: vk.depth_image.image,
-                        dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
-                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                        new_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                        old_layout: vk::ImageLayout::UNDEFINED,
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::DEPTH,
-                            level_count: 1,
-                            layer_count: 1,
-                            ..Default::default()
-                        },
+            vk.transition_depth_image()?;
+
+            Ok(vk)
+        }
+    }
+
+    // Returns NeedsRecreate instead of an error on OUT_OF_DATE, e.g. when the window is resized
+    pub fn acquire_next_image(&self) -> Result<FrameResult, VulkanError> {
+        let result = unsafe {
+            match &self.device_group {
+                // The image is acquired for the GPUs rendering the frame
+                Some(device_group) => {
+                    let acquire_info = vk::AcquireNextImageInfoKHR {
+                        swapchain: self.swapchain,
+                        timeout: u64::MAX,
+                        semaphore: self.present_complete_semaphore,
+                        device_mask: device_group.device_mask(),
                         ..Default::default()
                     };
+                    self.swapchain_loader.acquire_next_image2(&acquire_info)
+                }
+                None => self.swapchain_loader.acquire_next_image(
+                    self.swapchain,
+                    u64::MAX,
+                    self.present_complete_semaphore,
+                    vk::Fence::null(),
+                ),
+            }
+        };
+        match result {
+            // Suboptimal images can still be rendered, present() reports them
+            Ok((present_index, _suboptimal)) => Ok(FrameResult::Ok(present_index)),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(FrameResult::NeedsRecreate),
+            Err(err) => Err(err.into()),
+        }
+    }
 
-                    device.cmd_pipeline_barrier(
-                        setup_command_buffer,
-                        vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-                        vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
-                        vk::DependencyFlags::empty(),
-                        &[],
-                        &[],
-                        &[layout_transition_barriers],
-                    );
-                },
+    pub fn present(&self, present_index: u32) -> Result<FrameResult, VulkanError> {
+        // The image of the GPU that rendered the frame
+        let device_mask = self
+            .device_group
+            .as_ref()
+            .map_or(0, |group| 1 << group.present_device());
+        let device_group_info = vk::DeviceGroupPresentInfoKHR {
+            swapchain_count: 1,
+            p_device_masks: &device_mask,
+            mode: self
+                .device_group
+                .as_ref()
+                .map_or(vk::DeviceGroupPresentModeFlagsKHR::LOCAL, |group| {
+                    group.present_mode()
+                }),
+            ..Default::default()
+        };
+
+        let present_info = vk::PresentInfoKHR {
+            p_next: if self.device_group.is_some() {
+                &device_group_info as *const _ as *const std::os::raw::c_void
+            } else {
+                std::ptr::null()
+            },
+            wait_semaphore_count: 1,
+            p_wait_semaphores: &self.rendering_complete_semaphore,
+            swapchain_count: 1,
+            p_swapchains: &self.swapchain,
+            p_image_indices: &present_index,
+            ..Default::default()
+        };
+
+        let result = unsafe {
+            self.swapchain_loader
+                .queue_present(self.present_queue, &present_info)
+        };
+        match result {
+            Ok(false) => Ok(FrameResult::Ok(present_index)),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(FrameResult::NeedsRecreate),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Recreates the swapchain and depth buffer. Framebuffers and descriptors referencing
+    // present_image_views or depth_image_view are owned by the caller and must be rebuilt.
+    pub fn recreate_swapchain(
+        &mut self,
+        window_width: u32,
+        window_height: u32,
+    ) -> Result<(), VulkanError> {
+        unsafe {
+            self.device.device_wait_idle()?;
+
+            self.destroy_swapchain_images();
+
+            let old_swapchain = self.swapchain;
+            let swapchain = create_swapchain(
+                &self.surface_loader,
+                &self.swapchain_loader,
+                self.pdevice,
+                self.surface,
+                self.surface_format,
+                &self.memory_profile,
+                &self.display_settings,
+                self.full_screen_exclusive,
+                self.workarounds,
+                self.device_group_modes(),
+                (window_width, window_height),
+                old_swapchain,
+            );
+            self.swapchain_loader.destroy_swapchain(old_swapchain, None);
+            self.swapchain = vk::SwapchainKHR::null();
+
+            let (swapchain, surface_resolution) = swapchain?;
+            self.swapchain = swapchain;
+            self.surface_resolution = surface_resolution;
+            self.create_swapchain_images()?;
+        }
+
+        self.transition_depth_image()
+    }
+
+    // Destroys the views and depth buffer of the current swapchain, handles are nulled so
+    // a failed re-creation doesn't destroy them twice
+    unsafe fn destroy_swapchain_images(&mut self) {
+        for &image_view in self.present_image_views.iter() {
+            destroy_image_view(&self.device, image_view);
+        }
+        self.present_image_views.clear();
+        destroy_image_view(&self.device, self.depth_image_view);
+        self.depth_image_view = vk::ImageView::null();
+        if self.depth_image.image != vk::Image::null() {
+            self.depth_image.destroy(&self.device, &mut self.allocator);
+            self.depth_image.image = vk::Image::null();
+        }
+    }
+
+    unsafe fn create_swapchain_images(&mut self) -> Result<(), VulkanError> {
+        self.present_images = self.swapchain_loader.get_swapchain_images(self.swapchain)?;
+        self.present_image_views = create_present_image_views(
+            &self.device,
+            &self.present_images,
+            self.surface_format.format,
+        )?;
+
+        let (depth_image, depth_image_view) = create_depth_image(
+            &self.device,
+            &mut self.allocator,
+            &self.gpu_budget,
+            self.depth_format,
+            self.surface_resolution,
+        )?;
+        self.depth_image = depth_image;
+        self.depth_image_view = depth_image_view;
+        Ok(())
+    }
+
+    // Re-creates the device and everything owned by it after DeviceLost. The instance,
+    // surface and physical device are kept. Like recreate_swapchain, objects created by
+    // the caller on the old device must be destroyed before and re-created after.
+    pub fn try_recover(
+        &mut self,
+        window_width: u32,
+        window_height: u32,
+    ) -> Result<(), VulkanError> {
+        unsafe {
+            // Create the new device first, the old objects stay valid for Drop if this fails
+            let mut feature_chain = DeviceFeatureChain::query(
+                &self.instance,
+                self.pdevice,
+                self.capabilities.api_version,
             );
+            feature_chain.limit_to_queue_family(
+                self.instance
+                    .get_physical_device_queue_family_properties(self.pdevice)
+                    [self.queue_family_index as usize]
+                    .queue_flags,
+            );
+            let objects = create_device_objects(
+                &self.instance,
+                self.pdevice,
+                self.queue_family_index,
+                &mut feature_chain,
+                &self.device_extension_names,
+                self.device_group
+                    .as_ref()
+                    .map_or(&[], |group| &group.physical_devices[..]),
+            )?;
+
+            // Waiting on a lost device returns DEVICE_LOST, the objects are idle either way
+            let _ = self.device.device_wait_idle();
+            self.destroy_device_objects();
+
+            self.device = objects.device;
+            self.present_queue = objects.present_queue;
+            self.swapchain_loader = objects.swapchain_loader;
+            self.mesh_shader_loader = objects.mesh_shader_loader;
+            self.barrier_api = objects.barrier_api;
+            self.allocator = ManuallyDrop::new(objects.allocator);
+            self.present_complete_semaphore = objects.present_complete_semaphore;
+            self.rendering_complete_semaphore = objects.rendering_complete_semaphore;
+            self.command_buffer_pool = objects.command_buffer_pool;
+            if let Some(device_group) = &mut self.device_group {
+                device_group.query_present_modes(&self.swapchain_loader, self.surface)?;
+            }
+
+            let (swapchain, surface_resolution) = create_swapchain(
+                &self.surface_loader,
+                &self.swapchain_loader,
+                self.pdevice,
+                self.surface,
+                self.surface_format,
+                &self.memory_profile,
+                &self.display_settings,
+                self.full_screen_exclusive,
+                self.workarounds,
+                self.device_group_modes(),
+                (window_width, window_height),
+                vk::SwapchainKHR::null(),
+            )?;
+            self.swapchain = swapchain;
+            self.surface_resolution = surface_resolution;
+            self.create_swapchain_images()?;
+        }
+
+        self.transition_depth_image()
+    }
+
+    unsafe fn destroy_device_objects(&mut self) {
+        self.device
+            .destroy_semaphore(self.present_complete_semaphore, None);
+        self.device
+            .destroy_semaphore(self.rendering_complete_semaphore, None);
+
+        self.command_buffer_pool.destroy(&self.device);
+        self.deletion_queue
+            .destroy(&self.device, &mut self.allocator);
+        self.sampler_cache.destroy(&self.device);
+
+        self.destroy_swapchain_images();
+        self.swapchain_loader
+            .destroy_swapchain(self.swapchain, None);
+        self.swapchain = vk::SwapchainKHR::null();
+
+        ManuallyDrop::drop(&mut self.allocator);
+
+        self.device.destroy_device(None);
+    }
+
+    fn device_group_modes(&self) -> vk::DeviceGroupPresentModeFlagsKHR {
+        self.device_group
+            .as_ref()
+            .map_or(vk::DeviceGroupPresentModeFlagsKHR::empty(), |group| {
+                group.swapchain_modes()
+            })
+    }
+
+    // Alternate frame rendering with --device-group: acquire, submits and present of the
+    // next frame run on the next GPU. No-op on a single GPU.
+    pub fn begin_afr_frame(&self) {
+        if let Some(device_group) = &self.device_group {
+            device_group.begin_afr_frame();
+        }
+    }
+
+    // Frames that upload data read by later frames run on every GPU of the device group
+    pub fn begin_replicated_frame(&self) {
+        if let Some(device_group) = &self.device_group {
+            device_group.begin_replicated_frame();
+        }
+    }
+
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport::new(
+            &self.instance,
+            self.pdevice,
+            self.memory_budget,
+            &self.allocator,
+        )
+    }
+
+    // Requires VK_EXT_mesh_shader, check capabilities.mesh_shader first
+    pub fn cmd_draw_mesh_tasks(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        group_count_x: u32,
+        group_count_y: u32,
+        group_count_z: u32,
+    ) {
+        let mesh_shader_loader = self
+            .mesh_shader_loader
+            .as_ref()
+            .expect("VK_EXT_mesh_shader is not enabled");
+        unsafe {
+            mesh_shader_loader.cmd_draw_mesh_tasks(
+                command_buffer,
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            )
+        };
+    }
+
+    pub fn create_framebuffers(&self, render_pass: vk::RenderPass) -> Vec<vk::Framebuffer> {
+        self.present_image_views
+            .iter()
+            .map(|&present_image_view| {
+                let framebuffer_attachments = [present_image_view, self.depth_image_view];
+                let frame_buffer_create_info = vk::FramebufferCreateInfo {
+                    render_pass,
+                    attachment_count: framebuffer_attachments.len() as u32,
+                    p_attachments: framebuffer_attachments.as_ptr(),
+                    width: self.surface_resolution.width,
+                    height: self.surface_resolution.height,
+                    layers: 1,
+                    ..Default::default()
+                };
+                unsafe {
+                    self.device
+                        .create_framebuffer(&frame_buffer_create_info, None)
+                }
+                .unwrap()
+            })
+            .collect()
+    }
+
+    pub fn view_scissor(&self) -> VkViewScissor {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.surface_resolution.width as f32,
+            height: self.surface_resolution.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            extent: self.surface_resolution,
+            ..Default::default()
+        };
+        VkViewScissor { viewport, scissor }
+    }
+
+    fn transition_depth_image(&self) -> Result<(), VulkanError> {
+        self.record_submit_commandbuffer(
+            0,
+            self.present_queue,
+            &[],
+            &[],
+            &[],
+            |device, setup_command_buffer| {
+                self.depth_image.transition_discarding(
+                    device,
+                    &self.barrier_api,
+                    setup_command_buffer,
+                    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                );
+            },
+        )?;
+        Ok(())
+    }
 
-            vk
+    // None when the device lacks descriptor indexing with update-after-bind (Vulkan 1.2).
+    // The requested counts are clamped to the device limits.
+    pub fn create_bindless_table(
+        &self,
+        max_storage_buffers: u32,
+        max_sampled_images: u32,
+    ) -> Option<Result<BindlessTable, VulkanError>> {
+        if !self.capabilities.bindless {
+            return None;
         }
+
+        let mut indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2 {
+            p_next: &mut indexing_properties as *mut _ as *mut std::os::raw::c_void,
+            ..Default::default()
+        };
+        unsafe {
+            self.instance
+                .get_physical_device_properties2(self.pdevice, &mut properties)
+        };
+
+        let max_storage_buffers = max_storage_buffers
+            .min(indexing_properties.max_descriptor_set_update_after_bind_storage_buffers)
+            .min(indexing_properties.max_per_stage_descriptor_update_after_bind_storage_buffers);
+        let max_sampled_images = max_sampled_images
+            .min(indexing_properties.max_descriptor_set_update_after_bind_sampled_images)
+            .min(indexing_properties.max_per_stage_descriptor_update_after_bind_sampled_images)
+            .min(indexing_properties.max_descriptor_set_update_after_bind_samplers)
+            .min(indexing_properties.max_per_stage_descriptor_update_after_bind_samplers);
+        Some(BindlessTable::new(
+            &self.device,
+            max_storage_buffers,
+            max_sampled_images,
+        ))
+    }
+
+    pub fn create_secondary_command_buffers(&self, num_workers: u32) -> SecondaryCommandBuffers {
+        SecondaryCommandBuffers::new(
+            &self.device,
+            self.queue_family_index,
+            num_workers,
+            self.command_buffer_pool.command_buffers.len() as u32,
+        )
+    }
+
+    // Records each recorder into its own secondary command buffer on a worker thread and
+    // executes them in order. The render pass must have been begun with
+    // SubpassContents::SECONDARY_COMMAND_BUFFERS. active_command_buffer is the index passed
+    // to record_submit_commandbuffer, its fence guarantees the secondaries are not in flight.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_parallel(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        secondary: &SecondaryCommandBuffers,
+        active_command_buffer: usize,
+        render_pass: vk::RenderPass,
+        subpass: u32,
+        framebuffer: vk::Framebuffer,
+        recorders: &[SecondaryRecorder],
+    ) {
+        assert!(
+            recorders.len() <= secondary.num_workers(),
+            "More recorders than secondary command buffer workers"
+        );
+
+        let secondary_buffers: Vec<vk::CommandBuffer> = recorders
+            .iter()
+            .enumerate()
+            .map(|(worker, _)| secondary.command_buffers[worker][active_command_buffer])
+            .collect();
+
+        std::thread::scope(|scope| {
+            for (recorder, &secondary_buffer) in recorders.iter().zip(secondary_buffers.iter()) {
+                let device = &self.device;
+                scope.spawn(move || {
+                    let inheritance_info =
+                        secondary_inheritance_info(render_pass, subpass, framebuffer);
+                    begin_secondary_command_buffer(device, secondary_buffer, &inheritance_info);
+                    recorder(device, secondary_buffer);
+                    unsafe { device.end_command_buffer(secondary_buffer) }
+                        .expect("End secondary commandbuffer");
+                });
+            }
+        });
+
+        unsafe {
+            self.device
+                .cmd_execute_commands(command_buffer, &secondary_buffers)
+        };
     }
 
     pub fn record_submit_commandbuffer<F: FnOnce(&Device, vk::CommandBuffer)>(
@@ -537,27 +2136,52 @@ impl VulkanBase {
         wait_semaphores: &[vk::Semaphore],
         signal_semaphores: &[vk::Semaphore],
         f: F,
-    ) -> usize {
-        unsafe {
-            let submit_fence =
-                self.command_buffer_pool.command_buffers[active_command_buffer].fence;
-            let command_buffer =
-                self.command_buffer_pool.command_buffers[active_command_buffer].command_buffer;
+    ) -> Result<usize, VulkanError> {
+        let mut batch = SubmitBatch::new();
+        let next_command_buffer = self.record_commandbuffer(
+            &mut batch,
+            active_command_buffer,
+            wait_mask,
+            wait_semaphores,
+            signal_semaphores,
+            f,
+        )?;
+        self.submit_batch(&mut batch, submit_queue)?;
+        Ok(next_command_buffer)
+    }
 
-            self.device
-                .wait_for_fences(&[submit_fence], true, std::u64::MAX)
-                .expect("Wait for fence failed.");
+    // Records into the batch without submitting, returns the next command buffer index
+    pub fn record_commandbuffer<F: FnOnce(&Device, vk::CommandBuffer)>(
+        &self,
+        batch: &mut SubmitBatch,
+        active_command_buffer: usize,
+        wait_mask: &[vk::PipelineStageFlags],
+        wait_semaphores: &[vk::Semaphore],
+        signal_semaphores: &[vk::Semaphore],
+        f: F,
+    ) -> Result<usize, VulkanError> {
+        let pool_command_buffer = &self.command_buffer_pool.command_buffers[active_command_buffer];
+        assert!(
+            batch
+                .command_buffers
+                .iter()
+                .all(|batched| batched.index != active_command_buffer),
+            "Command buffer recorded twice in one submit batch"
+        );
 
-            self.device
-                .reset_fences(&[submit_fence])
-                .expect("Reset fences failed.");
+        unsafe {
+            let command_buffer = pool_command_buffer.command_buffer;
 
-            self.device
-                .reset_command_buffer(
-                    command_buffer,
-                    vk::CommandBufferResetFlags::RELEASE_RESOURCES,
-                )
-                .expect("Reset command buffer failed.");
+            self.device.wait_for_fences(
+                &[pool_command_buffer.guard_fence.get()],
+                true,
+                u64::MAX,
+            )?;
+
+            self.device.reset_command_buffer(
+                command_buffer,
+                vk::CommandBufferResetFlags::RELEASE_RESOURCES,
+            )?;
 
             let command_buffer_begin_info = vk::CommandBufferBeginInfo {
                 flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
@@ -565,67 +2189,161 @@ impl VulkanBase {
             };
 
             self.device
-                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
-                .expect("Begin commandbuffer");
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)?;
             f(&self.device, command_buffer);
-            self.device
-                .end_command_buffer(command_buffer)
-                .expect("End commandbuffer");
-
-            let command_buffers = vec![command_buffer];
-
-            let submit_info = vk::SubmitInfo {
-                wait_semaphore_count: wait_semaphores.len() as u32,
-                p_wait_semaphores: wait_semaphores.as_ptr(),
-                p_wait_dst_stage_mask: wait_mask.as_ptr(),
-                command_buffer_count: command_buffers.len() as u32,
-                p_command_buffers: command_buffers.as_ptr(),
-                signal_semaphore_count: signal_semaphores.len() as u32,
-                p_signal_semaphores: signal_semaphores.as_ptr(),
-                ..Default::default()
-            };
-
-            self.device
-                .queue_submit(submit_queue, &[submit_info], submit_fence)
-                .expect("queue submit failed.");
+            self.device.end_command_buffer(command_buffer)?;
         }
 
+        batch.command_buffers.push(BatchedCommandBuffer {
+            index: active_command_buffer,
+            wait_mask: wait_mask.to_vec(),
+            wait_semaphores: wait_semaphores.to_vec(),
+            signal_semaphores: signal_semaphores.to_vec(),
+        });
+
         let next_command_buffer = active_command_buffer + 1;
         if next_command_buffer < self.command_buffer_pool.command_buffers.len() {
-            next_command_buffer
+            Ok(next_command_buffer)
         } else {
-            0
+            Ok(0)
         }
     }
-}
 
-impl Drop for VulkanBase {
-    fn drop(&mut self) {
+    // Submits all batched command buffers with one vkQueueSubmit and empties the batch
+    pub fn submit_batch(
+        &self,
+        batch: &mut SubmitBatch,
+        submit_queue: vk::Queue,
+    ) -> Result<(), VulkanError> {
+        let batched = match batch.command_buffers.last() {
+            Some(batched) => batched,
+            None => return Ok(()),
+        };
+        let command_buffers = &self.command_buffer_pool.command_buffers;
+        let submit_fence = command_buffers[batched.index].fence;
+
+        let submit_command_buffers: Vec<vk::CommandBuffer> = batch
+            .command_buffers
+            .iter()
+            .map(|batched| command_buffers[batched.index].command_buffer)
+            .collect();
+
+        let mut submit_infos: Vec<vk::SubmitInfo> = batch
+            .command_buffers
+            .iter()
+            .zip(submit_command_buffers.iter())
+            .map(|(batched, command_buffer)| vk::SubmitInfo {
+                wait_semaphore_count: batched.wait_semaphores.len() as u32,
+                p_wait_semaphores: batched.wait_semaphores.as_ptr(),
+                p_wait_dst_stage_mask: batched.wait_mask.as_ptr(),
+                command_buffer_count: 1,
+                p_command_buffers: command_buffer,
+                signal_semaphore_count: batched.signal_semaphores.len() as u32,
+                p_signal_semaphores: batched.signal_semaphores.as_ptr(),
+                ..Default::default()
+            })
+            .collect();
+
+        // Every command buffer runs on the GPUs of the current frame, semaphores are waited
+        // and signaled on one of them
+        let device_group = self.device_group.as_ref();
+        let device_mask = device_group.map_or(0, |group| group.device_mask());
+        let max_semaphores = submit_infos
+            .iter()
+            .map(|info| info.wait_semaphore_count.max(info.signal_semaphore_count))
+            .max()
+            .unwrap_or(0);
+        let device_indices = vec![
+            device_group.map_or(0, |group| group.semaphore_device_index());
+            max_semaphores as usize
+        ];
+        let device_group_infos: Vec<vk::DeviceGroupSubmitInfo> = submit_infos
+            .iter()
+            .filter(|_| device_group.is_some())
+            .map(|submit_info| vk::DeviceGroupSubmitInfo {
+                wait_semaphore_count: submit_info.wait_semaphore_count,
+                p_wait_semaphore_device_indices: device_indices.as_ptr(),
+                command_buffer_count: 1,
+                p_command_buffer_device_masks: &device_mask,
+                signal_semaphore_count: submit_info.signal_semaphore_count,
+                p_signal_semaphore_device_indices: device_indices.as_ptr(),
+                ..Default::default()
+            })
+            .collect();
+        for (submit_info, device_group_info) in
+            submit_infos.iter_mut().zip(device_group_infos.iter())
+        {
+            submit_info.p_next = device_group_info as *const _ as *const std::os::raw::c_void;
+        }
+
         unsafe {
-            self.device.device_wait_idle().unwrap();
-            self.device
-                .destroy_semaphore(self.present_complete_semaphore, None);
+            // The fence was waited for when its command buffer was recorded into this batch
+            self.device.reset_fences(&[submit_fence])?;
+
+            let time_start = Instant::now();
             self.device
-                .destroy_semaphore(self.rendering_complete_semaphore, None);
+                .queue_submit(submit_queue, &submit_infos, submit_fence)?;
 
-            self.command_buffer_pool.destroy(&self.device);
+            let mut stats = self.submit_stats.get();
+            stats.submits += 1;
+            stats.command_buffers += submit_infos.len() as u32;
+            stats.submit_time += time_start.elapsed();
+            self.submit_stats.set(stats);
+        }
 
-            self.device.destroy_image_view(self.depth_image_view, None);
-            self.depth_image.destroy(&self.device, &mut self.allocator);
+        self.last_submitted.set(batched.index);
+        for batched in batch.command_buffers.drain(..) {
+            command_buffers[batched.index].guard_fence.set(submit_fence);
+        }
+        Ok(())
+    }
+
+    // Returns the submit stats since the previous call
+    pub fn take_submit_stats(&self) -> SubmitStats {
+        self.submit_stats.take()
+    }
 
-            for &image_view in self.present_image_views.iter() {
-                self.device.destroy_image_view(image_view, None);
+    // Destroys the resource once the latest submit finished. Relies on submits of the queue
+    // completing in order. Must not be called between recording and submitting a command
+    // buffer that uses the resource.
+    pub fn defer_destroy(&mut self, deletion: Deletion) {
+        self.deletion_queue
+            .push(self.last_submitted.get(), deletion);
+    }
+
+    // Destroys deferred resources of frames whose fence signaled, call once per frame
+    pub fn collect_deletions(&mut self) -> Result<(), VulkanError> {
+        for frame in 0..self.command_buffer_pool.command_buffers.len() {
+            let fence = self.command_buffer_pool.command_buffers[frame]
+                .guard_fence
+                .get();
+            if unsafe { self.device.get_fence_status(fence) }? {
+                self.deletion_queue
+                    .flush_frame(frame, &self.device, &mut self.allocator);
             }
-            self.swapchain_loader
-                .destroy_swapchain(self.swapchain, None);
+        }
+        Ok(())
+    }
+}
 
-            ManuallyDrop::drop(&mut self.allocator);
+impl Drop for VulkanBase {
+    fn drop(&mut self) {
+        unsafe {
+            // A lost device still has to be destroyed, so the error is ignored
+            let _ = self.device.device_wait_idle();
+            self.destroy_device_objects();
 
-            self.device.destroy_device(None);
             self.surface_loader.destroy_surface(self.surface, None);
-            self.debug_utils_loader
-                .destroy_debug_utils_messenger(self.debug_call_back, None);
+            if self.debug_call_back != vk::DebugUtilsMessengerEXT::null() {
+                self.debug_utils_loader
+                    .destroy_debug_utils_messenger(self.debug_call_back, None);
+            }
             self.instance.destroy_instance(None);
         }
+
+        // Exiting while a panic unwinds would hide the panic
+        if !std::thread::panicking() {
+            self.validation_log.finish();
+        }
     }
 }
@@ -1,32 +1,360 @@
 extern crate ash;
 extern crate gpu_allocator;
 
+use crate::allocator_telemetry::TrackingAllocator;
+use crate::device_capabilities::DeviceCapabilities;
+use crate::gpu_workarounds::{self, Workaround};
+use crate::object_counters::{self, ObjectType};
+use crate::spirv_reflection::*;
+use crate::texture_file::*;
+
+use ash::util::read_spv;
 use ash::vk;
 pub use ash::{Device, Instance};
+pub use bytemuck::{Pod, Zeroable};
 use gpu_allocator::vulkan::*;
 use gpu_allocator::MemoryLocation;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::io::{self, Cursor};
+use std::marker::PhantomData;
+use std::path::Path;
 use std::ptr;
+use std::rc::Rc;
 use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BudgetTag {
+    Atlas,
+    Octree,
+    Framebuffers,
+    Staging,
+    Scratch,
+}
+
+#[derive(Debug)]
+pub enum AllocationError {
+    OverBudget {
+        tag: BudgetTag,
+        requested: u64,
+        used: u64,
+        limit: u64,
+    },
+    OutOfMemory {
+        tag: Option<BudgetTag>,
+        requested: u64,
+        message: String,
+    },
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocationError::OverBudget {
+                tag,
+                requested,
+                used,
+                limit,
+            } => write!(
+                f,
+                "{:?} budget exceeded: requested {} bytes with {} of {} bytes already in use",
+                tag, requested, used, limit
+            ),
+            AllocationError::OutOfMemory {
+                tag,
+                requested,
+                message,
+            } => write!(
+                f,
+                "Out of GPU memory allocating {} bytes ({:?}): {}",
+                requested, tag, message
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferWriteError {
+    NotMapped, // GpuOnly buffers can't be written from the CPU
+    OutOfBounds {
+        offset: usize,
+        bytes: usize,
+        size: u64,
+    },
+    Misaligned {
+        offset: usize,
+        align: usize,
+    },
+}
+
+impl fmt::Display for BufferWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BufferWriteError::NotMapped => write!(f, "Buffer isn't host visible"),
+            BufferWriteError::OutOfBounds {
+                offset,
+                bytes,
+                size,
+            } => write!(
+                f,
+                "Writing {} bytes at offset {} overflows the {} byte buffer",
+                bytes, offset, size
+            ),
+            BufferWriteError::Misaligned { offset, align } => write!(
+                f,
+                "Offset {} isn't aligned to the {} byte alignment of the element type",
+                offset, align
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BufferWriteError {}
+
+#[derive(Default)]
+struct BudgetEntry {
+    used: u64,
+    limit: Option<u64>,
+}
+
+// Shared handle: resources keep a clone to release their bytes on destroy
+#[derive(Clone, Default)]
+pub struct GpuBudget {
+    entries: Rc<RefCell<HashMap<BudgetTag, BudgetEntry>>>,
+}
+
+impl GpuBudget {
+    pub fn new() -> GpuBudget {
+        GpuBudget::default()
+    }
+
+    pub fn set_limit(&self, tag: BudgetTag, limit: Option<u64>) {
+        self.entries.borrow_mut().entry(tag).or_default().limit = limit;
+    }
+
+    pub fn usage(&self, tag: BudgetTag) -> u64 {
+        self.entries
+            .borrow()
+            .get(&tag)
+            .map_or(0, |entry| entry.used)
+    }
+
+    pub fn limit(&self, tag: BudgetTag) -> Option<u64> {
+        self.entries
+            .borrow()
+            .get(&tag)
+            .and_then(|entry| entry.limit)
+    }
+
+    pub fn reserve(&self, tag: BudgetTag, bytes: u64) -> Result<(), AllocationError> {
+        let mut entries = self.entries.borrow_mut();
+        let entry = entries.entry(tag).or_default();
+        if let Some(limit) = entry.limit {
+            if entry.used + bytes > limit {
+                return Err(AllocationError::OverBudget {
+                    tag,
+                    requested: bytes,
+                    used: entry.used,
+                    limit,
+                });
+            }
+        }
+        entry.used += bytes;
+        Ok(())
+    }
+
+    pub fn release(&self, tag: BudgetTag, bytes: u64) {
+        let mut entries = self.entries.borrow_mut();
+        let entry = entries.entry(tag).or_default();
+        entry.used = entry.used.saturating_sub(bytes);
+    }
+}
+
+// Bytes the CPU wrote into one mapped buffer, see MappedBufferPool
+#[derive(Clone, Debug, Default)]
+pub struct UploadStats {
+    pub name: String,
+    pub bytes: u64, // Since the last report
+    pub peak_frame_bytes: u64,
+    frame_bytes: u64,
+}
+
+#[derive(Default)]
+struct MappedPoolEntries {
+    buffers: Vec<UploadStats>,
+    frames: u64,
+    seconds: f64,
+    frame_end: Option<Instant>,
+}
+
+// Upload bandwidth of the report interval
+#[derive(Clone, Debug, Default)]
+pub struct UploadReport {
+    pub buffers: Vec<UploadStats>,
+    pub frames: u64,
+    pub seconds: f64,
+}
+
+impl UploadReport {
+    pub fn bytes_per_frame(&self, bytes: u64) -> f64 {
+        bytes as f64 / self.frames.max(1) as f64
+    }
+
+    pub fn megabytes_per_second(&self, bytes: u64) -> f64 {
+        if self.seconds > 0.0 {
+            bytes as f64 / self.seconds / (1024.0 * 1024.0)
+        } else {
+            0.0
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.buffers.iter().map(|stats| stats.bytes).sum()
+    }
+
+    pub fn peak_frame_bytes(&self) -> u64 {
+        self.buffers
+            .iter()
+            .map(|stats| stats.peak_frame_bytes)
+            .sum()
+    }
+}
+
+// Persistently mapped CPU to GPU buffers with write statistics. Buffers come from create or
+// join with track, every VkBuffer write (copy_from_slice and the helpers on top of it,
+// StagingRing included) is then counted for the current frame. Shared handle like GpuBudget,
+// buffers keep a clone. Tracking a name again, e.g. after device recovery, reuses its entry.
+#[derive(Clone, Default)]
+pub struct MappedBufferPool {
+    entries: Rc<RefCell<MappedPoolEntries>>,
+}
+
+impl MappedBufferPool {
+    pub fn new() -> MappedBufferPool {
+        MappedBufferPool::default()
+    }
+
+    pub fn create(
+        &self,
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        name: &str,
+        buffer_info: &vk::BufferCreateInfo,
+    ) -> VkBuffer {
+        let mut buffer = VkBuffer::new(device, allocator, buffer_info, MemoryLocation::CpuToGpu);
+        self.track(&mut buffer, name);
+        buffer
+    }
+
+    pub fn track(&self, buffer: &mut VkBuffer, name: &str) {
+        assert!(
+            !buffer.mapped_ptr.is_null(),
+            "Only mapped buffers can join the pool"
+        );
+        let mut entries = self.entries.borrow_mut();
+        let index = match entries.buffers.iter().position(|stats| stats.name == name) {
+            Some(index) => index,
+            None => {
+                entries.buffers.push(UploadStats {
+                    name: name.to_string(),
+                    ..Default::default()
+                });
+                entries.buffers.len() - 1
+            }
+        };
+        buffer.upload_tracker = Some((self.clone(), index));
+    }
+
+    fn record_write(&self, index: usize, bytes: u64) {
+        let mut entries = self.entries.borrow_mut();
+        let stats = &mut entries.buffers[index];
+        stats.frame_bytes += bytes;
+        stats.bytes += bytes;
+    }
+
+    // Closes the frame, call once per frame after its uploads were written
+    pub fn end_frame(&self) {
+        let mut entries = self.entries.borrow_mut();
+        let now = Instant::now();
+        if let Some(frame_end) = entries.frame_end.replace(now) {
+            entries.seconds += (now - frame_end).as_secs_f64();
+        }
+        entries.frames += 1;
+        for stats in &mut entries.buffers {
+            stats.peak_frame_bytes = stats.peak_frame_bytes.max(stats.frame_bytes);
+            stats.frame_bytes = 0;
+        }
+    }
+
+    // Returns the statistics since the previous call
+    pub fn take_report(&self) -> UploadReport {
+        let mut entries = self.entries.borrow_mut();
+        let report = UploadReport {
+            buffers: entries.buffers.clone(),
+            frames: entries.frames,
+            seconds: entries.seconds,
+        };
+        entries.frames = 0;
+        entries.seconds = 0.0;
+        for stats in &mut entries.buffers {
+            stats.bytes = 0;
+            stats.peak_frame_bytes = 0;
+        }
+        report
+    }
+}
 
 pub struct VkBuffer {
     pub buffer: vk::Buffer,
     pub allocation: Option<Allocation>,
     pub size: u64,
     pub mapped_ptr: *mut u8,
+    pub budget: Option<(GpuBudget, BudgetTag, u64)>,
+    pub upload_tracker: Option<(MappedBufferPool, usize)>, // Pool and stats index
 }
 
 impl VkBuffer {
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
+        allocator: &mut TrackingAllocator,
         buffer_info: &vk::BufferCreateInfo,
         location: MemoryLocation,
     ) -> VkBuffer {
+        VkBuffer::new_internal(device, allocator, None, buffer_info, location).unwrap()
+    }
+
+    pub fn new_budgeted(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: (&GpuBudget, BudgetTag),
+        buffer_info: &vk::BufferCreateInfo,
+        location: MemoryLocation,
+    ) -> Result<VkBuffer, AllocationError> {
+        VkBuffer::new_internal(device, allocator, Some(budget), buffer_info, location)
+    }
+
+    fn new_internal(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: Option<(&GpuBudget, BudgetTag)>,
+        buffer_info: &vk::BufferCreateInfo,
+        location: MemoryLocation,
+    ) -> Result<VkBuffer, AllocationError> {
         let size = buffer_info.size;
 
         let buffer = unsafe { device.create_buffer(buffer_info, None) }.unwrap();
         let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
 
+        if let Some((budget, tag)) = budget {
+            if let Err(err) = budget.reserve(tag, requirements.size) {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        }
+
         let allocation = allocator
             .allocate(&AllocationCreateDesc {
                 name: "buffer",
@@ -35,7 +363,17 @@ impl VkBuffer {
                 linear: true,
                 allocation_scheme: AllocationScheme::GpuAllocatorManaged,
             })
-            .unwrap();
+            .map_err(|err| {
+                unsafe { device.destroy_buffer(buffer, None) };
+                if let Some((budget, tag)) = budget {
+                    budget.release(tag, requirements.size);
+                }
+                AllocationError::OutOfMemory {
+                    tag: budget.map(|(_, tag)| tag),
+                    requested: requirements.size,
+                    message: err.to_string(),
+                }
+            })?;
 
         unsafe {
             device
@@ -49,102 +387,3223 @@ impl VkBuffer {
             allocation.mapped_ptr().unwrap().as_ptr() as *mut u8
         };
 
-        VkBuffer {
+        object_counters::created(ObjectType::Buffer, 1);
+        Ok(VkBuffer {
             buffer,
             allocation: Some(allocation),
             size,
             mapped_ptr,
-        }
+            budget: budget.map(|(budget, tag)| (budget.clone(), tag, requirements.size)),
+            upload_tracker: None,
+        })
     }
 
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
         allocator.free(self.allocation.take().unwrap()).unwrap();
         unsafe { device.destroy_buffer(self.buffer, None) };
+        object_counters::destroyed(ObjectType::Buffer, 1);
+        self.upload_tracker = None;
+        if let Some((budget, tag, bytes)) = self.budget.take() {
+            budget.release(tag, bytes);
+        }
     }
 
-    pub fn copy_from_slice<T>(&self, slice: &[T], offset: usize)
-    where
-        T: Copy,
-    {
-        //assert!(std::mem::size_of_val(slice) + offset <= self.info.get_size());
+    // Writes slice at a byte offset of a host visible buffer. Writes past the end of the buffer
+    // or at offsets T isn't aligned to are rejected instead of corrupting other allocations.
+    pub fn copy_from_slice<T: Pod>(
+        &self,
+        slice: &[T],
+        offset: usize,
+    ) -> Result<(), BufferWriteError> {
+        if self.mapped_ptr.is_null() {
+            return Err(BufferWriteError::NotMapped);
+        }
+        let align = std::mem::align_of::<T>();
+        if !offset.is_multiple_of(align) {
+            return Err(BufferWriteError::Misaligned { offset, align });
+        }
+        let bytes: &[u8] = bytemuck::cast_slice(slice);
+        offset
+            .checked_add(bytes.len())
+            .filter(|&end| end <= self.size as usize)
+            .ok_or(BufferWriteError::OutOfBounds {
+                offset,
+                bytes: bytes.len(),
+                size: self.size,
+            })?;
+        // The mapping is shared with the GPU, writes must not race with frames reading it
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.mapped_ptr.add(offset), bytes.len());
+        }
+        self.record_write(bytes.len());
+        Ok(())
+    }
+
+    // Counts bytes written through the mapping for the pool the buffer is tracked by
+    fn record_write(&self, bytes: usize) {
+        if let Some((pool, index)) = &self.upload_tracker {
+            pool.record_write(*index, bytes as u64);
+        }
+    }
+
+    pub fn copy_struct<T: Pod>(&self, value: &T, offset: usize) -> Result<(), BufferWriteError> {
+        self.copy_from_slice(std::slice::from_ref(value), offset)
+    }
+
+    // Contents of a host visible buffer, the GPU must be done writing it
+    pub fn mapped_bytes(&self) -> &[u8] {
         assert!(!self.mapped_ptr.is_null());
+        unsafe { std::slice::from_raw_parts(self.mapped_ptr, self.size as usize) }
+    }
+}
 
-        unsafe {
-            let mem_ptr = self.mapped_ptr.add(offset);
-            let mapped_slice = from_raw_parts_mut(mem_ptr as *mut T, slice.len());
-            mapped_slice.copy_from_slice(slice);
+// Array of T in a buffer, indexed in elements. Pod guarantees T has no padding or invalid
+// bit patterns, so the mapped memory can be written and read as T without per call unsafe.
+pub struct TypedBuffer<T: Pod> {
+    pub buffer: VkBuffer,
+    len: usize,
+    _element: PhantomData<T>,
+}
+
+impl<T: Pod> TypedBuffer<T> {
+    // Zero sized elements would make every index alias the same bytes
+    const STRIDE: usize = {
+        assert!(
+            std::mem::size_of::<T>() > 0,
+            "TypedBuffer element has no size"
+        );
+        std::mem::size_of::<T>()
+    };
+
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> TypedBuffer<T> {
+        let buffer_info = TypedBuffer::<T>::buffer_info(len, usage);
+        TypedBuffer::from_buffer(
+            VkBuffer::new(device, allocator, &buffer_info, location),
+            len,
+        )
+    }
+
+    pub fn new_budgeted(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: (&GpuBudget, BudgetTag),
+        len: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Result<TypedBuffer<T>, AllocationError> {
+        let buffer_info = TypedBuffer::<T>::buffer_info(len, usage);
+        let buffer = VkBuffer::new_budgeted(device, allocator, budget, &buffer_info, location)?;
+        Ok(TypedBuffer::from_buffer(buffer, len))
+    }
+
+    fn buffer_info(len: usize, usage: vk::BufferUsageFlags) -> vk::BufferCreateInfo<'static> {
+        vk::BufferCreateInfo {
+            size: (TypedBuffer::<T>::STRIDE * len) as u64,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        }
+    }
+
+    fn from_buffer(buffer: VkBuffer, len: usize) -> TypedBuffer<T> {
+        TypedBuffer {
+            buffer,
+            len,
+            _element: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn size_bytes(&self) -> u64 {
+        (TypedBuffer::<T>::STRIDE * self.len) as u64
+    }
+
+    pub fn descriptor(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer.buffer,
+            offset: 0,
+            range: self.size_bytes(),
         }
     }
+
+    pub fn write(&self, index: usize, value: &T) {
+        self.write_range(index, std::slice::from_ref(value));
+    }
+
+    pub fn write_slice(&self, data: &[T]) {
+        self.write_range(0, data);
+    }
+
+    // Writes data to the elements starting at first
+    pub fn write_range(&self, first: usize, data: &[T]) {
+        assert!(
+            first + data.len() <= self.len,
+            "TypedBuffer write out of bounds"
+        );
+        let offset = first * TypedBuffer::<T>::STRIDE;
+        self.buffer.copy_from_slice(data, offset).unwrap();
+    }
+
+    // Contents of a persistently mapped buffer, the GPU must be done writing it
+    pub fn as_slice(&self) -> &[T] {
+        let bytes = &self.buffer.mapped_bytes()[..self.size_bytes() as usize];
+        bytemuck::cast_slice(bytes)
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+    }
 }
 
-pub struct VkImage {
-    pub image: vk::Image,
-    pub allocation: Option<Allocation>,
+// Same layout as vk::DrawIndexedIndirectCommand, but Pod so it can go through TypedBuffer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DrawIndexedIndirectCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
 }
 
-impl VkImage {
+unsafe impl Zeroable for DrawIndexedIndirectCommand {}
+unsafe impl Pod for DrawIndexedIndirectCommand {}
+
+// Arguments of draw_count indexed draws, filled by the CPU (host visible buffers), by
+// command buffer updates or by compute shaders through the storage buffer descriptor.
+// Without the multiDrawIndirect feature the draws are recorded one by one.
+pub struct IndirectArgsBuffer {
+    pub buffer: TypedBuffer<DrawIndexedIndirectCommand>,
+    multi_draw: bool,
+}
+
+impl IndirectArgsBuffer {
+    const STRIDE: u32 = std::mem::size_of::<DrawIndexedIndirectCommand>() as u32;
+
     pub fn new(
         device: &Device,
-        allocator: &mut Allocator,
-        image_info: &vk::ImageCreateInfo,
+        allocator: &mut TrackingAllocator,
+        capabilities: &DeviceCapabilities,
+        draw_count: usize,
         location: MemoryLocation,
-    ) -> VkImage {
-        let image = unsafe { device.create_image(image_info, None) }.unwrap();
-        let requirements = unsafe { device.get_image_memory_requirements(image) };
+    ) -> IndirectArgsBuffer {
+        assert!(draw_count > 0, "IndirectArgsBuffer needs at least one draw");
+        let usage = vk::BufferUsageFlags::STORAGE_BUFFER
+            | vk::BufferUsageFlags::TRANSFER_DST
+            | vk::BufferUsageFlags::INDIRECT_BUFFER;
+        IndirectArgsBuffer {
+            buffer: TypedBuffer::new(device, allocator, draw_count, usage, location),
+            multi_draw: capabilities.multi_draw_indirect,
+        }
+    }
 
-        let mut allocation = allocator.allocate(&AllocationCreateDesc {
-            name: "image",
-            requirements,
-            location,
-            linear: false,
-            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-        });
+    pub fn draw_count(&self) -> u32 {
+        self.buffer.len() as u32
+    }
 
-        // Workaround for gpu_allocator DEVICE_LOCAL memory running out on iGPUs
-        // TODO: Remove once gpu_allocator handles iGPUs properly!
-        if allocation.is_err() {
-            allocation = allocator.allocate(&AllocationCreateDesc {
-                name: "image",
-                requirements,
-                location: MemoryLocation::CpuToGpu,
-                linear: false,
-                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
-            });
-        };
+    pub fn size_bytes(&self) -> u64 {
+        self.buffer.size_bytes()
+    }
+
+    pub fn descriptor(&self) -> vk::DescriptorBufferInfo {
+        self.buffer.descriptor()
+    }
 
-        let allocation = allocation.unwrap();
+    // Host visible buffers only, the GPU must not be reading the draws
+    pub fn write(&self, draw: usize, command: &DrawIndexedIndirectCommand) {
+        self.buffer.write(draw, command);
+    }
 
+    // Recorded copy of the commands into the draws starting at first, works for GPU only
+    // buffers. vkCmdUpdateBuffer is limited to 64 KiB (2730 draws) per call.
+    pub fn cmd_write(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        first: usize,
+        commands: &[DrawIndexedIndirectCommand],
+    ) {
+        assert!(
+            first + commands.len() <= self.buffer.len(),
+            "IndirectArgsBuffer write out of bounds"
+        );
+        let bytes: &[u8] = bytemuck::cast_slice(commands);
+        assert!(
+            bytes.len() <= 65536,
+            "IndirectArgsBuffer update larger than 64 KiB"
+        );
         unsafe {
-            device
-                .bind_image_memory(image, allocation.memory(), allocation.offset())
-                .unwrap()
-        };
+            device.cmd_update_buffer(
+                command_buffer,
+                self.buffer.buffer.buffer,
+                (first as u64) * IndirectArgsBuffer::STRIDE as u64,
+                bytes,
+            );
+        }
+    }
 
-        VkImage {
-            image,
-            allocation: Some(allocation),
+    // Zeroes every field of every draw
+    pub fn cmd_zero(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_fill_buffer(
+                command_buffer,
+                self.buffer.buffer.buffer,
+                0,
+                vk::WHOLE_SIZE,
+                0,
+            );
+        }
+    }
+
+    // Zeroes only the index counts, for shaders that append to them every frame. The other
+    // fields keep what cmd_write or write put there.
+    pub fn cmd_reset_index_counts(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        for draw in 0..self.buffer.len() as u64 {
+            unsafe {
+                device.cmd_fill_buffer(
+                    command_buffer,
+                    self.buffer.buffer.buffer,
+                    draw * IndirectArgsBuffer::STRIDE as u64,
+                    std::mem::size_of::<u32>() as u64,
+                    0,
+                );
+            }
+        }
+    }
+
+    // Zeroes only the instance counts, for shaders that append instances to a fixed mesh
+    pub fn cmd_reset_instance_counts(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let instance_count_offset = std::mem::size_of::<u32>() as u64;
+        for draw in 0..self.buffer.len() as u64 {
+            unsafe {
+                device.cmd_fill_buffer(
+                    command_buffer,
+                    self.buffer.buffer.buffer,
+                    draw * IndirectArgsBuffer::STRIDE as u64 + instance_count_offset,
+                    std::mem::size_of::<u32>() as u64,
+                    0,
+                );
+            }
+        }
+    }
+
+    // Copies the handle, so the draws can be recorded on a worker thread
+    pub fn draws(&self) -> IndirectDraws {
+        IndirectDraws {
+            buffer: self.buffer.buffer.buffer,
+            draw_count: self.draw_count(),
+            multi_draw: self.multi_draw,
         }
     }
 
     pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
-        allocator.free(self.allocation.take().unwrap()).unwrap();
-        unsafe { device.destroy_image(self.image, None) };
+        self.buffer.destroy(device, allocator);
     }
 }
 
-pub struct VkViewScissor {
-    pub viewport: vk::Viewport,
-    pub scissor: vk::Rect2D,
+#[derive(Clone, Copy, Debug)]
+pub struct IndirectDraws {
+    pub buffer: vk::Buffer,
+    pub draw_count: u32,
+    multi_draw: bool,
 }
 
-pub fn raw_bytes<T>(data: &[T]) -> &[u8]
-where
-    T: Copy,
-{
-    unsafe {
-        from_raw_parts(
-            data.as_ptr() as *const u8,
-            data.len() * std::mem::size_of::<T>(),
-        )
+impl IndirectDraws {
+    // The index buffer and pipeline must be bound
+    pub fn record(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        let stride = IndirectArgsBuffer::STRIDE;
+        unsafe {
+            if self.multi_draw || self.draw_count == 1 {
+                device.cmd_draw_indexed_indirect(
+                    command_buffer,
+                    self.buffer,
+                    0,
+                    self.draw_count,
+                    stride,
+                );
+            } else {
+                for draw in 0..self.draw_count {
+                    let offset = draw as u64 * stride as u64;
+                    device.cmd_draw_indexed_indirect(
+                        command_buffer,
+                        self.buffer,
+                        offset,
+                        1,
+                        stride,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryKind {
+    Occlusion,          // Samples passing the depth and stencil tests
+    PipelineStatistics, // Vertex, clipping primitive and fragment shader invocations
+    Timestamp,          // GPU clock ticks written by cmd_write_timestamp
+}
+
+// Counters of one pipeline statistics query
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub vertex_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_invocations: u64,
+}
+
+impl PipelineStatistics {
+    const FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+        vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+    );
+}
+
+// Query pool with one result per query. Queries are reset with cmd_reset outside a render
+// pass before they are begun again. Results are fetched without waiting, None means the
+// GPU hasn't finished the query yet (or it was reset and not used since).
+pub struct QueryPoolWrapper {
+    pub pool: vk::QueryPool,
+    pub kind: QueryKind,
+    pub count: u32,
+    precise: bool, // Occlusion queries count samples instead of reporting any nonzero value
+    timestamp_period: f32,
+}
+
+impl QueryPoolWrapper {
+    pub fn occlusion(
+        device: &Device,
+        capabilities: &DeviceCapabilities,
+        count: u32,
+    ) -> Result<QueryPoolWrapper, vk::Result> {
+        let info = vk::QueryPoolCreateInfo {
+            query_type: vk::QueryType::OCCLUSION,
+            query_count: count,
+            ..Default::default()
+        };
+        let pool = unsafe { device.create_query_pool(&info, None) }?;
+        object_counters::created(ObjectType::QueryPool, 1);
+        Ok(QueryPoolWrapper {
+            pool,
+            kind: QueryKind::Occlusion,
+            count,
+            precise: capabilities.occlusion_query_precise,
+            timestamp_period: 0.0,
+        })
+    }
+
+    // Needs the pipelineStatisticsQuery feature, None without it
+    pub fn pipeline_statistics(
+        device: &Device,
+        capabilities: &DeviceCapabilities,
+        count: u32,
+    ) -> Result<Option<QueryPoolWrapper>, vk::Result> {
+        if !capabilities.pipeline_statistics_query {
+            return Ok(None);
+        }
+        let info = vk::QueryPoolCreateInfo {
+            query_type: vk::QueryType::PIPELINE_STATISTICS,
+            query_count: count,
+            pipeline_statistics: PipelineStatistics::FLAGS,
+            ..Default::default()
+        };
+        let pool = unsafe { device.create_query_pool(&info, None) }?;
+        object_counters::created(ObjectType::QueryPool, 1);
+        Ok(Some(QueryPoolWrapper {
+            pool,
+            kind: QueryKind::PipelineStatistics,
+            count,
+            precise: false,
+            timestamp_period: 0.0,
+        }))
+    }
+
+    // None when the graphics queue doesn't support timestamps
+    pub fn timestamps(
+        device: &Device,
+        capabilities: &DeviceCapabilities,
+        count: u32,
+    ) -> Result<Option<QueryPoolWrapper>, vk::Result> {
+        if capabilities.timestamp_period <= 0.0 {
+            return Ok(None);
+        }
+        let info = vk::QueryPoolCreateInfo {
+            query_type: vk::QueryType::TIMESTAMP,
+            query_count: count,
+            ..Default::default()
+        };
+        let pool = unsafe { device.create_query_pool(&info, None) }?;
+        object_counters::created(ObjectType::QueryPool, 1);
+        Ok(Some(QueryPoolWrapper {
+            pool,
+            kind: QueryKind::Timestamp,
+            count,
+            precise: false,
+            timestamp_period: capabilities.timestamp_period,
+        }))
+    }
+
+    pub fn cmd_reset(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        first: u32,
+        count: u32,
+    ) {
+        assert!(first + count <= self.count, "Query reset out of bounds");
+        unsafe { device.cmd_reset_query_pool(command_buffer, self.pool, first, count) };
+    }
+
+    pub fn cmd_reset_all(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        self.cmd_reset(device, command_buffer, 0, self.count);
+    }
+
+    pub fn cmd_begin(&self, device: &Device, command_buffer: vk::CommandBuffer, query: u32) {
+        assert!(query < self.count, "Query index out of bounds");
+        let flags = if self.precise {
+            vk::QueryControlFlags::PRECISE
+        } else {
+            vk::QueryControlFlags::empty()
+        };
+        unsafe { device.cmd_begin_query(command_buffer, self.pool, query, flags) };
+    }
+
+    pub fn cmd_end(&self, device: &Device, command_buffer: vk::CommandBuffer, query: u32) {
+        unsafe { device.cmd_end_query(command_buffer, self.pool, query) };
+    }
+
+    // Everything record puts in the command buffer is counted by the query
+    pub fn cmd_scoped<R>(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        query: u32,
+        record: impl FnOnce() -> R,
+    ) -> R {
+        self.cmd_begin(device, command_buffer, query);
+        let result = record();
+        self.cmd_end(device, command_buffer, query);
+        result
+    }
+
+    // Written once the commands before it have completed stage
+    pub fn cmd_write_timestamp(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags,
+        query: u32,
+    ) {
+        assert!(query < self.count, "Query index out of bounds");
+        unsafe { device.cmd_write_timestamp(command_buffer, stage, self.pool, query) };
+    }
+
+    // T is the result of one query, ash fetches one query per element
+    fn fetch<T: Copy + Default>(
+        &self,
+        device: &Device,
+        query: u32,
+    ) -> Result<Option<T>, vk::Result> {
+        assert!(query < self.count, "Query index out of bounds");
+        let mut result = [T::default()];
+        let fetched = unsafe {
+            device.get_query_pool_results(
+                self.pool,
+                query,
+                &mut result,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match fetched {
+            Ok(()) => Ok(Some(result[0])),
+            Err(vk::Result::NOT_READY) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Samples that passed, any nonzero value means visible without occlusionQueryPrecise
+    pub fn samples_passed(&self, device: &Device, query: u32) -> Result<Option<u64>, vk::Result> {
+        assert_eq!(
+            self.kind,
+            QueryKind::Occlusion,
+            "Not an occlusion query pool"
+        );
+        self.fetch::<u64>(device, query)
+    }
+
+    pub fn statistics(
+        &self,
+        device: &Device,
+        query: u32,
+    ) -> Result<Option<PipelineStatistics>, vk::Result> {
+        assert_eq!(
+            self.kind,
+            QueryKind::PipelineStatistics,
+            "Not a pipeline statistics pool"
+        );
+        // Values are written in flag bit order
+        Ok(self
+            .fetch::<[u64; 3]>(device, query)?
+            .map(|values| PipelineStatistics {
+                vertex_invocations: values[0],
+                clipping_primitives: values[1],
+                fragment_invocations: values[2],
+            }))
+    }
+
+    // Milliseconds between the timestamps start and end
+    pub fn elapsed_ms(
+        &self,
+        device: &Device,
+        start: u32,
+        end: u32,
+    ) -> Result<Option<f64>, vk::Result> {
+        assert_eq!(
+            self.kind,
+            QueryKind::Timestamp,
+            "Not a timestamp query pool"
+        );
+        let ticks = match (
+            self.fetch::<u64>(device, start)?,
+            self.fetch::<u64>(device, end)?,
+        ) {
+            (Some(start), Some(end)) => end.wrapping_sub(start),
+            _ => return Ok(None),
+        };
+        Ok(Some(ticks as f64 * self.timestamp_period as f64 * 1e-6))
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe { device.destroy_query_pool(self.pool, None) };
+        object_counters::destroyed(ObjectType::QueryPool, 1);
+    }
+}
+
+// Sub-range of a BufferArena, offset is a multiple of the arena alignment
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferRange {
+    pub buffer: vk::Buffer,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BufferRange {
+    pub fn descriptor(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer,
+            offset: self.offset,
+            range: self.size,
+        }
+    }
+}
+
+// One buffer per usage class that hands out aligned sub-ranges, so small uniform, storage and
+// index buffers don't each take their own allocation out of the gpu-allocator heaps. Freed
+// ranges go back to a free list and are merged with their neighbours. The alignment must cover
+// the descriptor offset limits of the usage (256 bytes satisfies every device).
+pub struct BufferArena {
+    pub buffer: VkBuffer,
+    alignment: u64,
+    free_ranges: Vec<(u64, u64)>, // (offset, size), sorted by offset
+}
+
+impl BufferArena {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        capacity: u64,
+        alignment: u64,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> BufferArena {
+        assert!(alignment > 0, "BufferArena alignment must not be zero");
+        let capacity = capacity.next_multiple_of(alignment);
+        let buffer_info = vk::BufferCreateInfo {
+            size: capacity,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        BufferArena {
+            buffer: VkBuffer::new(device, allocator, &buffer_info, location),
+            alignment,
+            free_ranges: vec![(0, capacity)],
+        }
+    }
+
+    // First fit, None when no free range is large enough
+    pub fn allocate(&mut self, size: u64) -> Option<BufferRange> {
+        let aligned_size = size.max(1).next_multiple_of(self.alignment);
+        let index = self
+            .free_ranges
+            .iter()
+            .position(|&(_, free_size)| free_size >= aligned_size)?;
+        let (offset, free_size) = self.free_ranges[index];
+        if free_size == aligned_size {
+            self.free_ranges.remove(index);
+        } else {
+            self.free_ranges[index] = (offset + aligned_size, free_size - aligned_size);
+        }
+        Some(BufferRange {
+            buffer: self.buffer.buffer,
+            offset,
+            size,
+        })
+    }
+
+    pub fn free(&mut self, range: BufferRange) {
+        assert_eq!(
+            range.buffer, self.buffer.buffer,
+            "BufferRange freed to the wrong arena"
+        );
+        let size = range.size.max(1).next_multiple_of(self.alignment);
+        let index = self
+            .free_ranges
+            .partition_point(|&(offset, _)| offset < range.offset);
+        self.free_ranges.insert(index, (range.offset, size));
+
+        // Merge with the following range, then with the preceding one
+        if let Some(&(next_offset, next_size)) = self.free_ranges.get(index + 1) {
+            if range.offset + size == next_offset {
+                self.free_ranges[index].1 += next_size;
+                self.free_ranges.remove(index + 1);
+            }
+        }
+        if index > 0 {
+            let (prev_offset, prev_size) = self.free_ranges[index - 1];
+            if prev_offset + prev_size == range.offset {
+                self.free_ranges[index - 1].1 += self.free_ranges[index].1;
+                self.free_ranges.remove(index);
+            }
+        }
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_ranges.iter().map(|&(_, size)| size).sum()
+    }
+
+    // Writes data at the start of a host visible range
+    pub fn write<T: Pod>(&self, range: &BufferRange, data: &[T]) -> Result<(), BufferWriteError> {
+        let bytes = std::mem::size_of_val(data);
+        if bytes as u64 > range.size {
+            return Err(BufferWriteError::OutOfBounds {
+                offset: range.offset as usize,
+                bytes,
+                size: range.size,
+            });
+        }
+        self.buffer.copy_from_slice(data, range.offset as usize)
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+    }
+}
+
+// Layout and last use of an image, the source half of its next barrier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+}
+
+impl ImageState {
+    pub const UNDEFINED: ImageState = ImageState {
+        layout: vk::ImageLayout::UNDEFINED,
+        stage: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+    };
+}
+
+const WRITE_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw(),
+);
+
+// Aspects a barrier on the whole image must name, both for combined depth/stencil formats
+pub fn image_aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+// Image views live next to the images in the modules, these keep them in object_counters
+pub fn create_image_view(
+    device: &Device,
+    view_info: &vk::ImageViewCreateInfo,
+) -> Result<vk::ImageView, vk::Result> {
+    let view = unsafe { device.create_image_view(view_info, None) }?;
+    object_counters::created(ObjectType::ImageView, 1);
+    Ok(view)
+}
+
+// Null views are skipped, like the handles of a swapchain whose re-creation failed
+pub fn destroy_image_view(device: &Device, view: vk::ImageView) {
+    if view != vk::ImageView::null() {
+        unsafe { device.destroy_image_view(view, None) };
+        object_counters::destroyed(ObjectType::ImageView, 1);
+    }
+}
+
+// The tracked state follows recording order, which must match the order the command
+// buffers execute in. Render passes change layouts on their own, set_state records that.
+pub struct VkImage {
+    pub image: vk::Image,
+    pub allocation: Option<Allocation>,
+    pub budget: Option<(GpuBudget, BudgetTag, u64)>,
+    pub subresource_range: vk::ImageSubresourceRange, // Every mip level and layer
+    state: Cell<ImageState>,
+}
+
+impl VkImage {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        image_info: &vk::ImageCreateInfo,
+        location: MemoryLocation,
+    ) -> VkImage {
+        VkImage::new_internal(device, allocator, None, image_info, location).unwrap()
+    }
+
+    pub fn new_budgeted(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: (&GpuBudget, BudgetTag),
+        image_info: &vk::ImageCreateInfo,
+        location: MemoryLocation,
+    ) -> Result<VkImage, AllocationError> {
+        VkImage::new_internal(device, allocator, Some(budget), image_info, location)
+    }
+
+    fn new_internal(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: Option<(&GpuBudget, BudgetTag)>,
+        image_info: &vk::ImageCreateInfo,
+        location: MemoryLocation,
+    ) -> Result<VkImage, AllocationError> {
+        let image = unsafe { device.create_image(image_info, None) }.unwrap();
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        if let Some((budget, tag)) = budget {
+            if let Err(err) = budget.reserve(tag, requirements.size) {
+                unsafe { device.destroy_image(image, None) };
+                return Err(err);
+            }
+        }
+
+        let mut allocation = allocator.allocate(&AllocationCreateDesc {
+            name: "image",
+            requirements,
+            location,
+            linear: false,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        });
+
+        // Workaround for gpu_allocator DEVICE_LOCAL memory running out on iGPUs
+        // TODO: Remove once gpu_allocator handles iGPUs properly!
+        if allocation.is_err() {
+            allocation = allocator.allocate(&AllocationCreateDesc {
+                name: "image",
+                requirements,
+                location: MemoryLocation::CpuToGpu,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            });
+        };
+
+        let allocation = allocation.map_err(|err| {
+            unsafe { device.destroy_image(image, None) };
+            if let Some((budget, tag)) = budget {
+                budget.release(tag, requirements.size);
+            }
+            AllocationError::OutOfMemory {
+                tag: budget.map(|(_, tag)| tag),
+                requested: requirements.size,
+                message: err.to_string(),
+            }
+        })?;
+
+        unsafe {
+            device
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+                .unwrap()
+        };
+
+        object_counters::created(ObjectType::Image, 1);
+        Ok(VkImage {
+            image,
+            allocation: Some(allocation),
+            budget: budget.map(|(budget, tag)| (budget.clone(), tag, requirements.size)),
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: image_aspect_mask(image_info.format),
+                base_mip_level: 0,
+                level_count: image_info.mip_levels,
+                base_array_layer: 0,
+                layer_count: image_info.array_layers,
+            },
+            state: Cell::new(ImageState::UNDEFINED),
+        })
+    }
+
+    pub fn state(&self) -> ImageState {
+        self.state.get()
+    }
+
+    // For layout changes made outside transition_to, like render pass final layouts
+    pub fn set_state(&self, state: ImageState) {
+        self.state.set(state);
+    }
+
+    // Barrier from the tracked state to the new use of the whole image. Reads following
+    // reads in the same layout need no barrier and only add their stages and accesses.
+    pub fn transition_to(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) {
+        let old = self.state.get();
+        let writes = (old.access | access).intersects(WRITE_ACCESS);
+        if old.layout == layout && !writes {
+            self.state.set(ImageState {
+                layout,
+                stage: old.stage | stage,
+                access: old.access | access,
+            });
+            return;
+        }
+        self.barrier(
+            device,
+            barrier_api,
+            command_buffer,
+            old,
+            layout,
+            stage,
+            access,
+        );
+    }
+
+    // Like transition_to, for uses that overwrite the whole image: the old contents are
+    // discarded (UNDEFINED old layout), only the execution dependency is kept
+    pub fn transition_discarding(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) {
+        let old = ImageState {
+            layout: vk::ImageLayout::UNDEFINED,
+            ..self.state.get()
+        };
+        self.barrier(
+            device,
+            barrier_api,
+            command_buffer,
+            old,
+            layout,
+            stage,
+            access,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn barrier(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        old: ImageState,
+        layout: vk::ImageLayout,
+        stage: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+    ) {
+        // Only writes have to be made available, reads just need the execution dependency
+        let barrier = vk::ImageMemoryBarrier2 {
+            src_stage_mask: old.stage,
+            src_access_mask: old.access & WRITE_ACCESS,
+            dst_stage_mask: stage,
+            dst_access_mask: access,
+            old_layout: old.layout,
+            new_layout: layout,
+            image: self.image,
+            subresource_range: self.subresource_range,
+            ..Default::default()
+        };
+        image_barrier2(device, barrier_api, command_buffer, barrier);
+        self.state.set(ImageState {
+            layout,
+            stage,
+            access,
+        });
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        allocator.free(self.allocation.take().unwrap()).unwrap();
+        unsafe { device.destroy_image(self.image, None) };
+        object_counters::destroyed(ObjectType::Image, 1);
+        if let Some((budget, tag, bytes)) = self.budget.take() {
+            budget.release(tag, bytes);
+        }
+    }
+}
+
+// Optimal or linear tiling features of format include all of features
+pub fn format_supports(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> bool {
+    let properties = unsafe { instance.get_physical_device_format_properties(pdevice, format) };
+    match tiling {
+        vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+        _ => properties.optimal_tiling_features.contains(features),
+    }
+}
+
+// First of the candidates, in order of preference, with all of features
+pub fn first_supported_format(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    candidates: &[vk::Format],
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> Option<vk::Format> {
+    candidates
+        .iter()
+        .copied()
+        .find(|&format| format_supports(instance, pdevice, format, tiling, features))
+}
+
+const SPARSE_IMAGE_FLAGS: vk::ImageCreateFlags = vk::ImageCreateFlags::from_raw(
+    vk::ImageCreateFlags::SPARSE_BINDING.as_raw() | vk::ImageCreateFlags::SPARSE_RESIDENCY.as_raw(),
+);
+
+// Whether a 3D image like image_info can be created as a VkSparseImage
+pub fn sparse_image3d_supported(
+    instance: &Instance,
+    pdevice: vk::PhysicalDevice,
+    capabilities: &DeviceCapabilities,
+    image_info: &vk::ImageCreateInfo,
+) -> bool {
+    if !capabilities.sparse_binding
+        || !capabilities.sparse_residency_image3d
+        || image_info.image_type != vk::ImageType::TYPE_3D
+    {
+        return false;
+    }
+    let properties = unsafe {
+        instance.get_physical_device_image_format_properties(
+            pdevice,
+            image_info.format,
+            image_info.image_type,
+            image_info.tiling,
+            image_info.usage,
+            image_info.flags | SPARSE_IMAGE_FLAGS,
+        )
+    };
+    let fits = properties.is_ok_and(|properties| {
+        let max = properties.max_extent;
+        image_info.extent.width <= max.width
+            && image_info.extent.height <= max.height
+            && image_info.extent.depth <= max.depth
+    });
+    let sparse_formats = unsafe {
+        instance.get_physical_device_sparse_image_format_properties(
+            pdevice,
+            image_info.format,
+            image_info.image_type,
+            image_info.samples,
+            image_info.usage,
+            image_info.tiling,
+        )
+    };
+    fits && !sparse_formats.is_empty()
+}
+
+// Image with sparse residency, only committed pages are backed by memory. A page is one
+// sparse block of mip level 0, granularity texels in size. commit and release only queue
+// the binds, bind_pending submits them. Texels of pages that aren't committed read as
+// undefined values, the shaders must not sample them.
+pub struct VkSparseImage {
+    pub image: VkImage, // Without allocation, destroyed by VkSparseImage::destroy
+    pub extent: vk::Extent3D,
+    pub granularity: vk::Extent3D,
+    pub page_size: u64,
+    memory_type_bits: u32,
+    pages: HashMap<(u32, u32, u32), Allocation>,
+    mip_tail: Vec<Allocation>, // Bound once, mip levels below the granularity and metadata
+    pending_binds: Vec<vk::SparseImageMemoryBind>,
+    pending_opaque_binds: Vec<vk::SparseMemoryBind>,
+    released: Vec<Allocation>, // Freed once their unbinds are done
+    budget: (GpuBudget, BudgetTag),
+    committed_bytes: u64,
+}
+
+impl VkSparseImage {
+    // Check sparse_image3d_supported first, SPARSE_BINDING and SPARSE_RESIDENCY are added
+    // to the flags of image_info
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: (&GpuBudget, BudgetTag),
+        image_info: &vk::ImageCreateInfo,
+    ) -> Result<VkSparseImage, AllocationError> {
+        let image_info = vk::ImageCreateInfo {
+            flags: image_info.flags | SPARSE_IMAGE_FLAGS,
+            ..*image_info
+        };
+        let image = unsafe { device.create_image(&image_info, None) }.unwrap();
+        object_counters::created(ObjectType::Image, 1);
+        let requirements = unsafe { device.get_image_memory_requirements(image) };
+        let sparse_requirements = unsafe { device.get_image_sparse_memory_requirements(image) };
+        let aspect_mask = image_aspect_mask(image_info.format);
+        let granularity = sparse_requirements
+            .iter()
+            .find(|r| r.format_properties.aspect_mask.intersects(aspect_mask))
+            .map(|r| r.format_properties.image_granularity)
+            .expect("No sparse memory requirements for the image aspect");
+
+        let mut sparse_image = VkSparseImage {
+            image: VkImage {
+                image,
+                allocation: None,
+                budget: None,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask,
+                    base_mip_level: 0,
+                    level_count: image_info.mip_levels,
+                    base_array_layer: 0,
+                    layer_count: image_info.array_layers,
+                },
+                state: Cell::new(ImageState::UNDEFINED),
+            },
+            extent: image_info.extent,
+            granularity,
+            page_size: requirements.alignment, // The sparse block size
+            memory_type_bits: requirements.memory_type_bits,
+            pages: HashMap::new(),
+            mip_tail: Vec::new(),
+            pending_binds: Vec::new(),
+            pending_opaque_binds: Vec::new(),
+            released: Vec::new(),
+            budget: (budget.0.clone(), budget.1),
+            committed_bytes: 0,
+        };
+
+        // Single mip tail per image, the atlas images have one array layer
+        for r in &sparse_requirements {
+            let metadata = r
+                .format_properties
+                .aspect_mask
+                .contains(vk::ImageAspectFlags::METADATA);
+            if r.image_mip_tail_first_lod >= image_info.mip_levels && !metadata {
+                continue;
+            }
+            let allocation = match sparse_image.allocate(allocator, r.image_mip_tail_size) {
+                Ok(allocation) => allocation,
+                Err(err) => {
+                    sparse_image.destroy(device, allocator);
+                    return Err(err);
+                }
+            };
+            sparse_image
+                .pending_opaque_binds
+                .push(vk::SparseMemoryBind {
+                    resource_offset: r.image_mip_tail_offset,
+                    size: r.image_mip_tail_size,
+                    memory: unsafe { allocation.memory() },
+                    memory_offset: allocation.offset(),
+                    flags: if metadata {
+                        vk::SparseMemoryBindFlags::METADATA
+                    } else {
+                        vk::SparseMemoryBindFlags::empty()
+                    },
+                });
+            sparse_image.mip_tail.push(allocation);
+        }
+        Ok(sparse_image)
+    }
+
+    fn allocate(
+        &mut self,
+        allocator: &mut TrackingAllocator,
+        size: u64,
+    ) -> Result<Allocation, AllocationError> {
+        let (budget, tag) = (&self.budget.0, self.budget.1);
+        budget.reserve(tag, size)?;
+        let requirements = vk::MemoryRequirements {
+            size,
+            alignment: self.page_size,
+            memory_type_bits: self.memory_type_bits,
+        };
+        let allocation = allocator
+            .allocate(&AllocationCreateDesc {
+                name: "sparse image page",
+                requirements,
+                location: MemoryLocation::GpuOnly,
+                linear: false,
+                allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+            })
+            .map_err(|err| {
+                budget.release(tag, size);
+                AllocationError::OutOfMemory {
+                    tag: Some(tag),
+                    requested: size,
+                    message: err.to_string(),
+                }
+            })?;
+        self.committed_bytes += size;
+        Ok(allocation)
+    }
+
+    // Page coordinates and texel region of every page overlapping the region of mip level 0
+    fn pages_in(
+        &self,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) -> Vec<((u32, u32, u32), vk::Offset3D, vk::Extent3D)> {
+        let axes = [
+            (
+                offset.x as u32,
+                extent.width,
+                self.granularity.width,
+                self.extent.width,
+            ),
+            (
+                offset.y as u32,
+                extent.height,
+                self.granularity.height,
+                self.extent.height,
+            ),
+            (
+                offset.z as u32,
+                extent.depth,
+                self.granularity.depth,
+                self.extent.depth,
+            ),
+        ];
+        let ranges = axes.map(|(start, size, page, image_size)| {
+            start / page..(start + size).min(image_size).div_ceil(page)
+        });
+        // Pages at the image edge are cut off, the bind extent ends at the edge
+        let page_region = |axis: usize, page_coord: u32| {
+            let (_, _, page, image_size) = axes[axis];
+            let start = page_coord * page;
+            (start as i32, page.min(image_size - start))
+        };
+        let mut pages = Vec::new();
+        for z in ranges[2].clone() {
+            for y in ranges[1].clone() {
+                for x in ranges[0].clone() {
+                    let (px, width) = page_region(0, x);
+                    let (py, height) = page_region(1, y);
+                    let (pz, depth) = page_region(2, z);
+                    pages.push((
+                        (x, y, z),
+                        vk::Offset3D {
+                            x: px,
+                            y: py,
+                            z: pz,
+                        },
+                        vk::Extent3D {
+                            width,
+                            height,
+                            depth,
+                        },
+                    ));
+                }
+            }
+        }
+        pages
+    }
+
+    // Commits memory for every page overlapping the region, pages already committed are
+    // kept. Returns the number of newly committed pages.
+    pub fn commit(
+        &mut self,
+        allocator: &mut TrackingAllocator,
+        offset: vk::Offset3D,
+        extent: vk::Extent3D,
+    ) -> Result<usize, AllocationError> {
+        let mut committed = 0;
+        for (page, page_offset, page_extent) in self.pages_in(offset, extent) {
+            if self.pages.contains_key(&page) {
+                continue;
+            }
+            let allocation = self.allocate(allocator, self.page_size)?;
+            self.pending_binds.push(vk::SparseImageMemoryBind {
+                subresource: self.subresource(),
+                offset: page_offset,
+                extent: page_extent,
+                memory: unsafe { allocation.memory() },
+                memory_offset: allocation.offset(),
+                flags: vk::SparseMemoryBindFlags::empty(),
+            });
+            self.pages.insert(page, allocation);
+            committed += 1;
+        }
+        Ok(committed)
+    }
+
+    // Releases the pages that lie completely inside the region, pages shared with texels
+    // outside of it stay committed. Returns the number of released pages.
+    pub fn release(&mut self, offset: vk::Offset3D, extent: vk::Extent3D) -> usize {
+        let end = (
+            offset.x as u32 + extent.width,
+            offset.y as u32 + extent.height,
+            offset.z as u32 + extent.depth,
+        );
+        let mut released = 0;
+        for (page, page_offset, page_extent) in self.pages_in(offset, extent) {
+            let inside = page_offset.x >= offset.x
+                && page_offset.y >= offset.y
+                && page_offset.z >= offset.z
+                && page_offset.x as u32 + page_extent.width <= end.0
+                && page_offset.y as u32 + page_extent.height <= end.1
+                && page_offset.z as u32 + page_extent.depth <= end.2;
+            if !inside {
+                continue;
+            }
+            if let Some(allocation) = self.pages.remove(&page) {
+                self.pending_binds.push(vk::SparseImageMemoryBind {
+                    subresource: self.subresource(),
+                    offset: page_offset,
+                    extent: page_extent,
+                    memory: vk::DeviceMemory::null(),
+                    memory_offset: 0,
+                    flags: vk::SparseMemoryBindFlags::empty(),
+                });
+                self.released.push(allocation);
+                released += 1;
+            }
+        }
+        released
+    }
+
+    fn subresource(&self) -> vk::ImageSubresource {
+        vk::ImageSubresource {
+            aspect_mask: self.image.subresource_range.aspect_mask,
+            mip_level: 0,
+            array_layer: 0,
+        }
+    }
+
+    // Submits the queued binds to a queue of a family with SPARSE_BINDING and waits for
+    // them, for setup and for streaming between frames. The queue has to be idle or only
+    // run work that doesn't touch the changed pages.
+    pub fn bind_pending(
+        &mut self,
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        queue: vk::Queue,
+    ) -> Result<(), vk::Result> {
+        if self.pending_binds.is_empty() && self.pending_opaque_binds.is_empty() {
+            return Ok(());
+        }
+        let image_binds = [vk::SparseImageMemoryBindInfo {
+            image: self.image.image,
+            bind_count: self.pending_binds.len() as u32,
+            p_binds: self.pending_binds.as_ptr(),
+            ..Default::default()
+        }];
+        let opaque_binds = [vk::SparseImageOpaqueMemoryBindInfo {
+            image: self.image.image,
+            bind_count: self.pending_opaque_binds.len() as u32,
+            p_binds: self.pending_opaque_binds.as_ptr(),
+            ..Default::default()
+        }];
+        let image_bind_count = (!self.pending_binds.is_empty()) as u32;
+        let opaque_bind_count = (!self.pending_opaque_binds.is_empty()) as u32;
+        let bind_info = vk::BindSparseInfo {
+            image_bind_count,
+            p_image_binds: image_binds.as_ptr(),
+            image_opaque_bind_count: opaque_bind_count,
+            p_image_opaque_binds: opaque_binds.as_ptr(),
+            ..Default::default()
+        };
+        unsafe {
+            let fence = device.create_fence(&vk::FenceCreateInfo::default(), None)?;
+            let result = device
+                .queue_bind_sparse(queue, &[bind_info], fence)
+                .and_then(|_| device.wait_for_fences(&[fence], true, u64::MAX));
+            device.destroy_fence(fence, None);
+            result?;
+        }
+        self.pending_binds.clear();
+        self.pending_opaque_binds.clear();
+        for allocation in self.released.drain(..) {
+            self.budget.0.release(self.budget.1, self.page_size);
+            self.committed_bytes -= self.page_size;
+            allocator.free(allocation).unwrap();
+        }
+        Ok(())
+    }
+
+    pub fn committed_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    // Pages and mip tail
+    pub fn committed_bytes(&self) -> u64 {
+        self.committed_bytes
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        unsafe { device.destroy_image(self.image.image, None) };
+        object_counters::destroyed(ObjectType::Image, 1);
+        let pages = self.pages.drain().map(|(_, allocation)| allocation);
+        for allocation in pages
+            .chain(self.mip_tail.drain(..))
+            .chain(self.released.drain(..))
+        {
+            allocator.free(allocation).unwrap();
+        }
+        self.budget.0.release(self.budget.1, self.committed_bytes);
+        self.committed_bytes = 0;
+        self.pending_binds.clear();
+        self.pending_opaque_binds.clear();
+    }
+}
+
+// Covers optimalBufferCopyOffsetAlignment and texel size alignment of image copies
+const STAGING_ALIGNMENT: u64 = 256;
+
+// The largest minUniformBufferOffsetAlignment the spec allows, valid for dynamic offsets anywhere
+pub const UNIFORM_ALIGNMENT: u64 = 256;
+
+// Sampled 2D texture loaded by load_texture_2d. record_upload copies the staged levels into the
+// image, which is in SHADER_READ_ONLY_OPTIMAL afterwards. The staging buffer lives until destroy.
+pub struct Texture2d {
+    pub image: VkImage,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    staging: VkBuffer,
+    regions: Vec<vk::BufferImageCopy>,
+}
+
+// PNG or KTX2 (including BC compressed levels) into a GpuOnly image with a staged upload.
+// srgb selects the format of PNG files, KTX2 files carry their own.
+pub fn load_texture_2d(
+    device: &Device,
+    allocator: &mut TrackingAllocator,
+    capabilities: &DeviceCapabilities,
+    path: &Path,
+    srgb: bool,
+) -> Result<Texture2d, TextureFileError> {
+    let data = TextureData::load(path, srgb)?;
+    if is_block_compressed(data.format) && !capabilities.texture_compression_bc {
+        return Err(TextureFileError::UnsupportedFormat(data.format));
+    }
+    let mip_levels = data.levels.len() as u32;
+
+    let image_info = vk::ImageCreateInfo {
+        image_type: vk::ImageType::TYPE_2D,
+        format: data.format,
+        extent: vk::Extent3D {
+            width: data.width,
+            height: data.height,
+            depth: 1,
+        },
+        mip_levels,
+        array_layers: 1,
+        samples: vk::SampleCountFlags::TYPE_1,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage: vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+    let image = VkImage::new(device, allocator, &image_info, MemoryLocation::GpuOnly);
+
+    // Levels are packed at 16 byte offsets, enough for the largest texel block
+    let mut regions = Vec::with_capacity(data.levels.len());
+    let mut staging_size = 0;
+    for (level, bytes) in data.levels.iter().enumerate() {
+        regions.push(vk::BufferImageCopy {
+            buffer_offset: staging_size,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: level as u32,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_extent: vk::Extent3D {
+                width: (data.width >> level).max(1),
+                height: (data.height >> level).max(1),
+                depth: 1,
+            },
+            ..Default::default()
+        });
+        staging_size = (staging_size + bytes.len() as u64).next_multiple_of(16);
+    }
+    let staging_info = vk::BufferCreateInfo {
+        size: staging_size.max(16),
+        usage: vk::BufferUsageFlags::TRANSFER_SRC,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        ..Default::default()
+    };
+    let staging = VkBuffer::new(device, allocator, &staging_info, MemoryLocation::CpuToGpu);
+    for (bytes, region) in data.levels.iter().zip(regions.iter()) {
+        staging
+            .copy_from_slice(&bytes[..], region.buffer_offset as usize)
+            .unwrap();
+    }
+
+    let view_info = vk::ImageViewCreateInfo {
+        view_type: vk::ImageViewType::TYPE_2D,
+        format: data.format,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            level_count: mip_levels,
+            layer_count: 1,
+            ..Default::default()
+        },
+        image: image.image,
+        ..Default::default()
+    };
+    let view = create_image_view(device, &view_info).unwrap();
+
+    Ok(Texture2d {
+        image,
+        view,
+        format: data.format,
+        extent: vk::Extent2D {
+            width: data.width,
+            height: data.height,
+        },
+        mip_levels,
+        staging,
+        regions,
+    })
+}
+
+impl Texture2d {
+    pub fn record_upload(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        self.image.transition_discarding(
+            device,
+            barrier_api,
+            command_buffer,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::PipelineStageFlags2::COPY,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
+
+        unsafe {
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                self.staging.buffer,
+                self.image.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &self.regions[..],
+            )
+        };
+
+        self.image.transition_to(
+            device,
+            barrier_api,
+            command_buffer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+        );
+    }
+
+    pub fn descriptor(&self, sampler: vk::Sampler) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler,
+            image_view: self.view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut TrackingAllocator) {
+        destroy_image_view(device, self.view);
+        self.image.destroy(device, allocator);
+        self.staging.destroy(device, allocator);
+    }
+}
+
+// Persistently mapped upload memory replacing a CpuToGpu buffer per upload. The buffer has
+// one region per frame in flight, allocations are linear within the region of the current
+// frame and the region is recycled when its frame index comes around again.
+pub struct StagingRing {
+    pub buffer: VkBuffer,
+    pub frame_size: u64,
+    frame: usize,
+    head: u64, // Bytes allocated in the current frame's region
+}
+
+impl StagingRing {
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: &GpuBudget,
+        frame_size: u64,
+        num_frames: usize,
+    ) -> Result<StagingRing, AllocationError> {
+        let frame_size = frame_size.max(1).next_multiple_of(STAGING_ALIGNMENT);
+        let buffer_info = vk::BufferCreateInfo {
+            size: frame_size * num_frames as u64,
+            usage: vk::BufferUsageFlags::TRANSFER_SRC,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = VkBuffer::new_budgeted(
+            device,
+            allocator,
+            (budget, BudgetTag::Staging),
+            &buffer_info,
+            MemoryLocation::CpuToGpu,
+        )?;
+        Ok(StagingRing {
+            buffer,
+            frame_size,
+            frame: 0,
+            head: 0,
+        })
+    }
+
+    pub fn num_frames(&self) -> usize {
+        (self.buffer.size / self.frame_size) as usize
+    }
+
+    // Recycles the region of frame. Call after waiting for the previous command buffer
+    // recorded with this frame index, e.g. inside VulkanBase::record_commandbuffer.
+    pub fn begin_frame(&mut self, frame: usize) {
+        self.frame = frame % self.num_frames();
+        self.head = 0;
+    }
+
+    // Mapped pointer and buffer offset of size bytes in the current frame's region
+    pub fn allocate(&mut self, size: u64) -> Result<(*mut u8, u64), AllocationError> {
+        let start = self.head.next_multiple_of(STAGING_ALIGNMENT);
+        if start + size > self.frame_size {
+            return Err(AllocationError::OverBudget {
+                tag: BudgetTag::Staging,
+                requested: size,
+                used: self.head,
+                limit: self.frame_size,
+            });
+        }
+        self.head = start + size;
+        let offset = self.frame as u64 * self.frame_size + start;
+        Ok((
+            unsafe { self.buffer.mapped_ptr.add(offset as usize) },
+            offset,
+        ))
+    }
+
+    fn stage<T: Pod>(&mut self, data: &[T]) -> Result<u64, AllocationError> {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let (mapped_ptr, offset) = self.allocate(bytes.len() as u64)?;
+        unsafe { from_raw_parts_mut(mapped_ptr, bytes.len()) }.copy_from_slice(bytes);
+        self.buffer.record_write(bytes.len());
+        Ok(offset)
+    }
+
+    // Records a copy of data into dst. Barriers around the copy are up to the caller.
+    pub fn copy_to_buffer<T: Pod>(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        data: &[T],
+        dst: vk::Buffer,
+        dst_offset: u64,
+    ) -> Result<(), AllocationError> {
+        let offset = self.stage(data)?;
+        let region = vk::BufferCopy {
+            src_offset: offset,
+            dst_offset,
+            size: std::mem::size_of_val(data) as u64,
+        };
+        unsafe { device.cmd_copy_buffer(command_buffer, self.buffer.buffer, dst, &[region]) };
+        Ok(())
+    }
+
+    // Records a copy of tightly packed texels into dst, which must be in
+    // TRANSFER_DST_OPTIMAL. The buffer offset of region is filled in.
+    pub fn copy_to_image<T: Pod>(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        data: &[T],
+        dst: vk::Image,
+        region: vk::BufferImageCopy,
+    ) -> Result<(), AllocationError> {
+        let region = vk::BufferImageCopy {
+            buffer_offset: self.stage(data)?,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            ..region
+        };
+        unsafe {
+            device.cmd_copy_buffer_to_image(
+                command_buffer,
+                self.buffer.buffer,
+                dst,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            )
+        };
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+    }
+}
+
+// Bump allocator for transient GPU buffers that only live for one frame (culling uniforms,
+// readback targets). Like StagingRing the buffer has one region per frame in flight, but the
+// usage and memory location are up to the caller and allocations come back as BufferRange so
+// they can be bound directly. Nothing is freed individually: begin_frame resets the region once
+// the fence of the frame that last used it has signaled.
+pub struct ScratchAllocator {
+    pub buffer: VkBuffer,
+    pub frame_size: u64,
+    frame: usize,
+    head: u64,
+    peak: u64, // Largest per frame usage seen, for sizing frame_size
+}
+
+impl ScratchAllocator {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        allocator: &mut TrackingAllocator,
+        budget: &GpuBudget,
+        frame_size: u64,
+        num_frames: usize,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> Result<ScratchAllocator, AllocationError> {
+        let frame_size = frame_size.max(1).next_multiple_of(STAGING_ALIGNMENT);
+        let buffer_info = vk::BufferCreateInfo {
+            size: frame_size * num_frames as u64,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = VkBuffer::new_budgeted(
+            device,
+            allocator,
+            (budget, BudgetTag::Scratch),
+            &buffer_info,
+            location,
+        )?;
+        Ok(ScratchAllocator {
+            buffer,
+            frame_size,
+            frame: 0,
+            head: 0,
+            peak: 0,
+        })
+    }
+
+    pub fn num_frames(&self) -> usize {
+        (self.buffer.size / self.frame_size) as usize
+    }
+
+    // Call after waiting for the previous command buffer recorded with this frame index
+    pub fn begin_frame(&mut self, frame: usize) {
+        self.frame = frame % self.num_frames();
+        self.head = 0;
+    }
+
+    pub fn peak_bytes(&self) -> u64 {
+        self.peak
+    }
+
+    // Range of size bytes in the current frame's region, valid until the region comes around
+    // again. Alignment must cover the descriptor offset limit when the range is bound.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Result<BufferRange, AllocationError> {
+        let start = self.head.next_multiple_of(alignment.max(1));
+        if start + size > self.frame_size {
+            return Err(AllocationError::OverBudget {
+                tag: BudgetTag::Scratch,
+                requested: size,
+                used: self.head,
+                limit: self.frame_size,
+            });
+        }
+        self.head = start + size;
+        self.peak = self.peak.max(self.head);
+        Ok(BufferRange {
+            buffer: self.buffer.buffer,
+            offset: self.frame as u64 * self.frame_size + start,
+            size,
+        })
+    }
+
+    // Allocates a range holding data, the buffer must be host visible
+    pub fn push<T: Pod>(
+        &mut self,
+        data: &[T],
+        alignment: u64,
+    ) -> Result<BufferRange, AllocationError> {
+        let alignment = alignment.max(std::mem::align_of::<T>() as u64);
+        let range = self.allocate(std::mem::size_of_val(data) as u64, alignment)?;
+        self.buffer
+            .copy_from_slice(data, range.offset as usize)
+            .unwrap();
+        Ok(range)
+    }
+
+    // Descriptor for a UNIFORM_BUFFER_DYNAMIC binding reading one T. The offset of each frame's
+    // copy comes from push_uniform and is passed to cmd_bind_descriptor_sets.
+    pub fn uniform_descriptor<T: Pod>(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer.buffer,
+            offset: 0,
+            range: std::mem::size_of::<T>() as u64,
+        }
+    }
+
+    // Copies uniforms into this frame's region and returns their dynamic offset. The buffer
+    // needs UNIFORM_BUFFER usage.
+    pub fn push_uniform<T: Pod>(&mut self, uniforms: &T) -> Result<u32, AllocationError> {
+        let range = self.push(std::slice::from_ref(uniforms), UNIFORM_ALIGNMENT)?;
+        Ok(range.offset as u32)
+    }
+
+    pub fn destroy(&mut self, device: &Device, allocator: &mut Allocator) {
+        self.buffer.destroy(device, allocator);
+    }
+}
+
+// Sampler state that modules ask for. Anisotropy comes from the cache, so one desc
+// maps to one sampler for the whole application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode: vk::SamplerAddressMode,
+    pub max_lod: u32,
+    pub anisotropic: bool, // Off for samplers used by reductions (depth pyramid)
+}
+
+impl Default for SamplerDesc {
+    fn default() -> SamplerDesc {
+        SamplerDesc {
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_lod: 0,
+            anisotropic: true,
+        }
+    }
+}
+
+// One vk::Sampler per SamplerDesc, so identical samplers requested by different modules (SVO
+// textures, depth pyramid, detail texture) are shared. VulkanBase owns the cache and destroys
+// the samplers with the device, also when vbufferbench recovers from a lost device.
+pub struct SamplerCache {
+    pub max_anisotropy: f32,    // 1.0 = anisotropic filtering disabled
+    device_max_anisotropy: f32, // 1.0 when samplerAnisotropy isn't supported
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+}
+
+impl SamplerCache {
+    // Requested anisotropy is clamped to the device limit
+    pub fn new(capabilities: &DeviceCapabilities, requested_anisotropy: f32) -> SamplerCache {
+        let device_max_anisotropy = if capabilities.sampler_anisotropy {
+            capabilities.max_sampler_anisotropy.max(1.0)
+        } else {
+            1.0
+        };
+        SamplerCache {
+            max_anisotropy: requested_anisotropy.clamp(1.0, device_max_anisotropy),
+            device_max_anisotropy,
+            samplers: HashMap::new(),
+        }
+    }
+
+    // Cached samplers keep the anisotropy they were created with, so this has to be called
+    // before modules ask for samplers (VulkanBase creates the cache with anisotropy off)
+    pub fn set_max_anisotropy(&mut self, requested_anisotropy: f32) {
+        assert!(
+            self.samplers.is_empty(),
+            "Sampler anisotropy changed after samplers were created"
+        );
+        self.max_anisotropy = requested_anisotropy.clamp(1.0, self.device_max_anisotropy);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+
+    pub fn get(&mut self, device: &Device, desc: SamplerDesc) -> vk::Sampler {
+        let max_anisotropy = self.max_anisotropy;
+        *self.samplers.entry(desc).or_insert_with(|| {
+            let anisotropy = if desc.anisotropic {
+                max_anisotropy
+            } else {
+                1.0
+            };
+            let sampler_info = vk::SamplerCreateInfo {
+                mag_filter: desc.filter,
+                min_filter: desc.filter,
+                mipmap_mode: desc.mipmap_mode,
+                address_mode_u: desc.address_mode,
+                address_mode_v: desc.address_mode,
+                address_mode_w: desc.address_mode,
+                anisotropy_enable: (anisotropy > 1.0) as u32,
+                max_anisotropy: anisotropy,
+                border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+                compare_op: vk::CompareOp::NEVER,
+                min_lod: 0.0,
+                max_lod: desc.max_lod as f32,
+                ..Default::default()
+            };
+            let sampler = unsafe { device.create_sampler(&sampler_info, None).unwrap() };
+            object_counters::created(ObjectType::Sampler, 1);
+            sampler
+        })
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        for (_, sampler) in self.samplers.drain() {
+            unsafe { device.destroy_sampler(sampler, None) };
+            object_counters::destroyed(ObjectType::Sampler, 1);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct VkViewScissor {
+    pub viewport: vk::Viewport,
+    pub scissor: vk::Rect2D,
+}
+
+impl VkViewScissor {
+    pub fn from_rect(rect: vk::Rect2D) -> VkViewScissor {
+        let viewport = vk::Viewport {
+            x: rect.offset.x as f32,
+            y: rect.offset.y as f32,
+            width: rect.extent.width as f32,
+            height: rect.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        VkViewScissor {
+            viewport,
+            scissor: rect,
+        }
+    }
+
+    // Square sub viewport in the top right corner, shrinks to fit small windows
+    pub fn top_right(extent: vk::Extent2D, size: u32, margin: u32) -> VkViewScissor {
+        let size = size.min(extent.width.min(extent.height).saturating_sub(2 * margin));
+        VkViewScissor::from_rect(vk::Rect2D {
+            offset: vk::Offset2D {
+                x: extent.width.saturating_sub(size + margin) as i32,
+                y: margin as i32,
+            },
+            extent: vk::Extent2D {
+                width: size,
+                height: size,
+            },
+        })
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let rect = self.scissor;
+        x >= rect.offset.x
+            && y >= rect.offset.y
+            && x < rect.offset.x + rect.extent.width as i32
+            && y < rect.offset.y + rect.extent.height as i32
+    }
+
+    pub fn set(&self, device: &Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &[self.viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[self.scissor]);
+        }
+    }
+}
+
+// Which vkCmdPipelineBarrier2 entry point is available. Legacy translates the
+// synchronization2 masks to vkCmdPipelineBarrier, so callers always use the precise masks.
+#[derive(Clone)]
+pub enum BarrierApi {
+    Legacy,
+    Core, // Vulkan 1.3
+    Extension(ash::khr::synchronization2::Device),
+}
+
+impl BarrierApi {
+    pub fn is_synchronization2(&self) -> bool {
+        !matches!(self, BarrierApi::Legacy)
+    }
+}
+
+pub fn barrier2(
+    device: &Device,
+    barrier_api: &BarrierApi,
+    command_buffer: vk::CommandBuffer,
+    memory_barriers: &[vk::MemoryBarrier2],
+    buffer_barriers: &[vk::BufferMemoryBarrier2],
+    image_barriers: &[vk::ImageMemoryBarrier2],
+) {
+    let dependency_info = vk::DependencyInfo {
+        memory_barrier_count: memory_barriers.len() as u32,
+        p_memory_barriers: memory_barriers.as_ptr(),
+        buffer_memory_barrier_count: buffer_barriers.len() as u32,
+        p_buffer_memory_barriers: buffer_barriers.as_ptr(),
+        image_memory_barrier_count: image_barriers.len() as u32,
+        p_image_memory_barriers: image_barriers.as_ptr(),
+        ..Default::default()
+    };
+
+    match barrier_api {
+        BarrierApi::Core => unsafe {
+            device.cmd_pipeline_barrier2(command_buffer, &dependency_info)
+        },
+        BarrierApi::Extension(synchronization2) => unsafe {
+            synchronization2.cmd_pipeline_barrier2(command_buffer, &dependency_info)
+        },
+        BarrierApi::Legacy => legacy_barrier(
+            device,
+            command_buffer,
+            memory_barriers,
+            buffer_barriers,
+            image_barriers,
+        ),
+    }
+}
+
+pub fn image_barrier2(
+    device: &Device,
+    barrier_api: &BarrierApi,
+    command_buffer: vk::CommandBuffer,
+    image_barrier: vk::ImageMemoryBarrier2,
+) {
+    barrier2(
+        device,
+        barrier_api,
+        command_buffer,
+        &[],
+        &[],
+        &[image_barrier],
+    );
+}
+
+// One vkCmdPipelineBarrier with the union of all stage masks
+fn legacy_barrier(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    memory_barriers: &[vk::MemoryBarrier2],
+    buffer_barriers: &[vk::BufferMemoryBarrier2],
+    image_barriers: &[vk::ImageMemoryBarrier2],
+) {
+    let mut src_stage = vk::PipelineStageFlags2::NONE;
+    let mut dst_stage = vk::PipelineStageFlags2::NONE;
+
+    let memory: Vec<vk::MemoryBarrier> = memory_barriers
+        .iter()
+        .map(|b| {
+            src_stage |= b.src_stage_mask;
+            dst_stage |= b.dst_stage_mask;
+            vk::MemoryBarrier {
+                src_access_mask: legacy_access(b.src_access_mask),
+                dst_access_mask: legacy_access(b.dst_access_mask),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let buffers: Vec<vk::BufferMemoryBarrier> = buffer_barriers
+        .iter()
+        .map(|b| {
+            src_stage |= b.src_stage_mask;
+            dst_stage |= b.dst_stage_mask;
+            vk::BufferMemoryBarrier {
+                src_access_mask: legacy_access(b.src_access_mask),
+                dst_access_mask: legacy_access(b.dst_access_mask),
+                src_queue_family_index: b.src_queue_family_index,
+                dst_queue_family_index: b.dst_queue_family_index,
+                buffer: b.buffer,
+                offset: b.offset,
+                size: b.size,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let images: Vec<vk::ImageMemoryBarrier> = image_barriers
+        .iter()
+        .map(|b| {
+            src_stage |= b.src_stage_mask;
+            dst_stage |= b.dst_stage_mask;
+            vk::ImageMemoryBarrier {
+                src_access_mask: legacy_access(b.src_access_mask),
+                dst_access_mask: legacy_access(b.dst_access_mask),
+                old_layout: b.old_layout,
+                new_layout: b.new_layout,
+                src_queue_family_index: b.src_queue_family_index,
+                dst_queue_family_index: b.dst_queue_family_index,
+                image: b.image,
+                subresource_range: b.subresource_range,
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    // Legacy stage masks can't be empty
+    let mut src_stage = legacy_stage(src_stage);
+    if src_stage.is_empty() {
+        src_stage = vk::PipelineStageFlags::TOP_OF_PIPE;
+    }
+    let mut dst_stage = legacy_stage(dst_stage);
+    if dst_stage.is_empty() {
+        dst_stage = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+    }
+
+    unsafe {
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            src_stage,
+            dst_stage,
+            vk::DependencyFlags::empty(),
+            &memory,
+            &buffers,
+            &images,
+        )
+    };
+}
+
+// The low 32 bits match the legacy flags, the synchronization2-only bits map to the
+// legacy stage containing them
+pub fn legacy_stage(stage: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+    let mut legacy = vk::PipelineStageFlags::from_raw(stage.as_raw() as u32);
+    if stage.intersects(
+        vk::PipelineStageFlags2::COPY
+            | vk::PipelineStageFlags2::RESOLVE
+            | vk::PipelineStageFlags2::BLIT
+            | vk::PipelineStageFlags2::CLEAR,
+    ) {
+        legacy |= vk::PipelineStageFlags::TRANSFER;
+    }
+    if stage.intersects(
+        vk::PipelineStageFlags2::INDEX_INPUT | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+    ) {
+        legacy |= vk::PipelineStageFlags::VERTEX_INPUT;
+    }
+    // Tessellation and geometry shaders aren't enabled
+    if stage.contains(vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS) {
+        legacy |= vk::PipelineStageFlags::VERTEX_SHADER;
+    }
+    legacy
+}
+
+pub fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    let mut legacy = vk::AccessFlags::from_raw(access.as_raw() as u32);
+    if access
+        .intersects(vk::AccessFlags2::SHADER_SAMPLED_READ | vk::AccessFlags2::SHADER_STORAGE_READ)
+    {
+        legacy |= vk::AccessFlags::SHADER_READ;
+    }
+    if access.contains(vk::AccessFlags2::SHADER_STORAGE_WRITE) {
+        legacy |= vk::AccessFlags::SHADER_WRITE;
+    }
+    legacy
+}
+
+pub fn raw_bytes<T>(data: &[T]) -> &[u8]
+where
+    T: Copy,
+{
+    unsafe { from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+// Pipeline layout with descriptor set layouts in set order and push constant ranges.
+// Ranges added with push_constants follow each other, so one block per stage can be
+// pushed at the returned offset.
+#[derive(Clone, Default)]
+pub struct PipelineLayoutBuilder {
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineLayoutBuilder {
+    pub fn new() -> PipelineLayoutBuilder {
+        PipelineLayoutBuilder::default()
+    }
+
+    pub fn set_layout(mut self, set_layout: vk::DescriptorSetLayout) -> PipelineLayoutBuilder {
+        self.set_layouts.push(set_layout);
+        self
+    }
+
+    pub fn set_layouts(mut self, set_layouts: &[vk::DescriptorSetLayout]) -> PipelineLayoutBuilder {
+        self.set_layouts.extend_from_slice(set_layouts);
+        self
+    }
+
+    // Range sized for T, placed after the previous range
+    pub fn push_constants<T: Copy>(
+        self,
+        stage_flags: vk::ShaderStageFlags,
+    ) -> PipelineLayoutBuilder {
+        self.push_constant_bytes(stage_flags, std::mem::size_of::<T>() as u32)
+    }
+
+    pub fn push_constant_bytes(
+        mut self,
+        stage_flags: vk::ShaderStageFlags,
+        size: u32,
+    ) -> PipelineLayoutBuilder {
+        let offset = self.push_constants_size();
+        self.push_constant_ranges.push(vk::PushConstantRange {
+            stage_flags,
+            offset,
+            size,
+        });
+        self
+    }
+
+    // Vulkan guarantees 128 bytes (maxPushConstantsSize)
+    pub fn push_constants_size(&self) -> u32 {
+        self.push_constant_ranges
+            .iter()
+            .map(|range| range.offset + range.size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn build(&self, device: &Device) -> Result<vk::PipelineLayout, vk::Result> {
+        let layout_create_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: self.set_layouts.len() as u32,
+            p_set_layouts: self.set_layouts.as_ptr(),
+            push_constant_range_count: self.push_constant_ranges.len() as u32,
+            p_push_constant_ranges: self.push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+        unsafe { device.create_pipeline_layout(&layout_create_info, None) }
+    }
+}
+
+// 32-bit specialization constants of a shader stage. The SpecializationInfo points into
+// this, keep it alive until the pipeline is created.
+#[derive(Clone, Debug, Default)]
+pub struct SpecializationConstants {
+    entries: Vec<vk::SpecializationMapEntry>,
+    data: Vec<u32>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> SpecializationConstants {
+        SpecializationConstants::default()
+    }
+
+    // bool, int, uint or float bits
+    pub fn with(mut self, id: u32, value: u32) -> SpecializationConstants {
+        self.entries.push(vk::SpecializationMapEntry {
+            constant_id: id,
+            offset: (self.data.len() * 4) as u32,
+            size: 4,
+        });
+        self.data.push(value);
+        self
+    }
+
+    pub fn info(&self) -> vk::SpecializationInfo<'_> {
+        vk::SpecializationInfo::default()
+            .map_entries(&self.entries)
+            .data(bytemuck::cast_slice(&self.data))
+    }
+}
+
+// Graphics pipeline with one color attachment. The defaults match the renderers: triangle
+// list, no culling, counter clockwise front faces, reversed depth test and write
+// (GREATER_OR_EQUAL, depth_compare with DepthConvention::compare_op for standard depth),
+// blending off and dynamic viewport and scissor.
+#[derive(Clone)]
+pub struct GraphicsPipelineBuilder {
+    stages: Vec<(vk::ShaderStageFlags, vk::ShaderModule)>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    blend: vk::PipelineColorBlendAttachmentState,
+    view_scissor: Option<VkViewScissor>,
+    specialization: Vec<(u32, u32)>, // (constant_id, value), given to every stage
+}
+
+impl Default for GraphicsPipelineBuilder {
+    fn default() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder {
+            stages: Vec::new(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode: vk::CullModeFlags::NONE,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::GREATER_OR_EQUAL,
+            blend: vk::PipelineColorBlendAttachmentState {
+                blend_enable: 0,
+                src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
+                dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_DST_COLOR,
+                color_blend_op: vk::BlendOp::ADD,
+                src_alpha_blend_factor: vk::BlendFactor::ZERO,
+                dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+                alpha_blend_op: vk::BlendOp::ADD,
+                color_write_mask: vk::ColorComponentFlags::RGBA,
+            },
+            view_scissor: None,
+            specialization: Vec::new(),
+        }
+    }
+}
+
+impl GraphicsPipelineBuilder {
+    pub fn new() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder::default()
+    }
+
+    // Entry point "main"
+    pub fn stage(
+        mut self,
+        stage: vk::ShaderStageFlags,
+        module: vk::ShaderModule,
+    ) -> GraphicsPipelineBuilder {
+        self.stages.push((stage, module));
+        self
+    }
+
+    // 32-bit specialization constant (bool, int, uint or float bits) of every stage, stages
+    // without the constant_id ignore it
+    pub fn specialization_constant(mut self, id: u32, value: u32) -> GraphicsPipelineBuilder {
+        self.specialization.push((id, value));
+        self
+    }
+
+    // Vertex buffer binding and the attributes read from it, no vertex buffers by default
+    pub fn vertex_input(
+        mut self,
+        binding: vk::VertexInputBindingDescription,
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> GraphicsPipelineBuilder {
+        self.vertex_bindings.push(binding);
+        self.vertex_attributes.extend_from_slice(attributes);
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> GraphicsPipelineBuilder {
+        self.topology = topology;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags) -> GraphicsPipelineBuilder {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn front_face(mut self, front_face: vk::FrontFace) -> GraphicsPipelineBuilder {
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn depth(mut self, test: bool, write: bool) -> GraphicsPipelineBuilder {
+        self.depth_test = test;
+        self.depth_write = write;
+        self
+    }
+
+    pub fn depth_compare(mut self, compare_op: vk::CompareOp) -> GraphicsPipelineBuilder {
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn blend(
+        mut self,
+        blend: vk::PipelineColorBlendAttachmentState,
+    ) -> GraphicsPipelineBuilder {
+        self.blend = blend;
+        self
+    }
+
+    // Premultiplied alpha over the attachment
+    pub fn alpha_blend(self) -> GraphicsPipelineBuilder {
+        self.blend(vk::PipelineColorBlendAttachmentState {
+            blend_enable: 1,
+            src_color_blend_factor: vk::BlendFactor::ONE,
+            dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            color_blend_op: vk::BlendOp::ADD,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            alpha_blend_op: vk::BlendOp::ADD,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        })
+    }
+
+    // Initial viewport and scissor, command buffers still set them (dynamic state)
+    pub fn view_scissor(mut self, view_scissor: &VkViewScissor) -> GraphicsPipelineBuilder {
+        self.view_scissor = Some(*view_scissor);
+        self
+    }
+
+    pub fn build(
+        &self,
+        device: &Device,
+        layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+    ) -> Result<vk::Pipeline, vk::Result> {
+        let shader_entry_name = CStr::from_bytes_with_nul(b"main\0").unwrap();
+        let specialization = self
+            .specialization
+            .iter()
+            .fold(SpecializationConstants::new(), |constants, &(id, value)| {
+                constants.with(id, value)
+            });
+        let specialization_info = specialization.info();
+        let p_specialization_info = if self.specialization.is_empty() {
+            ptr::null()
+        } else {
+            &specialization_info as *const vk::SpecializationInfo
+        };
+        let shader_stage_create_infos: Vec<vk::PipelineShaderStageCreateInfo> = self
+            .stages
+            .iter()
+            .map(|&(stage, module)| vk::PipelineShaderStageCreateInfo {
+                module,
+                p_name: shader_entry_name.as_ptr(),
+                stage,
+                p_specialization_info,
+                ..Default::default()
+            })
+            .collect();
+
+        let vertex_input_state_info = vk::PipelineVertexInputStateCreateInfo {
+            vertex_binding_description_count: self.vertex_bindings.len() as u32,
+            p_vertex_binding_descriptions: self.vertex_bindings.as_ptr(),
+            vertex_attribute_description_count: self.vertex_attributes.len() as u32,
+            p_vertex_attribute_descriptions: self.vertex_attributes.as_ptr(),
+            ..Default::default()
+        };
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            topology: self.topology,
+            ..Default::default()
+        };
+
+        // Viewport and scissor are dynamic, some drivers read the arrays regardless
+        let view_scissor = self.view_scissor.or_else(|| {
+            gpu_workarounds::active()
+                .contains(Workaround::PlaceholderViewportState)
+                .then(|| {
+                    VkViewScissor::from_rect(vk::Rect2D {
+                        extent: vk::Extent2D {
+                            width: 1,
+                            height: 1,
+                        },
+                        ..Default::default()
+                    })
+                })
+        });
+        let scissors = view_scissor.map(|view_scissor| [view_scissor.scissor]);
+        let viewports = view_scissor.map(|view_scissor| [view_scissor.viewport]);
+        let viewport_state_info = vk::PipelineViewportStateCreateInfo {
+            viewport_count: 1,
+            p_viewports: viewports
+                .as_ref()
+                .map_or(ptr::null(), |viewports| viewports.as_ptr()),
+            scissor_count: 1,
+            p_scissors: scissors
+                .as_ref()
+                .map_or(ptr::null(), |scissors| scissors.as_ptr()),
+            ..Default::default()
+        };
+
+        let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            ..Default::default()
+        };
+
+        let multisample_state_info = vk::PipelineMultisampleStateCreateInfo {
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+            ..Default::default()
+        };
+
+        let noop_stencil_state = vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            ..Default::default()
+        };
+        let depth_state_info = vk::PipelineDepthStencilStateCreateInfo {
+            depth_test_enable: self.depth_test.into(),
+            depth_write_enable: self.depth_write.into(),
+            depth_compare_op: self.depth_compare_op,
+            front: noop_stencil_state,
+            back: noop_stencil_state,
+            max_depth_bounds: 1.0,
+            ..Default::default()
+        };
+
+        let color_blend_attachment_states = [self.blend];
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            logic_op: vk::LogicOp::CLEAR,
+            attachment_count: color_blend_attachment_states.len() as u32,
+            p_attachments: color_blend_attachment_states.as_ptr(),
+            ..Default::default()
+        };
+
+        let dynamic_state = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
+            dynamic_state_count: dynamic_state.len() as u32,
+            p_dynamic_states: dynamic_state.as_ptr(),
+            ..Default::default()
+        };
+
+        let graphic_pipeline_info = vk::GraphicsPipelineCreateInfo {
+            stage_count: shader_stage_create_infos.len() as u32,
+            p_stages: shader_stage_create_infos.as_ptr(),
+            p_vertex_input_state: &vertex_input_state_info,
+            p_input_assembly_state: &vertex_input_assembly_state_info,
+            p_viewport_state: &viewport_state_info,
+            p_rasterization_state: &rasterization_info,
+            p_multisample_state: &multisample_state_info,
+            p_depth_stencil_state: &depth_state_info,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: &dynamic_state_info,
+            layout,
+            render_pass,
+            ..Default::default()
+        };
+
+        let pipelines = unsafe {
+            device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[graphic_pipeline_info],
+                None,
+            )
+        }
+        .map_err(|(_, err)| err)?;
+        Ok(pipelines[0])
+    }
+}
+
+// Pushes one constant block, stage_flags and offset must match a range of the layout
+pub fn cmd_push<T: Copy>(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    stage_flags: vk::ShaderStageFlags,
+    offset: u32,
+    data: &T,
+) {
+    unsafe {
+        device.cmd_push_constants(
+            command_buffer,
+            pipeline_layout,
+            stage_flags,
+            offset,
+            raw_bytes(std::slice::from_ref(data)),
+        );
+    }
+}
+
+// Storage resources a dispatch reads or writes. Storage images must be in GENERAL layout,
+// all mips and layers of the color aspect are covered by the barriers.
+#[derive(Clone, Copy, Default)]
+pub struct ComputeResources<'a> {
+    pub read_buffers: &'a [vk::Buffer],
+    pub write_buffers: &'a [vk::Buffer],
+    pub read_images: &'a [vk::Image],
+    pub write_images: &'a [vk::Image],
+}
+
+#[derive(Debug)]
+pub enum ComputePipelineError {
+    InvalidSpirv(io::Error),
+    Vk(vk::Result),
+}
+
+impl fmt::Display for ComputePipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ComputePipelineError::InvalidSpirv(err) => write!(f, "Invalid SPIR-V: {}", err),
+            ComputePipelineError::Vk(result) => write!(f, "Vulkan error: {:?}", result),
+        }
+    }
+}
+
+impl From<vk::Result> for ComputePipelineError {
+    fn from(result: vk::Result) -> Self {
+        ComputePipelineError::Vk(result)
+    }
+}
+
+// Compute shader module, pipeline layout and pipeline. dispatch() records the barriers
+// for the resources it touches, so passes don't have to track previous users.
+pub struct ComputePipeline {
+    pub shader_module: vk::ShaderModule,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+}
+
+impl ComputePipeline {
+    // Entry point "main", layout describes the descriptor sets and push constants
+    pub fn new(
+        device: &Device,
+        spv: &[u8],
+        layout: &PipelineLayoutBuilder,
+    ) -> Result<ComputePipeline, ComputePipelineError> {
+        let code = read_spv(&mut Cursor::new(spv)).map_err(ComputePipelineError::InvalidSpirv)?;
+        let shader_info = vk::ShaderModuleCreateInfo {
+            code_size: code.len() * 4,
+            p_code: code.as_ptr(),
+            ..Default::default()
+        };
+        let shader_module = unsafe { device.create_shader_module(&shader_info, None) }?;
+
+        let pipeline_layout = match layout.build(device) {
+            Ok(pipeline_layout) => pipeline_layout,
+            Err(err) => {
+                unsafe { device.destroy_shader_module(shader_module, None) };
+                return Err(err.into());
+            }
+        };
+
+        let shader_entry_name = CStr::from_bytes_with_nul(b"main\0").unwrap();
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: shader_module,
+                p_name: shader_entry_name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            ..Default::default()
+        };
+        let pipelines = unsafe {
+            device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+        };
+        match pipelines {
+            Ok(pipelines) => Ok(ComputePipeline {
+                shader_module,
+                pipeline_layout,
+                pipeline: pipelines[0],
+            }),
+            Err((_, err)) => {
+                unsafe {
+                    device.destroy_pipeline_layout(pipeline_layout, None);
+                    device.destroy_shader_module(shader_module, None);
+                }
+                Err(err.into())
+            }
+        }
+    }
+
+    // Push constants for the next dispatch, offset 0 of the compute stage range
+    pub fn push<T: Copy>(&self, device: &Device, command_buffer: vk::CommandBuffer, data: &T) {
+        cmd_push(
+            device,
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            data,
+        );
+    }
+
+    // Waits for all earlier writes to the resources, later commands of any stage see the
+    // written resources. Conservative, passes with known producers can use barrier2.
+    #[allow(clippy::too_many_arguments)]
+    pub fn dispatch(
+        &self,
+        device: &Device,
+        barrier_api: &BarrierApi,
+        command_buffer: vk::CommandBuffer,
+        descriptor_sets: &[vk::DescriptorSet],
+        group_count: (u32, u32, u32),
+        resources: &ComputeResources,
+    ) {
+        let buffer_barrier = |buffer: vk::Buffer,
+                              src_stage_mask: vk::PipelineStageFlags2,
+                              src_access_mask: vk::AccessFlags2,
+                              dst_stage_mask: vk::PipelineStageFlags2,
+                              dst_access_mask: vk::AccessFlags2| {
+            vk::BufferMemoryBarrier2 {
+                src_stage_mask,
+                src_access_mask,
+                dst_stage_mask,
+                dst_access_mask,
+                buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
+            }
+        };
+        let image_barrier = |image: vk::Image,
+                             src_stage_mask: vk::PipelineStageFlags2,
+                             src_access_mask: vk::AccessFlags2,
+                             dst_stage_mask: vk::PipelineStageFlags2,
+                             dst_access_mask: vk::AccessFlags2| {
+            vk::ImageMemoryBarrier2 {
+                src_stage_mask,
+                src_access_mask,
+                dst_stage_mask,
+                dst_access_mask,
+                old_layout: vk::ImageLayout::GENERAL,
+                new_layout: vk::ImageLayout::GENERAL,
+                image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        };
+
+        let all = vk::PipelineStageFlags2::ALL_COMMANDS;
+        let compute = vk::PipelineStageFlags2::COMPUTE_SHADER;
+        let memory_write = vk::AccessFlags2::MEMORY_WRITE;
+        let memory_rw = vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE;
+        let storage_read = vk::AccessFlags2::SHADER_STORAGE_READ;
+        let storage_rw = storage_read | vk::AccessFlags2::SHADER_STORAGE_WRITE;
+
+        // Reads wait for earlier writes, writes also for earlier reads
+        let before_buffers: Vec<vk::BufferMemoryBarrier2> = resources
+            .read_buffers
+            .iter()
+            .map(|&buffer| buffer_barrier(buffer, all, memory_write, compute, storage_read))
+            .chain(
+                resources
+                    .write_buffers
+                    .iter()
+                    .map(|&buffer| buffer_barrier(buffer, all, memory_rw, compute, storage_rw)),
+            )
+            .collect();
+        let before_images: Vec<vk::ImageMemoryBarrier2> = resources
+            .read_images
+            .iter()
+            .map(|&image| image_barrier(image, all, memory_write, compute, storage_read))
+            .chain(
+                resources
+                    .write_images
+                    .iter()
+                    .map(|&image| image_barrier(image, all, memory_rw, compute, storage_rw)),
+            )
+            .collect();
+
+        let after_buffers: Vec<vk::BufferMemoryBarrier2> = resources
+            .write_buffers
+            .iter()
+            .map(|&buffer| buffer_barrier(buffer, compute, storage_rw, all, memory_rw))
+            .collect();
+        let after_images: Vec<vk::ImageMemoryBarrier2> = resources
+            .write_images
+            .iter()
+            .map(|&image| image_barrier(image, compute, storage_rw, all, memory_rw))
+            .collect();
+
+        if !before_buffers.is_empty() || !before_images.is_empty() {
+            barrier2(
+                device,
+                barrier_api,
+                command_buffer,
+                &[],
+                &before_buffers,
+                &before_images,
+            );
+        }
+
+        unsafe {
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                self.pipeline,
+            );
+            if !descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::COMPUTE,
+                    self.pipeline_layout,
+                    0,
+                    descriptor_sets,
+                    &[],
+                );
+            }
+            device.cmd_dispatch(command_buffer, group_count.0, group_count.1, group_count.2);
+        }
+
+        if !after_buffers.is_empty() || !after_images.is_empty() {
+            barrier2(
+                device,
+                barrier_api,
+                command_buffer,
+                &[],
+                &after_buffers,
+                &after_images,
+            );
+        }
+    }
+
+    pub fn destroy(&self, device: &Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_shader_module(self.shader_module, None);
+        }
+    }
+}
+
+// Work groups needed to cover size threads with groups of group_size
+pub fn dispatch_size(size: u32, group_size: u32) -> u32 {
+    size.div_ceil(group_size)
+}
+
+#[derive(Debug)]
+pub enum ShaderSetError {
+    InvalidSpirv(io::Error),
+    Reflection(SpirvError),
+    Vk(vk::Result),
+    // Two stages declare the same set and binding with different types
+    BindingMismatch {
+        set: u32,
+        binding: u32,
+    },
+    UnknownBinding {
+        set: u32,
+        binding: u32,
+    },
+    WrongResource {
+        set: u32,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+    },
+    Unbound {
+        set: u32,
+        binding: u32,
+    },
+}
+
+impl fmt::Display for ShaderSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderSetError::InvalidSpirv(err) => write!(f, "Invalid SPIR-V: {}", err),
+            ShaderSetError::Reflection(err) => write!(f, "{}", err),
+            ShaderSetError::Vk(result) => write!(f, "Vulkan error: {:?}", result),
+            ShaderSetError::BindingMismatch { set, binding } => {
+                write!(
+                    f,
+                    "Stages disagree on the type of set {} binding {}",
+                    set, binding
+                )
+            }
+            ShaderSetError::UnknownBinding { set, binding } => {
+                write!(f, "Shaders don't use set {} binding {}", set, binding)
+            }
+            ShaderSetError::WrongResource {
+                set,
+                binding,
+                descriptor_type,
+            } => write!(
+                f,
+                "Set {} binding {} is {:?}, bound the wrong kind of resource",
+                set, binding, descriptor_type
+            ),
+            ShaderSetError::Unbound { set, binding } => {
+                write!(f, "Set {} binding {} wasn't bound", set, binding)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShaderSetError {}
+
+impl From<vk::Result> for ShaderSetError {
+    fn from(result: vk::Result) -> Self {
+        ShaderSetError::Vk(result)
+    }
+}
+
+impl From<SpirvError> for ShaderSetError {
+    fn from(err: SpirvError) -> Self {
+        ShaderSetError::Reflection(err)
+    }
+}
+
+fn is_buffer_descriptor(descriptor_type: vk::DescriptorType) -> bool {
+    matches!(
+        descriptor_type,
+        vk::DescriptorType::UNIFORM_BUFFER
+            | vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            | vk::DescriptorType::STORAGE_BUFFER
+            | vk::DescriptorType::STORAGE_BUFFER_DYNAMIC
+    )
+}
+
+fn is_image_descriptor(descriptor_type: vk::DescriptorType) -> bool {
+    matches!(
+        descriptor_type,
+        vk::DescriptorType::SAMPLER
+            | vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            | vk::DescriptorType::SAMPLED_IMAGE
+            | vk::DescriptorType::STORAGE_IMAGE
+            | vk::DescriptorType::INPUT_ATTACHMENT
+    )
+}
+
+// Shader modules of one pipeline with the descriptor set layouts, push constant range and
+// vertex inputs read from their SPIR-V, so layouts can't drift from the shaders. Stages
+// sharing a binding must agree on its type, push constants are one block at offset 0
+// visible to every stage that declares it.
+pub struct ShaderSet {
+    pub modules: Vec<(vk::ShaderStageFlags, vk::ShaderModule)>,
+    pub bindings: Vec<Vec<vk::DescriptorSetLayoutBinding<'static>>>, // Per set
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub push_constant_range: Option<vk::PushConstantRange>,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub vertex_inputs: Vec<ReflectedInput>,
+}
+
+impl ShaderSet {
+    pub fn from_spv(device: &Device, spvs: &[&[u8]]) -> Result<ShaderSet, ShaderSetError> {
+        ShaderSet::from_spv_with_dynamic(device, spvs, &[])
+    }
+
+    // SPIR-V doesn't tell dynamic uniform buffers apart, (set, binding) pairs in dynamic
+    // become UNIFORM_BUFFER_DYNAMIC
+    pub fn from_spv_with_dynamic(
+        device: &Device,
+        spvs: &[&[u8]],
+        dynamic: &[(u32, u32)],
+    ) -> Result<ShaderSet, ShaderSetError> {
+        let mut codes = Vec::new();
+        let mut reflections = Vec::new();
+        for spv in spvs {
+            let code = read_spv(&mut Cursor::new(spv)).map_err(ShaderSetError::InvalidSpirv)?;
+            reflections.push(reflect(&code)?);
+            codes.push(code);
+        }
+
+        // Merge the stages, sets without bindings in between get empty layouts
+        let mut bindings: Vec<Vec<vk::DescriptorSetLayoutBinding<'static>>> = Vec::new();
+        let mut push_constant_range: Option<vk::PushConstantRange> = None;
+        let mut vertex_inputs = Vec::new();
+        for reflection in &reflections {
+            for reflected in &reflection.bindings {
+                let set = reflected.set as usize;
+                if bindings.len() <= set {
+                    bindings.resize(set + 1, Vec::new());
+                }
+                let mut descriptor_type = reflected.descriptor_type;
+                if descriptor_type == vk::DescriptorType::UNIFORM_BUFFER
+                    && dynamic.contains(&(reflected.set, reflected.binding))
+                {
+                    descriptor_type = vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC;
+                }
+                match bindings[set]
+                    .iter_mut()
+                    .find(|existing| existing.binding == reflected.binding)
+                {
+                    Some(existing) if existing.descriptor_type != descriptor_type => {
+                        return Err(ShaderSetError::BindingMismatch {
+                            set: reflected.set,
+                            binding: reflected.binding,
+                        });
+                    }
+                    Some(existing) => {
+                        existing.stage_flags |= reflection.stage;
+                        existing.descriptor_count = existing.descriptor_count.max(reflected.count);
+                    }
+                    None => bindings[set].push(vk::DescriptorSetLayoutBinding {
+                        binding: reflected.binding,
+                        descriptor_type,
+                        descriptor_count: reflected.count,
+                        stage_flags: reflection.stage,
+                        ..Default::default()
+                    }),
+                }
+            }
+            if reflection.push_constant_size > 0 {
+                let range = push_constant_range.get_or_insert(vk::PushConstantRange::default());
+                range.stage_flags |= reflection.stage;
+                range.size = range.size.max(reflection.push_constant_size);
+            }
+            vertex_inputs.extend(reflection.vertex_inputs.iter().cloned());
+        }
+
+        let mut shader_set = ShaderSet {
+            modules: Vec::new(),
+            bindings,
+            set_layouts: Vec::new(),
+            push_constant_range,
+            pipeline_layout: vk::PipelineLayout::null(),
+            vertex_inputs,
+        };
+        // Partially created objects are destroyed on error
+        let created = shader_set.create_objects(device, &codes, &reflections);
+        if let Err(err) = created {
+            shader_set.destroy(device);
+            return Err(err);
+        }
+        Ok(shader_set)
+    }
+
+    fn create_objects(
+        &mut self,
+        device: &Device,
+        codes: &[Vec<u32>],
+        reflections: &[ShaderReflection],
+    ) -> Result<(), ShaderSetError> {
+        for (code, reflection) in codes.iter().zip(reflections) {
+            let shader_info = vk::ShaderModuleCreateInfo {
+                code_size: code.len() * 4,
+                p_code: code.as_ptr(),
+                ..Default::default()
+            };
+            let module = unsafe { device.create_shader_module(&shader_info, None) }?;
+            self.modules.push((reflection.stage, module));
+        }
+        for set_bindings in &self.bindings {
+            let descriptor_info = vk::DescriptorSetLayoutCreateInfo {
+                binding_count: set_bindings.len() as u32,
+                p_bindings: set_bindings.as_ptr(),
+                ..Default::default()
+            };
+            let set_layout =
+                unsafe { device.create_descriptor_set_layout(&descriptor_info, None) }?;
+            self.set_layouts.push(set_layout);
+        }
+        let mut layout = PipelineLayoutBuilder::new().set_layouts(&self.set_layouts);
+        if let Some(range) = self.push_constant_range {
+            layout = layout.push_constant_bytes(range.stage_flags, range.size);
+        }
+        self.pipeline_layout = layout.build(device)?;
+        Ok(())
+    }
+
+    // Stages and vertex inputs filled in, vertex inputs are read from one tightly packed
+    // per vertex buffer at binding 0
+    pub fn graphics_pipeline(&self) -> GraphicsPipelineBuilder {
+        let mut builder = GraphicsPipelineBuilder::new();
+        for &(stage, module) in &self.modules {
+            builder = builder.stage(stage, module);
+        }
+        if self.vertex_inputs.is_empty() {
+            return builder;
+        }
+        let mut stride = 0;
+        let attributes: Vec<vk::VertexInputAttributeDescription> = self
+            .vertex_inputs
+            .iter()
+            .map(|input| {
+                let offset = stride;
+                stride += format_block(input.format).map_or(0, |(_, _, bytes)| bytes);
+                vk::VertexInputAttributeDescription {
+                    location: input.location,
+                    binding: 0,
+                    format: input.format,
+                    offset,
+                }
+            })
+            .collect();
+        let binding = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        builder.vertex_input(binding, &attributes)
+    }
+
+    pub fn module(&self, stage: vk::ShaderStageFlags) -> Option<vk::ShaderModule> {
+        self.modules
+            .iter()
+            .find(|(module_stage, _)| *module_stage == stage)
+            .map(|&(_, module)| module)
+    }
+
+    // Writes for one descriptor set allocated with set_layouts[set]
+    pub fn bind(&self, set: u32, descriptor_set: vk::DescriptorSet) -> DescriptorBindings<'_> {
+        DescriptorBindings {
+            shader_set: self,
+            set,
+            descriptor_set,
+            buffers: Vec::new(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe {
+            if self.pipeline_layout != vk::PipelineLayout::null() {
+                device.destroy_pipeline_layout(self.pipeline_layout, None);
+                self.pipeline_layout = vk::PipelineLayout::null();
+            }
+            for set_layout in self.set_layouts.drain(..) {
+                device.destroy_descriptor_set_layout(set_layout, None);
+            }
+            for (_, module) in self.modules.drain(..) {
+                device.destroy_shader_module(module, None);
+            }
+        }
+    }
+}
+
+// Resources for the bindings of one descriptor set. write() checks them against the
+// reflected layout: every binding filled, buffers only at buffer bindings and images only
+// at image bindings.
+pub struct DescriptorBindings<'a> {
+    shader_set: &'a ShaderSet,
+    set: u32,
+    descriptor_set: vk::DescriptorSet,
+    buffers: Vec<(u32, vk::DescriptorBufferInfo)>,
+    images: Vec<(u32, vk::DescriptorImageInfo)>,
+}
+
+impl<'a> DescriptorBindings<'a> {
+    pub fn buffer(
+        mut self,
+        binding: u32,
+        info: vk::DescriptorBufferInfo,
+    ) -> DescriptorBindings<'a> {
+        self.buffers.push((binding, info));
+        self
+    }
+
+    pub fn typed_buffer<T: Pod>(
+        self,
+        binding: u32,
+        buffer: &TypedBuffer<T>,
+    ) -> DescriptorBindings<'a> {
+        self.buffer(binding, buffer.descriptor())
+    }
+
+    pub fn image(mut self, binding: u32, info: vk::DescriptorImageInfo) -> DescriptorBindings<'a> {
+        self.images.push((binding, info));
+        self
+    }
+
+    fn layout_binding(
+        &self,
+        binding: u32,
+    ) -> Result<&'a vk::DescriptorSetLayoutBinding<'static>, ShaderSetError> {
+        self.shader_set
+            .bindings
+            .get(self.set as usize)
+            .and_then(|bindings| bindings.iter().find(|layout| layout.binding == binding))
+            .ok_or(ShaderSetError::UnknownBinding {
+                set: self.set,
+                binding,
+            })
+    }
+
+    pub fn write(self, device: &Device) -> Result<(), ShaderSetError> {
+        let mut write_desc_sets = Vec::new();
+        for (binding, info) in &self.buffers {
+            let layout = self.layout_binding(*binding)?;
+            if !is_buffer_descriptor(layout.descriptor_type) {
+                return Err(ShaderSetError::WrongResource {
+                    set: self.set,
+                    binding: *binding,
+                    descriptor_type: layout.descriptor_type,
+                });
+            }
+            write_desc_sets.push(vk::WriteDescriptorSet {
+                dst_set: self.descriptor_set,
+                dst_binding: *binding,
+                descriptor_count: 1,
+                descriptor_type: layout.descriptor_type,
+                p_buffer_info: info,
+                ..Default::default()
+            });
+        }
+        for (binding, info) in &self.images {
+            let layout = self.layout_binding(*binding)?;
+            if !is_image_descriptor(layout.descriptor_type) {
+                return Err(ShaderSetError::WrongResource {
+                    set: self.set,
+                    binding: *binding,
+                    descriptor_type: layout.descriptor_type,
+                });
+            }
+            write_desc_sets.push(vk::WriteDescriptorSet {
+                dst_set: self.descriptor_set,
+                dst_binding: *binding,
+                descriptor_count: 1,
+                descriptor_type: layout.descriptor_type,
+                p_image_info: info,
+                ..Default::default()
+            });
+        }
+
+        let set_bindings = self.shader_set.bindings.get(self.set as usize);
+        for layout in set_bindings.into_iter().flatten() {
+            let bound = write_desc_sets
+                .iter()
+                .any(|write| write.dst_binding == layout.binding);
+            if !bound {
+                return Err(ShaderSetError::Unbound {
+                    set: self.set,
+                    binding: layout.binding,
+                });
+            }
+        }
+
+        unsafe { device.update_descriptor_sets(&write_desc_sets, &[]) };
+        Ok(())
     }
 }
@@ -0,0 +1,44 @@
+// The library API the examples use, exercised from outside the crate
+
+use rust_test::brick_cache::*;
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::svosdf::*;
+
+#[test]
+fn svo_from_sphere_matches_source() {
+    let sdf = generate_sphere(32, 0.6, 0.1);
+    let svo_sdf = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    assert!(!svo_sdf.bricks.is_empty());
+    assert!(
+        svo_sdf.bricks.len() < 64,
+        "Uniform bricks should be left out"
+    );
+
+    let compare = svo_sdf.compare(&SdfLod::new(&sdf, 0, LodFilter::Point), 0.01);
+    assert_eq!(compare.missing_surface, 0);
+    assert_eq!(compare.error.max, 0.0);
+
+    // Surface voxel on the +x axis
+    let brick = svo_sdf.brick_at(UVec3::new(25, 16, 16)).unwrap();
+    assert_eq!(brick.size, 8);
+}
+
+#[test]
+fn svo_save_load_and_sample() {
+    let sdf = generate_sphere(32, 0.6, 0.1);
+    let svo_sdf = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    let path = std::env::temp_dir().join(format!("api_smoke_{}.svosdf", std::process::id()));
+    svo_sdf.save(path.to_str().unwrap()).unwrap();
+
+    let loaded = SvoSdf::load(path.to_str().unwrap());
+    let voxel = UVec3::new(25, 16, 16);
+    let sampled = BrickCache::open(&path, 16).and_then(|mut cache| cache.sample(voxel));
+    std::fs::remove_file(&path).unwrap();
+
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.header, svo_sdf.header);
+    assert_eq!(loaded.bricks.len(), svo_sdf.bricks.len());
+    let index = (25 + 16 * 32 + 16 * 32 * 32) as usize;
+    assert_eq!(sampled.unwrap(), Some(sdf.voxels[index]));
+}
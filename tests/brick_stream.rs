@@ -0,0 +1,103 @@
+// Brick directories sent by a content server: directory_from_bytes reads back what
+// directory_to_bytes wrote and rejects brick sizes the directory can't hold, before their
+// voxel bytes are computed. The server closes connections whose request head doesn't end
+// within its header limit.
+
+use rust_test::brick_cache::*;
+use rust_test::brick_stream::*;
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::svosdf::*;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+
+fn directory(brick_size: u32, sizes: &[u32]) -> BrickDirectory {
+    let header = SdfHeader {
+        dim: (64, 64, 64),
+        box_min: (0.0, 0.0, 0.0),
+        spacing: (1.0, 1.0, 1.0),
+    };
+    let entries = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, &size)| BrickEntry {
+            offset: 0,
+            size,
+            position: UVec3::new(i as u32 * 8, 0, 0),
+            checksum: Some(i as u64),
+        })
+        .collect();
+    BrickDirectory::new(header, brick_size, entries)
+}
+
+#[test]
+fn directory_round_trip() {
+    let bytes = directory_to_bytes(&directory(8, &[8, 8, 5]));
+    let directory = directory_from_bytes(&bytes).unwrap();
+    assert_eq!(directory.brick_size, 8);
+    let sizes: Vec<u32> = directory.entries.iter().map(|entry| entry.size).collect();
+    assert_eq!(sizes, [8, 8, 5]);
+    assert_eq!(directory.entries[2].checksum, Some(2));
+    assert_eq!(directory.entries[2].data_bytes(), 5 * 5 * 5 * 2);
+
+    assert!(directory_from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+// Overwrites the brick size and the size of the only entry of a directory, sizes that
+// BrickDirectory::new couldn't grid
+fn with_sizes(mut bytes: Vec<u8>, brick_size: u32, size: u32) -> Vec<u8> {
+    let header_bytes = bytes.len() - 12 - 24;
+    bytes[header_bytes..header_bytes + 4].copy_from_slice(&brick_size.to_le_bytes());
+    bytes[header_bytes + 12..header_bytes + 16].copy_from_slice(&size.to_le_bytes());
+    bytes
+}
+
+#[test]
+fn oversized_bricks_rejected() {
+    let larger_than_brick_size = directory_to_bytes(&directory(8, &[8, 9]));
+    assert!(directory_from_bytes(&larger_than_brick_size).is_err());
+
+    // Would overflow when cubed
+    let bytes = directory_to_bytes(&directory(8, &[8]));
+    assert!(directory_from_bytes(&with_sizes(bytes.clone(), u32::MAX, u32::MAX)).is_err());
+    let above_max = with_sizes(bytes.clone(), MAX_BRICK_SIZE + 1, MAX_BRICK_SIZE + 1);
+    assert!(directory_from_bytes(&above_max).is_err());
+    assert!(directory_from_bytes(&with_sizes(bytes, MAX_BRICK_SIZE, MAX_BRICK_SIZE)).is_ok());
+}
+
+fn serve_sphere() -> (String, PathBuf) {
+    let svo_sdf = SvoSdf::from_sdf(&generate_sphere(16, 0.6, 0.1), 4, 8, 0.004);
+    let path = std::env::temp_dir().join(format!("brick_stream_{}.svosdf", std::process::id()));
+    svo_sdf.save(path.to_str().unwrap()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let served = path.clone();
+    std::thread::spawn(move || serve(listener, &served));
+    (address, path)
+}
+
+#[test]
+fn server_rejects_long_request_heads() {
+    let (address, path) = serve_sphere();
+
+    let mut stream = TcpStream::connect(&address).unwrap();
+    stream
+        .write_all(b"GET /directory HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut status = [0u8; 15];
+    stream.read_exact(&mut status).unwrap();
+    assert_eq!(&status, b"HTTP/1.1 200 OK");
+
+    // A request line that never ends is cut off at the header limit and the connection closed
+    // without a response
+    let mut stream = TcpStream::connect(&address).unwrap();
+    let _ = stream.write_all(&[b'a'; 16 * 1024]);
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    assert!(response.is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
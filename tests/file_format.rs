@@ -0,0 +1,126 @@
+use rust_test::file_format::*;
+use rust_test::sdf::*;
+use rust_test::svosdf::*;
+use rust_test::thumbnail::*;
+
+use miniz_oxide::deflate::compress_to_vec;
+use rust_test::serialization::Loader;
+
+fn sdf_bytes(sdf: &Sdf) -> Vec<u8> {
+    let mut bytes = sdf.header.to_bytes();
+    for voxel in &sdf.voxels {
+        bytes.extend_from_slice(&voxel.to_le_bytes());
+    }
+    bytes
+}
+
+#[test]
+fn sdf_header_bytes_round_trip() {
+    let header = SdfHeader {
+        dim: (3, 4, 5),
+        box_min: (-1.5, 0.0, 2.25),
+        spacing: (0.5, 0.25, 1.0),
+    };
+    let bytes = header.to_bytes();
+    assert_eq!(bytes.len(), SDF_HEADER_BYTES);
+    assert_eq!(SdfHeader::load(&mut Loader::new(), &bytes), header);
+    assert_eq!(SdfHeader::read(&mut &bytes[..]).unwrap(), header);
+}
+
+#[test]
+fn sdf_header_version1_has_isotropic_spacing() {
+    let mut bytes = Vec::new();
+    for value in [8u32, 9, 10] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [0.0f32, 1.0, 2.0, 0.125] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    assert_eq!(bytes.len(), SDF_HEADER_V1_BYTES);
+    let header = SdfHeader::read(&mut &bytes[..]).unwrap();
+    assert_eq!(header.dim, (8, 9, 10));
+    assert_eq!(header.spacing, (0.125, 0.125, 0.125));
+}
+
+#[test]
+fn sdf_header_json_round_trip() {
+    let header = generate_sphere(4, 0.6, 0.1).header;
+    let json = serde_json::to_string(&header).unwrap();
+    assert_eq!(serde_json::from_str::<SdfHeader>(&json).unwrap(), header);
+}
+
+#[test]
+fn sdf_file_info_raw_and_compressed() {
+    let sdf = generate_sphere(8, 0.6, 0.1);
+    let bytes = sdf_bytes(&sdf);
+
+    let raw = SdfFileInfo::parse(&bytes).unwrap();
+    assert_eq!(raw.version, 2);
+    assert!(!raw.compressed);
+    assert_eq!(raw.header, sdf.header);
+    assert_eq!(raw.voxels.offset, SDF_HEADER_BYTES as u64);
+    assert_eq!(raw.voxels.bytes, 8 * 8 * 8 * 2);
+
+    let compressed = SdfFileInfo::parse(&compress_to_vec(&bytes, 5)).unwrap();
+    assert!(compressed.compressed);
+    assert_eq!(compressed.voxels, raw.voxels);
+
+    let info = FileInfo::Sdf(raw);
+    let parsed: FileInfo = serde_json::from_str(&info.to_json()).unwrap();
+    assert_eq!(parsed, info);
+}
+
+#[test]
+fn sdf_file_info_rejects_truncated_files() {
+    let bytes = sdf_bytes(&generate_sphere(8, 0.6, 0.1));
+    assert!(SdfFileInfo::parse(&bytes[..bytes.len() - 2]).is_err());
+    assert!(SdfFileInfo::parse(&bytes[..10]).is_err());
+}
+
+#[test]
+fn svosdf_file_info_sections_cover_the_file() {
+    let mut svo_sdf = SvoSdf::from_sdf(&generate_sphere(16, 0.6, 0.1), 4, 8, 0.004);
+    svo_sdf.thumbnail = Some(Thumbnail::project(&svo_sdf, 16));
+    let path = std::env::temp_dir().join(format!("file_format_{}.svosdf", std::process::id()));
+    svo_sdf.save(path.to_str().unwrap()).unwrap();
+    let file_bytes = std::fs::metadata(&path).unwrap().len();
+    let info = FileInfo::load(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let info = match info.unwrap() {
+        FileInfo::SvoSdf(info) => info,
+        other => panic!("Expected an svosdf, got {:?}", other),
+    };
+    assert_eq!(info.header, svo_sdf.header);
+    assert_eq!(info.brick_size, 4);
+    assert_eq!(info.brick_count as usize, svo_sdf.bricks.len());
+    assert!(info.brick_count > 0);
+
+    let sections = &info.sections;
+    let brick_ranges = sections.brick_ranges.unwrap();
+    let brick_checksums = sections.brick_checksums.unwrap();
+    let thumbnail = sections.thumbnail.unwrap();
+    let order = [
+        sections.header,
+        sections.bricks,
+        sections.octree,
+        brick_ranges,
+        brick_checksums,
+        thumbnail,
+    ];
+    assert_eq!(order[0].offset, 0);
+    for pair in order.windows(2) {
+        assert_eq!(pair[0].offset + pair[0].bytes, pair[1].offset);
+    }
+    assert_eq!(thumbnail.offset + thumbnail.bytes, file_bytes);
+    assert_eq!(brick_ranges.bytes, info.brick_count as u64 * 4);
+    assert_eq!(brick_checksums.bytes, info.brick_count as u64 * 8 + 8);
+    assert_eq!(
+        info.thumbnail_size,
+        svo_sdf.thumbnail.as_ref().map(|t| (t.width, t.height))
+    );
+
+    let info = FileInfo::SvoSdf(info);
+    let parsed: FileInfo = serde_json::from_str(&info.to_json()).unwrap();
+    assert_eq!(parsed, info);
+}
@@ -0,0 +1,227 @@
+// End to end: an analytic sphere is voxelized, converted to an SVO, saved and loaded, sampled
+// against the analytic distance and rendered by rendersvosdf batch. The render part needs a
+// Vulkan device and only runs with RUST_TEST_GPU=1. It compares a perceptual hash of the frame
+// with tests/golden/golden_path.dhash, RUST_TEST_BLESS=1 writes the hash of the current frame
+// there instead (on a reference GPU, after intended rendering changes).
+
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::svosdf::*;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DIM: u32 = 64;
+const SPACING: f32 = 0.5;
+const CENTER: Vec3 = Vec3 {
+    x: 16.0,
+    y: 16.0,
+    z: 16.0,
+};
+const RADIUS: f32 = 10.0;
+
+const SAMPLES: usize = 1000;
+// Trilinear filtering error on the sphere's curvature and the u16 quantization step
+const SAMPLE_TOLERANCE: f32 = 0.02;
+
+// Differing bits of the 64-bit dHash that still count as the same image
+const MAX_HASH_DISTANCE: u32 = 6;
+
+const SPHERE: SdfShape = SdfShape::Sphere {
+    center: CENTER,
+    radius: RADIUS,
+};
+
+fn analytic_distance(p: Vec3) -> f32 {
+    SPHERE.distance(p)
+}
+
+fn analytic_sdf() -> Sdf {
+    let header = SdfHeader {
+        dim: (DIM, DIM, DIM),
+        box_min: (0.0, 0.0, 0.0),
+        spacing: (SPACING, SPACING, SPACING),
+    };
+    generate(header, &SPHERE)
+}
+
+fn voxel(svo_sdf: &SvoSdf, v: UVec3) -> Option<u16> {
+    let brick = svo_sdf.brick_at(v)?;
+    let local = (v - brick.position).linear_index(UVec3::splat(brick.size));
+    Some(brick.data[local])
+}
+
+// Trilinear world space distance from the brick voxels, None outside the bricks. Voxel values
+// are at the voxel centers.
+fn sample(svo_sdf: &SvoSdf, p: Vec3) -> Option<f32> {
+    let g = p * (1.0 / SPACING) - Vec3::from_scalar(0.5);
+    let base = UVec3::new(g.x.floor() as u32, g.y.floor() as u32, g.z.floor() as u32);
+    let f = g - Vec3 {
+        x: base.x as f32,
+        y: base.y as f32,
+        z: base.z as f32,
+    };
+    let step = svo_sdf.header.distance_per_step();
+    let mut distance = 0.0;
+    for corner in 0..8 {
+        let offset = UVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2);
+        let weight = |o: u32, t: f32| if o == 1 { t } else { 1.0 - t };
+        let w = weight(offset.x, f.x) * weight(offset.y, f.y) * weight(offset.z, f.z);
+        let value = voxel(svo_sdf, base + offset)?;
+        distance += w * (value as i32 - LEVEL_ZERO as i32) as f32 * step;
+    }
+    Some(distance)
+}
+
+// Points within a few voxels of the surface, where the bricks are
+fn near_surface_points(count: usize) -> Vec<Vec3> {
+    let mut rng = StdRng::seed_from_u64(0x5df);
+    (0..count)
+        .map(|_| {
+            let direction = Vec3 {
+                x: rng.gen_range(-1.0, 1.0),
+                y: rng.gen_range(-1.0, 1.0),
+                z: rng.gen_range(-1.0, 1.0),
+            };
+            let direction = if direction.length() < 1e-3 {
+                Vec3 {
+                    x: 1.0,
+                    y: 0.0,
+                    z: 0.0,
+                }
+            } else {
+                direction.normalize()
+            };
+            CENTER + direction * (RADIUS + rng.gen_range(-1.5, 1.5))
+        })
+        .collect()
+}
+
+#[test]
+fn golden_path() {
+    let sdf = analytic_sdf();
+    let svo_sdf = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    assert!(!svo_sdf.bricks.is_empty());
+
+    let path = env::temp_dir().join(format!("golden_path_{}.svosdf", std::process::id()));
+    svo_sdf.save(path.to_str().unwrap()).unwrap();
+    let loaded = SvoSdf::load(path.to_str().unwrap());
+    let loaded = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            fs::remove_file(&path).unwrap();
+            panic!("Loading {} failed: {}", path.display(), err);
+        }
+    };
+    assert_eq!(loaded.header, svo_sdf.header);
+    assert_eq!(loaded.bricks.len(), svo_sdf.bricks.len());
+    for (a, b) in loaded.bricks.iter().zip(&svo_sdf.bricks) {
+        assert_eq!((a.position, a.size), (b.position, b.size));
+        assert!(a.data == b.data, "brick at {:?} differs", a.position);
+    }
+
+    for p in near_surface_points(SAMPLES) {
+        let truth = analytic_distance(p);
+        let sampled = sample(&loaded, p).unwrap_or_else(|| panic!("{:?} isn't in a brick", p));
+        assert!(
+            (sampled - truth).abs() <= SAMPLE_TOLERANCE,
+            "{:?}: sampled {} vs analytic {}",
+            p,
+            sampled,
+            truth
+        );
+    }
+
+    let render = env::var_os("RUST_TEST_GPU").is_some() && render_and_check_hash(&path);
+    fs::remove_file(&path).unwrap();
+    if !render {
+        println!("golden_path: render skipped, set RUST_TEST_GPU=1 to render on the GPU");
+    }
+}
+
+// Renders the volume from the front with rendersvosdf batch and checks the frame's hash
+fn render_and_check_hash(volume: &Path) -> bool {
+    let dir = env::temp_dir().join(format!("golden_path_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("frame.png");
+    let jobs = serde_json::json!([{
+        "volume": volume,
+        "camera": {
+            "position": [CENTER.x, CENTER.y, 60.0],
+            "direction": [0.0, 0.0, -1.0]
+        },
+        "resolution": [256, 256],
+        "output": output
+    }]);
+    let jobs_path = dir.join("jobs.json");
+    fs::write(&jobs_path, jobs.to_string()).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_rendersvosdf"))
+        .arg("batch")
+        .arg(&jobs_path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("rendersvosdf didn't start");
+    assert!(status.success(), "rendersvosdf batch failed: {}", status);
+
+    let image = image::open(&output).expect("rendered frame").to_luma8();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The sphere is in front of the background
+    let (width, height) = image.dimensions();
+    assert_ne!(
+        image.get_pixel(width / 2, height / 2),
+        image.get_pixel(0, 0),
+        "the sphere isn't in the frame"
+    );
+
+    let hash = dhash(&image);
+    let golden_path = golden_hash_path();
+    if env::var_os("RUST_TEST_BLESS").is_some() {
+        fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        fs::write(&golden_path, format!("{:016x}\n", hash)).unwrap();
+        println!(
+            "golden_path: wrote {:016x} to {}",
+            hash,
+            golden_path.display()
+        );
+        return true;
+    }
+    let golden = fs::read_to_string(&golden_path).unwrap_or_else(|_| {
+        panic!(
+            "{} is missing, run with RUST_TEST_BLESS=1 on a reference GPU to create it",
+            golden_path.display()
+        )
+    });
+    let golden = u64::from_str_radix(golden.trim(), 16).expect("hex hash");
+    let distance = (hash ^ golden).count_ones();
+    assert!(
+        distance <= MAX_HASH_DISTANCE,
+        "frame hash {:016x} is {} bits from the golden {:016x}",
+        hash,
+        distance,
+        golden
+    );
+    true
+}
+
+fn golden_hash_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/golden_path.dhash")
+}
+
+// Difference hash: one bit per horizontal neighbor pair of a 9x8 downscale, brighter left
+// pixel = 1. Stable under small shading, filtering and driver differences.
+fn dhash(image: &image::GrayImage) -> u64 {
+    let small = image::imageops::resize(image, 9, 8, image::imageops::FilterType::Triangle);
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    hash
+}
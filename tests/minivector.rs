@@ -0,0 +1,183 @@
+// Mat4x4 inverse and decompose, including the singular and degenerate matrices they reject,
+// and screen rays of both depth conventions
+
+use rust_test::minivector::*;
+use rust_test::projection_settings::DepthConvention;
+
+fn components(m: &Mat4x4) -> [f32; 16] {
+    let mut out = [0.0; 16];
+    for (i, r) in [m.r0, m.r1, m.r2, m.r3].iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&[r.x, r.y, r.z, r.w]);
+    }
+    out
+}
+
+fn assert_mat4_near(a: &Mat4x4, b: &Mat4x4, tolerance: f32) {
+    let (a, b) = (components(a), components(b));
+    for i in 0..16 {
+        assert!(
+            (a[i] - b[i]).abs() <= tolerance,
+            "component {}: {} vs {}",
+            i,
+            a[i],
+            b[i]
+        );
+    }
+}
+
+fn assert_vec3_near(a: Vec3, b: Vec3, tolerance: f32) {
+    assert!((a - b).length() <= tolerance, "{:?} vs {:?}", a, b);
+}
+
+fn vec3(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3 { x, y, z }
+}
+
+fn rotation() -> Quat {
+    Quat::from_axis_angle(vec3(1.0, 1.0, 0.3).normalize(), 0.7)
+}
+
+// Scale, then rotation, then translation like Transform::to_mat4, per-axis scale
+fn trs(translation: Vec3, rotation: Quat, scale: Vec3) -> Mat4x4 {
+    let mut s = identity();
+    s.r0.x = scale.x;
+    s.r1.y = scale.y;
+    s.r2.z = scale.z;
+    s * rotation.to_mat4() * Transform::from_translation(translation).to_mat4()
+}
+
+#[test]
+fn inverse_of_transforms() {
+    let camera = view(
+        vec3(3.0, -2.0, 5.0),
+        vec3(-0.3, 0.4, -1.0),
+        vec3(0.0, 1.0, 0.0),
+    );
+    let matrices = [
+        identity(),
+        trs(vec3(1.0, 2.0, 3.0), rotation(), vec3(2.0, 0.5, 3.0)),
+        Transform::new(vec3(-4.0, 0.0, 9.0), rotation(), 0.001).to_mat4(),
+        camera * projection(1.2, 16.0 / 9.0, 0.1, 1000.0),
+        camera * orthographic(8.0, 4.0 / 3.0, 0.1, 100.0),
+    ];
+    for m in matrices.iter() {
+        let inv = inverse(*m).expect("invertible");
+        assert_mat4_near(&(inv * *m), &identity(), 1e-4);
+        assert_mat4_near(&inverse_or_identity(*m), &inv, 0.0);
+    }
+}
+
+#[test]
+fn inverse_rejects_singular_matrices() {
+    let mut zero_scale = identity();
+    zero_scale.r1.y = 0.0;
+    let mut duplicate_rows = identity();
+    duplicate_rows.r1 = duplicate_rows.r0;
+    let mut not_finite = identity();
+    not_finite.r2.z = f32::NAN;
+    let zeros = Mat4x4 {
+        r0: Vec4 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 0.0,
+        },
+        ..identity()
+    };
+
+    let matrices = [
+        zero_scale,
+        // Rounding leaves a tiny determinant, multiplying back catches it
+        rotation().to_mat4() * zero_scale * rotation().to_mat4(),
+        rotation().to_mat4() * duplicate_rows * rotation().to_mat4(),
+        Transform::new(vec3(1.0, 2.0, 3.0), rotation(), 0.0).to_mat4(),
+        zeros,
+        not_finite,
+    ];
+    for (i, m) in matrices.iter().enumerate() {
+        assert!(inverse(*m).is_none(), "matrix {} is singular", i);
+        assert_mat4_near(&inverse_or_identity(*m), &identity(), 0.0);
+    }
+    assert_eq!(determinant(zero_scale), 0.0);
+}
+
+#[test]
+fn decompose_round_trip() {
+    let cases = [
+        (vec3(0.0, 0.0, 0.0), Quat::IDENTITY, vec3(1.0, 1.0, 1.0)),
+        (vec3(1.0, -2.0, 3.0), rotation(), vec3(2.5, 2.5, 2.5)),
+        (vec3(-7.0, 0.5, 100.0), rotation(), vec3(0.25, 4.0, 1.5)),
+        (
+            vec3(0.0, 1.0, 0.0),
+            Quat::from_yaw_pitch(2.5, -1.0),
+            vec3(0.01, 100.0, 1.0),
+        ),
+    ];
+    for &(translation, rotation, scale) in cases.iter() {
+        let m = trs(translation, rotation, scale);
+        let (t, r, s) = decompose(m).expect("affine TRS");
+        assert_vec3_near(t, translation, 1e-4);
+        assert_vec3_near(s, scale, scale.length() * 1e-5);
+        // q and -q are the same rotation
+        assert!(
+            r.dot(rotation).abs() > 1.0 - 1e-5,
+            "{:?} vs {:?}",
+            r,
+            rotation
+        );
+        assert_mat4_near(&trs(t, r, s), &m, 1e-3);
+    }
+
+    let transform = Transform::new(vec3(4.0, 5.0, 6.0), rotation(), 3.0);
+    let (t, _, s) = decompose(transform.to_mat4()).unwrap();
+    assert_vec3_near(t, transform.translation, 1e-5);
+    assert_vec3_near(s, Vec3::from_scalar(3.0), 1e-5);
+}
+
+#[test]
+fn decompose_mirrored() {
+    let m = trs(vec3(1.0, 2.0, 3.0), rotation(), vec3(2.0, -1.0, 0.5));
+    let (t, r, s) = decompose(m).unwrap();
+    // The mirroring lands on x, the recomposed matrix is the same
+    assert!(s.x < 0.0 && s.y > 0.0 && s.z > 0.0, "{:?}", s);
+    assert_mat4_near(&trs(t, r, s), &m, 1e-4);
+}
+
+#[test]
+fn decompose_rejects_degenerate_matrices() {
+    let mut zero_scale = trs(vec3(1.0, 2.0, 3.0), rotation(), vec3(1.0, 1.0, 1.0));
+    zero_scale.r2 = Vec4 {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        w: 0.0,
+    };
+    let mut shear = identity();
+    shear.r1.x = 0.5;
+    let mut not_finite = identity();
+    not_finite.r0.x = f32::INFINITY;
+
+    assert!(decompose(zero_scale).is_none());
+    assert!(decompose(trs(vec3(1.0, 0.0, 0.0), rotation(), vec3(1.0, 0.0, 1.0))).is_none());
+    assert!(decompose(shear).is_none());
+    assert!(decompose(projection(1.2, 1.0, 0.1, 100.0)).is_none());
+    assert!(decompose(not_finite).is_none());
+}
+
+#[test]
+fn ray_from_screen_in_both_depth_conventions() {
+    let eye = vec3(1.0, 2.0, -5.0);
+    let view = Mat4x4::look_at(eye, vec3(1.0, 2.0, 0.0), vec3(0.0, 1.0, 0.0));
+    let ndc = Vec2 { x: 0.5, y: -0.25 };
+    for depth in [DepthConvention::Reversed, DepthConvention::Standard] {
+        let perspective = view * depth.projection(1.2, 1.0, 0.5, 100.0);
+        let ray = Ray::from_screen(&perspective, ndc, depth.near_depth(), depth.clear_depth());
+        assert_vec3_near(ray.origin, eye + ray.dir * (0.5 / ray.dir.z), 1e-3);
+        assert!(ray.dir.z > 0.0, "{:?} {:?}", depth, ray);
+
+        let ortho = view * depth.orthographic(4.0, 4.0, 0.5, 100.0);
+        let ray = Ray::from_screen(&ortho, ndc, depth.near_depth(), depth.clear_depth());
+        assert_vec3_near(ray.dir, vec3(0.0, 0.0, 1.0), 1e-4);
+        assert_vec3_near(ray.origin, vec3(2.0, 2.5, -4.5), 1e-3);
+    }
+}
@@ -0,0 +1,125 @@
+// The SSE/NEON matrix operations against the scalar versions, run with
+// cargo test --features simd
+#![cfg(feature = "simd")]
+
+use rust_test::minivector::*;
+use rust_test::minivector_simd;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+fn random_vec4(rng: &mut StdRng) -> Vec4 {
+    Vec4 {
+        x: rng.gen_range(-10.0, 10.0),
+        y: rng.gen_range(-10.0, 10.0),
+        z: rng.gen_range(-10.0, 10.0),
+        w: rng.gen_range(-10.0, 10.0),
+    }
+}
+
+fn random_mat4(rng: &mut StdRng) -> Mat4x4 {
+    Mat4x4 {
+        r0: random_vec4(rng),
+        r1: random_vec4(rng),
+        r2: random_vec4(rng),
+        r3: random_vec4(rng),
+    }
+}
+
+fn components(m: &Mat4x4) -> [f32; 16] {
+    let mut out = [0.0; 16];
+    for (i, r) in [m.r0, m.r1, m.r2, m.r3].iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&[r.x, r.y, r.z, r.w]);
+    }
+    out
+}
+
+fn assert_mat4_near(a: &Mat4x4, b: &Mat4x4, tolerance: f32) {
+    let (a, b) = (components(a), components(b));
+    for i in 0..16 {
+        let scale = a[i].abs().max(b[i].abs()).max(1.0);
+        assert!(
+            (a[i] - b[i]).abs() <= tolerance * scale,
+            "component {}: {} vs {}",
+            i,
+            a[i],
+            b[i]
+        );
+    }
+}
+
+// Matrices of the renderer, the inverse of these is well conditioned
+fn transform_matrices() -> Vec<Mat4x4> {
+    let camera = view(
+        Vec3 {
+            x: 3.0,
+            y: -2.0,
+            z: 5.0,
+        },
+        Vec3 {
+            x: -0.3,
+            y: 0.4,
+            z: -1.0,
+        },
+        Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        },
+    );
+    vec![
+        identity(),
+        camera,
+        camera * projection(1.2, 16.0 / 9.0, 0.1, 1000.0),
+        camera * orthographic(8.0, 4.0 / 3.0, 0.1, 100.0),
+    ]
+}
+
+#[test]
+fn mat4_product_matches_scalar() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for _ in 0..1000 {
+        let (a, b) = (random_mat4(&mut rng), random_mat4(&mut rng));
+        let simd = minivector_simd::mul_mat4(a, b);
+        assert_eq!(components(&simd), components(&mul_mat4_scalar(a, b)));
+        assert_eq!(components(&(a * b)), components(&simd));
+    }
+}
+
+#[test]
+fn vec4_transform_matches_scalar() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for _ in 0..1000 {
+        let (v, m) = (random_vec4(&mut rng), random_mat4(&mut rng));
+        let simd = minivector_simd::mul_vec4_mat4(v, m);
+        let scalar = mul_vec4_mat4_scalar(v, m);
+        assert_eq!(
+            [simd.x, simd.y, simd.z, simd.w],
+            [scalar.x, scalar.y, scalar.z, scalar.w]
+        );
+    }
+}
+
+#[test]
+fn inverse_matches_scalar() {
+    let mut rng = StdRng::seed_from_u64(3);
+    for m in transform_matrices() {
+        assert_mat4_near(&minivector_simd::inverse(m), &inverse_scalar(m), 1e-5);
+    }
+    for _ in 0..1000 {
+        let m = random_mat4(&mut rng);
+        let scalar = inverse_scalar(m);
+        // Ill conditioned random matrices legitimately differ by rounding
+        let conditioned = components(&scalar).iter().all(|c| c.abs() < 10.0);
+        if conditioned {
+            assert_mat4_near(&minivector_simd::inverse(m), &scalar, 1e-3);
+        }
+    }
+}
+
+#[test]
+fn inverse_times_matrix_is_identity() {
+    for m in transform_matrices() {
+        assert_mat4_near(&(minivector_simd::inverse(m) * m), &identity(), 1e-4);
+        assert_mat4_near(&(inverse(m).unwrap() * m), &identity(), 1e-4);
+    }
+}
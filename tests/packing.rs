@@ -0,0 +1,130 @@
+// The packing encodings round to the nearest code, clamp out of range values and give the
+// codes back unchanged through their unpack functions. Half floats follow IEEE rounding,
+// including subnormals, overflow to infinity and NaN.
+
+use rust_test::minivector::*;
+use rust_test::packing::*;
+
+fn vec4(x: f32, y: f32, z: f32, w: f32) -> Vec4 {
+    Vec4 { x, y, z, w }
+}
+
+#[test]
+fn unorm8_round_trip_and_clamp() {
+    for code in 0..=u8::MAX {
+        assert_eq!(pack_unorm8(unpack_unorm8(code)), code);
+    }
+    assert_eq!(pack_unorm8(0.0), 0);
+    assert_eq!(pack_unorm8(1.0), 255);
+    assert_eq!(pack_unorm8(0.5), 128);
+    assert_eq!(pack_unorm8(-0.5), 0);
+    assert_eq!(pack_unorm8(7.0), 255);
+}
+
+#[test]
+fn unorm16_round_trip_and_clamp() {
+    for code in 0..=u16::MAX {
+        assert_eq!(pack_unorm16(unpack_unorm16(code)), code);
+    }
+    assert_eq!(pack_unorm16(1.0), UNORM16_MAX as u16);
+    assert_eq!(pack_unorm16(32768.0 / UNORM16_MAX), 32768);
+    assert_eq!(pack_unorm16(-1.0), 0);
+    assert_eq!(pack_unorm16(2.0), u16::MAX);
+}
+
+#[test]
+fn snorm8x4_round_trip_and_clamp() {
+    // x in the lowest byte, two's complement codes
+    assert_eq!(pack_snorm8x4(vec4(1.0, -1.0, 0.0, 0.5)), 0x40_00_81_7f);
+    assert_eq!(pack_snorm8x4(vec4(3.0, -3.0, 0.0, 0.0)), 0x00_00_81_7f);
+
+    for code in -127..=127i8 {
+        let byte = code as u8 as u32;
+        let packed = byte | (byte << 8) | (byte << 16) | (byte << 24);
+        assert_eq!(pack_snorm8x4(unpack_snorm8x4(packed)), packed);
+    }
+    // -128 unpacks to -1 like -127 and packs back as -127
+    let v = unpack_snorm8x4(0x80);
+    assert_eq!(v.x, -1.0);
+    assert_eq!(pack_snorm8x4(v) & 0xff, 0x81);
+}
+
+#[test]
+fn r10g10b10a2_round_trip_and_clamp() {
+    let packed = pack_r10g10b10a2(vec4(1.0, 0.0, 0.5, 1.0));
+    assert_eq!(packed, 1023 | (512 << 20) | (3 << 30));
+    assert_eq!(
+        pack_r10g10b10a2(vec4(-1.0, 2.0, 0.0, 5.0)),
+        (1023 << 10) | (3 << 30)
+    );
+
+    for code in (0..1024u32).step_by(7) {
+        let w = code & 3;
+        let packed = code | ((1023 - code) << 10) | ((code / 2) << 20) | (w << 30);
+        assert_eq!(pack_r10g10b10a2(unpack_r10g10b10a2(packed)), packed);
+    }
+
+    let v = vec4(0.1, 0.6, 0.9, 0.4);
+    let unpacked = unpack_r10g10b10a2(pack_r10g10b10a2(v));
+    for (a, b) in [(v.x, unpacked.x), (v.y, unpacked.y), (v.z, unpacked.z)] {
+        assert!((a - b).abs() <= 0.5 / 1023.0, "{} vs {}", a, b);
+    }
+    assert_eq!(unpacked.w, 1.0 / 3.0);
+}
+
+#[test]
+fn f16_round_trip_of_every_half() {
+    for bits in 0..=u16::MAX {
+        let value = f16_to_f32(bits);
+        if value.is_nan() {
+            continue;
+        }
+        assert_eq!(f32_to_f16(value), bits, "{:#06x}", bits);
+    }
+}
+
+#[test]
+fn f16_rounding() {
+    assert_eq!(f32_to_f16(1.0), 0x3c00);
+    assert_eq!(f32_to_f16(-2.0), 0xc000);
+    assert_eq!(f32_to_f16(0.1), 0x2e66);
+    // Halfway between two halves rounds to the even one
+    assert_eq!(f32_to_f16(1.0 + 2.0f32.powi(-11)), 0x3c00);
+    assert_eq!(f32_to_f16(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3c02);
+    assert_eq!(f32_to_f16(0.0), 0);
+    assert_eq!(f32_to_f16(-0.0), 0x8000);
+}
+
+#[test]
+fn f16_subnormals() {
+    // Smallest and largest subnormal half
+    assert_eq!(f32_to_f16(2.0f32.powi(-24)), 0x0001);
+    assert_eq!(f32_to_f16(1023.0 * 2.0f32.powi(-24)), 0x03ff);
+    assert_eq!(f32_to_f16(2.0f32.powi(-14)), 0x0400);
+    assert_eq!(f16_to_f32(0x0001), 2.0f32.powi(-24));
+    assert_eq!(f16_to_f32(0x83ff), -1023.0 * 2.0f32.powi(-24));
+    // Half of the smallest subnormal ties to zero, above it rounds up
+    assert_eq!(f32_to_f16(2.0f32.powi(-25)), 0);
+    assert_eq!(f32_to_f16(1.5 * 2.0f32.powi(-25)), 0x0001);
+    // Too small for a half, including f32 subnormals, flushes to signed zero
+    assert_eq!(f32_to_f16(1e-10), 0);
+    assert_eq!(f32_to_f16(-1e-10), 0x8000);
+    assert_eq!(f32_to_f16(f32::MIN_POSITIVE / 2.0), 0);
+}
+
+#[test]
+fn f16_infinity_and_nan() {
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16(65519.0), 0x7bff);
+    assert_eq!(f32_to_f16(65520.0), 0x7c00);
+    assert_eq!(f32_to_f16(1e6), 0x7c00);
+    assert_eq!(f32_to_f16(f32::INFINITY), 0x7c00);
+    assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+    assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+    assert_eq!(f16_to_f32(0xfc00), f32::NEG_INFINITY);
+
+    let nan = f32_to_f16(f32::NAN);
+    assert_eq!(nan & 0x7c00, 0x7c00);
+    assert_ne!(nan & 0x03ff, 0);
+    assert!(f16_to_f32(nan).is_nan());
+}
@@ -0,0 +1,152 @@
+// sdf::generate rasterizes analytic shapes into the voxel grid, the encoded voxels decode back
+// to the analytic distances and the CSG combinators keep the expected inside/outside regions.
+
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+
+const DIM: u32 = 32;
+
+fn header() -> SdfHeader {
+    SdfHeader {
+        dim: (DIM, DIM, DIM),
+        box_min: (-1.0, -1.0, -1.0),
+        spacing: (2.0 / DIM as f32, 2.0 / DIM as f32, 2.0 / DIM as f32),
+    }
+}
+
+fn point(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3 { x, y, z }
+}
+
+fn voxel_center(header: &SdfHeader, x: u32, y: u32, z: u32) -> Vec3 {
+    point(
+        header.box_min.0 + (x as f32 + 0.5) * header.spacing.0,
+        header.box_min.1 + (y as f32 + 0.5) * header.spacing.1,
+        header.box_min.2 + (z as f32 + 0.5) * header.spacing.2,
+    )
+}
+
+#[test]
+fn voxels_decode_to_the_analytic_distance() {
+    let shape = SdfShape::Torus {
+        center: point(0.0, 0.0, 0.0),
+        major_radius: 0.5,
+        minor_radius: 0.2,
+    };
+    let header = header();
+    let sdf = generate(header, &shape);
+    assert_eq!(sdf.voxels.len(), (DIM * DIM * DIM) as usize);
+
+    let step = header.distance_per_step();
+    for z in 0..DIM {
+        for y in 0..DIM {
+            for x in 0..DIM {
+                let index = (x + y * DIM + z * DIM * DIM) as usize;
+                let decoded = (sdf.voxels[index] as f32 - LEVEL_ZERO as f32) * step;
+                let truth = shape.distance(voxel_center(&header, x, y, z));
+                assert!(
+                    (decoded - truth).abs() <= step,
+                    "({} {} {}): {}",
+                    x,
+                    y,
+                    z,
+                    decoded
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn primitives_are_negative_inside() {
+    let origin = point(0.0, 0.0, 0.0);
+    let primitives = [
+        SdfShape::Sphere {
+            center: origin,
+            radius: 0.5,
+        },
+        SdfShape::Box {
+            center: origin,
+            half_size: point(0.5, 0.2, 0.3),
+        },
+        SdfShape::Capsule {
+            a: point(-0.5, 0.0, 0.0),
+            b: point(0.5, 0.0, 0.0),
+            radius: 0.1,
+        },
+        SdfShape::Plane {
+            normal: point(0.0, 1.0, 0.0),
+            offset: 0.25,
+        },
+    ];
+    for shape in &primitives {
+        assert!(shape.distance(origin) < 0.0, "{:?}", shape);
+        assert!(shape.distance(point(0.0, 0.9, 0.0)) > 0.0, "{:?}", shape);
+    }
+
+    let sphere = &primitives[0];
+    assert!((sphere.distance(point(0.0, 0.0, 1.0)) - 0.5).abs() < 1e-6);
+    let cuboid = &primitives[1];
+    assert!((cuboid.distance(point(1.0, 0.0, 0.0)) - 0.5).abs() < 1e-6);
+    assert!((cuboid.distance(origin) + 0.2).abs() < 1e-6);
+}
+
+#[test]
+fn csg_combinators() {
+    let a = SdfShape::Sphere {
+        center: point(-0.3, 0.0, 0.0),
+        radius: 0.4,
+    };
+    let b = SdfShape::Sphere {
+        center: point(0.3, 0.0, 0.0),
+        radius: 0.4,
+    };
+    let only_a = point(-0.6, 0.0, 0.0);
+    let only_b = point(0.6, 0.0, 0.0);
+    let both = point(0.0, 0.0, 0.0);
+    let neither = point(0.0, 0.8, 0.0);
+
+    let union = a.clone().union(b.clone());
+    assert!(union.distance(only_a) < 0.0 && union.distance(only_b) < 0.0);
+    assert!(union.distance(neither) > 0.0);
+
+    let intersection = a.clone().intersect(b.clone());
+    assert!(intersection.distance(both) < 0.0);
+    assert!(intersection.distance(only_a) > 0.0 && intersection.distance(only_b) > 0.0);
+
+    let subtraction = a.clone().subtract(b.clone());
+    assert!(subtraction.distance(only_a) < 0.0);
+    assert!(subtraction.distance(both) > 0.0 && subtraction.distance(only_b) > 0.0);
+
+    // The blend fills the crease between the spheres and never removes material
+    let smooth = a.clone().smooth_union(b.clone(), 0.2);
+    let crease = point(0.0, 0.3, 0.0);
+    assert!(union.distance(crease) > 0.0 && smooth.distance(crease) < 0.0);
+    for p in [only_a, only_b, both, neither, crease] {
+        assert!(smooth.distance(p) <= union.distance(p) + 1e-6);
+    }
+    assert_eq!(
+        a.clone().smooth_union(b.clone(), 0.0).distance(crease),
+        union.distance(crease)
+    );
+}
+
+#[test]
+fn band_clamps_away_from_the_surface() {
+    let sphere = SdfShape::Sphere {
+        center: point(0.0, 0.0, 0.0),
+        radius: 0.5,
+    };
+    let band = sphere.clone().band(0.1);
+    assert_eq!(band.distance(point(0.0, 0.0, 0.0)), -0.1);
+    assert_eq!(band.distance(point(0.0, 0.0, 0.9)), 0.1);
+    let near = point(0.0, 0.55, 0.0);
+    assert_eq!(band.distance(near), sphere.distance(near));
+
+    // The voxels outside the band are all the same, so the octree can leave them out
+    let sdf = generate(header(), &band);
+    let outside = sdf.voxels[0];
+    let inside = sdf.voxels[((DIM / 2) * (1 + DIM + DIM * DIM)) as usize];
+    assert!(inside < LEVEL_ZERO && outside > LEVEL_ZERO);
+    assert!(sdf.voxels.iter().all(|&v| v >= inside && v <= outside));
+}
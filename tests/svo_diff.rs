@@ -0,0 +1,83 @@
+// SvoDiff and diff_heatmap on two octrees of the same generated sphere, built with a tight and
+// a loose threshold. The loose one leaves out bricks at the edge of the distance band.
+
+use rust_test::minivector::*;
+use rust_test::sdf::*;
+use rust_test::svo_diff::*;
+use rust_test::svosdf::*;
+
+use std::collections::HashSet;
+
+const DIM: u32 = 32;
+
+fn brick_keys(svo_sdf: &SvoSdf) -> HashSet<(UVec3, u32)> {
+    svo_sdf
+        .bricks
+        .iter()
+        .map(|brick| (brick.position, brick.size))
+        .collect()
+}
+
+fn assert_depth_counts(depths: &[DepthCounts], svo_sdf: &SvoSdf) {
+    assert_eq!(depths[0].nodes, 1, "one root");
+    let nodes: u32 = depths.iter().map(|counts| counts.nodes).sum();
+    let bricks: u32 = depths.iter().map(|counts| counts.bricks).sum();
+    assert_eq!(nodes as usize, svo_sdf.memory_usage().node_count);
+    assert_eq!(bricks as usize, svo_sdf.bricks.len());
+    assert!(depths.iter().all(|counts| counts.leaves <= counts.nodes));
+}
+
+#[test]
+fn diff_of_two_thresholds() {
+    let sdf = generate_sphere(DIM, 0.6, 0.2);
+    let a = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    let b = SvoSdf::from_sdf(&sdf, 8, 8, 0.01);
+    let diff = SvoDiff::compute(&a, &b);
+
+    assert!(diff.same_grid);
+    assert_depth_counts(&diff.depths_a, &a);
+    assert_depth_counts(&diff.depths_b, &b);
+
+    let (keys_a, keys_b) = (brick_keys(&a), brick_keys(&b));
+    let only_a: Vec<_> = keys_a.difference(&keys_b).collect();
+    assert!(!only_a.is_empty(), "the loose threshold should drop bricks");
+    assert_eq!(diff.bricks_only_a, only_a.len());
+    assert_eq!(diff.bricks_only_b, keys_b.difference(&keys_a).count());
+    assert_eq!(diff.bricks_common, keys_a.intersection(&keys_b).count());
+
+    // Common bricks come from the same source voxels
+    assert!(diff.changed_bricks.is_empty());
+    assert_eq!((diff.changed_voxels, diff.max_delta), (0, 0));
+    assert!(!diff.is_identical());
+
+    let heatmap = diff_heatmap(&a, &b).unwrap();
+    assert_eq!(heatmap.header, a.header);
+    let dim = UVec3::from(a.header.dim);
+    for &&(position, _) in &only_a {
+        assert_eq!(
+            heatmap.voxels[position.linear_index(dim)],
+            HEATMAP_ONLY_ONE,
+            "brick at {:?}",
+            position
+        );
+    }
+    for &(position, _) in keys_a.intersection(&keys_b) {
+        assert_eq!(heatmap.voxels[position.linear_index(dim)], 0);
+    }
+}
+
+#[test]
+fn identical_builds() {
+    let sdf = generate_sphere(DIM, 0.6, 0.2);
+    let a = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    let again = SvoSdf::from_sdf(&sdf, 8, 8, 0.004);
+    let diff = SvoDiff::compute(&a, &again);
+
+    assert!(diff.is_identical());
+    assert_eq!(diff.depths_a, diff.depths_b);
+    assert_eq!(diff.bricks_common, a.bricks.len());
+    assert_eq!(diff.rms_delta, 0.0);
+
+    let heatmap = diff_heatmap(&a, &again).unwrap();
+    assert!(heatmap.voxels.iter().all(|&heat| heat == 0));
+}